@@ -0,0 +1,207 @@
+//! Converting values between types. One table covers both the conversions
+//! a write makes on its own, storing a value in a column of another type,
+//! and the ones `CAST(expr AS type)` asks for; each rule says which of them
+//! it's allowed in.
+
+use crate::catalog::row::Value;
+use crate::catalog::schema::DataType;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Conversion {
+    /// Storing a value in a column in strict mode: only lossless widening,
+    /// so an INTEGER can go into a BIGINT column but nothing else changes
+    /// type
+    Strict,
+    /// Storing a value in a column otherwise: also a value the column's
+    /// type holds exactly, like '42' in an INTEGER column, 1 in a BOOLEAN
+    /// one or 2.0 in an INTEGER one, but nothing that would lose anything
+    Implicit,
+    /// CAST, which also rounds, truncates floats toward zero, takes any
+    /// nonzero number as TRUE and turns text into bytes and back
+    Cast,
+}
+
+/// `value` as a `to`, or None if `conversion` doesn't allow it or the
+/// value has no equivalent (like `'abc'` as an INTEGER). NULL stays NULL.
+pub fn convert(value: &Value, to: &DataType, conversion: Conversion) -> Option<Value> {
+    let cast = conversion == Conversion::Cast;
+    match (value, to) {
+        (Value::Null, _) => Some(Value::Null),
+        (Value::Integer(_), DataType::Integer)
+        | (Value::BigInt(_), DataType::BigInt)
+        | (Value::Float(_), DataType::Float)
+        | (Value::Boolean(_), DataType::Boolean)
+        | (Value::Text(_), DataType::Text)
+        | (Value::Blob(_), DataType::Blob) => Some(value.clone()),
+        (Value::Integer(n), DataType::BigInt) => Some(Value::BigInt(*n as i64)),
+
+        // Everything below only outside strict mode
+        _ if conversion == Conversion::Strict => None,
+        (Value::BigInt(n), DataType::Integer) => fitted(*n, to),
+        (Value::Integer(_) | Value::BigInt(_), DataType::Float) => {
+            // Past 2^53 not every integer is a float; only CAST rounds
+            let n = integer(value)?;
+            let f = n as f64;
+            (cast || f as i128 == n as i128).then_some(Value::Float(f))
+        }
+        (Value::Float(f), DataType::Integer | DataType::BigInt) => {
+            // 2^63 is the first float past i64::MAX
+            let truncated = f.trunc();
+            let exact = truncated == *f;
+            (truncated.is_finite() && truncated.abs() < 9_223_372_036_854_775_808.0)
+                .then(|| fitted(truncated as i64, to))
+                .flatten()
+                .filter(|_| cast || exact)
+        }
+        (Value::Integer(_) | Value::BigInt(_), DataType::Boolean) => match integer(value)? {
+            0 => Some(Value::Boolean(false)),
+            1 => Some(Value::Boolean(true)),
+            _ => cast.then_some(Value::Boolean(true)),
+        },
+        (Value::Boolean(b), DataType::Integer | DataType::BigInt) => fitted(*b as i64, to),
+        (
+            Value::Integer(_) | Value::BigInt(_) | Value::Float(_) | Value::Boolean(_),
+            DataType::Text,
+        ) => Some(Value::Text(value.to_string())),
+        (Value::Text(s), DataType::Integer | DataType::BigInt) => {
+            s.trim().parse::<i64>().ok().and_then(|n| fitted(n, to))
+        }
+        (Value::Text(s), DataType::Float) => s.trim().parse::<f64>().ok().map(Value::Float),
+        (Value::Text(s), DataType::Boolean) => match s.trim().to_ascii_lowercase().as_str() {
+            "true" => Some(Value::Boolean(true)),
+            "false" => Some(Value::Boolean(false)),
+            _ => None,
+        },
+
+        // Not all bytes are text, so only CAST goes between them
+        _ if !cast => None,
+        (Value::Text(s), DataType::Blob) => Some(Value::Blob(s.as_bytes().to_vec())),
+        (Value::Blob(b), DataType::Text) => String::from_utf8(b.clone()).ok().map(Value::Text),
+        _ => None,
+    }
+}
+
+fn integer(value: &Value) -> Option<i64> {
+    match value {
+        Value::Integer(n) => Some(*n as i64),
+        Value::BigInt(n) => Some(*n),
+        _ => None,
+    }
+}
+
+/// `n` as an INTEGER or BIGINT, if it fits
+fn fitted(n: i64, to: &DataType) -> Option<Value> {
+    match to {
+        DataType::Integer => i32::try_from(n).ok().map(Value::Integer),
+        _ => Some(Value::BigInt(n)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cast(value: Value, to: DataType) -> Option<Value> {
+        convert(&value, &to, Conversion::Cast)
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_strict_only_widens() {
+        let strict = |value: Value, to: DataType| convert(&value, &to, Conversion::Strict);
+        assert_eq!(
+            strict(Value::Integer(7), DataType::BigInt),
+            Some(Value::BigInt(7))
+        );
+        assert_eq!(strict(Value::Null, DataType::Text), Some(Value::Null));
+        assert_eq!(strict(text("x"), DataType::Text), Some(text("x")));
+        assert_eq!(strict(Value::Integer(7), DataType::Float), None);
+        assert_eq!(strict(text("7"), DataType::Integer), None);
+    }
+
+    #[test]
+    fn test_implicit_is_exact() {
+        let implicit = |value: Value, to: DataType| convert(&value, &to, Conversion::Implicit);
+        assert_eq!(
+            implicit(text(" 42 "), DataType::Integer),
+            Some(Value::Integer(42))
+        );
+        assert_eq!(
+            implicit(Value::Integer(1), DataType::Boolean),
+            Some(Value::Boolean(true))
+        );
+        assert_eq!(implicit(Value::Integer(2), DataType::Boolean), None);
+        assert_eq!(
+            implicit(Value::Float(2.0), DataType::BigInt),
+            Some(Value::BigInt(2))
+        );
+        assert_eq!(implicit(Value::Float(2.5), DataType::Integer), None);
+        assert_eq!(
+            implicit(Value::Integer(3), DataType::Float),
+            Some(Value::Float(3.0))
+        );
+        assert_eq!(implicit(Value::BigInt(i64::MAX), DataType::Float), None);
+        assert_eq!(implicit(Value::BigInt(1 << 40), DataType::Integer), None);
+        assert_eq!(
+            implicit(Value::Float(1.5), DataType::Text),
+            Some(text("1.5"))
+        );
+        assert_eq!(implicit(text("4.5"), DataType::Integer), None);
+        assert_eq!(implicit(text("hi"), DataType::Blob), None);
+    }
+
+    #[test]
+    fn test_cast_numbers() {
+        assert_eq!(
+            cast(Value::BigInt(7), DataType::Integer),
+            Some(Value::Integer(7))
+        );
+        assert_eq!(cast(Value::BigInt(1 << 40), DataType::Integer), None);
+        assert_eq!(
+            cast(Value::Float(-2.9), DataType::Integer),
+            Some(Value::Integer(-2))
+        );
+        assert_eq!(cast(Value::Float(f64::NAN), DataType::BigInt), None);
+        assert_eq!(
+            cast(Value::Integer(3), DataType::Float),
+            Some(Value::Float(3.0))
+        );
+        assert_eq!(
+            cast(Value::Integer(0), DataType::Boolean),
+            Some(Value::Boolean(false))
+        );
+        assert_eq!(
+            cast(Value::Boolean(true), DataType::Integer),
+            Some(Value::Integer(1))
+        );
+    }
+
+    #[test]
+    fn test_cast_text() {
+        assert_eq!(
+            cast(text(" 42 "), DataType::Integer),
+            Some(Value::Integer(42))
+        );
+        assert_eq!(cast(text("4.5"), DataType::Integer), None);
+        assert_eq!(cast(text("4.5"), DataType::Float), Some(Value::Float(4.5)));
+        assert_eq!(
+            cast(text("TRUE"), DataType::Boolean),
+            Some(Value::Boolean(true))
+        );
+        assert_eq!(cast(text("yes"), DataType::Boolean), None);
+        assert_eq!(cast(Value::Float(1.0), DataType::Text), Some(text("1.0")));
+        assert_eq!(
+            cast(Value::Boolean(false), DataType::Text),
+            Some(text("false"))
+        );
+        assert_eq!(
+            cast(text("hi"), DataType::Blob),
+            Some(Value::Blob(b"hi".to_vec()))
+        );
+        assert_eq!(cast(Value::Blob(vec![0xFF]), DataType::Text), None);
+        assert_eq!(cast(Value::Boolean(true), DataType::Float), None);
+    }
+}
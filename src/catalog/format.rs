@@ -0,0 +1,182 @@
+//! Rendering values as text. `ValueFormat` holds the choices that differ
+//! between outputs (how NULL reads, how text is quoted), with presets for
+//! plain display, SQL literals, CSV fields and JSON.
+//!
+//! There is no date type yet, so there is no date format either; timestamps
+//! stored as BIGINT print as numbers.
+
+use crate::catalog::row::Value;
+use std::fmt;
+
+/// How Text values (and Blobs, which render as `X'..'`) are quoted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuoteStyle {
+    /// As is
+    None,
+    /// `'it''s'`, readable back as a SQL literal
+    Sql,
+    /// Bare unless the field holds a comma, quote or line break, then
+    /// `"say ""hi"""`
+    Csv,
+    /// A JSON string
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueFormat {
+    /// Text shown for NULL
+    pub null: String,
+    pub quote: QuoteStyle,
+}
+
+impl Default for ValueFormat {
+    /// Plain display: NULL and unquoted text
+    fn default() -> Self {
+        ValueFormat {
+            null: "NULL".to_string(),
+            quote: QuoteStyle::None,
+        }
+    }
+}
+
+impl ValueFormat {
+    pub fn sql() -> Self {
+        ValueFormat {
+            quote: QuoteStyle::Sql,
+            ..ValueFormat::default()
+        }
+    }
+
+    /// NULL is an empty field
+    pub fn csv() -> Self {
+        ValueFormat {
+            null: String::new(),
+            quote: QuoteStyle::Csv,
+        }
+    }
+
+    pub fn json() -> Self {
+        ValueFormat {
+            null: "null".to_string(),
+            quote: QuoteStyle::Json,
+        }
+    }
+}
+
+/// A value paired with the format to render it in, see `Value::display_with`
+pub struct FormattedValue<'a> {
+    value: &'a Value,
+    format: &'a ValueFormat,
+}
+
+impl Value {
+    pub fn display_with<'a>(&'a self, format: &'a ValueFormat) -> FormattedValue<'a> {
+        FormattedValue {
+            value: self,
+            format,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.display_with(&ValueFormat::default()).fmt(f)
+    }
+}
+
+impl fmt::Display for FormattedValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let quote = self.format.quote;
+        match self.value {
+            Value::Null => f.write_str(&self.format.null),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::BigInt(n) => write!(f, "{}", n),
+            // JSON has no NaN or infinity
+            Value::Float(n) if quote == QuoteStyle::Json && !n.is_finite() => f.write_str("null"),
+            // Debug always keeps a '.' or exponent, so the value reads back as a float
+            Value::Float(n) => write!(f, "{:?}", n),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Text(text) => write_quoted(f, text, quote),
+            Value::Blob(bytes) => match quote {
+                QuoteStyle::Json => write_quoted(f, &blob_literal(bytes), quote),
+                _ => f.write_str(&blob_literal(bytes)),
+            },
+        }
+    }
+}
+
+fn write_quoted(f: &mut fmt::Formatter, text: &str, quote: QuoteStyle) -> fmt::Result {
+    match quote {
+        QuoteStyle::None => f.write_str(text),
+        QuoteStyle::Sql => write!(f, "'{}'", text.replace('\'', "''")),
+        QuoteStyle::Csv if text.contains([',', '"', '\n', '\r']) => {
+            write!(f, "\"{}\"", text.replace('"', "\"\""))
+        }
+        QuoteStyle::Csv => f.write_str(text),
+        QuoteStyle::Json => {
+            f.write_str("\"")?;
+            for c in text.chars() {
+                match c {
+                    '"' => f.write_str("\\\"")?,
+                    '\\' => f.write_str("\\\\")?,
+                    '\n' => f.write_str("\\n")?,
+                    '\r' => f.write_str("\\r")?,
+                    '\t' => f.write_str("\\t")?,
+                    c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                    c => write!(f, "{}", c)?,
+                }
+            }
+            f.write_str("\"")
+        }
+    }
+}
+
+/// A blob as an `X'..'` literal
+pub fn blob_literal(bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!("X'{}'", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_formats() {
+        let text = Value::Text("say \"hi\", it's".to_string());
+        assert_eq!(text.to_string(), "say \"hi\", it's");
+        assert_eq!(
+            text.display_with(&ValueFormat::sql()).to_string(),
+            "'say \"hi\", it''s'"
+        );
+        assert_eq!(
+            text.display_with(&ValueFormat::csv()).to_string(),
+            "\"say \"\"hi\"\", it's\""
+        );
+        assert_eq!(
+            text.display_with(&ValueFormat::json()).to_string(),
+            "\"say \\\"hi\\\", it's\""
+        );
+
+        assert_eq!(Value::Null.to_string(), "NULL");
+        assert_eq!(
+            Value::Null.display_with(&ValueFormat::csv()).to_string(),
+            ""
+        );
+        let custom = ValueFormat {
+            null: "<none>".to_string(),
+            ..ValueFormat::default()
+        };
+        assert_eq!(Value::Null.display_with(&custom).to_string(), "<none>");
+
+        assert_eq!(Value::Float(2.0).to_string(), "2.0");
+        assert_eq!(
+            Value::Float(f64::NAN)
+                .display_with(&ValueFormat::json())
+                .to_string(),
+            "null"
+        );
+        assert_eq!(Value::Blob(vec![0xDE, 0xAD]).to_string(), "X'DEAD'");
+        assert_eq!(Value::Boolean(true).to_string(), "true");
+    }
+}
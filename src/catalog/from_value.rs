@@ -0,0 +1,146 @@
+//! Reading values as Rust types, for code that gets rows back from a query.
+//! A value converts to a type that holds it exactly: an INTEGER reads as an
+//! i64, but a BIGINT only reads as an i32 if it fits. NULL only reads as an
+//! `Option`, as None.
+
+use crate::catalog::format::ValueFormat;
+use crate::catalog::row::Value;
+use crate::error::{HozonError, Result};
+
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self>;
+}
+
+fn mismatch(value: &Value, to: &str) -> HozonError {
+    HozonError::TypeMismatch(format!(
+        "Can't read {} as {}",
+        value.display_with(&ValueFormat::sql()),
+        to
+    ))
+}
+
+impl FromValue for i32 {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Integer(n) => Ok(*n),
+            Value::BigInt(n) => i32::try_from(*n).map_err(|_| mismatch(value, "i32")),
+            _ => Err(mismatch(value, "i32")),
+        }
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Integer(n) => Ok(*n as i64),
+            Value::BigInt(n) => Ok(*n),
+            _ => Err(mismatch(value, "i64")),
+        }
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Float(n) => Ok(*n),
+            Value::Integer(n) => Ok(*n as f64),
+            _ => Err(mismatch(value, "f64")),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Boolean(b) => Ok(*b),
+            _ => Err(mismatch(value, "bool")),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Text(text) => Ok(text.clone()),
+            _ => Err(mismatch(value, "String")),
+        }
+    }
+}
+
+impl FromValue for Vec<u8> {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Blob(bytes) => Ok(bytes.clone()),
+            _ => Err(mismatch(value, "Vec<u8>")),
+        }
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self> {
+        Ok(value.clone())
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+// `Option<T>` can't have TryFrom too: the standard library's blanket impl
+// could overlap it
+macro_rules! try_from_value {
+    ($($t:ty),*) => {
+        $(
+            impl TryFrom<Value> for $t {
+                type Error = HozonError;
+
+                fn try_from(value: Value) -> Result<Self> {
+                    <$t>::from_value(&value)
+                }
+            }
+
+            impl TryFrom<&Value> for $t {
+                type Error = HozonError;
+
+                fn try_from(value: &Value) -> Result<Self> {
+                    <$t>::from_value(value)
+                }
+            }
+        )*
+    };
+}
+
+try_from_value!(i32, i64, f64, bool, String, Vec<u8>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_value() {
+        assert_eq!(i64::try_from(Value::Integer(7)).unwrap(), 7);
+        assert_eq!(i32::try_from(&Value::BigInt(7)).unwrap(), 7);
+        assert_eq!(f64::from_value(&Value::Integer(2)).unwrap(), 2.0);
+        assert!(bool::try_from(Value::Boolean(true)).unwrap());
+        assert_eq!(String::try_from(Value::Text("a".to_string())).unwrap(), "a");
+        assert_eq!(Option::<i64>::from_value(&Value::Null).unwrap(), None);
+        assert_eq!(
+            Option::<i64>::from_value(&Value::BigInt(1)).unwrap(),
+            Some(1)
+        );
+
+        let err = i32::try_from(Value::BigInt(i64::MAX)).unwrap_err();
+        assert!(matches!(err, HozonError::TypeMismatch(_)), "{:?}", err);
+        let err = i64::try_from(Value::Text("abc".to_string())).unwrap_err();
+        assert_eq!(err.to_string(), "Can't read 'abc' as i64");
+        let err = String::from_value(&Value::Null).unwrap_err();
+        assert_eq!(err.to_string(), "Can't read NULL as String");
+        let err = Option::<bool>::from_value(&Value::Integer(1)).unwrap_err();
+        assert!(matches!(err, HozonError::TypeMismatch(_)), "{:?}", err);
+    }
+}
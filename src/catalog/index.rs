@@ -0,0 +1,222 @@
+//! Indexes: a catalog entry naming the indexed columns, and a B-tree
+//! (`storage::btree`) rooted at its root page holding each row's values of
+//! those columns with its rowid.
+
+use crate::catalog::format::ValueFormat;
+use crate::catalog::ordering::SortKey;
+use crate::catalog::row::Value;
+use crate::catalog::table::read_string;
+use crate::error::{HozonError, Result};
+use crate::storage::btree::{BTree, Entry};
+
+/// Catalog entry for an index over one or more columns of a table
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexMetadata {
+    name: String,
+    table_name: String,
+    columns: Vec<String>,
+    /// No two rows may share a key, unless it holds a NULL
+    unique: bool,
+    root_page: u32,
+}
+
+impl IndexMetadata {
+    pub fn new(
+        name: &str,
+        table_name: &str,
+        columns: Vec<String>,
+        unique: bool,
+        root_page: u32,
+    ) -> Self {
+        IndexMetadata {
+            name: name.to_string(),
+            table_name: table_name.to_string(),
+            columns,
+            unique,
+            root_page,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn is_unique(&self) -> bool {
+        self.unique
+    }
+
+    pub fn root_page(&self) -> u32 {
+        self.root_page
+    }
+
+    pub fn tree(&self) -> BTree {
+        BTree::open(self.root_page)
+    }
+
+    /// Fail if the sorted `entries` break this index's UNIQUE constraint
+    pub fn check_unique(&self, entries: &[Entry]) -> Result<()> {
+        if !self.unique {
+            return Ok(());
+        }
+        let duplicate = entries
+            .windows(2)
+            .find(|pair| pair[0].0 == pair[1].0 && constrains(&pair[0].0));
+        match duplicate {
+            Some(pair) => Err(self.duplicate(&pair[0].0)),
+            None => Ok(()),
+        }
+    }
+
+    /// The error for a second row with `key` in this UNIQUE index
+    pub fn duplicate(&self, key: &SortKey) -> HozonError {
+        HozonError::ConstraintViolation(format!(
+            "Duplicate key {} for UNIQUE index '{}' on {} ({})",
+            describe_key(key),
+            self.name,
+            self.table_name,
+            self.columns.join(", ")
+        ))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        // name and table (length + text)
+        for text in [&self.name, &self.table_name] {
+            bytes.extend_from_slice(&(text.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(text.as_bytes());
+        }
+
+        // number of columns, then each column name
+        bytes.extend_from_slice(&(self.columns.len() as u32).to_le_bytes());
+        for column in &self.columns {
+            bytes.extend_from_slice(&(column.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(column.as_bytes());
+        }
+
+        bytes.extend_from_slice(&self.root_page.to_le_bytes());
+        bytes.push(self.unique as u8);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize)> {
+        let mut offset = 0;
+        let name = read_string(bytes, &mut offset, "index name")?;
+        let table_name = read_string(bytes, &mut offset, "index table name")?;
+
+        let num_columns = read_u32(bytes, &mut offset, "number of index columns")?;
+        let mut columns = Vec::new();
+        for _ in 0..num_columns {
+            columns.push(read_string(bytes, &mut offset, "index column name")?);
+        }
+
+        let root_page = read_u32(bytes, &mut offset, "index root page")?;
+        let unique = match bytes.get(offset) {
+            Some(&flag) => flag != 0,
+            None => {
+                return Err(HozonError::Corruption(
+                    "Not enough bytes for index UNIQUE flag".to_string(),
+                ));
+            }
+        };
+        offset += 1;
+
+        Ok((
+            IndexMetadata {
+                name,
+                table_name,
+                columns,
+                unique,
+                root_page,
+            },
+            offset,
+        ))
+    }
+}
+
+/// Whether a UNIQUE index holds `key` to being unique; keys with a NULL
+/// never clash
+pub fn constrains(key: &SortKey) -> bool {
+    !key.0.iter().any(|v| matches!(v, Value::Null))
+}
+
+/// `(1, 'a')` for the key of values 1 and 'a'
+fn describe_key(key: &SortKey) -> String {
+    let format = ValueFormat::sql();
+    let values: Vec<String> = key
+        .0
+        .iter()
+        .map(|v| v.display_with(&format).to_string())
+        .collect();
+    format!("({})", values.join(", "))
+}
+
+fn read_u32(bytes: &[u8], offset: &mut usize, what: &str) -> Result<u32> {
+    if bytes.len() < *offset + 4 {
+        return Err(HozonError::Corruption(format!(
+            "Not enough bytes for {}",
+            what
+        )));
+    }
+    let value = u32::from_le_bytes([
+        bytes[*offset],
+        bytes[*offset + 1],
+        bytes[*offset + 2],
+        bytes[*offset + 3],
+    ]);
+    *offset += 4;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_metadata_serialization() {
+        let columns = vec!["last".to_string(), "first".to_string()];
+        let index = IndexMetadata::new("by_name", "users", columns, true, 7);
+
+        let bytes = index.to_bytes();
+        let (decoded, consumed) = IndexMetadata::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, index);
+        assert!(decoded.is_unique());
+        assert_eq!(consumed, bytes.len());
+
+        assert!(IndexMetadata::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_check_unique() {
+        let entries = vec![
+            (SortKey(vec![Value::Null, Value::Integer(1)]), 4),
+            (SortKey(vec![Value::Integer(1), Value::Text("a".into())]), 2),
+            (SortKey(vec![Value::Integer(1), Value::Text("a".into())]), 3),
+        ];
+        let columns = vec!["a".to_string(), "b".to_string()];
+        let plain = IndexMetadata::new("i", "t", columns.clone(), false, 7);
+        assert!(plain.check_unique(&entries).is_ok());
+        let unique = IndexMetadata::new("i", "t", columns, true, 7);
+        let err = unique.check_unique(&entries).unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+        assert!(err.to_string().contains("(1, 'a')"), "{}", err);
+        // Keys holding NULL never clash
+        assert!(
+            unique
+                .check_unique(&[entries[0].clone(), entries[0].clone()])
+                .is_ok()
+        );
+    }
+}
@@ -1,3 +1,10 @@
+pub mod convert;
+pub mod format;
+pub mod from_value;
+pub mod index;
+pub mod ordering;
 pub mod row;
 pub mod schema;
+pub mod stats;
+pub mod system;
 pub mod table;
@@ -0,0 +1,218 @@
+//! How values compare, shared by everything that orders or matches them.
+//!
+//! - `compare` is SQL comparison, for WHERE: NULL, NaN and values of
+//!   unrelated types are unknown (None).
+//! - `total_cmp` puts every value in one order, for ORDER BY, UNIQUE checks
+//!   and index keys: NULL < BOOLEAN < numbers < TEXT < BLOB. Numbers compare
+//!   numerically whatever their type, with NaN after every other number.
+//!   `SortKey` wraps rows of values in this order for use as map keys.
+//! - `PartialOrd for Value` is `total_cmp` with ties between numeric types
+//!   broken by type (INTEGER, BIGINT, FLOAT), so it agrees with `==`.
+
+use crate::catalog::row::Value;
+use std::cmp::Ordering;
+
+/// SQL comparison: numbers compare numerically across types, Text and Blob
+/// by bytes, false before true. NULL, NaN and values of unrelated types
+/// don't compare at all.
+pub fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+        (Value::Boolean(a), Value::Boolean(b)) => Some(a.cmp(b)),
+        (Value::Blob(a), Value::Blob(b)) => Some(a.cmp(b)),
+        _ => match (number(a)?, number(b)?) {
+            (Number::Int(a), Number::Int(b)) => Some(a.cmp(&b)),
+            (Number::Int(a), Number::Float(b)) => int_float_cmp(a, b),
+            (Number::Float(a), Number::Int(b)) => int_float_cmp(b, a).map(Ordering::reverse),
+            (Number::Float(a), Number::Float(b)) => a.partial_cmp(&b),
+        },
+    }
+}
+
+/// A total order over all values, see the module docs
+pub fn total_cmp(a: &Value, b: &Value) -> Ordering {
+    rank(a).cmp(&rank(b)).then_with(|| match (a, b) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        _ => compare(a, b).unwrap_or_else(|| {
+            // Only NaN is left, which sorts after the other numbers
+            let a_nan = matches!(a, Value::Float(f) if f.is_nan());
+            let b_nan = matches!(b, Value::Float(f) if f.is_nan());
+            a_nan.cmp(&b_nan)
+        }),
+    })
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        // NaN isn't equal to itself, so it can't be ordered either
+        if [self, other]
+            .iter()
+            .any(|v| matches!(v, Value::Float(f) if f.is_nan()))
+        {
+            return None;
+        }
+        Some(total_cmp(self, other).then_with(|| numeric_type(self).cmp(&numeric_type(other))))
+    }
+}
+
+/// A row of values ordered by `total_cmp`, column by column
+#[derive(Debug, Clone)]
+pub struct SortKey(pub Vec<Value>);
+
+impl Ord for SortKey {
+    fn cmp(&self, other: &SortKey) -> Ordering {
+        self.0
+            .iter()
+            .zip(&other.0)
+            .map(|(a, b)| total_cmp(a, b))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| self.0.len().cmp(&other.0.len()))
+    }
+}
+
+impl PartialOrd for SortKey {
+    fn partial_cmp(&self, other: &SortKey) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for SortKey {
+    fn eq(&self, other: &SortKey) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for SortKey {}
+
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+fn number(value: &Value) -> Option<Number> {
+    match value {
+        Value::Integer(n) => Some(Number::Int(*n as i64)),
+        Value::BigInt(n) => Some(Number::Int(*n)),
+        Value::Float(f) => Some(Number::Float(*f)),
+        _ => None,
+    }
+}
+
+/// Exact comparison of an integer with a float; `i as f64` alone rounds
+/// integers past 2^53
+fn int_float_cmp(i: i64, f: f64) -> Option<Ordering> {
+    if f.is_nan() {
+        return None;
+    }
+    // 2^63 is the first float past i64::MAX
+    if f >= 9_223_372_036_854_775_808.0 {
+        return Some(Ordering::Less);
+    }
+    if f < -9_223_372_036_854_775_808.0 {
+        return Some(Ordering::Greater);
+    }
+    let truncated = f.trunc();
+    Some(i.cmp(&(truncated as i64)).then_with(|| {
+        // Equal integer parts; any fraction decides
+        0.0.partial_cmp(&(f - truncated)).unwrap()
+    }))
+}
+
+fn rank(value: &Value) -> u8 {
+    match value {
+        Value::Null => 0,
+        Value::Boolean(_) => 1,
+        Value::Integer(_) | Value::BigInt(_) | Value::Float(_) => 2,
+        Value::Text(_) => 3,
+        Value::Blob(_) => 4,
+    }
+}
+
+fn numeric_type(value: &Value) -> u8 {
+    match value {
+        Value::BigInt(_) => 1,
+        Value::Float(_) => 2,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_total_order() {
+        let mut values = vec![
+            Value::Blob(vec![1]),
+            Value::Text("b".to_string()),
+            Value::Float(f64::NAN),
+            Value::Float(2.5),
+            Value::BigInt(1 << 40),
+            Value::Integer(-3),
+            Value::Boolean(false),
+            Value::Text("a".to_string()),
+            Value::Null,
+        ];
+        values.sort_by(total_cmp);
+        assert_eq!(values[0], Value::Null);
+        assert_eq!(values[1], Value::Boolean(false));
+        assert_eq!(values[2..4], [Value::Integer(-3), Value::Float(2.5)]);
+        assert_eq!(values[4], Value::BigInt(1 << 40));
+        assert!(matches!(values[5], Value::Float(f) if f.is_nan()));
+        assert_eq!(values[6], Value::Text("a".to_string()));
+        assert_eq!(values[8], Value::Blob(vec![1]));
+
+        assert_eq!(
+            total_cmp(&Value::Integer(1), &Value::Float(1.0)),
+            Ordering::Equal
+        );
+        assert_eq!(total_cmp(&Value::Null, &Value::Null), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_integer_float_comparison_is_exact() {
+        let big = (1i64 << 53) + 1;
+        assert_eq!(
+            compare(&Value::BigInt(big), &Value::Float((1i64 << 53) as f64)),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            compare(&Value::BigInt(i64::MAX), &Value::Float(i64::MAX as f64)),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            compare(&Value::Float(-1.5), &Value::Integer(-1)),
+            Some(Ordering::Less)
+        );
+        assert_eq!(compare(&Value::Integer(1), &Value::Float(f64::NAN)), None);
+    }
+
+    #[test]
+    fn test_partial_ord_agrees_with_eq() {
+        assert!(Value::Integer(1) < Value::Float(1.0));
+        assert!(Value::Float(1.0) < Value::Integer(2));
+        assert!(Value::Null < Value::Integer(0));
+        assert_eq!(
+            Value::Float(0.0).partial_cmp(&Value::Float(-0.0)),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(Value::Float(f64::NAN).partial_cmp(&Value::Null), None);
+    }
+
+    #[test]
+    fn test_sort_keys() {
+        let keys: BTreeSet<SortKey> = [
+            vec![Value::Integer(1), Value::Text("b".to_string())],
+            vec![Value::Float(1.0), Value::Text("b".to_string())],
+            vec![Value::Integer(1), Value::Null],
+        ]
+        .into_iter()
+        .map(SortKey)
+        .collect();
+
+        // 1 and 1.0 are the same key; NULL sorts first
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys.first().unwrap().0[1], Value::Null);
+    }
+}
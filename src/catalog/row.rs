@@ -1,14 +1,76 @@
-use std::io::{self, Error, ErrorKind};
+use crate::catalog::from_value::FromValue;
+use crate::catalog::ordering;
+use crate::catalog::schema::{Column, DataType, Schema};
+use crate::error::{HozonError, Result};
+use std::cmp::Ordering;
+
+/// First byte of a row in the compact encoding. Tagged rows start with a
+/// value type (1-7) or the terminator instead.
+pub(crate) const ROW_COMPACT: u8 = 0x80;
+/// What a deleted row leaves on its page: this one byte, which keeps its
+/// slot so the rows after it keep their rowids
+pub(crate) const ROW_DELETED: u8 = 0x81;
+
+// Value type tags of the tagged encoding
+const TAG_INTEGER: u8 = 1;
+const TAG_TEXT: u8 = 2;
+const TAG_BOOLEAN: u8 = 3;
+const TAG_NULL: u8 = 4;
+const TAG_FLOAT: u8 = 5;
+const TAG_BIGINT: u8 = 6;
+const TAG_BLOB: u8 = 7;
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Value {
     Integer(i32),
     Text(String),
     Boolean(bool),
     Null,
+    Float(f64),
+    BigInt(i64),
+    Blob(Vec<u8>),
 }
 
-#[derive(Debug)]
+impl Value {
+    /// SQL comparison, see `ordering::compare`
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        ordering::compare(self, other)
+    }
+
+    /// Whether the value can be stored in a column of `data_type`
+    fn fits(&self, data_type: &DataType) -> bool {
+        matches!(
+            (self, data_type),
+            (Value::Null, _)
+                | (Value::Integer(_), DataType::Integer)
+                | (Value::Text(_), DataType::Text)
+                | (Value::Boolean(_), DataType::Boolean)
+                | (Value::Float(_), DataType::Float)
+                | (Value::BigInt(_), DataType::BigInt)
+                | (Value::Blob(_), DataType::Blob)
+        )
+    }
+}
+
+fn take<'a>(bytes: &'a [u8], offset: &mut usize, len: usize, what: &str) -> Result<&'a [u8]> {
+    if bytes.len() < *offset + len {
+        return Err(HozonError::Corruption(format!(
+            "Not enough bytes for {}",
+            what
+        )));
+    }
+    let slice = &bytes[*offset..*offset + len];
+    *offset += len;
+    Ok(slice)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct Row {
     values: Vec<Value>,
 }
@@ -27,27 +89,108 @@ impl Row {
         self.values.get(index)
     }
 
+    /// The value at `index` as a `T`; see `FromValue`
+    pub fn get<T: FromValue>(&self, index: usize) -> Result<T> {
+        let value = self.values.get(index).ok_or_else(|| {
+            HozonError::ColumnNotFound(format!(
+                "Row has no column {}; it has {}",
+                index,
+                self.values.len()
+            ))
+        })?;
+        T::from_value(value).map_err(|e| e.context(format!("Column {}", index)))
+    }
+
+    /// Encode a row of a table with `columns`: the ROW_COMPACT marker, a
+    /// bitmap of the NULL columns, then every other value in its declared
+    /// type with no tag. Integers and booleans take fixed-width slots; text
+    /// and blobs are length-prefixed. A row that doesn't match the columns
+    /// falls back to the tagged encoding.
+    pub fn encode(&self, columns: &[Column]) -> Vec<u8> {
+        let matches = self.values.len() == columns.len()
+            && self
+                .values
+                .iter()
+                .zip(columns)
+                .all(|(value, column)| value.fits(column.data_type()));
+        if !matches {
+            return self.to_bytes();
+        }
+
+        let mut bytes = vec![ROW_COMPACT];
+        let mut null_bitmap = vec![0u8; columns.len().div_ceil(8)];
+        for (i, value) in self.values.iter().enumerate() {
+            if *value == Value::Null {
+                null_bitmap[i / 8] |= 1 << (i % 8);
+            }
+        }
+        bytes.extend_from_slice(&null_bitmap);
+
+        for value in self.values.iter() {
+            match value {
+                Value::Integer(val) => bytes.extend_from_slice(&val.to_le_bytes()),
+                Value::Boolean(val) => bytes.push(*val as u8),
+                Value::Float(val) => bytes.extend_from_slice(&val.to_le_bytes()),
+                Value::BigInt(val) => bytes.extend_from_slice(&val.to_le_bytes()),
+                Value::Text(text) => {
+                    bytes.extend_from_slice(&(text.len() as u32).to_le_bytes());
+                    bytes.extend_from_slice(text.as_bytes());
+                }
+                Value::Blob(blob) => {
+                    bytes.extend_from_slice(&(blob.len() as u32).to_le_bytes());
+                    bytes.extend_from_slice(blob);
+                }
+                Value::Null => {}
+            }
+        }
+
+        bytes
+    }
+
+    /// Decode a row written by `encode` for the same columns, or a tagged
+    /// row from `to_bytes` (which is how rows were stored before the compact
+    /// encoding, and still are when they don't match the schema).
+    pub fn decode(bytes: &[u8], columns: &[Column]) -> Result<(Self, usize)> {
+        let (view, consumed) = RowView::new(bytes, columns)?;
+        Ok((view.to_row(), consumed))
+    }
+
+    /// Self-describing encoding: each value behind a type tag, then a zero
+    /// terminator
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
 
         for value in self.values.iter() {
             match value {
                 Value::Integer(val) => {
-                    bytes.push(1);
+                    bytes.push(TAG_INTEGER);
                     bytes.extend_from_slice(&val.to_le_bytes());
                 }
                 Value::Text(text) => {
-                    bytes.push(2);
+                    bytes.push(TAG_TEXT);
                     let text_bytes = text.as_bytes();
                     bytes.extend_from_slice(&(text_bytes.len() as u32).to_le_bytes());
                     bytes.extend_from_slice(text_bytes);
                 }
                 Value::Boolean(bool) => {
-                    bytes.push(3);
+                    bytes.push(TAG_BOOLEAN);
                     bytes.push(if *bool { 1 } else { 0 });
                 }
                 Value::Null => {
-                    bytes.push(4);
+                    bytes.push(TAG_NULL);
+                }
+                Value::Float(val) => {
+                    bytes.push(TAG_FLOAT);
+                    bytes.extend_from_slice(&val.to_le_bytes());
+                }
+                Value::BigInt(val) => {
+                    bytes.push(TAG_BIGINT);
+                    bytes.extend_from_slice(&val.to_le_bytes());
+                }
+                Value::Blob(blob) => {
+                    bytes.push(TAG_BLOB);
+                    bytes.extend_from_slice(&(blob.len() as u32).to_le_bytes());
+                    bytes.extend_from_slice(blob);
                 }
             }
         }
@@ -56,96 +199,201 @@ impl Row {
         bytes
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> io::Result<(Self, usize)> {
-        let mut values = Vec::new();
-        let mut offset = 0;
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize)> {
+        let (view, consumed) = RowView::tagged(bytes)?;
+        Ok((view.to_row(), consumed))
+    }
 
-        // Read until we hit zero terminator
-        while offset < bytes.len() && bytes[offset] != 0 {
-            let value_type = bytes[offset];
-            offset += 1;
+    /// Decode a row of a table with `schema`, rejecting one whose values
+    /// don't fit the schema, as a partly written row may not
+    pub fn from_bytes_with_schema(bytes: &[u8], schema: &Schema) -> Result<(Self, usize)> {
+        let (view, consumed) = RowView::with_schema(bytes, schema)?;
+        Ok((view.to_row(), consumed))
+    }
+}
 
-            match value_type {
-                1 => {
-                    if bytes.len() < offset + 4 {
-                        return Err(Error::new(
-                            ErrorKind::InvalidData,
-                            "Not enough bytes for Integer",
-                        ));
-                    }
-
-                    let int_val = i32::from_le_bytes([
-                        bytes[offset],
-                        bytes[offset + 1],
-                        bytes[offset + 2],
-                        bytes[offset + 3],
-                    ]);
-                    values.push(Value::Integer(int_val));
-                    offset += 4;
-                }
-                2 => {
-                    if bytes.len() < offset + 4 {
-                        return Err(Error::new(
-                            ErrorKind::InvalidData,
-                            "Not enough bytes for Text length",
-                        ));
-                    }
-
-                    let text_len = u32::from_le_bytes([
-                        bytes[offset],
-                        bytes[offset + 1],
-                        bytes[offset + 2],
-                        bytes[offset + 3],
-                    ]) as usize;
-                    offset += 4;
-
-                    if bytes.len() < offset + text_len {
-                        return Err(Error::new(
-                            ErrorKind::InvalidData,
-                            "Not enough bytes for Text",
-                        ));
-                    }
-
-                    let text = String::from_utf8(bytes[offset..offset + text_len].to_vec())
-                        .map_err(|e| {
-                            Error::new(
-                                ErrorKind::InvalidData,
-                                format!("Invalid UTF-8 in Text value: {}", e),
-                            )
-                        })?;
-                    values.push(Value::Text(text));
-                    offset += text_len;
-                }
-                3 => {
-                    if bytes.len() < offset + 1 {
-                        return Err(Error::new(
-                            ErrorKind::InvalidData,
-                            "Not enough bytes for Boolean",
-                        ));
-                    }
-
-                    let bool_val = bytes[offset] != 0;
-                    values.push(Value::Boolean(bool_val));
-                    offset += 1;
-                }
-                4 => {
-                    values.push(Value::Null);
+/// A stored row indexed in place. Finding the values only needs their
+/// lengths, so a scan can pick out the columns it wants without decoding,
+/// or allocating for, the rest.
+#[derive(Debug)]
+pub struct RowView<'a> {
+    bytes: &'a [u8],
+    // Type tag and payload offset of each value
+    slots: Vec<(u8, usize)>,
+}
+
+impl<'a> RowView<'a> {
+    /// Index a row in either encoding, returning the view and how many bytes
+    /// the row takes
+    pub fn new(bytes: &'a [u8], columns: &[Column]) -> Result<(Self, usize)> {
+        if bytes.first() != Some(&ROW_COMPACT) {
+            return RowView::tagged(bytes);
+        }
+
+        let mut offset = 1;
+        let null_bitmap = take(bytes, &mut offset, columns.len().div_ceil(8), "NULL bitmap")?;
+
+        let mut slots = Vec::with_capacity(columns.len());
+        for (i, column) in columns.iter().enumerate() {
+            let tag = if null_bitmap[i / 8] & (1 << (i % 8)) != 0 {
+                TAG_NULL
+            } else {
+                match column.data_type() {
+                    DataType::Integer => TAG_INTEGER,
+                    DataType::Text => TAG_TEXT,
+                    DataType::Boolean => TAG_BOOLEAN,
+                    DataType::Float => TAG_FLOAT,
+                    DataType::BigInt => TAG_BIGINT,
+                    DataType::Blob => TAG_BLOB,
                 }
-                _ => {
-                    return Err(Error::new(
-                        ErrorKind::InvalidData,
-                        format!("Unknown value type: {}", value_type),
-                    ));
+            };
+            slots.push((tag, offset));
+            skip_value(bytes, &mut offset, tag)?;
+        }
+
+        Ok((RowView { bytes, slots }, offset))
+    }
+
+    /// Like `new`, then `validate` against `schema`
+    pub fn with_schema(bytes: &'a [u8], schema: &Schema) -> Result<(Self, usize)> {
+        let (view, consumed) = RowView::new(bytes, schema.columns())?;
+        view.validate(schema)?;
+        Ok((view, consumed))
+    }
+
+    /// Check there is one value per column of `schema`, each NULL or of its
+    /// column's type, and no NULL in a column that doesn't allow it
+    pub fn validate(&self, schema: &Schema) -> Result<()> {
+        let columns = schema.columns();
+        if self.len() != columns.len() {
+            return Err(HozonError::Corruption(format!(
+                "Row has {} values but table '{}' has {} columns",
+                self.len(),
+                schema.table_name(),
+                columns.len()
+            )));
+        }
+
+        for (&(tag, _), column) in self.slots.iter().zip(columns) {
+            let data_type = match tag {
+                TAG_NULL if column.is_nullable() => continue,
+                TAG_NULL => {
+                    return Err(HozonError::ConstraintViolation(format!(
+                        "NULL in NOT NULL column '{}'",
+                        column.name()
+                    )));
                 }
+                TAG_INTEGER => DataType::Integer,
+                TAG_TEXT => DataType::Text,
+                TAG_BOOLEAN => DataType::Boolean,
+                TAG_FLOAT => DataType::Float,
+                TAG_BIGINT => DataType::BigInt,
+                TAG_BLOB => DataType::Blob,
+                _ => unreachable!("skip_value rejects unknown tags"),
+            };
+            if data_type != *column.data_type() {
+                return Err(HozonError::Corruption(format!(
+                    "{} value in {} column '{}'",
+                    data_type.as_str(),
+                    column.data_type().as_str(),
+                    column.name()
+                )));
             }
         }
+        Ok(())
+    }
+
+    fn tagged(bytes: &'a [u8]) -> Result<(Self, usize)> {
+        let mut slots = Vec::new();
+        let mut offset = 0;
+
+        // Read until we hit zero terminator
+        while offset < bytes.len() && bytes[offset] != 0 {
+            let tag = bytes[offset];
+            offset += 1;
+            slots.push((tag, offset));
+            skip_value(bytes, &mut offset, tag)?;
+        }
 
         // Skip the terminator
-        if offset < bytes.len() && bytes[offset] == 0 {
+        if offset < bytes.len() {
             offset += 1;
         }
 
-        Ok((Row { values }, offset))
+        Ok((RowView { bytes, slots }, offset))
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Decode the value at `index`
+    pub fn get(&self, index: usize) -> Option<Value> {
+        let &(tag, offset) = self.slots.get(index)?;
+        let value = match tag {
+            TAG_INTEGER => Value::Integer(i32::from_le_bytes(self.fixed(offset))),
+            TAG_TEXT => Value::Text(self.get_str(index)?.to_string()),
+            TAG_BOOLEAN => Value::Boolean(self.bytes[offset] != 0),
+            TAG_FLOAT => Value::Float(f64::from_le_bytes(self.fixed(offset))),
+            TAG_BIGINT => Value::BigInt(i64::from_le_bytes(self.fixed(offset))),
+            TAG_BLOB => Value::Blob(self.payload(offset).to_vec()),
+            _ => Value::Null,
+        };
+        Some(value)
+    }
+
+    /// The Text value at `index`, borrowed from the row's bytes
+    pub fn get_str(&self, index: usize) -> Option<&'a str> {
+        match self.slots.get(index)? {
+            &(TAG_TEXT, offset) => std::str::from_utf8(self.payload(offset)).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn to_row(&self) -> Row {
+        Row::new((0..self.len()).filter_map(|i| self.get(i)).collect())
+    }
+
+    fn fixed<const N: usize>(&self, offset: usize) -> [u8; N] {
+        self.bytes[offset..offset + N].try_into().unwrap()
+    }
+
+    // Text and Blob payloads follow a u32 length
+    fn payload(&self, offset: usize) -> &'a [u8] {
+        let len = u32::from_le_bytes(self.fixed(offset)) as usize;
+        &self.bytes[offset + 4..offset + 4 + len]
+    }
+}
+
+/// Step over one value's payload, checking it is all there
+fn skip_value(bytes: &[u8], offset: &mut usize, tag: u8) -> Result<()> {
+    match tag {
+        TAG_INTEGER => take(bytes, offset, 4, "Integer").map(|_| ()),
+        TAG_BOOLEAN => take(bytes, offset, 1, "Boolean").map(|_| ()),
+        TAG_FLOAT => take(bytes, offset, 8, "Float").map(|_| ()),
+        TAG_BIGINT => take(bytes, offset, 8, "BigInt").map(|_| ()),
+        TAG_NULL => Ok(()),
+        TAG_TEXT => {
+            let len = take(bytes, offset, 4, "Text length")?;
+            let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+            let text = take(bytes, offset, len, "Text")?;
+            std::str::from_utf8(text)
+                .map(|_| ())
+                .map_err(|e| HozonError::Corruption(format!("Invalid UTF-8 in Text value: {}", e)))
+        }
+        TAG_BLOB => {
+            let len = take(bytes, offset, 4, "Blob length")?;
+            let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+            take(bytes, offset, len, "Blob").map(|_| ())
+        }
+        other => Err(HozonError::Corruption(format!(
+            "Unknown value type: {}",
+            other
+        ))),
     }
 }
 
@@ -155,11 +403,15 @@ mod tests {
 
     #[test]
     fn test_row_serialization() {
-        let mut values = Vec::new();
-        values.push(Value::Integer(42));
-        values.push(Value::Text("Hello".to_string()));
-        values.push(Value::Boolean(true));
-        values.push(Value::Null);
+        let values = vec![
+            Value::Integer(42),
+            Value::Text("Hello".to_string()),
+            Value::Boolean(true),
+            Value::Null,
+            Value::Float(-2.5),
+            Value::BigInt(i64::MIN),
+            Value::Blob(vec![0, 0xFF, 0]),
+        ];
         let row = Row::new(values);
 
         let bytes = row.to_bytes();
@@ -172,11 +424,154 @@ mod tests {
                 (Value::Text(a), Value::Text(b)) => assert_eq!(a, b),
                 (Value::Boolean(a), Value::Boolean(b)) => assert_eq!(a, b),
                 (Value::Null, Value::Null) => (),
+                (Value::Float(a), Value::Float(b)) => assert_eq!(a, b),
+                (Value::BigInt(a), Value::BigInt(b)) => assert_eq!(a, b),
+                (Value::Blob(a), Value::Blob(b)) => assert_eq!(a, b),
                 _ => panic!("Mismatched value types"),
             }
         }
     }
 
+    #[test]
+    fn test_compact_encoding() {
+        let columns = vec![
+            Column::new("id", DataType::Integer),
+            Column::new("name", DataType::Text),
+            Column::new("active", DataType::Boolean),
+            Column::new("score", DataType::Float),
+            Column::new("at", DataType::BigInt),
+            Column::new("data", DataType::Blob),
+            Column::new("note", DataType::Text),
+            Column::new("flag", DataType::Boolean),
+            Column::new("extra", DataType::Integer),
+        ];
+        let row = Row::new(vec![
+            Value::Integer(7),
+            Value::Text("Hello".to_string()),
+            Value::Boolean(true),
+            Value::Float(0.5),
+            Value::BigInt(-1),
+            Value::Blob(vec![1, 2]),
+            Value::Null,
+            Value::Boolean(false),
+            Value::Null,
+        ]);
+
+        let bytes = row.encode(&columns);
+        assert_eq!(bytes[0], ROW_COMPACT);
+        assert!(bytes.len() < row.to_bytes().len());
+        let (decoded, consumed) = Row::decode(&bytes, &columns).unwrap();
+        assert_eq!(decoded.values(), row.values());
+        assert_eq!(consumed, bytes.len());
+
+        // Tagged rows still decode, and rows off the schema are written tagged
+        let (decoded, _) = Row::decode(&row.to_bytes(), &columns).unwrap();
+        assert_eq!(decoded.values(), row.values());
+        let short = Row::new(vec![Value::Integer(1)]);
+        assert_eq!(short.encode(&columns), short.to_bytes());
+
+        assert!(Row::decode(&bytes[..bytes.len() - 1], &columns).is_err());
+    }
+
+    #[test]
+    fn test_row_view() {
+        let columns = vec![
+            Column::new("id", DataType::Integer),
+            Column::new("name", DataType::Text),
+            Column::new("note", DataType::Text),
+        ];
+        let row = Row::new(vec![
+            Value::Integer(7),
+            Value::Text("Hello".to_string()),
+            Value::Null,
+        ]);
+
+        for bytes in [row.encode(&columns), row.to_bytes()] {
+            let (view, consumed) = RowView::new(&bytes, &columns).unwrap();
+            assert_eq!(consumed, bytes.len());
+            assert_eq!(view.len(), 3);
+            assert_eq!(view.get(0), Some(Value::Integer(7)));
+            assert_eq!(view.get_str(1), Some("Hello"));
+            assert_eq!(view.get(2), Some(Value::Null));
+            assert_eq!(view.get_str(0), None);
+            assert_eq!(view.get(3), None);
+            assert_eq!(view.to_row().values(), row.values());
+        }
+    }
+
+    #[test]
+    fn test_schema_validated_decoding() {
+        let schema = Schema::new(
+            "users",
+            vec![
+                Column::new("id", DataType::Integer).not_null(),
+                Column::new("name", DataType::Text),
+            ],
+        )
+        .unwrap();
+        let decode = |values: Vec<Value>| {
+            Row::from_bytes_with_schema(&Row::new(values).to_bytes(), &schema)
+                .map(|(row, _)| row)
+                .map_err(|e| e.to_string())
+        };
+
+        let row = decode(vec![Value::Integer(1), Value::Null]).unwrap();
+        assert_eq!(row.values()[0], Value::Integer(1));
+        assert_eq!(
+            decode(vec![Value::Integer(1)]).unwrap_err(),
+            "Row has 1 values but table 'users' has 2 columns"
+        );
+        assert_eq!(
+            decode(vec![Value::Integer(1), Value::Boolean(true)]).unwrap_err(),
+            "BOOLEAN value in TEXT column 'name'"
+        );
+        assert_eq!(
+            decode(vec![Value::Null, Value::Null]).unwrap_err(),
+            "NULL in NOT NULL column 'id'"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_row_serde() {
+        let row = Row::new(vec![
+            Value::Integer(1),
+            Value::Text("Alice".to_string()),
+            Value::Null,
+            Value::Blob(vec![0xFF]),
+        ]);
+
+        let json = serde_json::to_string(&row).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"Integer":1},{"Text":"Alice"},"Null",{"Blob":[255]}]"#
+        );
+        let parsed: Row = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.values(), row.values());
+    }
+
+    #[test]
+    fn test_value_ordering() {
+        assert_eq!(
+            Value::Integer(2).compare(&Value::Float(2.5)),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            Value::Float(3.0).compare(&Value::Integer(3)),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            Value::BigInt(1 << 40).compare(&Value::Integer(i32::MAX)),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(Value::Float(f64::NAN).compare(&Value::Float(1.0)), None);
+        assert_eq!(Value::Null.compare(&Value::Null), None);
+        assert_eq!(
+            Value::Text("a".to_string()).compare(&Value::Integer(1)),
+            None
+        );
+    }
+
     #[test]
     fn test_row_with_terminator() {
         let row = Row::new(vec![Value::Integer(42), Value::Text("test".to_string())]);
@@ -1,17 +1,95 @@
-use std::io::{self, Error, ErrorKind};
-
-#[derive(Debug)]
+use crate::error::{HozonError, Result};
+
+// Constraint flags stored in the high bits of a column's data type byte.
+// Catalogs written before they existed have them clear.
+const COLUMN_PRIMARY_KEY: u8 = 0x80;
+const COLUMN_NOT_NULL: u8 = 0x40;
+const COLUMN_AUTOINCREMENT: u8 = 0x20;
+const COLUMN_FLAGS: u8 = COLUMN_PRIMARY_KEY | COLUMN_NOT_NULL | COLUMN_AUTOINCREMENT;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "UPPERCASE")
+)]
 pub enum DataType {
     Integer,
     Text,
     Boolean,
-    Null,
+    Float,
+    BigInt,
+    Blob,
 }
 
-#[derive(Debug)]
+impl DataType {
+    /// The type's name in SQL
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataType::Integer => "INTEGER",
+            DataType::Text => "TEXT",
+            DataType::Boolean => "BOOLEAN",
+            DataType::Float => "FLOAT",
+            DataType::BigInt => "BIGINT",
+            DataType::Blob => "BLOB",
+        }
+    }
+}
+
+/// How table, view, index and column names in statements are matched
+/// against the catalog. Names are stored as declared either way, and must be
+/// unique ignoring case so a database behaves the same in both modes.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IdentifierCase {
+    /// `Users` and `users` name the same table, as in SQLite (ASCII only)
+    #[default]
+    Insensitive,
+    /// Names must match exactly
+    Sensitive,
+}
+
+impl IdentifierCase {
+    /// Parse a PRAGMA-style setting: INSENSITIVE or SENSITIVE
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_uppercase().as_str() {
+            "INSENSITIVE" => Some(IdentifierCase::Insensitive),
+            "SENSITIVE" => Some(IdentifierCase::Sensitive),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IdentifierCase::Insensitive => "INSENSITIVE",
+            IdentifierCase::Sensitive => "SENSITIVE",
+        }
+    }
+
+    /// Whether `name` in a statement refers to the identifier `declared`
+    pub fn matches(&self, declared: &str, name: &str) -> bool {
+        match self {
+            IdentifierCase::Insensitive => declared.eq_ignore_ascii_case(name),
+            IdentifierCase::Sensitive => declared == name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Column {
     name: String,
     data_type: DataType,
+    #[cfg_attr(feature = "serde", serde(default))]
+    primary_key: bool,
+    #[cfg_attr(feature = "serde", serde(default = "nullable_by_default"))]
+    nullable: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    auto_increment: bool,
+}
+
+#[cfg(feature = "serde")]
+fn nullable_by_default() -> bool {
+    true
 }
 
 impl Column {
@@ -19,9 +97,44 @@ impl Column {
         Column {
             name: name.to_string(),
             data_type,
+            primary_key: false,
+            nullable: true,
+            auto_increment: false,
         }
     }
 
+    /// Make this the table's primary key: unique and never NULL
+    pub fn primary_key(mut self) -> Self {
+        self.primary_key = true;
+        self.nullable = false;
+        self
+    }
+
+    /// Reject NULL values in this column
+    pub fn not_null(mut self) -> Self {
+        self.nullable = false;
+        self
+    }
+
+    /// Fill this column from the table's sequence when an insert leaves it
+    /// out or gives NULL. Only valid on INTEGER and BIGINT columns.
+    pub fn auto_increment(mut self) -> Self {
+        self.auto_increment = true;
+        self
+    }
+
+    pub fn is_primary_key(&self) -> bool {
+        self.primary_key
+    }
+
+    pub fn is_nullable(&self) -> bool {
+        self.nullable
+    }
+
+    pub fn is_auto_increment(&self) -> bool {
+        self.auto_increment
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -32,17 +145,82 @@ impl Column {
 }
 
 #[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "SchemaFields")
+)]
 pub struct Schema {
     table_name: String,
     columns: Vec<Column>,
 }
 
+/// A deserialized schema, before `Schema::new` has checked it
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct SchemaFields {
+    table_name: String,
+    columns: Vec<Column>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<SchemaFields> for Schema {
+    type Error = HozonError;
+
+    fn try_from(fields: SchemaFields) -> Result<Schema> {
+        let columns = fields
+            .columns
+            .into_iter()
+            .map(|c| if c.primary_key { c.primary_key() } else { c })
+            .collect();
+        Schema::new(&fields.table_name, columns)
+    }
+}
+
 impl Schema {
-    pub fn new(table_name: &str, columns: Vec<Column>) -> Self {
-        Schema {
+    /// Build a schema, rejecting two columns with the same name, more than
+    /// one primary key or AUTOINCREMENT column, and AUTOINCREMENT on a
+    /// column that isn't INTEGER or BIGINT
+    pub fn new(table_name: &str, columns: Vec<Column>) -> Result<Self> {
+        if columns.iter().filter(|c| c.primary_key).count() > 1 {
+            return Err(HozonError::InvalidInput(format!(
+                "Table '{}' has more than one primary key",
+                table_name
+            )));
+        }
+
+        if columns.iter().filter(|c| c.auto_increment).count() > 1 {
+            return Err(HozonError::InvalidInput(format!(
+                "Table '{}' has more than one AUTOINCREMENT column",
+                table_name
+            )));
+        }
+
+        if let Some(column) = columns.iter().find(|c| {
+            c.auto_increment && !matches!(c.data_type, DataType::Integer | DataType::BigInt)
+        }) {
+            return Err(HozonError::InvalidInput(format!(
+                "AUTOINCREMENT column '{}' must be INTEGER or BIGINT",
+                column.name
+            )));
+        }
+
+        for (i, column) in columns.iter().enumerate() {
+            if columns[..i]
+                .iter()
+                .any(|c| c.name.eq_ignore_ascii_case(&column.name))
+            {
+                return Err(HozonError::InvalidInput(format!(
+                    "Duplicate column name '{}' in table '{}'",
+                    column.name, table_name
+                )));
+            }
+        }
+
+        Ok(Schema {
             table_name: table_name.to_string(),
             columns,
-        }
+        })
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
@@ -61,21 +239,40 @@ impl Schema {
             let col_name_bytes = column.name.as_bytes();
             bytes.extend_from_slice(&(col_name_bytes.len() as u32).to_le_bytes());
             bytes.extend_from_slice(col_name_bytes);
-            bytes.push(match column.data_type {
+            let data_type = match column.data_type {
                 DataType::Integer => 0,
                 DataType::Text => 1,
                 DataType::Boolean => 2,
-                DataType::Null => 3,
-            })
+                // 3 was the NULL type, retired in format version 6
+                DataType::Float => 4,
+                DataType::BigInt => 5,
+                DataType::Blob => 6,
+            };
+            let mut flags = 0;
+            if column.primary_key {
+                flags |= COLUMN_PRIMARY_KEY;
+            }
+            if !column.nullable {
+                flags |= COLUMN_NOT_NULL;
+            }
+            if column.auto_increment {
+                flags |= COLUMN_AUTOINCREMENT;
+            }
+            bytes.push(data_type | flags);
         }
 
         bytes
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> io::Result<(Self, usize)> {
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize)> {
         let mut offset = 0;
 
         // extract table name
+        if bytes.len() < offset + 4 {
+            return Err(HozonError::Corruption(
+                "Not enough bytes for table name length".to_string(),
+            ));
+        }
         let table_name_len = u32::from_le_bytes([
             bytes[offset],
             bytes[offset + 1],
@@ -85,24 +282,17 @@ impl Schema {
         offset += 4; // 4 bytes for length
 
         if bytes.len() < offset + table_name_len {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
+            return Err(HozonError::Corruption(
                 "Not enough bytes for table name length".to_string(),
             ));
         }
         let table_name = String::from_utf8(bytes[offset..offset + table_name_len].to_vec())
-            .map_err(|e| {
-                Error::new(
-                    ErrorKind::InvalidData,
-                    format!("Invalid UTF-8 in table name: {}", e),
-                )
-            })?;
+            .map_err(|e| HozonError::Corruption(format!("Invalid UTF-8 in table name: {}", e)))?;
         offset += table_name_len;
 
         // extract columns
         if bytes.len() < offset + 4 {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
+            return Err(HozonError::Corruption(
                 "Not enough bytes for number of columns".to_string(),
             ));
         }
@@ -118,8 +308,7 @@ impl Schema {
         let mut columns = Vec::new();
         for _ in 0..num_columns {
             if bytes.len() < offset + 4 {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
+                return Err(HozonError::Corruption(
                     "Not enough bytes for column name length".to_string(),
                 ));
             }
@@ -132,42 +321,49 @@ impl Schema {
             offset += 4; // 4 bytes for column name length
 
             if bytes.len() < offset + col_name_len {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
+                return Err(HozonError::Corruption(
                     "Not enough bytes for column name".to_string(),
                 ));
             }
 
             let col_name = String::from_utf8(bytes[offset..offset + col_name_len].to_vec())
                 .map_err(|e| {
-                    Error::new(
-                        ErrorKind::InvalidData,
-                        format!("Invalid UTF8 in column name: {}", e),
-                    )
+                    HozonError::Corruption(format!("Invalid UTF8 in column name: {}", e))
                 })?;
 
             offset += col_name_len;
 
             if bytes.len() < offset + 1 {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
+                return Err(HozonError::Corruption(
                     "Not enough bytes for column data type".to_string(),
                 ));
             }
 
-            let data_type = match bytes[offset] {
+            let flags = bytes[offset] & COLUMN_FLAGS;
+            let data_type = match bytes[offset] & !COLUMN_FLAGS {
                 0 => DataType::Integer,
                 1 => DataType::Text,
                 2 => DataType::Boolean,
-                3 => DataType::Null,
-                _ => panic!("Unknown data type"),
+                4 => DataType::Float,
+                5 => DataType::BigInt,
+                6 => DataType::Blob,
+                other => {
+                    return Err(HozonError::Corruption(format!(
+                        "Unknown column data type {}",
+                        other
+                    )));
+                }
             };
 
             offset += 1; // 1 byte for data type
 
+            let primary_key = flags & COLUMN_PRIMARY_KEY != 0;
             columns.push(Column {
                 name: col_name,
                 data_type,
+                primary_key,
+                nullable: !primary_key && flags & COLUMN_NOT_NULL == 0,
+                auto_increment: flags & COLUMN_AUTOINCREMENT != 0,
             });
         }
 
@@ -187,6 +383,13 @@ impl Schema {
     pub fn columns(&self) -> &Vec<Column> {
         &self.columns
     }
+
+    /// Position of the column `name` refers to
+    pub fn column_index(&self, name: &str, case: IdentifierCase) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|c| case.matches(&c.name, name))
+    }
 }
 
 #[cfg(test)]
@@ -196,23 +399,91 @@ mod tests {
     #[test]
     fn test_schema_serialization() {
         let columns = vec![
-            Column {
-                name: "id".to_string(),
-                data_type: DataType::Integer,
-            },
-            Column {
-                name: "name".to_string(),
-                data_type: DataType::Text,
-            },
+            Column::new("id", DataType::Integer)
+                .primary_key()
+                .auto_increment(),
+            Column::new("name", DataType::Text).not_null(),
+            Column::new("email", DataType::Text),
         ];
 
-        let schema = Schema::new("users", columns);
+        let schema = Schema::new("users", columns).unwrap();
         let bytes = schema.to_bytes();
         let (decoded, _) = Schema::from_bytes(&bytes).unwrap();
 
         assert_eq!(decoded.table_name, "users");
-        assert_eq!(decoded.columns.len(), 2);
+        assert_eq!(decoded.columns.len(), 3);
         assert_eq!(decoded.columns[0].name, "id");
         assert_eq!(decoded.columns[1].name, "name");
+        assert!(decoded.columns[0].is_primary_key());
+        assert!(!decoded.columns[1].is_primary_key());
+        assert!(!decoded.columns[0].is_nullable());
+        assert!(!decoded.columns[1].is_nullable());
+        assert!(decoded.columns[2].is_nullable());
+        assert!(decoded.columns[0].is_auto_increment());
+        assert!(!decoded.columns[1].is_auto_increment());
+    }
+
+    #[test]
+    fn test_single_primary_key() {
+        let columns = vec![
+            Column::new("a", DataType::Integer).primary_key(),
+            Column::new("b", DataType::Integer).primary_key(),
+        ];
+        assert!(Schema::new("t", columns).is_err());
+    }
+
+    #[test]
+    fn test_auto_increment_must_be_integer() {
+        let columns = vec![Column::new("a", DataType::Text).auto_increment()];
+        let err = Schema::new("t", columns).unwrap_err();
+        assert!(err.to_string().contains("must be INTEGER"));
+    }
+
+    #[test]
+    fn test_duplicate_column_names_rejected() {
+        let columns = vec![
+            Column::new("a", DataType::Integer),
+            Column::new("A", DataType::Text),
+        ];
+        let err = Schema::new("t", columns).unwrap_err();
+        assert!(matches!(err, HozonError::InvalidInput(_)), "{:?}", err);
+        assert!(err.to_string().contains("Duplicate column name 'A'"));
+    }
+
+    #[test]
+    fn test_schema_rejects_garbage() {
+        assert!(Schema::from_bytes(&[1, 0]).is_err());
+
+        let mut bytes = Schema::new("t", vec![Column::new("a", DataType::Integer)])
+            .unwrap()
+            .to_bytes();
+        *bytes.last_mut().unwrap() = 9; // unknown data type
+        assert!(Schema::from_bytes(&bytes).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_schema_from_json() {
+        let json = r#"{
+            "table_name": "users",
+            "columns": [
+                {"name": "id", "data_type": "INTEGER", "primary_key": true},
+                {"name": "name", "data_type": "TEXT"}
+            ]
+        }"#;
+        let schema: Schema = serde_json::from_str(json).unwrap();
+        assert!(!schema.columns()[0].is_nullable());
+        assert!(schema.columns()[1].is_nullable());
+
+        let round_trip: Schema =
+            serde_json::from_str(&serde_json::to_string(&schema).unwrap()).unwrap();
+        assert_eq!(round_trip.to_bytes(), schema.to_bytes());
+
+        // Fixtures get the same checks as CREATE TABLE
+        let json = r#"{"table_name": "t", "columns": [
+            {"name": "a", "data_type": "TEXT"}, {"name": "A", "data_type": "TEXT"}
+        ]}"#;
+        let err = serde_json::from_str::<Schema>(json).unwrap_err();
+        assert!(err.to_string().contains("Duplicate column name 'A'"));
     }
 }
@@ -0,0 +1,301 @@
+//! Table statistics, gathered by ANALYZE: how many rows a table had and,
+//! for each column, how many distinct and NULL values it held and the
+//! smallest and largest. The planner reads them to guess how many rows a
+//! condition keeps. They are a snapshot, and drift as rows change until
+//! the table is analyzed again.
+
+use crate::catalog::ordering::total_cmp;
+use crate::catalog::row::{Row, Value};
+use crate::catalog::table::read_string;
+use crate::error::{HozonError, Result};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::ops::Bound;
+
+/// Longest encoded value kept as a column's MIN or MAX; a longer one is
+/// left NULL, as the catalog has one page to fit everything in
+const MAX_BOUND_LEN: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableStats {
+    table_name: String,
+    rows: u64,
+    columns: Vec<ColumnStats>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnStats {
+    pub distinct: u64,
+    pub nulls: u64,
+    /// NULL if every value is, or the value is too long to keep
+    pub min: Value,
+    pub max: Value,
+}
+
+/// Gathers a table's statistics a row at a time
+pub struct Collector {
+    stats: TableStats,
+    // Encoded values seen so far, per column
+    seen: Vec<HashSet<Vec<u8>>>,
+}
+
+impl Collector {
+    /// Collect statistics of `table_name`, whose rows have `width` columns
+    pub fn new(table_name: &str, width: usize) -> Self {
+        let column = ColumnStats {
+            distinct: 0,
+            nulls: 0,
+            min: Value::Null,
+            max: Value::Null,
+        };
+        Collector {
+            stats: TableStats {
+                table_name: table_name.to_string(),
+                rows: 0,
+                columns: vec![column; width],
+            },
+            seen: vec![HashSet::new(); width],
+        }
+    }
+
+    pub fn add(&mut self, row: &[Value]) {
+        self.stats.rows += 1;
+        for ((value, column), seen) in row.iter().zip(&mut self.stats.columns).zip(&mut self.seen) {
+            if *value == Value::Null {
+                column.nulls += 1;
+                continue;
+            }
+            if seen.insert(Row::new(vec![value.clone()]).to_bytes()) {
+                column.distinct += 1;
+            }
+            if column.min == Value::Null || total_cmp(value, &column.min).is_lt() {
+                column.min = value.clone();
+            }
+            if column.max == Value::Null || total_cmp(value, &column.max).is_gt() {
+                column.max = value.clone();
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> TableStats {
+        for column in &mut self.stats.columns {
+            for bound in [&mut column.min, &mut column.max] {
+                if Row::new(vec![bound.clone()]).to_bytes().len() > MAX_BOUND_LEN {
+                    *bound = Value::Null;
+                }
+            }
+        }
+        self.stats
+    }
+}
+
+impl TableStats {
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    /// How many rows the table had when analyzed
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    pub fn columns(&self) -> &[ColumnStats] {
+        &self.columns
+    }
+
+    /// The share of rows whose column `column` equals a given value
+    pub fn equality_selectivity(&self, column: usize) -> Option<f64> {
+        let stats = self.columns.get(column)?;
+        if stats.distinct == 0 {
+            return Some(0.0);
+        }
+        Some(self.non_null_share(stats) / stats.distinct as f64)
+    }
+
+    /// The share of rows whose column `column` falls between `lower` and
+    /// `upper`, assuming its values spread evenly from MIN to MAX. None
+    /// unless those and the bounds are numbers.
+    pub fn range_selectivity(
+        &self,
+        column: usize,
+        lower: Bound<&Value>,
+        upper: Bound<&Value>,
+    ) -> Option<f64> {
+        let stats = self.columns.get(column)?;
+        let (min, max) = (number(&stats.min)?, number(&stats.max)?);
+        let low = match lower {
+            Bound::Included(v) | Bound::Excluded(v) => number(v)?.max(min),
+            Bound::Unbounded => min,
+        };
+        let high = match upper {
+            Bound::Included(v) | Bound::Excluded(v) => number(v)?.min(max),
+            Bound::Unbounded => max,
+        };
+        let covered = match high.partial_cmp(&low)? {
+            Ordering::Less => 0.0,
+            // One value, or every value where they're all the same
+            _ if max == min => 1.0,
+            _ => (high - low) / (max - min),
+        };
+        Some(covered * self.non_null_share(stats))
+    }
+
+    fn non_null_share(&self, stats: &ColumnStats) -> f64 {
+        match self.rows {
+            0 => 0.0,
+            rows => (rows - stats.nulls.min(rows)) as f64 / rows as f64,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(self.table_name.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(self.table_name.as_bytes());
+        bytes.extend_from_slice(&self.rows.to_le_bytes());
+
+        // number of columns, then each column's counts and bounds
+        bytes.extend_from_slice(&(self.columns.len() as u32).to_le_bytes());
+        for column in &self.columns {
+            bytes.extend_from_slice(&column.distinct.to_le_bytes());
+            bytes.extend_from_slice(&column.nulls.to_le_bytes());
+            bytes.extend_from_slice(
+                &Row::new(vec![column.min.clone(), column.max.clone()]).to_bytes(),
+            );
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize)> {
+        let mut offset = 0;
+        let table_name = read_string(bytes, &mut offset, "statistics table name")?;
+        let rows = read_u64(bytes, &mut offset, "statistics row count")?;
+
+        let num_columns = read_u32(bytes, &mut offset, "number of statistics columns")?;
+        let mut columns = Vec::new();
+        for _ in 0..num_columns {
+            let distinct = read_u64(bytes, &mut offset, "distinct count")?;
+            let nulls = read_u64(bytes, &mut offset, "NULL count")?;
+            let (bounds, consumed) = Row::from_bytes(&bytes[offset..])?;
+            offset += consumed;
+            let [min, max] = <[Value; 2]>::try_from(bounds.values().clone()).map_err(|_| {
+                HozonError::Corruption("Statistics bounds are not a MIN and MAX".to_string())
+            })?;
+            columns.push(ColumnStats {
+                distinct,
+                nulls,
+                min,
+                max,
+            });
+        }
+
+        Ok((
+            TableStats {
+                table_name,
+                rows,
+                columns,
+            },
+            offset,
+        ))
+    }
+}
+
+/// A numeric value as a float, for interpolating
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(n) => Some(*n as f64),
+        Value::BigInt(n) => Some(*n as f64),
+        Value::Float(n) if !n.is_nan() => Some(*n),
+        _ => None,
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: &mut usize, what: &str) -> Result<u32> {
+    let field = read_field::<4>(bytes, offset, what)?;
+    Ok(u32::from_le_bytes(field))
+}
+
+fn read_u64(bytes: &[u8], offset: &mut usize, what: &str) -> Result<u64> {
+    let field = read_field::<8>(bytes, offset, what)?;
+    Ok(u64::from_le_bytes(field))
+}
+
+fn read_field<const N: usize>(bytes: &[u8], offset: &mut usize, what: &str) -> Result<[u8; N]> {
+    let Some(field) = bytes.get(*offset..*offset + N) else {
+        return Err(HozonError::Corruption(format!(
+            "Not enough bytes for {}",
+            what
+        )));
+    };
+    *offset += N;
+    Ok(field.try_into().expect("slice of N bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect() -> TableStats {
+        let mut collector = Collector::new("t", 2);
+        for row in [
+            vec![Value::Integer(1), Value::Text("a".to_string())],
+            vec![Value::Integer(5), Value::Null],
+            vec![Value::Integer(9), Value::Text("a".to_string())],
+            vec![Value::Integer(5), Value::Text("b".repeat(100))],
+        ] {
+            collector.add(&row);
+        }
+        collector.finish()
+    }
+
+    #[test]
+    fn test_collect() {
+        let stats = collect();
+        assert_eq!(stats.rows(), 4);
+
+        let id = &stats.columns()[0];
+        assert_eq!((id.distinct, id.nulls), (3, 0));
+        assert_eq!((&id.min, &id.max), (&Value::Integer(1), &Value::Integer(9)));
+
+        // The long text is counted but too long to keep as the MAX
+        let name = &stats.columns()[1];
+        assert_eq!((name.distinct, name.nulls), (2, 1));
+        assert_eq!(name.min, Value::Text("a".to_string()));
+        assert_eq!(name.max, Value::Null);
+    }
+
+    #[test]
+    fn test_selectivity() {
+        let stats = collect();
+        assert_eq!(stats.equality_selectivity(0), Some(1.0 / 3.0));
+        assert_eq!(stats.equality_selectivity(1), Some(0.75 / 2.0));
+        assert_eq!(stats.equality_selectivity(2), None);
+
+        let five = Value::Integer(5);
+        let range = |lower, upper| stats.range_selectivity(0, lower, upper);
+        assert_eq!(range(Bound::Included(&five), Bound::Unbounded), Some(0.5));
+        assert_eq!(range(Bound::Unbounded, Bound::Excluded(&five)), Some(0.5));
+        assert_eq!(
+            range(Bound::Excluded(&Value::Integer(20)), Bound::Unbounded),
+            Some(0.0)
+        );
+        assert_eq!(
+            stats.range_selectivity(1, Bound::Unbounded, Bound::Unbounded),
+            None
+        );
+    }
+
+    #[test]
+    fn test_stats_serialization() {
+        let stats = collect();
+        let mut bytes = stats.to_bytes();
+        bytes.extend_from_slice(&[0xAB; 3]);
+
+        let (decoded, consumed) = TableStats::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, stats);
+        assert_eq!(consumed, bytes.len() - 3);
+
+        // Cut off in the first column's distinct count
+        let err = TableStats::from_bytes(&bytes[..20]).unwrap_err();
+        assert!(matches!(err, HozonError::Corruption(_)), "{:?}", err);
+    }
+}
@@ -0,0 +1,124 @@
+//! Read-only system tables generated from the catalog, so schemas can be
+//! discovered with plain SELECTs:
+//!
+//! - `hozon_tables`: name, type ('table' or 'view'), first_page, row_count
+//! - `hozon_columns`: table_name, name, position, data_type, nullable,
+//!   primary_key
+//! - `hozon_stats`: table_name, column_name, row_count, distinct_count,
+//!   null_count, min, max - what the last ANALYZE of each table found
+
+use crate::catalog::row::{Row, Value};
+use crate::catalog::table::TableCatalog;
+
+/// User tables and views can't take names with this prefix
+pub const RESERVED_PREFIX: &str = "hozon_";
+
+/// Column names and rows of the system table `name`, if there is one
+pub fn system_table(catalog: &TableCatalog, name: &str) -> Option<(Vec<String>, Vec<Row>)> {
+    let case = catalog.identifier_case();
+    if case.matches("hozon_tables", name) {
+        Some(tables(catalog))
+    } else if case.matches("hozon_columns", name) {
+        Some(columns(catalog))
+    } else if case.matches("hozon_stats", name) {
+        Some(stats(catalog))
+    } else {
+        None
+    }
+}
+
+fn names(columns: &[&str]) -> Vec<String> {
+    columns.iter().map(|c| c.to_string()).collect()
+}
+
+fn tables(catalog: &TableCatalog) -> (Vec<String>, Vec<Row>) {
+    let mut rows = Vec::new();
+
+    for name in catalog.list_tables() {
+        let table = catalog.get_table(&name).expect("listed table exists");
+        rows.push(Row::new(vec![
+            Value::Text(name),
+            Value::Text("table".to_string()),
+            Value::Integer(table.first_page() as i32),
+            Value::Integer(table.row_count() as i32),
+        ]));
+    }
+
+    for name in catalog.list_views() {
+        rows.push(Row::new(vec![
+            Value::Text(name),
+            Value::Text("view".to_string()),
+            Value::Null,
+            Value::Null,
+        ]));
+    }
+
+    (names(&["name", "type", "first_page", "row_count"]), rows)
+}
+
+fn columns(catalog: &TableCatalog) -> (Vec<String>, Vec<Row>) {
+    let mut rows = Vec::new();
+
+    for name in catalog.list_tables() {
+        let schema = catalog
+            .get_table(&name)
+            .expect("listed table exists")
+            .schema();
+        for (position, column) in schema.columns().iter().enumerate() {
+            rows.push(Row::new(vec![
+                Value::Text(name.clone()),
+                Value::Text(column.name().to_string()),
+                Value::Integer(position as i32 + 1),
+                Value::Text(column.data_type().as_str().to_string()),
+                Value::Boolean(column.is_nullable()),
+                Value::Boolean(column.is_primary_key()),
+            ]));
+        }
+    }
+
+    let columns = [
+        "table_name",
+        "name",
+        "position",
+        "data_type",
+        "nullable",
+        "primary_key",
+    ];
+    (names(&columns), rows)
+}
+
+fn stats(catalog: &TableCatalog) -> (Vec<String>, Vec<Row>) {
+    let mut rows = Vec::new();
+
+    for name in catalog.list_tables() {
+        let Some(stats) = catalog.table_stats(&name) else {
+            continue;
+        };
+        let schema = catalog
+            .get_table(&name)
+            .expect("listed table exists")
+            .schema();
+        for (column, column_stats) in schema.columns().iter().zip(stats.columns()) {
+            rows.push(Row::new(vec![
+                Value::Text(name.clone()),
+                Value::Text(column.name().to_string()),
+                Value::Integer(stats.rows() as i32),
+                Value::Integer(column_stats.distinct as i32),
+                Value::Integer(column_stats.nulls as i32),
+                column_stats.min.clone(),
+                column_stats.max.clone(),
+            ]));
+        }
+    }
+
+    let columns = [
+        "table_name",
+        "column_name",
+        "row_count",
+        "distinct_count",
+        "null_count",
+        "min",
+        "max",
+    ];
+    (names(&columns), rows)
+}
@@ -1,10 +1,61 @@
-use crate::catalog::schema::Schema;
-use crate::storage::page::{PageManager, PageMetadata};
-use std::collections::HashMap;
-use std::io::{self, Error, ErrorKind};
+use crate::catalog::index::{self, IndexMetadata};
+use crate::catalog::ordering::SortKey;
+use crate::catalog::row::{ROW_DELETED, Row, RowView, Value};
+use crate::catalog::schema::{IdentifierCase, Schema};
+use crate::catalog::stats::{Collector, TableStats};
+use crate::catalog::system;
+use crate::error::{HozonError, Result};
+use crate::storage::backup::BackupStats;
+use crate::storage::btree::{self, BTree, Entry};
+use crate::storage::log::{self, LogStore};
+use crate::storage::migration;
+use crate::storage::page::{
+    PAGE_DATA_START, PageManager, PageMetadata, PageType, StorageEngine, SyncMode,
+};
+use crate::storage::stats::IoStats;
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
+use std::time::Duration;
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// Read a u32 length-prefixed UTF-8 string at `offset`, advancing past it
+pub(crate) fn read_string(bytes: &[u8], offset: &mut usize, what: &str) -> Result<String> {
+    if bytes.len() < *offset + 4 {
+        return Err(HozonError::Corruption(format!(
+            "Not enough bytes for {} length",
+            what
+        )));
+    }
+    let len = read_u32(bytes, *offset) as usize;
+    *offset += 4;
+
+    if bytes.len() < *offset + len {
+        return Err(HozonError::Corruption(format!(
+            "Not enough bytes for {}",
+            what
+        )));
+    }
+    let text = String::from_utf8(bytes[*offset..*offset + len].to_vec())
+        .map_err(|e| HozonError::Corruption(format!("Invalid UTF-8 in {}: {}", what, e)))?;
+    *offset += len;
+    Ok(text)
+}
+
 pub struct TableMetadata {
     schema: Schema,
     first_page: u32,
+    // last value handed out for the AUTOINCREMENT column, 0 if none yet
+    sequence: i64,
+    // page new rows are appended to
+    last_page: u32,
+    row_count: usize,
 }
 
 impl TableMetadata {
@@ -12,96 +63,317 @@ impl TableMetadata {
         self.first_page
     }
 
+    pub fn sequence(&self) -> i64 {
+        self.sequence
+    }
+
+    pub fn last_page(&self) -> u32 {
+        self.last_page
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.row_count
+    }
+
     pub fn schema(&self) -> &Schema {
         &self.schema
     }
-}
 
-pub struct TableCatalog {
-    tables: HashMap<String, TableMetadata>,
-    page_manager: PageManager,
-}
+    /// Parse one catalog entry: the schema followed by its first page,
+    /// sequence, last page and row count
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize)> {
+        let (schema, mut offset) = Schema::from_bytes(bytes)?;
 
-impl TableCatalog {
-    pub fn new(mut page_manager: PageManager) -> io::Result<Self> {
-        // If this is a new database (only page 0 exists), allocate page 1 for catalog
-        if page_manager.num_pages() == 1 {
-            page_manager.allocate_page()?;
+        if bytes.len() < offset + 4 {
+            return Err(HozonError::Corruption(
+                "Not enough bytes for first page".to_string(),
+            ));
         }
 
-        let catalog_data = page_manager.read_page(1u32)?;
+        let first_page = u32::from_le_bytes([
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ]);
+        offset += 4;
 
-        // check if catalog is empty
-        if catalog_data.iter().all(|&b| b == 0) {
-            // empty catalog - new db
-            return Ok(TableCatalog {
-                tables: HashMap::new(),
-                page_manager,
-            });
+        if bytes.len() < offset + 8 {
+            return Err(HozonError::Corruption(
+                "Not enough bytes for sequence".to_string(),
+            ));
         }
 
-        // parse catalog data
-        let mut offset = 0;
+        let mut sequence = [0u8; 8];
+        sequence.copy_from_slice(&bytes[offset..offset + 8]);
+        offset += 8;
 
-        if catalog_data.len() < 4 {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "Not enough bytes for number of tables".to_string(),
+        if bytes.len() < offset + 12 {
+            return Err(HozonError::Corruption(
+                "Not enough bytes for last page and row count".to_string(),
             ));
         }
 
-        // Reconstruct catalog
-        let num_tables = u32::from_le_bytes([
-            catalog_data[offset],
-            catalog_data[offset + 1],
-            catalog_data[offset + 2],
-            catalog_data[offset + 3],
-        ]) as usize;
+        let last_page = u32::from_le_bytes([
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ]);
+        offset += 4;
+
+        let mut row_count = [0u8; 8];
+        row_count.copy_from_slice(&bytes[offset..offset + 8]);
+        offset += 8;
+
+        Ok((
+            TableMetadata {
+                schema,
+                first_page,
+                sequence: i64::from_le_bytes(sequence),
+                last_page,
+                row_count: u64::from_le_bytes(row_count) as usize,
+            },
+            offset,
+        ))
+    }
+}
+
+/// The tables, views, indexes and statistics the catalog page lists, each
+/// by `key`
+fn read_catalog(page_manager: &PageManager) -> Result<Entries> {
+    let catalog_data = page_manager.read_page(1u32)?;
+
+    // check if catalog is empty
+    if catalog_data.iter().all(|&b| b == 0) {
+        // empty catalog - new db
+        return Ok((
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+        ));
+    }
+
+    // parse catalog data
+    let mut offset = 0;
+
+    if catalog_data.len() < 4 {
+        return Err(HozonError::Corruption(
+            "Not enough bytes for number of tables".to_string(),
+        ));
+    }
+
+    // Reconstruct catalog
+    let num_tables = u32::from_le_bytes([
+        catalog_data[offset],
+        catalog_data[offset + 1],
+        catalog_data[offset + 2],
+        catalog_data[offset + 3],
+    ]) as usize;
+    offset += 4;
+
+    let mut tables = HashMap::new();
+
+    for _ in 0..num_tables {
+        let (table_metadata, bytes_consumed) = TableMetadata::from_bytes(&catalog_data[offset..])?;
+        offset += bytes_consumed;
+
+        tables.insert(key(table_metadata.schema.table_name()), table_metadata);
+    }
+
+    // Views follow the tables. Catalogs written before views existed end
+    // in zero padding here, which reads as no views.
+    let mut views = HashMap::new();
+    if offset + 4 <= catalog_data.len() {
+        let num_views = read_u32(&catalog_data, offset) as usize;
         offset += 4;
+        for _ in 0..num_views {
+            let name = read_string(&catalog_data, &mut offset, "view name")?;
+            let query = read_string(&catalog_data, &mut offset, "view query")?;
+            views.insert(key(&name), View { name, query });
+        }
+    }
 
-        let mut tables = HashMap::new();
+    // Then indexes, missing the same way from older catalogs
+    let mut indexes = HashMap::new();
+    if offset + 4 <= catalog_data.len() {
+        let num_indexes = read_u32(&catalog_data, offset) as usize;
+        offset += 4;
+        for _ in 0..num_indexes {
+            let (index, bytes_consumed) = IndexMetadata::from_bytes(&catalog_data[offset..])?;
+            offset += bytes_consumed;
+            indexes.insert(key(index.name()), index);
+        }
+    }
 
-        for _ in 0..num_tables {
-            let (schema, bytes_consumed) = Schema::from_bytes(&catalog_data[offset..])?;
+    // And the statistics ANALYZE gathered, by table
+    let mut statistics = HashMap::new();
+    if offset + 4 <= catalog_data.len() {
+        let num_stats = read_u32(&catalog_data, offset) as usize;
+        offset += 4;
+        for _ in 0..num_stats {
+            let (stats, bytes_consumed) = TableStats::from_bytes(&catalog_data[offset..])?;
             offset += bytes_consumed;
+            statistics.insert(key(stats.table_name()), stats);
+        }
+    }
 
-            if catalog_data.len() < offset + 4 {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    "Not enough bytes for first page".to_string(),
-                ));
-            }
+    Ok((tables, views, indexes, statistics))
+}
 
-            let first_page = u32::from_le_bytes([
-                catalog_data[offset],
-                catalog_data[offset + 1],
-                catalog_data[offset + 2],
-                catalog_data[offset + 3],
-            ]);
-            offset += 4;
+/// A row's stable id: the page it lives on in the high 32 bits and its slot
+/// on that page in the low ones. Log-engine rows are numbered from the
+/// table's first page in insertion order.
+pub fn rowid(page: u32, slot: usize) -> i64 {
+    ((page as i64) << 32) | slot as i64
+}
 
-            let table_metadata = TableMetadata { schema, first_page };
+/// The row at the start of `bytes` on a data page and its length in
+/// bytes; None for a deleted row's tombstone
+fn page_row<'a>(bytes: &'a [u8], schema: &Schema) -> Result<(Option<RowView<'a>>, usize)> {
+    if bytes.first() == Some(&ROW_DELETED) {
+        return Ok((None, 1));
+    }
+    let (view, len) = RowView::with_schema(bytes, schema)?;
+    Ok((Some(view), len))
+}
 
-            tables.insert(
-                table_metadata.schema.table_name().to_string(),
-                table_metadata,
-            );
+/// What `TableCatalog::table_info` reports about one table
+#[derive(Debug)]
+pub struct TableInfo<'a> {
+    pub schema: &'a Schema,
+    pub first_page: u32,
+    pub row_count: usize,
+}
+
+struct View {
+    name: String,
+    // SELECT text
+    query: String,
+}
+
+/// Positions in `schema` of the columns `index` is on
+fn key_positions(schema: &Schema, index: &IndexMetadata) -> Vec<usize> {
+    index
+        .columns()
+        .iter()
+        .map(|name| {
+            schema
+                .columns()
+                .iter()
+                .position(|c| c.name() == name)
+                .expect("indexes are on columns of their table")
+        })
+        .collect()
+}
+
+/// The key of `row` in an index on the columns at `positions`
+fn index_key(positions: &[usize], row: &Row) -> SortKey {
+    SortKey(positions.iter().map(|&i| row.values()[i].clone()).collect())
+}
+
+/// Lookup key for a table, view or index name. Names are unique ignoring
+/// case, so a file opened in either identifier mode resolves the same way.
+fn key(name: &str) -> String {
+    name.to_ascii_lowercase()
+}
+
+type Entries = (
+    HashMap<String, TableMetadata>,
+    HashMap<String, View>,
+    HashMap<String, IndexMetadata>,
+    HashMap<String, TableStats>,
+);
+
+pub struct TableCatalog {
+    // All four are keyed by `key(name)`, statistics by their table's
+    tables: HashMap<String, TableMetadata>,
+    views: HashMap<String, View>,
+    indexes: HashMap<String, IndexMetadata>,
+    statistics: HashMap<String, TableStats>,
+    identifier_case: IdentifierCase,
+    page_manager: PageManager,
+    // row storage for StorageEngine::Log databases
+    log: Option<LogStore>,
+}
+
+impl TableCatalog {
+    pub fn new(mut page_manager: PageManager) -> Result<Self> {
+        // Upgrade older files before reading anything whose layout may have changed
+        migration::migrate(&mut page_manager)?;
+
+        // If this is a new database (only page 0 exists), allocate page 1 for catalog
+        if page_manager.num_pages() == 1 {
+            page_manager.allocate_page()?;
         }
 
+        let log = match (page_manager.engine(), page_manager.path()) {
+            (StorageEngine::Pages, _) => None,
+            // Opening the segments creates and appends to them
+            (StorageEngine::Log, Some(_)) if page_manager.is_read_only() => {
+                return Err(HozonError::Unsupported(
+                    "A log-structured database can't be opened read-only".to_string(),
+                ));
+            }
+            (StorageEngine::Log, Some(path)) => Some(LogStore::open(log::log_dir(path))?),
+            (StorageEngine::Log, None) => {
+                return Err(HozonError::InvalidInput(
+                    "The log-structured engine needs a database file".to_string(),
+                ));
+            }
+        };
+
+        let (tables, views, indexes, statistics) = read_catalog(&page_manager)?;
         Ok(TableCatalog {
             tables,
+            views,
+            indexes,
+            statistics,
+            identifier_case: IdentifierCase::default(),
             page_manager,
+            log,
         })
     }
 
-    pub fn create_table(&mut self, schema: Schema) -> io::Result<()> {
+    pub fn identifier_case(&self) -> IdentifierCase {
+        self.identifier_case
+    }
+
+    /// How table, view, index and column names are matched from now on
+    pub fn set_identifier_case(&mut self, case: IdentifierCase) {
+        self.identifier_case = case;
+    }
+
+    /// The key `name` is stored under, if it resolves in the current mode
+    fn resolve(&self, name: &str, stored_name: Option<&str>) -> Option<String> {
+        stored_name
+            .filter(|stored| self.identifier_case.matches(stored, name))
+            .map(key)
+    }
+
+    fn table_key(&self, name: &str) -> Option<String> {
+        let stored = self.tables.get(&key(name)).map(|t| t.schema.table_name());
+        self.resolve(name, stored)
+    }
+
+    pub fn create_table(&mut self, schema: Schema) -> Result<()> {
+        // Overwriting the entry would leak the old table's pages
+        self.check_name_free(schema.table_name())?;
+
         // allocate first page for table data
         let first_page = self.page_manager.allocate_page()?;
 
-        let table_name = schema.table_name().to_string();
-        let table_metadata = TableMetadata { schema, first_page };
+        let table_key = key(schema.table_name());
+        let table_metadata = TableMetadata {
+            schema,
+            first_page,
+            sequence: 0,
+            last_page: first_page,
+            row_count: 0,
+        };
 
-        self.tables.insert(table_name, table_metadata);
+        self.tables.insert(table_key, table_metadata);
 
         // save catalog
         self.save()?;
@@ -109,7 +381,315 @@ impl TableCatalog {
         Ok(())
     }
 
-    pub fn save(&mut self) -> io::Result<()> {
+    /// Tables and views share one namespace, minus the names reserved for
+    /// the system tables
+    fn check_name_free(&self, name: &str) -> Result<()> {
+        if key(name).starts_with(system::RESERVED_PREFIX) {
+            return Err(HozonError::InvalidInput(format!(
+                "Names starting with '{}' are reserved",
+                system::RESERVED_PREFIX
+            )));
+        }
+        if let Some(table) = self.tables.get(&key(name)) {
+            return Err(HozonError::AlreadyExists(format!(
+                "Table '{}' already exists",
+                table.schema.table_name()
+            )));
+        }
+        if let Some(view) = self.views.get(&key(name)) {
+            return Err(HozonError::AlreadyExists(format!(
+                "View '{}' already exists",
+                view.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Save a view defined by the SELECT text `query`. The caller checks that
+    /// the query is valid.
+    pub fn create_view(&mut self, name: &str, query: &str) -> Result<()> {
+        self.check_name_free(name)?;
+        let view = View {
+            name: name.to_string(),
+            query: query.to_string(),
+        };
+        self.views.insert(key(name), view);
+        self.save()
+    }
+
+    pub fn get_view(&self, name: &str) -> Option<&str> {
+        let stored = self.views.get(&key(name)).map(|v| v.name.as_str());
+        let view_key = self.resolve(name, stored)?;
+        Some(&self.views[&view_key].query)
+    }
+
+    /// View names in alphabetical order
+    pub fn list_views(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.views.values().map(|v| v.name.clone()).collect();
+        names.sort();
+        names
+    }
+
+    /// Add an index on `columns` of `table_name` and fill it from the
+    /// table's rows. A UNIQUE index fails if two of them share a key.
+    pub fn create_index(
+        &mut self,
+        name: &str,
+        table_name: &str,
+        columns: Vec<String>,
+        unique: bool,
+    ) -> Result<&IndexMetadata> {
+        if let Some(index) = self.indexes.get(&key(name)) {
+            return Err(HozonError::AlreadyExists(format!(
+                "Index '{}' already exists",
+                index.name()
+            )));
+        }
+
+        let schema = self
+            .get_table(table_name)
+            .map(|t| t.schema())
+            .ok_or_else(|| {
+                HozonError::TableNotFound(format!("Table '{}' does not exist", table_name))
+            })?;
+        if columns.is_empty() {
+            return Err(HozonError::InvalidInput(format!(
+                "Index '{}' needs at least one column",
+                name
+            )));
+        }
+        // Store the names as the schema spells them
+        let mut resolved = Vec::with_capacity(columns.len());
+        for column in &columns {
+            match schema.column_index(column, self.identifier_case) {
+                Some(idx) => resolved.push(schema.columns()[idx].name().to_string()),
+                None => {
+                    return Err(HozonError::ColumnNotFound(format!(
+                        "Column '{}' does not exist in table '{}'",
+                        column, table_name
+                    )));
+                }
+            }
+        }
+        let table_name = schema.table_name().to_string();
+
+        // Check the rows before taking a page
+        let mut index = IndexMetadata::new(name, &table_name, resolved, unique, 0);
+        let positions = key_positions(schema, &index);
+        let mut entries = Vec::new();
+        self.scan_views(&table_name, |rowid, view| {
+            let key = positions
+                .iter()
+                .map(|&i| view.get(i).unwrap_or(Value::Null));
+            entries.push((SortKey(key.collect()), rowid));
+            Ok(())
+        })?;
+        entries.sort();
+        index.check_unique(&entries)?;
+        for (key, _) in &entries {
+            btree::check_key(&key.0)?;
+        }
+
+        let tree = BTree::create(&mut self.page_manager)?;
+        for (key, rowid) in entries {
+            tree.insert(&mut self.page_manager, key.0, rowid)?;
+        }
+        let root_page = tree.root();
+        index = IndexMetadata::new(
+            name,
+            &table_name,
+            index.columns().to_vec(),
+            unique,
+            root_page,
+        );
+        self.indexes.insert(key(name), index);
+        self.save()?;
+        Ok(&self.indexes[&key(name)])
+    }
+
+    pub fn get_index(&self, name: &str) -> Option<&IndexMetadata> {
+        let index = self.indexes.get(&key(name))?;
+        self.identifier_case
+            .matches(index.name(), name)
+            .then_some(index)
+    }
+
+    /// Indexes on `table_name`, ordered by name
+    pub fn table_indexes(&self, table_name: &str) -> Vec<&IndexMetadata> {
+        let Some(table_key) = self.table_key(table_name) else {
+            return Vec::new();
+        };
+        let mut indexes: Vec<&IndexMetadata> = self
+            .indexes
+            .values()
+            .filter(|index| key(index.table_name()) == table_key)
+            .collect();
+        indexes.sort_by(|a, b| a.name().cmp(b.name()));
+        indexes
+    }
+
+    pub fn drop_index(&mut self, name: &str) -> Result<()> {
+        if self.get_index(name).is_none() {
+            return Err(HozonError::NotFound(format!(
+                "Index '{}' does not exist",
+                name
+            )));
+        }
+        self.indexes.remove(&key(name));
+        self.save()
+    }
+
+    /// Gather statistics about `table_name`'s rows and store them in place
+    /// of any from an earlier ANALYZE
+    pub fn analyze(&mut self, table_name: &str) -> Result<&TableStats> {
+        self.table_page(table_name)?;
+        let schema = self.table_schema(table_name);
+        let width = schema.columns().len();
+        let table_name = schema.table_name().to_string();
+        let mut collector = Collector::new(&table_name, width);
+        self.scan_views(&table_name, |_, view| {
+            let row: Vec<Value> = (0..width)
+                .map(|i| view.get(i).unwrap_or(Value::Null))
+                .collect();
+            collector.add(&row);
+            Ok(())
+        })?;
+        self.statistics.insert(key(&table_name), collector.finish());
+        self.save()?;
+        Ok(&self.statistics[&key(&table_name)])
+    }
+
+    /// What the last ANALYZE of `table_name` found, if it has been analyzed
+    pub fn table_stats(&self, table_name: &str) -> Option<&TableStats> {
+        self.statistics.get(&self.table_key(table_name)?)
+    }
+
+    fn index(&self, name: &str) -> Result<&IndexMetadata> {
+        self.get_index(name)
+            .ok_or_else(|| HozonError::NotFound(format!("Index '{}' does not exist", name)))
+    }
+
+    /// The entries of index `name`, ordered by key and then rowid
+    pub fn index_entries(&self, name: &str) -> Result<Vec<Entry>> {
+        self.index(name)?.tree().entries(&self.page_manager)
+    }
+
+    /// Rowids of the rows whose key in index `name` starts with `key`
+    pub fn index_lookup(&self, name: &str, key: &[Value]) -> Result<Vec<i64>> {
+        self.index(name)?.tree().get(&self.page_manager, key)
+    }
+
+    /// The entries of index `name` with keys between `lower` and `upper`,
+    /// see `BTree::range`
+    pub fn index_range(
+        &self,
+        name: &str,
+        lower: Bound<&[Value]>,
+        upper: Bound<&[Value]>,
+    ) -> Result<Vec<Entry>> {
+        self.index(name)?
+            .tree()
+            .range(&self.page_manager, lower, upper)
+    }
+
+    /// Fail if writing `rows` would break an index on `table`: a key too
+    /// large for it, or a key a UNIQUE index already has for a row other
+    /// than those `replaced`. Runs before anything is written, so a failure
+    /// leaves the table as it was.
+    fn check_indexes(
+        &self,
+        table_name: &str,
+        replaced: &[i64],
+        rows: &[(i64, &Row)],
+    ) -> Result<()> {
+        let schema = self.table_schema(table_name);
+        for index in self.table_indexes(table_name) {
+            let positions = key_positions(schema, index);
+            let mut entries: Vec<Entry> = rows
+                .iter()
+                .map(|(rowid, row)| (index_key(&positions, row), *rowid))
+                .collect();
+            for (key, _) in &entries {
+                btree::check_key(&key.0)?;
+            }
+            if !index.is_unique() {
+                continue;
+            }
+
+            entries.sort();
+            index.check_unique(&entries)?;
+            for (key, _) in entries.iter().filter(|(key, _)| index::constrains(key)) {
+                let taken = index.tree().get(&self.page_manager, &key.0)?;
+                if taken.iter().any(|rowid| !replaced.contains(rowid)) {
+                    return Err(index.duplicate(key));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The rows of `table` holding a key a UNIQUE index would refuse `row`
+    /// for, each with the columns of that index
+    pub fn unique_conflicts(&self, table_name: &str, row: &Row) -> Result<Vec<(Vec<String>, i64)>> {
+        let schema = self.table_schema(table_name);
+        let mut conflicts = Vec::new();
+        for index in self.table_indexes(table_name) {
+            let key = index_key(&key_positions(schema, index), row);
+            if !index.is_unique() || !index::constrains(&key) {
+                continue;
+            }
+            for rowid in index.tree().get(&self.page_manager, &key.0)? {
+                conflicts.push((index.columns().to_vec(), rowid));
+            }
+        }
+        Ok(conflicts)
+    }
+
+    /// Move the entries of every index on `table` from the rows `old` to
+    /// those `new`
+    fn update_indexes(
+        &mut self,
+        table_name: &str,
+        old: &[(i64, Row)],
+        new: &[(i64, &Row)],
+    ) -> Result<()> {
+        let schema = self.table_schema(table_name);
+        let trees: Vec<(BTree, Vec<usize>)> = self
+            .table_indexes(table_name)
+            .into_iter()
+            .map(|index| (index.tree(), key_positions(schema, index)))
+            .collect();
+        for (tree, positions) in trees {
+            for (rowid, row) in old {
+                let key = index_key(&positions, row).0;
+                tree.delete(&mut self.page_manager, key, *rowid)?;
+            }
+            for (rowid, row) in new {
+                let key = index_key(&positions, row).0;
+                tree.insert(&mut self.page_manager, key, *rowid)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The rows of `table` with `rowids` as they are now, when it has
+    /// indexes whose entries for them are about to change
+    fn indexed_rows(&self, table_name: &str, rowids: &[i64]) -> Result<Vec<(i64, Row)>> {
+        if self.table_indexes(table_name).is_empty() {
+            return Ok(Vec::new());
+        }
+        let wanted: HashSet<i64> = rowids.iter().copied().collect();
+        let mut rows = Vec::new();
+        self.scan_views(table_name, |rowid, view| {
+            if wanted.contains(&rowid) {
+                rows.push((rowid, view.to_row()));
+            }
+            Ok(())
+        })?;
+        Ok(rows)
+    }
+
+    pub fn save(&mut self) -> Result<()> {
         let bytes = self.to_bytes();
         self.page_manager.write_page(1u32, &bytes)?;
         Ok(())
@@ -127,57 +707,510 @@ impl TableCatalog {
 
             // first page
             bytes.extend_from_slice(&metadata.first_page.to_le_bytes());
+
+            // sequence
+            bytes.extend_from_slice(&metadata.sequence.to_le_bytes());
+
+            // last page and row count
+            bytes.extend_from_slice(&metadata.last_page.to_le_bytes());
+            bytes.extend_from_slice(&(metadata.row_count as u64).to_le_bytes());
+        }
+
+        // views (name length + name, query length + query)
+        bytes.extend_from_slice(&(self.views.len() as u32).to_le_bytes());
+        for view in self.views.values() {
+            for text in [&view.name, &view.query] {
+                bytes.extend_from_slice(&(text.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(text.as_bytes());
+            }
+        }
+
+        // indexes
+        bytes.extend_from_slice(&(self.indexes.len() as u32).to_le_bytes());
+        for index in self.indexes.values() {
+            bytes.extend_from_slice(&index.to_bytes());
+        }
+
+        // statistics
+        bytes.extend_from_slice(&(self.statistics.len() as u32).to_le_bytes());
+        for stats in self.statistics.values() {
+            bytes.extend_from_slice(&stats.to_bytes());
         }
 
         bytes
     }
 
     pub fn get_table(&self, name: &str) -> Option<&TableMetadata> {
-        self.tables.get(name)
+        self.tables.get(&self.table_key(name)?)
     }
 
+    /// Table names in alphabetical order
     pub fn list_tables(&self) -> Vec<String> {
-        self.tables.keys().cloned().collect()
+        let mut names: Vec<String> = self
+            .tables
+            .values()
+            .map(|t| t.schema.table_name().to_string())
+            .collect();
+        names.sort();
+        names
     }
 
-    pub fn drop_table(&mut self, name: &str) -> io::Result<()> {
-        match self.tables.remove(name) {
-            Some(_) => {
+    pub fn table_exists(&self, name: &str) -> bool {
+        self.get_table(name).is_some()
+    }
+
+    pub fn table_info(&self, name: &str) -> Result<TableInfo<'_>> {
+        let first_page = self.table_page(name)?;
+
+        let table = self.get_table(name).expect("table_page found it");
+        Ok(TableInfo {
+            schema: table.schema(),
+            first_page,
+            row_count: table.row_count,
+        })
+    }
+
+    pub fn drop_table(&mut self, name: &str) -> Result<()> {
+        match self.table_key(name).and_then(|k| self.tables.remove(&k)) {
+            Some(table) => {
+                if let Some(log) = &mut self.log {
+                    log.drop_table(table.first_page)?;
+                }
+                let table_key = key(table.schema.table_name());
+                self.indexes
+                    .retain(|_, index| key(index.table_name()) != table_key);
+                self.statistics.remove(&table_key);
                 self.save()?;
-                return Ok(());
+                Ok(())
             }
+            None => Err(HozonError::TableNotFound(format!(
+                "Table '{}' does not exist",
+                name
+            ))),
+        }
+    }
+
+    /// Record the last AUTOINCREMENT value used by `table`
+    pub fn set_sequence(&mut self, table_name: &str, sequence: i64) -> Result<()> {
+        match self
+            .table_key(table_name)
+            .and_then(|k| self.tables.get_mut(&k))
+        {
+            Some(meta) => meta.sequence = sequence,
             None => {
-                return Err(Error::new(
-                    ErrorKind::NotFound,
-                    format!("Table '{}' does not exist", name),
-                ));
+                return Err(HozonError::TableNotFound(format!(
+                    "Table '{}' does not exist",
+                    table_name
+                )));
             }
         }
+        self.save()
     }
 
-    pub fn read_page(&self, page_id: u32) -> io::Result<[u8; 4096]> {
+    /// Append `row` to `table`, returning its rowid. The row must already
+    /// match the schema.
+    pub fn insert_row(&mut self, table_name: &str, row: Row) -> Result<i64> {
+        let first_page = self.table_page(table_name)?;
+        let row_bytes = row.encode(self.table_schema(table_name).columns());
+        let rowid = self.next_rowid(table_name)?;
+        self.check_indexes(table_name, &[], &[(rowid, &row)])?;
+
+        match &mut self.log {
+            Some(log) => log.append(first_page, &row_bytes)?,
+            None => self.append_to_page(table_name, &row_bytes)?,
+        }
+        self.update_indexes(table_name, &[], &[(rowid, &row)])?;
+
+        if let Some(meta) = self
+            .table_key(table_name)
+            .and_then(|k| self.tables.get_mut(&k))
+        {
+            meta.row_count += 1;
+        }
+        self.save()?;
+        Ok(rowid)
+    }
+
+    /// The rowid the next row inserted into `table` gets
+    fn next_rowid(&self, table_name: &str) -> Result<i64> {
+        let table = self
+            .get_table(table_name)
+            .expect("insert_row checked the table");
+        Ok(match &self.log {
+            Some(log) => rowid(table.first_page, log.slot_count(table.first_page)),
+            None => {
+                let page_meta = self.page_manager.read_page_metadata(table.last_page)?;
+                rowid(table.last_page, page_meta.num_rows)
+            }
+        })
+    }
+
+    fn append_to_page(&mut self, table_name: &str, row_bytes: &[u8]) -> Result<()> {
+        let last_page = self
+            .get_table(table_name)
+            .expect("insert_row checked the table")
+            .last_page;
+
+        let page_meta = self.page_manager.read_page_metadata(last_page)?;
+        let offset = page_meta.last_offset;
+
+        // Check if it fits
+        // TODO: multiple page support
+        if row_bytes.len() > page_meta.free_space() {
+            return Err(HozonError::Full(
+                "Page full - multiple page support not yet implemented".to_string(),
+            ));
+        }
+
+        // Write just the row, then the metadata that makes it visible
+        // TODO: update is_full based on when page is actually full
+        self.page_manager.write_at(last_page, offset, row_bytes)?;
+        let metadata = PageMetadata {
+            last_offset: offset + row_bytes.len(),
+            num_rows: page_meta.num_rows + 1,
+            ..page_meta
+        };
+        self.page_manager.update_page_metadata(last_page, &metadata)
+    }
+
+    /// Give the row of `table` with `rowid` new contents. It keeps its rowid
+    /// and its place in scan order. The row must already match the schema.
+    pub fn replace_row(&mut self, table_name: &str, rowid: i64, row: Row) -> Result<()> {
+        self.replace_rows(table_name, &[(rowid, row)])
+    }
+
+    /// `replace_row` for several rows at once. UNIQUE indexes are checked
+    /// against all the new rows together, so rows can swap keys.
+    pub fn replace_rows(&mut self, table_name: &str, rows: &[(i64, Row)]) -> Result<()> {
+        self.table_page(table_name)?;
+        let rowids: Vec<i64> = rows.iter().map(|(rowid, _)| *rowid).collect();
+        let new: Vec<(i64, &Row)> = rows.iter().map(|(rowid, row)| (*rowid, row)).collect();
+        self.check_indexes(table_name, &rowids, &new)?;
+        let old = self.indexed_rows(table_name, &rowids)?;
+
+        for (rowid, row) in rows {
+            let row_bytes = row.encode(self.table_schema(table_name).columns());
+            self.rewrite_row(table_name, *rowid, Some(&row_bytes))?;
+        }
+        self.update_indexes(table_name, &old, &new)
+    }
+
+    /// Delete the rows of `table` with `rowids`. Their slots stay taken, so
+    /// no other row's rowid changes.
+    pub fn delete_rows(&mut self, table_name: &str, rowids: &[i64]) -> Result<()> {
+        self.table_page(table_name)?;
+        let old = self.indexed_rows(table_name, rowids)?;
+        for &rowid in rowids {
+            self.rewrite_row(table_name, rowid, None)?;
+        }
+        self.update_indexes(table_name, &old, &[])?;
+
+        if let Some(meta) = self
+            .table_key(table_name)
+            .and_then(|k| self.tables.get_mut(&k))
+        {
+            meta.row_count -= rowids.len();
+        }
+        self.save()
+    }
+
+    /// Put `row_bytes` in the slot of the live row `rowid`, or delete the
+    /// row for None
+    fn rewrite_row(
+        &mut self,
+        table_name: &str,
+        rowid: i64,
+        row_bytes: Option<&[u8]>,
+    ) -> Result<()> {
+        let first_page = self.table_page(table_name)?;
+        let (page, slot) = ((rowid >> 32) as u32, (rowid & 0xFFFF_FFFF) as usize);
+        let missing =
+            || HozonError::NotFound(format!("Table '{}' has no row {}", table_name, rowid));
+        if page != first_page {
+            return Err(missing());
+        }
+
+        match &mut self.log {
+            Some(log) => match row_bytes {
+                Some(row_bytes) => log.replace(first_page, slot, row_bytes),
+                None => log.delete(first_page, slot),
+            }
+            .map_err(|e| match e {
+                HozonError::NotFound(_) => missing(),
+                e => e,
+            }),
+            None => {
+                let row_bytes = row_bytes.unwrap_or(&[ROW_DELETED]);
+                match self.rewrite_on_page(table_name, first_page, slot, row_bytes)? {
+                    true => Ok(()),
+                    false => Err(missing()),
+                }
+            }
+        }
+    }
+
+    /// Write `row_bytes` over the live row in `slot`, moving the rows after
+    /// it; false if there is no such row
+    fn rewrite_on_page(
+        &mut self,
+        table_name: &str,
+        page: u32,
+        slot: usize,
+        row_bytes: &[u8],
+    ) -> Result<bool> {
+        let page_data = self.page_manager.read_page(page)?;
+        let page_meta = PageManager::read_metadata_from_buffer(&page_data);
+        if slot >= page_meta.num_rows {
+            return Ok(false);
+        }
+        let schema = self.table_schema(table_name);
+
+        let mut start = PAGE_DATA_START;
+        for _ in 0..slot {
+            start += page_row(&page_data[start..], schema)?.1;
+        }
+        let (live, len) = page_row(&page_data[start..], schema)?;
+        if live.is_none() {
+            return Ok(false);
+        }
+        let end = start + len;
+
+        if row_bytes.len() > len + page_meta.free_space() {
+            return Err(HozonError::Full(
+                "Page full - multiple page support not yet implemented".to_string(),
+            ));
+        }
+
+        // The rows after this one move with it; zero what they leave behind
+        let tail = &page_data[end..page_meta.last_offset];
+        let last_offset = start + row_bytes.len() + tail.len();
+        let mut moved = [row_bytes, tail].concat();
+        moved.resize(last_offset.max(page_meta.last_offset) - start, 0);
+        self.page_manager.write_at(page, start, &moved)?;
+        let metadata = PageMetadata {
+            last_offset,
+            ..page_meta
+        };
+        self.page_manager.update_page_metadata(page, &metadata)?;
+        Ok(true)
+    }
+
+    /// Every row of `table`, in insertion order
+    pub fn scan_rows(&self, table_name: &str) -> Result<Vec<Row>> {
+        let mut rows = Vec::new();
+        self.scan_views(table_name, |_, view| {
+            rows.push(view.to_row());
+            Ok(())
+        })?;
+        Ok(rows)
+    }
+
+    /// The row of `table` with `rowid`, if there is one
+    pub fn get_row(&self, table_name: &str, rowid: i64) -> Result<Option<Row>> {
+        let mut found = None;
+        self.scan_views(table_name, |id, view| {
+            if id == rowid {
+                found = Some(view.to_row());
+            }
+            Ok(())
+        })?;
+        Ok(found)
+    }
+
+    /// Visit every row of `table` in insertion order with its rowid,
+    /// decoding only the values `visit` asks the view for. A row that
+    /// doesn't fit the schema is reported as corrupt, with where it was found.
+    pub fn scan_views(
+        &self,
+        table_name: &str,
+        mut visit: impl FnMut(i64, &RowView) -> Result<()>,
+    ) -> Result<()> {
+        let first_page = self.table_page(table_name)?;
+        let schema = self.table_schema(table_name);
+        let corrupt = |index: usize, location: String, e: HozonError| {
+            HozonError::Corruption(format!(
+                "Corrupt row at index {} {} of table '{}': {}",
+                index,
+                location,
+                schema.table_name(),
+                e
+            ))
+        };
+
+        if let Some(log) = &self.log {
+            return log.scan_records(first_page, |slot, row_bytes| {
+                let (view, _) = RowView::with_schema(row_bytes, schema)
+                    .map_err(|e| corrupt(slot, "in the log".to_string(), e))?;
+                visit(rowid(first_page, slot), &view)
+            });
+        }
+
+        let page_data = self.page_manager.read_page(first_page)?;
+        let page_meta = PageManager::read_metadata_from_buffer(&page_data);
+
+        let mut offset = PAGE_DATA_START;
+        for slot in 0..page_meta.num_rows {
+            let (view, byte_consumed) = page_row(&page_data[offset..], schema)
+                .map_err(|e| corrupt(slot, format!("on page {}", first_page), e))?;
+            if let Some(view) = view {
+                visit(rowid(first_page, slot), &view)?;
+            }
+            offset += byte_consumed;
+        }
+        Ok(())
+    }
+
+    /// Schema of a table `table_page` has already found
+    fn table_schema(&self, table_name: &str) -> &Schema {
+        &self
+            .get_table(table_name)
+            .expect("table_page checked the table")
+            .schema
+    }
+
+    fn table_page(&self, table_name: &str) -> Result<u32> {
+        match self.get_table(table_name) {
+            Some(meta) => Ok(meta.first_page),
+            None => Err(HozonError::TableNotFound(format!(
+                "Table '{}' does not exist",
+                table_name
+            ))),
+        }
+    }
+
+    pub fn engine(&self) -> StorageEngine {
+        self.page_manager.engine()
+    }
+
+    /// Path of the database file; None for an in-memory database
+    pub fn path(&self) -> Option<&str> {
+        self.page_manager.path()
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.page_manager.is_read_only()
+    }
+
+    /// Add an empty data page that belongs to no table
+    pub fn allocate_page(&mut self) -> Result<u32> {
+        self.page_manager.allocate_page()
+    }
+
+    pub fn read_page(&self, page_id: u32) -> Result<[u8; 4096]> {
         self.page_manager.read_page(page_id)
     }
 
-    pub fn write_page(&mut self, page_id: u32, data: &[u8]) -> io::Result<()> {
+    pub fn write_page(&mut self, page_id: u32, data: &[u8]) -> Result<()> {
         self.page_manager.write_page(page_id, data)
     }
 
-    pub fn read_page_metadata(&self, page_id: u32) -> io::Result<PageMetadata> {
+    pub fn write_at(&mut self, page_id: u32, offset: usize, data: &[u8]) -> Result<()> {
+        self.page_manager.write_at(page_id, offset, data)
+    }
+
+    pub fn read_page_metadata(&self, page_id: u32) -> Result<PageMetadata> {
         self.page_manager.read_page_metadata(page_id)
     }
 
-    pub fn update_page_metadata(
-        &mut self,
-        page_id: u32,
-        metadata: &PageMetadata,
-    ) -> io::Result<()> {
+    pub fn update_page_metadata(&mut self, page_id: u32, metadata: &PageMetadata) -> Result<()> {
         self.page_manager.update_page_metadata(page_id, metadata)
     }
 
+    pub fn page_type(&self, page_id: u32) -> Result<PageType> {
+        self.page_manager.page_type(page_id)
+    }
+
     pub fn number_of_pages(&self) -> u32 {
         self.page_manager.num_pages()
     }
+
+    pub fn sync_mode(&self) -> SyncMode {
+        self.page_manager.sync_mode()
+    }
+
+    pub fn set_sync_mode(&mut self, mode: SyncMode) -> Result<()> {
+        self.page_manager.set_sync_mode(mode)
+    }
+
+    pub fn commit(&mut self) -> Result<()> {
+        if let Some(log) = &mut self.log
+            && self.page_manager.sync_mode() != SyncMode::Off
+        {
+            log.sync()?;
+        }
+        self.page_manager.commit()
+    }
+
+    /// Open a savepoint; see `PageManager::savepoint`
+    pub fn savepoint(&mut self, name: &str) -> Result<()> {
+        if self.log.is_some() {
+            return Err(HozonError::Unsupported(
+                "The log-structured engine doesn't support savepoints".to_string(),
+            ));
+        }
+        self.page_manager.savepoint(name);
+        Ok(())
+    }
+
+    pub fn in_savepoint(&self) -> bool {
+        self.page_manager.in_savepoint()
+    }
+
+    pub fn release(&mut self, name: &str) -> Result<()> {
+        self.page_manager.release(name)
+    }
+
+    /// Undo every change since the savepoint `name`, tables and views
+    /// created since included
+    pub fn rollback_to(&mut self, name: &str) -> Result<()> {
+        self.page_manager.rollback_to(name)?;
+        (self.tables, self.views, self.indexes, self.statistics) =
+            read_catalog(&self.page_manager)?;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> IoStats {
+        self.page_manager.stats()
+    }
+
+    pub fn reset_stats(&self) {
+        self.page_manager.reset_stats()
+    }
+
+    pub fn checkpoint(&mut self) -> Result<()> {
+        if let Some(log) = &mut self.log {
+            log.sync()?;
+        }
+        self.page_manager.checkpoint()
+    }
+
+    pub fn checkpoint_interval(&self) -> Option<Duration> {
+        self.page_manager.checkpoint_interval()
+    }
+
+    pub fn set_checkpoint_interval(&mut self, interval: Option<Duration>) -> Result<()> {
+        self.page_manager.set_checkpoint_interval(interval)
+    }
+
+    pub fn backup_to(&self, path: &str) -> Result<u32> {
+        let pages = self.page_manager.backup_to(path)?;
+        self.backup_log(path)?;
+        Ok(pages)
+    }
+
+    /// Only the pages are incremental; log segments are always copied whole
+    pub fn backup_incremental(&self, path: &str) -> Result<BackupStats> {
+        let stats = self.page_manager.backup_incremental(path)?;
+        self.backup_log(path)?;
+        Ok(stats)
+    }
+
+    fn backup_log(&self, path: &str) -> Result<()> {
+        match &self.log {
+            Some(log) => log.backup_to(log::log_dir(path)),
+            None => Ok(()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -216,7 +1249,8 @@ mod tests {
                 Column::new("id", DataType::Integer),
                 Column::new("name", DataType::Text),
             ],
-        );
+        )
+        .unwrap();
 
         catalog.create_table(schema).unwrap();
 
@@ -234,7 +1268,8 @@ mod tests {
         let mut catalog = TableCatalog::new(pm).unwrap();
 
         // Create first table
-        let users_schema = Schema::new("users", vec![Column::new("id", DataType::Integer)]);
+        let users_schema =
+            Schema::new("users", vec![Column::new("id", DataType::Integer)]).unwrap();
         catalog.create_table(users_schema).unwrap();
 
         // Create second table
@@ -244,7 +1279,8 @@ mod tests {
                 Column::new("id", DataType::Integer),
                 Column::new("total", DataType::Integer),
             ],
-        );
+        )
+        .unwrap();
         catalog.create_table(orders_schema).unwrap();
 
         assert_eq!(catalog.tables.len(), 2);
@@ -269,7 +1305,8 @@ mod tests {
                     Column::new("id", DataType::Integer),
                     Column::new("name", DataType::Text),
                 ],
-            );
+            )
+            .unwrap();
 
             catalog.create_table(schema).unwrap();
             assert_eq!(catalog.tables.len(), 1);
@@ -301,30 +1338,35 @@ mod tests {
             let mut catalog = TableCatalog::new(pm).unwrap();
 
             catalog
-                .create_table(Schema::new(
-                    "users",
-                    vec![Column::new("id", DataType::Integer)],
-                ))
+                .create_table(
+                    Schema::new("users", vec![Column::new("id", DataType::Integer)]).unwrap(),
+                )
                 .unwrap();
 
             catalog
-                .create_table(Schema::new(
-                    "orders",
-                    vec![
-                        Column::new("id", DataType::Integer),
-                        Column::new("user_id", DataType::Integer),
-                    ],
-                ))
+                .create_table(
+                    Schema::new(
+                        "orders",
+                        vec![
+                            Column::new("id", DataType::Integer),
+                            Column::new("user_id", DataType::Integer),
+                        ],
+                    )
+                    .unwrap(),
+                )
                 .unwrap();
 
             catalog
-                .create_table(Schema::new(
-                    "products",
-                    vec![
-                        Column::new("name", DataType::Text),
-                        Column::new("price", DataType::Integer),
-                    ],
-                ))
+                .create_table(
+                    Schema::new(
+                        "products",
+                        vec![
+                            Column::new("name", DataType::Text),
+                            Column::new("price", DataType::Integer),
+                        ],
+                    )
+                    .unwrap(),
+                )
                 .unwrap();
         }
 
@@ -363,10 +1405,7 @@ mod tests {
 
         // Create first table
         catalog
-            .create_table(Schema::new(
-                "users",
-                vec![Column::new("id", DataType::Integer)],
-            ))
+            .create_table(Schema::new("users", vec![Column::new("id", DataType::Integer)]).unwrap())
             .unwrap();
 
         let users_page = catalog.get_table("users").unwrap().first_page;
@@ -374,10 +1413,9 @@ mod tests {
 
         // Create second table
         catalog
-            .create_table(Schema::new(
-                "orders",
-                vec![Column::new("id", DataType::Integer)],
-            ))
+            .create_table(
+                Schema::new("orders", vec![Column::new("id", DataType::Integer)]).unwrap(),
+            )
             .unwrap();
 
         let orders_page = catalog.get_table("orders").unwrap().first_page;
@@ -399,9 +1437,10 @@ mod tests {
                 Column::new("int_col", DataType::Integer),
                 Column::new("text_col", DataType::Text),
                 Column::new("bool_col", DataType::Boolean),
-                Column::new("null_col", DataType::Null),
+                Column::new("null_col", DataType::Text),
             ],
-        );
+        )
+        .unwrap();
 
         catalog.create_table(schema).unwrap();
 
@@ -423,7 +1462,7 @@ mod tests {
         let pm = PageManager::new("test_empty_name.hdb").unwrap();
         let mut catalog = TableCatalog::new(pm).unwrap();
 
-        let schema = Schema::new("", vec![Column::new("id", DataType::Integer)]);
+        let schema = Schema::new("", vec![Column::new("id", DataType::Integer)]).unwrap();
 
         // Should still work (validation not implemented yet)
         catalog.create_table(schema).unwrap();
@@ -440,7 +1479,7 @@ mod tests {
         let mut catalog = TableCatalog::new(pm).unwrap();
 
         let long_name = "a".repeat(1000);
-        let schema = Schema::new(&long_name, vec![Column::new("id", DataType::Integer)]);
+        let schema = Schema::new(&long_name, vec![Column::new("id", DataType::Integer)]).unwrap();
 
         catalog.create_table(schema).unwrap();
 
@@ -461,7 +1500,7 @@ mod tests {
         let pm = PageManager::new("test_get.hdb").unwrap();
         let mut catalog = TableCatalog::new(pm).unwrap();
 
-        let schema = Schema::new("users", vec![Column::new("id", DataType::Integer)]);
+        let schema = Schema::new("users", vec![Column::new("id", DataType::Integer)]).unwrap();
         catalog.create_table(schema).unwrap();
 
         let result = catalog.get_table("users");
@@ -490,21 +1529,215 @@ mod tests {
         let pm = PageManager::new("test_list.hdb").unwrap();
         let mut catalog = TableCatalog::new(pm).unwrap();
 
-        catalog.create_table(Schema::new("users", vec![])).unwrap();
-        catalog.create_table(Schema::new("orders", vec![])).unwrap();
         catalog
-            .create_table(Schema::new("products", vec![]))
+            .create_table(Schema::new("users", vec![]).unwrap())
+            .unwrap();
+        catalog
+            .create_table(Schema::new("orders", vec![]).unwrap())
+            .unwrap();
+        catalog
+            .create_table(Schema::new("products", vec![]).unwrap())
             .unwrap();
 
         let tables = catalog.list_tables();
-        assert_eq!(tables.len(), 3);
-        assert!(tables.contains(&"users".to_string()));
-        assert!(tables.contains(&"orders".to_string()));
-        assert!(tables.contains(&"products".to_string()));
+        assert_eq!(tables, vec!["orders", "products", "users"]);
+        assert!(catalog.table_exists("users"));
+        assert!(!catalog.table_exists("nonexistent"));
 
         cleanup("test_list");
     }
 
+    #[test]
+    fn test_table_info() {
+        use crate::catalog::row::Value;
+
+        cleanup("test_table_info");
+
+        let pm = PageManager::new("test_table_info.hdb").unwrap();
+        let mut catalog = TableCatalog::new(pm).unwrap();
+        catalog
+            .create_table(Schema::new("users", vec![Column::new("id", DataType::Integer)]).unwrap())
+            .unwrap();
+        for id in 0..3 {
+            catalog
+                .insert_row("users", Row::new(vec![Value::Integer(id)]))
+                .unwrap();
+        }
+
+        let info = catalog.table_info("users").unwrap();
+        assert_eq!(info.schema.columns().len(), 1);
+        assert_eq!(
+            info.first_page,
+            catalog.get_table("users").unwrap().first_page()
+        );
+        assert_eq!(info.row_count, 3);
+
+        let err = catalog.table_info("nonexistent").unwrap_err();
+        assert!(matches!(err, HozonError::TableNotFound(_)), "{:?}", err);
+        drop(catalog);
+
+        // The count is kept in the catalog rather than recounted
+        let catalog = TableCatalog::new(PageManager::new("test_table_info.hdb").unwrap()).unwrap();
+        let users = catalog.get_table("users").unwrap();
+        assert_eq!(users.row_count(), 3);
+        assert_eq!(users.last_page(), users.first_page());
+
+        cleanup("test_table_info");
+    }
+
+    #[test]
+    fn test_rowids() {
+        use crate::catalog::row::Value;
+
+        let mut catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        catalog
+            .create_table(Schema::new("users", vec![Column::new("id", DataType::Integer)]).unwrap())
+            .unwrap();
+        let first_page = catalog.get_table("users").unwrap().first_page();
+        let rowids: Vec<i64> = (0..3)
+            .map(|id| {
+                catalog
+                    .insert_row("users", Row::new(vec![Value::Integer(id)]))
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            rowids,
+            (0..3)
+                .map(|slot| rowid(first_page, slot))
+                .collect::<Vec<_>>()
+        );
+
+        let mut scanned = Vec::new();
+        catalog
+            .scan_views("users", |id, _| {
+                scanned.push(id);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(scanned, rowids);
+
+        assert_eq!(
+            catalog.get_row("users", rowids[1]).unwrap(),
+            Some(Row::new(vec![Value::Integer(1)]))
+        );
+        assert_eq!(
+            catalog.get_row("users", rowid(first_page, 3)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_replace_and_delete_rows() {
+        use crate::catalog::row::Value;
+
+        let mut catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let columns = vec![
+            Column::new("id", DataType::Integer),
+            Column::new("name", DataType::Text),
+        ];
+        catalog
+            .create_table(Schema::new("users", columns).unwrap())
+            .unwrap();
+        let row =
+            |id: i32, name: &str| Row::new(vec![Value::Integer(id), Value::Text(name.into())]);
+        let rowids: Vec<i64> = ["a", "b", "c"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| catalog.insert_row("users", row(i as i32, name)).unwrap())
+            .collect();
+
+        // Longer, then shorter than before; the rows after it move along
+        catalog
+            .replace_row("users", rowids[1], row(1, "a much longer name"))
+            .unwrap();
+        assert_eq!(
+            catalog.scan_rows("users").unwrap(),
+            vec![row(0, "a"), row(1, "a much longer name"), row(2, "c")]
+        );
+        catalog.replace_row("users", rowids[0], row(7, "")).unwrap();
+        assert_eq!(
+            catalog.get_row("users", rowids[2]).unwrap(),
+            Some(row(2, "c"))
+        );
+        assert_eq!(
+            catalog.get_row("users", rowids[0]).unwrap(),
+            Some(row(7, ""))
+        );
+        assert_eq!(catalog.table_info("users").unwrap().row_count, 3);
+
+        let missing = rowids[2] + 1;
+        let err = catalog
+            .replace_row("users", missing, row(3, "d"))
+            .unwrap_err();
+        assert!(matches!(err, HozonError::NotFound(_)), "{:?}", err);
+
+        // Deleting leaves the other rows and their rowids as they were
+        catalog.delete_rows("users", &rowids[1..2]).unwrap();
+        let mut scanned = Vec::new();
+        catalog
+            .scan_views("users", |id, view| {
+                scanned.push((id, view.to_row()));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            scanned,
+            vec![(rowids[0], row(7, "")), (rowids[2], row(2, "c"))]
+        );
+        assert_eq!(catalog.table_info("users").unwrap().row_count, 2);
+        assert!(
+            catalog
+                .replace_row("users", rowids[1], row(1, "b"))
+                .is_err()
+        );
+        assert!(catalog.delete_rows("users", &rowids[1..2]).is_err());
+        // New rows still take new slots
+        let next = catalog.insert_row("users", row(3, "d")).unwrap();
+        assert_eq!(next, rowids[2] + 1);
+        catalog
+            .replace_row("users", rowids[2], row(2, "cc"))
+            .unwrap();
+        assert_eq!(catalog.get_row("users", next).unwrap(), Some(row(3, "d")));
+    }
+
+    #[test]
+    fn test_corrupt_row_reported() {
+        use crate::catalog::row::Value;
+
+        cleanup("test_corrupt_row");
+
+        let pm = PageManager::new("test_corrupt_row.hdb").unwrap();
+        let mut catalog = TableCatalog::new(pm).unwrap();
+        let columns = vec![Column::new("id", DataType::Integer)];
+        catalog
+            .create_table(Schema::new("users", columns.clone()).unwrap())
+            .unwrap();
+        for id in 0..2 {
+            catalog
+                .insert_row("users", Row::new(vec![Value::Integer(id)]))
+                .unwrap();
+        }
+
+        // Overwrite the second row with one that parses but has the wrong type
+        let page = catalog.get_table("users").unwrap().first_page();
+        let second = PAGE_DATA_START + Row::new(vec![Value::Integer(0)]).encode(&columns).len();
+        let bad = Row::new(vec![Value::Text("x".to_string())]).to_bytes();
+        catalog.write_at(page, second, &bad).unwrap();
+
+        let err = catalog.scan_rows("users").unwrap_err();
+        assert!(matches!(err, HozonError::Corruption(_)), "{:?}", err);
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Corrupt row at index 1 on page {} of table 'users': TEXT value in INTEGER column 'id'",
+                page
+            )
+        );
+
+        cleanup("test_corrupt_row");
+    }
+
     #[test]
     fn test_drop_table() {
         cleanup("test_drop");
@@ -512,8 +1745,12 @@ mod tests {
         let pm = PageManager::new("test_drop.hdb").unwrap();
         let mut catalog = TableCatalog::new(pm).unwrap();
 
-        catalog.create_table(Schema::new("users", vec![])).unwrap();
-        catalog.create_table(Schema::new("orders", vec![])).unwrap();
+        catalog
+            .create_table(Schema::new("users", vec![]).unwrap())
+            .unwrap();
+        catalog
+            .create_table(Schema::new("orders", vec![]).unwrap())
+            .unwrap();
 
         assert_eq!(catalog.tables.len(), 2);
 
@@ -526,6 +1763,175 @@ mod tests {
         cleanup("test_drop");
     }
 
+    #[test]
+    fn test_indexes_persist() {
+        cleanup("test_index_catalog");
+
+        let pm = PageManager::new("test_index_catalog.hdb").unwrap();
+        let mut catalog = TableCatalog::new(pm).unwrap();
+        let columns = vec![
+            Column::new("id", DataType::Integer),
+            Column::new("name", DataType::Text),
+        ];
+        catalog
+            .create_table(Schema::new("users", columns).unwrap())
+            .unwrap();
+
+        let root_page = catalog
+            .create_index("by_name", "users", vec!["name".to_string()], false)
+            .unwrap()
+            .root_page();
+        assert_eq!(catalog.page_type(root_page).unwrap(), PageType::Index);
+
+        let by_name = vec!["name".to_string()];
+        let err = catalog.create_index("by_name", "users", by_name.clone(), false);
+        assert!(matches!(err, Err(HozonError::AlreadyExists(_))));
+        assert!(
+            catalog
+                .create_index("i", "missing", by_name, false)
+                .is_err()
+        );
+        assert!(
+            catalog
+                .create_index("i", "users", vec!["nope".to_string()], false)
+                .is_err()
+        );
+        drop(catalog);
+
+        let pm = PageManager::new("test_index_catalog.hdb").unwrap();
+        let mut catalog = TableCatalog::new(pm).unwrap();
+        let indexes = catalog.table_indexes("users");
+        assert_eq!(indexes.len(), 1);
+        assert_eq!(indexes[0].columns(), ["name"]);
+        assert_eq!(indexes[0].root_page(), root_page);
+
+        // Dropping the table takes its indexes with it
+        catalog.drop_table("users").unwrap();
+        assert!(catalog.get_index("by_name").is_none());
+
+        cleanup("test_index_catalog");
+    }
+
+    #[test]
+    fn test_stats_persist() {
+        use crate::catalog::row::Value;
+        cleanup("test_stats_catalog");
+
+        let pm = PageManager::new("test_stats_catalog.hdb").unwrap();
+        let mut catalog = TableCatalog::new(pm).unwrap();
+        let columns = vec![
+            Column::new("id", DataType::Integer),
+            Column::new("name", DataType::Text),
+        ];
+        catalog
+            .create_table(Schema::new("users", columns).unwrap())
+            .unwrap();
+        for (id, name) in [(1, "a"), (2, "b"), (3, "a")] {
+            let row = Row::new(vec![Value::Integer(id), Value::Text(name.to_string())]);
+            catalog.insert_row("users", row).unwrap();
+        }
+        assert!(catalog.table_stats("users").is_none());
+        assert!(matches!(
+            catalog.analyze("missing"),
+            Err(HozonError::TableNotFound(_))
+        ));
+        catalog.analyze("USERS").unwrap();
+        drop(catalog);
+
+        let pm = PageManager::new("test_stats_catalog.hdb").unwrap();
+        let mut catalog = TableCatalog::new(pm).unwrap();
+        let stats = catalog.table_stats("users").unwrap();
+        assert_eq!(stats.table_name(), "users");
+        assert_eq!(stats.rows(), 3);
+        assert_eq!(stats.columns()[0].max, Value::Integer(3));
+        assert_eq!(stats.columns()[1].distinct, 2);
+
+        // Dropping the table takes its statistics with it
+        catalog.drop_table("users").unwrap();
+        assert!(catalog.table_stats("users").is_none());
+
+        cleanup("test_stats_catalog");
+    }
+
+    #[test]
+    fn test_indexes_maintained() {
+        use crate::catalog::row::Value;
+
+        let mut catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let columns = vec![
+            Column::new("id", DataType::Integer),
+            Column::new("name", DataType::Text),
+        ];
+        catalog
+            .create_table(Schema::new("users", columns).unwrap())
+            .unwrap();
+        let row =
+            |id: i32, name: &str| Row::new(vec![Value::Integer(id), Value::Text(name.into())]);
+        let a = catalog.insert_row("users", row(1, "b")).unwrap();
+        let b = catalog.insert_row("users", row(2, "a")).unwrap();
+        let keys = |catalog: &TableCatalog| -> Vec<(Vec<Value>, i64)> {
+            let entries = catalog.index_entries("by_name").unwrap();
+            entries
+                .into_iter()
+                .map(|(key, rowid)| (key.0, rowid))
+                .collect()
+        };
+        let name = |name: &str| vec![Value::Text(name.into())];
+
+        // Filled from the rows already there
+        catalog
+            .create_index("by_name", "users", vec!["name".to_string()], true)
+            .unwrap();
+        assert_eq!(keys(&catalog), [(name("a"), b), (name("b"), a)]);
+
+        // A taken key is rejected before anything is written
+        let err = catalog.insert_row("users", row(3, "a")).unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+        assert_eq!(catalog.table_info("users").unwrap().row_count, 2);
+        let c = catalog.insert_row("users", row(3, "c")).unwrap();
+
+        // Swapping keys is fine when the rows change together
+        catalog
+            .replace_rows("users", &[(a, row(1, "a")), (b, row(2, "b"))])
+            .unwrap();
+        assert_eq!(
+            keys(&catalog),
+            [(name("a"), a), (name("b"), b), (name("c"), c)]
+        );
+        let err = catalog.replace_row("users", c, row(3, "a")).unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+
+        catalog.delete_rows("users", &[a]).unwrap();
+        assert_eq!(keys(&catalog), [(name("b"), b), (name("c"), c)]);
+        assert_eq!(catalog.index_lookup("by_name", &name("c")).unwrap(), [c]);
+        let from_b = catalog
+            .index_range("by_name", Bound::Excluded(&name("b")), Bound::Unbounded)
+            .unwrap();
+        assert_eq!(from_b.len(), 1);
+        catalog.insert_row("users", row(4, "a")).unwrap();
+
+        // Existing duplicates stop a UNIQUE index being made
+        catalog.drop_index("by_name").unwrap();
+        catalog.insert_row("users", row(5, "b")).unwrap();
+        let err = catalog
+            .create_index("by_name_again", "users", vec!["name".to_string()], true)
+            .unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+        assert!(catalog.get_index("by_name_again").is_none());
+    }
+
     #[test]
     fn test_drop_table_persists() {
         cleanup("test_drop_persist");
@@ -534,8 +1940,12 @@ mod tests {
             let pm = PageManager::new("test_drop_persist.hdb").unwrap();
             let mut catalog = TableCatalog::new(pm).unwrap();
 
-            catalog.create_table(Schema::new("users", vec![])).unwrap();
-            catalog.create_table(Schema::new("orders", vec![])).unwrap();
+            catalog
+                .create_table(Schema::new("users", vec![]).unwrap())
+                .unwrap();
+            catalog
+                .create_table(Schema::new("orders", vec![]).unwrap())
+                .unwrap();
             catalog.drop_table("users").unwrap();
         }
 
@@ -552,6 +1962,138 @@ mod tests {
         cleanup("test_drop_persist");
     }
 
+    #[test]
+    fn test_in_memory_catalog() {
+        let pm = PageManager::new_in_memory().unwrap();
+        let mut catalog = TableCatalog::new(pm).unwrap();
+
+        catalog
+            .create_table(Schema::new("users", vec![Column::new("id", DataType::Integer)]).unwrap())
+            .unwrap();
+
+        assert!(catalog.get_table("users").is_some());
+        assert_eq!(catalog.number_of_pages(), 3);
+    }
+
+    #[test]
+    fn test_old_format_upgraded_on_open() {
+        cleanup("test_old_format");
+
+        let pm = PageManager::new("test_old_format.hdb").unwrap();
+        let mut catalog = TableCatalog::new(pm).unwrap();
+        catalog
+            .create_table(Schema::new("users", vec![Column::new("id", DataType::Integer)]).unwrap())
+            .unwrap();
+        // Pretend the file predates the version field: untyped, rows from byte 5
+        let first_page = catalog.get_table("users").unwrap().first_page();
+        let mut v1_page = [0u8; 4096];
+        v1_page[1..3].copy_from_slice(&5u16.to_le_bytes());
+        catalog.write_page(first_page, &v1_page).unwrap();
+        catalog.page_manager.set_format_version(0).unwrap();
+        drop(catalog);
+
+        let pm = PageManager::new("test_old_format.hdb").unwrap();
+        let catalog = TableCatalog::new(pm).unwrap();
+        assert_eq!(
+            catalog.page_manager.format_version().unwrap(),
+            crate::storage::page::FORMAT_VERSION
+        );
+        let metadata = catalog.read_page_metadata(first_page).unwrap();
+        assert_eq!(metadata.last_offset, crate::storage::page::PAGE_DATA_START);
+        drop(catalog);
+
+        cleanup("test_old_format");
+    }
+
+    #[test]
+    fn test_log_structured_rows() {
+        use crate::catalog::row::Value;
+
+        cleanup("test_log_catalog");
+        let _ = fs::remove_dir_all("test_log_catalog.hdb-log");
+
+        let pm = PageManager::new_log_structured("test_log_catalog.hdb").unwrap();
+        let mut catalog = TableCatalog::new(pm).unwrap();
+        catalog
+            .create_table(
+                Schema::new("events", vec![Column::new("id", DataType::Integer)]).unwrap(),
+            )
+            .unwrap();
+        let mut rowids = Vec::new();
+        for i in 0..3 {
+            rowids.push(
+                catalog
+                    .insert_row("events", Row::new(vec![Value::Integer(i)]))
+                    .unwrap(),
+            );
+        }
+        catalog.commit().unwrap();
+        let first_page = catalog.get_table("events").unwrap().first_page();
+        drop(catalog);
+
+        // The engine comes from the header, whichever constructor reopens it
+        let mut catalog =
+            TableCatalog::new(PageManager::new("test_log_catalog.hdb").unwrap()).unwrap();
+        assert_eq!(catalog.engine(), StorageEngine::Log);
+        assert_eq!(catalog.scan_rows("events").unwrap().len(), 3);
+        assert_eq!(catalog.table_info("events").unwrap().row_count, 3);
+        assert_eq!(
+            catalog.get_row("events", rowids[2]).unwrap(),
+            Some(Row::new(vec![Value::Integer(2)]))
+        );
+        // Nothing went into the table's page
+        assert_eq!(catalog.read_page_metadata(first_page).unwrap().num_rows, 0);
+
+        catalog
+            .replace_row("events", rowids[0], Row::new(vec![Value::Integer(9)]))
+            .unwrap();
+        assert_eq!(
+            catalog.get_row("events", rowids[0]).unwrap(),
+            Some(Row::new(vec![Value::Integer(9)]))
+        );
+        catalog.delete_rows("events", &rowids[1..2]).unwrap();
+        let next = catalog
+            .insert_row("events", Row::new(vec![Value::Integer(3)]))
+            .unwrap();
+        assert_eq!(next, rowids[2] + 1);
+        assert_eq!(catalog.get_row("events", rowids[1]).unwrap(), None);
+        assert_eq!(catalog.table_info("events").unwrap().row_count, 3);
+
+        catalog.drop_table("events").unwrap();
+        catalog
+            .create_table(
+                Schema::new("events", vec![Column::new("id", DataType::Integer)]).unwrap(),
+            )
+            .unwrap();
+        assert!(catalog.scan_rows("events").unwrap().is_empty());
+        drop(catalog);
+
+        cleanup("test_log_catalog");
+        let _ = fs::remove_dir_all("test_log_catalog.hdb-log");
+    }
+
+    #[test]
+    fn test_log_structured_needs_a_file() {
+        assert!(PageManager::new_log_structured(":memory:").is_err());
+    }
+
+    #[test]
+    fn test_duplicate_table_name_rejected() {
+        let mut catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        catalog
+            .create_table(Schema::new("users", vec![]).unwrap())
+            .unwrap();
+        let pages = catalog.number_of_pages();
+
+        let err = catalog
+            .create_table(Schema::new("users", vec![]).unwrap())
+            .unwrap_err();
+        assert!(matches!(err, HozonError::AlreadyExists(_)), "{:?}", err);
+        assert!(err.to_string().contains("Table 'users' already exists"));
+        // No page was allocated for the rejected table
+        assert_eq!(catalog.number_of_pages(), pages);
+    }
+
     #[test]
     fn test_drop_nonexistent_table() {
         cleanup("test_drop_none");
@@ -0,0 +1,519 @@
+//! Opening a database and running SQL text on it from another program,
+//! without putting the pager, catalog, parser and executor together by hand.
+//!
+//! A `Database` owns the open file; statements run through the `Connection`s
+//! it hands out. Both can be shared between threads: the connections share
+//! one executor behind a lock, where a SELECT takes it shared, so reads run
+//! alongside each other, and anything else takes it alone. Savepoints
+//! belong to the database, so one a connection opens covers what the others
+//! write until it's released.
+
+use crate::catalog::from_value::FromValue;
+use crate::catalog::row::{Row, Value};
+use crate::catalog::table::TableCatalog;
+use crate::error::{HozonError, Result};
+use crate::sql::attach;
+use crate::sql::executor::{ExecutionResult, Executor};
+use crate::sql::parser::{Parser, Statement};
+use crate::sql::prepared::PreparedStatement;
+use crate::storage::backend::StorageBackend;
+use crate::storage::page::PageManager;
+use std::sync::{Arc, Mutex, RwLock, RwLockWriteGuard};
+
+#[derive(Clone)]
+pub struct Database {
+    inner: Arc<RwLock<Executor>>,
+}
+
+impl Database {
+    /// Open or create the database file at `path`, or an in-memory database
+    /// for ":memory:"
+    pub fn open(path: &str) -> Result<Self> {
+        Self::with_pager(PageManager::new(path)?)
+    }
+
+    /// Open the database even if its header names a running process as
+    /// owner; see `PageManager::new_with_force`
+    pub fn open_with_force(path: &str) -> Result<Self> {
+        Self::with_pager(PageManager::new_with_force(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::with_pager(PageManager::new_in_memory()?)
+    }
+
+    /// Open the database read-only; see `PageManager::new_read_only`
+    pub fn open_read_only(path: &str) -> Result<Self> {
+        Self::with_pager(PageManager::new_read_only(path)?)
+    }
+
+    /// Open the database stored in `backend`, or create one there if it's
+    /// empty; for storage other than a file, like a browser's
+    pub fn with_backend(backend: impl StorageBackend + 'static) -> Result<Self> {
+        Self::with_pager(PageManager::with_backend(backend)?)
+    }
+
+    fn with_pager(pm: PageManager) -> Result<Self> {
+        Ok(Database {
+            inner: Arc::new(RwLock::new(Executor::new(TableCatalog::new(pm)?))),
+        })
+    }
+
+    /// A new connection to run statements through
+    pub fn connect(&self) -> Connection {
+        Connection {
+            inner: self.inner.clone(),
+            last_insert_id: Mutex::new(None),
+        }
+    }
+
+    /// The executor underneath, for what the connection doesn't offer:
+    /// prepared statements, hooks, timeouts. No connection runs anything
+    /// while it's held.
+    pub fn executor(&self) -> RwLockWriteGuard<'_, Executor> {
+        self.inner.write().unwrap()
+    }
+}
+
+pub struct Connection {
+    // Shared with every other connection to the database
+    inner: Arc<RwLock<Executor>>,
+    // AUTOINCREMENT value this connection's most recent INSERT inserted, None
+    // if it inserted none; the executor only knows the last through any
+    last_insert_id: Mutex<Option<i64>>,
+}
+
+impl Connection {
+    /// Run the statements in `sql` in order, stopping at the first that
+    /// fails. Returns how many rows they inserted, updated or deleted.
+    pub fn execute(&self, sql: &str) -> Result<usize> {
+        let mut changed = 0;
+        for statement in parse(sql)? {
+            changed += self
+                .execute_statement(statement)?
+                .affected_rows()
+                .unwrap_or(0);
+        }
+        Ok(changed)
+    }
+
+    /// Run `sql`, one statement that returns rows: a SELECT, an EXPLAIN, a
+    /// PRAGMA that reads a setting or a write with RETURNING
+    pub fn query(&self, sql: &str) -> Result<Rows> {
+        let mut statements = parse(sql)?;
+        if statements.len() != 1 {
+            return Err(HozonError::InvalidInput(format!(
+                "A query is one statement, not {}",
+                statements.len()
+            )));
+        }
+        match self.execute_statement(statements.remove(0))? {
+            ExecutionResult::Rows { columns, rows } => Ok(Rows { columns, rows }),
+            _ => Err(HozonError::InvalidInput(
+                "The statement returns no rows; use execute".to_string(),
+            )),
+        }
+    }
+
+    /// Run the query `sql` and make a `T` of each row it returns
+    pub fn query_as<T: FromRow>(&self, sql: &str) -> Result<Vec<T>> {
+        self.query(sql)?
+            .iter()
+            .map(|row| T::from_row(&row))
+            .collect()
+    }
+
+    /// Parse `sql`, one statement with `?` or `$N` parameters, once to run
+    /// with different values
+    pub fn prepare(&self, sql: &str) -> Result<PreparedStatement> {
+        match sql.trim_end().ends_with(';') {
+            true => PreparedStatement::new(sql),
+            false => PreparedStatement::new(&format!("{}\n;", sql)),
+        }
+    }
+
+    /// Run `prepared` with `values` for its parameters
+    pub fn execute_prepared(
+        &self,
+        prepared: &PreparedStatement,
+        values: &[Value],
+    ) -> Result<ExecutionResult> {
+        self.execute_statement(prepared.bind(values)?)
+    }
+
+    /// The AUTOINCREMENT column value of this connection's most recent
+    /// INSERT, None if it inserted none; see `Executor::execute_with_insert_id`
+    pub fn last_insert_id(&self) -> Option<i64> {
+        *self.last_insert_id.lock().unwrap()
+    }
+
+    /// The executor, for the caller alone until the guard is dropped
+    pub(crate) fn executor(&self) -> RwLockWriteGuard<'_, Executor> {
+        self.inner.write().unwrap()
+    }
+
+    /// Run a parsed statement
+    pub fn execute_statement(&self, statement: Statement) -> Result<ExecutionResult> {
+        self.execute_inserting(statement).map(|(result, _)| result)
+    }
+
+    /// Run a parsed statement, with the AUTOINCREMENT value it inserted if
+    /// it's an INSERT that inserted one
+    pub(crate) fn execute_inserting(
+        &self,
+        statement: Statement,
+    ) -> Result<(ExecutionResult, Option<i64>)> {
+        if shared(&statement)? {
+            return Ok((self.inner.read().unwrap().query(statement)?, None));
+        }
+        self.execute_inserting_on(&mut self.inner.write().unwrap(), statement)
+    }
+
+    /// `execute_inserting` on `executor`, which the caller already holds
+    pub(crate) fn execute_inserting_on(
+        &self,
+        executor: &mut Executor,
+        statement: Statement,
+    ) -> Result<(ExecutionResult, Option<i64>)> {
+        let insert = matches!(statement, Statement::Insert { .. });
+        let (result, inserted_id) = executor.execute_with_insert_id(statement)?;
+        if insert {
+            *self.last_insert_id.lock().unwrap() = inserted_id;
+        }
+        Ok((result, inserted_id))
+    }
+}
+
+/// Whether `statement` can run under the shared lock, through
+/// `Executor::query`: a SELECT or EXPLAIN of the database's own tables
+fn shared(statement: &Statement) -> Result<bool> {
+    Ok(
+        matches!(statement, Statement::Select { .. } | Statement::Explain(_))
+            && attach::route(statement.clone())?.0.is_none(),
+    )
+}
+
+/// The statements in `sql`. The last needn't end with ';', even after a
+/// trailing comment.
+pub(crate) fn parse(sql: &str) -> Result<Vec<Statement>> {
+    Parser::from_sql(&format!("{}\n;", sql))?.parse_all()
+}
+
+/// The rows a query returned, and the names of their columns
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rows {
+    columns: Vec<String>,
+    rows: Vec<Row>,
+}
+
+impl Rows {
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The row at `index`
+    pub fn get(&self, index: usize) -> Option<ResultRow<'_>> {
+        self.rows.get(index).map(|row| self.row(row))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ResultRow<'_>> {
+        self.rows.iter().map(|row| self.row(row))
+    }
+
+    fn row<'a>(&'a self, row: &'a Row) -> ResultRow<'a> {
+        ResultRow {
+            columns: &self.columns,
+            row,
+        }
+    }
+}
+
+impl IntoIterator for Rows {
+    type Item = Row;
+    type IntoIter = std::vec::IntoIter<Row>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Rows {
+    type Item = ResultRow<'a>;
+    type IntoIter = Box<dyn Iterator<Item = ResultRow<'a>> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+/// A row of `Rows`, whose values can be read by column name
+#[derive(Debug, Clone, Copy)]
+pub struct ResultRow<'a> {
+    columns: &'a [String],
+    row: &'a Row,
+}
+
+impl<'a> ResultRow<'a> {
+    pub fn values(&self) -> &'a [Value] {
+        self.row.values()
+    }
+
+    /// The value at `index` as a `T`; see `FromValue`
+    pub fn get<T: FromValue>(&self, index: usize) -> Result<T> {
+        self.row.get(index)
+    }
+
+    /// The value of the column named `name` as a `T`. Names match as SQL
+    /// identifiers do, ignoring case; with two alike the first is read.
+    pub fn get_by_name<T: FromValue>(&self, name: &str) -> Result<T> {
+        let index = self
+            .columns
+            .iter()
+            .position(|column| column.eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                HozonError::ColumnNotFound(format!(
+                    "No column '{}' in the result; it has {}",
+                    name,
+                    self.columns.join(", ")
+                ))
+            })?;
+        T::from_value(&self.row.values()[index])
+            .map_err(|e| e.context(format!("Column '{}'", name)))
+    }
+}
+
+/// A type made from a query's row, as `Connection::query_as` makes them.
+/// A struct would read each field with `ResultRow::get_by_name`; tuples of
+/// up to six `FromValue` types read the columns in order.
+pub trait FromRow: Sized {
+    fn from_row(row: &ResultRow) -> Result<Self>;
+}
+
+macro_rules! tuple_from_row {
+    ($($t:ident $i:tt),+) => {
+        impl<$($t: FromValue),+> FromRow for ($($t,)+) {
+            fn from_row(row: &ResultRow) -> Result<Self> {
+                Ok(($(row.get::<$t>($i)?,)+))
+            }
+        }
+    };
+}
+
+tuple_from_row!(A 0);
+tuple_from_row!(A 0, B 1);
+tuple_from_row!(A 0, B 1, C 2);
+tuple_from_row!(A 0, B 1, C 2, D 3);
+tuple_from_row!(A 0, B 1, C 2, D 3, E 4);
+tuple_from_row!(A 0, B 1, C 2, D 3, E 4, F 5);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::backend::MemBackend;
+
+    #[test]
+    fn test_execute_and_query() {
+        let db = Database::open_in_memory().unwrap();
+        let conn = db.connect();
+        let changed = conn
+            .execute(
+                "CREATE TABLE users (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT);
+                 INSERT INTO users (name) VALUES ('alice'), ('bob');
+                 UPDATE users SET name = 'BOB' WHERE id = 2 -- no ';' needed",
+            )
+            .unwrap();
+        assert_eq!(changed, 3);
+        assert_eq!(conn.last_insert_id(), Some(2));
+
+        let rows = conn
+            .query("SELECT id, name FROM users ORDER BY id")
+            .unwrap();
+        assert_eq!(rows.columns(), ["id", "name"]);
+        assert_eq!(rows.len(), 2);
+        let names: Vec<&Value> = rows.iter().map(|row| &row.values()[1]).collect();
+        assert_eq!(
+            names,
+            [
+                &Value::Text("alice".to_string()),
+                &Value::Text("BOB".to_string())
+            ]
+        );
+        let rows = conn
+            .query("DELETE FROM users WHERE id = 1 RETURNING name;")
+            .unwrap();
+        assert_eq!(rows.into_iter().count(), 1);
+
+        let err = conn
+            .query("SELECT * FROM users; SELECT * FROM users;")
+            .unwrap_err();
+        assert!(matches!(err, HozonError::InvalidInput(_)), "{:?}", err);
+        let err = conn.query("DELETE FROM users;").unwrap_err();
+        assert!(matches!(err, HozonError::InvalidInput(_)), "{:?}", err);
+        let err = conn.execute("SELECT * FROM missing;").unwrap_err();
+        assert!(matches!(err, HozonError::TableNotFound(_)), "{:?}", err);
+
+        let insert = conn.prepare("INSERT INTO users (name) VALUES (?)").unwrap();
+        let result = conn
+            .execute_prepared(&insert, &[Value::Text("carol".to_string())])
+            .unwrap();
+        assert_eq!(result.affected_rows(), Some(1));
+        assert_eq!(conn.last_insert_id(), Some(3));
+    }
+
+    #[test]
+    fn test_typed_access() {
+        let db = Database::open_in_memory().unwrap();
+        let conn = db.connect();
+        conn.execute(
+            "CREATE TABLE users (id INTEGER, name TEXT, visits BIGINT, active BOOLEAN);
+             INSERT INTO users VALUES (1, 'alice', NULL, TRUE);",
+        )
+        .unwrap();
+        let rows = conn.query("SELECT * FROM users;").unwrap();
+        let row = rows.get(0).unwrap();
+        assert_eq!(row.get::<i32>(0).unwrap(), 1);
+        assert_eq!(row.get::<i64>(0).unwrap(), 1);
+        assert_eq!(row.get_by_name::<String>("NAME").unwrap(), "alice");
+        assert_eq!(row.get_by_name::<Option<i64>>("visits").unwrap(), None);
+        assert!(row.get_by_name::<bool>("active").unwrap());
+        assert!(rows.get(1).is_none());
+
+        let err = row.get_by_name::<i64>("visits").unwrap_err();
+        assert!(matches!(err, HozonError::TypeMismatch(_)), "{:?}", err);
+        assert_eq!(err.to_string(), "Column 'visits': Can't read NULL as i64");
+        let err = row.get::<bool>(1).unwrap_err();
+        assert_eq!(err.to_string(), "Column 1: Can't read 'alice' as bool");
+        let err = row.get::<i32>(4).unwrap_err();
+        assert!(matches!(err, HozonError::ColumnNotFound(_)), "{:?}", err);
+        let err = row.get_by_name::<i32>("email").unwrap_err();
+        assert!(matches!(err, HozonError::ColumnNotFound(_)), "{:?}", err);
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct User {
+        id: i64,
+        name: Option<String>,
+    }
+
+    impl FromRow for User {
+        fn from_row(row: &ResultRow) -> Result<Self> {
+            Ok(User {
+                id: row.get_by_name("id")?,
+                name: row.get_by_name("name")?,
+            })
+        }
+    }
+
+    #[test]
+    fn test_query_as() {
+        let db = Database::open_in_memory().unwrap();
+        let conn = db.connect();
+        conn.execute(
+            "CREATE TABLE users (id INTEGER, name TEXT);
+             INSERT INTO users VALUES (1, 'alice'), (2, NULL);",
+        )
+        .unwrap();
+        // By name, whatever order the columns come in
+        let users: Vec<User> = conn.query_as("SELECT name, id FROM users;").unwrap();
+        assert_eq!(
+            users,
+            [
+                User {
+                    id: 1,
+                    name: Some("alice".to_string())
+                },
+                User { id: 2, name: None }
+            ]
+        );
+        let pairs: Vec<(i32, Option<String>)> =
+            conn.query_as("SELECT id, name FROM users;").unwrap();
+        assert_eq!(pairs, [(1, Some("alice".to_string())), (2, None)]);
+
+        let err = conn.query_as::<User>("SELECT id FROM users;").unwrap_err();
+        assert!(matches!(err, HozonError::ColumnNotFound(_)), "{:?}", err);
+        let err = conn
+            .query_as::<(i32, String)>("SELECT id, name FROM users;")
+            .unwrap_err();
+        assert!(matches!(err, HozonError::TypeMismatch(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_connections_across_threads() {
+        fn shareable<T: Send + Sync>() {}
+        shareable::<Database>();
+        shareable::<Connection>();
+
+        let db = Database::open_in_memory().unwrap();
+        db.connect()
+            .execute("CREATE TABLE t (id INTEGER PRIMARY KEY AUTOINCREMENT, thread INTEGER);")
+            .unwrap();
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                let conn = db.connect();
+                std::thread::spawn(move || {
+                    let mut ids = Vec::new();
+                    for _ in 0..25 {
+                        conn.execute(&format!("INSERT INTO t (thread) VALUES ({});", thread))
+                            .unwrap();
+                        ids.push(conn.last_insert_id().unwrap());
+                        conn.query("SELECT COUNT(*) FROM t;").unwrap();
+                    }
+                    ids
+                })
+            })
+            .collect();
+        let mut ids: Vec<i64> = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+        // Each connection saw the ids of its own inserts
+        ids.sort();
+        assert_eq!(ids, (1..=100).collect::<Vec<_>>());
+
+        let conn = db.connect();
+        let (count,): (i64,) = conn.query_as("SELECT COUNT(*) FROM t;").unwrap().remove(0);
+        assert_eq!(count, 100);
+        assert_eq!(conn.last_insert_id(), None);
+        assert!(db.executor().last_insert_id().is_some());
+
+        // An insert that generates no id reports none, not another connection's
+        conn.execute("CREATE TABLE plain (x INTEGER);").unwrap();
+        let other = db.connect();
+        other.execute("INSERT INTO t (thread) VALUES (9);").unwrap();
+        conn.execute("INSERT INTO plain VALUES (1);").unwrap();
+        assert_eq!(conn.last_insert_id(), None);
+        assert_eq!(other.last_insert_id(), Some(101));
+    }
+
+    #[test]
+    fn test_with_backend() {
+        let path = "test_database_image.hdb";
+        let _ = std::fs::remove_file(path);
+        let db = Database::open(path).unwrap();
+        db.connect()
+            .execute("CREATE TABLE t (id INTEGER); INSERT INTO t VALUES (7);")
+            .unwrap();
+        drop(db);
+        let image = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        // The file's pages, with no file underneath
+        let db = Database::with_backend(MemBackend::from_bytes(&image).unwrap()).unwrap();
+        let conn = db.connect();
+        let ids: Vec<(i32,)> = conn.query_as("SELECT id FROM t;").unwrap();
+        assert_eq!(ids, [(7,)]);
+        conn.execute("INSERT INTO t VALUES (8);").unwrap();
+        assert_eq!(conn.query("SELECT * FROM t;").unwrap().len(), 2);
+
+        let err = MemBackend::from_bytes(&image[1..]).unwrap_err();
+        assert!(matches!(err, HozonError::InvalidInput(_)), "{:?}", err);
+    }
+}
@@ -0,0 +1,187 @@
+//! The error type every layer returns.
+//!
+//! Each variant says what went wrong in terms a caller can act on: a parse
+//! error points at the SQL, a constraint violation at the data, corruption at
+//! the file. `kind` maps them back onto `io::ErrorKind` for code that only
+//! cares about the broad category, and a `HozonError` converts into an
+//! `io::Error` wherever an `io` trait needs one.
+
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong in HozonDB
+#[derive(Debug)]
+pub enum HozonError {
+    /// The SQL didn't tokenize or parse
+    Parse(String),
+    /// A value had the wrong type for where it was used
+    TypeMismatch(String),
+    /// A table or view that doesn't exist
+    TableNotFound(String),
+    /// A column that doesn't exist, or is ambiguous
+    ColumnNotFound(String),
+    /// Some other object or row that doesn't exist
+    NotFound(String),
+    /// A table, view or file that already exists
+    AlreadyExists(String),
+    /// A NOT NULL, PRIMARY KEY or UNIQUE constraint would be broken
+    ConstraintViolation(String),
+    /// A statement that parsed but can't run as written
+    InvalidStatement(String),
+    /// A bad argument from the caller: a pragma value, parameter count, key
+    InvalidInput(String),
+    /// Another process holds the database
+    Locked(String),
+    /// A write to a database opened read-only
+    ReadOnly(String),
+    /// The database is encrypted and the key is missing or wrong
+    Encryption(String),
+    /// A page or the database has no room left
+    Full(String),
+    /// Something this build or version doesn't support
+    Unsupported(String),
+    /// A statement was cancelled or ran past its timeout
+    Interrupted(String),
+    /// Bytes on disk that don't decode
+    Corruption(String),
+    /// The filesystem failed
+    Io(io::Error),
+}
+
+/// A `Result` whose error is `HozonError`
+pub type Result<T> = std::result::Result<T, HozonError>;
+
+impl HozonError {
+    /// The closest `io::ErrorKind`, for callers that only need the category
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            HozonError::Parse(_)
+            | HozonError::TypeMismatch(_)
+            | HozonError::ColumnNotFound(_)
+            | HozonError::ConstraintViolation(_)
+            | HozonError::InvalidStatement(_)
+            | HozonError::Corruption(_) => io::ErrorKind::InvalidData,
+            HozonError::TableNotFound(_) | HozonError::NotFound(_) => io::ErrorKind::NotFound,
+            HozonError::AlreadyExists(_) => io::ErrorKind::AlreadyExists,
+            HozonError::InvalidInput(_) => io::ErrorKind::InvalidInput,
+            HozonError::Locked(_) => io::ErrorKind::WouldBlock,
+            HozonError::ReadOnly(_) | HozonError::Encryption(_) => io::ErrorKind::PermissionDenied,
+            HozonError::Full(_) => io::ErrorKind::OutOfMemory,
+            HozonError::Unsupported(_) => io::ErrorKind::Unsupported,
+            HozonError::Interrupted(_) => io::ErrorKind::Interrupted,
+            HozonError::Io(e) => e.kind(),
+        }
+    }
+
+    /// The same error with `context` in front of its message
+    pub fn context(self, context: impl fmt::Display) -> Self {
+        let prefix = |msg: String| format!("{}: {}", context, msg);
+        match self {
+            HozonError::Parse(msg) => HozonError::Parse(prefix(msg)),
+            HozonError::TypeMismatch(msg) => HozonError::TypeMismatch(prefix(msg)),
+            HozonError::TableNotFound(msg) => HozonError::TableNotFound(prefix(msg)),
+            HozonError::ColumnNotFound(msg) => HozonError::ColumnNotFound(prefix(msg)),
+            HozonError::NotFound(msg) => HozonError::NotFound(prefix(msg)),
+            HozonError::AlreadyExists(msg) => HozonError::AlreadyExists(prefix(msg)),
+            HozonError::ConstraintViolation(msg) => HozonError::ConstraintViolation(prefix(msg)),
+            HozonError::InvalidStatement(msg) => HozonError::InvalidStatement(prefix(msg)),
+            HozonError::InvalidInput(msg) => HozonError::InvalidInput(prefix(msg)),
+            HozonError::Locked(msg) => HozonError::Locked(prefix(msg)),
+            HozonError::ReadOnly(msg) => HozonError::ReadOnly(prefix(msg)),
+            HozonError::Encryption(msg) => HozonError::Encryption(prefix(msg)),
+            HozonError::Full(msg) => HozonError::Full(prefix(msg)),
+            HozonError::Unsupported(msg) => HozonError::Unsupported(prefix(msg)),
+            HozonError::Interrupted(msg) => HozonError::Interrupted(prefix(msg)),
+            HozonError::Corruption(msg) => HozonError::Corruption(prefix(msg)),
+            HozonError::Io(e) => HozonError::Io(io::Error::new(e.kind(), prefix(e.to_string()))),
+        }
+    }
+}
+
+impl fmt::Display for HozonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HozonError::Parse(msg)
+            | HozonError::TypeMismatch(msg)
+            | HozonError::TableNotFound(msg)
+            | HozonError::ColumnNotFound(msg)
+            | HozonError::NotFound(msg)
+            | HozonError::AlreadyExists(msg)
+            | HozonError::ConstraintViolation(msg)
+            | HozonError::InvalidStatement(msg)
+            | HozonError::InvalidInput(msg)
+            | HozonError::Locked(msg)
+            | HozonError::ReadOnly(msg)
+            | HozonError::Encryption(msg)
+            | HozonError::Full(msg)
+            | HozonError::Unsupported(msg)
+            | HozonError::Interrupted(msg)
+            | HozonError::Corruption(msg) => write!(f, "{}", msg),
+            HozonError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for HozonError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HozonError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HozonError {
+    fn from(e: io::Error) -> Self {
+        // An io::Error that wraps a HozonError, from an io trait, unwraps back
+        if e.get_ref().is_some_and(|inner| inner.is::<HozonError>()) {
+            let inner = e.into_inner().expect("checked above");
+            return *inner.downcast::<HozonError>().expect("checked above");
+        }
+        HozonError::Io(e)
+    }
+}
+
+impl From<HozonError> for io::Error {
+    fn from(e: HozonError) -> Self {
+        match e {
+            HozonError::Io(e) => e,
+            other => io::Error::new(other.kind(), other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_and_message() {
+        let e = HozonError::TableNotFound("Table 't' does not exist".to_string());
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        assert_eq!(e.to_string(), "Table 't' does not exist");
+
+        let e = HozonError::from(io::Error::new(io::ErrorKind::UnexpectedEof, "short read"));
+        assert!(matches!(e, HozonError::Io(_)));
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_round_trip_through_io_error() {
+        let e = HozonError::Corruption("Invalid page checksum".to_string());
+        let io_error = io::Error::from(e);
+        assert_eq!(io_error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(io_error.to_string(), "Invalid page checksum");
+        match HozonError::from(io_error) {
+            HozonError::Corruption(msg) => assert_eq!(msg, "Invalid page checksum"),
+            other => panic!("Expected Corruption, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_context() {
+        let e = HozonError::Corruption("bad byte".to_string()).context("Page 3");
+        assert_eq!(e.to_string(), "Page 3: bad byte");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,249 @@
+//! Loading a CSV file into a table. The first record names the columns the
+//! rest fill; each field goes in as text and is converted to its column's
+//! type as any inserted value is, and an empty field is NULL. A record that
+//! doesn't fit is rejected and the rest still load. A table that doesn't
+//! exist is created with a TEXT column per name.
+
+use crate::catalog::row::Value;
+use crate::catalog::schema::{Column, DataType};
+use crate::error::{HozonError, Result};
+use crate::sql::executor::Executor;
+use crate::sql::parser::{Expr, Statement};
+use std::fs;
+
+/// Savepoint the rows load under, so they're written and synced together
+const SAVEPOINT: &str = "hozon_import";
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// Whether the table was created for the file
+    pub created: bool,
+    pub rows: usize,
+    /// The line each rejected record started on, and why it was
+    pub rejected: Vec<(usize, HozonError)>,
+}
+
+/// Load the CSV file at `path` into `table_name`
+pub fn import_csv(executor: &mut Executor, path: &str, table_name: &str) -> Result<ImportReport> {
+    let text = fs::read_to_string(path)?;
+    let mut records = parse_csv(&text)?.into_iter();
+    let Some((_, header)) = records.next() else {
+        return Err(HozonError::InvalidInput(format!(
+            "'{}' has no header row",
+            path
+        )));
+    };
+    let columns: Vec<String> = header
+        .into_iter()
+        .map(|name| name.unwrap_or_default())
+        .collect();
+
+    let mut report = ImportReport::default();
+    match executor.catalog().get_table(table_name) {
+        Some(meta) => {
+            let case = executor.catalog().identifier_case();
+            if let Some(name) = columns
+                .iter()
+                .find(|name| meta.schema().column_index(name, case).is_none())
+            {
+                return Err(HozonError::ColumnNotFound(format!(
+                    "Column '{}' does not exist in table '{}'",
+                    name, table_name
+                )));
+            }
+        }
+        None => {
+            executor.execute(Statement::CreateTable {
+                name: table_name.to_string(),
+                columns: columns
+                    .iter()
+                    .map(|name| Column::new(name, DataType::Text))
+                    .collect(),
+            })?;
+            report.created = true;
+        }
+    }
+
+    // The log-structured engine has no savepoints; there each row commits
+    // as it goes in
+    let batched = executor
+        .execute(Statement::Savepoint {
+            name: SAVEPOINT.to_string(),
+        })
+        .is_ok();
+    for (line, fields) in records {
+        let insert = Statement::Insert {
+            table_name: table_name.to_string(),
+            columns: Some(columns.clone()),
+            values: vec![
+                fields
+                    .into_iter()
+                    .map(|field| Expr::Literal(field.map_or(Value::Null, Value::Text)))
+                    .collect(),
+            ],
+            on_conflict: None,
+            returning: None,
+        };
+        match executor.execute(insert) {
+            Ok(_) => report.rows += 1,
+            Err(err) => report.rejected.push((line, err)),
+        }
+    }
+    if batched {
+        executor.execute(Statement::Release {
+            name: SAVEPOINT.to_string(),
+        })?;
+    }
+    Ok(report)
+}
+
+/// The records of CSV `text`, each with the line it starts on. A field is
+/// None if it was empty and unquoted, so `""` can still be an empty text.
+pub fn parse_csv(text: &str) -> Result<Vec<(usize, Vec<Option<String>>)>> {
+    let mut records = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+
+    while chars.peek().is_some() {
+        let start = line;
+        let mut fields = Vec::new();
+        loop {
+            let mut field = String::new();
+            let mut quoted = false;
+            if chars.peek() == Some(&'"') {
+                chars.next();
+                quoted = true;
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            field.push(c);
+                        }
+                        None => {
+                            return Err(HozonError::Parse(format!(
+                                "Line {}: unterminated quoted field",
+                                line
+                            )));
+                        }
+                    }
+                }
+            }
+            while let Some(&c) = chars.peek() {
+                if c == ',' || c == '\n' || c == '\r' {
+                    break;
+                }
+                field.push(c);
+                chars.next();
+            }
+            fields.push(match quoted || !field.is_empty() {
+                true => Some(field),
+                false => None,
+            });
+
+            match chars.next() {
+                Some(',') => continue,
+                Some('\r') if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                }
+                _ => {}
+            }
+            line += 1;
+            break;
+        }
+
+        // A blank line has no record
+        if fields != [None] {
+            records.push((start, fields));
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::table::TableCatalog;
+    use crate::sql::parser::Parser;
+    use crate::storage::page::PageManager;
+
+    fn text(field: &str) -> Option<String> {
+        Some(field.to_string())
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let csv = "id,name\r\n1,\"Smith, \"\"J\"\"\"\n\n2,\"two\nlines\"\n3,\n4,\"\"";
+        assert_eq!(
+            parse_csv(csv).unwrap(),
+            [
+                (1, vec![text("id"), text("name")]),
+                (2, vec![text("1"), text("Smith, \"J\"")]),
+                (4, vec![text("2"), text("two\nlines")]),
+                (6, vec![text("3"), None]),
+                (7, vec![text("4"), text("")]),
+            ]
+        );
+
+        let err = parse_csv("a\n\"open").unwrap_err();
+        assert!(matches!(err, HozonError::Parse(_)), "{:?}", err);
+        assert_eq!(err.to_string(), "Line 2: unterminated quoted field");
+    }
+
+    #[test]
+    fn test_import_csv() {
+        let path = "test_import.csv";
+        fs::write(
+            path,
+            "name,id\nAlice,1\nBob,two\n\"Carol, Jr\",3\nDan\nEve,1\n,6\n",
+        )
+        .unwrap();
+
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        let sql = "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);";
+        executor
+            .execute(Parser::from_sql(sql).unwrap().parse().unwrap())
+            .unwrap();
+
+        let report = import_csv(&mut executor, path, "users").unwrap();
+        assert!(!report.created);
+        assert_eq!(report.rows, 3);
+        let lines: Vec<usize> = report.rejected.iter().map(|(line, _)| *line).collect();
+        // Not a number, too few fields, a taken key
+        assert_eq!(lines, [3, 5, 6]);
+        assert!(
+            matches!(report.rejected[0].1, HozonError::TypeMismatch(_)),
+            "{:?}",
+            report.rejected[0].1
+        );
+        assert!(!executor.catalog().in_savepoint());
+
+        let rows = executor.catalog().scan_rows("users").unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(
+            rows[1].values(),
+            &[Value::Integer(3), Value::Text("Carol, Jr".to_string())]
+        );
+        assert_eq!(rows[2].values(), &[Value::Integer(6), Value::Null]);
+
+        // A new table takes its columns from the header
+        let report = import_csv(&mut executor, path, "people").unwrap();
+        assert!(report.created);
+        assert_eq!(report.rows, 5);
+        let people = executor.catalog().get_table("people").unwrap();
+        assert_eq!(people.schema().columns()[1].name(), "id");
+
+        fs::write(path, "name,email\nAlice,a@b\n").unwrap();
+        let err = import_csv(&mut executor, path, "users").unwrap_err();
+        assert!(matches!(err, HozonError::ColumnNotFound(_)), "{:?}", err);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,4 +1,18 @@
 pub mod catalog;
+pub mod database;
+pub mod error;
+pub mod import;
+pub mod output;
+#[cfg(feature = "python")]
+mod python;
+pub mod recover;
+#[cfg(not(target_family = "wasm"))]
 pub mod repl;
+pub mod server;
 pub mod sql;
 pub mod storage;
+
+pub use catalog::from_value::FromValue;
+pub use catalog::row::{Row, Value};
+pub use database::{Connection, Database, FromRow, ResultRow, Rows};
+pub use error::{HozonError, Result};
@@ -1,6 +1,132 @@
+#[cfg(not(target_family = "wasm"))]
+use hozondb::Database;
+#[cfg(not(target_family = "wasm"))]
 use hozondb::repl::Repl;
+#[cfg(not(target_family = "wasm"))]
+use hozondb::server::{self, Protocol, Server};
+#[cfg(not(target_family = "wasm"))]
+use std::io::{self, IsTerminal, Read};
+use std::process::ExitCode;
 
-fn main() {
-    let mut repl = Repl::new();
-    repl.run();
+#[cfg(not(target_family = "wasm"))]
+const USAGE: &str =
+    "Usage: hozondb [database] [sql]\n       hozondb serve <database> [--port N] [--postgres]";
+
+// There's no terminal to run the REPL in; on wasm HozonDB is a library
+#[cfg(target_family = "wasm")]
+fn main() -> ExitCode {
+    eprintln!("The hozondb shell isn't available on this platform");
+    ExitCode::FAILURE
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "serve") {
+        return serve(&args[1..]);
+    }
+    if args.iter().any(|arg| arg.starts_with('-')) {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(2);
+    }
+    let (database, sql) = match args.as_slice() {
+        [] => (None, None),
+        [database] => (Some(database.as_str()), None),
+        [database, sql @ ..] => (Some(database.as_str()), Some(sql.join(" "))),
+    };
+
+    // With nothing to run and someone at the keyboard, start the prompt
+    let stdin = io::stdin();
+    if sql.is_none() && stdin.is_terminal() {
+        let mut repl = Repl::new();
+        if let Err(e) = repl.load_config() {
+            eprintln!("Error: {}", e);
+        }
+        // A database named on the command line replaces one the config opened
+        if let Some(database) = database
+            && let Err(e) = repl.open(database)
+        {
+            eprintln!("Error: {}", e);
+            return ExitCode::FAILURE;
+        }
+        repl.run();
+        return ExitCode::SUCCESS;
+    }
+
+    let script = match sql {
+        Some(sql) => sql,
+        None => {
+            let mut script = String::new();
+            if let Err(e) = stdin.lock().read_to_string(&mut script) {
+                eprintln!("Error: {}", e);
+                return ExitCode::FAILURE;
+            }
+            script
+        }
+    };
+    let mut repl = Repl::batch();
+    match repl
+        .open(database.unwrap_or(":memory:"))
+        .and_then(|_| repl.run_script(&script))
+    {
+        Ok(0) => ExitCode::SUCCESS,
+        // Each was reported as it happened
+        Ok(_) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// `hozondb serve <database> [--port N] [--postgres]`: answer clients on
+/// this machine until stopped
+#[cfg(not(target_family = "wasm"))]
+fn serve(args: &[String]) -> ExitCode {
+    let mut database = None;
+    let mut port = None;
+    let mut protocol = Protocol::HozonDB;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => match args.next().map(|port| port.parse::<u16>()) {
+                Some(Ok(n)) => port = Some(n),
+                _ => {
+                    eprintln!("Error: --port takes a number up to 65535");
+                    return ExitCode::from(2);
+                }
+            },
+            "--postgres" => protocol = Protocol::Postgres,
+            arg if database.is_none() && !arg.starts_with('-') => database = Some(arg),
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::from(2);
+            }
+        }
+    }
+    let Some(database) = database else {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(2);
+    };
+    let port = port.unwrap_or(match protocol {
+        Protocol::HozonDB => server::DEFAULT_PORT,
+        Protocol::Postgres => server::pg::DEFAULT_PORT,
+    });
+
+    let server = match Database::open(database)
+        .and_then(|database| Server::bind(database, ("127.0.0.1", port), protocol))
+    {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Ok(addr) = server.local_addr() {
+        println!("Serving {} on {}", database, addr);
+    }
+    if let Err(e) = server.serve() {
+        eprintln!("Error: {}", e);
+    }
+    ExitCode::FAILURE
 }
@@ -0,0 +1,473 @@
+//! Rendering query results as text, for the REPL and for files written from
+//! it. An `OutputMode` picks the layout - an aligned table, CSV, JSON,
+//! Markdown or plain lines - and `ResultFormat` holds it with the settings
+//! the REPL's `.mode` and `.headers` change.
+
+use crate::catalog::format::ValueFormat;
+use crate::catalog::row::{Row, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputMode {
+    /// Columns lined up between ASCII borders
+    Table,
+    /// `Table` drawn with box-drawing characters
+    Box,
+    Csv,
+    /// An array with an object per row
+    Json,
+    /// A GitHub-flavored Markdown table
+    Markdown,
+    /// Each row's values separated by '|'
+    List,
+}
+
+impl OutputMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "table" => Some(OutputMode::Table),
+            "box" => Some(OutputMode::Box),
+            "csv" => Some(OutputMode::Csv),
+            "json" => Some(OutputMode::Json),
+            "markdown" => Some(OutputMode::Markdown),
+            "list" => Some(OutputMode::List),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputMode::Table => "table",
+            OutputMode::Box => "box",
+            OutputMode::Csv => "csv",
+            OutputMode::Json => "json",
+            OutputMode::Markdown => "markdown",
+            OutputMode::List => "list",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultFormat {
+    pub mode: OutputMode,
+    /// Whether a header line names the columns. JSON always names them and
+    /// Markdown always has one, as its tables need it.
+    pub headers: bool,
+    /// Widest a table column may be; longer values are cut short with an
+    /// ellipsis. None to show them whole.
+    pub max_width: Option<usize>,
+    /// Text shown for NULL in place of the mode's own, "NULL" or an empty
+    /// CSV field. JSON keeps its null.
+    pub null: Option<String>,
+}
+
+impl Default for ResultFormat {
+    fn default() -> Self {
+        ResultFormat {
+            mode: OutputMode::Table,
+            headers: true,
+            max_width: None,
+            null: None,
+        }
+    }
+}
+
+/// The characters a table is drawn with
+struct Borders {
+    /// Left, middle and right of the top, header and bottom rules
+    top: [char; 3],
+    middle: [char; 3],
+    bottom: [char; 3],
+    horizontal: char,
+    vertical: char,
+}
+
+const ASCII: Borders = Borders {
+    top: ['+', '+', '+'],
+    middle: ['+', '+', '+'],
+    bottom: ['+', '+', '+'],
+    horizontal: '-',
+    vertical: '|',
+};
+
+const BOX: Borders = Borders {
+    top: ['┌', '┬', '┐'],
+    middle: ['├', '┼', '┤'],
+    bottom: ['└', '┴', '┘'],
+    horizontal: '─',
+    vertical: '│',
+};
+
+impl ResultFormat {
+    /// `rows` under `columns` as text, each line ending in a newline
+    pub fn render(&self, columns: &[String], rows: &[Row]) -> String {
+        match self.mode {
+            OutputMode::Table => self.table(columns, rows, &ASCII),
+            OutputMode::Box => self.table(columns, rows, &BOX),
+            OutputMode::Csv => self.separated(columns, rows, ",", &self.values(ValueFormat::csv())),
+            OutputMode::Json => json(columns, rows),
+            OutputMode::Markdown => markdown(columns, rows, &self.values(ValueFormat::default())),
+            OutputMode::List => {
+                self.separated(columns, rows, "|", &self.values(ValueFormat::default()))
+            }
+        }
+    }
+
+    /// `format` with NULL shown as set
+    fn values(&self, format: ValueFormat) -> ValueFormat {
+        match &self.null {
+            Some(null) => ValueFormat {
+                null: null.clone(),
+                ..format
+            },
+            None => format,
+        }
+    }
+
+    fn table(&self, columns: &[String], rows: &[Row], borders: &Borders) -> String {
+        let fit = |text: &str| self.fit(text);
+        let format = self.values(ValueFormat::default());
+        let header: Vec<String> = columns.iter().map(|c| fit(c)).collect();
+        // Numbers are right-aligned, so their digits line up
+        let cells: Vec<Vec<(String, bool)>> = rows
+            .iter()
+            .map(|row| {
+                row.values()
+                    .iter()
+                    .map(|value| {
+                        let numeric = matches!(
+                            value,
+                            Value::Integer(_) | Value::BigInt(_) | Value::Float(_)
+                        );
+                        (fit(&value.display_with(&format).to_string()), numeric)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut widths: Vec<usize> = match self.headers {
+            true => header.iter().map(|c| width(c)).collect(),
+            false => vec![0; columns.len()],
+        };
+        for row in &cells {
+            for (width, (cell, _)) in widths.iter_mut().zip(row) {
+                *width = (*width).max(self::width(cell));
+            }
+        }
+
+        let rule = |[left, middle, right]: [char; 3]| {
+            let lines: Vec<String> = widths
+                .iter()
+                .map(|w| borders.horizontal.to_string().repeat(w + 2))
+                .collect();
+            format!("{}{}{}\n", left, lines.join(&middle.to_string()), right)
+        };
+        let line = |cells: Vec<(&str, bool)>| {
+            let cells: Vec<String> = cells
+                .iter()
+                .zip(&widths)
+                .map(|((cell, numeric), &width)| {
+                    let padding = " ".repeat(width - self::width(cell));
+                    match numeric {
+                        true => format!(" {}{} ", padding, cell),
+                        false => format!(" {}{} ", cell, padding),
+                    }
+                })
+                .collect();
+            let vertical = borders.vertical.to_string();
+            format!("{}{}{}\n", vertical, cells.join(&vertical), vertical)
+        };
+
+        let mut out = rule(borders.top);
+        if self.headers {
+            out.push_str(&line(header.iter().map(|c| (c.as_str(), false)).collect()));
+            if !cells.is_empty() {
+                out.push_str(&rule(borders.middle));
+            }
+        }
+        for row in &cells {
+            out.push_str(&line(row.iter().map(|(c, n)| (c.as_str(), *n)).collect()));
+        }
+        out.push_str(&rule(borders.bottom));
+        out
+    }
+
+    /// `text` on one line, cut short to the widest a column may be
+    fn fit(&self, text: &str) -> String {
+        // A line break would split the row
+        let text: String = text
+            .chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect();
+        match self.max_width {
+            Some(max) if width(&text) > max => {
+                let kept: String = text.chars().take(max.saturating_sub(1)).collect();
+                format!("{}…", kept)
+            }
+            _ => text,
+        }
+    }
+
+    fn separated(
+        &self,
+        columns: &[String],
+        rows: &[Row],
+        separator: &str,
+        format: &ValueFormat,
+    ) -> String {
+        let mut out = String::new();
+        if self.headers {
+            let names: Vec<String> = columns
+                .iter()
+                .map(|c| Value::Text(c.clone()).display_with(format).to_string())
+                .collect();
+            out.push_str(&names.join(separator));
+            out.push('\n');
+        }
+        for row in cells(rows, format, |text| text) {
+            out.push_str(&row.join(separator));
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Each row's values rendered in `format`, then passed through `escape`
+fn cells(
+    rows: &[Row],
+    format: &ValueFormat,
+    escape: impl Fn(String) -> String,
+) -> Vec<Vec<String>> {
+    rows.iter()
+        .map(|row| {
+            row.values()
+                .iter()
+                .map(|v| escape(v.display_with(format).to_string()))
+                .collect()
+        })
+        .collect()
+}
+
+/// Characters, not bytes, so non-ASCII text lines up
+fn width(text: &str) -> usize {
+    text.chars().count()
+}
+
+fn table_line(cells: &[impl AsRef<str>], widths: &[usize]) -> String {
+    let cells: Vec<String> = cells
+        .iter()
+        .zip(widths)
+        .map(|(cell, width)| format!(" {:<width$} ", cell.as_ref(), width = width))
+        .collect();
+    format!("|{}|\n", cells.join("|"))
+}
+
+fn json(columns: &[String], rows: &[Row]) -> String {
+    let format = ValueFormat::json();
+    let names: Vec<String> = columns
+        .iter()
+        .map(|c| Value::Text(c.clone()).display_with(&format).to_string())
+        .collect();
+    let objects: Vec<String> = cells(rows, &format, |text| text)
+        .into_iter()
+        .map(|row| {
+            let fields: Vec<String> = names
+                .iter()
+                .zip(row)
+                .map(|(name, value)| format!("{}: {}", name, value))
+                .collect();
+            format!("{{{}}}", fields.join(", "))
+        })
+        .collect();
+    format!("[{}]\n", objects.join(",\n "))
+}
+
+fn markdown(columns: &[String], rows: &[Row], format: &ValueFormat) -> String {
+    // A '|' in a value would end its cell
+    let escape = |text: String| text.replace('|', "\\|");
+    let header: Vec<String> = columns.iter().map(|c| escape(c.clone())).collect();
+    let cells = cells(rows, format, escape);
+    let mut widths: Vec<usize> = header.iter().map(|c| width(c).max(3)).collect();
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(self::width(cell));
+        }
+    }
+
+    let rule: Vec<String> = widths.iter().map(|w| "-".repeat(w + 2)).collect();
+    let mut out = table_line(&header, &widths);
+    out.push_str(&format!("|{}|\n", rule.join("|")));
+    for row in &cells {
+        out.push_str(&table_line(row, &widths));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result() -> (Vec<String>, Vec<Row>) {
+        let columns = vec!["id".to_string(), "name".to_string()];
+        let rows = vec![
+            Row::new(vec![Value::Integer(1), Value::Text("Zoë".to_string())]),
+            Row::new(vec![
+                Value::Integer(22),
+                Value::Text("a, \"b\"|c".to_string()),
+            ]),
+            Row::new(vec![Value::Integer(3), Value::Null]),
+        ];
+        (columns, rows)
+    }
+
+    fn render(mode: OutputMode, headers: bool) -> String {
+        let (columns, rows) = result();
+        ResultFormat {
+            mode,
+            headers,
+            max_width: None,
+            null: None,
+        }
+        .render(&columns, &rows)
+    }
+
+    #[test]
+    fn test_table() {
+        assert_eq!(
+            render(OutputMode::Table, true),
+            "+----+----------+\n\
+             | id | name     |\n\
+             +----+----------+\n\
+             |  1 | Zoë      |\n\
+             | 22 | a, \"b\"|c |\n\
+             |  3 | NULL     |\n\
+             +----+----------+\n"
+        );
+        assert_eq!(
+            render(OutputMode::Table, false).lines().next(),
+            Some("+----+----------+")
+        );
+
+        let empty = ResultFormat::default().render(&["id".to_string()], &[]);
+        assert_eq!(empty, "+----+\n| id |\n+----+\n");
+    }
+
+    #[test]
+    fn test_box_and_width() {
+        let (columns, rows) = result();
+        let format = ResultFormat {
+            mode: OutputMode::Box,
+            max_width: Some(5),
+            ..ResultFormat::default()
+        };
+        assert_eq!(
+            format.render(&columns, &rows),
+            "┌────┬───────┐\n\
+             │ id │ name  │\n\
+             ├────┼───────┤\n\
+             │  1 │ Zoë   │\n\
+             │ 22 │ a, \"… │\n\
+             │  3 │ NULL  │\n\
+             └────┴───────┘\n"
+        );
+
+        // Line breaks don't split a row
+        let rows = [Row::new(vec![Value::Text("two\nlines".to_string())])];
+        let format = ResultFormat {
+            headers: false,
+            ..ResultFormat::default()
+        };
+        assert_eq!(
+            format.render(&["t".to_string()], &rows),
+            "+-----------+\n| two lines |\n+-----------+\n"
+        );
+    }
+
+    #[test]
+    fn test_csv_and_list() {
+        assert_eq!(
+            render(OutputMode::Csv, true),
+            "id,name\n1,Zoë\n22,\"a, \"\"b\"\"|c\"\n3,\n"
+        );
+        assert_eq!(
+            render(OutputMode::List, false),
+            "1|Zoë\n22|a, \"b\"|c\n3|NULL\n"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let json = render(OutputMode::Json, false);
+        assert_eq!(
+            json,
+            "[{\"id\": 1, \"name\": \"Zoë\"},\n \
+             {\"id\": 22, \"name\": \"a, \\\"b\\\"|c\"},\n \
+             {\"id\": 3, \"name\": null}]\n"
+        );
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed[1]["name"], "a, \"b\"|c");
+
+        let empty = ResultFormat {
+            mode: OutputMode::Json,
+            ..ResultFormat::default()
+        };
+        assert_eq!(empty.render(&["id".to_string()], &[]), "[]\n");
+    }
+
+    #[test]
+    fn test_markdown() {
+        assert_eq!(
+            render(OutputMode::Markdown, false),
+            "| id  | name      |\n\
+             |-----|-----------|\n\
+             | 1   | Zoë       |\n\
+             | 22  | a, \"b\"\\|c |\n\
+             | 3   | NULL      |\n"
+        );
+    }
+
+    #[test]
+    fn test_null_value() {
+        let (columns, rows) = result();
+        let rows = &rows[2..];
+        let format = |mode| ResultFormat {
+            mode,
+            headers: false,
+            max_width: None,
+            null: Some("(null)".to_string()),
+        };
+        assert_eq!(
+            format(OutputMode::List).render(&columns, rows),
+            "3|(null)\n"
+        );
+        assert_eq!(format(OutputMode::Csv).render(&columns, rows), "3,(null)\n");
+        assert_eq!(
+            format(OutputMode::Table).render(&columns, rows),
+            "+---+--------+\n| 3 | (null) |\n+---+--------+\n"
+        );
+        assert!(
+            format(OutputMode::Markdown)
+                .render(&columns, rows)
+                .ends_with("| 3   | (null) |\n")
+        );
+        assert_eq!(
+            format(OutputMode::Json).render(&columns, rows),
+            "[{\"id\": 3, \"name\": null}]\n"
+        );
+    }
+
+    #[test]
+    fn test_mode_names() {
+        for mode in [
+            OutputMode::Table,
+            OutputMode::Box,
+            OutputMode::Csv,
+            OutputMode::Json,
+            OutputMode::Markdown,
+            OutputMode::List,
+        ] {
+            assert_eq!(OutputMode::from_name(mode.as_str()), Some(mode));
+        }
+        assert_eq!(OutputMode::from_name("JSON"), Some(OutputMode::Json));
+        assert_eq!(OutputMode::from_name("html"), None);
+    }
+}
@@ -0,0 +1,343 @@
+//! The `hozondb` Python module, in the shape of Python's DB-API (PEP 249):
+//! `connect()` gives a connection, whose cursors `execute()` SQL with `?`
+//! parameters and `fetchone()`, `fetchmany()` or `fetchall()` the rows.
+//! Each statement commits as it runs, so `commit()` has nothing to do.
+//!
+//! Values map to Python's: INTEGER and BIGINT to int, FLOAT to float, TEXT
+//! to str, BOOLEAN to bool, BLOB to bytes and NULL to None.
+
+use crate::catalog::row::{Row, Value};
+use crate::database::{Connection as DbConnection, Database};
+use crate::error::HozonError;
+use crate::sql::executor::ExecutionResult;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyBytes, PyFloat, PyInt, PyString, PyTuple};
+use pyo3::{IntoPyObjectExt, create_exception};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+create_exception!(hozondb, Error, PyException);
+
+fn error(e: HozonError) -> PyErr {
+    Error::new_err(e.to_string())
+}
+
+fn closed(what: &str) -> PyErr {
+    Error::new_err(format!("Cannot operate on a closed {}", what))
+}
+
+fn to_python(py: Python<'_>, value: &Value) -> PyResult<Py<PyAny>> {
+    match value {
+        Value::Integer(n) => n.into_py_any(py),
+        Value::BigInt(n) => n.into_py_any(py),
+        Value::Float(n) => n.into_py_any(py),
+        Value::Text(text) => text.into_py_any(py),
+        Value::Boolean(b) => b.into_py_any(py),
+        Value::Blob(bytes) => PyBytes::new(py, bytes).into_py_any(py),
+        Value::Null => Ok(py.None()),
+    }
+}
+
+fn from_python(value: &Bound<'_, PyAny>) -> PyResult<Value> {
+    // bool before int, which it's a subclass of
+    if value.is_none() {
+        Ok(Value::Null)
+    } else if value.is_instance_of::<PyBool>() {
+        Ok(Value::Boolean(value.extract()?))
+    } else if value.is_instance_of::<PyInt>() {
+        let n: i64 = value.extract()?;
+        Ok(i32::try_from(n).map_or(Value::BigInt(n), Value::Integer))
+    } else if value.is_instance_of::<PyFloat>() {
+        Ok(Value::Float(value.extract()?))
+    } else if value.is_instance_of::<PyString>() {
+        Ok(Value::Text(value.extract()?))
+    } else if value.is_instance_of::<PyBytes>() {
+        Ok(Value::Blob(value.extract()?))
+    } else {
+        Err(Error::new_err(format!(
+            "Can't use a {} as a parameter",
+            value.get_type().name()?
+        )))
+    }
+}
+
+/// The values of a parameter sequence
+fn values(parameters: Option<&Bound<'_, PyAny>>) -> PyResult<Vec<Value>> {
+    match parameters {
+        Some(parameters) => parameters
+            .try_iter()?
+            .map(|value| from_python(&value?))
+            .collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn row_tuple<'py>(py: Python<'py>, row: &Row) -> PyResult<Bound<'py, PyTuple>> {
+    let values = row
+        .values()
+        .iter()
+        .map(|value| to_python(py, value))
+        .collect::<PyResult<Vec<_>>>()?;
+    PyTuple::new(py, values)
+}
+
+/// Open or create the database file at `path`, or an in-memory database
+/// for ":memory:"
+#[pyfunction]
+#[pyo3(signature = (path = ":memory:"))]
+fn connect(path: &str) -> PyResult<Connection> {
+    let database = Database::open(path).map_err(error)?;
+    Ok(Connection {
+        inner: Some(Arc::new(database.connect())),
+    })
+}
+
+#[pyclass(module = "hozondb")]
+struct Connection {
+    // None once closed
+    inner: Option<Arc<DbConnection>>,
+}
+
+impl Connection {
+    fn inner(&self) -> PyResult<&Arc<DbConnection>> {
+        self.inner.as_ref().ok_or_else(|| closed("database"))
+    }
+}
+
+#[pymethods]
+impl Connection {
+    fn cursor(&self) -> PyResult<Cursor> {
+        Ok(Cursor::new(self.inner()?.clone()))
+    }
+
+    /// A new cursor that has run `sql`
+    #[pyo3(signature = (sql, parameters = None))]
+    fn execute(
+        &self,
+        py: Python<'_>,
+        sql: &str,
+        parameters: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<Cursor> {
+        let mut cursor = self.cursor()?;
+        cursor.run(py, sql, &values(parameters)?)?;
+        Ok(cursor)
+    }
+
+    /// Nothing to do: every statement is committed when it finishes
+    fn commit(&self) -> PyResult<()> {
+        self.inner().map(|_| ())
+    }
+
+    /// Close this connection; the database closes with its last one
+    fn close(&mut self) {
+        self.inner = None;
+    }
+}
+
+#[pyclass(module = "hozondb")]
+struct Cursor {
+    connection: Option<Arc<DbConnection>>,
+    columns: Option<Vec<String>>,
+    rows: VecDeque<Row>,
+    /// Rows the last statement changed, -1 if it wasn't a write
+    #[pyo3(get)]
+    rowcount: i64,
+    #[pyo3(get)]
+    lastrowid: Option<i64>,
+    /// How many rows `fetchmany` fetches by default
+    #[pyo3(get, set)]
+    arraysize: usize,
+}
+
+impl Cursor {
+    fn new(connection: Arc<DbConnection>) -> Self {
+        Cursor {
+            connection: Some(connection),
+            columns: None,
+            rows: VecDeque::new(),
+            rowcount: -1,
+            lastrowid: None,
+            arraysize: 1,
+        }
+    }
+
+    fn run(&mut self, py: Python<'_>, sql: &str, values: &[Value]) -> PyResult<()> {
+        let connection = self.connection.clone().ok_or_else(|| closed("cursor"))?;
+        let statement = connection
+            .prepare(sql)
+            .and_then(|prepared| prepared.bind(values))
+            .map_err(error)?;
+        // Other threads' Python runs while the statement does
+        let (result, inserted_id) = py
+            .detach(|| connection.execute_inserting(statement))
+            .map_err(error)?;
+
+        self.rowcount = result.affected_rows().map_or(-1, |n| n as i64);
+        self.lastrowid = inserted_id;
+        match result {
+            ExecutionResult::Rows { columns, rows } => {
+                self.columns = Some(columns);
+                self.rows = rows.into();
+            }
+            _ => {
+                self.columns = None;
+                self.rows.clear();
+            }
+        }
+        Ok(())
+    }
+
+    fn fetch<'py>(&mut self, py: Python<'py>, n: usize) -> PyResult<Vec<Bound<'py, PyTuple>>> {
+        let n = n.min(self.rows.len());
+        self.rows
+            .drain(..n)
+            .map(|row| row_tuple(py, &row))
+            .collect()
+    }
+}
+
+#[pymethods]
+impl Cursor {
+    /// Run `sql`, one statement, with `parameters` for its `?`s
+    #[pyo3(signature = (sql, parameters = None))]
+    fn execute<'py>(
+        mut slf: PyRefMut<'py, Self>,
+        sql: &str,
+        parameters: Option<&Bound<'py, PyAny>>,
+    ) -> PyResult<PyRefMut<'py, Self>> {
+        let values = values(parameters)?;
+        let py = slf.py();
+        slf.run(py, sql, &values)?;
+        Ok(slf)
+    }
+
+    /// Run `sql` once for each parameter sequence in `seq_of_parameters`
+    fn executemany(&mut self, sql: &str, seq_of_parameters: &Bound<'_, PyAny>) -> PyResult<()> {
+        let mut changed = 0;
+        for parameters in seq_of_parameters.try_iter()? {
+            self.run(seq_of_parameters.py(), sql, &values(Some(&parameters?))?)?;
+            changed += self.rowcount.max(0);
+        }
+        self.rowcount = changed;
+        Ok(())
+    }
+
+    /// The next row, or None after the last
+    fn fetchone<'py>(&mut self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyTuple>>> {
+        Ok(self.fetch(py, 1)?.pop())
+    }
+
+    #[pyo3(signature = (size = None))]
+    fn fetchmany<'py>(
+        &mut self,
+        py: Python<'py>,
+        size: Option<usize>,
+    ) -> PyResult<Vec<Bound<'py, PyTuple>>> {
+        let size = size.unwrap_or(self.arraysize);
+        self.fetch(py, size)
+    }
+
+    fn fetchall<'py>(&mut self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyTuple>>> {
+        let n = self.rows.len();
+        self.fetch(py, n)
+    }
+
+    /// A 7-item sequence per column of the last query's rows, of which
+    /// only the name is known; None after a statement without rows
+    #[getter]
+    fn description<'py>(&self, py: Python<'py>) -> PyResult<Option<Vec<Bound<'py, PyTuple>>>> {
+        let Some(columns) = &self.columns else {
+            return Ok(None);
+        };
+        columns
+            .iter()
+            .map(|name| {
+                let mut items = vec![name.into_py_any(py)?];
+                items.resize_with(7, || py.None());
+                PyTuple::new(py, items)
+            })
+            .collect::<PyResult<Vec<_>>>()
+            .map(Some)
+    }
+
+    fn close(&mut self) {
+        self.connection = None;
+        self.rows.clear();
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(&mut self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyTuple>>> {
+        self.fetchone(py)
+    }
+}
+
+#[pymodule]
+fn hozondb(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("apilevel", "2.0")?;
+    // Connections can be shared between threads, see `crate::database`
+    m.add("threadsafety", 2)?;
+    m.add("paramstyle", "qmark")?;
+    m.add("Error", m.py().get_type::<Error>())?;
+    m.add_function(wrap_pyfunction!(connect, m)?)?;
+    m.add_class::<Connection>()?;
+    m.add_class::<Cursor>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_python_module() {
+        Python::initialize();
+        Python::attach(|py| {
+            let module = PyModule::new(py, "hozondb").unwrap();
+            hozondb(&module).unwrap();
+            let globals = PyDict::new(py);
+            globals.set_item("hozondb", module).unwrap();
+            py.run(
+                cr#"
+conn = hozondb.connect()
+cur = conn.cursor()
+cur.execute("CREATE TABLE t (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT, big BIGINT, ok BOOLEAN, data BLOB)")
+cur.executemany("INSERT INTO t (name, big, ok, data) VALUES (?, ?, ?, ?)", [
+    ("a", 2**40, True, b"\x00\x01"),
+    (None, 1, False, None),
+])
+assert cur.rowcount == 2, cur.rowcount
+assert cur.lastrowid == 2, cur.lastrowid
+cur.execute("SELECT id FROM t")
+assert cur.lastrowid is None, cur.lastrowid
+
+cur = conn.execute("SELECT * FROM t WHERE id >= ? ORDER BY id", (1,))
+assert [d[0] for d in cur.description] == ["id", "name", "big", "ok", "data"]
+assert cur.fetchone() == (1, "a", 2**40, True, b"\x00\x01")
+assert cur.fetchall() == [(2, None, 1, False, None)]
+assert cur.fetchone() is None
+assert [row[0] for row in conn.execute("SELECT id FROM t")] == [1, 2]
+
+try:
+    conn.execute("SELECT * FROM missing")
+    raise AssertionError("no error")
+except hozondb.Error as e:
+    assert "missing" in str(e)
+conn.close()
+try:
+    conn.cursor()
+    raise AssertionError("no error")
+except hozondb.Error:
+    pass
+"#,
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
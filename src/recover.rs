@@ -0,0 +1,403 @@
+//! Best-effort salvage of a damaged database into a fresh file.
+//!
+//! Every data page is scanned for rows that still parse. Rows on a page the
+//! catalog still points at go back into that table; rows from pages no
+//! surviving catalog entry owns go into `recovered_<page>` tables whose
+//! column types are inferred from the data. Only rows in the tagged encoding
+//! carry their types, so compact rows can't be salvaged from orphaned pages.
+
+use crate::error::{HozonError, Result};
+use std::collections::HashMap;
+use std::fs;
+
+use crate::catalog::row::{ROW_COMPACT, ROW_DELETED, Row, Value};
+use crate::catalog::schema::{Column, DataType};
+use crate::catalog::table::{TableCatalog, TableMetadata};
+use crate::sql::executor::Executor;
+use crate::sql::parser::{Expr, Statement};
+use crate::storage::page::{
+    FIRST_DATA_PAGE, FORMAT_VERSION, PAGE_DATA_START, PAGE_SIZE, PageId, PageManager, PageType,
+};
+
+#[derive(Debug, Default, PartialEq)]
+pub struct RecoveryReport {
+    /// Tables created in the new database
+    pub tables: usize,
+    pub rows: usize,
+    /// Rows that parsed but did not fit their table's schema
+    pub skipped_rows: usize,
+    /// Pages that could not be read at all (e.g. failed to decompress), or
+    /// whose rows can't be decoded without their lost schema
+    pub unreadable_pages: Vec<PageId>,
+    /// Whether any catalog entries were lost
+    pub catalog_damaged: bool,
+}
+
+/// Salvage what can be read from `source` into a new database at `target`
+pub fn recover(source: &str, target: &str) -> Result<RecoveryReport> {
+    if fs::metadata(target).is_ok() {
+        return Err(HozonError::AlreadyExists(format!(
+            "'{}' already exists",
+            target
+        )));
+    }
+
+    let source = PageManager::new(source)?;
+    let version = source.format_version()?;
+    if version < FORMAT_VERSION {
+        return Err(HozonError::Unsupported(format!(
+            "Database format version {} must be upgraded before recovery; open it first",
+            version
+        )));
+    }
+
+    let mut report = RecoveryReport::default();
+    let tables = read_catalog(&source, &mut report);
+    let columns: HashMap<_, _> = tables
+        .iter()
+        .map(|t| (t.first_page(), t.schema().columns().as_slice()))
+        .collect();
+
+    // Salvage rows page by page before touching the target
+    let mut pages = Vec::new();
+    for page_id in FIRST_DATA_PAGE..source.num_pages() {
+        match source.read_page_unchecked(page_id) {
+            Ok(page) => {
+                let columns = columns.get(&page_id).copied();
+                let (rows, compact_orphan) = salvage_rows(&page, columns);
+                if !rows.is_empty() {
+                    pages.push((page_id, rows));
+                }
+                if compact_orphan {
+                    report.unreadable_pages.push(page_id);
+                }
+            }
+            Err(_) => report.unreadable_pages.push(page_id),
+        }
+    }
+    drop(source);
+
+    let mut executor = Executor::new(TableCatalog::new(PageManager::new(target)?)?);
+
+    let mut owners = HashMap::new();
+    for table in &tables {
+        let schema = table.schema();
+        create_table(&mut executor, schema.table_name(), schema.columns().clone())?;
+        owners.insert(table.first_page(), schema);
+        report.tables += 1;
+    }
+
+    for (page_id, rows) in pages {
+        let table_name = match owners.get(&page_id) {
+            Some(schema) => schema.table_name().to_string(),
+            None => {
+                let name = format!("recovered_{}", page_id);
+                create_table(&mut executor, &name, infer_columns(&rows))?;
+                report.tables += 1;
+                name
+            }
+        };
+
+        for row in rows {
+            let insert = Statement::Insert {
+                table_name: table_name.clone(),
+                columns: None,
+                values: vec![row.values().iter().cloned().map(Expr::Literal).collect()],
+                on_conflict: None,
+                returning: None,
+            };
+            match executor.execute(insert) {
+                Ok(_) => report.rows += 1,
+                Err(_) => report.skipped_rows += 1,
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Catalog entries that still parse, stopping at the first damaged one
+fn read_catalog(source: &PageManager, report: &mut RecoveryReport) -> Vec<TableMetadata> {
+    let mut tables = Vec::new();
+
+    let catalog_data = match source.read_page_unchecked(1) {
+        Ok(data) => data,
+        Err(_) => {
+            report.catalog_damaged = true;
+            return tables;
+        }
+    };
+
+    let num_tables = u32::from_le_bytes([
+        catalog_data[0],
+        catalog_data[1],
+        catalog_data[2],
+        catalog_data[3],
+    ]) as usize;
+
+    let mut offset = 4;
+    while tables.len() < num_tables {
+        match TableMetadata::from_bytes(&catalog_data[offset..]) {
+            Ok((table, consumed)) => {
+                offset += consumed;
+                // Two entries can't share a name or a page; keep the first
+                let duplicate = tables.iter().any(|t: &TableMetadata| {
+                    t.first_page() == table.first_page()
+                        || t.schema().table_name() == table.schema().table_name()
+                });
+                if !duplicate {
+                    tables.push(table);
+                }
+            }
+            Err(_) => {
+                report.catalog_damaged = true;
+                break;
+            }
+        }
+    }
+
+    tables
+}
+
+/// Rows that parse from the start of a data page, up to the first bad one.
+/// Without the owning table's `columns` only tagged rows parse; the flag
+/// says whether salvage stopped at a compact row for that reason.
+fn salvage_rows(page: &[u8; PAGE_SIZE], columns: Option<&[Column]>) -> (Vec<Row>, bool) {
+    // Index and overflow pages don't hold rows. An unknown type byte may just
+    // be damaged metadata, so those pages are still scanned.
+    if matches!(
+        PageManager::page_type_from_buffer(page),
+        Some(PageType::Index | PageType::IndexInterior | PageType::Overflow | PageType::Free)
+    ) {
+        return (Vec::new(), false);
+    }
+
+    let meta = PageManager::read_metadata_from_buffer(page);
+    let end = if (PAGE_DATA_START..=PAGE_SIZE).contains(&meta.last_offset) {
+        meta.last_offset
+    } else {
+        PAGE_SIZE
+    };
+
+    let mut rows = Vec::new();
+    let mut offset = PAGE_DATA_START;
+    while offset < end {
+        if page[offset] == ROW_DELETED {
+            offset += 1;
+            continue;
+        }
+        let compact = page[offset] == ROW_COMPACT;
+        let parsed = match columns {
+            Some(columns) => Row::decode(&page[offset..end], columns),
+            None if compact => return (rows, true),
+            None => Row::from_bytes(&page[offset..end]),
+        };
+        match parsed {
+            // A complete row has at least one value, and tagged rows end with
+            // their terminator
+            Ok((row, consumed))
+                if !row.values().is_empty() && (compact || page[offset + consumed - 1] == 0) =>
+            {
+                rows.push(row);
+                offset += consumed;
+            }
+            _ => break,
+        }
+    }
+    (rows, false)
+}
+
+/// Columns `c1..cN` typed by the first non-NULL value seen in each
+fn infer_columns(rows: &[Row]) -> Vec<Column> {
+    let width = rows[0].values().len();
+
+    (0..width)
+        .map(|i| {
+            let data_type = rows
+                .iter()
+                .filter(|row| row.values().len() == width)
+                .find_map(|row| match row.get_value(i) {
+                    Some(Value::Integer(_)) => Some(DataType::Integer),
+                    Some(Value::Text(_)) => Some(DataType::Text),
+                    Some(Value::Boolean(_)) => Some(DataType::Boolean),
+                    Some(Value::Float(_)) => Some(DataType::Float),
+                    Some(Value::BigInt(_)) => Some(DataType::BigInt),
+                    Some(Value::Blob(_)) => Some(DataType::Blob),
+                    _ => None,
+                })
+                .unwrap_or(DataType::Text);
+            Column::new(&format!("c{}", i + 1), data_type)
+        })
+        .collect()
+}
+
+fn create_table(executor: &mut Executor, name: &str, columns: Vec<Column>) -> Result<()> {
+    executor.execute(Statement::CreateTable {
+        name: name.to_string(),
+        columns,
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::executor::ExecutionResult;
+    use crate::sql::parser::SelectColumns;
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
+
+    fn cleanup(path: &str) {
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(format!("{}-dwb", path));
+    }
+
+    fn users_columns() -> Vec<Column> {
+        vec![
+            Column::new("id", DataType::Integer),
+            Column::new("name", DataType::Text),
+        ]
+    }
+
+    fn users_rows() -> Vec<Row> {
+        [(1, "alice"), (2, "bob"), (3, "carol")]
+            .into_iter()
+            .map(|(id, name)| Row::new(vec![Value::Integer(id), Value::Text(name.to_string())]))
+            .collect()
+    }
+
+    fn build_source(path: &str) {
+        cleanup(path);
+        let mut executor =
+            Executor::new(TableCatalog::new(PageManager::new(path).unwrap()).unwrap());
+        executor
+            .execute(Statement::CreateTable {
+                name: "users".to_string(),
+                columns: users_columns(),
+            })
+            .unwrap();
+        for row in users_rows() {
+            executor
+                .execute(Statement::Insert {
+                    table_name: "users".to_string(),
+                    columns: None,
+                    values: vec![row.values().iter().cloned().map(Expr::Literal).collect()],
+                    on_conflict: None,
+                    returning: None,
+                })
+                .unwrap();
+        }
+    }
+
+    /// Rewrite page 2's rows in the tagged encoding older files use
+    fn write_tagged_rows(path: &str) {
+        let rows: Vec<u8> = users_rows().iter().flat_map(Row::to_bytes).collect();
+        let end = (PAGE_DATA_START + rows.len()) as u32;
+        corrupt(path, 2, PAGE_DATA_START, &rows);
+        corrupt(path, 2, 4, &end.to_le_bytes());
+    }
+
+    fn corrupt(path: &str, page_id: PageId, offset: usize, bytes: &[u8]) {
+        let mut file = OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::Start(
+            (page_id as usize * PAGE_SIZE + offset) as u64,
+        ))
+        .unwrap();
+        file.write_all(bytes).unwrap();
+    }
+
+    fn select_all(path: &str, table: &str) -> Vec<Row> {
+        let executor = Executor::new(TableCatalog::new(PageManager::new(path).unwrap()).unwrap());
+        match executor
+            .query(Statement::Select {
+                table_name: table.to_string(),
+                columns: SelectColumns::All,
+                where_clause: None,
+                order_by: Vec::new(),
+                distinct: false,
+                joins: Vec::new(),
+            })
+            .unwrap()
+        {
+            ExecutionResult::Rows { rows, .. } => rows,
+            other => panic!("expected rows, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_recover_intact_database() {
+        build_source("test_recover_intact.db");
+        cleanup("test_recover_intact_out.db");
+
+        let report = recover("test_recover_intact.db", "test_recover_intact_out.db").unwrap();
+        assert_eq!(report.tables, 1);
+        assert_eq!(report.rows, 3);
+        assert!(!report.catalog_damaged);
+        assert_eq!(select_all("test_recover_intact_out.db", "users").len(), 3);
+
+        cleanup("test_recover_intact.db");
+        cleanup("test_recover_intact_out.db");
+    }
+
+    #[test]
+    fn test_recover_with_damaged_catalog() {
+        build_source("test_recover_catalog.db");
+        cleanup("test_recover_catalog_out.db");
+        write_tagged_rows("test_recover_catalog.db");
+        corrupt("test_recover_catalog.db", 1, 4, &[0xFF; 16]);
+
+        let report = recover("test_recover_catalog.db", "test_recover_catalog_out.db").unwrap();
+        assert!(report.catalog_damaged);
+        assert_eq!(report.rows, 3);
+
+        // The rows survive in a table named after their page, with inferred types
+        let rows = select_all("test_recover_catalog_out.db", "recovered_2");
+        assert_eq!(rows[1].values()[1], Value::Text("bob".to_string()));
+
+        cleanup("test_recover_catalog.db");
+        cleanup("test_recover_catalog_out.db");
+    }
+
+    #[test]
+    fn test_compact_rows_need_their_schema() {
+        build_source("test_recover_compact.db");
+        cleanup("test_recover_compact_out.db");
+        corrupt("test_recover_compact.db", 1, 4, &[0xFF; 16]);
+
+        let report = recover("test_recover_compact.db", "test_recover_compact_out.db").unwrap();
+        assert!(report.catalog_damaged);
+        assert_eq!(report.rows, 0);
+        assert_eq!(report.unreadable_pages, vec![2]);
+
+        cleanup("test_recover_compact.db");
+        cleanup("test_recover_compact_out.db");
+    }
+
+    #[test]
+    fn test_recover_keeps_rows_before_damage() {
+        build_source("test_recover_rows.db");
+        cleanup("test_recover_rows_out.db");
+
+        // Break the second row's marker; the page type byte goes too
+        let second_row_at = PAGE_DATA_START + users_rows()[0].encode(&users_columns()).len();
+        corrupt("test_recover_rows.db", 2, second_row_at, &[0xEE]);
+        corrupt("test_recover_rows.db", 2, 5, &[0xEE]);
+
+        let report = recover("test_recover_rows.db", "test_recover_rows_out.db").unwrap();
+        assert_eq!(report.rows, 1);
+        assert_eq!(select_all("test_recover_rows_out.db", "users").len(), 1);
+
+        cleanup("test_recover_rows.db");
+        cleanup("test_recover_rows_out.db");
+    }
+
+    #[test]
+    fn test_recover_refuses_existing_target() {
+        build_source("test_recover_exists.db");
+
+        let err = recover("test_recover_exists.db", "test_recover_exists.db").unwrap_err();
+        assert!(matches!(err, HozonError::AlreadyExists(_)), "{:?}", err);
+
+        cleanup("test_recover_exists.db");
+    }
+}
@@ -1,19 +1,216 @@
-use crate::catalog::{row::Value, table::TableCatalog};
+use crate::catalog::table::TableCatalog;
+use crate::error::{HozonError, Result};
+use crate::import;
+use crate::output::{OutputMode, ResultFormat};
+use crate::recover;
 use crate::sql::{
     executor::{ExecutionResult, Executor},
-    parser::Parser,
-    tokenizer::{self},
+    formatter,
+    interrupt::CancelToken,
+    parser::{Expr, Parser, Statement},
+    tokenizer,
 };
-use crate::storage::page::PageManager;
-use std::io::{self, Write};
+use crate::storage::page::{MEMORY_PATH, PAGE_SIZE, PageManager, PageType};
+use crate::storage::stats::IoStats;
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// File in the home directory that lines typed at the prompt are kept in
+const HISTORY_FILE: &str = ".hozondb_history";
+
+/// File in the home directory run before the first prompt, to set up the
+/// output settings and perhaps open a database
+const CONFIG_FILE: &str = ".hozondbrc";
 
 pub struct Repl {
     executor: Option<Executor>,
+    output: ResultFormat,
+    destination: Destination,
+    /// Print only results, not what a statement or command did, for output
+    /// another program reads
+    quiet: bool,
+    /// Print how long each statement took, and the pages it read and wrote
+    timer: bool,
+    /// Shared with every database opened, for Ctrl-C to cancel a statement
+    interrupt: CancelToken,
+    /// Stop a script at its first error
+    bail: bool,
+    /// Print each command before running it
+    echo: bool,
+    /// Errors scripts reported and carried on past
+    failures: usize,
+}
+
+/// Where result rows are printed: the terminal, or a file from `.output`
+/// or `.once`. Messages always go to the terminal.
+enum Destination {
+    Stdout,
+    File {
+        file: File,
+        /// Back to the terminal after the next SQL, for `.once`
+        once: bool,
+    },
+}
+
+impl Destination {
+    fn write(&mut self, text: &str) -> Result<()> {
+        match self {
+            Destination::Stdout => print!("{}", text),
+            Destination::File { file, .. } => file.write_all(text.as_bytes())?,
+        }
+        Ok(())
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Repl {
     pub fn new() -> Self {
-        Repl { executor: None }
+        Repl {
+            executor: None,
+            output: ResultFormat::default(),
+            destination: Destination::Stdout,
+            quiet: false,
+            timer: false,
+            interrupt: CancelToken::new(),
+            bail: false,
+            echo: false,
+            failures: 0,
+        }
+    }
+
+    /// A REPL for running SQL given on the command line or piped in: rows
+    /// print as `|`-separated lines without a header, and nothing else does
+    pub fn batch() -> Self {
+        Repl {
+            output: ResultFormat {
+                mode: OutputMode::List,
+                headers: false,
+                max_width: None,
+                null: None,
+            },
+            quiet: true,
+            ..Self::new()
+        }
+    }
+
+    /// Open or create the database at `path`, or an in-memory one for
+    /// ":memory:", closing the one open first
+    pub fn open(&mut self, path: &str) -> Result<()> {
+        // It may be the same file, which stays locked until it's closed
+        self.close();
+        self.start(PageManager::new(path)?)
+    }
+
+    fn start(&mut self, pm: PageManager) -> Result<()> {
+        let catalog = TableCatalog::new(pm)?;
+        let mut executor = Executor::new(catalog);
+        executor.set_cancel_token(self.interrupt.clone());
+        self.executor = Some(executor);
+        Ok(())
+    }
+
+    /// Close the open database, if there is one
+    fn close(&mut self) {
+        if let Some(executor) = self.executor.take()
+            && !self.quiet
+        {
+            let path = executor.catalog().path().unwrap_or(MEMORY_PATH);
+            println!("Closed database file: {}", path);
+        }
+    }
+
+    /// Run `script` as though each line were typed at the prompt, until an
+    /// `.exit`. Each command that fails is reported and the rest still run,
+    /// unless `.bail on` makes the first error stop the script and come back
+    /// from here. Returns how many failed.
+    pub fn run_script(&mut self, script: &str) -> Result<usize> {
+        self.run_lines(script, None)
+    }
+
+    /// Run the commands in ~/.hozondbrc, if there is one
+    pub fn load_config(&mut self) -> Result<()> {
+        match home_file(CONFIG_FILE) {
+            Some(path) => self.run_file(&path).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Run the script at `path` if it exists, as `run_script` does
+    fn run_file(&mut self, path: &Path) -> Result<usize> {
+        let script = match std::fs::read_to_string(path) {
+            Ok(script) => script,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(HozonError::from(e).context(path.display())),
+        };
+        self.run_lines(&script, Some(&path.display().to_string()))
+    }
+
+    /// Run a script; `name` says where it came from in its errors
+    fn run_lines(&mut self, script: &str, name: Option<&str>) -> Result<usize> {
+        let failures = self.failures;
+        let mut sql = String::new();
+        let mut start = 0;
+        for (number, text) in script.lines().enumerate() {
+            let line = text.trim();
+            if sql.is_empty() {
+                if line.is_empty() {
+                    continue;
+                }
+                if line == ".exit" || line == ".quit" {
+                    break;
+                }
+                start = number + 1;
+                if line.starts_with('.') {
+                    self.run_script_command(line, name, start)?;
+                    continue;
+                }
+            }
+            // As typed, since it may be in a string
+            sql.push_str(text);
+            sql.push('\n');
+            if tokenizer::ends_statement(&sql) {
+                let sql = std::mem::take(&mut sql);
+                self.run_script_command(&sql, name, start)?;
+            }
+        }
+        // The last statement needn't end with ';'
+        if !sql.trim().is_empty() {
+            let sql = format!("{};", sql.trim_end());
+            self.run_script_command(&sql, name, start)?;
+        }
+        Ok(self.failures - failures)
+    }
+
+    /// Run a command from line `line` of a script
+    fn run_script_command(&mut self, command: &str, name: Option<&str>, line: usize) -> Result<()> {
+        let Err(e) = self.execute_command(command) else {
+            return Ok(());
+        };
+        let e = match name {
+            Some(name) => e.context(format!("{}, line {}", name, line)),
+            None => e.context(format!("line {}", line)),
+        };
+        self.script_error(e)
+    }
+
+    /// Report an error from a script and carry on, or with `.bail on` stop
+    /// the script with it
+    fn script_error(&mut self, e: HozonError) -> Result<()> {
+        if self.bail {
+            return Err(e);
+        }
+        eprintln!("Error: {}", e);
+        self.failures += 1;
+        Ok(())
     }
 
     pub fn run(&mut self) {
@@ -21,34 +218,93 @@ impl Repl {
         println!("Enter '.help' for usage hints.");
         println!();
 
-        loop {
-            print!("hozondb> ");
-            io::stdout().flush().unwrap();
-
-            let mut input = String::new();
-            if io::stdin().read_line(&mut input).is_err() {
-                eprintln!("Error reading input");
-                continue;
+        let mut editor = match DefaultEditor::new() {
+            Ok(editor) => editor,
+            Err(e) => {
+                eprintln!("Error starting the line editor: {}", e);
+                return;
             }
+        };
+        // Ctrl-C at the prompt reaches the editor as a key; while a
+        // statement runs it's a signal, and stops just that statement
+        let interrupt = self.interrupt.clone();
+        if let Err(e) = ctrlc::set_handler(move || interrupt.cancel()) {
+            eprintln!("Error installing the Ctrl-C handler: {}", e);
+        }
+
+        // No history yet is fine, as is a home that can't be found
+        let history = home_file(HISTORY_FILE);
+        if let Some(path) = &history {
+            let _ = editor.load_history(path);
+        }
 
-            let input = input.trim();
+        // SQL can span lines; it runs once a line ends it with ';'
+        let mut sql = String::new();
+        loop {
+            let prompt = if sql.is_empty() {
+                "hozondb> "
+            } else {
+                "   ...> "
+            };
+            let input = match editor.readline(prompt) {
+                Ok(input) => input,
+                // Ctrl-C drops a half-typed statement; Ctrl-D leaves
+                Err(ReadlineError::Interrupted) => {
+                    sql.clear();
+                    continue;
+                }
+                Err(ReadlineError::Eof) => break,
+                Err(e) => {
+                    eprintln!("Error reading input: {}", e);
+                    break;
+                }
+            };
 
-            if input.len() == 0 {
+            let text = input;
+            let input = text.trim();
+
+            // A blank line only matters inside a statement, in a string
+            if input.is_empty() {
+                if !sql.is_empty() {
+                    sql.push('\n');
+                }
                 continue;
             }
+            let _ = editor.add_history_entry(input);
 
-            if input == ".exit" || input == ".quit" {
+            if sql.is_empty() && (input == ".exit" || input == ".quit") {
                 println!("Exiting HozonDB. Goodbye!");
                 break;
             }
 
-            if let Err(e) = self.execute_command(input) {
+            if sql.is_empty() && input.starts_with('.') {
+                if let Err(e) = self.execute_command(input) {
+                    eprintln!("Error: {}", e);
+                }
+                continue;
+            }
+
+            sql.push_str(&text);
+            sql.push('\n');
+            if !tokenizer::ends_statement(&sql) {
+                continue;
+            }
+            if let Err(e) = self.execute_command(&std::mem::take(&mut sql)) {
                 eprintln!("Error: {}", e);
             }
         }
+
+        if let Some(path) = &history
+            && let Err(e) = editor.save_history(path)
+        {
+            eprintln!("Error saving history to {}: {}", path.display(), e);
+        }
     }
 
-    pub fn execute_command(&mut self, command: &str) -> io::Result<()> {
+    pub fn execute_command(&mut self, command: &str) -> Result<()> {
+        if self.echo {
+            println!("{}", command.trim_end());
+        }
         if command.starts_with(".") {
             self.execute_meta_command(command)
         } else {
@@ -56,27 +312,257 @@ impl Repl {
         }
     }
 
-    fn execute_meta_command(&mut self, command: &str) -> io::Result<()> {
+    fn execute_meta_command(&mut self, command: &str) -> Result<()> {
         let parts: Vec<&str> = command.split_whitespace().collect();
 
         if parts.is_empty() {
             return Ok(());
         }
 
-        let command = parts[0];
-
-        match command {
+        match parts[0] {
             ".help" => self.cmd_help(),
             ".open" => self.cmd_open(&parts),
+            ".close" => self.cmd_close(),
+            ".backup" => self.cmd_backup(&parts),
+            ".mode" => self.cmd_mode(&parts),
+            ".headers" => self.cmd_headers(&parts),
+            ".width" => self.cmd_width(&parts),
+            ".timer" => self.cmd_timer(&parts),
+            ".bail" => self.cmd_bail(&parts),
+            ".echo" => self.cmd_echo(&parts),
+            ".nullvalue" => self.cmd_nullvalue(command),
+            ".import" => self.cmd_import(&parts),
+            ".output" => self.cmd_output(&parts, false),
+            ".once" => self.cmd_output(&parts, true),
+            ".export" => self.cmd_export(&parts),
+            ".dump" => self.cmd_dump(&parts),
+            ".read" => self.cmd_read(&parts),
+            ".tables" => self.cmd_tables(),
+            ".schema" => self.cmd_schema(&parts),
+            ".pages" => self.cmd_pages(),
+            ".page" => self.cmd_page(&parts),
+            ".allocate" => self.cmd_allocate(),
+            ".write" => self.cmd_write(command, &parts),
+            ".stats" => self.cmd_stats(&parts),
+            ".recover" => self.cmd_recover(&parts),
             _ => {
-                eprintln!("Unknown command: '{}'. Type '.help' for usage.", command);
+                eprintln!("Unknown command: '{}'. Type '.help' for usage.", parts[0]);
                 Ok(())
             }
         }
     }
 
-    fn execute_sql_command(&mut self, sql: &str) -> io::Result<()> {
+    fn execute_sql_command(&mut self, sql: &str) -> Result<()> {
         // check if database is open
+        match self.executor {
+            Some(_) => {}
+            None if self.quiet => {
+                return Err(HozonError::InvalidInput("No database is open".to_string()));
+            }
+            None => {
+                eprintln!("No database is open. Use '.open <file>' first.");
+                return Ok(());
+            }
+        }
+
+        // Each statement runs, and shows its result, in turn; the first
+        // error stops the rest
+        let statements = Parser::from_sql(sql)?.parse_all()?;
+        let printed = statements
+            .into_iter()
+            .try_for_each(|statement| self.run_statement(statement));
+        if let Destination::File { once: true, .. } = self.destination {
+            self.destination = Destination::Stdout;
+        }
+        printed
+    }
+
+    /// Run a statement on the open database and print its result, then its
+    /// timing with `.timer on`
+    fn run_statement(&mut self, statement: Statement) -> Result<()> {
+        let executor = self.executor.as_mut().expect("a database is open");
+        let before = executor.catalog().stats();
+        let started = Instant::now();
+        let result = executor.execute(statement);
+        let elapsed = started.elapsed();
+        let io = executor.catalog().stats().since(&before);
+
+        let printed = result.and_then(|result| {
+            print_result(result, &self.output, &mut self.destination, self.quiet)
+        });
+        if self.timer {
+            println!("{}", timing(elapsed, &io));
+        }
+        printed
+    }
+
+    fn cmd_help(&self) -> Result<()> {
+        println!("Available commands:");
+        println!("  .help              - Show this help message");
+        println!("  .open [--readonly|--create] <file>");
+        println!("                     - Open or create a database file (:memory: for in-memory),");
+        println!("                       closing the one open; --readonly never writes to it,");
+        println!("                       --create refuses a file that already exists");
+        println!("  .close             - Close the open database");
+        println!("  .mode [mode]       - Show or set how results print: table, box, csv,");
+        println!("                       json, markdown or list");
+        println!("  .headers on|off    - Whether results start with the column names");
+        println!("  .width [n|off]     - Show or set the widest a table column gets");
+        println!("  .nullvalue [text]  - Show NULL as text, or as the mode does by default");
+        println!("  .timer on|off      - Whether each statement prints how long it took");
+        println!("  .import <file> <table>");
+        println!("                     - Load a CSV file with a header row into a table");
+        println!("  .output [file]     - Print results to a file, or back to the terminal");
+        println!("  .once <file>       - Print the next SQL's results to a file");
+        println!("  .export <table> <file>");
+        println!("                     - Write a table to a CSV file, or JSON for a .json file");
+        println!("  .dump [table]      - Print SQL that recreates the database, or one table");
+        println!("  .read <file>       - Run the SQL statements in a file");
+        println!("  .bail on|off       - Whether a script stops at its first error");
+        println!("  .echo on|off       - Whether each command is printed before it runs");
+        println!("  .tables            - List the tables and views");
+        println!("  .schema [table]    - Show the CREATE statements of every table, or one");
+        println!("  .backup <file>     - Copy the open database to a new file");
+        println!("  .backup --incremental <file>");
+        println!("                     - Copy only pages changed since the last backup to <file>");
+        println!("  .pages             - List every page with its type and row count");
+        println!("  .page <id> [offset] [length]");
+        println!("                     - Show a page's bytes in hex, or just part of it");
+        println!("  .allocate          - Add an empty data page");
+        println!("  .write <id> <offset> <text>");
+        println!("                     - Write text into a page at a byte offset");
+        println!("  .stats [reset]     - Show storage I/O counters, or reset them");
+        println!("  .recover <damaged> <new>");
+        println!(
+            "                     - Salvage readable rows from a damaged database into a new file"
+        );
+        println!("  .exit              - Exit the program");
+        Ok(())
+    }
+
+    fn cmd_open(&mut self, parts: &[&str]) -> Result<()> {
+        let (flag, filename) = match parts {
+            [_, filename] => (None, *filename),
+            [_, flag @ ("--readonly" | "--create"), filename] => (Some(*flag), *filename),
+            _ => {
+                eprintln!("Usage: .open [--readonly|--create] <file>");
+                return Ok(());
+            }
+        };
+        if flag == Some("--create") && Path::new(filename).exists() {
+            return Err(HozonError::AlreadyExists(format!(
+                "Database file '{}' already exists",
+                filename
+            )));
+        }
+
+        match flag {
+            Some("--readonly") => {
+                self.close();
+                self.start(PageManager::new_read_only(filename)?)?;
+            }
+            _ => self.open(filename)?,
+        }
+        if !self.quiet {
+            let read_only = match flag {
+                Some("--readonly") => " (read-only)",
+                _ => "",
+            };
+            println!("Opened database file: {}{}", filename, read_only);
+        }
+        Ok(())
+    }
+
+    fn cmd_close(&mut self) -> Result<()> {
+        match self.executor {
+            Some(_) => self.close(),
+            None => eprintln!("No database is open."),
+        }
+        Ok(())
+    }
+
+    fn cmd_mode(&mut self, parts: &[&str]) -> Result<()> {
+        match parts {
+            [_] => println!("{}", self.output.mode.as_str()),
+            [_, name] => match OutputMode::from_name(name) {
+                Some(mode) => self.output.mode = mode,
+                None => eprintln!(
+                    "Unknown mode '{}'. Use table, box, csv, json, markdown or list.",
+                    name
+                ),
+            },
+            _ => eprintln!("Usage: .mode [table|box|csv|json|markdown|list]"),
+        }
+        Ok(())
+    }
+
+    fn cmd_headers(&mut self, parts: &[&str]) -> Result<()> {
+        match parts {
+            [_, on] if on.eq_ignore_ascii_case("on") => self.output.headers = true,
+            [_, off] if off.eq_ignore_ascii_case("off") => self.output.headers = false,
+            _ => eprintln!("Usage: .headers on|off"),
+        }
+        Ok(())
+    }
+
+    fn cmd_nullvalue(&mut self, command: &str) -> Result<()> {
+        // The text is the rest of the line, spaces and all; none resets it
+        self.output.null = command
+            .split_once(char::is_whitespace)
+            .map(|(_, text)| text.trim())
+            .filter(|text| !text.is_empty())
+            .map(str::to_string);
+        Ok(())
+    }
+
+    fn cmd_bail(&mut self, parts: &[&str]) -> Result<()> {
+        match parts {
+            [_, on] if on.eq_ignore_ascii_case("on") => self.bail = true,
+            [_, off] if off.eq_ignore_ascii_case("off") => self.bail = false,
+            _ => eprintln!("Usage: .bail on|off"),
+        }
+        Ok(())
+    }
+
+    fn cmd_echo(&mut self, parts: &[&str]) -> Result<()> {
+        match parts {
+            [_, on] if on.eq_ignore_ascii_case("on") => self.echo = true,
+            [_, off] if off.eq_ignore_ascii_case("off") => self.echo = false,
+            _ => eprintln!("Usage: .echo on|off"),
+        }
+        Ok(())
+    }
+
+    fn cmd_timer(&mut self, parts: &[&str]) -> Result<()> {
+        match parts {
+            [_, on] if on.eq_ignore_ascii_case("on") => self.timer = true,
+            [_, off] if off.eq_ignore_ascii_case("off") => self.timer = false,
+            _ => eprintln!("Usage: .timer on|off"),
+        }
+        Ok(())
+    }
+
+    fn cmd_width(&mut self, parts: &[&str]) -> Result<()> {
+        match parts {
+            [_] => match self.output.max_width {
+                Some(width) => println!("{}", width),
+                None => println!("off"),
+            },
+            [_, off] if off.eq_ignore_ascii_case("off") => self.output.max_width = None,
+            [_, width] => match width.parse() {
+                Ok(width) if width > 0 => self.output.max_width = Some(width),
+                _ => eprintln!("Usage: .width [n|off]"),
+            },
+            _ => eprintln!("Usage: .width [n|off]"),
+        }
+        Ok(())
+    }
+
+    fn cmd_import(&mut self, parts: &[&str]) -> Result<()> {
+        let [_, path, table] = parts else {
+            eprintln!("Usage: .import <file> <table>");
+            return Ok(());
+        };
         let executor = match self.executor.as_mut() {
             Some(exec) => exec,
             None => {
@@ -85,63 +571,473 @@ impl Repl {
             }
         };
 
-        let tokens = tokenizer::tokenize(sql)?;
-        let statement = Parser::new(tokens).parse()?;
-        let res = executor.execute(statement)?;
+        let report = import::import_csv(executor, path, table)?;
+        if report.created {
+            println!("Created table '{}'.", table);
+        }
+        for (line, err) in &report.rejected {
+            eprintln!("Line {}: {}", line, err);
+        }
+        println!(
+            "Imported {} rows into '{}' ({} rejected).",
+            report.rows,
+            table,
+            report.rejected.len()
+        );
+        Ok(())
+    }
+
+    fn cmd_output(&mut self, parts: &[&str], once: bool) -> Result<()> {
+        match (parts, once) {
+            ([_], false) => self.destination = Destination::Stdout,
+            ([_, path], _) => {
+                let file = File::create(path)?;
+                self.destination = Destination::File { file, once };
+            }
+            (_, false) => eprintln!("Usage: .output [file]"),
+            (_, true) => eprintln!("Usage: .once <file>"),
+        }
+        Ok(())
+    }
+
+    fn cmd_export(&self, parts: &[&str]) -> Result<()> {
+        let [_, table, path] = parts else {
+            eprintln!("Usage: .export <table> <file>");
+            return Ok(());
+        };
+        let executor = match self.executor.as_ref() {
+            Some(exec) => exec,
+            None => {
+                eprintln!("No database is open. Use '.open <file>' first.");
+                return Ok(());
+            }
+        };
+
+        let sql = format!("SELECT * FROM {};", formatter::identifier(table));
+        let ExecutionResult::Rows { columns, rows } =
+            executor.query(Parser::from_sql(&sql)?.parse()?)?
+        else {
+            unreachable!("a SELECT returns rows")
+        };
+        let mode = match path.rsplit_once('.') {
+            Some((_, extension)) if extension.eq_ignore_ascii_case("json") => OutputMode::Json,
+            _ => OutputMode::Csv,
+        };
+        let format = ResultFormat {
+            mode,
+            null: self.output.null.clone(),
+            ..ResultFormat::default()
+        };
+        std::fs::write(path, format.render(&columns, &rows))?;
+        println!("Exported {} rows from '{}' to {}", rows.len(), table, path);
+        Ok(())
+    }
+
+    fn cmd_dump(&mut self, parts: &[&str]) -> Result<()> {
+        let catalog = match self.executor.as_ref() {
+            Some(exec) => exec.catalog(),
+            None => {
+                eprintln!("No database is open. Use '.open <file>' first.");
+                return Ok(());
+            }
+        };
+
+        let statements = match parts {
+            [_] => dump(catalog, None)?,
+            [_, table] => dump(catalog, Some(table))?,
+            _ => {
+                eprintln!("Usage: .dump [table]");
+                return Ok(());
+            }
+        };
+        let mut script = String::new();
+        for statement in statements {
+            script.push_str(&statement);
+            script.push('\n');
+        }
+        self.destination.write(&script)
+    }
+
+    fn cmd_read(&mut self, parts: &[&str]) -> Result<()> {
+        let [_, path] = parts else {
+            eprintln!("Usage: .read <file>");
+            return Ok(());
+        };
+        if self.executor.is_none() {
+            eprintln!("No database is open. Use '.open <file>' first.");
+            return Ok(());
+        }
+
+        // A syntax error anywhere runs nothing; it already says where it is
+        let sql = std::fs::read_to_string(path)?;
+        let statements = Parser::from_sql(&sql)
+            .and_then(|mut parser| parser.parse_all_with_lines())
+            .map_err(|e| e.context(path))?;
+        for (line, statement) in statements {
+            if self.echo {
+                println!("{}", formatter::statement(&statement));
+            }
+            if let Err(e) = self.run_statement(statement) {
+                self.script_error(e.context(format!("{}, line {}", path, line)))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn cmd_tables(&self) -> Result<()> {
+        let catalog = match self.executor.as_ref() {
+            Some(exec) => exec.catalog(),
+            None => {
+                eprintln!("No database is open. Use '.open <file>' first.");
+                return Ok(());
+            }
+        };
+
+        let mut names = catalog.list_tables();
+        names.extend(catalog.list_views());
+        names.sort();
+        for name in names {
+            println!("{}", name);
+        }
+        Ok(())
+    }
+
+    fn cmd_schema(&self, parts: &[&str]) -> Result<()> {
+        let catalog = match self.executor.as_ref() {
+            Some(exec) => exec.catalog(),
+            None => {
+                eprintln!("No database is open. Use '.open <file>' first.");
+                return Ok(());
+            }
+        };
+
+        let statements = match parts {
+            [_] => schema(catalog, None)?,
+            [_, table] => schema(catalog, Some(table))?,
+            _ => {
+                eprintln!("Usage: .schema [table]");
+                return Ok(());
+            }
+        };
+        for statement in statements {
+            println!("{}", statement);
+        }
+        Ok(())
+    }
 
-        match res {
-            ExecutionResult::Success { message } => {
-                println!("{}", message);
+    fn cmd_pages(&self) -> Result<()> {
+        let catalog = match self.executor.as_ref() {
+            Some(exec) => exec.catalog(),
+            None => {
+                eprintln!("No database is open. Use '.open <file>' first.");
+                return Ok(());
             }
-            ExecutionResult::Rows { columns, rows } => {
-                for c in columns {
-                    print!("| {c} ");
+        };
+
+        println!("{:<6} {:<9} {:>5} {:>6}", "page", "type", "rows", "used");
+        for page_id in 0..catalog.number_of_pages() {
+            match catalog.page_type(page_id) {
+                Ok(page_type @ (PageType::Header | PageType::Catalog)) => {
+                    println!("{:<6} {}", page_id, page_type.as_str())
                 }
-                println!("|");
-
-                for row in rows {
-                    for r in row.values() {
-                        match r {
-                            Value::Integer(int) => print!("| {:?} ", int),
-                            Value::Text(s) => print!("| {:?} ", s),
-                            Value::Boolean(b) => print!("| {:?} ", b),
-                            Value::Null => print!("| {} ", "Null".to_string()),
-                        }
-                    }
-                    println!("|");
+                Ok(page_type) => {
+                    let metadata = catalog.read_page_metadata(page_id)?;
+                    println!(
+                        "{:<6} {:<9} {:>5} {:>6}",
+                        page_id,
+                        page_type.as_str(),
+                        metadata.num_rows,
+                        metadata.last_offset
+                    );
                 }
+                // Keep listing: finding the bad pages is the point
+                Err(e) => println!("{:<6} {}", page_id, e),
             }
         }
         Ok(())
     }
 
-    fn cmd_help(&self) -> io::Result<()> {
-        println!("Available commands:");
-        println!("  .help              - Show this help message");
-        println!("  .open <file>       - Open or create a database file");
-        println!("  .exit              - Exit the program");
+    fn cmd_page(&self, parts: &[&str]) -> Result<()> {
+        let catalog = match self.executor.as_ref() {
+            Some(exec) => exec.catalog(),
+            None => {
+                eprintln!("No database is open. Use '.open <file>' first.");
+                return Ok(());
+            }
+        };
+
+        let numbers: Option<Vec<usize>> = parts[1..].iter().map(|p| p.parse().ok()).collect();
+        let (page_id, offset, length) = match numbers.as_deref() {
+            Some(&[page_id]) => (page_id, 0, PAGE_SIZE),
+            Some(&[page_id, offset]) => (page_id, offset, PAGE_SIZE.saturating_sub(offset)),
+            Some(&[page_id, offset, length]) => (page_id, offset, length),
+            _ => {
+                eprintln!("Usage: .page <id> [offset] [length]");
+                return Ok(());
+            }
+        };
+        let Some(end) = offset.checked_add(length).filter(|&end| end <= PAGE_SIZE) else {
+            eprintln!("A page has {} bytes", PAGE_SIZE);
+            return Ok(());
+        };
+
+        let page_id = u32::try_from(page_id).unwrap_or(u32::MAX);
+        let page = catalog.read_page(page_id)?;
+        print!("{}", hex_dump(&page[offset..end], offset));
+        Ok(())
+    }
+
+    fn cmd_allocate(&mut self) -> Result<()> {
+        let catalog = match self.executor.as_mut() {
+            Some(exec) => exec.catalog_mut(),
+            None => {
+                eprintln!("No database is open. Use '.open <file>' first.");
+                return Ok(());
+            }
+        };
+
+        let page_id = catalog.allocate_page()?;
+        commit(catalog)?;
+        println!("Allocated page {}", page_id);
+        Ok(())
+    }
+
+    fn cmd_write(&mut self, command: &str, parts: &[&str]) -> Result<()> {
+        let catalog = match self.executor.as_mut() {
+            Some(exec) => exec.catalog_mut(),
+            None => {
+                eprintln!("No database is open. Use '.open <file>' first.");
+                return Ok(());
+            }
+        };
+
+        // The text is the rest of the line, spaces and all
+        let text = command
+            .splitn(4, char::is_whitespace)
+            .nth(3)
+            .map(str::trim_start);
+        let (page_id, offset, text) = match (parts.get(1..3), text) {
+            (Some([page_id, offset]), Some(text)) => match (page_id.parse(), offset.parse()) {
+                (Ok(page_id), Ok(offset)) => (page_id, offset, text),
+                _ => {
+                    eprintln!("Usage: .write <id> <offset> <text>");
+                    return Ok(());
+                }
+            },
+            _ => {
+                eprintln!("Usage: .write <id> <offset> <text>");
+                return Ok(());
+            }
+        };
+
+        catalog.write_at(page_id, offset, text.as_bytes())?;
+        commit(catalog)?;
+        println!(
+            "Wrote {} bytes to page {} at offset {}",
+            text.len(),
+            page_id,
+            offset
+        );
         Ok(())
     }
 
-    fn cmd_open(&mut self, parts: &[&str]) -> io::Result<()> {
-        if parts.len() != 2 {
-            eprintln!("Usage: .open <file>");
+    fn cmd_stats(&self, parts: &[&str]) -> Result<()> {
+        let catalog = match self.executor.as_ref() {
+            Some(exec) => exec.catalog(),
+            None => {
+                eprintln!("No database is open. Use '.open <file>' first.");
+                return Ok(());
+            }
+        };
+
+        match parts {
+            [_] => println!("{}", catalog.stats()),
+            [_, "reset"] => {
+                catalog.reset_stats();
+                println!("Statistics reset.");
+            }
+            _ => eprintln!("Usage: .stats [reset]"),
+        }
+        Ok(())
+    }
+
+    fn cmd_recover(&self, parts: &[&str]) -> Result<()> {
+        let [_, source, target] = parts else {
+            eprintln!("Usage: .recover <damaged> <new>");
             return Ok(());
+        };
+
+        let report = recover::recover(source, target)?;
+        if report.catalog_damaged {
+            println!("Catalog is damaged; unowned rows went into recovered_<page> tables.");
+        }
+        for page_id in &report.unreadable_pages {
+            println!("Page {} could not be read.", page_id);
         }
+        println!(
+            "Recovered {} rows into {} tables in {} ({} rows skipped).",
+            report.rows, report.tables, target, report.skipped_rows
+        );
+        Ok(())
+    }
 
-        let filename = parts[1];
+    fn cmd_backup(&mut self, parts: &[&str]) -> Result<()> {
+        let (incremental, path) = match parts {
+            [_, path] => (false, *path),
+            [_, "--incremental", path] => (true, *path),
+            _ => {
+                eprintln!("Usage: .backup [--incremental] <file>");
+                return Ok(());
+            }
+        };
 
-        // create new executor
-        let pm = PageManager::new(filename)?;
-        let catalog = TableCatalog::new(pm)?;
-        let executor = Executor::new(catalog);
-        self.executor = Some(executor);
+        let executor = match self.executor.as_ref() {
+            Some(exec) => exec,
+            None => {
+                eprintln!("No database is open. Use '.open <file>' first.");
+                return Ok(());
+            }
+        };
 
-        println!("Opened database file: {}", filename);
+        if incremental {
+            let stats = executor.catalog().backup_incremental(path)?;
+            println!(
+                "Backed up {} of {} pages to {}",
+                stats.pages_copied, stats.total_pages, path
+            );
+        } else {
+            let pages = executor.catalog().backup_to(path)?;
+            println!("Backed up {} pages to {}", pages, path);
+        }
         Ok(())
     }
 }
 
+/// The statements that create `table` and its indexes, or the view of that
+/// name; without one, those of every table, then every view
+fn schema(catalog: &TableCatalog, table: Option<&str>) -> Result<Vec<String>> {
+    script(catalog, table, false)
+}
+
+/// `schema`, with an INSERT for each row of a table after its CREATE
+/// TABLE; its indexes are made once the rows are in
+fn dump(catalog: &TableCatalog, table: Option<&str>) -> Result<Vec<String>> {
+    script(catalog, table, true)
+}
+
+fn script(catalog: &TableCatalog, table: Option<&str>, rows: bool) -> Result<Vec<String>> {
+    let (tables, views) = match table {
+        None => (catalog.list_tables(), catalog.list_views()),
+        Some(name) if catalog.table_exists(name) => (vec![name.to_string()], Vec::new()),
+        Some(name) if catalog.get_view(name).is_some() => (Vec::new(), vec![name.to_string()]),
+        Some(name) => {
+            return Err(HozonError::TableNotFound(format!(
+                "Table '{}' does not exist",
+                name
+            )));
+        }
+    };
+
+    let mut statements = Vec::new();
+    for name in tables {
+        let schema = catalog.table_info(&name)?.schema;
+        statements.push(formatter::statement(&Statement::CreateTable {
+            name: schema.table_name().to_string(),
+            columns: schema.columns().to_vec(),
+        }));
+        if rows {
+            for row in catalog.scan_rows(&name)? {
+                statements.push(formatter::statement(&Statement::Insert {
+                    table_name: schema.table_name().to_string(),
+                    columns: None,
+                    values: vec![row.values().iter().cloned().map(Expr::Literal).collect()],
+                    on_conflict: None,
+                    returning: None,
+                }));
+            }
+        }
+        for index in catalog.table_indexes(&name) {
+            statements.push(formatter::statement(&Statement::CreateIndex {
+                name: index.name().to_string(),
+                table_name: index.table_name().to_string(),
+                columns: index.columns().to_vec(),
+                unique: index.is_unique(),
+            }));
+        }
+    }
+    for name in views {
+        let query = catalog.get_view(&name).expect("listed views exist");
+        statements.push(formatter::statement(&Statement::CreateView {
+            name,
+            query: query.to_string(),
+        }));
+    }
+    Ok(statements)
+}
+
+/// Commit a page command's write, as a statement would, unless a
+/// savepoint holds it open
+fn commit(catalog: &mut TableCatalog) -> Result<()> {
+    match catalog.in_savepoint() {
+        true => Ok(()),
+        false => catalog.commit(),
+    }
+}
+
+/// `bytes` as lines of 16 hex bytes, each led by its offset in the page
+/// and followed by the bytes that are printable ASCII
+fn hex_dump(bytes: &[u8], offset: usize) -> String {
+    let mut dump = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let text: String = line
+            .iter()
+            .map(|&b| match b.is_ascii_graphic() || b == b' ' {
+                true => b as char,
+                false => '.',
+            })
+            .collect();
+        dump.push_str(&format!(
+            "{:04x}  {:<47}  |{}|\n",
+            offset + i * 16,
+            hex.join(" "),
+            text
+        ));
+    }
+    dump
+}
+
+/// `name` in the home directory, if there is one
+fn home_file(name: &str) -> Option<PathBuf> {
+    std::env::home_dir().map(|home| home.join(name))
+}
+
+/// How long a statement took and the storage I/O it did
+fn timing(elapsed: Duration, io: &IoStats) -> String {
+    format!(
+        "Run Time: {:.3}s, {} pages read, {} written",
+        elapsed.as_secs_f64(),
+        io.pages_read,
+        io.pages_written
+    )
+}
+
+fn print_result(
+    result: ExecutionResult,
+    output: &ResultFormat,
+    destination: &mut Destination,
+    quiet: bool,
+) -> Result<()> {
+    match result {
+        ExecutionResult::Rows { columns, rows } => {
+            destination.write(&output.render(&columns, &rows))?
+        }
+        _ if quiet => {}
+        result => println!("{}", result.message().expect("only rows have no message")),
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -196,6 +1092,555 @@ mod tests {
         cleanup("test_repl_existing");
     }
 
+    #[test]
+    fn test_open_in_memory() {
+        let mut repl = Repl::new();
+        repl.execute_command(".open :memory:").unwrap();
+        repl.execute_command("CREATE TABLE users (id INTEGER);")
+            .unwrap();
+        repl.execute_command("INSERT INTO users VALUES (1);")
+            .unwrap();
+
+        let result = repl.execute_command("SELECT * FROM users;");
+        assert!(result.is_ok());
+        assert!(!std::path::Path::new(":memory:").exists());
+    }
+
+    #[test]
+    fn test_backup_in_memory_database() {
+        cleanup("test_repl_backup");
+
+        let mut repl = Repl::new();
+        assert!(repl.execute_command(".backup test_repl_backup.hdb").is_ok()); // nothing open
+        assert!(!std::path::Path::new("test_repl_backup.hdb").exists());
+
+        repl.execute_command(".open :memory:").unwrap();
+        repl.execute_command("CREATE TABLE users (id INTEGER);")
+            .unwrap();
+        repl.execute_command("INSERT INTO users VALUES (1);")
+            .unwrap();
+        repl.execute_command(".backup test_repl_backup.hdb")
+            .unwrap();
+
+        let mut restored = Repl::new();
+        restored
+            .execute_command(".open test_repl_backup.hdb")
+            .unwrap();
+        assert!(restored.execute_command("SELECT * FROM users;").is_ok());
+        drop(restored);
+
+        cleanup("test_repl_backup");
+    }
+
+    #[test]
+    fn test_pages_command() {
+        let mut repl = Repl::new();
+        assert!(repl.execute_command(".pages").is_ok()); // nothing open
+
+        repl.execute_command(".open :memory:").unwrap();
+        repl.execute_command("CREATE TABLE users (id INTEGER);")
+            .unwrap();
+        assert!(repl.execute_command(".pages").is_ok());
+    }
+
+    #[test]
+    fn test_page_commands() {
+        let mut repl = Repl::new();
+        assert!(repl.execute_command(".allocate").is_ok()); // nothing open
+
+        repl.execute_command(".open :memory:").unwrap();
+        repl.execute_command(".allocate").unwrap();
+        let catalog = repl.executor.as_ref().unwrap().catalog();
+        let page_id = catalog.number_of_pages() - 1;
+        assert_eq!(catalog.page_type(page_id).unwrap(), PageType::TableData);
+
+        repl.execute_command(&format!(".write {} 100 hello  world", page_id))
+            .unwrap();
+        let page = repl
+            .executor
+            .as_ref()
+            .unwrap()
+            .catalog()
+            .read_page(page_id)
+            .unwrap();
+        assert_eq!(&page[100..112], b"hello  world");
+
+        assert!(repl.execute_command(&format!(".page {}", page_id)).is_ok());
+        assert!(
+            repl.execute_command(&format!(".page {} 96 32", page_id))
+                .is_ok()
+        );
+        assert!(repl.execute_command(".page 1 4000 200").is_ok()); // prints the page size
+        assert!(repl.execute_command(".page 999").is_err());
+        assert!(repl.execute_command(".write 999 0 x").is_err());
+    }
+
+    #[test]
+    fn test_schema() {
+        let mut repl = Repl::new();
+        assert!(repl.execute_command(".tables").is_ok()); // nothing open
+        repl.execute_command(".open :memory:").unwrap();
+        repl.execute_command(
+            "CREATE TABLE users (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL); \
+             CREATE UNIQUE INDEX idx_name ON users (name); \
+             CREATE TABLE \"order\" (n FLOAT); \
+             CREATE VIEW names AS SELECT name FROM users;",
+        )
+        .unwrap();
+        assert!(repl.execute_command(".tables").is_ok());
+        assert!(repl.execute_command(".schema").is_ok());
+
+        let catalog = repl.executor.as_ref().unwrap().catalog();
+        assert_eq!(
+            schema(catalog, None).unwrap(),
+            [
+                "CREATE TABLE \"order\" (n FLOAT);",
+                "CREATE TABLE users (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL);",
+                "CREATE UNIQUE INDEX idx_name ON users (name);",
+                "CREATE VIEW names AS SELECT name FROM users;",
+            ]
+        );
+        assert_eq!(
+            schema(catalog, Some("names")).unwrap(),
+            ["CREATE VIEW names AS SELECT name FROM users;"]
+        );
+        assert_eq!(schema(catalog, Some("users")).unwrap().len(), 2);
+        let err = schema(catalog, Some("missing")).unwrap_err();
+        assert!(matches!(err, HozonError::TableNotFound(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_open_and_close() {
+        cleanup("test_repl_reopen");
+
+        let mut repl = Repl::new();
+        repl.execute_command(".open --create test_repl_reopen.hdb")
+            .unwrap();
+        repl.execute_command("CREATE TABLE t (id INTEGER);")
+            .unwrap();
+        repl.execute_command("INSERT INTO t VALUES (1);").unwrap();
+
+        // Refused before the open database is closed
+        let err = repl
+            .execute_command(".open --create test_repl_reopen.hdb")
+            .unwrap_err();
+        assert!(matches!(err, HozonError::AlreadyExists(_)), "{:?}", err);
+        assert!(repl.executor.is_some());
+
+        // Reopening the same file works, as it's closed first
+        repl.execute_command(".open --readonly test_repl_reopen.hdb")
+            .unwrap();
+        let catalog = repl.executor.as_ref().unwrap().catalog();
+        assert!(catalog.is_read_only());
+        assert_eq!(catalog.get_table("t").unwrap().row_count(), 1);
+        let err = repl
+            .execute_command("INSERT INTO t VALUES (2);")
+            .unwrap_err();
+        assert!(matches!(err, HozonError::ReadOnly(_)), "{:?}", err);
+        assert!(repl.execute_command("SELECT * FROM t;").is_ok());
+
+        repl.execute_command(".close").unwrap();
+        assert!(repl.executor.is_none());
+        repl.execute_command(".close").unwrap();
+
+        // A failed open leaves nothing open
+        repl.execute_command(".open test_repl_reopen.hdb").unwrap();
+        assert!(
+            repl.execute_command(".open --readonly test_repl_missing.hdb")
+                .is_err()
+        );
+        assert!(repl.executor.is_none());
+
+        cleanup("test_repl_reopen");
+    }
+
+    #[test]
+    fn test_timer() {
+        let mut repl = Repl::new();
+        repl.execute_command(".timer ON").unwrap();
+        assert!(repl.timer);
+        repl.execute_command(".timer sometimes").unwrap();
+        assert!(repl.timer);
+        repl.execute_command(".timer off").unwrap();
+        assert!(!repl.timer);
+
+        let before = IoStats {
+            pages_read: 10,
+            pages_written: 2,
+            bytes_written: 8192,
+            syncs: 1,
+        };
+        let after = IoStats {
+            pages_read: 14,
+            pages_written: 3,
+            bytes_written: 12288,
+            syncs: 2,
+        };
+        assert_eq!(
+            timing(Duration::from_millis(1234), &after.since(&before)),
+            "Run Time: 1.234s, 4 pages read, 1 written"
+        );
+    }
+
+    #[test]
+    fn test_mode_and_headers() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.output, ResultFormat::default());
+
+        repl.execute_command(".mode CSV").unwrap();
+        repl.execute_command(".headers off").unwrap();
+        assert_eq!(
+            repl.output,
+            ResultFormat {
+                mode: OutputMode::Csv,
+                headers: false,
+                max_width: None,
+                null: None,
+            }
+        );
+
+        repl.execute_command(".width 20").unwrap();
+        assert_eq!(repl.output.max_width, Some(20));
+        repl.execute_command(".nullvalue  (no value)").unwrap();
+        assert_eq!(repl.output.null.as_deref(), Some("(no value)"));
+
+        // Bad settings change nothing
+        repl.execute_command(".mode html").unwrap();
+        repl.execute_command(".headers maybe").unwrap();
+        repl.execute_command(".width 0").unwrap();
+        assert_eq!(repl.output.mode, OutputMode::Csv);
+        assert!(!repl.output.headers);
+        assert_eq!(repl.output.max_width, Some(20));
+        repl.execute_command(".width off").unwrap();
+        assert_eq!(repl.output.max_width, None);
+        repl.execute_command(".nullvalue").unwrap();
+        assert_eq!(repl.output.null, None);
+        assert!(repl.execute_command(".mode").is_ok());
+    }
+
+    #[test]
+    fn test_import_command() {
+        let path = "test_repl_import.csv";
+        fs::write(path, "id,name\n1,Alice\n2,Bob\n").unwrap();
+
+        let mut repl = Repl::new();
+        assert!(repl.execute_command(".import").is_ok()); // prints usage
+        repl.execute_command(".open :memory:").unwrap();
+        repl.execute_command(&format!(".import {} users", path))
+            .unwrap();
+        let catalog = repl.executor.as_ref().unwrap().catalog();
+        assert_eq!(catalog.get_table("users").unwrap().row_count(), 2);
+
+        assert!(repl.execute_command(".import missing.csv users").is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_output_and_once() {
+        let (output, once) = ("test_repl_output.txt", "test_repl_once.txt");
+        let mut repl = Repl::new();
+        repl.execute_command(".open :memory:").unwrap();
+        repl.execute_command(".mode list").unwrap();
+        repl.execute_command("CREATE TABLE t (n INTEGER); INSERT INTO t VALUES (1), (2);")
+            .unwrap();
+
+        repl.execute_command(&format!(".output {}", output))
+            .unwrap();
+        repl.execute_command("SELECT n FROM t WHERE n = 1;")
+            .unwrap();
+        repl.execute_command(&format!(".once {}", once)).unwrap();
+        repl.execute_command("SELECT n FROM t WHERE n = 2;")
+            .unwrap();
+        // Then back to the terminal
+        assert!(matches!(repl.destination, Destination::Stdout));
+        repl.execute_command("SELECT n FROM t;").unwrap();
+
+        assert_eq!(fs::read_to_string(output).unwrap(), "n\n1\n");
+        assert_eq!(fs::read_to_string(once).unwrap(), "n\n2\n");
+        fs::remove_file(output).unwrap();
+        fs::remove_file(once).unwrap();
+    }
+
+    #[test]
+    fn test_export() {
+        let (csv, json) = ("test_repl_export.csv", "test_repl_export.json");
+        let mut repl = Repl::new();
+        repl.execute_command(".open :memory:").unwrap();
+        repl.execute_command(
+            "CREATE TABLE \"order\" (id INTEGER, note TEXT); \
+             INSERT INTO \"order\" VALUES (1, 'a, b'), (2, NULL);",
+        )
+        .unwrap();
+
+        repl.execute_command(&format!(".export order {}", csv))
+            .unwrap();
+        repl.execute_command(&format!(".export order {}", json))
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(csv).unwrap(),
+            "id,note\n1,\"a, b\"\n2,\n"
+        );
+        assert_eq!(
+            fs::read_to_string(json).unwrap(),
+            "[{\"id\": 1, \"note\": \"a, b\"},\n {\"id\": 2, \"note\": null}]\n"
+        );
+        assert!(repl.execute_command(".export missing out.csv").is_err());
+        fs::remove_file(csv).unwrap();
+        fs::remove_file(json).unwrap();
+    }
+
+    #[test]
+    fn test_dump() {
+        let mut repl = Repl::new();
+        repl.execute_command(".open :memory:").unwrap();
+        repl.execute_command(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, note TEXT, x FLOAT, b BLOB, ok BOOLEAN); \
+             CREATE INDEX idx_note ON t (note); \
+             INSERT INTO t VALUES (1, 'it''s', -2.5, X'00FF', TRUE), (2, NULL, 1e300, NULL, FALSE); \
+             CREATE VIEW v AS SELECT note FROM t WHERE id > 1;",
+        )
+        .unwrap();
+
+        let catalog = repl.executor.as_ref().unwrap().catalog();
+        let statements = dump(catalog, None).unwrap();
+        assert_eq!(
+            statements,
+            [
+                "CREATE TABLE t (id INTEGER PRIMARY KEY, note TEXT, x FLOAT, b BLOB, ok BOOLEAN);",
+                "INSERT INTO t VALUES (1, 'it''s', -2.5, X'00FF', TRUE);",
+                "INSERT INTO t VALUES (2, NULL, 1e300, NULL, FALSE);",
+                "CREATE INDEX idx_note ON t (note);",
+                "CREATE VIEW v AS SELECT note FROM t WHERE id > 1;",
+            ]
+        );
+        assert_eq!(dump(catalog, Some("v")).unwrap().len(), 1);
+
+        // The script builds the same database
+        let mut copy = Repl::new();
+        copy.execute_command(".open :memory:").unwrap();
+        copy.execute_command(&statements.join("\n")).unwrap();
+        let copied = copy.executor.as_ref().unwrap().catalog();
+        assert_eq!(dump(copied, None).unwrap(), statements);
+        assert!(repl.execute_command(".dump").is_ok());
+    }
+
+    #[test]
+    fn test_read() {
+        let path = "test_repl_read.sql";
+        fs::write(
+            path,
+            "-- Users and their notes\n\
+             CREATE TABLE users (id INTEGER PRIMARY KEY, note TEXT);\n\
+             INSERT INTO users VALUES (1, 'a; b'), /* the second */ (2, '--');\n\
+             \n\
+             INSERT INTO users\n  VALUES (1, 'taken');\n\
+             INSERT INTO users VALUES (3, 'never run');\n",
+        )
+        .unwrap();
+
+        let mut repl = Repl::new();
+        repl.execute_command(".open :memory:").unwrap();
+        repl.execute_command(".bail on").unwrap();
+        let err = repl
+            .execute_command(&format!(".read {}", path))
+            .unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+        assert!(
+            err.to_string().starts_with("test_repl_read.sql, line 5: "),
+            "{}",
+            err
+        );
+        let catalog = repl.executor.as_ref().unwrap().catalog();
+        assert_eq!(catalog.get_table("users").unwrap().row_count(), 2);
+
+        // A syntax error stops the script before anything runs
+        fs::write(path, "DELETE FROM users;\nSELECT FROM;\n").unwrap();
+        let err = repl
+            .execute_command(&format!(".read {}", path))
+            .unwrap_err();
+        assert!(err.to_string().contains("at line 2"), "{}", err);
+        let catalog = repl.executor.as_ref().unwrap().catalog();
+        assert_eq!(catalog.get_table("users").unwrap().row_count(), 2);
+
+        // Without .bail the rest runs past a failed statement
+        fs::write(
+            path,
+            "INSERT INTO users VALUES (1, 'taken');\nINSERT INTO users VALUES (3, 'runs');\n",
+        )
+        .unwrap();
+        repl.execute_command(".bail off").unwrap();
+        repl.execute_command(&format!(".read {}", path)).unwrap();
+        assert_eq!(repl.failures, 1);
+        let catalog = repl.executor.as_ref().unwrap().catalog();
+        assert_eq!(catalog.get_table("users").unwrap().row_count(), 3);
+
+        fs::remove_file(path).unwrap();
+        assert!(repl.execute_command(&format!(".read {}", path)).is_err());
+    }
+
+    #[test]
+    fn test_config_file() {
+        let path = Path::new("test_repl_config.hozondbrc");
+        let _ = fs::remove_file(path);
+        let mut repl = Repl::new();
+        // Having none is fine
+        repl.run_file(path).unwrap();
+        assert_eq!(repl.output, ResultFormat::default());
+
+        fs::write(
+            path,
+            ".mode box\n.headers off\n.timer on\n.nullvalue -\n.open :memory:\n",
+        )
+        .unwrap();
+        repl.run_file(path).unwrap();
+        assert_eq!(repl.output.mode, OutputMode::Box);
+        assert!(!repl.output.headers);
+        assert!(repl.timer);
+        assert_eq!(repl.output.null.as_deref(), Some("-"));
+        assert!(repl.executor.is_some());
+
+        fs::write(path, ".bail on\n\nSELECT * FROM missing;\n.mode csv\n").unwrap();
+        let err = repl.run_file(path).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("test_repl_config.hozondbrc, line 3: "),
+            "{}",
+            err
+        );
+        assert_eq!(repl.output.mode, OutputMode::Box);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_run_script() {
+        let mut repl = Repl::batch();
+        // SQL needs a database even without the prompt to say so
+        assert_eq!(repl.run_script("SELECT 1;").unwrap(), 1);
+
+        repl.open(":memory:").unwrap();
+        let failed = repl
+            .run_script(
+                "CREATE TABLE t (id INTEGER PRIMARY KEY,\n  name TEXT);\n\
+             .mode csv\n\
+             INSERT INTO t VALUES (1, 'a');\n\
+             INSERT INTO t VALUES (2, 'b')",
+            )
+            .unwrap();
+        assert_eq!(failed, 0);
+        assert_eq!(repl.output.mode, OutputMode::Csv);
+        let catalog = repl.executor.as_ref().unwrap().catalog();
+        assert_eq!(catalog.get_table("t").unwrap().row_count(), 2);
+
+        // A ';' ending a line in a string or comment doesn't end the statement
+        let failed = repl
+            .run_script(
+                "INSERT INTO t VALUES (10, 'one;\n  two;\n\nthree');\n\
+             /* not yet;\n\
+             .tables; */ INSERT INTO t VALUES (11, 'x');\n\
+             DELETE FROM t WHERE id = 11; -- done;",
+            )
+            .unwrap();
+        assert_eq!(failed, 0);
+        let executor = repl.executor.as_mut().unwrap();
+        let statement = Parser::from_sql("SELECT name FROM t WHERE id >= 10;")
+            .and_then(|mut parser| parser.parse())
+            .unwrap();
+        let ExecutionResult::Rows { rows, .. } = executor.execute(statement).unwrap() else {
+            panic!("SELECT returns rows");
+        };
+        let names: Vec<String> = rows.iter().map(|row| row.values()[0].to_string()).collect();
+        assert_eq!(names, ["one;\n  two;\n\nthree"]);
+        repl.execute_command("DELETE FROM t WHERE id = 10;")
+            .unwrap();
+
+        // Errors are counted and passed, unless .bail stops at the first
+        let script = "INSERT INTO t VALUES (1, 'c');\nINSERT INTO t VALUES (3, 'd');";
+        assert_eq!(repl.run_script(script).unwrap(), 1);
+        let catalog = repl.executor.as_ref().unwrap().catalog();
+        assert_eq!(catalog.get_table("t").unwrap().row_count(), 3);
+
+        let script = ".bail on\nINSERT INTO t VALUES (1, 'c');\nINSERT INTO t VALUES (4, 'e');";
+        let err = repl.run_script(script).unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+        assert!(err.to_string().starts_with("line 2: "), "{}", err);
+
+        // .exit ends the script
+        repl.run_script(".exit\nINSERT INTO t VALUES (4, 'e');")
+            .unwrap();
+        let catalog = repl.executor.as_ref().unwrap().catalog();
+        assert_eq!(catalog.get_table("t").unwrap().row_count(), 3);
+        assert!(repl.bail);
+        repl.execute_command(".echo ON").unwrap();
+        assert!(repl.echo);
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let dump = hex_dump(b"HozonDB\x00\x01 page bytes!", 32);
+        assert_eq!(
+            dump,
+            "0020  48 6f 7a 6f 6e 44 42 00 01 20 70 61 67 65 20 62  |HozonDB.. page b|\n\
+             0030  79 74 65 73 21                                   |ytes!|\n"
+        );
+    }
+
+    #[test]
+    fn test_stats_command() {
+        let mut repl = Repl::new();
+        assert!(repl.execute_command(".stats").is_ok()); // nothing open
+
+        repl.execute_command(".open :memory:").unwrap();
+        repl.execute_command("CREATE TABLE users (id INTEGER);")
+            .unwrap();
+        assert!(repl.execute_command(".stats").is_ok());
+        assert!(repl.execute_command(".stats reset").is_ok());
+
+        let stats = repl.executor.as_ref().unwrap().catalog().stats();
+        assert_eq!(stats.pages_written, 0);
+    }
+
+    #[test]
+    fn test_recover_command() {
+        cleanup("test_repl_recover");
+        cleanup("test_repl_recovered");
+
+        let mut repl = Repl::new();
+        assert!(repl.execute_command(".recover").is_ok()); // prints usage
+
+        repl.execute_command(".open test_repl_recover.hdb").unwrap();
+        repl.execute_command("CREATE TABLE users (id INTEGER);")
+            .unwrap();
+        repl.execute_command("INSERT INTO users VALUES (1);")
+            .unwrap();
+        repl.executor = None;
+
+        repl.execute_command(".recover test_repl_recover.hdb test_repl_recovered.hdb")
+            .unwrap();
+        assert!(fs::metadata("test_repl_recovered.hdb").is_ok());
+
+        cleanup("test_repl_recover");
+        cleanup("test_repl_recovered");
+    }
+
+    #[test]
+    fn test_pragma_synchronous() {
+        let mut repl = Repl::new();
+        repl.execute_command(".open :memory:").unwrap();
+
+        assert!(repl.execute_command("PRAGMA synchronous = NORMAL;").is_ok());
+        assert!(repl.execute_command("PRAGMA synchronous;").is_ok());
+        assert!(repl.execute_command("PRAGMA synchronous = maybe;").is_err());
+    }
+
     #[test]
     fn test_open_without_filename() {
         let mut repl = Repl::new();
@@ -468,7 +1913,7 @@ mod tests {
         repl.execute_command(".open test_repl_types.hdb").unwrap();
 
         repl.execute_command(
-            "CREATE TABLE test (int_col INTEGER, text_col TEXT, bool_col BOOLEAN, null_col NULL);",
+            "CREATE TABLE test (int_col INTEGER, text_col TEXT, bool_col BOOLEAN, null_col TEXT);",
         )
         .unwrap();
 
@@ -527,4 +1972,29 @@ mod tests {
 
         cleanup("test_repl_special");
     }
+
+    #[test]
+    fn test_multiple_statements_in_one_command() {
+        let mut repl = Repl::new();
+        repl.execute_command(".open :memory:").unwrap();
+
+        repl.execute_command(
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);
+             INSERT INTO users VALUES (1, 'Alice'); INSERT INTO users VALUES (2, 'Bob');",
+        )
+        .unwrap();
+
+        // The duplicate stops the script before the last insert
+        let result = repl.execute_command(
+            "INSERT INTO users VALUES (3, 'Carol'); INSERT INTO users VALUES (1, 'Dup'); \
+             INSERT INTO users VALUES (4, 'Dan');",
+        );
+        assert!(result.is_err());
+
+        let executor = repl.executor.as_ref().unwrap();
+        assert_eq!(
+            executor.catalog().get_table("users").unwrap().row_count(),
+            3
+        );
+    }
 }
@@ -0,0 +1,536 @@
+//! Serving a database over TCP, so programs elsewhere can run SQL on it.
+//! Each client gets its own `Connection`, and with it its own session: the
+//! id of its last insert is its own. Savepoints undo the whole database, so
+//! a client with one open has it to itself until it releases the last, and
+//! every other client waits; one that sits idle with a savepoint open past
+//! the server's timeout is disconnected, and its work rolled back.
+//!
+//! A server speaks HozonDB's own protocol, below, or enough of Postgres's
+//! for its clients (see `pg`).
+//!
+//! Every message is a 4-byte big-endian length and then that many bytes: a
+//! type byte and its body. Strings are a u32 length and UTF-8 bytes.
+//!
+//! The client sends
+//! - `Q` and the SQL text, one or more statements
+//!
+//! and for each the server answers with the last statement's result, or the
+//! first error, which stops the rest:
+//! - `R`, rows: a u16 column count and each column name, then a u32 row
+//!   count and each row in the tagged row encoding (`Row::to_bytes`)
+//! - `C`, done: the rows changed as a u64, a byte that's 1 if the last
+//!   statement was an INSERT and the id it inserted follows as an i64, and
+//!   the message to show
+//! - `E`, an error: its message
+
+use crate::catalog::row::Row;
+use crate::database::{self, Connection, Database};
+use crate::error::{HozonError, Result};
+use crate::sql::executor::{ExecutionResult, Executor};
+use crate::sql::parser::Statement;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::RwLockWriteGuard;
+use std::thread;
+use std::time::Duration;
+
+pub mod pg;
+
+pub const DEFAULT_PORT: u16 = 7654;
+
+/// What a server's clients speak
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    /// HozonDB's own, which `Client` speaks
+    HozonDB,
+    /// PostgreSQL's, for psql and Postgres drivers
+    Postgres,
+}
+
+/// Longest message either side will read, to stop a bad length asking for
+/// all the memory there is
+const MAX_MESSAGE: usize = 64 * 1024 * 1024;
+
+const QUERY: u8 = b'Q';
+const ROWS: u8 = b'R';
+const DONE: u8 = b'C';
+const ERROR: u8 = b'E';
+
+/// What the server answers a query with
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    Rows {
+        columns: Vec<String>,
+        rows: Vec<Row>,
+    },
+    Done {
+        changed: u64,
+        last_insert_id: Option<i64>,
+        message: String,
+    },
+    Error(String),
+}
+
+impl Response {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Response::Rows { columns, rows } => {
+                buf.push(ROWS);
+                buf.extend_from_slice(&(columns.len() as u16).to_be_bytes());
+                for column in columns {
+                    put_string(&mut buf, column);
+                }
+                buf.extend_from_slice(&(rows.len() as u32).to_be_bytes());
+                for row in rows {
+                    buf.extend_from_slice(&row.to_bytes());
+                }
+            }
+            Response::Done {
+                changed,
+                last_insert_id,
+                message,
+            } => {
+                buf.push(DONE);
+                buf.extend_from_slice(&changed.to_be_bytes());
+                match last_insert_id {
+                    Some(id) => {
+                        buf.push(1);
+                        buf.extend_from_slice(&id.to_be_bytes());
+                    }
+                    None => buf.push(0),
+                }
+                put_string(&mut buf, message);
+            }
+            Response::Error(message) => {
+                buf.push(ERROR);
+                put_string(&mut buf, message);
+            }
+        }
+        buf
+    }
+
+    fn decode(message: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes: message };
+        let response = match reader.take(1)?[0] {
+            ROWS => {
+                let columns = (0..u16::from_be_bytes(reader.array()?))
+                    .map(|_| reader.string())
+                    .collect::<Result<_>>()?;
+                let rows = (0..u32::from_be_bytes(reader.array()?))
+                    .map(|_| {
+                        let (row, consumed) = Row::from_bytes(reader.bytes)?;
+                        reader.take(consumed)?;
+                        Ok(row)
+                    })
+                    .collect::<Result<_>>()?;
+                Response::Rows { columns, rows }
+            }
+            DONE => Response::Done {
+                changed: u64::from_be_bytes(reader.array()?),
+                last_insert_id: match reader.take(1)?[0] {
+                    0 => None,
+                    _ => Some(i64::from_be_bytes(reader.array()?)),
+                },
+                message: reader.string()?,
+            },
+            ERROR => Response::Error(reader.string()?),
+            kind => return Err(protocol_error(format!("Unknown response type {}", kind))),
+        };
+        if !reader.bytes.is_empty() {
+            return Err(protocol_error("Response has bytes left over"));
+        }
+        Ok(response)
+    }
+
+    fn from_result(result: Option<ExecutionResult>, last_insert_id: Option<i64>) -> Self {
+        match result {
+            Some(ExecutionResult::Rows { columns, rows }) => Response::Rows { columns, rows },
+            result => Response::Done {
+                changed: result
+                    .as_ref()
+                    .and_then(|result| result.affected_rows())
+                    .unwrap_or(0) as u64,
+                last_insert_id,
+                message: result
+                    .and_then(|result| result.message())
+                    .unwrap_or_default(),
+            },
+        }
+    }
+}
+
+fn protocol_error(message: impl Into<String>) -> HozonError {
+    HozonError::InvalidInput(message.into())
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u32).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < n {
+            return Err(protocol_error("Message ends too soon"));
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = u32::from_be_bytes(self.array()?) as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| protocol_error("A string isn't valid UTF-8"))
+    }
+}
+
+/// The next message's type and body, or None if the other side has gone
+fn read_message(reader: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len == 0 || len > MAX_MESSAGE {
+        return Err(protocol_error(format!(
+            "A message of {} bytes is out of bounds",
+            len
+        )));
+    }
+    let mut message = vec![0u8; len];
+    reader.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+fn write_message(writer: &mut impl Write, message: &[u8]) -> Result<()> {
+    writer.write_all(&(message.len() as u32).to_be_bytes())?;
+    writer.write_all(message)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub struct Server {
+    listener: TcpListener,
+    database: Database,
+    protocol: Protocol,
+    idle_timeout: Option<Duration>,
+}
+
+impl Server {
+    pub fn bind(database: Database, addr: impl ToSocketAddrs, protocol: Protocol) -> Result<Self> {
+        Ok(Server {
+            listener: TcpListener::bind(addr)?,
+            database,
+            protocol,
+            idle_timeout: Some(pg::IDLE_IN_TRANSACTION_TIMEOUT),
+        })
+    }
+
+    /// Disconnect a client that sends nothing for `timeout` while it keeps
+    /// the others out, in a transaction or with a savepoint open, rolling
+    /// its work back; None to wait for it however long. A zero timeout is
+    /// taken as None.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout.filter(|timeout| !timeout.is_zero());
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Take clients until accepting one fails, each on its own thread
+    pub fn serve(&self) -> Result<()> {
+        loop {
+            let (stream, peer) = self.listener.accept()?;
+            let conn = self.database.connect();
+            let protocol = self.protocol;
+            let idle_timeout = self.idle_timeout;
+            thread::spawn(move || {
+                let result = match protocol {
+                    Protocol::HozonDB => session(stream, conn, idle_timeout),
+                    Protocol::Postgres => pg::session(stream, conn, idle_timeout),
+                };
+                if let Err(e) = result {
+                    eprintln!("{}: {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+/// The executor, kept by a client from its first SAVEPOINT until it
+/// releases the last
+struct Held<'a> {
+    executor: RwLockWriteGuard<'a, Executor>,
+    /// The client's first savepoint, to roll back to if it goes
+    outermost: String,
+}
+
+/// Answer one client's queries until it disconnects, or sits idle for
+/// `idle_timeout` with a savepoint open
+fn session(stream: TcpStream, conn: Connection, idle_timeout: Option<Duration>) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    let mut held = None;
+    let result = loop {
+        stream.set_read_timeout(held.as_ref().and(idle_timeout))?;
+        let message = match read_message(&mut reader) {
+            Ok(Some(message)) => message,
+            Ok(None) => break Ok(()),
+            Err(HozonError::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                let response = Response::Error(
+                    "Disconnected: idle with a savepoint open for too long".to_string(),
+                );
+                break write_message(&mut writer, &response.encode());
+            }
+            Err(e) => break Err(e),
+        };
+        let response = match message.split_first() {
+            Some((&QUERY, sql)) => match std::str::from_utf8(sql) {
+                Ok(sql) => run(&conn, &mut held, sql),
+                Err(_) => Response::Error("The SQL isn't valid UTF-8".to_string()),
+            },
+            _ => Response::Error(format!("Unknown request type {}", message[0])),
+        };
+        if let Err(e) = write_message(&mut writer, &response.encode()) {
+            break Err(e);
+        }
+    };
+    // Savepoints left open go with the client
+    if let Some(Held {
+        mut executor,
+        outermost,
+    }) = held
+    {
+        let _ = executor.execute(Statement::RollbackTo {
+            name: outermost.clone(),
+        });
+        let _ = executor.execute(Statement::Release { name: outermost });
+    }
+    result
+}
+
+fn run<'a>(conn: &'a Connection, held: &mut Option<Held<'a>>, sql: &str) -> Response {
+    let mut run = || -> Result<Option<(ExecutionResult, Option<i64>)>> {
+        let mut last = None;
+        for statement in database::parse(sql)? {
+            last = Some(execute(conn, held, statement)?);
+        }
+        Ok(last)
+    };
+    match run() {
+        Ok(Some((result, inserted_id))) => Response::from_result(Some(result), inserted_id),
+        Ok(None) => Response::from_result(None, None),
+        Err(e) => Response::Error(e.to_string()),
+    }
+}
+
+/// Run `statement` for a client, with the id it inserted if it's an INSERT,
+/// on the executor the client holds if it has a savepoint open
+fn execute<'a>(
+    conn: &'a Connection,
+    held: &mut Option<Held<'a>>,
+    statement: Statement,
+) -> Result<(ExecutionResult, Option<i64>)> {
+    if held.is_none() {
+        // Waits for any other client's savepoints to go
+        let Statement::Savepoint { name } = &statement else {
+            return conn.execute_inserting(statement);
+        };
+        *held = Some(Held {
+            outermost: name.clone(),
+            executor: conn.executor(),
+        });
+    }
+    let executor = &mut held.as_mut().expect("held above").executor;
+    let result = conn.execute_inserting_on(executor, statement);
+    if !executor.catalog().in_savepoint() {
+        *held = None;
+    }
+    result
+}
+
+/// A connection to a `Server`
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl Client {
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        Ok(Client {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        })
+    }
+
+    /// Run `sql` on the server. An error running it comes back as
+    /// `Response::Error`; Err is for the connection failing.
+    pub fn query(&mut self, sql: &str) -> Result<Response> {
+        let mut message = vec![QUERY];
+        message.extend_from_slice(sql.as_bytes());
+        write_message(&mut self.writer, &message)?;
+        let message = read_message(&mut self.reader)?
+            .ok_or_else(|| protocol_error("The server closed the connection"))?;
+        Response::decode(&message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::row::Value;
+
+    #[test]
+    fn test_response_encoding() {
+        for response in [
+            Response::Rows {
+                columns: vec!["id".to_string(), "name".to_string()],
+                rows: vec![
+                    Row::new(vec![Value::Integer(1), Value::Text("a".to_string())]),
+                    Row::new(vec![Value::BigInt(-2), Value::Null]),
+                ],
+            },
+            Response::Done {
+                changed: 3,
+                last_insert_id: Some(-1),
+                message: "3 rows inserted.".to_string(),
+            },
+            Response::Done {
+                changed: 0,
+                last_insert_id: None,
+                message: String::new(),
+            },
+            Response::Error("no".to_string()),
+        ] {
+            assert_eq!(Response::decode(&response.encode()).unwrap(), response);
+        }
+        let encoded = Response::Error("no".to_string()).encode();
+        let err = Response::decode(&encoded[..encoded.len() - 1]).unwrap_err();
+        assert!(matches!(err, HozonError::InvalidInput(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_serve() {
+        let server = Server::bind(
+            Database::open_in_memory().unwrap(),
+            "127.0.0.1:0",
+            Protocol::HozonDB,
+        )
+        .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut alice = Client::connect(addr).unwrap();
+        let mut bob = Client::connect(addr).unwrap();
+        alice
+            .query("CREATE TABLE t (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT);")
+            .unwrap();
+        assert_eq!(
+            alice
+                .query("INSERT INTO t (name) VALUES ('a'); INSERT INTO t (name) VALUES ('b')")
+                .unwrap(),
+            Response::Done {
+                changed: 1,
+                last_insert_id: Some(2),
+                message: "1 row inserted.".to_string(),
+            }
+        );
+        // Each client has its own last insert
+        let Response::Done { last_insert_id, .. } = bob.query("CHECKPOINT;").unwrap() else {
+            panic!("not done");
+        };
+        assert_eq!(last_insert_id, None);
+        // Only an INSERT answers with the id it inserted
+        let Response::Done { last_insert_id, .. } = alice.query("CHECKPOINT;").unwrap() else {
+            panic!("not done");
+        };
+        assert_eq!(last_insert_id, None);
+
+        assert_eq!(
+            bob.query("SELECT name FROM t WHERE id = 2;").unwrap(),
+            Response::Rows {
+                columns: vec!["name".to_string()],
+                rows: vec![Row::new(vec![Value::Text("b".to_string())])],
+            }
+        );
+        let Response::Error(message) = bob
+            .query("INSERT INTO t (name) VALUES ('c'); SELECT * FROM missing;")
+            .unwrap()
+        else {
+            panic!("no error");
+        };
+        assert!(message.contains("missing"), "{}", message);
+        // The session carries on after an error
+        let Response::Rows { rows, .. } = alice.query("SELECT * FROM t;").unwrap() else {
+            panic!("no rows");
+        };
+        assert_eq!(rows.len(), 3);
+    }
+
+    #[test]
+    fn test_savepoints_keep_others_out() {
+        let mut server = Server::bind(
+            Database::open_in_memory().unwrap(),
+            "127.0.0.1:0",
+            Protocol::HozonDB,
+        )
+        .unwrap();
+        server.set_idle_timeout(Some(Duration::from_millis(500)));
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut alice = Client::connect(addr).unwrap();
+        alice.query("CREATE TABLE t (name TEXT);").unwrap();
+        alice
+            .query("SAVEPOINT a; INSERT INTO t VALUES ('alice');")
+            .unwrap();
+
+        // Bob waits for Alice's savepoint, so her ROLLBACK TO can't undo
+        // his insert
+        let (sent, done) = std::sync::mpsc::channel();
+        let bob = thread::spawn(move || {
+            let mut bob = Client::connect(addr).unwrap();
+            let response = bob.query("INSERT INTO t VALUES ('bob');").unwrap();
+            sent.send(()).unwrap();
+            (bob, response)
+        });
+        thread::sleep(Duration::from_millis(100));
+        assert!(done.try_recv().is_err());
+        alice.query("SAVEPOINT b; ROLLBACK TO a;").unwrap();
+        assert!(done.try_recv().is_err());
+        alice.query("RELEASE a;").unwrap();
+        let (mut bob, response) = bob.join().unwrap();
+        assert!(matches!(response, Response::Done { .. }), "{:?}", response);
+        let Response::Rows { rows, .. } = alice.query("SELECT name FROM t;").unwrap() else {
+            panic!("no rows");
+        };
+        assert_eq!(rows, [Row::new(vec![Value::Text("bob".to_string())])]);
+
+        // One idle with a savepoint open is dropped, its work undone
+        bob.query("SAVEPOINT c; DELETE FROM t;").unwrap();
+        let Response::Rows { rows, .. } = alice.query("SELECT name FROM t;").unwrap() else {
+            panic!("no rows");
+        };
+        assert_eq!(rows.len(), 1);
+        let Response::Error(message) = bob.query("RELEASE c;").unwrap() else {
+            panic!("not dropped");
+        };
+        assert!(message.contains("idle"), "{}", message);
+    }
+}
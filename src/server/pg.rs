@@ -0,0 +1,981 @@
+//! Enough of PostgreSQL's frontend/backend protocol (version 3) for psql
+//! and Postgres drivers to connect and run HozonDB's SQL: the startup
+//! handshake, with no password and no TLS, and simple queries, answered
+//! with row descriptions, data rows in text format, command tags and
+//! errors. The extended query protocol (prepared statements, as JDBC uses)
+//! isn't supported.
+//!
+//! Drivers wrap work in transactions, so BEGIN, COMMIT and ROLLBACK are
+//! taken here, wherever they are in a query, and run as a savepoint of the
+//! session's own. Savepoints undo the whole database's pages, so a session
+//! holds the executor's write lock from BEGIN to its end, and every other
+//! client waits for it to finish; one that sits idle in a transaction past
+//! the server's timeout is disconnected, and its work rolled back. After an
+//! error in a transaction, as in Postgres, only ROLLBACK (or ROLLBACK TO a
+//! savepoint) is run until it ends. `SET` is accepted and ignored.
+//!
+//! A CancelRequest with the key a session was given cancels the statement
+//! it's running.
+
+use crate::catalog::format::ValueFormat;
+use crate::catalog::row::{Row, Value};
+use crate::database::{self, Connection};
+use crate::error::{HozonError, Result};
+use crate::sql::executor::{ExecutionResult, Executor, StatementKind};
+use crate::sql::interrupt::{self, CancelToken};
+use crate::sql::parser::Statement;
+use crate::sql::tokenizer::{self, Token};
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, RandomState};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, RwLockWriteGuard};
+use std::time::Duration;
+
+pub const DEFAULT_PORT: u16 = 5432;
+
+const PROTOCOL_VERSION: i32 = 3 << 16;
+const SSL_REQUEST: i32 = 80877103;
+const GSSENC_REQUEST: i32 = 80877104;
+const CANCEL_REQUEST: i32 = 80877102;
+
+/// The version psql and drivers are told, the oldest whose protocol this
+/// speaks all of that they'd expect
+const SERVER_VERSION: &str = "14.0";
+
+/// Longest message a client may send
+const MAX_MESSAGE: usize = 64 * 1024 * 1024;
+
+/// How long a session may sit in a transaction, keeping every other client
+/// out, before it's disconnected, unless the server says otherwise
+pub const IDLE_IN_TRANSACTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Sessions are numbered for their BackendKeyData and savepoint names
+static SESSIONS: AtomicU32 = AtomicU32::new(1);
+
+// The secret key and cancel token of each session, by number, for a
+// CancelRequest to find
+static CANCEL_KEYS: Mutex<BTreeMap<u32, (i32, CancelToken)>> = Mutex::new(BTreeMap::new());
+
+/// Type OIDs, from Postgres's pg_type
+const BOOL: i32 = 16;
+const BYTEA: i32 = 17;
+const INT8: i32 = 20;
+const INT4: i32 = 23;
+const TEXT: i32 = 25;
+const FLOAT8: i32 = 701;
+
+/// A message to the client: its type byte and body
+struct Message {
+    kind: u8,
+    body: Vec<u8>,
+}
+
+impl Message {
+    fn new(kind: u8) -> Self {
+        Message {
+            kind,
+            body: Vec::new(),
+        }
+    }
+
+    fn i16(mut self, n: i16) -> Self {
+        self.body.extend_from_slice(&n.to_be_bytes());
+        self
+    }
+
+    fn i32(mut self, n: i32) -> Self {
+        self.body.extend_from_slice(&n.to_be_bytes());
+        self
+    }
+
+    fn byte(mut self, b: u8) -> Self {
+        self.body.push(b);
+        self
+    }
+
+    /// A NUL-terminated string
+    fn string(mut self, s: &str) -> Self {
+        self.body.extend_from_slice(s.as_bytes());
+        self.body.push(0);
+        self
+    }
+
+    fn send(self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(&[self.kind])?;
+        writer.write_all(&(self.body.len() as i32 + 4).to_be_bytes())?;
+        writer.write_all(&self.body)?;
+        Ok(())
+    }
+}
+
+/// The body of the next message, whose length counts itself
+fn read_body(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let len = i32::from_be_bytes(len);
+    if !(4..=MAX_MESSAGE as i32).contains(&len) {
+        return Err(HozonError::InvalidInput(format!(
+            "A message of {} bytes is out of bounds",
+            len
+        )));
+    }
+    let mut body = vec![0u8; len as usize - 4];
+    reader.read_exact(&mut body)?;
+    Ok(body)
+}
+
+struct Session<'a> {
+    conn: &'a Connection,
+    id: u32,
+    secret: i32,
+    /// For the read timeout while in a transaction
+    socket: TcpStream,
+    idle_timeout: Option<Duration>,
+    /// Inside BEGIN: the executor, kept from other clients until COMMIT or
+    /// ROLLBACK, with the session's work under its savepoint
+    transaction: Option<RwLockWriteGuard<'a, Executor>>,
+    /// Whether a statement in the transaction failed
+    failed: bool,
+}
+
+/// What a query asks for: a statement, or a transaction command or SET
+/// that's run here
+#[derive(Debug, PartialEq)]
+enum Command {
+    Statement(Statement),
+    Transaction(&'static str),
+}
+
+impl Command {
+    /// Whether it runs in a failed transaction, to end it or undo the failure
+    fn recovers(&self) -> bool {
+        matches!(
+            self,
+            Command::Transaction("rollback" | "abort" | "commit" | "end")
+                | Command::Statement(Statement::RollbackTo { .. })
+        )
+    }
+}
+
+/// Talk to one Postgres client until it leaves; one in a transaction is
+/// dropped once it has sent nothing for `idle_timeout`
+pub(crate) fn session(
+    stream: TcpStream,
+    conn: Connection,
+    idle_timeout: Option<Duration>,
+) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    let id = SESSIONS.fetch_add(1, Ordering::Relaxed);
+    let mut session = Session {
+        conn: &conn,
+        id,
+        secret: RandomState::new().hash_one(id) as i32,
+        socket: stream,
+        idle_timeout,
+        transaction: None,
+        failed: false,
+    };
+    // The session's statements are cancelled through its own token, as
+    // they run on this thread
+    let token = CancelToken::new();
+    interrupt::set_thread_token(Some(token.clone()));
+    CANCEL_KEYS
+        .lock()
+        .unwrap()
+        .insert(id, (session.secret, token));
+
+    let result = match session.start(&mut reader, &mut writer) {
+        Ok(true) => session.serve(&mut reader, &mut writer),
+        result => result.map(|_| ()),
+    };
+    // A transaction left open goes with the client
+    if session.transaction.is_some() {
+        let _ = session.rollback();
+    }
+    CANCEL_KEYS.lock().unwrap().remove(&id);
+    interrupt::set_thread_token(None);
+    result
+}
+
+/// Cancel what the session `id` is running, if `secret` is its key
+fn cancel(id: u32, secret: i32) {
+    if let Some((key, token)) = CANCEL_KEYS.lock().unwrap().get(&id)
+        && *key == secret
+    {
+        token.cancel();
+    }
+}
+
+impl Session<'_> {
+    fn savepoint(&self) -> String {
+        format!("hozon_pg_{}", self.id)
+    }
+
+    /// The startup handshake; false if the client went away instead
+    fn start(&self, reader: &mut impl Read, writer: &mut impl Write) -> Result<bool> {
+        loop {
+            let body = read_body(reader)?;
+            let code = i32::from_be_bytes(
+                body.get(..4)
+                    .and_then(|code| code.try_into().ok())
+                    .ok_or_else(|| HozonError::InvalidInput("Startup message too short".into()))?,
+            );
+            match code {
+                // No TLS or GSSAPI here; the client carries on in the clear
+                SSL_REQUEST | GSSENC_REQUEST => {
+                    writer.write_all(b"N")?;
+                    writer.flush()?;
+                }
+                // Answered with nothing, and the connection closed
+                CANCEL_REQUEST => {
+                    let key = |at: usize| {
+                        body.get(at..at + 4)
+                            .and_then(|n| n.try_into().ok())
+                            .map(i32::from_be_bytes)
+                    };
+                    if let (Some(id), Some(secret)) = (key(4), key(8)) {
+                        cancel(id as u32, secret);
+                    }
+                    return Ok(false);
+                }
+                PROTOCOL_VERSION => break,
+                version => {
+                    error(&HozonError::Unsupported(format!(
+                        "Protocol version {}.{} isn't supported; only 3.0 is",
+                        version >> 16,
+                        version & 0xffff
+                    )))
+                    .send(writer)?;
+                    writer.flush()?;
+                    return Ok(false);
+                }
+            }
+        }
+        // The user and database asked for in the rest don't matter: there's
+        // no login, and one database
+        Message::new(b'R').i32(0).send(writer)?;
+        for (name, value) in [
+            ("server_version", SERVER_VERSION),
+            ("server_encoding", "UTF8"),
+            ("client_encoding", "UTF8"),
+            ("DateStyle", "ISO, MDY"),
+            ("integer_datetimes", "on"),
+            ("standard_conforming_strings", "on"),
+        ] {
+            Message::new(b'S').string(name).string(value).send(writer)?;
+        }
+        Message::new(b'K')
+            .i32(self.id as i32)
+            .i32(self.secret)
+            .send(writer)?;
+        self.ready(writer)?;
+        Ok(true)
+    }
+
+    fn ready(&self, writer: &mut impl Write) -> Result<()> {
+        let status = match (&self.transaction, self.failed) {
+            (None, _) => b'I',
+            (Some(_), false) => b'T',
+            (Some(_), true) => b'E',
+        };
+        Message::new(b'Z').byte(status).send(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn serve(&mut self, reader: &mut impl Read, writer: &mut impl Write) -> Result<()> {
+        // After an error in the extended protocol, everything up to the
+        // next Sync is skipped
+        let mut skipping = false;
+        loop {
+            let timeout = self.transaction.as_ref().and(self.idle_timeout);
+            self.socket.set_read_timeout(timeout)?;
+            let mut kind = [0u8; 1];
+            match reader.read_exact(&mut kind) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+                // Going, the session rolls its transaction back
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    response(
+                        "FATAL",
+                        "25P03",
+                        "Terminating the connection: idle in a transaction for too long",
+                    )
+                    .send(writer)?;
+                    writer.flush()?;
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            }
+            let body = read_body(reader)?;
+            match kind[0] {
+                b'Q' => {
+                    let sql = body.strip_suffix(&[0]).unwrap_or(&body);
+                    match std::str::from_utf8(sql) {
+                        Ok(sql) => self.query(sql, writer)?,
+                        Err(_) => error(&HozonError::InvalidInput(
+                            "The query isn't valid UTF-8".to_string(),
+                        ))
+                        .send(writer)?,
+                    }
+                    self.ready(writer)?;
+                }
+                b'X' => return Ok(()),
+                b'S' => {
+                    skipping = false;
+                    self.ready(writer)?;
+                }
+                b'H' => writer.flush()?,
+                _ if skipping => {}
+                _ => {
+                    error(&HozonError::Unsupported(
+                        "Only simple queries are supported, not the extended query protocol"
+                            .to_string(),
+                    ))
+                    .send(writer)?;
+                    skipping = true;
+                }
+            }
+        }
+    }
+
+    /// Run the commands of a simple query, answering each, until one
+    /// fails. If any of it doesn't parse, none of it runs.
+    fn query(&mut self, sql: &str, writer: &mut impl Write) -> Result<()> {
+        let commands = match commands(sql) {
+            Ok(commands) => commands,
+            Err(e) => return self.fail(&e, writer),
+        };
+        if commands.is_empty() {
+            return Message::new(b'I').send(writer);
+        }
+        for command in commands {
+            if self.failed && !command.recovers() {
+                return response(
+                    "ERROR",
+                    "25P02",
+                    "The transaction failed; commands are ignored until it's rolled back",
+                )
+                .send(writer);
+            }
+            let result = match command {
+                Command::Transaction(command) => self
+                    .transaction_command(command)
+                    .map(|tag| Message::new(b'C').string(tag).send(writer)),
+                Command::Statement(statement) => {
+                    let tag = tag(&statement);
+                    let rollback_to = matches!(statement, Statement::RollbackTo { .. });
+                    self.execute(statement).map(|result| {
+                        // Back at a savepoint, the transaction is sound again
+                        self.failed &= !rollback_to;
+                        send_result(result, tag, writer)
+                    })
+                }
+            };
+            match result {
+                Ok(sent) => sent?,
+                Err(e) => return self.fail(&e, writer),
+            }
+        }
+        Ok(())
+    }
+
+    /// Answer with `e`, which fails a transaction the session is in
+    fn fail(&mut self, e: &HozonError, writer: &mut impl Write) -> Result<()> {
+        self.failed = self.transaction.is_some();
+        error(e).send(writer)
+    }
+
+    /// Run `statement` in the session's transaction, or on its own
+    fn execute(&mut self, statement: Statement) -> Result<ExecutionResult> {
+        match &mut self.transaction {
+            Some(executor) => executor.execute(statement),
+            None => self.conn.execute_statement(statement),
+        }
+    }
+
+    fn transaction_command(&mut self, command: &str) -> Result<&'static str> {
+        match command {
+            "begin" | "start" => {
+                if self.transaction.is_none() {
+                    // Waits for any other session's transaction to end
+                    let mut executor = self.conn.executor();
+                    executor.execute(Statement::Savepoint {
+                        name: self.savepoint(),
+                    })?;
+                    self.transaction = Some(executor);
+                }
+                Ok("BEGIN")
+            }
+            // Ending a failed transaction can only roll it back
+            "commit" | "end" if self.failed => {
+                self.rollback()?;
+                Ok("ROLLBACK")
+            }
+            "commit" | "end" => {
+                let name = self.savepoint();
+                if let Some(executor) = &mut self.transaction {
+                    executor.execute(Statement::Release { name })?;
+                    self.transaction = None;
+                }
+                Ok("COMMIT")
+            }
+            "rollback" | "abort" => {
+                if self.transaction.is_some() {
+                    self.rollback()?;
+                }
+                Ok("ROLLBACK")
+            }
+            _ => Ok("SET"),
+        }
+    }
+
+    /// Undo the open transaction and let other clients in again
+    fn rollback(&mut self) -> Result<()> {
+        let name = self.savepoint();
+        self.failed = false;
+        if let Some(mut executor) = self.transaction.take() {
+            executor.execute(Statement::RollbackTo { name: name.clone() })?;
+            executor.execute(Statement::Release { name })?;
+        }
+        Ok(())
+    }
+}
+
+/// The commands of a simple query, in order
+fn commands(sql: &str) -> Result<Vec<Command>> {
+    // Cut at each ';' the tokenizer finds, so not those in strings
+    let (tokens, offsets) = tokenizer::tokenize_with_offsets(sql)?;
+    let mut commands = Vec::new();
+    let mut start = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        if !matches!(token, Token::Semicolon | Token::Eof) {
+            continue;
+        }
+        if i > start {
+            let text = &sql[offsets[start]..offsets[i]];
+            match transaction_command(text) {
+                Some(command) => commands.push(Command::Transaction(command)),
+                None => commands.extend(database::parse(text)?.into_iter().map(Command::Statement)),
+            }
+        }
+        start = i + 1;
+    }
+    Ok(commands)
+}
+
+/// The lowercased command word if `sql` is a statement this layer runs
+/// itself: a transaction command or a SET. ROLLBACK TO is a real one.
+fn transaction_command(sql: &str) -> Option<&'static str> {
+    let sql = sql.trim().trim_end_matches(';').trim_end().to_lowercase();
+    let mut words = sql.split_whitespace();
+    match words.next()? {
+        "begin" => Some("begin"),
+        "start" => Some("start"),
+        "commit" => Some("commit"),
+        "end" => Some("end"),
+        "abort" => Some("abort"),
+        "rollback" if words.next() != Some("to") => Some("rollback"),
+        "set" => Some("set"),
+        _ => None,
+    }
+}
+
+/// The command tag for `statement`, if it's not one the result gives
+fn tag(statement: &Statement) -> &'static str {
+    match statement {
+        Statement::CreateTable { .. } => "CREATE TABLE",
+        Statement::CreateIndex { .. } => "CREATE INDEX",
+        Statement::CreateView { .. } => "CREATE VIEW",
+        Statement::DropIndex { .. } => "DROP INDEX",
+        Statement::Pragma { .. } => "PRAGMA",
+        Statement::Checkpoint => "CHECKPOINT",
+        Statement::Analyze { .. } => "ANALYZE",
+        Statement::Savepoint { .. } => "SAVEPOINT",
+        Statement::Release { .. } => "RELEASE",
+        Statement::RollbackTo { .. } => "ROLLBACK",
+        Statement::Attach { .. } => "ATTACH",
+        Statement::Detach { .. } => "DETACH",
+        Statement::Explain(_) => "EXPLAIN",
+        Statement::Select { .. } => "SELECT",
+        Statement::Insert { .. } => "INSERT",
+        Statement::Update { .. } => "UPDATE",
+        Statement::Delete { .. } => "DELETE",
+    }
+}
+
+fn send_result(result: ExecutionResult, tag: &str, writer: &mut impl Write) -> Result<()> {
+    match result {
+        ExecutionResult::Rows { columns, rows } => {
+            let mut description = Message::new(b'T').i16(columns.len() as i16);
+            for (i, name) in columns.iter().enumerate() {
+                let (oid, len) = column_type(&rows, i);
+                description = description
+                    .string(name)
+                    .i32(0)
+                    .i16(0)
+                    .i32(oid)
+                    .i16(len)
+                    .i32(-1)
+                    .i16(0);
+            }
+            description.send(writer)?;
+            for row in &rows {
+                data_row(row).send(writer)?;
+            }
+            // A write with RETURNING is tagged as the write
+            let tag = match tag {
+                "INSERT" => format!("INSERT 0 {}", rows.len()),
+                "SELECT" | "UPDATE" | "DELETE" => format!("{} {}", tag, rows.len()),
+                tag => tag.to_string(),
+            };
+            Message::new(b'C').string(&tag).send(writer)
+        }
+        ExecutionResult::Affected { kind, rows } => {
+            let tag = match kind {
+                StatementKind::Insert | StatementKind::Replace => format!("INSERT 0 {}", rows),
+                StatementKind::Update => format!("UPDATE {}", rows),
+                StatementKind::Delete => format!("DELETE {}", rows),
+            };
+            Message::new(b'C').string(&tag).send(writer)
+        }
+        ExecutionResult::Success { .. } => Message::new(b'C').string(tag).send(writer),
+    }
+}
+
+/// The type OID and size of column `i`, going by its first value that
+/// isn't NULL; results don't carry their columns' types
+fn column_type(rows: &[Row], i: usize) -> (i32, i16) {
+    let value = rows
+        .iter()
+        .filter_map(|row| row.get_value(i))
+        .find(|value| **value != Value::Null);
+    match value {
+        Some(Value::Integer(_)) => (INT4, 4),
+        Some(Value::BigInt(_)) => (INT8, 8),
+        Some(Value::Float(_)) => (FLOAT8, 8),
+        Some(Value::Boolean(_)) => (BOOL, 1),
+        Some(Value::Blob(_)) => (BYTEA, -1),
+        Some(Value::Text(_)) | Some(Value::Null) | None => (TEXT, -1),
+    }
+}
+
+fn data_row(row: &Row) -> Message {
+    let mut message = Message::new(b'D').i16(row.values().len() as i16);
+    for value in row.values() {
+        let text = match value {
+            Value::Null => {
+                message = message.i32(-1);
+                continue;
+            }
+            Value::Boolean(b) => if *b { "t" } else { "f" }.to_string(),
+            Value::Blob(bytes) => {
+                let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("\\x{}", hex)
+            }
+            Value::Float(n) if n.is_nan() => "NaN".to_string(),
+            Value::Float(n) if n.is_infinite() => {
+                if *n > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+            }
+            value => value.display_with(&ValueFormat::default()).to_string(),
+        };
+        message = message.i32(text.len() as i32);
+        message.body.extend_from_slice(text.as_bytes());
+    }
+    message
+}
+
+/// An ErrorResponse for `e`, with the SQLSTATE Postgres would give
+fn error(e: &HozonError) -> Message {
+    let code = match e {
+        HozonError::Parse(_) => "42601",
+        HozonError::TableNotFound(_) => "42P01",
+        HozonError::ColumnNotFound(_) => "42703",
+        HozonError::NotFound(_) => "42704",
+        HozonError::AlreadyExists(_) => "42P07",
+        HozonError::TypeMismatch(_) => "42804",
+        HozonError::ConstraintViolation(_) => "23000",
+        HozonError::InvalidStatement(_) => "42000",
+        HozonError::InvalidInput(_) => "22023",
+        HozonError::Unsupported(_) => "0A000",
+        HozonError::ReadOnly(_) => "25006",
+        HozonError::Locked(_) => "55P03",
+        HozonError::Interrupted(_) => "57014",
+        HozonError::Full(_) => "53100",
+        HozonError::Corruption(_) => "XX001",
+        HozonError::Io(_) => "58030",
+        HozonError::Encryption(_) => "XX000",
+    };
+    response("ERROR", code, &e.to_string())
+}
+
+/// An ErrorResponse of `severity`, ERROR or FATAL
+fn response(severity: &str, code: &str, message: &str) -> Message {
+    Message::new(b'E')
+        .byte(b'S')
+        .string(severity)
+        .byte(b'V')
+        .string(severity)
+        .byte(b'C')
+        .string(code)
+        .byte(b'M')
+        .string(message)
+        .byte(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use crate::server::{Protocol, Server};
+    use std::thread;
+
+    /// A bare-bones Postgres client
+    struct Client {
+        stream: TcpStream,
+        /// Its BackendKeyData: the session's number and secret
+        key: Vec<u8>,
+    }
+
+    impl Client {
+        fn connect(addr: std::net::SocketAddr) -> Self {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            // Ask for TLS first, as psql does, and be told no
+            stream.write_all(&[0, 0, 0, 8, 4, 210, 22, 47]).unwrap();
+            let mut answer = [0u8; 1];
+            stream.read_exact(&mut answer).unwrap();
+            assert_eq!(&answer, b"N");
+
+            let mut body = PROTOCOL_VERSION.to_be_bytes().to_vec();
+            body.extend_from_slice(b"user\0alice\0\0");
+            stream
+                .write_all(&(body.len() as i32 + 4).to_be_bytes())
+                .unwrap();
+            stream.write_all(&body).unwrap();
+            let mut client = Client {
+                stream,
+                key: Vec::new(),
+            };
+            let messages = client.until_ready();
+            assert_eq!(kinds(&messages), "RSSSSSSKZ");
+            client.key = messages[7].1.clone();
+            client
+        }
+
+        /// Ask a new connection to cancel what `key`'s session is running
+        fn cancel(addr: std::net::SocketAddr, key: &[u8]) {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let mut body = CANCEL_REQUEST.to_be_bytes().to_vec();
+            body.extend_from_slice(key);
+            stream
+                .write_all(&(body.len() as i32 + 4).to_be_bytes())
+                .unwrap();
+            stream.write_all(&body).unwrap();
+            // Answered by the server hanging up
+            assert_eq!(stream.read(&mut [0u8; 1]).unwrap(), 0);
+        }
+
+        fn until_ready(&mut self) -> Vec<(u8, Vec<u8>)> {
+            let mut messages = Vec::new();
+            loop {
+                let mut kind = [0u8; 1];
+                self.stream.read_exact(&mut kind).unwrap();
+                let body = read_body(&mut self.stream).unwrap();
+                messages.push((kind[0], body));
+                if kind[0] == b'Z' {
+                    return messages;
+                }
+            }
+        }
+
+        fn query(&mut self, sql: &str) -> Vec<(u8, Vec<u8>)> {
+            let message = Message::new(b'Q').string(sql);
+            let mut buf = Vec::new();
+            message.send(&mut buf).unwrap();
+            self.stream.write_all(&buf).unwrap();
+            self.until_ready()
+        }
+    }
+
+    fn kinds(messages: &[(u8, Vec<u8>)]) -> String {
+        messages.iter().map(|(kind, _)| *kind as char).collect()
+    }
+
+    fn strings(body: &[u8]) -> Vec<String> {
+        body.split(|b| *b == 0)
+            .map(|s| String::from_utf8_lossy(s).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_transaction_command() {
+        assert_eq!(transaction_command(" BEGIN;"), Some("begin"));
+        assert_eq!(transaction_command("rollback"), Some("rollback"));
+        assert_eq!(transaction_command("ROLLBACK TO a;"), None);
+        assert_eq!(
+            transaction_command("SET extra_float_digits = 3"),
+            Some("set")
+        );
+        assert_eq!(transaction_command("SELECT * FROM t;"), None);
+    }
+
+    #[test]
+    fn test_commands() {
+        let commands = commands("BEGIN; INSERT INTO t VALUES ('a;\nb'); COMMIT").unwrap();
+        assert_eq!(commands.len(), 3);
+        assert_eq!(commands[0], Command::Transaction("begin"));
+        assert!(matches!(
+            commands[1],
+            Command::Statement(Statement::Insert { .. })
+        ));
+        assert_eq!(commands[2], Command::Transaction("commit"));
+        assert!(super::commands(" ; -- nothing").unwrap().is_empty());
+        assert!(super::commands("BEGIN; SELECT * FROM;").is_err());
+    }
+
+    #[test]
+    fn test_postgres_session() {
+        let database = Database::open_in_memory().unwrap();
+        let server = Server::bind(database, "127.0.0.1:0", Protocol::Postgres).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut client = Client::connect(addr);
+        let messages = client.query(
+            "CREATE TABLE t (id INTEGER, name TEXT, ok BOOLEAN); \
+             INSERT INTO t VALUES (1, 'a', TRUE), (2, NULL, FALSE);",
+        );
+        assert_eq!(kinds(&messages), "CCZ");
+        assert_eq!(strings(&messages[1].1)[0], "INSERT 0 2");
+
+        let messages = client.query("SELECT * FROM t;");
+        assert_eq!(kinds(&messages), "TDDCZ");
+        let (_, description) = &messages[0];
+        assert_eq!(&description[..2], &3i16.to_be_bytes());
+        assert!(description.starts_with(b"\0\x03id\0"));
+        // id's type: int4
+        assert_eq!(&description[11..15], &INT4.to_be_bytes());
+        let (_, row) = &messages[2];
+        assert_eq!(
+            row,
+            &[
+                &[0, 3][..],
+                &[0, 0, 0, 1],
+                b"2",
+                &[255; 4],
+                &[0, 0, 0, 1],
+                b"f"
+            ]
+            .concat()
+        );
+        assert_eq!(strings(&messages[3].1)[0], "SELECT 2");
+        assert_eq!(messages[4].1, b"I");
+
+        // An error stops the rest of the query
+        let messages = client.query("SELECT * FROM missing; DELETE FROM t;");
+        assert_eq!(kinds(&messages), "EZ");
+        assert!(strings(&messages[0].1).contains(&"C42P01".to_string()));
+        assert_eq!(kinds(&client.query("")), "IZ");
+
+        // A transaction rolled back leaves nothing behind
+        let messages = client.query("BEGIN;");
+        assert_eq!(messages[1].1, b"T");
+        client.query("DELETE FROM t;");
+        assert_eq!(kinds(&client.query("ROLLBACK;")), "CZ");
+        let messages = client.query("SELECT * FROM t;");
+        assert_eq!(strings(&messages[3].1)[0], "SELECT 2");
+
+        // A client that leaves mid-transaction takes it with it
+        let mut other = Client::connect(addr);
+        other.query("BEGIN; ");
+        other.query("DELETE FROM t WHERE id = 1;");
+        drop(other);
+        let mut messages = Vec::new();
+        for _ in 0..100 {
+            messages = client.query("SELECT * FROM t;");
+            if strings(&messages[3].1)[0] == "SELECT 2" {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(strings(&messages[3].1)[0], "SELECT 2");
+
+        let messages = client.query("SET client_encoding = 'UTF8';");
+        assert_eq!(strings(&messages[0].1)[0], "SET");
+
+        // Transaction commands run where they are in a query
+        let messages = client.query("BEGIN; DELETE FROM t; ROLLBACK; SELECT * FROM t;");
+        assert_eq!(kinds(&messages), "CCCTDDCZ");
+        assert_eq!(messages[7].1, b"I");
+        let messages = client.query("BEGIN; DELETE FROM t WHERE id = 2; COMMIT");
+        assert_eq!(kinds(&messages), "CCCZ");
+        assert_eq!(strings(&messages[2].1)[0], "COMMIT");
+        assert_eq!(messages[3].1, b"I");
+        let messages = client.query("SELECT * FROM t;");
+        assert_eq!(strings(&messages[2].1)[0], "SELECT 1");
+    }
+
+    #[test]
+    fn test_postgres_failed_transaction() {
+        let database = Database::open_in_memory().unwrap();
+        let server = Server::bind(database, "127.0.0.1:0", Protocol::Postgres).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut client = Client::connect(addr);
+        client.query("CREATE TABLE t (id INTEGER);");
+        let messages = client.query("BEGIN; INSERT INTO t VALUES (1); SELECT * FROM missing;");
+        assert_eq!(kinds(&messages), "CCEZ");
+        assert_eq!(messages[3].1, b"E");
+
+        // Nothing else runs until the transaction is rolled back
+        for sql in ["SELECT * FROM t;", "BEGIN;", "SET a = 1;"] {
+            let messages = client.query(sql);
+            assert_eq!(kinds(&messages), "EZ");
+            assert!(strings(&messages[0].1).contains(&"C25P02".to_string()));
+            assert_eq!(messages[1].1, b"E");
+        }
+        let messages = client.query("COMMIT;");
+        assert_eq!(strings(&messages[0].1)[0], "ROLLBACK");
+        assert_eq!(messages[1].1, b"I");
+        let messages = client.query("SELECT * FROM t;");
+        assert_eq!(strings(&messages[1].1)[0], "SELECT 0");
+
+        // ROLLBACK TO a savepoint makes it sound again
+        let messages = client.query(
+            "BEGIN; INSERT INTO t VALUES (1); SAVEPOINT a; \
+             INSERT INTO t VALUES (2); INSERT INTO missing VALUES (3);",
+        );
+        assert_eq!(messages.last().unwrap().1, b"E");
+        let messages = client.query("ROLLBACK TO a; COMMIT;");
+        assert_eq!(kinds(&messages), "CCZ");
+        assert_eq!(messages[2].1, b"I");
+        let messages = client.query("SELECT * FROM t;");
+        assert_eq!(strings(&messages[2].1)[0], "SELECT 1");
+
+        // A query that doesn't parse fails the transaction, running nothing
+        client.query("BEGIN;");
+        let messages = client.query("DELETE FROM t; SELECT * FROM;");
+        assert_eq!(kinds(&messages), "EZ");
+        assert_eq!(messages[1].1, b"E");
+        assert_eq!(kinds(&client.query("ROLLBACK;")), "CZ");
+    }
+
+    #[test]
+    fn test_postgres_idle_transaction_timeout() {
+        let database = Database::open_in_memory().unwrap();
+        let mut server = Server::bind(database, "127.0.0.1:0", Protocol::Postgres).unwrap();
+        server.set_idle_timeout(Some(std::time::Duration::from_millis(100)));
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut alice = Client::connect(addr);
+        alice.query("CREATE TABLE t (name TEXT);");
+        alice.query("BEGIN; INSERT INTO t VALUES ('alice');");
+
+        // Alice goes quiet, so Bob gets in once she's been dropped
+        let mut bob = Client::connect(addr);
+        assert_eq!(kinds(&bob.query("INSERT INTO t VALUES ('bob');")), "CZ");
+        let mut kind = [0u8; 1];
+        alice.stream.read_exact(&mut kind).unwrap();
+        let body = read_body(&mut alice.stream).unwrap();
+        assert_eq!(&kind, b"E");
+        assert!(strings(&body).contains(&"SFATAL".to_string()));
+        assert!(strings(&body).contains(&"C25P03".to_string()));
+        assert_eq!(alice.stream.read(&mut kind).unwrap(), 0);
+
+        let messages = bob.query("SELECT name FROM t;");
+        assert_eq!(kinds(&messages), "TDCZ");
+        assert!(messages[1].1.ends_with(b"bob"));
+
+        // Outside a transaction, a client may idle as long as it likes
+        thread::sleep(std::time::Duration::from_millis(200));
+        assert_eq!(kinds(&bob.query("SELECT name FROM t;")), "TDCZ");
+    }
+
+    #[test]
+    fn test_postgres_cancel() {
+        let database = Database::open_in_memory().unwrap();
+        let server = Server::bind(database, "127.0.0.1:0", Protocol::Postgres).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut client = Client::connect(addr);
+        let values: Vec<String> = (1..=100).map(|n| format!("({})", n)).collect();
+        for table in ["a", "b", "c", "d"] {
+            client.query(&format!(
+                "CREATE TABLE {0} (n INTEGER); INSERT INTO {0} VALUES {1};",
+                table,
+                values.join(", ")
+            ));
+        }
+        // Should the cancel miss, the timeout ends it with another message
+        client.query("PRAGMA statement_timeout = 10000;");
+
+        // A wrong key cancels nothing; the right one stops the statement
+        let key = client.key.clone();
+        let mut wrong = key.clone();
+        wrong[7] ^= 1;
+        let canceller = thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(100));
+            Client::cancel(addr, &wrong);
+            Client::cancel(addr, &key);
+        });
+        let started = std::time::Instant::now();
+        let messages = client.query(
+            "SELECT * FROM a JOIN b ON a.n > b.n JOIN c ON b.n > c.n JOIN d ON c.n > d.n \
+             WHERE d.n > a.n;",
+        );
+        canceller.join().unwrap();
+        assert_eq!(kinds(&messages), "EZ", "{:?}", strings(&messages[0].1));
+        assert!(
+            strings(&messages[0].1).contains(&"C57014".to_string()),
+            "{:?}",
+            strings(&messages[0].1)
+        );
+        assert!(
+            strings(&messages[0].1).contains(&"MThe statement was cancelled".to_string()),
+            "{:?}",
+            strings(&messages[0].1)
+        );
+        assert!(started.elapsed() < std::time::Duration::from_secs(3));
+
+        // The next statement runs as normal
+        let messages = client.query("SELECT * FROM a WHERE n = 1;");
+        assert_eq!(kinds(&messages), "TDCZ");
+    }
+
+    #[test]
+    fn test_postgres_transactions_keep_others_out() {
+        let database = Database::open_in_memory().unwrap();
+        let server = Server::bind(database, "127.0.0.1:0", Protocol::Postgres).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut alice = Client::connect(addr);
+        alice.query("CREATE TABLE t (name TEXT);");
+        alice.query("BEGIN;");
+        alice.query("INSERT INTO t VALUES ('alice');");
+
+        // Bob waits for Alice's transaction, so her ROLLBACK can't undo his
+        // insert and he never sees hers
+        let (sent, done) = std::sync::mpsc::channel();
+        let bob = thread::spawn(move || {
+            let mut bob = Client::connect(addr);
+            let messages = bob.query("INSERT INTO t VALUES ('bob');");
+            sent.send(()).unwrap();
+            messages
+        });
+        thread::sleep(std::time::Duration::from_millis(100));
+        assert!(done.try_recv().is_err());
+        assert_eq!(kinds(&alice.query("ROLLBACK;")), "CZ");
+        assert_eq!(kinds(&bob.join().unwrap()), "CZ");
+
+        let messages = alice.query("SELECT name FROM t;");
+        assert_eq!(kinds(&messages), "TDCZ");
+        assert!(messages[1].1.ends_with(b"bob"));
+    }
+}
@@ -0,0 +1,107 @@
+//! Async entry points to the executor, for embedding in tokio services.
+
+use crate::catalog::table::TableCatalog;
+use crate::error::Result;
+use crate::sql::executor::{ExecutionResult, Executor};
+use crate::sql::interrupt::CancelToken;
+use crate::sql::parser::Statement;
+use crate::storage::async_page::blocking;
+use crate::storage::page::PageManager;
+use std::sync::{Arc, RwLock};
+
+/// An Executor that runs each statement on tokio's blocking pool. SELECTs
+/// sent through `query` can run alongside each other.
+#[derive(Clone)]
+pub struct AsyncExecutor {
+    inner: Arc<RwLock<Executor>>,
+    // Kept outside the lock, which a running statement holds
+    interrupt: CancelToken,
+}
+
+impl AsyncExecutor {
+    pub fn new(executor: Executor) -> Self {
+        AsyncExecutor {
+            interrupt: executor.cancel_token(),
+            inner: Arc::new(RwLock::new(executor)),
+        }
+    }
+
+    /// Cancels the statements running now; see `Executor::cancel_token`
+    pub fn cancel_token(&self) -> CancelToken {
+        self.interrupt.clone()
+    }
+
+    pub async fn open(path: &str) -> Result<Self> {
+        let path = path.to_string();
+        let executor =
+            blocking(move || Ok(Executor::new(TableCatalog::new(PageManager::new(&path)?)?)))
+                .await?;
+        Ok(Self::new(executor))
+    }
+
+    pub async fn execute(&self, statement: Statement) -> Result<ExecutionResult> {
+        let inner = self.inner.clone();
+        blocking(move || inner.write().unwrap().execute(statement)).await
+    }
+
+    pub fn last_insert_id(&self) -> Option<i64> {
+        self.inner.read().unwrap().last_insert_id()
+    }
+
+    /// Run a read-only statement; see `Executor::query`
+    pub async fn query(&self, statement: Statement) -> Result<ExecutionResult> {
+        let inner = self.inner.clone();
+        blocking(move || inner.read().unwrap().query(statement)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::row::Value;
+    use crate::catalog::schema::{Column, DataType};
+    use crate::sql::parser::{Expr, SelectColumns};
+
+    #[test]
+    fn test_async_execute_and_query() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let executor = AsyncExecutor::open(":memory:").await.unwrap();
+            executor
+                .execute(Statement::CreateTable {
+                    name: "users".to_string(),
+                    columns: vec![Column::new("id", DataType::Integer)],
+                })
+                .await
+                .unwrap();
+            executor
+                .execute(Statement::Insert {
+                    table_name: "users".to_string(),
+                    columns: None,
+                    values: vec![vec![Expr::Literal(Value::Integer(7))]],
+                    on_conflict: None,
+                    returning: None,
+                })
+                .await
+                .unwrap();
+
+            let select = Statement::Select {
+                table_name: "users".to_string(),
+                columns: SelectColumns::All,
+                where_clause: None,
+                order_by: Vec::new(),
+                distinct: false,
+                joins: Vec::new(),
+            };
+            match executor.query(select).await.unwrap() {
+                ExecutionResult::Rows { rows, .. } => {
+                    assert_eq!(rows[0].values(), &vec![Value::Integer(7)])
+                }
+                other => panic!("expected rows, got {:?}", other),
+            }
+        });
+    }
+}
@@ -0,0 +1,106 @@
+//! Databases opened alongside an executor's own with ATTACH. Each has an
+//! alias that qualifies its tables, `other.users`, and `main` qualifies the
+//! executor's own. A statement runs in one database, the one its table names
+//! say; unqualified names in it are looked up there too, so
+//! `SELECT * FROM other.users JOIN orders ON ...` joins two tables of
+//! `other`. Tables in subqueries are always looked up unqualified.
+
+use crate::error::{HozonError, Result};
+use crate::sql::parser::Statement;
+
+/// Alias of the executor's own database
+pub const MAIN: &str = "main";
+
+/// The database `statement` runs in, None for main, and the statement with
+/// its table names unqualified
+pub fn route(mut statement: Statement) -> Result<(Option<String>, Statement)> {
+    let mut database: Option<String> = None;
+    for name in table_names(&mut statement) {
+        let Some((qualifier, table)) = name.split_once('.') else {
+            continue;
+        };
+        match &database {
+            Some(database) if !database.eq_ignore_ascii_case(qualifier) => {
+                return Err(HozonError::Unsupported(format!(
+                    "A statement can only use the tables of one database, not {} and {}",
+                    database, qualifier
+                )));
+            }
+            Some(_) => {}
+            None => database = Some(qualifier.to_string()),
+        }
+        *name = table.to_string();
+    }
+    let database = database.filter(|database| !database.eq_ignore_ascii_case(MAIN));
+    Ok((database, statement))
+}
+
+/// Every table name `statement` has, to look up in one database
+fn table_names(statement: &mut Statement) -> Vec<&mut String> {
+    match statement {
+        Statement::CreateTable { name, .. } | Statement::CreateView { name, .. } => vec![name],
+        Statement::Insert { table_name, .. }
+        | Statement::Update { table_name, .. }
+        | Statement::Delete { table_name, .. }
+        | Statement::CreateIndex { table_name, .. } => vec![table_name],
+        Statement::Select {
+            table_name, joins, ..
+        } => {
+            let mut names = vec![table_name];
+            names.extend(joins.iter_mut().map(|join| &mut join.table_name));
+            names
+        }
+        Statement::Analyze { table_name } => table_name.iter_mut().collect(),
+        Statement::Explain(statement) => table_names(statement),
+        Statement::DropIndex { .. }
+        | Statement::Pragma { .. }
+        | Statement::Checkpoint
+        | Statement::Savepoint { .. }
+        | Statement::Release { .. }
+        | Statement::RollbackTo { .. }
+        | Statement::Attach { .. }
+        | Statement::Detach { .. } => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::{formatter, parser::Parser};
+
+    fn route_sql(sql: &str) -> Result<(Option<String>, String)> {
+        let statement = Parser::from_sql(sql).unwrap().parse().unwrap();
+        let (database, statement) = route(statement)?;
+        Ok((database, formatter::statement(&statement)))
+    }
+
+    #[test]
+    fn test_route() {
+        assert_eq!(
+            route_sql("SELECT * FROM users;").unwrap(),
+            (None, "SELECT * FROM users;".to_string())
+        );
+        assert_eq!(
+            route_sql("SELECT users.id FROM other.users JOIN orders ON users.id = orders.id;")
+                .unwrap(),
+            (
+                Some("other".to_string()),
+                "SELECT users.id FROM users JOIN orders ON users.id = orders.id;".to_string()
+            )
+        );
+        assert_eq!(
+            route_sql("EXPLAIN DELETE FROM Other.t WHERE id = 1;").unwrap(),
+            (
+                Some("Other".to_string()),
+                "EXPLAIN DELETE FROM t WHERE id = 1;".to_string()
+            )
+        );
+        assert_eq!(
+            route_sql("INSERT INTO MAIN.t VALUES (1);").unwrap(),
+            (None, "INSERT INTO t VALUES (1);".to_string())
+        );
+
+        let err = route_sql("SELECT * FROM main.t JOIN other.u ON t.id = u.id;").unwrap_err();
+        assert!(matches!(err, HozonError::Unsupported(_)), "{:?}", err);
+    }
+}
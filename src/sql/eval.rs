@@ -0,0 +1,703 @@
+//! Evaluating expressions against rows. `bind` resolves the column names in
+//! a parsed `Expr` once per query, so a missing column is an error even on
+//! an empty table, and runs its subqueries; the bound expression is then
+//! evaluated per row with SQL's three-valued logic, where NULL means unknown.
+//!
+//! A subquery that refers to the enclosing query's columns is correlated:
+//! its result depends on the row, so instead of running once at bind time
+//! it runs again as each row is evaluated.
+
+use crate::catalog::convert::{self, Conversion};
+use crate::catalog::format::ValueFormat;
+use crate::catalog::ordering;
+use crate::catalog::row::{Row, Value};
+use crate::catalog::schema::DataType;
+use crate::error::{HozonError, Result};
+use crate::sql::functions::{self, Function};
+use crate::sql::parser::{BinaryOperator, Expr, Statement};
+use std::cmp::Ordering;
+use std::fmt;
+use std::rc::Rc;
+
+/// An `Expr` with its columns resolved to positions in the row. `'a` is
+/// the query it runs in, which correlated subqueries run against.
+#[derive(Debug, Clone)]
+pub enum Bound<'a> {
+    Literal(Value),
+    Column(usize),
+    Not(Box<Bound<'a>>),
+    Negate(Box<Bound<'a>>),
+    IsNull {
+        expr: Box<Bound<'a>>,
+        negated: bool,
+    },
+    /// Also what an uncorrelated IN subquery becomes once it has run
+    InList {
+        expr: Box<Bound<'a>>,
+        list: Vec<Bound<'a>>,
+        negated: bool,
+    },
+    BinaryOp {
+        left: Box<Bound<'a>>,
+        op: BinaryOperator,
+        right: Box<Bound<'a>>,
+    },
+    Function {
+        function: &'static Function,
+        args: Vec<Bound<'a>>,
+    },
+    Cast {
+        expr: Box<Bound<'a>>,
+        data_type: DataType,
+    },
+    /// A correlated subquery, and what the expression asks of its rows
+    Correlated {
+        query: Rc<dyn Correlated + 'a>,
+        test: SubqueryTest<'a>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum SubqueryTest<'a> {
+    /// `(SELECT ...)`: the one value of its one row, NULL if no rows
+    Scalar,
+    /// `EXISTS (SELECT ...)`: whether it has rows
+    Exists,
+    /// `expr [NOT] IN (SELECT ...)`
+    In { expr: Box<Bound<'a>>, negated: bool },
+}
+
+/// A subquery to run for each row of the enclosing query
+pub trait Correlated: fmt::Debug {
+    /// Its rows for the enclosing row whose columns `column` returns
+    fn rows(&self, column: &dyn Fn(usize) -> Value) -> Result<Vec<Row>>;
+}
+
+/// A bound subquery
+pub enum Subquery<'a> {
+    /// Its rows, for one that doesn't refer to the enclosing query and so
+    /// only runs once
+    Rows(Vec<Row>),
+    Correlated(Rc<dyn Correlated + 'a>),
+}
+
+/// What binding needs from the query an expression is in
+pub trait Binder<'a> {
+    /// What the column `name` refers to: a position in the row, or the
+    /// value of an enclosing query's column
+    fn column(&self, name: &str) -> Result<Bound<'a>>;
+    /// A subquery's number of columns, and the subquery bound
+    fn subquery(&self, query: &Statement) -> Result<(usize, Subquery<'a>)>;
+}
+
+/// Resolve every column and function in `expr` and run its subqueries with
+/// `binder`
+pub fn bind<'a>(expr: &Expr, binder: &impl Binder<'a>) -> Result<Bound<'a>> {
+    Ok(match expr {
+        Expr::Literal(value) => Bound::Literal(value.clone()),
+        Expr::Parameter(n) => return Err(unbound(*n)),
+        Expr::Column(name) => binder.column(name)?,
+        // A SelectPlan counts rows itself; it's no value of one
+        Expr::CountAll => {
+            return Err(HozonError::InvalidStatement(
+                "COUNT(*) can only be a SELECT's only column".to_string(),
+            ));
+        }
+        Expr::Not(inner) => Bound::Not(Box::new(bind(inner, binder)?)),
+        Expr::Negate(inner) => Bound::Negate(Box::new(bind(inner, binder)?)),
+        Expr::IsNull { expr, negated } => Bound::IsNull {
+            expr: Box::new(bind(expr, binder)?),
+            negated: *negated,
+        },
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => Bound::InList {
+            expr: Box::new(bind(expr, binder)?),
+            list: list
+                .iter()
+                .map(|item| bind(item, binder))
+                .collect::<Result<_>>()?,
+            negated: *negated,
+        },
+        Expr::InSubquery {
+            expr,
+            query,
+            negated,
+        } => {
+            let expr = Box::new(bind(expr, binder)?);
+            match one_column(binder.subquery(query)?, "IN (SELECT ...)")? {
+                Subquery::Rows(rows) => Bound::InList {
+                    expr,
+                    list: rows
+                        .into_iter()
+                        .map(|row| Bound::Literal(row.values()[0].clone()))
+                        .collect(),
+                    negated: *negated,
+                },
+                Subquery::Correlated(query) => Bound::Correlated {
+                    query,
+                    test: SubqueryTest::In {
+                        expr,
+                        negated: *negated,
+                    },
+                },
+            }
+        }
+        Expr::Subquery(query) => match one_column(binder.subquery(query)?, "A scalar subquery")? {
+            Subquery::Rows(rows) => Bound::Literal(scalar(rows)?),
+            Subquery::Correlated(query) => Bound::Correlated {
+                query,
+                test: SubqueryTest::Scalar,
+            },
+        },
+        Expr::Exists(query) => match binder.subquery(query)?.1 {
+            Subquery::Rows(rows) => Bound::Literal(Value::Boolean(!rows.is_empty())),
+            Subquery::Correlated(query) => Bound::Correlated {
+                query,
+                test: SubqueryTest::Exists,
+            },
+        },
+        Expr::BinaryOp { left, op, right } => Bound::BinaryOp {
+            left: Box::new(bind(left, binder)?),
+            op: op.clone(),
+            right: Box::new(bind(right, binder)?),
+        },
+        Expr::Function { name, args } => Bound::Function {
+            function: functions::lookup(name, args.len())?,
+            args: args
+                .iter()
+                .map(|arg| bind(arg, binder))
+                .collect::<Result<_>>()?,
+        },
+        Expr::Cast { expr, data_type } => Bound::Cast {
+            expr: Box::new(bind(expr, binder)?),
+            data_type: data_type.clone(),
+        },
+    })
+}
+
+/// The error for running a statement whose parameter `n` has no value
+pub fn unbound(n: usize) -> HozonError {
+    HozonError::InvalidInput(format!(
+        "Parameter ${} has no value; prepare the statement and bind it",
+        n
+    ))
+}
+
+/// `subquery`, if it has one column; `what` says where it's used
+fn one_column<'a>((columns, subquery): (usize, Subquery<'a>), what: &str) -> Result<Subquery<'a>> {
+    if columns != 1 {
+        return Err(HozonError::InvalidStatement(format!(
+            "{} must return one column, not {}",
+            what, columns
+        )));
+    }
+    Ok(subquery)
+}
+
+/// A scalar subquery's value
+fn scalar(rows: Vec<Row>) -> Result<Value> {
+    match &rows[..] {
+        [] => Ok(Value::Null),
+        [row] => Ok(row.values()[0].clone()),
+        _ => Err(HozonError::InvalidStatement(format!(
+            "A scalar subquery returned {} rows, not one",
+            rows.len()
+        ))),
+    }
+}
+
+impl Bound<'_> {
+    /// The expression's value for the row whose columns `column` returns
+    pub fn eval(&self, column: &impl Fn(usize) -> Value) -> Result<Value> {
+        match self {
+            Bound::Literal(value) => Ok(value.clone()),
+            Bound::Column(col) => Ok(column(*col)),
+            Bound::Not(inner) => Ok(truth_value(truth(inner.eval(column)?)?.map(|b| !b))),
+            Bound::Negate(inner) => negate(inner.eval(column)?),
+            Bound::IsNull { expr, negated } => Ok(Value::Boolean(
+                (expr.eval(column)? == Value::Null) != *negated,
+            )),
+            Bound::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let value = expr.eval(column)?;
+                contains(&value, list.iter().map(|item| item.eval(column)), *negated)
+            }
+            Bound::BinaryOp { left, op, right } => {
+                let left = left.eval(column)?;
+                match op {
+                    // Short-circuit where the left side already decides
+                    BinaryOperator::And => match truth(left)? {
+                        Some(false) => Ok(Value::Boolean(false)),
+                        left => {
+                            let right = truth(right.eval(column)?)?;
+                            Ok(truth_value(match (left, right) {
+                                (_, Some(false)) => Some(false),
+                                (Some(true), Some(true)) => Some(true),
+                                _ => None,
+                            }))
+                        }
+                    },
+                    BinaryOperator::Or => match truth(left)? {
+                        Some(true) => Ok(Value::Boolean(true)),
+                        left => {
+                            let right = truth(right.eval(column)?)?;
+                            Ok(truth_value(match (left, right) {
+                                (_, Some(true)) => Some(true),
+                                (Some(false), Some(false)) => Some(false),
+                                _ => None,
+                            }))
+                        }
+                    },
+                    BinaryOperator::Subtract => subtract(left, right.eval(column)?),
+                    comparison => {
+                        let ordering = ordering::compare(&left, &right.eval(column)?);
+                        Ok(truth_value(ordering.map(|o| compares(comparison, o))))
+                    }
+                }
+            }
+            Bound::Function { function, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(column))
+                    .collect::<Result<Vec<Value>>>()?;
+                function.call(&args)
+            }
+            Bound::Correlated { query, test } => {
+                let rows = query.rows(column)?;
+                match test {
+                    SubqueryTest::Scalar => scalar(rows),
+                    SubqueryTest::Exists => Ok(Value::Boolean(!rows.is_empty())),
+                    SubqueryTest::In { expr, negated } => {
+                        let value = expr.eval(column)?;
+                        let list = rows.into_iter().map(|row| Ok(row.values()[0].clone()));
+                        contains(&value, list, *negated)
+                    }
+                }
+            }
+            Bound::Cast { expr, data_type } => {
+                let value = expr.eval(column)?;
+                convert::convert(&value, data_type, Conversion::Cast).ok_or_else(|| {
+                    HozonError::TypeMismatch(format!(
+                        "Cannot CAST {} AS {}",
+                        value.display_with(&ValueFormat::sql()),
+                        data_type.as_str()
+                    ))
+                })
+            }
+        }
+    }
+
+    /// Whether the row passes, as a WHERE condition: unknown doesn't
+    pub fn matches(&self, column: &impl Fn(usize) -> Value) -> Result<bool> {
+        Ok(truth(self.eval(column)?)? == Some(true))
+    }
+}
+
+impl<'a> Bound<'a> {
+    /// The positions of the columns the expression reads, or None if it
+    /// has a correlated subquery, which reads the row in its own way
+    pub fn columns(&self) -> Option<Vec<usize>> {
+        let mut columns = Vec::new();
+        self.collect_columns(&mut columns).then_some(columns)
+    }
+
+    fn collect_columns(&self, columns: &mut Vec<usize>) -> bool {
+        match self {
+            Bound::Column(position) => {
+                columns.push(*position);
+                true
+            }
+            Bound::Correlated { .. } => false,
+            _ => self
+                .children()
+                .into_iter()
+                .all(|child| child.collect_columns(columns)),
+        }
+    }
+
+    /// The expression for rows holding only the columns from `offset` on of
+    /// those it was bound to. It must read none before.
+    pub fn shifted(mut self, offset: usize) -> Self {
+        self.shift(offset);
+        self
+    }
+
+    fn shift(&mut self, offset: usize) {
+        match self {
+            Bound::Column(position) => *position -= offset,
+            _ => {
+                for child in self.children_mut() {
+                    child.shift(offset);
+                }
+            }
+        }
+    }
+
+    fn children(&self) -> Vec<&Bound<'a>> {
+        match self {
+            Bound::Literal(_) | Bound::Column(_) => Vec::new(),
+            Bound::Not(inner) | Bound::Negate(inner) => vec![inner],
+            Bound::IsNull { expr, .. } | Bound::Cast { expr, .. } => vec![expr],
+            Bound::InList { expr, list, .. } => {
+                let mut children: Vec<&Bound<'a>> = vec![expr];
+                children.extend(list);
+                children
+            }
+            Bound::BinaryOp { left, right, .. } => vec![left, right],
+            Bound::Function { args, .. } => args.iter().collect(),
+            Bound::Correlated { test, .. } => match test {
+                SubqueryTest::In { expr, .. } => vec![expr],
+                _ => Vec::new(),
+            },
+        }
+    }
+
+    fn children_mut(&mut self) -> Vec<&mut Bound<'a>> {
+        match self {
+            Bound::Literal(_) | Bound::Column(_) => Vec::new(),
+            Bound::Not(inner) | Bound::Negate(inner) => vec![inner],
+            Bound::IsNull { expr, .. } | Bound::Cast { expr, .. } => vec![expr],
+            Bound::InList { expr, list, .. } => {
+                let mut children: Vec<&mut Bound<'a>> = vec![expr];
+                children.extend(list);
+                children
+            }
+            Bound::BinaryOp { left, right, .. } => vec![left, right],
+            Bound::Function { args, .. } => args.iter_mut().collect(),
+            Bound::Correlated { test, .. } => match test {
+                SubqueryTest::In { expr, .. } => vec![expr],
+                _ => Vec::new(),
+            },
+        }
+    }
+}
+
+/// `value [NOT] IN list`: TRUE on the first match; otherwise unknown if
+/// any comparison was, FALSE if none
+fn contains(
+    value: &Value,
+    list: impl Iterator<Item = Result<Value>>,
+    negated: bool,
+) -> Result<Value> {
+    let mut unknown = false;
+    for item in list {
+        match ordering::compare(value, &item?) {
+            Some(Ordering::Equal) => return Ok(Value::Boolean(!negated)),
+            Some(_) => {}
+            None => unknown = true,
+        }
+    }
+    Ok(truth_value((!unknown).then_some(negated)))
+}
+
+fn compares(op: &BinaryOperator, ordering: Ordering) -> bool {
+    match op {
+        BinaryOperator::Equals => ordering.is_eq(),
+        BinaryOperator::NotEquals => ordering.is_ne(),
+        BinaryOperator::LessThan => ordering.is_lt(),
+        BinaryOperator::GreaterThan => ordering.is_gt(),
+        BinaryOperator::LessOrEqual => ordering.is_le(),
+        BinaryOperator::GreaterOrEqual => ordering.is_ge(),
+        BinaryOperator::And | BinaryOperator::Or | BinaryOperator::Subtract => {
+            unreachable!("not a comparison")
+        }
+    }
+}
+
+/// `-value`. INTEGER widens to BIGINT rather than overflow, as ABS does.
+fn negate(value: Value) -> Result<Value> {
+    match value {
+        Value::Integer(n) => Ok(n
+            .checked_neg()
+            .map_or(Value::BigInt(-(n as i64)), Value::Integer)),
+        Value::BigInt(n) => n
+            .checked_neg()
+            .map(Value::BigInt)
+            .ok_or_else(|| HozonError::TypeMismatch("Negation overflows BIGINT".to_string())),
+        Value::Float(f) => Ok(Value::Float(-f)),
+        Value::Null => Ok(Value::Null),
+        other => Err(not_a_number("negate", &other)),
+    }
+}
+
+/// `left - right`: FLOAT if either is, otherwise INTEGER if both are and
+/// the difference fits, BIGINT if not. NULL if either is NULL.
+fn subtract(left: Value, right: Value) -> Result<Value> {
+    let overflow = || HozonError::TypeMismatch("Subtraction overflows BIGINT".to_string());
+    match (&left, &right) {
+        (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+        (Value::Integer(a), Value::Integer(b)) => Ok(a
+            .checked_sub(*b)
+            .map_or(Value::BigInt(*a as i64 - *b as i64), Value::Integer)),
+        (Value::Float(a), _) => Ok(Value::Float(a - float(&right)?)),
+        (_, Value::Float(b)) => Ok(Value::Float(float(&left)? - b)),
+        _ => bigint(&left)?
+            .checked_sub(bigint(&right)?)
+            .map(Value::BigInt)
+            .ok_or_else(overflow),
+    }
+}
+
+fn bigint(value: &Value) -> Result<i64> {
+    match value {
+        Value::Integer(n) => Ok(*n as i64),
+        Value::BigInt(n) => Ok(*n),
+        other => Err(not_a_number("subtract", other)),
+    }
+}
+
+fn float(value: &Value) -> Result<f64> {
+    match value {
+        Value::Float(f) => Ok(*f),
+        other => bigint(other).map(|n| n as f64),
+    }
+}
+
+fn not_a_number(operation: &str, value: &Value) -> HozonError {
+    HozonError::TypeMismatch(format!(
+        "Cannot {} {}, which isn't a number",
+        operation,
+        value.display_with(&ValueFormat::sql())
+    ))
+}
+
+/// A condition's truth, None for unknown
+fn truth(value: Value) -> Result<Option<bool>> {
+    match value {
+        Value::Boolean(b) => Ok(Some(b)),
+        Value::Null => Ok(None),
+        other => Err(HozonError::TypeMismatch(format!(
+            "Expected a BOOLEAN condition, found {}",
+            other.display_with(&ValueFormat::sql())
+        ))),
+    }
+}
+
+fn truth_value(truth: Option<bool>) -> Value {
+    truth.map_or(Value::Null, Value::Boolean)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::{Parser, Statement};
+    use crate::sql::tokenizer::tokenize;
+
+    /// Columns a and b, and subqueries that return 1 and NULL
+    struct Columns;
+
+    impl Binder<'static> for Columns {
+        fn column(&self, name: &str) -> Result<Bound<'static>> {
+            match name {
+                "a" => Ok(Bound::Column(0)),
+                "b" => Ok(Bound::Column(1)),
+                _ => Err(HozonError::ColumnNotFound("no such column".to_string())),
+            }
+        }
+
+        fn subquery(&self, _: &Statement) -> Result<(usize, Subquery<'static>)> {
+            Ok((
+                1,
+                Subquery::Rows(vec![
+                    Row::new(vec![Value::Integer(1)]),
+                    Row::new(vec![Value::Null]),
+                ]),
+            ))
+        }
+    }
+
+    /// The WHERE clause of `SELECT * FROM t WHERE <condition>;`, bound to
+    /// `Columns`
+    fn condition(condition: &str) -> Bound<'static> {
+        let sql = format!("SELECT * FROM t WHERE {};", condition);
+        let where_clause = match Parser::new(tokenize(&sql).unwrap()).parse().unwrap() {
+            Statement::Select { where_clause, .. } => where_clause.unwrap(),
+            _ => unreachable!(),
+        };
+        bind(&where_clause, &Columns).unwrap()
+    }
+
+    fn eval(condition_sql: &str, a: Value, b: Value) -> Value {
+        let row = [a, b];
+        condition(condition_sql).eval(&|i| row[i].clone()).unwrap()
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let one = || Value::Integer(1);
+        assert_eq!(eval("a = 1", one(), Value::Null), Value::Boolean(true));
+        assert_eq!(eval("a >= 1.5", one(), Value::Null), Value::Boolean(false));
+        assert_eq!(
+            eval("1 < a", Value::BigInt(2), Value::Null),
+            Value::Boolean(true)
+        );
+        assert_eq!(eval("b != 'x'", one(), Value::Null), Value::Null);
+        // Unrelated types are unknown rather than unequal
+        assert_eq!(eval("a = 'x'", one(), Value::Null), Value::Null);
+    }
+
+    #[test]
+    fn test_three_valued_logic() {
+        let t = || Value::Boolean(true);
+        let f = || Value::Boolean(false);
+        assert_eq!(eval("a AND b", Value::Null, f()), f());
+        assert_eq!(eval("a AND b", Value::Null, t()), Value::Null);
+        assert_eq!(eval("a OR b", Value::Null, t()), t());
+        assert_eq!(eval("a OR b", f(), Value::Null), Value::Null);
+        assert_eq!(eval("NOT a", Value::Null, Value::Null), Value::Null);
+        // AND binds tighter than OR
+        assert_eq!(eval("a OR b AND FALSE", t(), t()), t());
+        assert_eq!(eval("(a OR b) AND FALSE", t(), t()), f());
+    }
+
+    #[test]
+    fn test_null_is_unknown() {
+        let t = || Value::Boolean(true);
+        let f = || Value::Boolean(false);
+        // NULL isn't equal, or unequal, even to itself
+        assert_eq!(eval("a = b", Value::Null, Value::Null), Value::Null);
+        assert_eq!(eval("a != b", Value::Null, Value::Null), Value::Null);
+        assert_eq!(eval("NOT (a = 1)", Value::Null, Value::Null), Value::Null);
+        // IS NULL is the way to ask
+        assert_eq!(eval("a IS NULL", Value::Null, Value::Null), t());
+        assert_eq!(eval("a IS NOT NULL", Value::Null, Value::Null), f());
+        assert_eq!(eval("a = 1 IS NULL", Value::Null, Value::Null), t());
+        assert_eq!(eval("a IS NOT NULL AND b", Value::Integer(1), t()), t());
+    }
+
+    #[test]
+    fn test_minus() {
+        let n = Value::Integer;
+        let null = || Value::Null;
+        assert_eq!(eval("a - b = 2", n(5), n(3)), Value::Boolean(true));
+        assert_eq!(eval("a - -b = 8", n(5), n(3)), Value::Boolean(true));
+        assert_eq!(eval("-a < b", n(5), n(3)), Value::Boolean(true));
+        assert_eq!(eval("a - b IS NULL", n(5), null()), Value::Boolean(true));
+
+        let value = |sql: &str, a: Value, b: Value| {
+            let row = [a, b];
+            condition(sql).eval(&|i| row[i].clone())
+        };
+        assert_eq!(
+            value("a - b", n(5), Value::Float(0.5)).unwrap(),
+            Value::Float(4.5)
+        );
+        // INTEGER widens rather than overflow
+        assert_eq!(
+            value("a - b", n(i32::MIN), n(1)).unwrap(),
+            Value::BigInt(i32::MIN as i64 - 1)
+        );
+        assert_eq!(
+            value("-a", n(i32::MIN), null()).unwrap(),
+            Value::BigInt(1 << 31)
+        );
+        assert!(value("a - b", Value::BigInt(i64::MIN), n(1)).is_err());
+        assert!(value("-a", Value::Text("x".to_string()), null()).is_err());
+    }
+
+    #[test]
+    fn test_in() {
+        let t = || Value::Boolean(true);
+        let f = || Value::Boolean(false);
+        let n = Value::Integer;
+        assert_eq!(eval("a IN (1, b, 3)", n(3), Value::Null), t());
+        assert_eq!(eval("a IN (1, 2)", n(3), Value::Null), f());
+        assert_eq!(eval("a NOT IN (1, 2)", n(3), Value::Null), t());
+        // No match, but b might have been one
+        assert_eq!(eval("a IN (1, b)", n(3), Value::Null), Value::Null);
+        assert_eq!(eval("a NOT IN (1, b)", n(3), Value::Null), Value::Null);
+        assert_eq!(eval("a IN (1, b)", n(1), Value::Null), t());
+        assert_eq!(eval("a IN (1)", Value::Null, Value::Null), Value::Null);
+
+        // The subquery returns 1 and NULL
+        assert_eq!(eval("a IN (SELECT n FROM t)", n(1), Value::Null), t());
+        assert_eq!(
+            eval("a NOT IN (SELECT n FROM t)", n(2), Value::Null),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn test_uncorrelated_subqueries_run_once() {
+        // The subquery returns 1 and NULL, already at bind time
+        assert!(matches!(
+            condition("EXISTS (SELECT n FROM t)"),
+            Bound::Literal(Value::Boolean(true))
+        ));
+        let sql = "SELECT * FROM t WHERE a = (SELECT n FROM t);";
+        let Statement::Select { where_clause, .. } =
+            Parser::new(tokenize(sql).unwrap()).parse().unwrap()
+        else {
+            unreachable!()
+        };
+        let err = bind(&where_clause.unwrap(), &Columns).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "A scalar subquery returned 2 rows, not one"
+        );
+    }
+
+    #[test]
+    fn test_functions() {
+        let text = |s: &str| Value::Text(s.to_string());
+        assert_eq!(
+            eval("UPPER(a) = 'AB'", text("ab"), Value::Null),
+            Value::Boolean(true)
+        );
+        assert_eq!(
+            eval("COALESCE(b, a) = 'ab'", text("ab"), Value::Null),
+            Value::Boolean(true)
+        );
+        assert_eq!(
+            eval("LENGTH(SUBSTR(a, 2)) > ABS(-2)", text("abcd"), Value::Null),
+            Value::Boolean(true)
+        );
+        assert_eq!(eval("LOWER(b) = 'x'", text("ab"), Value::Null), Value::Null);
+
+        let sql = "SELECT * FROM t WHERE NOPE(a);";
+        let Statement::Select { where_clause, .. } =
+            Parser::new(tokenize(sql).unwrap()).parse().unwrap()
+        else {
+            unreachable!()
+        };
+        let err = bind(&where_clause.unwrap(), &Columns).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown function 'NOPE'");
+    }
+
+    #[test]
+    fn test_cast() {
+        let text = |s: &str| Value::Text(s.to_string());
+        assert_eq!(
+            eval("CAST(a AS INTEGER) = 42", text("42"), Value::Null),
+            Value::Boolean(true)
+        );
+        assert_eq!(
+            eval("CAST(a AS TEXT) = '1.5'", Value::Float(1.5), Value::Null),
+            Value::Boolean(true)
+        );
+        assert_eq!(
+            eval("CAST(b AS BOOLEAN)", Value::Null, Value::Null),
+            Value::Null
+        );
+        let err = condition("CAST(a AS INTEGER) = 1")
+            .eval(&|_| text("x"))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Cannot CAST 'x' AS INTEGER");
+    }
+
+    #[test]
+    fn test_condition_must_be_boolean() {
+        let bound = condition("a");
+        let err = bound
+            .matches(&|_| Value::Text("yes".to_string()))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Expected a BOOLEAN condition, found 'yes'");
+        assert!(!bound.matches(&|_| Value::Null).unwrap());
+    }
+}
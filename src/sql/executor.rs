@@ -1,17 +1,44 @@
-use std::io::{self, Error, ErrorKind};
+use crate::error::{HozonError, Result};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 
 use crate::{
     catalog::{
+        convert::{Conversion, convert},
+        format::ValueFormat,
+        ordering,
         row::{Row, Value},
-        schema::{Column, Schema},
+        schema::{Column, DataType, IdentifierCase, Schema},
+        system,
         table::TableCatalog,
     },
-    sql::parser::{SelectColumns, Statement},
-    storage::page::{PAGE_DATA_START, PAGE_SIZE, PageManager, PageMetadata},
+    sql::{
+        attach,
+        eval::{self, Bound},
+        hooks::Hooks,
+        interrupt::{self, CancelToken, Running},
+        join,
+        optimizer::{self, LogicalPlan, LogicalTable},
+        parser::{Assignment, ConflictAction, Expr, OnConflict, Parser, SelectColumns, Statement},
+        plan::{self, Access, Predicate, QueryPlan, Scan, Source},
+        prepared::PreparedStatement,
+        select::{Context, Outer, Scope, SelectPlan, row_value},
+    },
+    storage::page::{PageManager, SyncMode},
 };
 
 pub struct Executor {
     catalog: TableCatalog,
+    // AUTOINCREMENT value of the last row inserted into a table that has one
+    last_insert_id: Option<i64>,
+    interrupt: CancelToken,
+    // How long one statement may run, None for as long as it takes
+    statement_timeout: Option<Duration>,
+    // Whether writes refuse values of another type they could convert
+    strict: bool,
+    hooks: Hooks,
+    // Databases opened with ATTACH, by alias
+    attached: Vec<(String, TableCatalog)>,
 }
 
 #[derive(Debug)]
@@ -19,26 +46,473 @@ pub enum ExecutionResult {
     Success {
         message: String,
     },
+    /// An INSERT, UPDATE or DELETE without RETURNING, and how many rows it
+    /// changed
+    Affected {
+        kind: StatementKind,
+        rows: usize,
+    },
     Rows {
         columns: Vec<String>,
         rows: Vec<Row>,
     },
 }
 
+/// What a write did to the rows it changed. An INSERT that hit an existing
+/// key reports what it did to that row instead: `Replace` for OR REPLACE,
+/// `Update` for ON CONFLICT DO UPDATE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatementKind {
+    Insert,
+    Replace,
+    Update,
+    Delete,
+}
+
+impl StatementKind {
+    /// "inserted", "deleted", ...
+    pub fn verb(self) -> &'static str {
+        match self {
+            StatementKind::Insert => "inserted",
+            StatementKind::Replace => "replaced",
+            StatementKind::Update => "updated",
+            StatementKind::Delete => "deleted",
+        }
+    }
+}
+
+impl ExecutionResult {
+    /// What to tell the user, for a result without rows: "Table 't'
+    /// created.", "1 row inserted.", "3 rows deleted."
+    pub fn message(&self) -> Option<String> {
+        match self {
+            ExecutionResult::Success { message } => Some(message.clone()),
+            ExecutionResult::Affected { kind, rows } => Some(match rows {
+                1 => format!("1 row {}.", kind.verb()),
+                n => format!("{} rows {}.", n, kind.verb()),
+            }),
+            ExecutionResult::Rows { .. } => None,
+        }
+    }
+
+    /// How many rows an INSERT, UPDATE or DELETE changed
+    pub fn affected_rows(&self) -> Option<usize> {
+        match self {
+            ExecutionResult::Affected { rows, .. } => Some(*rows),
+            _ => None,
+        }
+    }
+}
+
 impl Executor {
     pub fn new(catalog: TableCatalog) -> Self {
-        Executor { catalog }
+        Executor {
+            catalog,
+            last_insert_id: None,
+            interrupt: CancelToken::new(),
+            statement_timeout: None,
+            strict: false,
+            hooks: Hooks::default(),
+            attached: Vec::new(),
+        }
     }
 
-    pub fn execute(&mut self, statement: Statement) -> io::Result<ExecutionResult> {
-        match statement {
+    pub fn catalog(&self) -> &TableCatalog {
+        &self.catalog
+    }
+
+    /// The catalog for writing pages directly, as the REPL's page commands
+    /// do; nothing written this way is checked against the schema
+    pub fn catalog_mut(&mut self) -> &mut TableCatalog {
+        &mut self.catalog
+    }
+
+    /// The AUTOINCREMENT column value of the most recent insert into a table
+    /// with such a column, whether generated or given explicitly
+    pub fn last_insert_id(&self) -> Option<i64> {
+        self.last_insert_id
+    }
+
+    /// A token that cancels the statements running when it's used, from
+    /// another thread or a signal handler
+    pub fn cancel_token(&self) -> CancelToken {
+        self.interrupt.clone()
+    }
+
+    /// Cancel statements through `token` from now on, so one made before
+    /// the executor, as for a Ctrl-C handler, can stop them
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.interrupt = token;
+    }
+
+    pub fn statement_timeout(&self) -> Option<Duration> {
+        self.statement_timeout
+    }
+
+    /// Stop statements that run longer than `timeout`; None to let them
+    /// run as long as they take
+    pub fn set_statement_timeout(&mut self, timeout: Option<Duration>) {
+        self.statement_timeout = timeout;
+    }
+
+    /// Whether a value written to a column of another type fails, rather
+    /// than being converted when the column's type holds it exactly; see
+    /// `Conversion`
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Call `hook` with the table, rowid and row of each row inserted
+    pub fn on_insert(&mut self, hook: impl FnMut(&str, i64, &Row) + Send + Sync + 'static) {
+        self.hooks.on_insert(Box::new(hook));
+    }
+
+    /// Call `hook` with the table, rowid, and old and new row of each row
+    /// updated, by UPDATE or by an INSERT that replaced or updated a row
+    /// with the same key
+    pub fn on_update(&mut self, hook: impl FnMut(&str, i64, &Row, &Row) + Send + Sync + 'static) {
+        self.hooks.on_update(Box::new(hook));
+    }
+
+    /// Call `hook` with the table, rowid and row of each row deleted
+    pub fn on_delete(&mut self, hook: impl FnMut(&str, i64, &Row) + Send + Sync + 'static) {
+        self.hooks.on_delete(Box::new(hook));
+    }
+
+    pub fn clear_hooks(&mut self) {
+        self.hooks.clear();
+    }
+
+    fn conversion(&self) -> Conversion {
+        match self.strict {
+            true => Conversion::Strict,
+            false => Conversion::Implicit,
+        }
+    }
+
+    pub fn execute(&mut self, statement: Statement) -> Result<ExecutionResult> {
+        let (database, statement) = attach::route(statement)?;
+        if let Some(database) = database {
+            return self.execute_attached(&database, statement);
+        }
+        let _running = Running::start(&self.interrupt, self.statement_timeout);
+        let result = match statement {
             Statement::CreateTable { name, columns } => self.execute_create(name, columns),
-            Statement::Insert { table_name, values } => self.execute_insert(table_name, values),
-            Statement::Select {
+            Statement::CreateView { name, query } => self.execute_create_view(name, query),
+            Statement::CreateIndex {
+                name,
                 table_name,
                 columns,
-                where_clause,
-            } => self.execute_select(table_name, columns),
+                unique,
+            } => {
+                self.catalog
+                    .create_index(&name, &table_name, columns, unique)?;
+                Ok(ExecutionResult::Success {
+                    message: format!("Index '{}' created.", name),
+                })
+            }
+            Statement::DropIndex { name } => {
+                self.catalog.drop_index(&name)?;
+                Ok(ExecutionResult::Success {
+                    message: format!("Index '{}' dropped.", name),
+                })
+            }
+            insert @ Statement::Insert { .. } => self.execute_insert(insert),
+            update @ Statement::Update { .. } => self.execute_update(update),
+            delete @ Statement::Delete { .. } => self.execute_delete(delete),
+            select @ Statement::Select { .. } => self.execute_select(select, None),
+            Statement::Pragma { name, value } => self.execute_pragma(name, value),
+            Statement::Explain(statement) => self.explain(*statement),
+            Statement::Checkpoint => {
+                self.catalog.checkpoint()?;
+                Ok(ExecutionResult::Success {
+                    message: "Checkpoint complete.".to_string(),
+                })
+            }
+            Statement::Analyze { table_name } => self.execute_analyze(table_name),
+            Statement::Savepoint { name } => {
+                self.catalog.savepoint(&name)?;
+                Ok(ExecutionResult::Success {
+                    message: format!("Savepoint '{}' set.", name),
+                })
+            }
+            Statement::Release { name } => {
+                self.catalog.release(&name)?;
+                Ok(ExecutionResult::Success {
+                    message: format!("Savepoint '{}' released.", name),
+                })
+            }
+            Statement::RollbackTo { name } => {
+                self.catalog.rollback_to(&name)?;
+                Ok(ExecutionResult::Success {
+                    message: format!("Rolled back to savepoint '{}'.", name),
+                })
+            }
+            Statement::Attach { path, alias } => self.execute_attach(path, alias),
+            Statement::Detach { alias } => self.execute_detach(alias),
+        }?;
+
+        // Each statement is its own commit boundary, unless a savepoint is
+        // open; releasing the outermost one commits everything since
+        if !self.catalog.in_savepoint() {
+            self.catalog.commit()?;
+        }
+
+        Ok(result)
+    }
+
+    /// Run `statements` in order, each with its own result. After one fails
+    /// the rest are skipped, unless `continue_on_error`.
+    pub fn execute_all(
+        &mut self,
+        statements: Vec<Statement>,
+        continue_on_error: bool,
+    ) -> Vec<Result<ExecutionResult>> {
+        let mut results = Vec::new();
+        for statement in statements {
+            let result = self.execute(statement);
+            let failed = result.is_err();
+            results.push(result);
+            if failed && !continue_on_error {
+                break;
+            }
+        }
+        results
+    }
+
+    /// Run a read-only statement through a shared reference.
+    ///
+    /// Several threads can call this at once (e.g. with the executor behind an
+    /// `RwLock`, readers taking `read()`); their page reads run concurrently.
+    /// Statements that change anything have to go through `execute`.
+    pub fn query(&self, statement: Statement) -> Result<ExecutionResult> {
+        let (database, statement) = attach::route(statement)?;
+        if let Some(database) = database {
+            return Err(HozonError::Unsupported(format!(
+                "Tables of attached database '{}' can't be read by a read-only query; use execute",
+                database
+            )));
+        }
+        let _running = Running::start(&self.interrupt, self.statement_timeout);
+        match statement {
+            select @ Statement::Select { .. } => self.execute_select(select, None),
+            Statement::Explain(statement) => self.explain(*statement),
+            _ => Err(HozonError::InvalidInput(
+                "Only SELECT can run as a read-only query; use execute".to_string(),
+            )),
+        }
+    }
+
+    /// The aliases of the attached databases, in the order they were attached
+    pub fn attached(&self) -> impl Iterator<Item = &str> {
+        self.attached.iter().map(|(alias, _)| alias.as_str())
+    }
+
+    fn execute_attach(&mut self, path: String, alias: String) -> Result<ExecutionResult> {
+        if alias.eq_ignore_ascii_case(attach::MAIN) || self.attached_index(&alias).is_some() {
+            return Err(HozonError::AlreadyExists(format!(
+                "Database '{}' is already in use",
+                alias
+            )));
+        }
+        let catalog = TableCatalog::new(PageManager::new(&path)?)?;
+        self.attached.push((alias.clone(), catalog));
+        Ok(ExecutionResult::Success {
+            message: format!("Database '{}' attached as '{}'.", path, alias),
+        })
+    }
+
+    fn execute_detach(&mut self, alias: String) -> Result<ExecutionResult> {
+        let index = self.attached_index(&alias).ok_or_else(|| {
+            HozonError::NotFound(format!("No database is attached as '{}'", alias))
+        })?;
+        self.attached.remove(index);
+        Ok(ExecutionResult::Success {
+            message: format!("Database '{}' detached.", alias),
+        })
+    }
+
+    fn attached_index(&self, alias: &str) -> Option<usize> {
+        self.attached
+            .iter()
+            .position(|(name, _)| name.eq_ignore_ascii_case(alias))
+    }
+
+    /// Run `statement`, its table names unqualified, in the database
+    /// attached as `alias`, with the attached catalog standing in for this
+    /// executor's own until it's done
+    fn execute_attached(&mut self, alias: &str, statement: Statement) -> Result<ExecutionResult> {
+        let index = self.attached_index(alias).ok_or_else(|| {
+            HozonError::NotFound(format!("No database is attached as '{}'", alias))
+        })?;
+        std::mem::swap(&mut self.catalog, &mut self.attached[index].1);
+        let result = self.execute(statement);
+        std::mem::swap(&mut self.catalog, &mut self.attached[index].1);
+        result
+    }
+
+    /// `execute`, with the AUTOINCREMENT column value `statement` inserted,
+    /// None if it inserted none (while `last_insert_id` keeps the one before)
+    pub fn execute_with_insert_id(
+        &mut self,
+        statement: Statement,
+    ) -> Result<(ExecutionResult, Option<i64>)> {
+        let before = self.last_insert_id.take();
+        let result = self.execute(statement);
+        let inserted_id = self.last_insert_id;
+        if inserted_id.is_none() {
+            self.last_insert_id = before;
+        }
+        Ok((result?, inserted_id))
+    }
+
+    /// Parse `sql` once to run with different parameter values
+    pub fn prepare(&self, sql: &str) -> Result<PreparedStatement> {
+        PreparedStatement::new(sql)
+    }
+
+    /// Run `prepared` with `values` for its parameters
+    pub fn execute_prepared(
+        &mut self,
+        prepared: &PreparedStatement,
+        values: &[Value],
+    ) -> Result<ExecutionResult> {
+        self.execute(prepared.bind(values)?)
+    }
+
+    /// Run a subquery of the query whose row is `outer`
+    pub(crate) fn query_within(
+        &self,
+        select: Statement,
+        outer: Option<&Outer>,
+    ) -> Result<ExecutionResult> {
+        self.execute_select(select, outer)
+    }
+
+    fn execute_pragma(&mut self, name: String, value: Option<String>) -> Result<ExecutionResult> {
+        match name.to_lowercase().as_str() {
+            "synchronous" => match value {
+                Some(value) => {
+                    let mode = SyncMode::parse(&value).ok_or_else(|| {
+                        HozonError::InvalidInput(format!(
+                            "Invalid synchronous mode '{}': expected OFF, NORMAL or FULL",
+                            value
+                        ))
+                    })?;
+                    self.catalog.set_sync_mode(mode)?;
+                    Ok(ExecutionResult::Success {
+                        message: format!("synchronous set to {}.", mode.as_str()),
+                    })
+                }
+                None => Ok(ExecutionResult::Rows {
+                    columns: vec!["synchronous".to_string()],
+                    rows: vec![Row::new(vec![Value::Text(
+                        self.catalog.sync_mode().as_str().to_string(),
+                    )])],
+                }),
+            },
+            "identifier_case" => match value {
+                Some(value) => {
+                    let case = IdentifierCase::parse(&value).ok_or_else(|| {
+                        HozonError::InvalidInput(format!(
+                            "Invalid identifier_case '{}': expected INSENSITIVE or SENSITIVE",
+                            value
+                        ))
+                    })?;
+                    self.catalog.set_identifier_case(case);
+                    Ok(ExecutionResult::Success {
+                        message: format!("identifier_case set to {}.", case.as_str()),
+                    })
+                }
+                None => Ok(ExecutionResult::Rows {
+                    columns: vec!["identifier_case".to_string()],
+                    rows: vec![Row::new(vec![Value::Text(
+                        self.catalog.identifier_case().as_str().to_string(),
+                    )])],
+                }),
+            },
+            // Milliseconds between background checkpoints, 0 = off
+            "checkpoint_interval" => match value {
+                Some(value) => {
+                    let millis: u64 = value.parse().map_err(|_| {
+                        HozonError::InvalidInput(format!(
+                            "Invalid checkpoint_interval '{}': expected milliseconds",
+                            value
+                        ))
+                    })?;
+                    let interval = (millis > 0).then(|| Duration::from_millis(millis));
+                    self.catalog.set_checkpoint_interval(interval)?;
+                    Ok(ExecutionResult::Success {
+                        message: format!("checkpoint_interval set to {}.", millis),
+                    })
+                }
+                None => {
+                    let millis = self
+                        .catalog
+                        .checkpoint_interval()
+                        .map_or(0, |interval| interval.as_millis() as i32);
+                    Ok(ExecutionResult::Rows {
+                        columns: vec!["checkpoint_interval".to_string()],
+                        rows: vec![Row::new(vec![Value::Integer(millis)])],
+                    })
+                }
+            },
+            "strict" => match value {
+                Some(value) => {
+                    self.strict = match value.to_ascii_uppercase().as_str() {
+                        "ON" | "TRUE" | "1" => true,
+                        "OFF" | "FALSE" | "0" => false,
+                        _ => {
+                            return Err(HozonError::InvalidInput(format!(
+                                "Invalid strict '{}': expected ON or OFF",
+                                value
+                            )));
+                        }
+                    };
+                    Ok(ExecutionResult::Success {
+                        message: format!(
+                            "strict set to {}.",
+                            if self.strict { "ON" } else { "OFF" }
+                        ),
+                    })
+                }
+                None => Ok(ExecutionResult::Rows {
+                    columns: vec!["strict".to_string()],
+                    rows: vec![Row::new(vec![Value::Boolean(self.strict)])],
+                }),
+            },
+            // Milliseconds a statement may run, 0 = no limit
+            "statement_timeout" => match value {
+                Some(value) => {
+                    let millis: u64 = value.parse().map_err(|_| {
+                        HozonError::InvalidInput(format!(
+                            "Invalid statement_timeout '{}': expected milliseconds",
+                            value
+                        ))
+                    })?;
+                    self.statement_timeout = (millis > 0).then(|| Duration::from_millis(millis));
+                    Ok(ExecutionResult::Success {
+                        message: format!("statement_timeout set to {}.", millis),
+                    })
+                }
+                None => {
+                    let millis = self
+                        .statement_timeout
+                        .map_or(0, |timeout| timeout.as_millis() as i32);
+                    Ok(ExecutionResult::Rows {
+                        columns: vec!["statement_timeout".to_string()],
+                        rows: vec![Row::new(vec![Value::Integer(millis)])],
+                    })
+                }
+            },
+            _ => Err(HozonError::InvalidInput(format!(
+                "Unknown pragma '{}'",
+                name
+            ))),
         }
     }
 
@@ -46,244 +520,1177 @@ impl Executor {
         &mut self,
         table_name: String,
         columns: Vec<Column>,
-    ) -> io::Result<ExecutionResult> {
-        let schema = Schema::new(&table_name, columns);
+    ) -> Result<ExecutionResult> {
+        let schema = Schema::new(&table_name, columns)?;
         self.catalog.create_table(schema)?;
         Ok(ExecutionResult::Success {
             message: format!("Table '{}' created.", table_name),
         })
     }
 
-    fn execute_insert(
-        &mut self,
-        table_name: String,
-        values: Vec<Value>,
-    ) -> io::Result<ExecutionResult> {
-        // Get table metadata
-        let (first_page, columns) = match self.catalog.get_table(&table_name) {
-            Some(meta) => (meta.first_page(), meta.schema().columns()),
+    fn execute_create_view(&mut self, name: String, query: String) -> Result<ExecutionResult> {
+        // Running the query once checks everything it refers to exists
+        self.query(parse_view(&query)?)?;
+        self.catalog.create_view(&name, &query)?;
+        Ok(ExecutionResult::Success {
+            message: format!("View '{}' created.", name),
+        })
+    }
+
+    fn execute_analyze(&mut self, table_name: Option<String>) -> Result<ExecutionResult> {
+        let message = match table_name {
+            Some(table_name) => {
+                self.catalog.analyze(&table_name)?;
+                format!("Table '{}' analyzed.", table_name)
+            }
             None => {
-                return Err(Error::new(
-                    ErrorKind::NotFound,
-                    format!("Table '{}' does not exist", table_name),
-                ));
+                let tables = self.catalog.list_tables();
+                for table_name in &tables {
+                    self.catalog.analyze(table_name)?;
+                }
+                match tables.len() {
+                    1 => "1 table analyzed.".to_string(),
+                    n => format!("{} tables analyzed.", n),
+                }
             }
         };
+        Ok(ExecutionResult::Success { message })
+    }
 
-        // Validate value count
-        if values.len() != columns.len() {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                format!("Expected {} values, got {}", columns.len(), values.len()),
-            ));
-        }
-
-        // Validate data types
-        for (value, column) in values.iter().zip(columns.iter()) {
-            let valid = match (value, column.data_type()) {
-                (Value::Integer(_), crate::catalog::schema::DataType::Integer) => true,
-                (Value::Text(_), crate::catalog::schema::DataType::Text) => true,
-                (Value::Boolean(_), crate::catalog::schema::DataType::Boolean) => true,
-                (Value::Null, crate::catalog::schema::DataType::Null) => true,
-                (Value::Null, _) => true, // NULL can go in any column
-                _ => false,
-            };
+    fn execute_insert(&mut self, insert: Statement) -> Result<ExecutionResult> {
+        let Statement::Insert {
+            table_name,
+            columns: column_names,
+            values: rows,
+            on_conflict,
+            returning,
+        } = insert
+        else {
+            unreachable!("execute_insert takes an INSERT")
+        };
 
-            if !valid {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    format!(
-                        "Type mismatch for column '{}': expected {:?}, got {:?}",
-                        column.name(),
-                        column.data_type(),
-                        value
-                    ),
-                ));
+        // Get table metadata
+        let case = self.catalog.identifier_case();
+        let (columns, last_sequence) = match self.catalog.get_table(&table_name) {
+            Some(meta) => (meta.schema().columns().to_vec(), meta.sequence()),
+            None => {
+                return Err(HozonError::TableNotFound(format!(
+                    "Table '{}' does not exist",
+                    table_name
+                )));
             }
-        }
-
-        // Read existing page data
-        let mut page_data = self.catalog.read_page(first_page)?;
-
-        let page_meta = PageManager::read_metadata_from_buffer(&page_data);
-        let offset = page_meta.last_offset;
-
-        // Serialize new row
-        let row_bytes = Row::new(values).to_bytes();
-
-        // Check if it fits
-        // TODO: multiple page support
-        if offset + row_bytes.len() > PAGE_SIZE {
-            return Err(Error::new(
-                ErrorKind::OutOfMemory,
-                "Page full - multiple page support not yet implemented",
-            ));
-        }
-
-        // Write row bytes to page
-        // TODO: update is_full based on when page is actually full
-        page_data[offset..offset + row_bytes.len()].copy_from_slice(&row_bytes);
-        let metadata = PageMetadata {
-            is_full: page_meta.is_full,
-            last_offset: offset + row_bytes.len(),
-            num_rows: page_meta.num_rows + 1,
         };
+        self.check_returning(&table_name, &returning)?;
 
-        // update page metadata
-        PageManager::update_metadata_in_buffer(&mut page_data, &metadata);
+        let pk = columns.iter().position(|c| c.is_primary_key());
+        if let Some(OnConflict { target, .. }) = &on_conflict
+            && !target.is_empty()
+            && !pk.is_some_and(|pk| targets_key(target, &[columns[pk].name()], case))
+            && !self.catalog.table_indexes(&table_name).iter().any(|index| {
+                let key: Vec<&str> = index.columns().iter().map(String::as_str).collect();
+                index.is_unique() && targets_key(target, &key, case)
+            })
+        {
+            return Err(HozonError::InvalidStatement(format!(
+                "ON CONFLICT ({}) does not match the PRIMARY KEY or a UNIQUE index of table '{}'",
+                target.join(", "),
+                table_name
+            )));
+        }
 
-        // Write page back
-        self.catalog.write_page(first_page, &page_data)?;
+        // Rows go in one at a time, so each sees the keys of those before
+        // it; if one fails, those already written are put back
+        let last_insert_id = self.last_insert_id;
+        let multi_row = rows.len() > 1;
+        let mut undo = Vec::new();
+        let mut written = Vec::new();
+        let mut kind = None;
+        for (i, row) in rows.into_iter().enumerate() {
+            match self.insert_values(
+                &table_name,
+                column_names.as_deref(),
+                row,
+                &on_conflict,
+                &mut undo,
+            ) {
+                Ok(Some((row, row_kind))) => {
+                    written.push(row);
+                    kind = match kind {
+                        Some(kind) if kind != row_kind => Some(StatementKind::Insert),
+                        _ => Some(row_kind),
+                    };
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    self.undo_insert(&table_name, undo, last_sequence)?;
+                    self.last_insert_id = last_insert_id;
+                    return Err(match multi_row {
+                        true => err.context(format!("Row {}", i + 1)),
+                        false => err,
+                    });
+                }
+            }
+        }
+        let mut rows = written.iter();
+        for inserted in &undo {
+            if let Inserted::Deleted(rowid, old) = inserted {
+                self.hooks.deleted(&table_name, *rowid, old);
+                continue;
+            }
+            let (rowid, row) = rows.next().expect("every other change wrote a row");
+            match inserted {
+                Inserted::Replaced(_, old) => self.hooks.updated(&table_name, *rowid, old, row),
+                _ => self.hooks.inserted(&table_name, *rowid, row),
+            }
+        }
 
-        Ok(ExecutionResult::Success {
-            message: "1 row inserted.".to_string(),
+        Ok(match returning {
+            Some(columns) => self.returned(&table_name, columns, &written)?,
+            None => ExecutionResult::Affected {
+                kind: kind.unwrap_or(StatementKind::Insert),
+                rows: written.len(),
+            },
         })
     }
 
-    fn execute_select(
+    /// Write one row of an INSERT, recording what it changed in `undo`.
+    /// Returns the row written and what was done, or None if it was
+    /// skipped for a taken key.
+    fn insert_values(
         &mut self,
-        table_name: String,
-        select_columns: SelectColumns,
-    ) -> io::Result<ExecutionResult> {
-        // Get table metadata
-        let (first_page, columns) = match self.catalog.get_table(&table_name) {
-            Some(meta) => (meta.first_page(), meta.schema().columns()),
-            None => {
-                return Err(Error::new(
-                    ErrorKind::NotFound,
-                    format!("Table '{}' does not exist", table_name),
-                ));
+        table_name: &str,
+        column_names: Option<&[String]>,
+        values: Vec<Expr>,
+        on_conflict: &Option<OnConflict>,
+        undo: &mut Vec<Inserted>,
+    ) -> Result<Option<(RowWithId, StatementKind)>> {
+        let case = self.catalog.identifier_case();
+        let meta = self
+            .catalog
+            .get_table(table_name)
+            .expect("execute_insert checked the table exists");
+        let schema = meta.schema();
+        let last_sequence = meta.sequence();
+        let columns = schema.columns();
+        let values = values
+            .into_iter()
+            .map(|expr| match expr {
+                Expr::Literal(value) => Ok(value),
+                Expr::Parameter(n) => Err(eval::unbound(n)),
+                _ => Err(HozonError::InvalidStatement(
+                    "INSERT VALUES must be literals".to_string(),
+                )),
+            })
+            .collect::<Result<Vec<Value>>>()?;
+
+        // Put listed values in schema order; the rest start out NULL
+        let mut values = match column_names {
+            Some(names) => {
+                if names.len() != values.len() {
+                    return Err(HozonError::InvalidStatement(format!(
+                        "Expected {} values, got {}",
+                        names.len(),
+                        values.len()
+                    )));
+                }
+
+                let mut row = vec![Value::Null; columns.len()];
+                for (i, (name, value)) in names.iter().zip(values).enumerate() {
+                    let idx = schema.column_index(name, case).ok_or_else(|| {
+                        HozonError::ColumnNotFound(format!(
+                            "Column '{}' does not exist in table '{}'",
+                            name, table_name
+                        ))
+                    })?;
+                    if names[..i].iter().any(|n| n.eq_ignore_ascii_case(name)) {
+                        return Err(HozonError::InvalidStatement(format!(
+                            "Column '{}' is listed more than once",
+                            name
+                        )));
+                    }
+                    row[idx] = value;
+                }
+                row
             }
+            None => values,
         };
 
-        // Read page data
-        let page_data = self.catalog.read_page(first_page)?;
-        let page_meta = PageManager::read_metadata_from_buffer(&page_data);
-        // Extract column names
-        let all_column_names: Vec<String> = columns.iter().map(|c| c.name().to_string()).collect();
+        // Validate value count
+        if values.len() != columns.len() {
+            return Err(HozonError::InvalidStatement(format!(
+                "Expected {} values, got {}",
+                columns.len(),
+                values.len()
+            )));
+        }
 
-        // check if there are any rows in this table
-        if page_meta.num_rows == 0 {
-            return Ok(ExecutionResult::Rows {
-                columns: all_column_names,
-                rows: Vec::<Row>::new(),
-            });
+        // A NULL AUTOINCREMENT column takes the next sequence value; an explicit
+        // one moves the sequence past it
+        let mut new_sequence = None;
+        let auto_column = columns.iter().position(|c| c.is_auto_increment());
+        if let Some(idx) = auto_column {
+            match values[idx] {
+                Value::Null => {
+                    let next = last_sequence + 1;
+                    values[idx] = match columns[idx].data_type() {
+                        DataType::BigInt => Value::BigInt(next),
+                        _ => Value::Integer(i32::try_from(next).map_err(|_| {
+                            HozonError::InvalidStatement(format!(
+                                "AUTOINCREMENT sequence of table '{}' is exhausted",
+                                table_name
+                            ))
+                        })?),
+                    };
+                    new_sequence = Some(next);
+                }
+                Value::Integer(id) if id as i64 > last_sequence => new_sequence = Some(id as i64),
+                Value::BigInt(id) if id > last_sequence => new_sequence = Some(id),
+                _ => {}
+            }
         }
 
-        // Parse all rows from the page
-        let mut rows = Vec::new();
-        let mut offset = PAGE_DATA_START;
+        conform(table_name, columns, &mut values, self.conversion())?;
+
+        // Primary key must be unique; NULL was already rejected above. So
+        // must the keys of UNIQUE indexes, but those only need looking up to
+        // resolve: without a conflict clause writing the row refuses them
+        let pk = columns.iter().position(|c| c.is_primary_key());
+        let conflict = match pk {
+            Some(pk) => self.find_key(table_name, pk, &values[pk], None)?,
+            None => None,
+        };
+        let unique_conflicts = match on_conflict {
+            Some(_) => {
+                let row = Row::new(values.clone());
+                self.catalog.unique_conflicts(table_name, &row)?
+            }
+            None => Vec::new(),
+        };
 
-        for _ in 0..page_meta.num_rows {
-            let (row, byte_consumed) = Row::from_bytes(&page_data[offset..])?;
-            rows.push(row);
-            offset += byte_consumed;
+        // The clause resolves conflicts on the key it targets, or on any key
+        // if it names none; one on another key still fails
+        let resolves = |key: &[&str]| match on_conflict {
+            Some(OnConflict { target, .. }) => target.is_empty() || targets_key(target, key, case),
+            None => false,
+        };
+        if conflict.is_some() {
+            let pk = pk.expect("only a primary key conflicts");
+            if !resolves(&[columns[pk].name()]) {
+                return Err(duplicate_key(table_name, &columns[pk], &values[pk]));
+            }
         }
+        let resolved: Vec<i64> = unique_conflicts
+            .iter()
+            .filter(|(key, _)| resolves(&key.iter().map(String::as_str).collect::<Vec<_>>()))
+            .map(|&(_, rowid)| rowid)
+            .collect();
+        let conflict = match (conflict, resolved.first()) {
+            (Some(conflict), _) => Some(conflict),
+            (None, Some(&rowid)) => {
+                let old = self.catalog.get_row(table_name, rowid)?;
+                Some((rowid, old.expect("an index only holds live rows")))
+            }
+            (None, None) => None,
+        };
+        let action = match (&conflict, on_conflict) {
+            (Some(_), Some(on_conflict)) => Some(on_conflict.action.clone()),
+            _ => None,
+        };
 
-        // Handle column selection
-        match select_columns {
-            SelectColumns::All => Ok(ExecutionResult::Rows {
-                columns: all_column_names,
-                rows,
-            }),
-            SelectColumns::Specific(requested_cols) => {
-                // Find indices of requested columns
-                let mut column_indices = Vec::new();
-                let mut result_column_names = Vec::new();
-
-                for req_col in &requested_cols {
-                    match all_column_names.iter().position(|c| c == req_col) {
-                        Some(idx) => {
-                            column_indices.push(idx);
-                            result_column_names.push(req_col.clone());
-                        }
-                        None => {
-                            return Err(Error::new(
-                                ErrorKind::InvalidData,
-                                format!(
-                                    "Column '{}' does not exist in table '{}'",
-                                    req_col, table_name
-                                ),
+        Ok(match (conflict, action) {
+            (Some(_), Some(ConflictAction::Nothing)) => None,
+            (Some((rowid, old)), Some(ConflictAction::Update(assignments))) => {
+                let updated =
+                    self.conflict_update(table_name, columns, old.clone(), values, &assignments)?;
+                let pk = pk.expect("only a primary key conflicts");
+                if self
+                    .find_key(table_name, pk, &updated[pk], Some(rowid))?
+                    .is_some()
+                {
+                    return Err(duplicate_key(table_name, &columns[pk], &updated[pk]));
+                }
+                let row = Row::new(updated);
+                self.advance_sequence(table_name, auto_column, last_sequence, [&row])?;
+                self.catalog.replace_row(table_name, rowid, row.clone())?;
+                undo.push(Inserted::Replaced(rowid, old));
+                Some(((rowid, row), StatementKind::Update))
+            }
+            (existing, _) => {
+                let inserted_id = auto_column.and_then(|idx| integer(&values[idx]));
+                let row = Row::new(values);
+                let (rowid, kind) = match existing {
+                    Some((rowid, old)) => {
+                        // Any other rows holding its keys go, too
+                        let mut others: Vec<i64> =
+                            resolved.into_iter().filter(|&id| id != rowid).collect();
+                        others.sort_unstable();
+                        others.dedup();
+                        for other in others {
+                            let old = self.catalog.get_row(table_name, other)?;
+                            self.catalog.delete_rows(table_name, &[other])?;
+                            undo.push(Inserted::Deleted(
+                                other,
+                                old.expect("an index only holds live rows"),
                             ));
                         }
+                        self.catalog.replace_row(table_name, rowid, row.clone())?;
+                        undo.push(Inserted::Replaced(rowid, old));
+                        (rowid, StatementKind::Replace)
+                    }
+                    None => {
+                        let rowid = self.catalog.insert_row(table_name, row.clone())?;
+                        undo.push(Inserted::New(rowid));
+                        (rowid, StatementKind::Insert)
                     }
+                };
+                if let Some(sequence) = new_sequence {
+                    self.catalog.set_sequence(table_name, sequence)?;
                 }
+                if inserted_id.is_some() {
+                    self.last_insert_id = inserted_id;
+                }
+                Some(((rowid, row), kind))
+            }
+        })
+    }
 
-                // Project rows to only include selected columns
-                let projected_rows: Vec<Row> = rows
-                    .iter()
-                    .map(|row| {
-                        let values: Vec<Value> = column_indices
-                            .iter()
-                            .filter_map(|&idx| row.get_value(idx).cloned())
-                            .collect();
-                        Row::new(values)
-                    })
-                    .collect();
-
-                Ok(ExecutionResult::Rows {
-                    columns: result_column_names,
-                    rows: projected_rows,
-                })
+    /// Put back the rows an INSERT wrote before one of its rows failed,
+    /// newest first, and the sequence as it was
+    fn undo_insert(
+        &mut self,
+        table_name: &str,
+        undo: Vec<Inserted>,
+        last_sequence: i64,
+    ) -> Result<()> {
+        for inserted in undo.into_iter().rev() {
+            match inserted {
+                Inserted::New(rowid) => self.catalog.delete_rows(table_name, &[rowid])?,
+                Inserted::Replaced(rowid, old) => {
+                    self.catalog.replace_row(table_name, rowid, old)?
+                }
+                // Back under a new rowid, as nothing else refers to it
+                Inserted::Deleted(_, old) => {
+                    self.catalog.insert_row(table_name, old)?;
+                }
             }
         }
+        let sequence = self
+            .catalog
+            .get_table(table_name)
+            .map(|meta| meta.sequence());
+        if sequence != Some(last_sequence) {
+            self.catalog.set_sequence(table_name, last_sequence)?;
+        }
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::catalog::schema::{Column, DataType};
-    use crate::storage::page::PageManager;
-    use std::fs;
+    fn execute_update(&mut self, update: Statement) -> Result<ExecutionResult> {
+        let Statement::Update {
+            table_name,
+            assignments,
+            where_clause,
+            returning,
+        } = update
+        else {
+            unreachable!("execute_update takes an UPDATE")
+        };
+        let (columns, last_sequence) = self.writable_table(&table_name)?;
+        self.check_returning(&table_name, &returning)?;
+        let (updated, assigned) =
+            self.updated_rows(&table_name, &columns, &assignments, where_clause)?;
 
-    fn cleanup(basename: &str) {
-        let _ = fs::remove_file(format!("{}.hdb", basename));
-        let _ = fs::remove_file(format!("{}.hdb.lock", basename));
-    }
+        if let Some(pk) = columns.iter().position(|c| c.is_primary_key())
+            && assigned.contains(&pk)
+        {
+            self.check_keys_unique(&table_name, &columns, pk, &updated)?;
+        }
+        let auto_column = columns.iter().position(|c| c.is_auto_increment());
+        self.advance_sequence(
+            &table_name,
+            auto_column,
+            last_sequence,
+            updated.iter().map(|(_, row)| row),
+        )?;
+        // The hooks are given the rows as they were
+        let old = match self.hooks.watches_updates() {
+            true => updated
+                .iter()
+                .map(|(rowid, _)| self.catalog.get_row(&table_name, *rowid))
+                .collect::<Result<Vec<_>>>()?,
+            false => Vec::new(),
+        };
+        self.catalog.replace_rows(&table_name, &updated)?;
+        for (old, (rowid, new)) in old.iter().zip(&updated) {
+            if let Some(old) = old {
+                self.hooks.updated(&table_name, *rowid, old, new);
+            }
+        }
 
-    fn create_test_executor(db_name: &str) -> Executor {
-        let pm = PageManager::new(&format!("{}.hdb", db_name)).unwrap();
-        let catalog = TableCatalog::new(pm).unwrap();
-        Executor::new(catalog)
+        Ok(match returning {
+            Some(columns) => self.returned(&table_name, columns, &updated)?,
+            None => ExecutionResult::Affected {
+                kind: StatementKind::Update,
+                rows: updated.len(),
+            },
+        })
     }
 
-    #[test]
-    fn test_execute_create_table() {
-        cleanup("test_exec_create");
+    fn execute_delete(&mut self, delete: Statement) -> Result<ExecutionResult> {
+        let Statement::Delete {
+            table_name,
+            where_clause,
+            returning,
+        } = delete
+        else {
+            unreachable!("execute_delete takes a DELETE")
+        };
+        let (columns, _) = self.writable_table(&table_name)?;
+        self.check_returning(&table_name, &returning)?;
+        let deleted = self.matching_rows(&table_name, &columns, where_clause)?;
 
-        let mut executor = create_test_executor("test_exec_create");
+        let rowids: Vec<i64> = deleted.iter().map(|(rowid, _)| *rowid).collect();
+        self.catalog.delete_rows(&table_name, &rowids)?;
+        for (rowid, row) in &deleted {
+            self.hooks.deleted(&table_name, *rowid, row);
+        }
 
-        let columns = vec![
-            Column::new("id", DataType::Integer),
-            Column::new("name", DataType::Text),
-        ];
+        Ok(match returning {
+            Some(columns) => self.returned(&table_name, columns, &deleted)?,
+            None => ExecutionResult::Affected {
+                kind: StatementKind::Delete,
+                rows: deleted.len(),
+            },
+        })
+    }
 
-        let statement = Statement::CreateTable {
-            name: "users".to_string(),
-            columns,
-        };
+    /// The columns and AUTOINCREMENT sequence of the stored table a write
+    /// goes to
+    fn writable_table(&self, table_name: &str) -> Result<(Vec<Column>, i64)> {
+        match self.catalog.get_table(table_name) {
+            Some(meta) => Ok((meta.schema().columns().to_vec(), meta.sequence())),
+            None if self.catalog.get_view(table_name).is_some() => Err(HozonError::InvalidInput(
+                format!("Cannot modify view '{}'", table_name),
+            )),
+            None => Err(HozonError::TableNotFound(format!(
+                "Table '{}' does not exist",
+                table_name
+            ))),
+        }
+    }
 
-        let result = executor.execute(statement).unwrap();
+    /// The rowids and rows of `table` that WHERE matches
+    fn matching_rows(
+        &self,
+        table_name: &str,
+        columns: &[Column],
+        where_clause: Option<Expr>,
+    ) -> Result<Vec<(i64, Row)>> {
+        let width = columns.len();
+        let scope = Scope::table(table_name, column_names(columns), true);
+        let condition = where_clause
+            .map(|expr| eval::bind(&expr, &self.context(&scope)))
+            .transpose()?;
 
-        match result {
-            ExecutionResult::Success { message } => {
-                assert!(message.contains("users"));
-                assert!(message.contains("created"));
+        let mut matching = Vec::new();
+        self.catalog.scan_views(table_name, |rowid, view| {
+            interrupt::check(&self.interrupt)?;
+            let value = |i| match i == width {
+                true => Value::BigInt(rowid),
+                false => view.get(i).unwrap_or(Value::Null),
+            };
+            if let Some(condition) = &condition
+                && !condition.matches(&value)?
+            {
+                return Ok(());
             }
-            _ => panic!("Expected Success result"),
+            matching.push((rowid, view.to_row()));
+            Ok(())
+        })?;
+        Ok(matching)
+    }
+
+    /// The rows an UPDATE changes, each with its new values, and the
+    /// positions of the columns it sets. Every new row is worked out from
+    /// the old ones before any is written.
+    fn updated_rows(
+        &self,
+        table_name: &str,
+        columns: &[Column],
+        assignments: &[Assignment],
+        where_clause: Option<Expr>,
+    ) -> Result<(Vec<RowWithId>, Vec<usize>)> {
+        let width = columns.len();
+        let scope = Scope::table(table_name, column_names(columns), true);
+        let context = self.context(&scope);
+        let mut targets: Vec<(usize, Bound)> = Vec::new();
+        for assignment in assignments {
+            let idx = assigned_column(columns, &assignment.column, table_name, &context)?;
+            if targets.iter().any(|(i, _)| *i == idx) {
+                return Err(HozonError::InvalidStatement(format!(
+                    "Column '{}' is assigned more than once",
+                    assignment.column
+                )));
+            }
+            targets.push((idx, eval::bind(&assignment.value, &context)?));
         }
 
-        cleanup("test_exec_create");
+        let mut updated = Vec::new();
+        for (rowid, row) in self.matching_rows(table_name, columns, where_clause)? {
+            let value = |i| match i == width {
+                true => Value::BigInt(rowid),
+                false => row_value(&row, i),
+            };
+            let mut values = row.values().clone();
+            for (idx, bound) in &targets {
+                values[*idx] = bound.eval(&value)?;
+            }
+            conform(table_name, columns, &mut values, self.conversion())?;
+            updated.push((rowid, Row::new(values)));
+        }
+        Ok((updated, targets.into_iter().map(|(idx, _)| idx).collect()))
     }
 
-    #[test]
-    fn test_execute_insert_single_row() {
-        cleanup("test_exec_insert");
+    /// Check RETURNING binds to `table`, before anything is written
+    fn check_returning(&self, table_name: &str, returning: &Option<SelectColumns>) -> Result<()> {
+        if let Some(columns) = returning {
+            self.returned(table_name, columns.clone(), &[])?;
+        }
+        Ok(())
+    }
 
-        let mut executor = create_test_executor("test_exec_insert");
+    /// A write's RETURNING rows: `columns` of each (rowid, row) written
+    fn returned(
+        &self,
+        table_name: &str,
+        columns: SelectColumns,
+        written: &[(i64, Row)],
+    ) -> Result<ExecutionResult> {
+        let (table_columns, _) = self.writable_table(table_name)?;
+        let width = table_columns.len();
+        let scope = Scope::table(table_name, column_names(&table_columns), true);
+        let plan = SelectPlan::new(&self.context(&scope), columns, Vec::new(), false)?;
 
-        // Create table
+        let mut rows = Vec::new();
+        for (rowid, row) in written {
+            plan.accept(
+                &|i| match i == width {
+                    true => Value::BigInt(*rowid),
+                    false => row_value(row, i),
+                },
+                &mut rows,
+            )?;
+        }
+        Ok(plan.finish(rows))
+    }
+
+    /// Fail if the rows of `table`, with `updated` in place of the rows of
+    /// the same rowids, would hold a primary key twice
+    fn check_keys_unique(
+        &self,
+        table_name: &str,
+        columns: &[Column],
+        pk: usize,
+        updated: &[(i64, Row)],
+    ) -> Result<()> {
+        let new_keys: HashMap<i64, &Value> = updated
+            .iter()
+            .map(|(rowid, row)| (*rowid, &row.values()[pk]))
+            .collect();
+        let mut keys = Vec::new();
+        self.catalog.scan_views(table_name, |rowid, view| {
+            keys.push(match new_keys.get(&rowid) {
+                Some(&key) => key.clone(),
+                None => view.get(pk).unwrap_or(Value::Null),
+            });
+            Ok(())
+        })?;
+
+        keys.sort_by(ordering::total_cmp);
+        match keys
+            .windows(2)
+            .find(|pair| ordering::total_cmp(&pair[0], &pair[1]).is_eq())
+        {
+            Some(pair) => Err(duplicate_key(table_name, &columns[pk], &pair[0])),
+            None => Ok(()),
+        }
+    }
+
+    /// Keep the AUTOINCREMENT sequence past any id in `rows`, so later
+    /// inserts don't generate one that's taken
+    fn advance_sequence<'a>(
+        &mut self,
+        table_name: &str,
+        auto_column: Option<usize>,
+        last_sequence: i64,
+        rows: impl IntoIterator<Item = &'a Row>,
+    ) -> Result<()> {
+        let Some(idx) = auto_column else {
+            return Ok(());
+        };
+        let highest = rows
+            .into_iter()
+            .filter_map(|row| integer(&row.values()[idx]))
+            .max();
+        match highest {
+            Some(id) if id > last_sequence => self.catalog.set_sequence(table_name, id),
+            _ => Ok(()),
+        }
+    }
+
+    /// The row of `table` other than `except` whose primary key column `pk`
+    /// holds `key`, with its rowid
+    fn find_key(
+        &self,
+        table_name: &str,
+        pk: usize,
+        key: &Value,
+        except: Option<i64>,
+    ) -> Result<Option<(i64, Row)>> {
+        let mut found = None;
+        self.catalog.scan_views(table_name, |rowid, view| {
+            if found.is_none()
+                && Some(rowid) != except
+                && view
+                    .get(pk)
+                    .is_some_and(|v| ordering::total_cmp(&v, key).is_eq())
+            {
+                found = Some((rowid, view.to_row()));
+            }
+            Ok(())
+        })?;
+        Ok(found)
+    }
+
+    /// The values of `existing` after ON CONFLICT DO UPDATE SET, which sees
+    /// its columns and the rejected row's as `excluded.column`
+    fn conflict_update(
+        &self,
+        table_name: &str,
+        columns: &[Column],
+        existing: Row,
+        excluded: Vec<Value>,
+        assignments: &[Assignment],
+    ) -> Result<Vec<Value>> {
+        let names = column_names(columns);
+        // Bare names are the existing row's
+        let scope = Scope::table(table_name, names.clone(), false)
+            .join(Scope::table("excluded", names, false).hidden());
+        let context = self.context(&scope);
+
+        let mut values = existing.values().clone();
+        let both: Vec<Value> = values.iter().cloned().chain(excluded).collect();
+        let mut assigned = Vec::new();
+        for assignment in assignments {
+            let idx = assigned_column(columns, &assignment.column, table_name, &context)?;
+            if assigned.contains(&idx) {
+                return Err(HozonError::InvalidStatement(format!(
+                    "Column '{}' is assigned more than once",
+                    assignment.column
+                )));
+            }
+            assigned.push(idx);
+            // Every value is computed from the row as it was
+            values[idx] = eval::bind(&assignment.value, &context)?.eval(&|i| both[i].clone())?;
+        }
+
+        conform(table_name, columns, &mut values, self.conversion())?;
+        Ok(values)
+    }
+
+    fn execute_select(&self, select: Statement, outer: Option<&Outer>) -> Result<ExecutionResult> {
+        self.with_plan(select, outer, |plan| self.run_plan(plan))
+    }
+
+    /// Plan `select`, a subquery of `outer`'s query if that's given, and
+    /// hand the plan to `f`
+    fn with_plan<T>(
+        &self,
+        select: Statement,
+        outer: Option<&Outer>,
+        f: impl FnOnce(QueryPlan) -> Result<T>,
+    ) -> Result<T> {
+        let Statement::Select {
+            table_name,
+            columns,
+            where_clause,
+            order_by,
+            distinct,
+            joins,
+        } = select
+        else {
+            unreachable!("with_plan takes a SELECT")
+        };
+        let case = self.catalog.identifier_case();
+
+        // The scope after each table, so each ON sees only the tables
+        // joined before it
+        let (scan, scope) = self.scan(&table_name)?;
+        let mut scopes = vec![scope];
+        let mut scans = Vec::new();
+        for join in &joins {
+            let scope = scopes.last().expect("starts with the first table");
+            if scope.has_table(&join.table_name, case) {
+                return Err(HozonError::InvalidInput(format!(
+                    "Table '{}' is joined more than once; table aliases aren't supported yet",
+                    join.table_name
+                )));
+            }
+            let (join_scan, right_scope) = self.scan(&join.table_name)?;
+            scopes.push(scope.clone().join(right_scope));
+            scans.push(join_scan);
+        }
+
+        let mut tables = vec![LogicalTable::new(scan, 0, scopes[0].len(), Vec::new())];
+        for ((join, scan), (left, scope)) in joins
+            .into_iter()
+            .zip(scans)
+            .zip(scopes.iter().zip(&scopes[1..]))
+        {
+            let on = self.predicates(join.on, &self.context_within(scope, outer))?;
+            let width = scope.len() - left.len();
+            tables.push(LogicalTable::new(scan, left.len(), width, on));
+        }
+
+        let context =
+            self.context_within(scopes.last().expect("starts with the first table"), outer);
+        let filter = match where_clause {
+            Some(condition) => self.predicates(condition, &context)?,
+            None => Vec::new(),
+        };
+        let select = SelectPlan::new(&context, columns, order_by.clone(), distinct)?;
+
+        let mut logical = LogicalPlan { tables, filter };
+        logical.optimize(select.columns());
+        f(logical.physical(&self.catalog, select, order_by))
+    }
+
+    /// `condition`'s conjuncts, each bound with `context`
+    fn predicates<'a>(&self, condition: Expr, context: &Context<'a>) -> Result<Vec<Predicate<'a>>> {
+        optimizer::conjuncts(condition)
+            .into_iter()
+            .map(|expr| {
+                Ok(Predicate {
+                    bound: eval::bind(&expr, context)?,
+                    expr,
+                })
+            })
+            .collect()
+    }
+
+    /// Run a planned SELECT
+    fn run_plan(&self, plan: QueryPlan) -> Result<ExecutionResult> {
+        let passes = |value: &dyn Fn(usize) -> Value| match &plan.filter {
+            Some(filter) => filter.bound.matches(&value),
+            None => Ok(true),
+        };
+        let mut kept = Vec::new();
+
+        if plan.source.scan.access == Access::RowCount {
+            let info = self.catalog.table_info(&plan.source.scan.table)?;
+            return Ok(plan.select.counted(info.row_count));
+        }
+
+        // A lone table's rows go straight into the projection
+        if plan.joins.is_empty() {
+            self.visit_source(&plan.source, |value| match passes(value)? {
+                true => plan.select.accept(&value, &mut kept),
+                false => Ok(()),
+            })?;
+            return Ok(plan.select.finish(kept));
+        }
+
+        let mut rows = self.source_rows(&plan.source)?;
+        for join in &plan.joins {
+            let right_rows = self.source_rows(&join.source)?;
+            rows = join::run(
+                &join.method,
+                &rows,
+                join.left_width,
+                &right_rows,
+                &join.on,
+                &self.interrupt,
+            )?;
+        }
+        for row in &rows {
+            let value = |i| row_value(row, i);
+            if passes(&value)? {
+                plan.select.accept(&value, &mut kept)?;
+            }
+        }
+        Ok(plan.select.finish(kept))
+    }
+
+    /// EXPLAIN: how `statement` would run, a step per row
+    fn explain(&self, statement: Statement) -> Result<ExecutionResult> {
+        let steps = match statement {
+            select @ Statement::Select { .. } => {
+                self.with_plan(select, None, |plan| Ok(plan.steps()))?
+            }
+            Statement::Update {
+                table_name,
+                where_clause,
+                ..
+            } => {
+                self.explain_write(&format!("UPDATE {}", table_name), &table_name, where_clause)?
+            }
+            Statement::Delete {
+                table_name,
+                where_clause,
+                ..
+            } => self.explain_write(
+                &format!("DELETE FROM {}", table_name),
+                &table_name,
+                where_clause,
+            )?,
+            Statement::Insert { table_name, .. } => {
+                self.writable_table(&table_name)?;
+                vec![format!("INSERT INTO {}", table_name)]
+            }
+            Statement::CreateTable { name, .. } => vec![format!("CREATE TABLE {}", name)],
+            Statement::CreateView { name, .. } => vec![format!("CREATE VIEW {}", name)],
+            Statement::CreateIndex {
+                name, table_name, ..
+            } => vec![format!("CREATE INDEX {} ON {}", name, table_name)],
+            Statement::DropIndex { name } => vec![format!("DROP INDEX {}", name)],
+            Statement::Pragma { name, .. } => vec![format!("PRAGMA {}", name)],
+            Statement::Checkpoint => vec!["CHECKPOINT".to_string()],
+            Statement::Analyze { table_name } => match table_name {
+                Some(table_name) => vec![format!("ANALYZE {}", table_name)],
+                None => vec!["ANALYZE".to_string()],
+            },
+            Statement::Savepoint { name } => vec![format!("SAVEPOINT {}", name)],
+            Statement::Release { name } => vec![format!("RELEASE {}", name)],
+            Statement::RollbackTo { name } => vec![format!("ROLLBACK TO {}", name)],
+            Statement::Attach { path, alias } => vec![format!("ATTACH '{}' AS {}", path, alias)],
+            Statement::Detach { alias } => vec![format!("DETACH {}", alias)],
+            Statement::Explain(_) => {
+                return Err(HozonError::InvalidStatement(
+                    "EXPLAIN can't explain EXPLAIN".to_string(),
+                ));
+            }
+        };
+        Ok(ExecutionResult::Rows {
+            columns: vec!["plan".to_string()],
+            rows: steps
+                .into_iter()
+                .map(|step| Row::new(vec![Value::Text(step)]))
+                .collect(),
+        })
+    }
+
+    /// The steps of an UPDATE or DELETE: scan the table, keep the rows
+    /// WHERE matches, then `action` them
+    fn explain_write(
+        &self,
+        action: &str,
+        table_name: &str,
+        where_clause: Option<Expr>,
+    ) -> Result<Vec<String>> {
+        let (columns, _) = self.writable_table(table_name)?;
+        let scope = Scope::table(table_name, column_names(&columns), true);
+        let mut steps = vec![format!("SCAN TABLE {}", table_name)];
+        if let Some(expr) = where_clause {
+            eval::bind(&expr, &self.context(&scope))?;
+            steps.push(format!("FILTER {}", plan::describe(&expr)));
+        }
+        steps.push(action.to_string());
+        Ok(steps)
+    }
+
+    /// Binding context for a query over `scope`; its subqueries run here
+    fn context<'a>(&'a self, scope: &'a Scope) -> Context<'a> {
+        self.context_within(scope, None)
+    }
+
+    /// `context` for a subquery of the query whose row is `outer`
+    fn context_within<'a>(&'a self, scope: &'a Scope, outer: Option<&'a Outer<'a>>) -> Context<'a> {
+        Context {
+            scope,
+            case: self.catalog.identifier_case(),
+            executor: self,
+            outer,
+        }
+    }
+
+    /// How to read every row of a table, view or system table, and the
+    /// scope its columns go by. Stored tables' rows end with their rowid.
+    fn scan(&self, table_name: &str) -> Result<(Scan, Scope)> {
+        let scan = |access| Scan {
+            table: table_name.to_string(),
+            access,
+        };
+        if let Some(meta) = self.catalog.get_table(table_name) {
+            let names = column_names(meta.schema().columns());
+            return Ok((scan(Access::Table), Scope::table(table_name, names, true)));
+        }
+
+        // A view's columns are those its query's plan returns
+        let (names, access) = match self.catalog.get_view(table_name) {
+            Some(query) => self.with_plan(parse_view(query)?, None, |plan| {
+                Ok((plan.select.names().to_vec(), Access::View(plan.steps())))
+            })?,
+            None => match system::system_table(&self.catalog, table_name) {
+                Some((names, _)) => (names, Access::System),
+                None => {
+                    return Err(HozonError::TableNotFound(format!(
+                        "Table '{}' does not exist",
+                        table_name
+                    )));
+                }
+            },
+        };
+        Ok((scan(access), Scope::table(table_name, names, false)))
+    }
+
+    /// Visit each row `source` reads that passes its filter, with a
+    /// function returning its values
+    fn visit_source(
+        &self,
+        source: &Source,
+        mut visit: impl FnMut(&dyn Fn(usize) -> Value) -> Result<()>,
+    ) -> Result<()> {
+        let mut passing = |value: &dyn Fn(usize) -> Value| {
+            interrupt::check(&self.interrupt)?;
+            if let Some(filter) = &source.filter
+                && !filter.bound.matches(&value)?
+            {
+                return Ok(());
+            }
+            visit(value)
+        };
+        let table_name = source.scan.table.as_str();
+        let rows = match &source.scan.access {
+            Access::Table | Access::Index { .. } => {
+                let found: Option<HashSet<i64>> = match &source.scan.access {
+                    Access::Index {
+                        name, lower, upper, ..
+                    } => {
+                        let entries = self.catalog.index_range(
+                            name,
+                            lower.as_ref().map(std::slice::from_ref),
+                            upper.as_ref().map(std::slice::from_ref),
+                        )?;
+                        Some(entries.into_iter().map(|(_, rowid)| rowid).collect())
+                    }
+                    _ => None,
+                };
+                // Stored rows end with their rowid
+                let rowid_position = source.width - 1;
+                return self.catalog.scan_views(table_name, |rowid, view| {
+                    if found.as_ref().is_some_and(|found| !found.contains(&rowid)) {
+                        return Ok(());
+                    }
+                    passing(&|i| match i == rowid_position {
+                        true => Value::BigInt(rowid),
+                        false => view.get(i).unwrap_or(Value::Null),
+                    })
+                });
+            }
+            // A view's rows are its query's result
+            Access::View(_) => {
+                let query = self
+                    .catalog
+                    .get_view(table_name)
+                    .expect("planned as a view");
+                match self.query(parse_view(query)?)? {
+                    ExecutionResult::Rows { rows, .. } => rows,
+                    _ => unreachable!("SELECT returns rows"),
+                }
+            }
+            Access::System => {
+                let (_, rows) = system::system_table(&self.catalog, table_name)
+                    .expect("planned as a system table");
+                rows
+            }
+            Access::RowCount => unreachable!("run_plan reads the row count itself"),
+        };
+        for row in &rows {
+            passing(&|i| row_value(row, i))?;
+        }
+        Ok(())
+    }
+
+    /// The rows `source` reads that pass its filter, with the columns the
+    /// query doesn't read left NULL
+    fn source_rows(&self, source: &Source) -> Result<Vec<Row>> {
+        let mut rows = Vec::new();
+        self.visit_source(source, |value| {
+            let values = (0..source.width)
+                .map(|i| match &source.read {
+                    Some(read) if !read[i] => Value::Null,
+                    _ => value(i),
+                })
+                .collect();
+            rows.push(Row::new(values));
+            Ok(())
+        })?;
+        Ok(rows)
+    }
+}
+
+/// Check `values` fit the `columns` of `table`, converting those
+/// `conversion` allows
+fn conform(
+    table_name: &str,
+    columns: &[Column],
+    values: &mut [Value],
+    conversion: Conversion,
+) -> Result<()> {
+    for (value, column) in values.iter_mut().zip(columns) {
+        if *value == Value::Null && !column.is_nullable() {
+            return Err(HozonError::ConstraintViolation(format!(
+                "Column '{}' of table '{}' cannot be NULL",
+                column.name(),
+                table_name
+            )));
+        }
+
+        *value = convert(value, column.data_type(), conversion).ok_or_else(|| {
+            HozonError::TypeMismatch(format!(
+                "Type mismatch for column '{}' of table '{}': expected {:?}, got {}",
+                column.name(),
+                table_name,
+                column.data_type(),
+                value.display_with(&ValueFormat::sql())
+            ))
+        })?;
+    }
+    Ok(())
+}
+
+/// A row of a table with its rowid
+type RowWithId = (i64, Row);
+
+/// A row one row of an INSERT wrote
+enum Inserted {
+    New(i64),
+    /// The rowid of a row replaced or updated, and the row it was
+    Replaced(i64, Row),
+    /// The rowid of a row OR REPLACE removed for holding a key, and the row
+    Deleted(i64, Row),
+}
+
+/// Whether an ON CONFLICT target names exactly the columns of `key`
+fn targets_key(target: &[String], key: &[&str], case: IdentifierCase) -> bool {
+    target.len() == key.len()
+        && target
+            .iter()
+            .all(|name| key.iter().any(|column| case.matches(column, name)))
+}
+
+fn column_names(columns: &[Column]) -> Vec<String> {
+    columns.iter().map(|c| c.name().to_string()).collect()
+}
+
+/// The position of the column a SET assignment names
+fn assigned_column(
+    columns: &[Column],
+    name: &str,
+    table_name: &str,
+    context: &Context,
+) -> Result<usize> {
+    columns
+        .iter()
+        .position(|c| context.case.matches(c.name(), name))
+        .ok_or_else(|| {
+            HozonError::ColumnNotFound(format!(
+                "Column '{}' does not exist in table '{}'",
+                name, table_name
+            ))
+        })
+}
+
+fn duplicate_key(table_name: &str, column: &Column, value: &Value) -> HozonError {
+    HozonError::ConstraintViolation(format!(
+        "Duplicate primary key {} for column '{}' of table '{}'",
+        value.display_with(&ValueFormat::sql()),
+        column.name(),
+        table_name
+    ))
+}
+
+/// An INTEGER or BIGINT value as i64
+fn integer(value: &Value) -> Option<i64> {
+    match value {
+        Value::Integer(n) => Some(*n as i64),
+        Value::BigInt(n) => Some(*n),
+        _ => None,
+    }
+}
+
+/// Parse a stored view definition back into its SELECT
+fn parse_view(query: &str) -> Result<Statement> {
+    match Parser::from_sql(query)?.parse()? {
+        statement @ Statement::Select { .. } => Ok(statement),
+        _ => Err(HozonError::InvalidStatement(format!(
+            "View definition is not a SELECT: {}",
+            query
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::schema::{Column, DataType};
+    use crate::sql::parser::{SelectColumns, SelectItem};
+    use crate::sql::tokenizer;
+    use crate::storage::page::{PAGE_DATA_START, PageManager};
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    fn cleanup(basename: &str) {
+        let _ = fs::remove_file(format!("{}.hdb", basename));
+        let _ = fs::remove_file(format!("{}.hdb.lock", basename));
+    }
+
+    fn create_test_executor(db_name: &str) -> Executor {
+        let pm = PageManager::new(&format!("{}.hdb", db_name)).unwrap();
+        let catalog = TableCatalog::new(pm).unwrap();
+        Executor::new(catalog)
+    }
+
+    /// A single row of INSERT values for `Statement::Insert`
+    fn literals(values: Vec<Value>) -> Vec<Vec<Expr>> {
+        vec![values.into_iter().map(Expr::Literal).collect()]
+    }
+
+    #[test]
+    fn test_execute_create_table() {
+        cleanup("test_exec_create");
+
+        let mut executor = create_test_executor("test_exec_create");
+
+        let columns = vec![
+            Column::new("id", DataType::Integer),
+            Column::new("name", DataType::Text),
+        ];
+
+        let statement = Statement::CreateTable {
+            name: "users".to_string(),
+            columns,
+        };
+
+        let result = executor.execute(statement).unwrap();
+
+        match result {
+            ExecutionResult::Success { message } => {
+                assert!(message.contains("users"));
+                assert!(message.contains("created"));
+            }
+            _ => panic!("Expected Success result"),
+        }
+
+        cleanup("test_exec_create");
+    }
+
+    #[test]
+    fn test_execute_insert_single_row() {
+        cleanup("test_exec_insert");
+
+        let mut executor = create_test_executor("test_exec_insert");
+
+        // Create table
         let columns = vec![
             Column::new("id", DataType::Integer),
             Column::new("name", DataType::Text),
@@ -300,15 +1707,18 @@ mod tests {
         let result = executor
             .execute(Statement::Insert {
                 table_name: "users".to_string(),
-                values,
+                columns: None,
+                values: literals(values),
+                on_conflict: None,
+                returning: None,
             })
             .unwrap();
 
         match result {
-            ExecutionResult::Success { message } => {
-                assert!(message.contains("1 row"));
+            ExecutionResult::Affected { kind, rows } => {
+                assert_eq!((kind, rows), (StatementKind::Insert, 1));
             }
-            _ => panic!("Expected Success result"),
+            _ => panic!("Expected Affected result"),
         }
 
         cleanup("test_exec_insert");
@@ -337,7 +1747,10 @@ mod tests {
             executor
                 .execute(Statement::Insert {
                     table_name: "users".to_string(),
-                    values,
+                    columns: None,
+                    values: literals(values),
+                    on_conflict: None,
+                    returning: None,
                 })
                 .unwrap();
         }
@@ -348,6 +1761,9 @@ mod tests {
                 table_name: "users".to_string(),
                 columns: SelectColumns::All,
                 where_clause: None,
+                order_by: Vec::new(),
+                distinct: false,
+                joins: Vec::new(),
             })
             .unwrap();
 
@@ -387,7 +1803,10 @@ mod tests {
         ];
         let result = executor.execute(Statement::Insert {
             table_name: "users".to_string(),
-            values,
+            columns: None,
+            values: literals(values),
+            on_conflict: None,
+            returning: None,
         });
 
         assert!(result.is_err());
@@ -418,7 +1837,10 @@ mod tests {
         ];
         let result = executor.execute(Statement::Insert {
             table_name: "users".to_string(),
-            values,
+            columns: None,
+            values: literals(values),
+            on_conflict: None,
+            returning: None,
         });
 
         assert!(result.is_err());
@@ -435,7 +1857,10 @@ mod tests {
         let values = vec![Value::Integer(1)];
         let result = executor.execute(Statement::Insert {
             table_name: "nonexistent".to_string(),
-            values,
+            columns: None,
+            values: literals(values),
+            on_conflict: None,
+            returning: None,
         });
 
         assert!(result.is_err());
@@ -464,11 +1889,14 @@ mod tests {
         executor
             .execute(Statement::Insert {
                 table_name: "users".to_string(),
-                values: vec![
+                columns: None,
+                values: literals(vec![
                     Value::Integer(1),
                     Value::Text("Alice".to_string()),
                     Value::Boolean(true),
-                ],
+                ]),
+                on_conflict: None,
+                returning: None,
             })
             .unwrap();
 
@@ -478,6 +1906,9 @@ mod tests {
                 table_name: "users".to_string(),
                 columns: SelectColumns::All,
                 where_clause: None,
+                order_by: Vec::new(),
+                distinct: false,
+                joins: Vec::new(),
             })
             .unwrap();
 
@@ -516,11 +1947,14 @@ mod tests {
         executor
             .execute(Statement::Insert {
                 table_name: "users".to_string(),
-                values: vec![
+                columns: None,
+                values: literals(vec![
                     Value::Integer(1),
                     Value::Text("Alice".to_string()),
                     Value::Text("alice@example.com".to_string()),
-                ],
+                ]),
+                on_conflict: None,
+                returning: None,
             })
             .unwrap();
 
@@ -528,8 +1962,14 @@ mod tests {
         let result = executor
             .execute(Statement::Select {
                 table_name: "users".to_string(),
-                columns: SelectColumns::Specific(vec!["name".to_string(), "id".to_string()]),
+                columns: SelectColumns::Specific(vec![
+                    SelectItem::column("name"),
+                    SelectItem::column("id"),
+                ]),
                 where_clause: None,
+                order_by: Vec::new(),
+                distinct: false,
+                joins: Vec::new(),
             })
             .unwrap();
 
@@ -572,14 +2012,20 @@ mod tests {
         executor
             .execute(Statement::Insert {
                 table_name: "users".to_string(),
-                values: vec![Value::Integer(1)],
+                columns: None,
+                values: literals(vec![Value::Integer(1)]),
+                on_conflict: None,
+                returning: None,
             })
             .unwrap();
 
         let result = executor.execute(Statement::Select {
             table_name: "users".to_string(),
-            columns: SelectColumns::Specific(vec!["nonexistent".to_string()]),
+            columns: SelectColumns::Specific(vec![SelectItem::column("nonexistent")]),
             where_clause: None,
+            order_by: Vec::new(),
+            distinct: false,
+            joins: Vec::new(),
         });
 
         assert!(result.is_err());
@@ -605,6 +2051,9 @@ mod tests {
                 table_name: "users".to_string(),
                 columns: SelectColumns::All,
                 where_clause: None,
+                order_by: Vec::new(),
+                distinct: false,
+                joins: Vec::new(),
             })
             .unwrap();
 
@@ -633,7 +2082,9 @@ mod tests {
                     Column::new("int_col", DataType::Integer),
                     Column::new("text_col", DataType::Text),
                     Column::new("bool_col", DataType::Boolean),
-                    Column::new("null_col", DataType::Null),
+                    Column::new("null_col", DataType::Text),
+                    Column::new("float_col", DataType::Float),
+                    Column::new("blob_col", DataType::Blob),
                 ],
             })
             .unwrap();
@@ -642,12 +2093,17 @@ mod tests {
         executor
             .execute(Statement::Insert {
                 table_name: "test".to_string(),
-                values: vec![
+                columns: None,
+                values: literals(vec![
                     Value::Integer(42),
                     Value::Text("hello".to_string()),
                     Value::Boolean(true),
                     Value::Null,
-                ],
+                    Value::Float(3.25),
+                    Value::Blob(vec![0xDE, 0xAD]),
+                ]),
+                on_conflict: None,
+                returning: None,
             })
             .unwrap();
 
@@ -657,6 +2113,9 @@ mod tests {
                 table_name: "test".to_string(),
                 columns: SelectColumns::All,
                 where_clause: None,
+                order_by: Vec::new(),
+                distinct: false,
+                joins: Vec::new(),
             })
             .unwrap();
 
@@ -664,16 +2123,24 @@ mod tests {
             ExecutionResult::Rows { rows, .. } => {
                 assert_eq!(rows.len(), 1);
                 let values = rows[0].values();
-                assert_eq!(values.len(), 4);
+                assert_eq!(values.len(), 6);
 
-                match (&values[0], &values[1], &values[2], &values[3]) {
-                    (Value::Integer(i), Value::Text(t), Value::Boolean(b), Value::Null) => {
+                match (&values[0], &values[1], &values[2], &values[3], &values[4]) {
+                    (
+                        Value::Integer(i),
+                        Value::Text(t),
+                        Value::Boolean(b),
+                        Value::Null,
+                        Value::Float(f),
+                    ) => {
                         assert_eq!(*i, 42);
                         assert_eq!(t, "hello");
-                        assert_eq!(*b, true);
+                        assert!(*b);
+                        assert_eq!(*f, 3.25);
                     }
                     _ => panic!("Unexpected value types"),
                 }
+                assert_eq!(values[5], Value::Blob(vec![0xDE, 0xAD]));
             }
             _ => panic!("Expected Rows result"),
         }
@@ -707,7 +2174,10 @@ mod tests {
         executor
             .execute(Statement::Insert {
                 table_name: "users".to_string(),
-                values: vec![Value::Integer(1)],
+                columns: None,
+                values: literals(vec![Value::Integer(1)]),
+                on_conflict: None,
+                returning: None,
             })
             .unwrap();
 
@@ -720,46 +2190,2524 @@ mod tests {
     }
 
     #[test]
-    fn test_null_values_in_any_column() {
-        cleanup("test_exec_nulls");
+    fn test_pragma_synchronous() {
+        cleanup("test_exec_pragma");
 
-        let mut executor = create_test_executor("test_exec_nulls");
+        let mut executor = create_test_executor("test_exec_pragma");
 
-        executor
-            .execute(Statement::CreateTable {
-                name: "users".to_string(),
-                columns: vec![
-                    Column::new("id", DataType::Integer),
-                    Column::new("name", DataType::Text),
-                ],
+        let result = executor
+            .execute(Statement::Pragma {
+                name: "synchronous".to_string(),
+                value: Some("off".to_string()),
             })
             .unwrap();
+        assert!(matches!(result, ExecutionResult::Success { .. }));
+        assert_eq!(executor.catalog.sync_mode(), SyncMode::Off);
 
-        // NULL can go in any column type
-        executor
-            .execute(Statement::Insert {
-                table_name: "users".to_string(),
-                values: vec![Value::Null, Value::Null],
+        let result = executor
+            .execute(Statement::Pragma {
+                name: "synchronous".to_string(),
+                value: None,
             })
             .unwrap();
+        match result {
+            ExecutionResult::Rows { rows, .. } => {
+                assert_eq!(rows[0].values()[0], Value::Text("OFF".to_string()));
+            }
+            _ => panic!("Expected Rows result"),
+        }
 
-        let result = executor
-            .execute(Statement::Select {
-                table_name: "users".to_string(),
-                columns: SelectColumns::All,
-                where_clause: None,
+        let result = executor.execute(Statement::Pragma {
+            name: "synchronous".to_string(),
+            value: Some("sometimes".to_string()),
+        });
+        assert!(result.is_err());
+
+        let result = executor.execute(Statement::Pragma {
+            name: "no_such_pragma".to_string(),
+            value: None,
+        });
+        assert!(result.is_err());
+
+        cleanup("test_exec_pragma");
+    }
+
+    #[test]
+    fn test_checkpoint_and_interval() {
+        cleanup("test_exec_checkpoint");
+
+        let mut executor = create_test_executor("test_exec_checkpoint");
+
+        let result = executor.execute(Statement::Checkpoint).unwrap();
+        assert!(matches!(result, ExecutionResult::Success { .. }));
+
+        executor
+            .execute(Statement::Pragma {
+                name: "checkpoint_interval".to_string(),
+                value: Some("250".to_string()),
             })
             .unwrap();
+        assert_eq!(
+            executor.catalog.checkpoint_interval(),
+            Some(Duration::from_millis(250))
+        );
 
-        match result {
+        executor
+            .execute(Statement::Pragma {
+                name: "checkpoint_interval".to_string(),
+                value: Some("0".to_string()),
+            })
+            .unwrap();
+        match executor
+            .execute(Statement::Pragma {
+                name: "checkpoint_interval".to_string(),
+                value: None,
+            })
+            .unwrap()
+        {
             ExecutionResult::Rows { rows, .. } => {
-                assert_eq!(rows.len(), 1);
-                assert!(matches!(rows[0].values()[0], Value::Null));
-                assert!(matches!(rows[0].values()[1], Value::Null));
+                assert_eq!(rows[0].values()[0], Value::Integer(0))
             }
             _ => panic!("Expected Rows result"),
         }
 
-        cleanup("test_exec_nulls");
+        let result = executor.execute(Statement::Pragma {
+            name: "checkpoint_interval".to_string(),
+            value: Some("soon".to_string()),
+        });
+        assert!(result.is_err());
+
+        drop(executor);
+        cleanup("test_exec_checkpoint");
+    }
+
+    #[test]
+    fn test_coercion_and_strict() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        run(
+            &mut executor,
+            "CREATE TABLE t (n INTEGER, ok BOOLEAN, f FLOAT, label TEXT);",
+        )
+        .unwrap();
+
+        run(&mut executor, "INSERT INTO t VALUES ('42', 1, 2, 7);").unwrap();
+        run(&mut executor, "UPDATE t SET ok = 'false', n = 3.0;").unwrap();
+        assert_eq!(
+            run(&mut executor, "SELECT * FROM t;").unwrap(),
+            "3|false|2.0|7"
+        );
+        // Nothing that would lose part of the value
+        for sql in [
+            "INSERT INTO t VALUES ('4.5', TRUE, 1.0, 'x');",
+            "INSERT INTO t VALUES (1, 2, 1.0, 'x');",
+            "UPDATE t SET n = 2.5;",
+        ] {
+            let err = run(&mut executor, sql).unwrap_err();
+            assert!(
+                matches!(err, HozonError::TypeMismatch(_)),
+                "{}: {:?}",
+                sql,
+                err
+            );
+        }
+
+        assert_eq!(
+            run(&mut executor, "PRAGMA strict = ON;").unwrap(),
+            "strict set to ON."
+        );
+        assert!(executor.is_strict());
+        assert_eq!(run(&mut executor, "PRAGMA strict;").unwrap(), "true");
+        let err = run(
+            &mut executor,
+            "INSERT INTO t VALUES ('42', TRUE, 1.0, 'x');",
+        )
+        .unwrap_err();
+        assert!(matches!(err, HozonError::TypeMismatch(_)), "{:?}", err);
+        // Widening still happens
+        run(&mut executor, "CREATE TABLE big (n BIGINT);").unwrap();
+        run(&mut executor, "INSERT INTO big VALUES (1);").unwrap();
+        assert!(run(&mut executor, "PRAGMA strict = maybe;").is_err());
+    }
+
+    #[test]
+    fn test_cancel_and_timeout() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        for sql in [
+            "CREATE TABLE t (n INTEGER);",
+            "INSERT INTO t VALUES (1);",
+            "INSERT INTO t VALUES (2);",
+        ] {
+            run(&mut executor, sql).unwrap();
+        }
+
+        assert_eq!(
+            run(&mut executor, "PRAGMA statement_timeout = 250;").unwrap(),
+            "statement_timeout set to 250."
+        );
+        assert_eq!(
+            executor.statement_timeout(),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(
+            run(&mut executor, "PRAGMA statement_timeout;").unwrap(),
+            "250"
+        );
+        assert!(run(&mut executor, "PRAGMA statement_timeout = soon;").is_err());
+
+        // Out of time at the first row, for reads, joins and writes alike
+        executor.set_statement_timeout(Some(Duration::ZERO));
+        for sql in [
+            "SELECT * FROM t;",
+            "SELECT * FROM t JOIN hozon_tables ON 1 = 1;",
+            "DELETE FROM t;",
+        ] {
+            let err = run(&mut executor, sql).unwrap_err();
+            assert!(
+                matches!(err, HozonError::Interrupted(_)),
+                "{}: {:?}",
+                sql,
+                err
+            );
+        }
+        run(&mut executor, "PRAGMA statement_timeout = 0;").unwrap();
+        assert_eq!(run(&mut executor, "SELECT * FROM t;").unwrap(), "1, 2");
+
+        // Cancelling with nothing running leaves later statements be
+        executor.cancel_token().cancel();
+        assert_eq!(
+            run(&mut executor, "SELECT n FROM t WHERE n > 1;").unwrap(),
+            "2"
+        );
+    }
+
+    #[test]
+    fn test_concurrent_readers_with_a_writer() {
+        cleanup("test_exec_concurrent");
+
+        let mut executor = create_test_executor("test_exec_concurrent");
+        executor
+            .execute(Statement::CreateTable {
+                name: "users".to_string(),
+                columns: vec![Column::new("id", DataType::Integer)],
+            })
+            .unwrap();
+
+        let select = || Statement::Select {
+            table_name: "users".to_string(),
+            columns: SelectColumns::All,
+            where_clause: None,
+            order_by: Vec::new(),
+            distinct: false,
+            joins: Vec::new(),
+        };
+        let insert = |id| Statement::Insert {
+            table_name: "users".to_string(),
+            columns: None,
+            values: literals(vec![Value::Integer(id)]),
+            on_conflict: None,
+            returning: None,
+        };
+        assert!(executor.query(insert(0)).is_err());
+
+        let executor = std::sync::RwLock::new(executor);
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let mut seen = 0;
+                    for _ in 0..20 {
+                        match executor.read().unwrap().query(select()).unwrap() {
+                            // Rows only ever appear, never half-written
+                            ExecutionResult::Rows { rows, .. } => {
+                                assert!(rows.len() >= seen);
+                                seen = rows.len();
+                            }
+                            _ => panic!("Expected Rows result"),
+                        }
+                    }
+                });
+            }
+            s.spawn(|| {
+                for id in 0..10 {
+                    executor.write().unwrap().execute(insert(id)).unwrap();
+                }
+            });
+        });
+
+        match executor.read().unwrap().query(select()).unwrap() {
+            ExecutionResult::Rows { rows, .. } => assert_eq!(rows.len(), 10),
+            _ => panic!("Expected Rows result"),
+        }
+
+        drop(executor);
+        cleanup("test_exec_concurrent");
+    }
+
+    #[test]
+    fn test_null_values_in_any_column() {
+        cleanup("test_exec_nulls");
+
+        let mut executor = create_test_executor("test_exec_nulls");
+
+        executor
+            .execute(Statement::CreateTable {
+                name: "users".to_string(),
+                columns: vec![
+                    Column::new("id", DataType::Integer),
+                    Column::new("name", DataType::Text),
+                ],
+            })
+            .unwrap();
+
+        // NULL can go in any nullable column
+        executor
+            .execute(Statement::Insert {
+                table_name: "users".to_string(),
+                columns: None,
+                values: literals(vec![Value::Null, Value::Null]),
+                on_conflict: None,
+                returning: None,
+            })
+            .unwrap();
+
+        let result = executor
+            .execute(Statement::Select {
+                table_name: "users".to_string(),
+                columns: SelectColumns::All,
+                where_clause: None,
+                order_by: Vec::new(),
+                distinct: false,
+                joins: Vec::new(),
+            })
+            .unwrap();
+
+        match result {
+            ExecutionResult::Rows { rows, .. } => {
+                assert_eq!(rows.len(), 1);
+                assert!(matches!(rows[0].values()[0], Value::Null));
+                assert!(matches!(rows[0].values()[1], Value::Null));
+            }
+            _ => panic!("Expected Rows result"),
+        }
+
+        cleanup("test_exec_nulls");
+    }
+
+    #[test]
+    fn test_primary_key_enforced() {
+        cleanup("test_exec_pk");
+
+        let mut executor = create_test_executor("test_exec_pk");
+        executor
+            .execute(Statement::CreateTable {
+                name: "users".to_string(),
+                columns: vec![
+                    Column::new("id", DataType::Integer).primary_key(),
+                    Column::new("name", DataType::Text),
+                ],
+            })
+            .unwrap();
+
+        let insert = |id: Value| Statement::Insert {
+            table_name: "users".to_string(),
+            columns: None,
+            values: literals(vec![id, Value::Text("alice".to_string())]),
+            on_conflict: None,
+            returning: None,
+        };
+        executor.execute(insert(Value::Integer(1))).unwrap();
+
+        let err = executor.execute(insert(Value::Integer(1))).unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+        assert!(err.to_string().contains("Duplicate primary key"));
+
+        let err = executor.execute(insert(Value::Null)).unwrap_err();
+        assert!(err.to_string().contains("cannot be NULL"));
+
+        executor.execute(insert(Value::Integer(2))).unwrap();
+        drop(executor);
+
+        // The flag survives reopening, so it is still enforced
+        let mut executor = create_test_executor("test_exec_pk");
+        let columns = executor
+            .catalog
+            .get_table("users")
+            .unwrap()
+            .schema()
+            .columns();
+        assert!(columns[0].is_primary_key());
+        assert!(executor.execute(insert(Value::Integer(2))).is_err());
+
+        drop(executor);
+        cleanup("test_exec_pk");
+    }
+
+    /// Run `sql`, returning its message, or its rows as `a|b, c|d`
+    fn run(executor: &mut Executor, sql: &str) -> Result<String> {
+        let statement = Parser::new(tokenizer::tokenize(sql)?).parse()?;
+        Ok(match executor.execute(statement)? {
+            ExecutionResult::Rows { rows, .. } => rows
+                .iter()
+                .map(|row| {
+                    let values: Vec<String> = row.values().iter().map(|v| v.to_string()).collect();
+                    values.join("|")
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+            result => result.message().expect("only rows have no message"),
+        })
+    }
+
+    #[test]
+    fn test_keywords_as_column_names() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        for sql in [
+            "CREATE TABLE kv (key TEXT PRIMARY KEY, value TEXT, index INTEGER);",
+            "INSERT INTO kv (key, value, index) VALUES ('a', 'x', 1);",
+            "INSERT INTO kv VALUES ('b', 'y', 2) ON CONFLICT (key) DO NOTHING;",
+            "UPDATE kv SET value = 'z' WHERE key = 'b';",
+        ] {
+            run(&mut executor, sql).unwrap();
+        }
+        assert_eq!(
+            run(
+                &mut executor,
+                "SELECT kv.key, value FROM kv ORDER BY index DESC;"
+            )
+            .unwrap(),
+            "b|z, a|x"
+        );
+    }
+
+    #[test]
+    fn test_create_and_drop_index() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        for sql in [
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT, team INTEGER);",
+            "INSERT INTO users VALUES (1, 'a@x', 1);",
+            "INSERT INTO users VALUES (2, 'b@x', 1);",
+            "INSERT INTO users VALUES (3, NULL, 2);",
+            "CREATE UNIQUE INDEX by_email ON users (email);",
+            "CREATE INDEX by_team ON users (team, id);",
+        ] {
+            run(&mut executor, sql).unwrap();
+        }
+        let indexes = executor.catalog().table_indexes("users");
+        assert_eq!(indexes.len(), 2);
+        assert!(indexes[0].is_unique());
+        assert_eq!(indexes[1].columns(), ["team", "id"]);
+
+        // Taken emails are rejected; NULLs never clash
+        let err = run(&mut executor, "INSERT INTO users VALUES (4, 'a@x', 2);").unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+        run(&mut executor, "INSERT INTO users VALUES (4, NULL, 2);").unwrap();
+        let err = run(
+            &mut executor,
+            "UPDATE users SET email = 'b@x' WHERE id = 1;",
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+        assert_eq!(
+            run(&mut executor, "SELECT email FROM users WHERE id = 1;").unwrap(),
+            "a@x"
+        );
+
+        // Entries follow updates and deletes
+        run(&mut executor, "UPDATE users SET team = 3 WHERE team = 1;").unwrap();
+        run(&mut executor, "DELETE FROM users WHERE id = 3;").unwrap();
+        let teams: Vec<i64> = executor
+            .catalog()
+            .index_entries("by_team")
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| integer(&key.0[0]).unwrap())
+            .collect();
+        assert_eq!(teams, [2, 3, 3]);
+        run(&mut executor, "DELETE FROM users WHERE id = 1;").unwrap();
+        run(&mut executor, "INSERT INTO users VALUES (5, 'a@x', 3);").unwrap();
+
+        let err = run(&mut executor, "CREATE INDEX by_email ON users (team);").unwrap_err();
+        assert!(matches!(err, HozonError::AlreadyExists(_)), "{:?}", err);
+        run(&mut executor, "DROP INDEX by_email;").unwrap();
+        run(&mut executor, "INSERT INTO users VALUES (6, 'a@x', 3);").unwrap();
+        let err = run(
+            &mut executor,
+            "CREATE UNIQUE INDEX by_email ON users (email);",
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+        let err = run(&mut executor, "DROP INDEX by_email;").unwrap_err();
+        assert!(matches!(err, HozonError::NotFound(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_index_search() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        run(&mut executor, "CREATE TABLE t (n INTEGER, label TEXT);").unwrap();
+        run(&mut executor, "CREATE INDEX by_n ON t (n);").unwrap();
+        run(&mut executor, "INSERT INTO t VALUES (1, 'one');").unwrap();
+        // On one row a scan is cheapest
+        assert_eq!(
+            run(&mut executor, "EXPLAIN SELECT label FROM t WHERE n = 1;").unwrap(),
+            "SCAN TABLE t WHERE n = 1, RESULT label"
+        );
+
+        for n in [9, 4, 7, 2, 8, 3, 6, 5, 4, 10] {
+            let sql = format!("INSERT INTO t VALUES ({}, 'n{}');", n, n);
+            run(&mut executor, &sql).unwrap();
+        }
+        run(&mut executor, "INSERT INTO t VALUES (NULL, 'none');").unwrap();
+        assert_eq!(
+            run(&mut executor, "EXPLAIN SELECT label FROM t WHERE n = 4;").unwrap(),
+            "SEARCH TABLE t USING INDEX by_n (n = 4) WHERE n = 4, RESULT label"
+        );
+        assert_eq!(
+            run(
+                &mut executor,
+                "EXPLAIN SELECT label FROM t WHERE 3 < n AND n <= 5 AND label != 'x';"
+            )
+            .unwrap(),
+            "SEARCH TABLE t USING INDEX by_n (n > 3 AND n <= 5) \
+             WHERE 3 < n AND n <= 5 AND label != 'x', RESULT label"
+        );
+
+        // Rows come in table order, as a scan gives them
+        for (sql, expected) in [
+            ("SELECT label FROM t WHERE n = 4;", "n4, n4"),
+            ("SELECT label FROM t WHERE 3 < n AND n <= 5;", "n4, n5, n4"),
+            ("SELECT n FROM t WHERE n < 3;", "1, 2"),
+            ("SELECT n FROM t WHERE n >= 9;", "9, 10"),
+            ("SELECT n FROM t WHERE n > 'a';", ""),
+            ("SELECT n FROM t WHERE n = 4.0 AND label = 'n4';", "4, 4"),
+        ] {
+            assert_eq!(run(&mut executor, sql).unwrap(), expected, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_analyze() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        run(&mut executor, "CREATE TABLE t (n INTEGER, label TEXT);").unwrap();
+        run(&mut executor, "CREATE TABLE empty (x BLOB);").unwrap();
+        run(&mut executor, "CREATE INDEX by_n ON t (n);").unwrap();
+        for n in 1..=10 {
+            let sql = format!("INSERT INTO t VALUES ({}, 'n{}');", n, n % 3);
+            run(&mut executor, &sql).unwrap();
+        }
+        run(&mut executor, "INSERT INTO t VALUES (NULL, NULL);").unwrap();
+
+        // Without statistics a range is guessed to keep few rows
+        let explain = "EXPLAIN SELECT label FROM t WHERE n >= 2;";
+        assert_eq!(
+            run(&mut executor, explain).unwrap(),
+            "SEARCH TABLE t USING INDEX by_n (n >= 2) WHERE n >= 2, RESULT label"
+        );
+        assert_eq!(
+            run(&mut executor, "SELECT * FROM hozon_stats;").unwrap(),
+            ""
+        );
+
+        assert_eq!(
+            run(&mut executor, "ANALYZE t;").unwrap(),
+            "Table 't' analyzed."
+        );
+        assert_eq!(
+            run(&mut executor, "ANALYZE;").unwrap(),
+            "2 tables analyzed."
+        );
+        assert_eq!(
+            run(&mut executor, "EXPLAIN ANALYZE t;").unwrap(),
+            "ANALYZE t"
+        );
+        assert!(matches!(
+            run(&mut executor, "ANALYZE missing;"),
+            Err(HozonError::TableNotFound(_))
+        ));
+        assert_eq!(
+            run(&mut executor, "SELECT * FROM hozon_stats;").unwrap(),
+            "empty|x|0|0|0|NULL|NULL, \
+             t|n|11|10|1|1|10, \
+             t|label|11|3|1|n0|n2"
+        );
+
+        // ...but most of them match, so a scan is cheaper
+        assert_eq!(
+            run(&mut executor, explain).unwrap(),
+            "SCAN TABLE t WHERE n >= 2, RESULT label"
+        );
+        assert_eq!(
+            run(&mut executor, "EXPLAIN SELECT label FROM t WHERE n >= 9;").unwrap(),
+            "SEARCH TABLE t USING INDEX by_n (n >= 9) WHERE n >= 9, RESULT label"
+        );
+        assert_eq!(
+            run(&mut executor, "SELECT label FROM t WHERE n >= 9;").unwrap(),
+            "n0, n1"
+        );
+    }
+
+    #[test]
+    fn test_count_all() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        for sql in [
+            "CREATE TABLE t (n INTEGER, label TEXT);",
+            "CREATE TABLE u (m INTEGER);",
+            "INSERT INTO t VALUES (1, 'a');",
+            "INSERT INTO t VALUES (2, 'b');",
+            "INSERT INTO t VALUES (3, NULL);",
+            "INSERT INTO u VALUES (2);",
+            "DELETE FROM t WHERE n = 1;",
+            "CREATE VIEW big AS SELECT * FROM t WHERE n > 2;",
+        ] {
+            run(&mut executor, sql).unwrap();
+        }
+
+        // Every row of a table is counted without reading any
+        assert_eq!(
+            run(&mut executor, "EXPLAIN SELECT COUNT(*) FROM t;").unwrap(),
+            "COUNT TABLE t USING ROW COUNT, RESULT COUNT(*)"
+        );
+        assert_eq!(
+            run(&mut executor, "EXPLAIN SELECT COUNT(*) FROM t WHERE n > 1;").unwrap(),
+            "SCAN TABLE t WHERE n > 1, RESULT COUNT(*)"
+        );
+        for (sql, expected) in [
+            ("SELECT COUNT(*) FROM t;", "2"),
+            ("SELECT COUNT(*) FROM u;", "1"),
+            ("SELECT COUNT(*) FROM t WHERE n > 2;", "1"),
+            ("SELECT COUNT(*) FROM t WHERE n > 5;", "0"),
+            ("SELECT DISTINCT COUNT(*) FROM t ORDER BY 1;", "2"),
+            ("SELECT COUNT(*) FROM t JOIN u ON t.n = u.m;", "1"),
+            ("SELECT COUNT(*) FROM big;", "1"),
+            ("SELECT COUNT(*) FROM hozon_tables;", "3"),
+            (
+                "SELECT n FROM t WHERE (SELECT COUNT(*) FROM u) = 1;",
+                "2, 3",
+            ),
+        ] {
+            assert_eq!(run(&mut executor, sql).unwrap(), expected, "{}", sql);
+        }
+
+        let query = Parser::new(tokenizer::tokenize("SELECT COUNT(*) AS n FROM u;").unwrap())
+            .parse()
+            .unwrap();
+        match executor.query(query).unwrap() {
+            ExecutionResult::Rows { columns, rows } => {
+                assert_eq!(columns, vec!["n"]);
+                assert_eq!(rows[0].values(), &vec![Value::BigInt(1)]);
+            }
+            other => panic!("expected rows, got {:?}", other),
+        }
+
+        for sql in [
+            "SELECT n, COUNT(*) FROM t;",
+            "SELECT * FROM t WHERE COUNT(*) > 1;",
+        ] {
+            let err = run(&mut executor, sql).unwrap_err();
+            assert!(
+                matches!(err, HozonError::InvalidStatement(_)),
+                "{}: {:?}",
+                sql,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_savepoints() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        for sql in [
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);",
+            "INSERT INTO users VALUES (1, 'alice');",
+            "SAVEPOINT a;",
+            "INSERT INTO users VALUES (2, 'bob');",
+            "SAVEPOINT b;",
+            "UPDATE users SET name = 'ALICE' WHERE id = 1;",
+            "CREATE TABLE orders (id INTEGER);",
+            "INSERT INTO orders VALUES (10);",
+        ] {
+            run(&mut executor, sql).unwrap();
+        }
+
+        // Undoes the update and the new table, but not what came before b
+        run(&mut executor, "ROLLBACK TO b;").unwrap();
+        assert_eq!(
+            run(&mut executor, "SELECT * FROM users;").unwrap(),
+            "1|alice, 2|bob"
+        );
+        assert!(run(&mut executor, "SELECT * FROM orders;").is_err());
+
+        // b stays open after a rollback to it
+        run(&mut executor, "DELETE FROM users WHERE id = 1;").unwrap();
+        run(&mut executor, "ROLLBACK TO SAVEPOINT b;").unwrap();
+        assert_eq!(run(&mut executor, "SELECT id FROM users;").unwrap(), "1, 2");
+
+        // Releasing b keeps its changes for a to undo
+        run(&mut executor, "INSERT INTO users VALUES (3, 'carol');").unwrap();
+        run(&mut executor, "RELEASE b;").unwrap();
+        assert!(run(&mut executor, "ROLLBACK TO b;").is_err());
+        run(&mut executor, "ROLLBACK TO a;").unwrap();
+        assert_eq!(run(&mut executor, "SELECT id FROM users;").unwrap(), "1");
+
+        // Tables created and written after the rollback still work
+        run(&mut executor, "INSERT INTO users VALUES (4, 'dan');").unwrap();
+        run(&mut executor, "RELEASE a;").unwrap();
+        assert!(!executor.catalog().in_savepoint());
+        assert_eq!(run(&mut executor, "SELECT id FROM users;").unwrap(), "1, 4");
+        let err = run(&mut executor, "RELEASE a;").unwrap_err();
+        assert!(matches!(err, HozonError::NotFound(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_attach() {
+        cleanup("test_attach");
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        run(&mut executor, "CREATE TABLE t (id INTEGER, name TEXT);").unwrap();
+        run(&mut executor, "INSERT INTO main.t VALUES (1, 'main');").unwrap();
+        assert_eq!(
+            run(&mut executor, "ATTACH 'test_attach.hdb' AS other;").unwrap(),
+            "Database 'test_attach.hdb' attached as 'other'."
+        );
+        run(
+            &mut executor,
+            "CREATE TABLE other.t (id INTEGER, name TEXT);",
+        )
+        .unwrap();
+        run(&mut executor, "CREATE TABLE other.u (id INTEGER);").unwrap();
+        run(&mut executor, "INSERT INTO other.t VALUES (1, 'other');").unwrap();
+        run(&mut executor, "INSERT INTO OTHER.u VALUES (1);").unwrap();
+        assert_eq!(
+            run(&mut executor, "SELECT name FROM other.t;").unwrap(),
+            "other"
+        );
+        assert_eq!(run(&mut executor, "SELECT name FROM t;").unwrap(), "main");
+        // The unqualified table is the one in the statement's database
+        assert_eq!(
+            run(
+                &mut executor,
+                "SELECT t.name FROM other.t JOIN u ON t.id = u.id;"
+            )
+            .unwrap(),
+            "other"
+        );
+        assert!(executor.catalog().get_table("u").is_none());
+        assert_eq!(executor.attached().collect::<Vec<_>>(), ["other"]);
+
+        let err = run(
+            &mut executor,
+            "SELECT * FROM main.t JOIN other.u ON t.id = u.id;",
+        )
+        .unwrap_err();
+        assert!(matches!(err, HozonError::Unsupported(_)), "{:?}", err);
+        let err = run(&mut executor, "ATTACH 'test_attach.hdb' AS main;").unwrap_err();
+        assert!(matches!(err, HozonError::AlreadyExists(_)), "{:?}", err);
+        let select = Parser::from_sql("SELECT * FROM other.t;")
+            .unwrap()
+            .parse()
+            .unwrap();
+        let err = executor.query(select).unwrap_err();
+        assert!(matches!(err, HozonError::Unsupported(_)), "{:?}", err);
+
+        run(&mut executor, "DETACH other;").unwrap();
+        let err = run(&mut executor, "SELECT * FROM other.t;").unwrap_err();
+        assert!(matches!(err, HozonError::NotFound(_)), "{:?}", err);
+        let err = run(&mut executor, "DETACH other;").unwrap_err();
+        assert!(matches!(err, HozonError::NotFound(_)), "{:?}", err);
+
+        // What was written went to the attached file
+        let mut executor = create_test_executor("test_attach");
+        assert_eq!(run(&mut executor, "SELECT * FROM u;").unwrap(), "1");
+        drop(executor);
+        cleanup("test_attach");
+    }
+
+    #[test]
+    fn test_savepoint_abandoned_on_close() {
+        cleanup("test_savepoint_close");
+        let mut executor = create_test_executor("test_savepoint_close");
+        run(&mut executor, "CREATE TABLE t (id INTEGER);").unwrap();
+        run(&mut executor, "INSERT INTO t VALUES (1);").unwrap();
+        run(&mut executor, "SAVEPOINT s;").unwrap();
+        run(&mut executor, "INSERT INTO t VALUES (2);").unwrap();
+        run(&mut executor, "CREATE TABLE u (id INTEGER);").unwrap();
+        drop(executor);
+
+        let mut executor = create_test_executor("test_savepoint_close");
+        assert_eq!(run(&mut executor, "SELECT * FROM t;").unwrap(), "1");
+        assert!(executor.catalog().get_table("u").is_none());
+        drop(executor);
+        cleanup("test_savepoint_close");
+    }
+
+    #[test]
+    fn test_explain() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        for sql in [
+            "CREATE TABLE users (id INTEGER, name TEXT);",
+            "CREATE TABLE orders (user_id INTEGER, total INTEGER);",
+            "INSERT INTO users VALUES (1, 'alice');",
+            "CREATE VIEW big AS SELECT user_id FROM orders WHERE total > 10;",
+        ] {
+            run(&mut executor, sql).unwrap();
+        }
+
+        let plan = run(
+            &mut executor,
+            "EXPLAIN SELECT DISTINCT name FROM users JOIN orders ON users.id = orders.user_id \
+             JOIN big ON total > users.id WHERE name != 'bob' ORDER BY name DESC;",
+        )
+        .unwrap();
+        assert_eq!(
+            plan.split(", ").collect::<Vec<_>>(),
+            [
+                "SCAN TABLE users WHERE name != 'bob'",
+                "HASH JOIN TABLE orders ON users.id = orders.user_id",
+                "NESTED LOOP JOIN VIEW big ON total > users.id",
+                "  SCAN TABLE orders WHERE total > 10",
+                "  RESULT user_id",
+                "DISTINCT",
+                "SORT BY name DESC",
+                "RESULT name",
+            ]
+        );
+        // Conditions on one table are pushed down to it, from ON too; what
+        // reads both stays
+        let plan = run(
+            &mut executor,
+            "EXPLAIN SELECT name FROM users JOIN orders ON users.id = orders.user_id \
+             AND total > 5 WHERE id = 1 AND (name = 'a' OR total = 2);",
+        )
+        .unwrap();
+        assert_eq!(
+            plan.split(", ").collect::<Vec<_>>(),
+            [
+                "SCAN TABLE users WHERE id = 1",
+                "HASH JOIN TABLE orders WHERE total > 5 ON users.id = orders.user_id",
+                "FILTER name = 'a' OR total = 2",
+                "RESULT name",
+            ]
+        );
+        assert_eq!(
+            run(&mut executor, "EXPLAIN DELETE FROM users WHERE id = 1;").unwrap(),
+            "SCAN TABLE users, FILTER id = 1, DELETE FROM users"
+        );
+
+        // Nothing runs
+        run(
+            &mut executor,
+            "EXPLAIN INSERT INTO users VALUES (2, 'bob');",
+        )
+        .unwrap();
+        run(&mut executor, "EXPLAIN UPDATE users SET name = 'x';").unwrap();
+        assert_eq!(
+            run(&mut executor, "SELECT * FROM users;").unwrap(),
+            "1|alice"
+        );
+        // But what would fail to run fails to explain
+        for sql in [
+            "EXPLAIN SELECT missing FROM users;",
+            "EXPLAIN SELECT * FROM nobody;",
+            "EXPLAIN DELETE FROM big;",
+        ] {
+            assert!(run(&mut executor, sql).is_err(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_affected_rows() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        let mut execute = |sql: &str| {
+            let statement = Parser::new(tokenizer::tokenize(sql).unwrap())
+                .parse()
+                .unwrap();
+            executor.execute(statement).unwrap()
+        };
+        execute("CREATE TABLE t (id INTEGER PRIMARY KEY, n INTEGER);");
+
+        for (sql, kind, rows) in [
+            ("INSERT INTO t VALUES (1, 1);", StatementKind::Insert, 1),
+            ("INSERT INTO t VALUES (2, 1);", StatementKind::Insert, 1),
+            (
+                "INSERT OR IGNORE INTO t VALUES (1, 5);",
+                StatementKind::Insert,
+                0,
+            ),
+            (
+                "INSERT OR REPLACE INTO t VALUES (1, 2);",
+                StatementKind::Replace,
+                1,
+            ),
+            (
+                "INSERT INTO t VALUES (2, 0) ON CONFLICT DO UPDATE SET n = 3;",
+                StatementKind::Update,
+                1,
+            ),
+            ("UPDATE t SET n = 4;", StatementKind::Update, 2),
+            ("DELETE FROM t WHERE n > 10;", StatementKind::Delete, 0),
+            ("DELETE FROM t;", StatementKind::Delete, 2),
+        ] {
+            let result = execute(sql);
+            assert!(
+                matches!(result, ExecutionResult::Affected { kind: k, rows: r } if k == kind && r == rows),
+                "{}: {:?}",
+                sql,
+                result
+            );
+            assert_eq!(result.affected_rows(), Some(rows));
+        }
+
+        // Other statements change no rows, or return them
+        let result = execute("CREATE TABLE u (x INTEGER);");
+        assert_eq!(result.affected_rows(), None);
+        assert_eq!(result.message().unwrap(), "Table 'u' created.");
+        let result = execute("INSERT INTO u VALUES (1) RETURNING x;");
+        assert_eq!((result.affected_rows(), result.message()), (None, None));
+    }
+
+    #[test]
+    fn test_multi_row_insert() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        run(
+            &mut executor,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL, age INTEGER);",
+        )
+        .unwrap();
+        run(&mut executor, "CREATE UNIQUE INDEX idx_name ON t (name);").unwrap();
+        assert_eq!(
+            run(
+                &mut executor,
+                "INSERT INTO t (name, age) VALUES ('a', 1), ('b', 2);"
+            )
+            .unwrap(),
+            "2 rows inserted."
+        );
+
+        // Each failure names the row, and none of the statement's rows stay
+        for (sql, message) in [
+            (
+                "INSERT INTO t (name, age) VALUES ('c', 3), (NULL, 4);",
+                "Row 2: Column 'name' of table 't' cannot be NULL",
+            ),
+            (
+                "INSERT INTO t (name, age) VALUES ('c', 3), ('d', 4), ('e', 'old');",
+                "Row 3: Type mismatch for column 'age' of table 't': expected Integer, got 'old'",
+            ),
+            (
+                "INSERT INTO t VALUES (9, 'c', 3), (2, 'd', 4);",
+                "Row 2: Duplicate primary key 2 for column 'id' of table 't'",
+            ),
+            (
+                "INSERT INTO t (name, age) VALUES ('c', 3), ('c', 4);",
+                "Row 2: Duplicate key ('c') for UNIQUE index 'idx_name' on t (name)",
+            ),
+            (
+                "INSERT OR REPLACE INTO t VALUES (1, 'z', 9), (5, NULL, 0);",
+                "Row 2: Column 'name' of table 't' cannot be NULL",
+            ),
+        ] {
+            let err = run(&mut executor, sql).unwrap_err();
+            assert_eq!(err.to_string(), message, "{}", sql);
+            assert_eq!(
+                run(&mut executor, "SELECT * FROM t;").unwrap(),
+                "1|a|1, 2|b|2"
+            );
+        }
+        assert_eq!(executor.last_insert_id(), Some(2));
+
+        // A single row's error has no row number, and the sequence went back
+        let err = run(&mut executor, "INSERT INTO t (name) VALUES (NULL);").unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.to_string(), "Column 'name' of table 't' cannot be NULL");
+        run(&mut executor, "INSERT INTO t (name) VALUES ('c'), ('d');").unwrap();
+        assert_eq!(
+            run(&mut executor, "SELECT id FROM t WHERE age IS NULL;").unwrap(),
+            "3, 4"
+        );
+        assert_eq!(executor.last_insert_id(), Some(4));
+    }
+
+    #[test]
+    fn test_write_hooks() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let text = |row: &Row| {
+            let values: Vec<String> = row.values().iter().map(|v| v.to_string()).collect();
+            values.join("|")
+        };
+        let seen = changes.clone();
+        executor.on_insert(move |table, _, row| {
+            seen.lock()
+                .unwrap()
+                .push(format!("+{} {}", table, text(row)));
+        });
+        let seen = changes.clone();
+        executor.on_update(move |table, _, old, new| {
+            let change = format!("~{} {} -> {}", table, text(old), text(new));
+            seen.lock().unwrap().push(change);
+        });
+        let seen = changes.clone();
+        executor.on_delete(move |table, _, row| {
+            seen.lock()
+                .unwrap()
+                .push(format!("-{} {}", table, text(row)));
+        });
+
+        for sql in [
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, n INTEGER);",
+            "INSERT INTO t VALUES (1, 1), (2, 2);",
+            // A failed statement wrote nothing, so calls nothing
+            "INSERT INTO t VALUES (3, 3), (1, 1);",
+            "INSERT OR REPLACE INTO t VALUES (1, 5);",
+            "UPDATE t SET n = 0 WHERE id = 2;",
+            "DELETE FROM t WHERE n = 5;",
+        ] {
+            let _ = run(&mut executor, sql);
+        }
+        assert_eq!(
+            *changes.lock().unwrap(),
+            [
+                "+t 1|1",
+                "+t 2|2",
+                "~t 1|1 -> 1|5",
+                "~t 2|2 -> 2|0",
+                "-t 1|5"
+            ]
+        );
+
+        executor.clear_hooks();
+        run(&mut executor, "DELETE FROM t;").unwrap();
+        assert_eq!(changes.lock().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_upsert() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        run(
+            &mut executor,
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, visits INTEGER);",
+        )
+        .unwrap();
+        run(&mut executor, "INSERT INTO users VALUES (1, 'alice', 1);").unwrap();
+        run(&mut executor, "INSERT INTO users VALUES (2, 'bob', 1);").unwrap();
+
+        assert_eq!(
+            run(
+                &mut executor,
+                "INSERT OR IGNORE INTO users VALUES (1, 'carol', 5);"
+            )
+            .unwrap(),
+            "0 rows inserted."
+        );
+        assert_eq!(
+            run(
+                &mut executor,
+                "INSERT INTO users VALUES (2, 'carol', 5) ON CONFLICT (id) DO NOTHING;"
+            )
+            .unwrap(),
+            "0 rows inserted."
+        );
+        assert_eq!(
+            run(
+                &mut executor,
+                "INSERT OR IGNORE INTO users VALUES (3, 'carol', 1);"
+            )
+            .unwrap(),
+            "1 row inserted."
+        );
+        assert_eq!(
+            run(
+                &mut executor,
+                "INSERT OR REPLACE INTO users VALUES (1, 'alice smith', 2);"
+            )
+            .unwrap(),
+            "1 row replaced."
+        );
+        // The existing row's columns, and the new row's through excluded
+        assert_eq!(
+            run(
+                &mut executor,
+                "INSERT INTO users VALUES (2, 'robert', 9) \
+                 ON CONFLICT DO UPDATE SET name = excluded.name, visits = id;"
+            )
+            .unwrap(),
+            "1 row updated."
+        );
+        assert_eq!(
+            run(&mut executor, "SELECT * FROM users;").unwrap(),
+            "1|alice smith|2, 2|robert|2, 3|carol|1"
+        );
+
+        // Updates are still checked against the schema and the other rows
+        let err = run(
+            &mut executor,
+            "INSERT INTO users VALUES (2, 'x', 1) ON CONFLICT DO UPDATE SET id = 3;",
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+        let err = run(
+            &mut executor,
+            "INSERT INTO users VALUES (2, 'x', 1) ON CONFLICT DO UPDATE SET visits = 'many';",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Type mismatch"));
+        let err = run(
+            &mut executor,
+            "INSERT INTO users VALUES (2, 'x', 1) ON CONFLICT (name) DO NOTHING;",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("does not match the PRIMARY KEY"));
+        let err = run(&mut executor, "INSERT INTO users VALUES (2, 'x', 1);").unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+        assert_eq!(
+            run(&mut executor, "SELECT name FROM users WHERE id = 2;").unwrap(),
+            "robert"
+        );
+    }
+
+    #[test]
+    fn test_upsert_unique_index() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        for sql in [
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT, visits INTEGER);",
+            "CREATE UNIQUE INDEX users_email ON users (email);",
+            "INSERT INTO users VALUES (1, 'a@x', 1);",
+            "INSERT INTO users VALUES (2, 'b@x', 1);",
+        ] {
+            run(&mut executor, sql).unwrap();
+        }
+        let all = "SELECT id, email, visits FROM users ORDER BY id;";
+
+        // Without a clause the index refuses the key
+        let err = run(&mut executor, "INSERT INTO users VALUES (3, 'a@x', 1);").unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+
+        assert_eq!(
+            run(
+                &mut executor,
+                "INSERT OR IGNORE INTO users VALUES (3, 'a@x', 5);"
+            )
+            .unwrap(),
+            "0 rows inserted."
+        );
+        assert_eq!(
+            run(
+                &mut executor,
+                "INSERT INTO users VALUES (3, 'a@x', 5) ON CONFLICT DO NOTHING;"
+            )
+            .unwrap(),
+            "0 rows inserted."
+        );
+        assert_eq!(
+            run(
+                &mut executor,
+                "INSERT INTO users VALUES (3, 'a@x', 5) ON CONFLICT (email) DO NOTHING;"
+            )
+            .unwrap(),
+            "0 rows inserted."
+        );
+        assert_eq!(run(&mut executor, all).unwrap(), "1|a@x|1, 2|b@x|1");
+
+        run(
+            &mut executor,
+            "INSERT INTO users VALUES (3, 'a@x', 2) \
+             ON CONFLICT (EMAIL) DO UPDATE SET visits = excluded.visits;",
+        )
+        .unwrap();
+        assert_eq!(run(&mut executor, all).unwrap(), "1|a@x|2, 2|b@x|1");
+
+        // A target names one key; a conflict on the other still fails
+        let err = run(
+            &mut executor,
+            "INSERT INTO users VALUES (2, 'c@x', 1) ON CONFLICT (email) DO NOTHING;",
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+        let err = run(
+            &mut executor,
+            "INSERT INTO users VALUES (2, 'c@x', 1) ON CONFLICT (visits) DO NOTHING;",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("or a UNIQUE index"));
+
+        // Replacing takes the place of the row with the key, and of any
+        // other row holding one of its keys, index entries and all
+        run(
+            &mut executor,
+            "INSERT OR REPLACE INTO users VALUES (3, 'b@x', 7);",
+        )
+        .unwrap();
+        assert_eq!(run(&mut executor, all).unwrap(), "1|a@x|2, 3|b@x|7");
+        run(
+            &mut executor,
+            "INSERT OR REPLACE INTO users VALUES (1, 'b@x', 9);",
+        )
+        .unwrap();
+        assert_eq!(run(&mut executor, all).unwrap(), "1|b@x|9");
+        run(&mut executor, "INSERT INTO users VALUES (3, 'a@x', 1);").unwrap();
+        assert_eq!(
+            run(&mut executor, "SELECT id FROM users WHERE email = 'a@x';").unwrap(),
+            "3"
+        );
+
+        // A failing row puts back the rows replacing removed
+        let err = run(
+            &mut executor,
+            "INSERT OR REPLACE INTO users VALUES (3, 'b@x', 1), (5, 'x', 'many');",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Row 2"), "{}", err);
+        assert_eq!(run(&mut executor, all).unwrap(), "1|b@x|9, 3|a@x|1");
+        assert_eq!(
+            run(&mut executor, "SELECT id FROM users WHERE email = 'b@x';").unwrap(),
+            "1"
+        );
+    }
+
+    #[test]
+    fn test_update() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        for sql in [
+            "CREATE TABLE users (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT, age INTEGER);",
+            "INSERT INTO users (name, age) VALUES ('alice', 30);",
+            "INSERT INTO users (name, age) VALUES ('bob', 25);",
+            "INSERT INTO users (name, age) VALUES ('carol', 35);",
+        ] {
+            run(&mut executor, sql).unwrap();
+        }
+
+        assert_eq!(
+            run(
+                &mut executor,
+                "UPDATE users SET name = 'robert' WHERE id = 2;"
+            )
+            .unwrap(),
+            "1 row updated."
+        );
+        assert_eq!(
+            run(&mut executor, "UPDATE users SET age = 40 WHERE age > 100;").unwrap(),
+            "0 rows updated."
+        );
+        // Values come from the row as it was: age gets the old id
+        run(
+            &mut executor,
+            "UPDATE users SET id = 7, age = id WHERE name = 'carol';",
+        )
+        .unwrap();
+        assert_eq!(
+            run(&mut executor, "SELECT * FROM users;").unwrap(),
+            "1|alice|30, 2|robert|25, 7|carol|3"
+        );
+        // The moved id still counts for AUTOINCREMENT
+        run(&mut executor, "INSERT INTO users (name) VALUES ('dave');").unwrap();
+        assert_eq!(
+            run(&mut executor, "SELECT id FROM users WHERE name = 'dave';").unwrap(),
+            "8"
+        );
+
+        // Nothing is written unless every row passes
+        let err = run(&mut executor, "UPDATE users SET id = 1 WHERE id = 2;").unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+        let err = run(&mut executor, "UPDATE users SET id = 3;").unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+        let err = run(&mut executor, "UPDATE users SET age = 'old';").unwrap_err();
+        assert!(err.to_string().contains("Type mismatch"));
+        let err = run(&mut executor, "UPDATE users SET name = NULL, nope = 1;").unwrap_err();
+        assert!(err.to_string().contains("Column 'nope' does not exist"));
+        assert_eq!(
+            run(&mut executor, "SELECT id FROM users;").unwrap(),
+            "1, 2, 7, 8"
+        );
+        run(
+            &mut executor,
+            "CREATE VIEW names AS SELECT name FROM users;",
+        )
+        .unwrap();
+        let err = run(&mut executor, "UPDATE names SET name = 'x';").unwrap_err();
+        assert!(matches!(err, HozonError::InvalidInput(_)), "{:?}", err);
+        let err = run(&mut executor, "UPDATE nobody SET name = 'x';").unwrap_err();
+        assert!(matches!(err, HozonError::TableNotFound(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_delete() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        run(
+            &mut executor,
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);",
+        )
+        .unwrap();
+        for (id, name) in [(1, "alice"), (2, "bob"), (3, "carol"), (4, "dave")] {
+            let sql = format!("INSERT INTO users VALUES ({}, '{}');", id, name);
+            run(&mut executor, &sql).unwrap();
+        }
+        let rowids = run(&mut executor, "SELECT rowid FROM users;").unwrap();
+
+        assert_eq!(
+            run(&mut executor, "DELETE FROM users WHERE id IN (2, 3);").unwrap(),
+            "2 rows deleted."
+        );
+        assert_eq!(
+            run(&mut executor, "DELETE FROM users WHERE id = 2;").unwrap(),
+            "0 rows deleted."
+        );
+        assert_eq!(
+            run(&mut executor, "SELECT * FROM users;").unwrap(),
+            "1|alice, 4|dave"
+        );
+        // The rows that are left keep their rowids
+        let rowids: Vec<&str> = rowids.split(", ").collect();
+        assert_eq!(
+            run(&mut executor, "SELECT rowid FROM users;").unwrap(),
+            [rowids[0], rowids[3]].join(", ")
+        );
+        assert_eq!(executor.catalog.get_table("users").unwrap().row_count(), 2);
+
+        // A deleted key is free again
+        run(&mut executor, "INSERT INTO users VALUES (2, 'bob');").unwrap();
+        run(
+            &mut executor,
+            "UPDATE users SET name = 'alicia' WHERE id = 1;",
+        )
+        .unwrap();
+        assert_eq!(
+            run(&mut executor, "SELECT * FROM users;").unwrap(),
+            "1|alicia, 4|dave, 2|bob"
+        );
+        assert_eq!(
+            run(&mut executor, "DELETE FROM users;").unwrap(),
+            "3 rows deleted."
+        );
+        assert_eq!(run(&mut executor, "SELECT * FROM users;").unwrap(), "");
+    }
+
+    #[test]
+    fn test_returning() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        run(
+            &mut executor,
+            "CREATE TABLE users (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT);",
+        )
+        .unwrap();
+
+        assert_eq!(
+            run(
+                &mut executor,
+                "INSERT INTO users (name) VALUES ('alice') RETURNING id;"
+            )
+            .unwrap(),
+            "1"
+        );
+        assert_eq!(
+            run(
+                &mut executor,
+                "INSERT INTO users (name) VALUES ('bob') RETURNING *;"
+            )
+            .unwrap(),
+            "2|bob"
+        );
+        assert_eq!(
+            run(
+                &mut executor,
+                "INSERT INTO users VALUES (2, 'x') ON CONFLICT DO NOTHING RETURNING id;"
+            )
+            .unwrap(),
+            ""
+        );
+        assert_eq!(
+            run(
+                &mut executor,
+                "INSERT INTO users VALUES (2, 'bobby') \
+                 ON CONFLICT DO UPDATE SET name = excluded.name RETURNING name;"
+            )
+            .unwrap(),
+            "bobby"
+        );
+        assert_eq!(
+            run(
+                &mut executor,
+                "UPDATE users SET name = 'al' WHERE id = 1 RETURNING users.name, id;"
+            )
+            .unwrap(),
+            "al|1"
+        );
+        let rowid = run(&mut executor, "SELECT rowid FROM users WHERE id = 2;").unwrap();
+        assert_eq!(
+            run(
+                &mut executor,
+                "DELETE FROM users WHERE id = 2 RETURNING rowid, name;"
+            )
+            .unwrap(),
+            format!("{}|bobby", rowid)
+        );
+
+        // A bad RETURNING list is caught before anything is written
+        let err = run(&mut executor, "DELETE FROM users RETURNING nope;").unwrap_err();
+        assert!(err.to_string().contains("Column 'nope' does not exist"));
+        assert_eq!(run(&mut executor, "SELECT * FROM users;").unwrap(), "1|al");
+    }
+
+    #[test]
+    fn test_scalar_functions() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        run(
+            &mut executor,
+            "CREATE TABLE users (id INTEGER, name TEXT, nick TEXT);",
+        )
+        .unwrap();
+        run(
+            &mut executor,
+            "INSERT INTO users VALUES (-1, 'Alice', NULL);",
+        )
+        .unwrap();
+        run(&mut executor, "INSERT INTO users VALUES (2, 'bob', 'B');").unwrap();
+
+        assert_eq!(
+            run(
+                &mut executor,
+                "SELECT UPPER(name), ABS(id), COALESCE(nick, SUBSTR(name, 1, 1)) FROM users;"
+            )
+            .unwrap(),
+            "ALICE|1|A, BOB|2|B"
+        );
+        assert_eq!(
+            run(
+                &mut executor,
+                "SELECT name FROM users WHERE LENGTH(name) > 3 OR lower(nick) = 'b' ORDER BY LOWER(name) DESC;"
+            )
+            .unwrap(),
+            "bob, Alice"
+        );
+
+        // Result columns are named as the expression was written
+        let statement =
+            Parser::new(tokenizer::tokenize("SELECT users.id, lower(name) FROM users;").unwrap())
+                .parse()
+                .unwrap();
+        match executor.execute(statement).unwrap() {
+            ExecutionResult::Rows { columns, .. } => assert_eq!(columns, vec!["id", "lower(name)"]),
+            _ => panic!("Expected Rows result"),
+        }
+
+        assert_eq!(
+            run(
+                &mut executor,
+                "SELECT CAST(id AS TEXT), UPPER(CAST(id > 0 AS TEXT)) FROM users WHERE CAST(id AS FLOAT) < 0;"
+            )
+            .unwrap(),
+            "-1|FALSE"
+        );
+
+        let err = run(&mut executor, "SELECT UPPER(id) FROM users;").unwrap_err();
+        assert_eq!(err.to_string(), "UPPER expects TEXT, found -1");
+        let err = run(&mut executor, "SELECT name FROM users WHERE NOPE(id);").unwrap_err();
+        assert_eq!(err.to_string(), "Unknown function 'NOPE'");
+    }
+
+    #[test]
+    fn test_not_null_enforced() {
+        cleanup("test_exec_not_null");
+
+        let mut executor = create_test_executor("test_exec_not_null");
+        executor
+            .execute(Statement::CreateTable {
+                name: "users".to_string(),
+                columns: vec![
+                    Column::new("id", DataType::Integer),
+                    Column::new("name", DataType::Text).not_null(),
+                ],
+            })
+            .unwrap();
+
+        let err = executor
+            .execute(Statement::Insert {
+                table_name: "users".to_string(),
+                columns: None,
+                values: literals(vec![Value::Integer(1), Value::Null]),
+                on_conflict: None,
+                returning: None,
+            })
+            .unwrap_err();
+        assert!(
+            matches!(err, HozonError::ConstraintViolation(_)),
+            "{:?}",
+            err
+        );
+        assert!(
+            err.to_string()
+                .contains("Column 'name' of table 'users' cannot be NULL")
+        );
+
+        executor
+            .execute(Statement::Insert {
+                table_name: "users".to_string(),
+                columns: None,
+                values: literals(vec![Value::Null, Value::Text("bob".to_string())]),
+                on_conflict: None,
+                returning: None,
+            })
+            .unwrap();
+        drop(executor);
+
+        let executor = create_test_executor("test_exec_not_null");
+        let columns = executor
+            .catalog
+            .get_table("users")
+            .unwrap()
+            .schema()
+            .columns();
+        assert!(!columns[1].is_nullable());
+
+        drop(executor);
+        cleanup("test_exec_not_null");
+    }
+
+    #[test]
+    fn test_auto_increment() {
+        cleanup("test_exec_autoinc");
+
+        let mut executor = create_test_executor("test_exec_autoinc");
+        executor
+            .execute(Statement::CreateTable {
+                name: "users".to_string(),
+                columns: vec![
+                    Column::new("id", DataType::Integer)
+                        .primary_key()
+                        .auto_increment(),
+                    Column::new("name", DataType::Text),
+                ],
+            })
+            .unwrap();
+
+        let insert_name = |name: &str| Statement::Insert {
+            table_name: "users".to_string(),
+            columns: Some(vec!["name".to_string()]),
+            values: literals(vec![Value::Text(name.to_string())]),
+            on_conflict: None,
+            returning: None,
+        };
+        executor.execute(insert_name("alice")).unwrap();
+        assert_eq!(executor.last_insert_id(), Some(1));
+        executor.execute(insert_name("bob")).unwrap();
+        assert_eq!(executor.last_insert_id(), Some(2));
+
+        // An explicit id moves the sequence past it
+        executor
+            .execute(Statement::Insert {
+                table_name: "users".to_string(),
+                columns: None,
+                values: literals(vec![Value::Integer(10), Value::Text("carol".to_string())]),
+                on_conflict: None,
+                returning: None,
+            })
+            .unwrap();
+        drop(executor);
+
+        // The sequence is persisted in the catalog
+        let mut executor = create_test_executor("test_exec_autoinc");
+        executor.execute(insert_name("dave")).unwrap();
+        assert_eq!(executor.last_insert_id(), Some(11));
+
+        let err = executor
+            .execute(Statement::Insert {
+                table_name: "users".to_string(),
+                columns: Some(vec!["nope".to_string()]),
+                values: literals(vec![Value::Integer(1)]),
+                on_conflict: None,
+                returning: None,
+            })
+            .unwrap_err();
+        assert!(err.to_string().contains("Column 'nope' does not exist"));
+        assert_eq!(executor.last_insert_id(), Some(11));
+
+        // Only a statement that inserted an id reports one
+        let (_, id) = executor
+            .execute_with_insert_id(insert_name("erin"))
+            .unwrap();
+        assert_eq!(id, Some(12));
+        let (_, id) = executor
+            .execute_with_insert_id(Statement::Checkpoint)
+            .unwrap();
+        assert_eq!(id, None);
+        assert_eq!(executor.last_insert_id(), Some(12));
+
+        drop(executor);
+        cleanup("test_exec_autoinc");
+    }
+
+    #[test]
+    fn test_bigint_column() {
+        cleanup("test_exec_bigint");
+
+        let mut executor = create_test_executor("test_exec_bigint");
+        executor
+            .execute(Statement::CreateTable {
+                name: "events".to_string(),
+                columns: vec![
+                    Column::new("id", DataType::BigInt)
+                        .primary_key()
+                        .auto_increment(),
+                    Column::new("at", DataType::BigInt),
+                    Column::new("n", DataType::Integer),
+                ],
+            })
+            .unwrap();
+
+        // Small literals widen to BIGINT; large ones don't fit INTEGER
+        executor
+            .execute(Statement::Insert {
+                table_name: "events".to_string(),
+                columns: Some(vec!["at".to_string()]),
+                values: literals(vec![Value::Integer(5)]),
+                on_conflict: None,
+                returning: None,
+            })
+            .unwrap();
+        assert_eq!(executor.last_insert_id(), Some(1));
+        executor
+            .execute(Statement::Insert {
+                table_name: "events".to_string(),
+                columns: None,
+                values: literals(vec![
+                    Value::BigInt(1 << 40),
+                    Value::BigInt(1_700_000_000_000),
+                    Value::Null,
+                ]),
+                on_conflict: None,
+                returning: None,
+            })
+            .unwrap();
+        assert_eq!(executor.last_insert_id(), Some(1 << 40));
+
+        let err = executor
+            .execute(Statement::Insert {
+                table_name: "events".to_string(),
+                columns: Some(vec!["n".to_string()]),
+                values: literals(vec![Value::BigInt(1 << 40)]),
+                on_conflict: None,
+                returning: None,
+            })
+            .unwrap_err();
+        assert!(matches!(err, HozonError::TypeMismatch(_)), "{:?}", err);
+
+        match executor
+            .query(Statement::Select {
+                table_name: "events".to_string(),
+                columns: SelectColumns::All,
+                where_clause: None,
+                order_by: Vec::new(),
+                distinct: false,
+                joins: Vec::new(),
+            })
+            .unwrap()
+        {
+            ExecutionResult::Rows { rows, .. } => {
+                assert_eq!(rows[0].values()[0], Value::BigInt(1));
+                assert_eq!(rows[0].values()[1], Value::BigInt(5));
+                assert_eq!(rows[1].values()[1], Value::BigInt(1_700_000_000_000));
+            }
+            other => panic!("expected rows, got {:?}", other),
+        }
+
+        drop(executor);
+        cleanup("test_exec_bigint");
+    }
+
+    #[test]
+    fn test_create_and_query_view() {
+        cleanup("test_exec_view");
+
+        let mut executor = create_test_executor("test_exec_view");
+        executor
+            .execute(Statement::CreateTable {
+                name: "users".to_string(),
+                columns: vec![
+                    Column::new("id", DataType::Integer),
+                    Column::new("name", DataType::Text),
+                ],
+            })
+            .unwrap();
+        executor
+            .execute(Statement::Insert {
+                table_name: "users".to_string(),
+                columns: None,
+                values: literals(vec![Value::Integer(1), Value::Text("alice".to_string())]),
+                on_conflict: None,
+                returning: None,
+            })
+            .unwrap();
+
+        let create_view = |name: &str, query: &str| Statement::CreateView {
+            name: name.to_string(),
+            query: query.to_string(),
+        };
+        executor
+            .execute(create_view("names", "SELECT name, id FROM users;"))
+            .unwrap();
+        assert!(
+            executor
+                .execute(create_view("users", "SELECT * FROM names;"))
+                .is_err()
+        );
+        assert!(
+            executor
+                .execute(create_view("broken", "SELECT * FROM missing;"))
+                .is_err()
+        );
+        drop(executor);
+
+        // Views survive reopening and can be selected from like tables
+        let executor = create_test_executor("test_exec_view");
+        let select = Statement::Select {
+            table_name: "names".to_string(),
+            columns: SelectColumns::Specific(vec![SelectItem::column("name")]),
+            where_clause: None,
+            order_by: Vec::new(),
+            distinct: false,
+            joins: Vec::new(),
+        };
+        match executor.query(select).unwrap() {
+            ExecutionResult::Rows { columns, rows } => {
+                assert_eq!(columns, vec!["name"]);
+                assert_eq!(rows[0].values(), &vec![Value::Text("alice".to_string())]);
+            }
+            _ => panic!("Expected Rows result"),
+        }
+
+        drop(executor);
+        cleanup("test_exec_view");
+    }
+
+    #[test]
+    fn test_select_rowid() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        executor
+            .execute(Statement::CreateTable {
+                name: "users".to_string(),
+                columns: vec![
+                    Column::new("id", DataType::Integer),
+                    Column::new("name", DataType::Text),
+                ],
+            })
+            .unwrap();
+        for (id, name) in [(1, "alice"), (2, "bob")] {
+            executor
+                .execute(Statement::Insert {
+                    table_name: "users".to_string(),
+                    columns: None,
+                    values: literals(vec![Value::Integer(id), Value::Text(name.to_string())]),
+                    on_conflict: None,
+                    returning: None,
+                })
+                .unwrap();
+        }
+        executor
+            .execute(Statement::CreateView {
+                name: "names".to_string(),
+                query: "SELECT name FROM users;".to_string(),
+            })
+            .unwrap();
+
+        let select = |table: &str, columns: &[&str]| Statement::Select {
+            table_name: table.to_string(),
+            columns: SelectColumns::Specific(
+                columns
+                    .iter()
+                    .map(|&c| match c {
+                        "*" => SelectItem::Wildcard(None),
+                        c => SelectItem::column(c),
+                    })
+                    .collect(),
+            ),
+            where_clause: None,
+            order_by: Vec::new(),
+            distinct: false,
+            joins: Vec::new(),
+        };
+        match executor.query(select("users", &["ROWID", "*"])).unwrap() {
+            ExecutionResult::Rows { columns, rows } => {
+                assert_eq!(columns, vec!["ROWID", "id", "name"]);
+                let first_page = executor.catalog.get_table("users").unwrap().first_page();
+                assert_eq!(
+                    rows[1].values(),
+                    &vec![
+                        Value::BigInt(crate::catalog::table::rowid(first_page, 1)),
+                        Value::Integer(2),
+                        Value::Text("bob".to_string()),
+                    ]
+                );
+                assert!(rows[0].values()[0] < rows[1].values()[0]);
+            }
+            _ => panic!("Expected Rows result"),
+        }
+
+        // Views have no rowids of their own
+        let err = executor.query(select("names", &["rowid"])).unwrap_err();
+        assert!(matches!(err, HozonError::ColumnNotFound(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_select_where() {
+        fn run(executor: &mut Executor, sql: &str) -> Result<ExecutionResult> {
+            executor.execute(Parser::new(tokenizer::tokenize(sql).unwrap()).parse()?)
+        }
+        /// The first column of each result row, as text
+        fn first_column(executor: &mut Executor, sql: &str) -> Vec<String> {
+            match run(executor, sql).unwrap() {
+                ExecutionResult::Rows { rows, .. } => {
+                    rows.iter().map(|row| row.values()[0].to_string()).collect()
+                }
+                other => panic!("expected rows, got {:?}", other),
+            }
+        }
+
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let executor = &mut Executor::new(catalog);
+        for sql in [
+            "CREATE TABLE users (id INTEGER, name TEXT, age INTEGER);",
+            "INSERT INTO users VALUES (1, 'alice', 30);",
+            "INSERT INTO users VALUES (2, 'bob', NULL);",
+            "INSERT INTO users VALUES (3, 'carol', 25);",
+            "CREATE VIEW adults AS SELECT name, age FROM users WHERE age >= 18;",
+        ] {
+            run(executor, sql).unwrap();
+        }
+
+        assert_eq!(
+            first_column(executor, "SELECT name FROM users WHERE id = 1;"),
+            ["alice"]
+        );
+        // The condition can use columns that aren't selected
+        assert_eq!(
+            first_column(executor, "SELECT name FROM users WHERE age < 30 OR id = 2;"),
+            ["bob", "carol"]
+        );
+        // NULL never matches a comparison, either way round
+        assert_eq!(
+            first_column(executor, "SELECT name FROM users WHERE NOT age > 26;"),
+            ["carol"]
+        );
+        assert_eq!(
+            first_column(executor, "SELECT name FROM adults WHERE name != 'alice';"),
+            ["carol"]
+        );
+        assert_eq!(
+            first_column(executor, "SELECT name FROM users WHERE age IS NULL;"),
+            ["bob"]
+        );
+        assert_eq!(
+            first_column(executor, "SELECT name FROM users WHERE age = NULL;"),
+            Vec::<String>::new()
+        );
+
+        let rowid = first_column(executor, "SELECT rowid FROM users;").remove(2);
+        assert_eq!(
+            first_column(
+                executor,
+                &format!("SELECT name FROM users WHERE rowid = {};", rowid)
+            ),
+            ["carol"]
+        );
+
+        let err = run(executor, "SELECT * FROM users WHERE missing = 1;").unwrap_err();
+        assert!(matches!(err, HozonError::ColumnNotFound(_)), "{:?}", err);
+        let err = run(executor, "SELECT * FROM users WHERE name;").unwrap_err();
+        assert!(matches!(err, HozonError::TypeMismatch(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_where_boolean_precedence() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        for sql in [
+            "CREATE TABLE t (id INTEGER, a INTEGER, b INTEGER, c BOOLEAN);",
+            "INSERT INTO t VALUES (1, 1, 2, TRUE);",
+            "INSERT INTO t VALUES (2, 1, 3, FALSE);",
+            "INSERT INTO t VALUES (3, 1, 3, TRUE);",
+            "INSERT INTO t VALUES (4, 2, 2, FALSE);",
+        ] {
+            executor
+                .execute(
+                    Parser::new(tokenizer::tokenize(sql).unwrap())
+                        .parse()
+                        .unwrap(),
+                )
+                .unwrap();
+        }
+
+        let ids = |condition: &str| {
+            let sql = format!("SELECT id FROM t WHERE {};", condition);
+            let statement = Parser::new(tokenizer::tokenize(&sql).unwrap())
+                .parse()
+                .unwrap();
+            match executor.query(statement).unwrap() {
+                ExecutionResult::Rows { rows, .. } => rows
+                    .iter()
+                    .map(|row| row.values()[0].to_string())
+                    .collect::<Vec<_>>(),
+                other => panic!("expected rows, got {:?}", other),
+            }
+        };
+
+        assert_eq!(ids("a = 1 AND (b = 2 OR NOT c)"), ["1", "2"]);
+        // AND binds tighter than OR, NOT tighter than AND
+        assert_eq!(ids("a = 2 OR b = 2 AND c"), ["1", "4"]);
+        assert_eq!(ids("(a = 2 OR b = 2) AND c"), ["1"]);
+        assert_eq!(ids("NOT c AND a = 1"), ["2"]);
+        assert_eq!(ids("NOT (c AND a = 1)"), ["2", "4"]);
+        assert_eq!(ids("NOT NOT c"), ["1", "3"]);
+    }
+
+    #[test]
+    fn test_select_order_by() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        for sql in [
+            "CREATE TABLE users (id INTEGER, name TEXT, age INTEGER);",
+            "INSERT INTO users VALUES (1, 'alice', 30);",
+            "INSERT INTO users VALUES (2, 'bob', NULL);",
+            "INSERT INTO users VALUES (3, 'carol', 25);",
+            "INSERT INTO users VALUES (4, 'dave', 30);",
+        ] {
+            executor
+                .execute(
+                    Parser::new(tokenizer::tokenize(sql).unwrap())
+                        .parse()
+                        .unwrap(),
+                )
+                .unwrap();
+        }
+
+        let ids = |sql: &str| -> Result<Vec<Value>> {
+            let statement = Parser::new(tokenizer::tokenize(sql)?).parse()?;
+            match executor.query(statement)? {
+                ExecutionResult::Rows { rows, .. } => {
+                    Ok(rows.iter().map(|row| row.values()[0].clone()).collect())
+                }
+                other => panic!("expected rows, got {:?}", other),
+            }
+        };
+        let ints = |ids: &[i32]| ids.iter().map(|&id| Value::Integer(id)).collect::<Vec<_>>();
+
+        // NULL sorts first ascending; ties keep insertion order
+        assert_eq!(
+            ids("SELECT id FROM users ORDER BY age;").unwrap(),
+            ints(&[2, 3, 1, 4])
+        );
+        assert_eq!(
+            ids("SELECT id FROM users ORDER BY age DESC, name DESC;").unwrap(),
+            ints(&[4, 1, 3, 2])
+        );
+        // Keys needn't be selected, and positions name result columns
+        assert_eq!(
+            ids("SELECT id, name FROM users WHERE age = 30 ORDER BY name DESC;").unwrap(),
+            ints(&[4, 1])
+        );
+        assert_eq!(
+            ids("SELECT id, name FROM users ORDER BY 2 DESC;").unwrap(),
+            ints(&[4, 3, 2, 1])
+        );
+        assert_eq!(
+            ids("SELECT id FROM users ORDER BY rowid DESC;").unwrap(),
+            ints(&[4, 3, 2, 1])
+        );
+
+        let err = ids("SELECT id FROM users ORDER BY 2;").unwrap_err();
+        assert!(matches!(err, HozonError::InvalidStatement(_)), "{:?}", err);
+        let err = ids("SELECT id FROM users ORDER BY missing;").unwrap_err();
+        assert!(matches!(err, HozonError::ColumnNotFound(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_select_distinct() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        for sql in [
+            "CREATE TABLE visits (user TEXT, page TEXT);",
+            "INSERT INTO visits VALUES ('alice', '/');",
+            "INSERT INTO visits VALUES ('bob', '/about');",
+            "INSERT INTO visits VALUES ('alice', '/');",
+            "INSERT INTO visits VALUES (NULL, '/');",
+            "INSERT INTO visits VALUES (NULL, '/');",
+            "CREATE VIEW pages AS SELECT page FROM visits;",
+        ] {
+            executor
+                .execute(
+                    Parser::new(tokenizer::tokenize(sql).unwrap())
+                        .parse()
+                        .unwrap(),
+                )
+                .unwrap();
+        }
+
+        let rows = |sql: &str| -> Result<Vec<String>> {
+            let statement = Parser::new(tokenizer::tokenize(sql)?).parse()?;
+            match executor.query(statement)? {
+                ExecutionResult::Rows { rows, .. } => Ok(rows
+                    .iter()
+                    .map(|row| {
+                        let values: Vec<String> =
+                            row.values().iter().map(Value::to_string).collect();
+                        values.join("|")
+                    })
+                    .collect()),
+                other => panic!("expected rows, got {:?}", other),
+            }
+        };
+
+        assert_eq!(
+            rows("SELECT DISTINCT * FROM visits;").unwrap(),
+            ["alice|/", "bob|/about", "NULL|/"]
+        );
+        assert_eq!(
+            rows("SELECT DISTINCT user FROM visits ORDER BY user DESC;").unwrap(),
+            ["bob", "alice", "NULL"]
+        );
+        assert_eq!(
+            rows("SELECT DISTINCT page FROM pages ORDER BY 1;").unwrap(),
+            ["/", "/about"]
+        );
+
+        // Which duplicate's page would decide the order?
+        let err = rows("SELECT DISTINCT user FROM visits ORDER BY page;").unwrap_err();
+        assert!(matches!(err, HozonError::InvalidStatement(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_inner_join() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        for sql in [
+            "CREATE TABLE users (id INTEGER, name TEXT);",
+            "CREATE TABLE orders (id INTEGER, user_id INTEGER, total INTEGER);",
+            "INSERT INTO users VALUES (1, 'alice');",
+            "INSERT INTO users VALUES (2, 'bob');",
+            "INSERT INTO users VALUES (3, 'carol');",
+            "INSERT INTO orders VALUES (10, 1, 50);",
+            "INSERT INTO orders VALUES (11, 3, 20);",
+            "INSERT INTO orders VALUES (12, 1, 5);",
+            "CREATE VIEW big_orders AS SELECT user_id, total FROM orders WHERE total >= 20;",
+        ] {
+            executor
+                .execute(
+                    Parser::new(tokenizer::tokenize(sql).unwrap())
+                        .parse()
+                        .unwrap(),
+                )
+                .unwrap();
+        }
+
+        let rows = |sql: &str| -> Result<(Vec<String>, Vec<String>)> {
+            let statement = Parser::new(tokenizer::tokenize(sql)?).parse()?;
+            match executor.query(statement)? {
+                ExecutionResult::Rows { columns, rows } => Ok((
+                    columns,
+                    rows.iter()
+                        .map(|row| {
+                            let values: Vec<String> =
+                                row.values().iter().map(Value::to_string).collect();
+                            values.join("|")
+                        })
+                        .collect(),
+                )),
+                other => panic!("expected rows, got {:?}", other),
+            }
+        };
+
+        let (columns, result) = rows(
+            "SELECT name, orders.id, total FROM users JOIN orders ON users.id = orders.user_id \
+             WHERE total > 10 ORDER BY total;",
+        )
+        .unwrap();
+        assert_eq!(columns, ["name", "id", "total"]);
+        assert_eq!(result, ["carol|11|20", "alice|10|50"]);
+
+        // `*` is every table's columns; `table.*` one table's
+        let (columns, result) =
+            rows("SELECT * FROM users INNER JOIN orders ON users.id = user_id ORDER BY 4;")
+                .unwrap();
+        assert_eq!(columns, ["id", "name", "id", "user_id", "total"]);
+        assert_eq!(result[0], "1|alice|10|1|50");
+        let (columns, _) =
+            rows("SELECT orders.* FROM users JOIN orders ON users.id = user_id;").unwrap();
+        assert_eq!(columns, ["id", "user_id", "total"]);
+
+        // Views join like tables
+        let (_, result) = rows(
+            "SELECT name FROM users JOIN big_orders ON big_orders.user_id = users.id ORDER BY name;",
+        )
+        .unwrap();
+        assert_eq!(result, ["alice", "carol"]);
+
+        let err = rows("SELECT id FROM users JOIN orders ON users.id = user_id;").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Column 'id' is ambiguous; qualify it with its table"
+        );
+        let err = rows("SELECT name FROM users JOIN orders ON users.missing = 1;").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Column 'missing' does not exist in table 'users'"
+        );
+        assert!(rows("SELECT name FROM users JOIN users ON users.id = users.id;").is_err());
+    }
+
+    #[test]
+    fn test_select_in() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        for sql in [
+            "CREATE TABLE users (id INTEGER, name TEXT);",
+            "CREATE TABLE orders (user_id INTEGER, total INTEGER);",
+            "INSERT INTO users VALUES (1, 'alice');",
+            "INSERT INTO users VALUES (2, 'bob');",
+            "INSERT INTO users VALUES (3, 'carol');",
+            "INSERT INTO orders VALUES (1, 50);",
+            "INSERT INTO orders VALUES (3, 20);",
+            "CREATE VIEW buyers AS SELECT name FROM users WHERE id IN (SELECT user_id FROM orders);",
+        ] {
+            executor
+                .execute(
+                    Parser::new(tokenizer::tokenize(sql).unwrap())
+                        .parse()
+                        .unwrap(),
+                )
+                .unwrap();
+        }
+
+        let names = |sql: &str| -> Result<Vec<String>> {
+            let statement = Parser::new(tokenizer::tokenize(sql)?).parse()?;
+            match executor.query(statement)? {
+                ExecutionResult::Rows { rows, .. } => {
+                    Ok(rows.iter().map(|row| row.values()[0].to_string()).collect())
+                }
+                other => panic!("expected rows, got {:?}", other),
+            }
+        };
+
+        assert_eq!(
+            names("SELECT name FROM users WHERE id IN (1, 3);").unwrap(),
+            ["alice", "carol"]
+        );
+        assert_eq!(
+            names("SELECT name FROM users WHERE id NOT IN (SELECT user_id FROM orders);").unwrap(),
+            ["bob"]
+        );
+        assert_eq!(
+            names(
+                "SELECT name FROM users WHERE id IN (SELECT user_id FROM orders WHERE total > 30);"
+            )
+            .unwrap(),
+            ["alice"]
+        );
+        assert_eq!(names("SELECT * FROM buyers;").unwrap(), ["alice", "carol"]);
+
+        let err = names("SELECT name FROM users WHERE id IN (SELECT * FROM orders);").unwrap_err();
+        assert!(matches!(err, HozonError::InvalidStatement(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_scalar_and_exists_subqueries() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        for sql in [
+            "CREATE TABLE users (id INTEGER, name TEXT);",
+            "CREATE TABLE orders (user_id INTEGER, total INTEGER);",
+            "CREATE TABLE settings (name TEXT, value INTEGER);",
+            "INSERT INTO users VALUES (1, 'alice');",
+            "INSERT INTO users VALUES (2, 'bob');",
+            "INSERT INTO users VALUES (3, 'carol');",
+            "INSERT INTO orders VALUES (1, 50);",
+            "INSERT INTO orders VALUES (3, 20);",
+            "INSERT INTO orders VALUES (3, 40);",
+            "INSERT INTO settings VALUES ('limit', 30);",
+        ] {
+            run(&mut executor, sql).unwrap();
+        }
+
+        // Uncorrelated: run once
+        assert_eq!(
+            run(
+                &mut executor,
+                "SELECT user_id FROM orders WHERE total > (SELECT value FROM settings WHERE name = 'limit');"
+            )
+            .unwrap(),
+            "1, 3"
+        );
+        assert_eq!(
+            run(
+                &mut executor,
+                "SELECT name FROM users WHERE EXISTS (SELECT * FROM settings);"
+            )
+            .unwrap(),
+            "alice, bob, carol"
+        );
+
+        // Correlated: run for each row, seeing its columns
+        assert_eq!(
+            run(
+                &mut executor,
+                "SELECT name FROM users WHERE NOT EXISTS (SELECT * FROM orders WHERE user_id = users.id);"
+            )
+            .unwrap(),
+            "bob"
+        );
+        assert_eq!(
+            run(
+                &mut executor,
+                "SELECT name, (SELECT total FROM orders WHERE user_id = id AND total > 30) FROM users;"
+            )
+            .unwrap(),
+            "alice|50, bob|NULL, carol|40"
+        );
+        assert_eq!(
+            run(
+                &mut executor,
+                "SELECT name FROM users WHERE 40 IN (SELECT total FROM orders WHERE user_id = users.id);"
+            )
+            .unwrap(),
+            "carol"
+        );
+        // Two levels out, and inside a write
+        assert_eq!(
+            run(
+                &mut executor,
+                "SELECT name FROM users WHERE EXISTS (SELECT * FROM orders WHERE user_id = users.id \
+                 AND EXISTS (SELECT * FROM settings WHERE value < orders.total AND users.name = 'carol'));"
+            )
+            .unwrap(),
+            "carol"
+        );
+        assert_eq!(
+            run(
+                &mut executor,
+                "DELETE FROM users WHERE NOT EXISTS (SELECT * FROM orders WHERE user_id = id) RETURNING name;"
+            )
+            .unwrap(),
+            "bob"
+        );
+
+        let err = run(
+            &mut executor,
+            "SELECT name, (SELECT total FROM orders WHERE user_id = id) FROM users;",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "A scalar subquery returned 2 rows, not one"
+        );
+        let err = run(
+            &mut executor,
+            "SELECT name FROM users WHERE EXISTS (SELECT * FROM orders WHERE nope = id);",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Column 'nope' does not exist"));
+    }
+
+    #[test]
+    fn test_quoted_identifiers() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        for sql in [
+            r#"CREATE TABLE "user table" ("select" INTEGER, `Full Name` TEXT);"#,
+            r#"INSERT INTO "user table" ("select", "Full Name") VALUES (1, 'Ada Lovelace');"#,
+            r#"CREATE VIEW "named users" AS SELECT "Full Name" FROM "user table" WHERE "select" = 1;"#,
+        ] {
+            run(&mut executor, sql).unwrap();
+        }
+
+        assert_eq!(
+            run(
+                &mut executor,
+                r#"SELECT "user table"."select", `Full Name` FROM "user table" WHERE "select" >= 1;"#
+            )
+            .unwrap(),
+            "1|Ada Lovelace"
+        );
+        assert_eq!(
+            run(&mut executor, r#"SELECT * FROM "named users";"#).unwrap(),
+            "Ada Lovelace"
+        );
+
+        // Unquoted, a keyword is still a keyword
+        assert!(run(&mut executor, r#"SELECT select FROM "user table";"#).is_err());
+    }
+
+    #[test]
+    fn test_prepared_statements() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        run(
+            &mut executor,
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);",
+        )
+        .unwrap();
+
+        // Parsed once, run with different values
+        let insert = executor
+            .prepare("INSERT INTO users VALUES (?, ?);")
+            .unwrap();
+        let text = |s: &str| Value::Text(s.to_string());
+        for (id, name) in [(1, "alice"), (2, "bob"), (3, "x'); DROP TABLE users; --")] {
+            executor
+                .execute_prepared(&insert, &[Value::Integer(id), text(name)])
+                .unwrap();
+        }
+
+        let select = executor
+            .prepare("SELECT name FROM users WHERE id > $1 - 1 AND name != $2 ORDER BY id;")
+            .unwrap();
+        let names = |executor: &mut Executor, values: &[Value]| match executor
+            .execute_prepared(&select, values)
+            .unwrap()
+        {
+            ExecutionResult::Rows { rows, .. } => rows
+                .iter()
+                .map(|row| row.values()[0].to_string())
+                .collect::<Vec<_>>(),
+            other => panic!("Expected rows, got {:?}", other),
+        };
+        assert_eq!(
+            names(&mut executor, &[Value::Integer(2), text("nobody")]),
+            ["bob", "x'); DROP TABLE users; --"]
+        );
+        assert_eq!(
+            names(&mut executor, &[Value::Integer(1), text("bob")]),
+            ["alice", "x'); DROP TABLE users; --"]
+        );
+
+        // Values have to fit the column like literals do
+        let err = executor
+            .execute_prepared(&insert, &[text("four"), text("dan")])
+            .unwrap_err();
+        assert!(matches!(err, HozonError::TypeMismatch(_)), "{:?}", err);
+        assert!(
+            executor
+                .execute_prepared(&insert, &[Value::Integer(4)])
+                .is_err()
+        );
+
+        // Run without binding, a parameter has no value
+        let err = run(&mut executor, "SELECT * FROM users WHERE id = ?;").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Parameter $1 has no value; prepare the statement and bind it"
+        );
+        assert!(run(&mut executor, "INSERT INTO users VALUES ($1, 'eve');").is_err());
+    }
+
+    #[test]
+    fn test_execute_all() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        let script = |sql: &str| {
+            Parser::new(tokenizer::tokenize(sql).unwrap())
+                .parse_all()
+                .unwrap()
+        };
+
+        let results = executor.execute_all(
+            script(
+                "CREATE TABLE t (id INTEGER PRIMARY KEY);
+                 INSERT INTO t VALUES (1);
+                 INSERT INTO t VALUES (1);
+                 INSERT INTO t VALUES (2);",
+            ),
+            false,
+        );
+        // Stopped at the duplicate key
+        assert_eq!(results.len(), 3);
+        assert!(results[1].is_ok());
+        assert!(results[2].is_err());
+        assert_eq!(run(&mut executor, "SELECT * FROM t;").unwrap(), "1");
+
+        let results = executor.execute_all(
+            script("INSERT INTO t VALUES (1); INSERT INTO t VALUES (3); SELECT * FROM t;"),
+            true,
+        );
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_err());
+        match &results[2] {
+            Ok(ExecutionResult::Rows { rows, .. }) => assert_eq!(rows.len(), 2),
+            other => panic!("Expected rows, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_system_tables() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        executor
+            .execute(Statement::CreateTable {
+                name: "users".to_string(),
+                columns: vec![
+                    Column::new("id", DataType::Integer).primary_key(),
+                    Column::new("name", DataType::Text),
+                ],
+            })
+            .unwrap();
+
+        let select = |table: &str| Statement::Select {
+            table_name: table.to_string(),
+            columns: SelectColumns::All,
+            where_clause: None,
+            order_by: Vec::new(),
+            distinct: false,
+            joins: Vec::new(),
+        };
+
+        match executor.query(select("hozon_columns")).unwrap() {
+            ExecutionResult::Rows { columns, rows } => {
+                assert_eq!(columns[1], "name");
+                assert_eq!(rows.len(), 2);
+                assert_eq!(
+                    rows[0].values(),
+                    &vec![
+                        Value::Text("users".to_string()),
+                        Value::Text("id".to_string()),
+                        Value::Integer(1),
+                        Value::Text("INTEGER".to_string()),
+                        Value::Boolean(false),
+                        Value::Boolean(true),
+                    ]
+                );
+            }
+            _ => panic!("Expected Rows result"),
+        }
+
+        match executor.query(select("hozon_tables")).unwrap() {
+            ExecutionResult::Rows { rows, .. } => {
+                assert_eq!(rows.len(), 1);
+                assert_eq!(rows[0].values()[0], Value::Text("users".to_string()));
+            }
+            _ => panic!("Expected Rows result"),
+        }
+
+        // The names are reserved
+        let err = executor
+            .execute(Statement::CreateTable {
+                name: "hozon_tables".to_string(),
+                columns: vec![Column::new("id", DataType::Integer)],
+            })
+            .unwrap_err();
+        assert!(err.to_string().contains("reserved"));
+    }
+
+    #[test]
+    fn test_identifier_case() {
+        let catalog = TableCatalog::new(PageManager::new_in_memory().unwrap()).unwrap();
+        let mut executor = Executor::new(catalog);
+        executor
+            .execute(Statement::CreateTable {
+                name: "Users".to_string(),
+                columns: vec![
+                    Column::new("id", DataType::Integer),
+                    Column::new("Name", DataType::Text),
+                ],
+            })
+            .unwrap();
+
+        // Insensitive by default, for tables and columns alike
+        executor
+            .execute(Statement::Insert {
+                table_name: "USERS".to_string(),
+                columns: Some(vec!["ID".to_string(), "name".to_string()]),
+                values: literals(vec![Value::Integer(1), Value::Text("alice".to_string())]),
+                on_conflict: None,
+                returning: None,
+            })
+            .unwrap();
+        let select = || Statement::Select {
+            table_name: "users".to_string(),
+            columns: SelectColumns::Specific(vec![SelectItem::column("NAME")]),
+            where_clause: None,
+            order_by: Vec::new(),
+            distinct: false,
+            joins: Vec::new(),
+        };
+        match executor.query(select()).unwrap() {
+            ExecutionResult::Rows { rows, .. } => assert_eq!(rows.len(), 1),
+            _ => panic!("Expected Rows result"),
+        }
+
+        // Names differing only in case clash in either mode
+        let err = executor
+            .execute(Statement::CreateTable {
+                name: "users".to_string(),
+                columns: vec![Column::new("id", DataType::Integer)],
+            })
+            .unwrap_err();
+        assert!(err.to_string().contains("Table 'Users' already exists"));
+
+        executor
+            .execute(Statement::Pragma {
+                name: "identifier_case".to_string(),
+                value: Some("sensitive".to_string()),
+            })
+            .unwrap();
+        assert!(executor.query(select()).is_err());
+        assert!(executor.catalog().get_table("Users").is_some());
+        assert!(executor.catalog().get_table("users").is_none());
     }
 }
@@ -0,0 +1,482 @@
+//! Statements written back out as SQL. The text is canonical - keywords in
+//! capitals, identifiers quoted only where they must be, one space between
+//! words - and parses back to the statement it came from. A view's query is
+//! stored this way.
+
+use crate::catalog::row::Value;
+use crate::sql::parser::{
+    Assignment, ConflictAction, Expr, OnConflict, SelectColumns, SelectItem, Statement,
+};
+use crate::sql::tokenizer::{self, Token};
+
+/// `statement` as SQL, with its closing semicolon
+pub fn statement(statement: &Statement) -> String {
+    match statement {
+        // The stored query has its own semicolon
+        Statement::CreateView { name, query } => format!(
+            "CREATE VIEW {} AS {};",
+            qualified(name),
+            query.trim_end_matches(';')
+        ),
+        statement => format!("{};", body(statement)),
+    }
+}
+
+/// `expr` as SQL
+pub fn expr(expr: &Expr) -> String {
+    expr_with(expr, &full_subquery)
+}
+
+/// `expr` as SQL, with each subquery written by `subquery`, parentheses
+/// included
+pub fn expr_with(expr: &Expr, subquery: &dyn Fn(&Statement) -> String) -> String {
+    let operand = |expr: &Expr, level: u8| operand(expr, level, subquery);
+    let list = |exprs: &[Expr]| {
+        exprs
+            .iter()
+            .map(|expr| expr_with(expr, subquery))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let not = |negated: bool| if negated { "NOT " } else { "" };
+
+    match expr {
+        Expr::Literal(value) => literal(value),
+        Expr::Parameter(n) => Token::Parameter(Some(*n)).to_string(),
+        Expr::Column(name) => qualified(name),
+        Expr::Not(inner) => format!("NOT {}", operand(inner, 3)),
+        // A '-' before a number would make it a negative literal instead
+        Expr::Negate(inner) => match &**inner {
+            Expr::Literal(Value::Integer(_) | Value::BigInt(_) | Value::Float(_)) => {
+                format!("-({})", expr_with(inner, subquery))
+            }
+            _ => match operand(inner, ATOM) {
+                inner if inner.starts_with('-') => format!("- {}", inner),
+                inner => format!("-{}", inner),
+            },
+        },
+        Expr::IsNull { expr, negated } => {
+            format!("{} IS {}NULL", operand(expr, 4), not(*negated))
+        }
+        Expr::InList {
+            expr,
+            list: items,
+            negated,
+        } => format!("{} {}IN ({})", operand(expr, 5), not(*negated), list(items)),
+        Expr::InSubquery {
+            expr,
+            query,
+            negated,
+        } => format!(
+            "{} {}IN {}",
+            operand(expr, 5),
+            not(*negated),
+            subquery(query)
+        ),
+        Expr::BinaryOp { left, op, right } => {
+            let precedence = op.precedence();
+            // Operators group left to right, so a right operand of the same
+            // precedence needs parentheses. Comparisons don't chain at all.
+            let left_level = match precedence {
+                4 => 5,
+                precedence => precedence,
+            };
+            format!(
+                "{} {} {}",
+                operand(left, left_level),
+                op.as_str(),
+                operand(right, precedence + 1)
+            )
+        }
+        Expr::Function { name, args } => format!("{}({})", identifier(name), list(args)),
+        Expr::CountAll => "COUNT(*)".to_string(),
+        Expr::Subquery(query) => subquery(query),
+        Expr::Exists(query) => format!("EXISTS {}", subquery(query)),
+        Expr::Cast { expr, data_type } => format!(
+            "CAST({} AS {})",
+            expr_with(expr, subquery),
+            data_type.as_str()
+        ),
+    }
+}
+
+/// How tightly an operand binds, above any operator
+const ATOM: u8 = 6;
+
+/// How tightly `expr` binds: as its operator (see
+/// `BinaryOperator::precedence`), with NOT at 3 and IS NULL and IN with
+/// the comparisons at 4
+fn level(expr: &Expr) -> u8 {
+    match expr {
+        Expr::BinaryOp { op, .. } => op.precedence(),
+        Expr::Not(_) => 3,
+        Expr::IsNull { .. } | Expr::InList { .. } | Expr::InSubquery { .. } => 4,
+        _ => ATOM,
+    }
+}
+
+/// `expr` where what takes it needs one binding at least as tightly as
+/// `level`, in parentheses if it binds less tightly
+fn operand(expr: &Expr, level: u8, subquery: &dyn Fn(&Statement) -> String) -> String {
+    match self::level(expr) < level {
+        true => format!("({})", expr_with(expr, subquery)),
+        false => expr_with(expr, subquery),
+    }
+}
+
+fn full_subquery(query: &Statement) -> String {
+    format!("({})", body(query))
+}
+
+/// `statement` as SQL, without its closing semicolon
+fn body(statement: &Statement) -> String {
+    match statement {
+        Statement::CreateTable { name, columns } => {
+            let columns: Vec<String> = columns
+                .iter()
+                .map(|column| {
+                    let mut sql = format!(
+                        "{} {}",
+                        identifier(column.name()),
+                        column.data_type().as_str()
+                    );
+                    if column.is_primary_key() {
+                        sql.push_str(" PRIMARY KEY");
+                    } else if !column.is_nullable() {
+                        sql.push_str(" NOT NULL");
+                    }
+                    if column.is_auto_increment() {
+                        sql.push_str(" AUTOINCREMENT");
+                    }
+                    sql
+                })
+                .collect();
+            format!("CREATE TABLE {} ({})", qualified(name), columns.join(", "))
+        }
+        Statement::Insert {
+            table_name,
+            columns,
+            values,
+            on_conflict,
+            returning,
+        } => {
+            let (or, conflict) = match on_conflict {
+                None => ("", String::new()),
+                Some(OnConflict {
+                    action: ConflictAction::Replace,
+                    ..
+                }) => ("OR REPLACE ", String::new()),
+                Some(OnConflict {
+                    target,
+                    action: ConflictAction::Nothing,
+                }) if target.is_empty() => ("OR IGNORE ", String::new()),
+                Some(OnConflict { target, action }) => {
+                    let target = match target.is_empty() {
+                        true => String::new(),
+                        false => format!("({}) ", identifiers(target)),
+                    };
+                    let action = match action {
+                        ConflictAction::Update(assignments) => {
+                            format!("DO UPDATE SET {}", self::assignments(assignments))
+                        }
+                        _ => "DO NOTHING".to_string(),
+                    };
+                    ("", format!(" ON CONFLICT {}{}", target, action))
+                }
+            };
+            let columns = match columns {
+                Some(columns) => format!(" ({})", identifiers(columns)),
+                None => String::new(),
+            };
+            let rows: Vec<String> = values
+                .iter()
+                .map(|row| {
+                    let values: Vec<String> = row.iter().map(expr).collect();
+                    format!("({})", values.join(", "))
+                })
+                .collect();
+            format!(
+                "INSERT {}INTO {}{} VALUES {}{}{}",
+                or,
+                qualified(table_name),
+                columns,
+                rows.join(", "),
+                conflict,
+                self::returning(returning)
+            )
+        }
+        Statement::Update {
+            table_name,
+            assignments,
+            where_clause,
+            returning,
+        } => format!(
+            "UPDATE {} SET {}{}{}",
+            qualified(table_name),
+            self::assignments(assignments),
+            where_sql(where_clause),
+            self::returning(returning)
+        ),
+        Statement::Delete {
+            table_name,
+            where_clause,
+            returning,
+        } => format!(
+            "DELETE FROM {}{}{}",
+            qualified(table_name),
+            where_sql(where_clause),
+            self::returning(returning)
+        ),
+        Statement::Select {
+            table_name,
+            columns,
+            where_clause,
+            order_by,
+            distinct,
+            joins,
+        } => {
+            let mut sql = String::from("SELECT ");
+            if *distinct {
+                sql.push_str("DISTINCT ");
+            }
+            sql.push_str(&format!(
+                "{} FROM {}",
+                self::columns(columns),
+                qualified(table_name)
+            ));
+            for join in joins {
+                sql.push_str(&format!(
+                    " JOIN {} ON {}",
+                    qualified(&join.table_name),
+                    expr(&join.on)
+                ));
+            }
+            sql.push_str(&where_sql(where_clause));
+            if !order_by.is_empty() {
+                let keys: Vec<String> = order_by
+                    .iter()
+                    .map(|key| match key.descending {
+                        true => format!("{} DESC", expr(&key.expr)),
+                        false => expr(&key.expr),
+                    })
+                    .collect();
+                sql.push_str(&format!(" ORDER BY {}", keys.join(", ")));
+            }
+            sql
+        }
+        Statement::CreateIndex {
+            name,
+            table_name,
+            columns,
+            unique,
+        } => format!(
+            "CREATE {}INDEX {} ON {} ({})",
+            if *unique { "UNIQUE " } else { "" },
+            identifier(name),
+            qualified(table_name),
+            identifiers(columns)
+        ),
+        Statement::DropIndex { name } => format!("DROP INDEX {}", identifier(name)),
+        Statement::CreateView { .. } => {
+            self::statement(statement).trim_end_matches(';').to_string()
+        }
+        Statement::Pragma { name, value } => match value {
+            Some(value) => format!("PRAGMA {} = {}", identifier(name), pragma_value(value)),
+            None => format!("PRAGMA {}", identifier(name)),
+        },
+        Statement::Checkpoint => "CHECKPOINT".to_string(),
+        Statement::Analyze { table_name } => match table_name {
+            Some(table_name) => format!("ANALYZE {}", qualified(table_name)),
+            None => "ANALYZE".to_string(),
+        },
+        Statement::Explain(statement) => format!("EXPLAIN {}", body(statement)),
+        Statement::Savepoint { name } => format!("SAVEPOINT {}", identifier(name)),
+        Statement::Release { name } => format!("RELEASE {}", identifier(name)),
+        Statement::RollbackTo { name } => format!("ROLLBACK TO {}", identifier(name)),
+        Statement::Attach { path, alias } => format!(
+            "ATTACH {} AS {}",
+            Token::StringLiteral(path.clone()),
+            identifier(alias)
+        ),
+        Statement::Detach { alias } => format!("DETACH {}", identifier(alias)),
+    }
+}
+
+/// A column list, giving each expression's result column its name with
+/// AS where it isn't the one the expression gets anyway
+fn columns(columns: &SelectColumns) -> String {
+    let items = match columns {
+        SelectColumns::All => return "*".to_string(),
+        SelectColumns::Specific(items) => items,
+    };
+    let items: Vec<String> = items
+        .iter()
+        .map(|item| match item {
+            SelectItem::Wildcard(None) => "*".to_string(),
+            SelectItem::Wildcard(Some(table)) => format!("{}.*", identifier(table)),
+            SelectItem::Expr {
+                expr: expression,
+                name,
+            } => {
+                let sql = expr(expression);
+                let default = match expression {
+                    Expr::Column(column) => SelectItem::column(column) == *item,
+                    _ => tokenizer::tokenize(&sql)
+                        .is_ok_and(|tokens| tokenizer::to_sql(&tokens) == *name),
+                };
+                match default {
+                    true => sql,
+                    false => format!("{} AS {}", sql, identifier(name)),
+                }
+            }
+        })
+        .collect();
+    items.join(", ")
+}
+
+/// `[RETURNING ...]`, with a space before it
+fn returning(returning: &Option<SelectColumns>) -> String {
+    match returning {
+        Some(columns) => format!(" RETURNING {}", self::columns(columns)),
+        None => String::new(),
+    }
+}
+
+/// `[WHERE ...]`, with a space before it
+fn where_sql(where_clause: &Option<Expr>) -> String {
+    match where_clause {
+        Some(condition) => format!(" WHERE {}", expr(condition)),
+        None => String::new(),
+    }
+}
+
+/// `col = expr, ...`
+fn assignments(assignments: &[Assignment]) -> String {
+    assignments
+        .iter()
+        .map(|a| format!("{} = {}", identifier(&a.column), expr(&a.value)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A PRAGMA's value: a number or boolean as it is, anything else as a name
+fn pragma_value(value: &str) -> String {
+    let digits = value.strip_prefix('-').unwrap_or(value);
+    let number = digits.parse::<u64>().is_ok_and(|n| n.to_string() == digits);
+    match number || value == "true" || value == "false" {
+        true => value.to_string(),
+        false => identifier(value),
+    }
+}
+
+fn literal(value: &Value) -> String {
+    match value {
+        Value::Integer(n) => n.to_string(),
+        Value::BigInt(n) => n.to_string(),
+        Value::Float(n) => Token::FloatLiteral(*n).to_string(),
+        Value::Text(s) => Token::StringLiteral(s.clone()).to_string(),
+        Value::Boolean(b) => Token::BoolLiteral(*b).to_string(),
+        Value::Blob(bytes) => Token::BlobLiteral(bytes.clone()).to_string(),
+        Value::Null => Token::Null.to_string(),
+    }
+}
+
+/// `name`, or `table.name` for a column and `database.name` for a table
+fn qualified(name: &str) -> String {
+    match name.split_once('.') {
+        Some((table, column)) => format!("{}.{}", identifier(table), identifier(column)),
+        None => identifier(name),
+    }
+}
+
+/// `name` as an identifier, quoted where it must be
+pub fn identifier(name: &str) -> String {
+    Token::Identifier(name.to_string()).to_string()
+}
+
+fn identifiers(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| identifier(name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::Parser;
+
+    fn parse(sql: &str) -> Statement {
+        Parser::from_sql(sql).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn test_round_trips() {
+        for sql in [
+            "CREATE TABLE users (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL, \"order\" FLOAT, b BLOB, big BIGINT, ok BOOLEAN);",
+            "INSERT INTO users VALUES (1, 'it''s', -2.5, X'00FF', NULL, TRUE);",
+            "INSERT OR REPLACE INTO users (id, name) VALUES ($1, $2) RETURNING *;",
+            "INSERT OR IGNORE INTO users (id) VALUES (1);",
+            "INSERT INTO users (id, name) VALUES (1, 'a'), (2, NULL);",
+            "INSERT INTO users (id) VALUES (1) ON CONFLICT (id) DO NOTHING;",
+            "INSERT INTO users (id) VALUES (1) ON CONFLICT DO UPDATE SET name = excluded.name RETURNING id, name AS n;",
+            "UPDATE users SET name = UPPER(name), id = id - 1 WHERE id IN (1, 2) RETURNING rowid;",
+            "DELETE FROM users WHERE name IS NOT NULL AND NOT id = 1;",
+            "DELETE FROM users;",
+            "SELECT DISTINCT u.*, o.total AS amount, id - -1, - -id, -(1), CAST(id AS TEXT) FROM users JOIN orders ON u.id = o.user_id WHERE (a = 1 OR b = 2) AND c = 3 ORDER BY 1 DESC, name;",
+            "SELECT * FROM t WHERE (a = 1) = TRUE AND (NOT a) IS NULL AND a - (b - c) > -1;",
+            "SELECT * FROM t WHERE a NOT IN (SELECT b FROM u WHERE EXISTS (SELECT * FROM v)) OR (SELECT MAX(b) FROM u) > 1;",
+            "SELECT (1 - 2), (a) AS b, 'x' AS \"select\" FROM t;",
+            "SELECT COUNT(*) AS n FROM t WHERE (SELECT COUNT(*) FROM u) > 1;",
+            "CREATE UNIQUE INDEX by_name ON users (name, id);",
+            "CREATE INDEX \"by id\" ON users (id);",
+            "DROP INDEX by_name;",
+            "CREATE VIEW names AS SELECT name FROM users WHERE id > 1;",
+            "PRAGMA page_size;",
+            "PRAGMA cache_size = -2000;",
+            "PRAGMA identifier_case = sensitive;",
+            "PRAGMA journal = 'write ahead';",
+            "CHECKPOINT;",
+            "ANALYZE;",
+            "ANALYZE users;",
+            "EXPLAIN SELECT * FROM users;",
+            "SAVEPOINT a;",
+            "RELEASE SAVEPOINT a;",
+            "ROLLBACK TO a;",
+            "ATTACH 'other.hdb' AS other;",
+            "DETACH other;",
+            "SELECT orders.id FROM other.orders JOIN other.users ON orders.user_id = users.id;",
+        ] {
+            let parsed = parse(sql);
+            let formatted = statement(&parsed);
+            assert_eq!(
+                parse(&formatted),
+                parsed,
+                "{} formatted as {}",
+                sql,
+                formatted
+            );
+            // Canonical text formats as itself
+            assert_eq!(statement(&parse(&formatted)), formatted);
+        }
+    }
+
+    #[test]
+    fn test_canonical_text() {
+        assert_eq!(
+            statement(&parse(
+                "select  id,NAME from Users where id>=1 order by id asc;"
+            )),
+            "SELECT id, NAME FROM Users WHERE id >= 1 ORDER BY id;"
+        );
+        assert_eq!(
+            statement(&parse("insert into t values (1.50, 'a', false);")),
+            "INSERT INTO t VALUES (1.5, 'a', FALSE);"
+        );
+        assert_eq!(
+            statement(&parse("SELECT (a) - (1) AS x FROM t;")),
+            "SELECT a - 1 AS x FROM t;"
+        );
+    }
+}
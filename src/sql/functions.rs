@@ -0,0 +1,333 @@
+//! Built-in scalar functions. Each takes its arguments' values for one row
+//! and returns one value; NULL arguments give NULL unless the function is
+//! about NULLs, like COALESCE.
+
+use crate::catalog::format::ValueFormat;
+use crate::catalog::row::Value;
+use crate::error::{HozonError, Result};
+
+pub struct Function {
+    pub name: &'static str,
+    min_args: usize,
+    /// None for any number
+    max_args: Option<usize>,
+    call: fn(&[Value]) -> Result<Value>,
+}
+
+impl std::fmt::Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+const FUNCTIONS: &[Function] = &[
+    Function {
+        name: "UPPER",
+        min_args: 1,
+        max_args: Some(1),
+        call: upper,
+    },
+    Function {
+        name: "LOWER",
+        min_args: 1,
+        max_args: Some(1),
+        call: lower,
+    },
+    Function {
+        name: "LENGTH",
+        min_args: 1,
+        max_args: Some(1),
+        call: length,
+    },
+    Function {
+        name: "ABS",
+        min_args: 1,
+        max_args: Some(1),
+        call: abs,
+    },
+    Function {
+        name: "COALESCE",
+        min_args: 2,
+        max_args: None,
+        call: coalesce,
+    },
+    Function {
+        name: "SUBSTR",
+        min_args: 2,
+        max_args: Some(3),
+        call: substr,
+    },
+];
+
+/// The function `name` calls with `args` arguments, matched ignoring case
+pub fn lookup(name: &str, args: usize) -> Result<&'static Function> {
+    let function = FUNCTIONS
+        .iter()
+        .find(|f| f.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| HozonError::InvalidStatement(format!("Unknown function '{}'", name)))?;
+    if args < function.min_args || function.max_args.is_some_and(|max| args > max) {
+        let expected = match function.max_args {
+            Some(max) if max == function.min_args => max.to_string(),
+            Some(max) => format!("{} to {}", function.min_args, max),
+            None => format!("at least {}", function.min_args),
+        };
+        return Err(HozonError::InvalidStatement(format!(
+            "{} takes {} argument{}, not {}",
+            function.name,
+            expected,
+            if expected == "1" { "" } else { "s" },
+            args
+        )));
+    }
+    Ok(function)
+}
+
+impl Function {
+    pub fn call(&self, args: &[Value]) -> Result<Value> {
+        (self.call)(args)
+    }
+
+    fn mismatch(&self, expected: &str, found: &Value) -> HozonError {
+        HozonError::TypeMismatch(format!(
+            "{} expects {}, found {}",
+            self.name,
+            expected,
+            found.display_with(&ValueFormat::sql())
+        ))
+    }
+}
+
+fn function(name: &str) -> &'static Function {
+    FUNCTIONS
+        .iter()
+        .find(|f| f.name == name)
+        .expect("registered")
+}
+
+fn upper(args: &[Value]) -> Result<Value> {
+    match &args[0] {
+        Value::Text(s) => Ok(Value::Text(s.to_uppercase())),
+        Value::Null => Ok(Value::Null),
+        other => Err(function("UPPER").mismatch("TEXT", other)),
+    }
+}
+
+fn lower(args: &[Value]) -> Result<Value> {
+    match &args[0] {
+        Value::Text(s) => Ok(Value::Text(s.to_lowercase())),
+        Value::Null => Ok(Value::Null),
+        other => Err(function("LOWER").mismatch("TEXT", other)),
+    }
+}
+
+/// Characters of TEXT, bytes of a BLOB; INTEGER unless it needs a BIGINT
+fn length(args: &[Value]) -> Result<Value> {
+    let length = match &args[0] {
+        Value::Text(s) => s.chars().count(),
+        Value::Blob(b) => b.len(),
+        Value::Null => return Ok(Value::Null),
+        other => return Err(function("LENGTH").mismatch("TEXT or BLOB", other)),
+    };
+    Ok(i32::try_from(length).map_or(Value::BigInt(length as i64), Value::Integer))
+}
+
+fn abs(args: &[Value]) -> Result<Value> {
+    match &args[0] {
+        // i32::MIN has no INTEGER absolute value, but it has a BIGINT one
+        Value::Integer(n) => Ok(n
+            .checked_abs()
+            .map_or(Value::BigInt((*n as i64).abs()), Value::Integer)),
+        Value::BigInt(n) => n
+            .checked_abs()
+            .map(Value::BigInt)
+            .ok_or_else(|| HozonError::TypeMismatch("ABS overflows BIGINT".to_string())),
+        Value::Float(f) => Ok(Value::Float(f.abs())),
+        Value::Null => Ok(Value::Null),
+        other => Err(function("ABS").mismatch("a number", other)),
+    }
+}
+
+/// The first argument that isn't NULL
+fn coalesce(args: &[Value]) -> Result<Value> {
+    Ok(args
+        .iter()
+        .find(|v| **v != Value::Null)
+        .cloned()
+        .unwrap_or(Value::Null))
+}
+
+/// SUBSTR(text, start[, length]) as in SQLite: `start` counts from 1, or
+/// back from the end if negative, and a negative length takes the
+/// characters before `start` instead. BLOBs are cut by bytes.
+fn substr(args: &[Value]) -> Result<Value> {
+    let substr = function("SUBSTR");
+    let mut numbers = Vec::new();
+    for arg in &args[1..] {
+        match arg {
+            Value::Integer(n) => numbers.push(*n as i64),
+            Value::BigInt(n) => numbers.push(*n),
+            Value::Null => return Ok(Value::Null),
+            other => return Err(substr.mismatch("an integer position and length", other)),
+        }
+    }
+    let len = match &args[0] {
+        Value::Text(s) => s.chars().count(),
+        Value::Blob(b) => b.len(),
+        Value::Null => return Ok(Value::Null),
+        other => return Err(substr.mismatch("TEXT or BLOB", other)),
+    };
+    let (start, count) = substr_range(len as i64, numbers[0], numbers.get(1).copied());
+
+    Ok(match &args[0] {
+        Value::Text(s) => Value::Text(s.chars().skip(start).take(count).collect()),
+        Value::Blob(b) => Value::Blob(b[start..start + count].to_vec()),
+        _ => unreachable!("checked above"),
+    })
+}
+
+/// The 0-based start and length SUBSTR's arguments select from something
+/// `len` long, clamped to it
+fn substr_range(len: i64, start: i64, count: Option<i64>) -> (usize, usize) {
+    // Without a length it runs to the end, however far back it starts
+    let mut count = count.unwrap_or(i64::MAX);
+    let backwards = count < 0;
+    count = count.saturating_abs();
+    let mut start = start;
+    if start < 0 {
+        start = start.saturating_add(len);
+        if start < 0 {
+            count = (count.saturating_add(start)).max(0);
+            start = 0;
+        }
+    } else if start > 0 {
+        start -= 1;
+    } else if count > 0 {
+        // Position 0 is just before the first character
+        count -= 1;
+    }
+    if backwards {
+        start -= count;
+        if start < 0 {
+            count += start;
+            start = 0;
+        }
+    }
+    let start = start.min(len);
+    let count = count.min(len - start);
+    (start as usize, count as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: Vec<Value>) -> Result<Value> {
+        lookup(name, args.len())?.call(&args)
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup("upper", 1).unwrap().name, "UPPER");
+        assert_eq!(
+            lookup("NOPE", 1).unwrap_err().to_string(),
+            "Unknown function 'NOPE'"
+        );
+        assert_eq!(
+            lookup("upper", 2).unwrap_err().to_string(),
+            "UPPER takes 1 argument, not 2"
+        );
+        assert_eq!(
+            lookup("SUBSTR", 1).unwrap_err().to_string(),
+            "SUBSTR takes 2 to 3 arguments, not 1"
+        );
+        assert_eq!(
+            lookup("COALESCE", 1).unwrap_err().to_string(),
+            "COALESCE takes at least 2 arguments, not 1"
+        );
+    }
+
+    #[test]
+    fn test_text_functions() {
+        assert_eq!(call("UPPER", vec![text("ümlaut")]).unwrap(), text("ÜMLAUT"));
+        assert_eq!(call("LOWER", vec![text("MiXeD")]).unwrap(), text("mixed"));
+        assert_eq!(
+            call("LENGTH", vec![text("héllo")]).unwrap(),
+            Value::Integer(5)
+        );
+        assert_eq!(
+            call("LENGTH", vec![Value::Blob(vec![0xC3, 0xA9])]).unwrap(),
+            Value::Integer(2)
+        );
+        assert_eq!(call("UPPER", vec![Value::Null]).unwrap(), Value::Null);
+        assert_eq!(
+            call("UPPER", vec![Value::Integer(1)])
+                .unwrap_err()
+                .to_string(),
+            "UPPER expects TEXT, found 1"
+        );
+    }
+
+    #[test]
+    fn test_abs_and_coalesce() {
+        assert_eq!(
+            call("ABS", vec![Value::Integer(-3)]).unwrap(),
+            Value::Integer(3)
+        );
+        assert_eq!(
+            call("ABS", vec![Value::Integer(i32::MIN)]).unwrap(),
+            Value::BigInt(2_147_483_648)
+        );
+        assert!(call("ABS", vec![Value::BigInt(i64::MIN)]).is_err());
+        assert_eq!(
+            call("ABS", vec![Value::Float(-1.5)]).unwrap(),
+            Value::Float(1.5)
+        );
+
+        assert_eq!(
+            call(
+                "COALESCE",
+                vec![Value::Null, Value::Integer(2), Value::Integer(3)]
+            )
+            .unwrap(),
+            Value::Integer(2)
+        );
+        assert_eq!(
+            call("COALESCE", vec![Value::Null, Value::Null]).unwrap(),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn test_substr() {
+        let substr = |start: i64, count: Option<i64>| {
+            let mut args = vec![text("hello"), Value::BigInt(start)];
+            args.extend(count.map(Value::BigInt));
+            call("SUBSTR", args).unwrap()
+        };
+        assert_eq!(substr(2, Some(3)), text("ell"));
+        assert_eq!(substr(2, None), text("ello"));
+        assert_eq!(substr(-3, None), text("llo"));
+        assert_eq!(substr(0, Some(2)), text("h"));
+        assert_eq!(substr(0, None), text("hello"));
+        assert_eq!(substr(-10, None), text("hello"));
+        assert_eq!(substr(4, Some(-2)), text("el"));
+        assert_eq!(substr(-10, Some(7)), text("he"));
+        assert_eq!(substr(9, Some(2)), text(""));
+        assert_eq!(
+            call(
+                "SUBSTR",
+                vec![Value::Blob(vec![1, 2, 3]), Value::Integer(2)]
+            )
+            .unwrap(),
+            Value::Blob(vec![2, 3])
+        );
+        assert_eq!(
+            call("SUBSTR", vec![text("x"), Value::Null]).unwrap(),
+            Value::Null
+        );
+    }
+}
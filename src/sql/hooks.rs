@@ -0,0 +1,114 @@
+//! Callbacks run for each row a write changes, so an application embedding
+//! HozonDB can keep a cache, replicate or audit on top of it. Each is given
+//! the table, the row's rowid and its values. They run once a statement
+//! has written all its rows, so a statement that fails calls none; a later
+//! ROLLBACK TO doesn't call them again for the rows it puts back.
+
+use crate::catalog::row::Row;
+
+/// Called with the table, rowid and row inserted or deleted
+pub type RowHook = Box<dyn FnMut(&str, i64, &Row) + Send + Sync>;
+
+/// Called with the table, rowid, and the row before and after
+pub type UpdateHook = Box<dyn FnMut(&str, i64, &Row, &Row) + Send + Sync>;
+
+#[derive(Default)]
+pub struct Hooks {
+    insert: Vec<RowHook>,
+    update: Vec<UpdateHook>,
+    delete: Vec<RowHook>,
+}
+
+impl Hooks {
+    pub fn on_insert(&mut self, hook: RowHook) {
+        self.insert.push(hook);
+    }
+
+    pub fn on_update(&mut self, hook: UpdateHook) {
+        self.update.push(hook);
+    }
+
+    pub fn on_delete(&mut self, hook: RowHook) {
+        self.delete.push(hook);
+    }
+
+    pub fn clear(&mut self) {
+        *self = Hooks::default();
+    }
+
+    /// Whether anything wants updated rows, which need their old values
+    /// read before they're written
+    pub fn watches_updates(&self) -> bool {
+        !self.update.is_empty()
+    }
+
+    pub fn inserted(&mut self, table_name: &str, rowid: i64, row: &Row) {
+        for hook in &mut self.insert {
+            hook(table_name, rowid, row);
+        }
+    }
+
+    pub fn updated(&mut self, table_name: &str, rowid: i64, old: &Row, new: &Row) {
+        for hook in &mut self.update {
+            hook(table_name, rowid, old, new);
+        }
+    }
+
+    pub fn deleted(&mut self, table_name: &str, rowid: i64, row: &Row) {
+        for hook in &mut self.delete {
+            hook(table_name, rowid, row);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::row::Value;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_hooks() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut hooks = Hooks::default();
+        assert!(!hooks.watches_updates());
+
+        let seen = calls.clone();
+        hooks.on_insert(Box::new(move |table, rowid, row| {
+            seen.lock()
+                .unwrap()
+                .push(format!("insert {} {} {:?}", table, rowid, row.values()));
+        }));
+        let seen = calls.clone();
+        hooks.on_update(Box::new(move |table, rowid, old, new| {
+            seen.lock().unwrap().push(format!(
+                "update {} {} {:?} {:?}",
+                table,
+                rowid,
+                old.values(),
+                new.values()
+            ));
+        }));
+        assert!(hooks.watches_updates());
+
+        let (one, two) = (
+            Row::new(vec![Value::Integer(1)]),
+            Row::new(vec![Value::Integer(2)]),
+        );
+        hooks.inserted("t", 7, &one);
+        hooks.updated("t", 7, &one, &two);
+        // Nothing watches deletes
+        hooks.deleted("t", 7, &two);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "insert t 7 [Integer(1)]",
+                "update t 7 [Integer(1)] [Integer(2)]"
+            ]
+        );
+
+        hooks.clear();
+        hooks.inserted("t", 8, &one);
+        assert_eq!(calls.lock().unwrap().len(), 2);
+    }
+}
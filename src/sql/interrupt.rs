@@ -0,0 +1,180 @@
+//! Stopping a statement before it finishes. A `CancelToken` is shared with
+//! whatever may want to stop it - another thread, a Ctrl-C handler - and a
+//! statement timeout bounds how long one may run. A running statement checks
+//! both for each row it reads and as it joins, and fails with
+//! `HozonError::Interrupted` once either says stop. Rows are only written
+//! after a statement has read everything it needs, so a stopped write
+//! changes nothing.
+//!
+//! A thread can also have a token of its own, as a server session does, to
+//! stop the statements it runs whichever executor they run on.
+
+use crate::error::{HozonError, Result};
+use std::cell::{Cell, RefCell};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Cancels the statements running when `cancel` is called. Clones share the
+/// token; statements started afterwards run as normal.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    // How many times `cancel` was called; a statement is cancelled once
+    // this moves past what it was when the statement started
+    cancels: Arc<AtomicU64>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop every statement running now at its next check
+    pub fn cancel(&self) {
+        self.cancels.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn cancels(&self) -> u64 {
+        self.cancels.load(Ordering::SeqCst)
+    }
+}
+
+/// What the statement running on a thread checks against
+#[derive(Clone, Copy)]
+struct State {
+    cancels: u64,
+    thread_cancels: u64,
+    deadline: Option<Instant>,
+}
+
+thread_local! {
+    // A statement runs on one thread, subqueries and all, so statements on
+    // other threads through the same executor keep their own deadlines
+    static RUNNING: Cell<Option<State>> = const { Cell::new(None) };
+    static THREAD_TOKEN: RefCell<Option<CancelToken>> = const { RefCell::new(None) };
+}
+
+/// Let `token` cancel the statements this thread runs from now on, as well
+/// as their executor's token; None to stop that
+pub fn set_thread_token(token: Option<CancelToken>) {
+    THREAD_TOKEN.set(token);
+}
+
+fn thread_cancels() -> u64 {
+    THREAD_TOKEN.with_borrow(|token| token.as_ref().map_or(0, CancelToken::cancels))
+}
+
+/// Marks a statement as running on this thread until it's dropped.
+/// Starting one inside another, as a view's query inside a SELECT, keeps the
+/// outer statement's start.
+pub struct Running {
+    outermost: bool,
+}
+
+impl Running {
+    pub fn start(token: &CancelToken, timeout: Option<Duration>) -> Self {
+        let outermost = RUNNING.get().is_none();
+        if outermost {
+            RUNNING.set(Some(State {
+                cancels: token.cancels(),
+                thread_cancels: thread_cancels(),
+                deadline: timeout.map(|timeout| Instant::now() + timeout),
+            }));
+        }
+        Running { outermost }
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        if self.outermost {
+            RUNNING.set(None);
+        }
+    }
+}
+
+/// Fail if the statement running on this thread was cancelled through
+/// `token` or the thread's own, or has run out of time
+pub fn check(token: &CancelToken) -> Result<()> {
+    let Some(running) = RUNNING.get() else {
+        return Ok(());
+    };
+    if token.cancels() != running.cancels || thread_cancels() != running.thread_cancels {
+        return Err(HozonError::Interrupted(
+            "The statement was cancelled".to_string(),
+        ));
+    }
+    if running
+        .deadline
+        .is_some_and(|deadline| Instant::now() >= deadline)
+    {
+        return Err(HozonError::Interrupted(
+            "The statement ran past its timeout".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel() {
+        let token = CancelToken::new();
+        // Nothing is running to cancel
+        token.cancel();
+        assert!(check(&token).is_ok());
+
+        let statement = Running::start(&token, None);
+        assert!(check(&token).is_ok());
+        token.clone().cancel();
+        let err = check(&token).unwrap_err();
+        assert!(matches!(err, HozonError::Interrupted(_)), "{:?}", err);
+
+        // A nested start doesn't undo it, and the next statement runs
+        drop(Running::start(&token, None));
+        assert!(check(&token).is_err());
+        drop(statement);
+        let _statement = Running::start(&token, None);
+        assert!(check(&token).is_ok());
+    }
+
+    #[test]
+    fn test_thread_token() {
+        let token = CancelToken::new();
+        let session = CancelToken::new();
+        set_thread_token(Some(session.clone()));
+        session.cancel();
+        let statement = Running::start(&token, None);
+        assert!(check(&token).is_ok());
+
+        // Cancelling it stops the statement, but only on this thread
+        let elsewhere = std::thread::spawn({
+            let (token, session) = (token.clone(), session.clone());
+            move || {
+                let _statement = Running::start(&token, None);
+                session.cancel();
+                check(&token)
+            }
+        });
+        assert!(elsewhere.join().unwrap().is_ok());
+        assert!(check(&token).is_err());
+        drop(statement);
+        set_thread_token(None);
+        let _statement = Running::start(&token, None);
+        assert!(check(&token).is_ok());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let token = CancelToken::new();
+        let statement = Running::start(&token, Some(Duration::ZERO));
+        let err = check(&token).unwrap_err();
+        assert_eq!(err.to_string(), "The statement ran past its timeout");
+        drop(statement);
+
+        let _statement = Running::start(&token, Some(Duration::from_secs(60)));
+        assert!(check(&token).is_ok());
+    }
+}
@@ -0,0 +1,289 @@
+//! Join operators. Each takes the rows of both sides and returns the pairs
+//! that satisfy the ON condition, left columns first, in left-row order,
+//! checking `interrupt` as it goes.
+//!
+//! `choose` picks the operator: a hash join when ON requires equal columns
+//! from both sides, a nested loop otherwise.
+
+use crate::catalog::row::{Row, Value};
+use crate::error::Result;
+use crate::sql::eval::Bound;
+use crate::sql::interrupt::{self, CancelToken};
+use crate::sql::parser::BinaryOperator;
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq)]
+pub enum JoinMethod {
+    /// Compare every left row with every right row
+    NestedLoop,
+    /// Match rows on equal key columns through a hash table built on the
+    /// smaller side; ON is still checked for each match
+    Hash {
+        left_keys: Vec<usize>,
+        /// Positions in the right row, not the joined one
+        right_keys: Vec<usize>,
+    },
+}
+
+/// How to join rows `left_width` columns wide to rows matched by `on`
+pub fn choose(on: &Bound, left_width: usize) -> JoinMethod {
+    let mut left_keys = Vec::new();
+    let mut right_keys = Vec::new();
+    for conjunct in conjuncts(on) {
+        if let Bound::BinaryOp {
+            left,
+            op: BinaryOperator::Equals,
+            right,
+        } = conjunct
+            && let (Bound::Column(a), Bound::Column(b)) = (left.as_ref(), right.as_ref())
+        {
+            let (l, r) = if a < b { (*a, *b) } else { (*b, *a) };
+            if l < left_width && r >= left_width {
+                left_keys.push(l);
+                right_keys.push(r - left_width);
+            }
+        }
+    }
+
+    if left_keys.is_empty() {
+        JoinMethod::NestedLoop
+    } else {
+        JoinMethod::Hash {
+            left_keys,
+            right_keys,
+        }
+    }
+}
+
+/// Join `left` and `right` with `method`
+pub fn run(
+    method: &JoinMethod,
+    left: &[Row],
+    left_width: usize,
+    right: &[Row],
+    on: &Bound,
+    interrupt: &CancelToken,
+) -> Result<Vec<Row>> {
+    match method {
+        JoinMethod::NestedLoop => nested_loop(left, left_width, right, on, interrupt),
+        JoinMethod::Hash {
+            left_keys,
+            right_keys,
+        } => hash(
+            left, left_width, left_keys, right, right_keys, on, interrupt,
+        ),
+    }
+}
+
+/// Compare every left row with every right row
+pub fn nested_loop(
+    left: &[Row],
+    left_width: usize,
+    right: &[Row],
+    on: &Bound,
+    interrupt: &CancelToken,
+) -> Result<Vec<Row>> {
+    let mut joined = Vec::new();
+    for l in left {
+        interrupt::check(interrupt)?;
+        for r in right {
+            if matches(l, left_width, r, on)? {
+                joined.push(concat(l, r));
+            }
+        }
+    }
+    Ok(joined)
+}
+
+fn hash(
+    left: &[Row],
+    left_width: usize,
+    left_keys: &[usize],
+    right: &[Row],
+    right_keys: &[usize],
+    on: &Bound,
+    interrupt: &CancelToken,
+) -> Result<Vec<Row>> {
+    // Candidate (left, right) index pairs whose keys are equal
+    let mut pairs = Vec::new();
+    if left.len() <= right.len() {
+        let table = build(left, left_keys);
+        for (r, row) in right.iter().enumerate() {
+            interrupt::check(interrupt)?;
+            if let Some(matches) = key(row, right_keys).and_then(|k| table.get(&k)) {
+                pairs.extend(matches.iter().map(|&l| (l, r)));
+            }
+        }
+        // Back to left-row order, as if the left side had probed
+        pairs.sort_unstable();
+    } else {
+        let table = build(right, right_keys);
+        for (l, row) in left.iter().enumerate() {
+            interrupt::check(interrupt)?;
+            if let Some(matches) = key(row, left_keys).and_then(|k| table.get(&k)) {
+                pairs.extend(matches.iter().map(|&r| (l, r)));
+            }
+        }
+    }
+
+    let mut joined = Vec::new();
+    for (l, r) in pairs {
+        interrupt::check(interrupt)?;
+        if matches(&left[l], left_width, &right[r], on)? {
+            joined.push(concat(&left[l], &right[r]));
+        }
+    }
+    Ok(joined)
+}
+
+/// Row indices by key; rows with a NULL key can't match anything
+fn build(rows: &[Row], keys: &[usize]) -> HashMap<Vec<KeyPart>, Vec<usize>> {
+    let mut table: HashMap<_, Vec<usize>> = HashMap::new();
+    for (i, row) in rows.iter().enumerate() {
+        if let Some(key) = key(row, keys) {
+            table.entry(key).or_default().push(i);
+        }
+    }
+    table
+}
+
+/// A key value that hashes equal exactly when `=` would hold: numbers of
+/// any type by value, other values by type and contents
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum KeyPart {
+    Int(i64),
+    /// Floats with no integer equivalent, by bits
+    Float(u64),
+    Boolean(bool),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+/// None if any key is NULL (or NaN), which equals nothing
+fn key(row: &Row, keys: &[usize]) -> Option<Vec<KeyPart>> {
+    keys.iter()
+        .map(|&i| match row.get_value(i)? {
+            Value::Null => None,
+            Value::Integer(n) => Some(KeyPart::Int(*n as i64)),
+            Value::BigInt(n) => Some(KeyPart::Int(*n)),
+            Value::Float(f) if f.is_nan() => None,
+            // 2^63 is the first float past i64::MAX
+            Value::Float(f) if f.fract() == 0.0 && f.abs() < 9_223_372_036_854_775_808.0 => {
+                Some(KeyPart::Int(*f as i64))
+            }
+            Value::Float(f) => Some(KeyPart::Float(f.to_bits())),
+            Value::Boolean(b) => Some(KeyPart::Boolean(*b)),
+            Value::Text(s) => Some(KeyPart::Text(s.clone())),
+            Value::Blob(b) => Some(KeyPart::Blob(b.clone())),
+        })
+        .collect()
+}
+
+/// The AND-ed parts of `expr`
+fn conjuncts<'b, 'a>(expr: &'b Bound<'a>) -> Vec<&'b Bound<'a>> {
+    match expr {
+        Bound::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            let mut parts = conjuncts(left);
+            parts.extend(conjuncts(right));
+            parts
+        }
+        other => vec![other],
+    }
+}
+
+fn matches(left: &Row, left_width: usize, right: &Row, on: &Bound) -> Result<bool> {
+    on.matches(&|i: usize| {
+        let value = match i.checked_sub(left_width) {
+            None => left.get_value(i),
+            Some(i) => right.get_value(i),
+        };
+        value.cloned().unwrap_or(Value::Null)
+    })
+}
+
+fn concat(left: &Row, right: &Row) -> Row {
+    Row::new(
+        left.values()
+            .iter()
+            .chain(right.values())
+            .cloned()
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(values: &[&[Value]]) -> Vec<Row> {
+        values.iter().map(|v| Row::new(v.to_vec())).collect()
+    }
+
+    /// `left.0 = right.0 AND <extra>`, for rows two columns wide on the left
+    fn on(extra: Bound) -> Bound {
+        Bound::BinaryOp {
+            left: Box::new(Bound::BinaryOp {
+                left: Box::new(Bound::Column(2)),
+                op: BinaryOperator::Equals,
+                right: Box::new(Bound::Column(0)),
+            }),
+            op: BinaryOperator::And,
+            right: Box::new(extra),
+        }
+    }
+
+    #[test]
+    fn test_choose() {
+        let on = on(Bound::Literal(Value::Boolean(true)));
+        assert_eq!(
+            choose(&on, 2),
+            JoinMethod::Hash {
+                left_keys: vec![0],
+                right_keys: vec![0],
+            }
+        );
+        // Both columns on the same side isn't a join key
+        assert_eq!(choose(&on, 3), JoinMethod::NestedLoop);
+    }
+
+    #[test]
+    fn test_hash_join_matches_nested_loop() {
+        let left = rows(&[
+            &[Value::Integer(1), Value::Text("a".to_string())],
+            &[Value::Null, Value::Text("b".to_string())],
+            &[Value::BigInt(2), Value::Text("c".to_string())],
+            &[Value::Integer(1), Value::Text("d".to_string())],
+        ]);
+        let right = rows(&[
+            &[Value::Float(1.0)],
+            &[Value::Null],
+            &[Value::Integer(2)],
+            &[Value::Float(2.5)],
+        ]);
+        // ON also excludes one key match, so it has to be rechecked
+        let on = on(Bound::BinaryOp {
+            left: Box::new(Bound::Column(1)),
+            op: BinaryOperator::NotEquals,
+            right: Box::new(Bound::Literal(Value::Text("d".to_string()))),
+        });
+        let method = choose(&on, 2);
+        assert_ne!(method, JoinMethod::NestedLoop);
+
+        let interrupt = CancelToken::new();
+        let expected = nested_loop(&left, 2, &right, &on, &interrupt).unwrap();
+        assert_eq!(expected.len(), 2);
+        // Built on either side
+        assert_eq!(
+            run(&method, &left, 2, &right, &on, &interrupt).unwrap(),
+            expected
+        );
+        assert_eq!(
+            run(&method, &left, 2, &right[..2], &on, &interrupt).unwrap(),
+            expected[..1]
+        );
+    }
+}
@@ -1,3 +1,16 @@
+#[cfg(feature = "async")]
+pub mod async_executor;
+pub mod attach;
+pub mod eval;
 pub mod executor;
+pub mod formatter;
+pub mod functions;
+pub mod hooks;
+pub mod interrupt;
+pub mod join;
+pub mod optimizer;
 pub mod parser;
+pub mod plan;
+pub mod prepared;
+pub mod select;
 pub mod tokenizer;
@@ -0,0 +1,442 @@
+//! Choosing how a SELECT runs. A `LogicalPlan` is what the query
+//! computes: its tables in join order, with each join's ON and the WHERE
+//! split into conjuncts bound to the joined row. Rewrite rules then move
+//! work earlier - predicate pushdown filters each table's rows as they're
+//! read, before joins multiply them, and projection pruning finds the
+//! columns nothing reads, which needn't be decoded - and `physical` picks
+//! how to read each table, a scan or an index search, by what its
+//! statistics say each would cost, and how to run each join.
+
+use crate::catalog::row::Value;
+use crate::catalog::table::TableCatalog;
+use crate::sql::eval::Bound;
+use crate::sql::join;
+use crate::sql::parser::{BinaryOperator, Expr, OrderBy};
+use crate::sql::plan::{Access, JoinStep, Predicate, QueryPlan, Scan, Source};
+use crate::sql::select::SelectPlan;
+use std::ops;
+
+/// Index entries read, beyond the rows they find, to search an index
+const INDEX_SEARCH_COST: f64 = 1.0;
+/// What reading a row an index found costs next to a scan reading it: its
+/// entry, then the row
+const INDEX_ROW_COST: f64 = 2.0;
+/// The share of rows `col = value` is guessed to keep on a column whose
+/// values aren't unique, when ANALYZE hasn't counted them
+const EQUALITY_SELECTIVITY: f64 = 0.1;
+/// The share of rows a range on a column is guessed to keep, when ANALYZE
+/// hasn't found its numeric MIN and MAX
+const RANGE_SELECTIVITY: f64 = 0.3;
+
+/// One table of a query
+pub struct LogicalTable<'a> {
+    pub scan: Scan,
+    /// Where its columns start in the joined row, and how many it has
+    pub offset: usize,
+    pub width: usize,
+    /// The ON joining it to the tables before, in conjuncts bound to the
+    /// joined row; empty for the first table
+    pub on: Vec<Predicate<'a>>,
+    /// Conjuncts on its columns alone, bound to its rows
+    pub filter: Vec<Predicate<'a>>,
+    /// Which of its columns the query reads, if that's known
+    pub read: Option<Vec<bool>>,
+}
+
+impl<'a> LogicalTable<'a> {
+    pub fn new(scan: Scan, offset: usize, width: usize, on: Vec<Predicate<'a>>) -> Self {
+        LogicalTable {
+            scan,
+            offset,
+            width,
+            on,
+            filter: Vec::new(),
+            read: None,
+        }
+    }
+}
+
+/// What a SELECT computes, before deciding how
+pub struct LogicalPlan<'a> {
+    pub tables: Vec<LogicalTable<'a>>,
+    /// WHERE, in conjuncts bound to the joined row
+    pub filter: Vec<Predicate<'a>>,
+}
+
+impl<'a> LogicalPlan<'a> {
+    /// Apply every rewrite rule; `reads` are the columns of the joined row
+    /// the result and sort keys read, if that's known
+    pub fn optimize(&mut self, reads: Option<Vec<usize>>) {
+        self.push_down_predicates();
+        self.prune_columns(reads);
+    }
+
+    /// Move each conjunct of WHERE or an ON that reads one table's columns
+    /// alone into that table's filter. Joins are inner, so a row one fails
+    /// could never be part of the result.
+    pub fn push_down_predicates(&mut self) {
+        let filter = std::mem::take(&mut self.filter);
+        self.filter = self.push_down(filter);
+        for i in 0..self.tables.len() {
+            let on = std::mem::take(&mut self.tables[i].on);
+            self.tables[i].on = self.push_down(on);
+        }
+    }
+
+    /// The `predicates` left after pushing down those that can be
+    fn push_down(&mut self, predicates: Vec<Predicate<'a>>) -> Vec<Predicate<'a>> {
+        let mut kept = Vec::new();
+        for predicate in predicates {
+            match self.owner(&predicate.bound) {
+                Some(i) => {
+                    let table = &mut self.tables[i];
+                    table.filter.push(Predicate {
+                        bound: predicate.bound.shifted(table.offset),
+                        expr: predicate.expr,
+                    });
+                }
+                None => kept.push(predicate),
+            }
+        }
+        kept
+    }
+
+    /// The table whose columns are the only ones `bound` reads
+    fn owner(&self, bound: &Bound) -> Option<usize> {
+        let columns = bound.columns()?;
+        let first = *columns.first()?;
+        let i = self
+            .tables
+            .iter()
+            .position(|t| (t.offset..t.offset + t.width).contains(&first))?;
+        let table = &self.tables[i];
+        columns
+            .iter()
+            .all(|c| (table.offset..table.offset + table.width).contains(c))
+            .then_some(i)
+    }
+
+    /// Mark the columns of each table that the result, sort keys or a
+    /// condition read. If any of them has a correlated subquery, which
+    /// could read anything, every column is.
+    pub fn prune_columns(&mut self, reads: Option<Vec<usize>>) {
+        let mut read = vec![false; self.tables.iter().map(|t| t.width).sum()];
+        let mut mark = |columns: Option<Vec<usize>>, offset: usize| match columns {
+            Some(columns) => {
+                for column in columns {
+                    read[offset + column] = true;
+                }
+                true
+            }
+            None => false,
+        };
+        let mut known = mark(reads, 0);
+        for predicate in &self.filter {
+            known &= mark(predicate.bound.columns(), 0);
+        }
+        for table in &self.tables {
+            for predicate in &table.on {
+                known &= mark(predicate.bound.columns(), 0);
+            }
+            for predicate in &table.filter {
+                known &= mark(predicate.bound.columns(), table.offset);
+            }
+        }
+        for table in &mut self.tables {
+            table.read = known.then(|| read[table.offset..table.offset + table.width].to_vec());
+        }
+    }
+
+    /// The plan that runs this: how to read each table and join it
+    pub fn physical(
+        self,
+        catalog: &TableCatalog,
+        select: SelectPlan<'a>,
+        order_by: Vec<OrderBy>,
+    ) -> QueryPlan<'a> {
+        let mut tables = self.tables.into_iter();
+        let (mut source, _) = source(catalog, tables.next().expect("a query has a table"));
+        let joins: Vec<JoinStep> = tables
+            .map(|table| {
+                let left_width = table.offset;
+                let (source, on) = self::source(catalog, table);
+                // Every conjunct may have been pushed down, leaving a cross join
+                let on = Predicate::all(on).unwrap_or_else(|| Predicate {
+                    bound: Bound::Literal(Value::Boolean(true)),
+                    expr: Expr::Literal(Value::Boolean(true)),
+                });
+                JoinStep {
+                    source,
+                    method: join::choose(&on.bound, left_width),
+                    on: on.bound,
+                    left_width,
+                    condition: on.expr,
+                }
+            })
+            .collect();
+
+        // Counting every row of one table needs none of them read
+        if select.is_count()
+            && joins.is_empty()
+            && self.filter.is_empty()
+            && source.filter.is_none()
+            && source.scan.access == Access::Table
+        {
+            source.scan.access = Access::RowCount;
+        }
+
+        QueryPlan {
+            source,
+            joins,
+            select,
+            filter: Predicate::all(self.filter),
+            order_by,
+        }
+    }
+}
+
+/// How to read `table`, and its ON
+fn source<'a>(
+    catalog: &TableCatalog,
+    mut table: LogicalTable<'a>,
+) -> (Source<'a>, Vec<Predicate<'a>>) {
+    if table.scan.access == Access::Table
+        && let Some(access) = choose_index(catalog, &table)
+    {
+        table.scan.access = access;
+    }
+    let source = Source {
+        scan: table.scan,
+        width: table.width,
+        filter: Predicate::all(table.filter),
+        read: table.read,
+    };
+    (source, table.on)
+}
+
+/// `expr`'s conjuncts: the operands of its top-level ANDs, in order
+pub fn conjuncts(expr: Expr) -> Vec<Expr> {
+    match expr {
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            let mut parts = conjuncts(*left);
+            parts.extend(conjuncts(*right));
+            parts
+        }
+        other => vec![other],
+    }
+}
+
+/// The index search on a stored table that costs less than scanning it,
+/// if there is one. An index serves a filter conjunct comparing its first
+/// column with a value; the whole filter is still checked on what it finds.
+fn choose_index(catalog: &TableCatalog, table: &LogicalTable) -> Option<Access> {
+    let meta = catalog.get_table(&table.scan.table)?;
+    let rows = meta.row_count() as f64;
+    let case = catalog.identifier_case();
+    let stats = catalog.table_stats(&table.scan.table);
+    let mut best = None;
+    let mut best_cost = rows;
+
+    for index in catalog.table_indexes(&table.scan.table) {
+        let column = &index.columns()[0];
+        let Some(position) = meta
+            .schema()
+            .columns()
+            .iter()
+            .position(|c| case.matches(c.name(), column))
+        else {
+            continue;
+        };
+        let Some((lower, upper)) = key_range(&table.filter, position) else {
+            continue;
+        };
+
+        let equality = matches!((&lower, &upper),
+            (ops::Bound::Included(a), ops::Bound::Included(b)) if a == b);
+        let found = match (equality, index.is_unique() && index.columns().len() == 1) {
+            (true, true) => rows.min(1.0),
+            (true, false) => {
+                let selectivity = stats.and_then(|s| s.equality_selectivity(position));
+                rows * selectivity.unwrap_or(EQUALITY_SELECTIVITY)
+            }
+            (false, _) => {
+                let selectivity = stats
+                    .and_then(|s| s.range_selectivity(position, lower.as_ref(), upper.as_ref()));
+                rows * selectivity.unwrap_or(RANGE_SELECTIVITY)
+            }
+        };
+        let cost = INDEX_SEARCH_COST + found * INDEX_ROW_COST;
+        if cost < best_cost {
+            best_cost = cost;
+            best = Some(Access::Index {
+                name: index.name().to_string(),
+                column: column.clone(),
+                lower,
+                upper,
+            });
+        }
+    }
+    best
+}
+
+/// The bounds the conjuncts of `filter` comparing column `position` with a
+/// value put on it, if any do. An equality wins over ranges.
+fn key_range(
+    filter: &[Predicate],
+    position: usize,
+) -> Option<(ops::Bound<Value>, ops::Bound<Value>)> {
+    let mut lower = ops::Bound::Unbounded;
+    let mut upper = ops::Bound::Unbounded;
+    for predicate in filter {
+        let Bound::BinaryOp { left, op, right } = &predicate.bound else {
+            continue;
+        };
+        // `value op column` is `column op' value`, with op' the reverse
+        let (op, value) = match (left.as_ref(), right.as_ref()) {
+            (Bound::Column(c), Bound::Literal(value)) if *c == position => (op.clone(), value),
+            (Bound::Literal(value), Bound::Column(c)) if *c == position => (reversed(op), value),
+            _ => continue,
+        };
+        if *value == Value::Null {
+            continue;
+        }
+        let value = value.clone();
+        match op {
+            BinaryOperator::Equals => {
+                return Some((
+                    ops::Bound::Included(value.clone()),
+                    ops::Bound::Included(value),
+                ));
+            }
+            BinaryOperator::GreaterThan => lower = ops::Bound::Excluded(value),
+            BinaryOperator::GreaterOrEqual => lower = ops::Bound::Included(value),
+            BinaryOperator::LessThan => upper = ops::Bound::Excluded(value),
+            BinaryOperator::LessOrEqual => upper = ops::Bound::Included(value),
+            _ => {}
+        }
+    }
+    match (&lower, &upper) {
+        (ops::Bound::Unbounded, ops::Bound::Unbounded) => None,
+        _ => Some((lower, upper)),
+    }
+}
+
+/// The comparison that holds with its operands swapped
+fn reversed(op: &BinaryOperator) -> BinaryOperator {
+    match op {
+        BinaryOperator::LessThan => BinaryOperator::GreaterThan,
+        BinaryOperator::GreaterThan => BinaryOperator::LessThan,
+        BinaryOperator::LessOrEqual => BinaryOperator::GreaterOrEqual,
+        BinaryOperator::GreaterOrEqual => BinaryOperator::LessOrEqual,
+        op => op.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn predicate(bound: Bound<'static>) -> Predicate<'static> {
+        Predicate {
+            bound,
+            expr: Expr::Literal(Value::Boolean(true)),
+        }
+    }
+
+    fn equals(a: Bound<'static>, b: Bound<'static>) -> Bound<'static> {
+        Bound::BinaryOp {
+            left: Box::new(a),
+            op: BinaryOperator::Equals,
+            right: Box::new(b),
+        }
+    }
+
+    fn table(name: &str, offset: usize, width: usize) -> LogicalTable<'static> {
+        let scan = Scan {
+            table: name.to_string(),
+            access: Access::Table,
+        };
+        LogicalTable::new(scan, offset, width, Vec::new())
+    }
+
+    #[test]
+    fn test_push_down_and_prune() {
+        // t (columns 0-2) JOIN u (columns 3-4) ON t.0 = u.3 AND u.4 = 1
+        // WHERE t.1 = 2 AND t.0 = u.4
+        let one = || Bound::Literal(Value::Integer(1));
+        let mut u = table("u", 3, 2);
+        u.on = vec![
+            predicate(equals(Bound::Column(0), Bound::Column(3))),
+            predicate(equals(Bound::Column(4), one())),
+        ];
+        let mut plan = LogicalPlan {
+            tables: vec![table("t", 0, 3), u],
+            filter: vec![
+                predicate(equals(Bound::Column(1), Bound::Literal(Value::Integer(2)))),
+                predicate(equals(Bound::Column(0), Bound::Column(4))),
+            ],
+        };
+        plan.optimize(Some(vec![2]));
+
+        // Conditions on one table move to it, bound to its own columns
+        assert_eq!(plan.filter.len(), 1);
+        assert_eq!(plan.tables[0].filter.len(), 1);
+        assert_eq!(plan.tables[1].on.len(), 1);
+        match &plan.tables[1].filter[..] {
+            [p] => assert_eq!(p.bound.columns(), Some(vec![1])),
+            other => panic!("Expected one pushed-down condition, got {:?}", other),
+        }
+        // t.0, t.1 (filter), t.2 (result), u.3 (ON), u.4 (WHERE) are read
+        assert_eq!(plan.tables[0].read, Some(vec![true, true, true]));
+        assert_eq!(plan.tables[1].read, Some(vec![true, true]));
+
+        plan.prune_columns(Some(vec![]));
+        assert_eq!(plan.tables[0].read, Some(vec![true, true, false]));
+        // Unknown reads keep every column
+        plan.prune_columns(None);
+        assert_eq!(plan.tables[0].read, None);
+    }
+
+    #[test]
+    fn test_key_range() {
+        let compare = |a, op, b| {
+            predicate(Bound::BinaryOp {
+                left: Box::new(a),
+                op,
+                right: Box::new(b),
+            })
+        };
+        let literal = |n| Bound::Literal(Value::Integer(n));
+        let filter = vec![
+            compare(Bound::Column(0), BinaryOperator::GreaterThan, literal(1)),
+            compare(literal(9), BinaryOperator::GreaterOrEqual, Bound::Column(0)),
+            compare(Bound::Column(1), BinaryOperator::Equals, literal(5)),
+            compare(
+                Bound::Column(2),
+                BinaryOperator::Equals,
+                Bound::Literal(Value::Null),
+            ),
+        ];
+        assert_eq!(
+            key_range(&filter, 0),
+            Some((
+                ops::Bound::Excluded(Value::Integer(1)),
+                ops::Bound::Included(Value::Integer(9))
+            ))
+        );
+        assert_eq!(
+            key_range(&filter, 1),
+            Some((
+                ops::Bound::Included(Value::Integer(5)),
+                ops::Bound::Included(Value::Integer(5))
+            ))
+        );
+        // `= NULL` matches nothing, and nothing narrows column 3
+        assert_eq!(key_range(&filter, 2), None);
+        assert_eq!(key_range(&filter, 3), None);
+    }
+}
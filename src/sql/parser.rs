@@ -1,39 +1,229 @@
 use crate::catalog::row::Value;
 use crate::catalog::schema::{Column, DataType};
-use crate::sql::tokenizer::Token;
-use std::io::{self, Error, ErrorKind};
+use crate::error::{HozonError, Result};
+use crate::sql::formatter;
+use crate::sql::tokenizer::{self, Token};
+use std::cell::Cell;
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum Statement {
     CreateTable {
         name: String,
         columns: Vec<Column>,
     },
+    /// INSERT INTO t [(col, ...)] VALUES (...); - omitted columns get NULL,
+    /// or the next sequence value for an AUTOINCREMENT column
     Insert {
         table_name: String,
-        values: Vec<Value>,
+        columns: Option<Vec<String>>,
+        /// One list per row, each value a literal, or a parameter until
+        /// the statement is bound
+        values: Vec<Vec<Expr>>,
+        /// INSERT OR ... / ON CONFLICT; without it a taken key is an error
+        on_conflict: Option<OnConflict>,
+        returning: Option<SelectColumns>,
+    },
+    /// UPDATE t SET col = expr, ... [WHERE ...] [RETURNING ...]; - the
+    /// values are computed from each row as it was
+    Update {
+        table_name: String,
+        assignments: Vec<Assignment>,
+        where_clause: Option<Expr>,
+        returning: Option<SelectColumns>,
+    },
+    /// DELETE FROM t [WHERE ...] [RETURNING ...];
+    Delete {
+        table_name: String,
+        where_clause: Option<Expr>,
+        returning: Option<SelectColumns>,
     },
     Select {
         table_name: String,
         columns: SelectColumns,
         where_clause: Option<Expr>,
+        order_by: Vec<OrderBy>,
+        /// SELECT DISTINCT: drop repeated result rows
+        distinct: bool,
+        /// Tables joined to `table_name`, in order
+        joins: Vec<Join>,
+    },
+    /// CREATE [UNIQUE] INDEX name ON t (col, ...);
+    CreateIndex {
+        name: String,
+        table_name: String,
+        columns: Vec<String>,
+        unique: bool,
+    },
+    /// DROP INDEX name;
+    DropIndex {
+        name: String,
+    },
+    /// CREATE VIEW name AS SELECT ...; - `query` is the SELECT's canonical
+    /// SQL text (see `formatter`), which is what the catalog stores
+    CreateView {
+        name: String,
+        query: String,
+    },
+    /// PRAGMA name [= value]; - read or change a connection setting
+    Pragma {
+        name: String,
+        value: Option<String>,
+    },
+    /// CHECKPOINT; - flush pending writes into the database file
+    Checkpoint,
+    /// ANALYZE [table]; - gather statistics about one table's rows, or
+    /// every table's
+    Analyze {
+        table_name: Option<String>,
+    },
+    /// EXPLAIN statement; - how the statement would run, without running it
+    Explain(Box<Statement>),
+    /// SAVEPOINT name; - mark a point later changes can be undone back to
+    Savepoint {
+        name: String,
+    },
+    /// RELEASE [SAVEPOINT] name; - keep the changes since the savepoint and
+    /// close it, with any opened after it
+    Release {
+        name: String,
+    },
+    /// ROLLBACK TO [SAVEPOINT] name; - undo the changes since the
+    /// savepoint, which stays open
+    RollbackTo {
+        name: String,
+    },
+    /// ATTACH [DATABASE] 'file' AS alias; - open another database
+    /// alongside, its tables named `alias.table`
+    Attach {
+        path: String,
+        alias: String,
+    },
+    /// DETACH [DATABASE] alias;
+    Detach {
+        alias: String,
     },
 }
 
-#[derive(Debug, PartialEq)]
+/// The columns of a SELECT, or of the rows a RETURNING clause reports
+#[derive(Debug, Clone, PartialEq)]
 pub enum SelectColumns {
     All,
-    Specific(Vec<String>),
+    /// The result's columns in order; `rowid` is each stored row's id
+    Specific(Vec<SelectItem>),
+}
+
+/// One entry of a column list
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectItem {
+    /// `*`, or `table.*` for one table's columns
+    Wildcard(Option<String>),
+    /// An expression, and the name of its result column: the SQL it was
+    /// written as, or for a `table.column` the column alone
+    Expr { expr: Expr, name: String },
+}
+
+impl SelectItem {
+    /// The column `name`, `column` or `table.column`
+    pub fn column(name: &str) -> Self {
+        let column = name.split_once('.').map_or(name, |(_, column)| column);
+        SelectItem::Expr {
+            expr: Expr::Column(name.to_string()),
+            name: column.to_string(),
+        }
+    }
+}
+
+/// `[INNER] JOIN table_name ON on`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    pub table_name: String,
+    pub on: Expr,
+}
+
+/// What an INSERT does when the new row's primary key is already taken
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnConflict {
+    /// The key columns of `ON CONFLICT (col, ...)`; empty for any key
+    pub target: Vec<String>,
+    pub action: ConflictAction,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConflictAction {
+    /// INSERT OR IGNORE, ON CONFLICT DO NOTHING: keep the existing row
+    Nothing,
+    /// INSERT OR REPLACE: the new row takes the existing one's place
+    Replace,
+    /// ON CONFLICT DO UPDATE SET ...: change the existing row instead. The
+    /// values see its columns, and the new row's as `excluded.column`.
+    Update(Vec<Assignment>),
+}
+
+/// `column = value` in a SET list
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub column: String,
+    pub value: Expr,
+}
+
+/// One ORDER BY key
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderBy {
+    /// An expression over the table's columns, or an integer literal naming
+    /// a result column by position (from 1)
+    pub expr: Expr,
+    pub descending: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Literal(Value),
+    /// The value bound to a prepared statement's parameter, numbered from 1
+    Parameter(usize),
+    /// `column` or `table.column`
     Column(String),
+    Not(Box<Expr>),
+    /// `-expr`; a negative number literal is a `Literal` instead
+    Negate(Box<Expr>),
+    /// `expr IS [NOT] NULL`, which unlike `= NULL` is never unknown
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    /// `expr [NOT] IN (value, ...)`
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    /// `expr [NOT] IN (SELECT ...)`, the SELECT returning one column
+    InSubquery {
+        expr: Box<Expr>,
+        query: Box<Statement>,
+        negated: bool,
+    },
     BinaryOp {
         left: Box<Expr>,
         op: BinaryOperator,
         right: Box<Expr>,
     },
+    /// `name(arg, ...)`, a built-in function
+    Function {
+        name: String,
+        args: Vec<Expr>,
+    },
+    /// `COUNT(*)`: how many rows the query finds. It can only be the whole
+    /// column list, as there are no other aggregates to go with it yet.
+    CountAll,
+    /// `(SELECT ...)` as a value: that of its one row and column
+    Subquery(Box<Statement>),
+    /// `EXISTS (SELECT ...)`
+    Exists(Box<Statement>),
+    /// `CAST(expr AS data_type)`
+    Cast {
+        expr: Box<Expr>,
+        data_type: DataType,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -48,11 +238,47 @@ pub enum BinaryOperator {
     // logical
     And,
     Or,
+    // arithmetic
+    Subtract,
+}
+
+impl BinaryOperator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinaryOperator::Equals => "=",
+            BinaryOperator::NotEquals => "!=",
+            BinaryOperator::LessThan => "<",
+            BinaryOperator::GreaterThan => ">",
+            BinaryOperator::LessOrEqual => "<=",
+            BinaryOperator::GreaterOrEqual => ">=",
+            BinaryOperator::And => "AND",
+            BinaryOperator::Or => "OR",
+            BinaryOperator::Subtract => "-",
+        }
+    }
+
+    /// How tightly the operator binds; NOT sits between AND (2) and the
+    /// comparisons (4)
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperator::Or => 1,
+            BinaryOperator::And => 2,
+            BinaryOperator::Subtract => 5,
+            _ => 4,
+        }
+    }
 }
 
 pub struct Parser {
     tokens: Vec<Token>,
     position: usize,
+    /// The highest parameter number seen so far
+    parameters: usize,
+    /// The furthest token looked at, which is where an error is
+    furthest: Cell<usize>,
+    /// The SQL the tokens came from and where each starts in it, to locate
+    /// errors with
+    source: Option<(String, Vec<usize>)>,
 }
 
 impl Parser {
@@ -60,10 +286,29 @@ impl Parser {
         Parser {
             tokens,
             position: 0,
+            parameters: 0,
+            furthest: Cell::new(0),
+            source: None,
         }
     }
 
+    /// A parser over `sql` whose errors say where in it they are
+    pub fn from_sql(sql: &str) -> Result<Self> {
+        let (tokens, offsets) = tokenizer::tokenize_with_offsets(sql)?;
+        Ok(Parser {
+            source: Some((sql.to_string(), offsets)),
+            ..Parser::new(tokens)
+        })
+    }
+
+    /// How many values the statements parsed so far need bound: the
+    /// highest parameter number in them
+    pub fn parameter_count(&self) -> usize {
+        self.parameters
+    }
+
     pub fn peek(&self) -> Option<&Token> {
+        self.furthest.set(self.furthest.get().max(self.position));
         self.tokens.get(self.position)
     }
 
@@ -77,57 +322,296 @@ impl Parser {
         Some(token)
     }
 
-    pub fn expect(&mut self, expected: Token) -> io::Result<()> {
-        let cur_token = self.consume().ok_or_else(|| {
-            return Error::new(ErrorKind::UnexpectedEof, "Unexpected end of input");
-        })?;
+    /// The next token as a name: an identifier, or a keyword that isn't
+    /// reserved, spelled as it was written
+    fn consume_name(&mut self) -> Option<String> {
+        let token = self.consume()?;
+        self.as_name(token, self.position - 1)
+    }
+
+    /// `token`, at `index`, as a name, if it can be one
+    fn as_name(&self, token: Token, index: usize) -> Option<String> {
+        match token {
+            Token::Identifier(name) => Some(name),
+            token if token.is_non_reserved() => {
+                let keyword = token.to_string();
+                Some(match &self.source {
+                    Some((sql, offsets)) => {
+                        let start = offsets[index];
+                        sql[start..start + keyword.len()].to_string()
+                    }
+                    None => keyword.to_lowercase(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn expect(&mut self, expected: Token) -> Result<()> {
+        let cur_token = self
+            .consume()
+            .ok_or_else(|| HozonError::Parse("Unexpected end of input".to_string()))?;
 
         if cur_token != expected {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                format!("Expected {:?}, found {:?}", expected, cur_token),
-            ));
+            return Err(HozonError::Parse(format!(
+                "Expected {:?}, found {:?}",
+                expected, cur_token
+            )));
         }
 
         Ok(())
     }
 
-    pub fn parse(&mut self) -> io::Result<Statement> {
+    pub fn parse(&mut self) -> Result<Statement> {
+        self.parse_statement().map_err(|e| self.locate(e))
+    }
+
+    /// `error` located at the furthest token looked at, if the SQL is known
+    fn locate(&self, error: HozonError) -> HozonError {
+        match &self.source {
+            Some((sql, offsets)) => {
+                let token = self.furthest.get().min(offsets.len() - 1);
+                tokenizer::located(error, sql, offsets[token])
+            }
+            None => error,
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement> {
         if let Some(token) = self.peek() {
             match token {
-                Token::Create => self.parse_create_table(),
+                Token::Create => match self.tokens.get(self.position + 1) {
+                    Some(Token::View) => self.parse_create_view(),
+                    Some(Token::Index | Token::Unique) => self.parse_create_index(),
+                    _ => self.parse_create_table(),
+                },
+                Token::Drop => {
+                    self.advance();
+                    self.expect(Token::Index)?;
+                    let name = self.get_name("index")?;
+                    self.expect(Token::Semicolon)?;
+                    Ok(Statement::DropIndex { name })
+                }
                 Token::Insert => self.parse_insert(),
+                Token::Update => self.parse_update(),
+                Token::Delete => self.parse_delete(),
                 Token::Select => self.parse_select(),
-                _ => Err(Error::new(
-                    ErrorKind::InvalidData,
-                    format!("Unexpected token: {:?}", token),
-                )),
+                Token::Pragma => self.parse_pragma(),
+                Token::Checkpoint => {
+                    self.advance();
+                    self.expect(Token::Semicolon)?;
+                    Ok(Statement::Checkpoint)
+                }
+                Token::Analyze => {
+                    self.advance();
+                    let table_name = match self.peek() {
+                        Some(Token::Semicolon) => None,
+                        _ => Some(self.get_name("table")?),
+                    };
+                    self.expect(Token::Semicolon)?;
+                    Ok(Statement::Analyze { table_name })
+                }
+                Token::Savepoint => {
+                    self.advance();
+                    let name = self.parse_savepoint_name()?;
+                    Ok(Statement::Savepoint { name })
+                }
+                Token::Release => {
+                    self.advance();
+                    let name = self.parse_savepoint_name()?;
+                    Ok(Statement::Release { name })
+                }
+                Token::Rollback => {
+                    self.advance();
+                    self.expect(Token::To)?;
+                    let name = self.parse_savepoint_name()?;
+                    Ok(Statement::RollbackTo { name })
+                }
+                Token::Attach => {
+                    self.advance();
+                    self.skip_database();
+                    let path = match self.consume() {
+                        Some(Token::StringLiteral(path)) => path,
+                        _ => {
+                            return Err(HozonError::Parse(
+                                "Expected the database file as a string".to_string(),
+                            ));
+                        }
+                    };
+                    self.expect(Token::As)?;
+                    let alias = self.get_name("database")?;
+                    self.expect(Token::Semicolon)?;
+                    Ok(Statement::Attach { path, alias })
+                }
+                Token::Detach => {
+                    self.advance();
+                    self.skip_database();
+                    let alias = self.get_name("database")?;
+                    self.expect(Token::Semicolon)?;
+                    Ok(Statement::Detach { alias })
+                }
+                Token::Explain => {
+                    self.advance();
+                    if self.peek() == Some(&Token::Explain) {
+                        return Err(HozonError::Parse(
+                            "EXPLAIN can't explain EXPLAIN".to_string(),
+                        ));
+                    }
+                    Ok(Statement::Explain(Box::new(self.parse_statement()?)))
+                }
+                _ => Err(HozonError::Parse(format!("Unexpected token: {:?}", token))),
             }
         } else {
-            Err(Error::new(
-                ErrorKind::UnexpectedEof,
-                "Unexpected end of input",
-            ))
+            Err(HozonError::Parse("Unexpected end of input".to_string()))
         }
     }
 
-    fn get_table_name(&mut self) -> io::Result<String> {
-        let token = self
-            .consume()
-            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Unexpected end of input"))?;
-        let table_name = if let Token::Identifier(name) = token {
-            name
-        } else {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "Expected table name".to_string(),
+    /// Every statement in the input, in order, skipping empty ones (a
+    /// stray `;`)
+    pub fn parse_all(&mut self) -> Result<Vec<Statement>> {
+        let statements = self.parse_all_with_lines()?;
+        Ok(statements
+            .into_iter()
+            .map(|(_, statement)| statement)
+            .collect())
+    }
+
+    /// `parse_all`, with the line each statement starts on; 1 for each if
+    /// the SQL isn't known
+    pub fn parse_all_with_lines(&mut self) -> Result<Vec<(usize, Statement)>> {
+        let mut statements = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Token::Semicolon) => self.advance(),
+                None | Some(Token::Eof) => return Ok(statements),
+                Some(_) => {
+                    let line = self.line();
+                    statements.push((line, self.parse()?));
+                }
+            }
+        }
+    }
+
+    /// The line the next token is on
+    fn line(&self) -> usize {
+        match &self.source {
+            Some((sql, offsets)) => {
+                let offset = offsets[self.position.min(offsets.len() - 1)];
+                sql[..offset].matches('\n').count() + 1
+            }
+            None => 1,
+        }
+    }
+
+    /// The optional DATABASE after ATTACH or DETACH
+    fn skip_database(&mut self) {
+        if self.peek() == Some(&Token::Database) {
+            self.advance();
+        }
+    }
+
+    /// `[SAVEPOINT] name;` after SAVEPOINT, RELEASE or ROLLBACK TO
+    fn parse_savepoint_name(&mut self) -> Result<String> {
+        if self.peek() == Some(&Token::Savepoint) {
+            self.advance();
+        }
+        let name = self.get_name("savepoint")?;
+        self.expect(Token::Semicolon)?;
+        Ok(name)
+    }
+
+    /// `table`, or `database.table` for a table of an attached database
+    fn get_table_name(&mut self) -> Result<String> {
+        if self.peek().is_none() {
+            return Err(HozonError::Parse("Unexpected end of input".to_string()));
+        }
+        let table_name = self
+            .consume_name()
+            .ok_or_else(|| HozonError::Parse("Expected table name".to_string()))?;
+        if self.peek() != Some(&Token::Dot) {
+            return Ok(table_name);
+        }
+        self.advance();
+        match self.consume_name() {
+            Some(name) => Ok(format!("{}.{}", table_name, name)),
+            _ => Err(HozonError::Parse(format!(
+                "Expected a table name after '{}.'",
+                table_name
+            ))),
+        }
+    }
+
+    /// An index or savepoint name, after which `what` names it
+    fn get_name(&mut self, what: &str) -> Result<String> {
+        match self.consume_name() {
+            Some(name) => Ok(name),
+            None => Err(HozonError::Parse(format!("Expected {} name", what))),
+        }
+    }
+
+    /// `(col, ...)`
+    fn parse_column_list(&mut self) -> Result<Vec<String>> {
+        self.expect(Token::LeftParen)?;
+        let mut columns = Vec::new();
+        loop {
+            match self.consume_name() {
+                Some(name) => columns.push(name),
+                None => return Err(HozonError::Parse("Expected column name".to_string())),
+            }
+            match self.consume() {
+                Some(Token::Comma) => continue,
+                Some(Token::RightParen) => return Ok(columns),
+                _ => {
+                    return Err(HozonError::Parse(
+                        "Expected ',' or ')' after column name".to_string(),
+                    ));
+                }
+            }
+        }
+    }
+
+    fn parse_create_index(&mut self) -> Result<Statement> {
+        self.expect(Token::Create)?;
+        let unique = self.peek() == Some(&Token::Unique);
+        if unique {
+            self.advance();
+        }
+        self.expect(Token::Index)?;
+        let name = self.get_name("index")?;
+        self.expect(Token::On)?;
+        let table_name = self.get_table_name()?;
+        let columns = self.parse_column_list()?;
+        self.expect(Token::Semicolon)?;
+
+        Ok(Statement::CreateIndex {
+            name,
+            table_name,
+            columns,
+            unique,
+        })
+    }
+
+    fn parse_create_view(&mut self) -> Result<Statement> {
+        self.expect(Token::Create)?;
+        self.expect(Token::View)?;
+        let name = self.get_table_name()?;
+        self.expect(Token::As)?;
+
+        // Keep the query as canonical text
+        let start = self.position;
+        let query = self.parse_select()?;
+        let tokens = &self.tokens[start..self.position];
+        if tokens.iter().any(|t| matches!(t, Token::Parameter(_))) {
+            return Err(HozonError::Parse(
+                "A view can't have parameters".to_string(),
             ));
-        };
+        }
+        let query = formatter::statement(&query);
 
-        Ok(table_name)
+        Ok(Statement::CreateView { name, query })
     }
 
-    fn parse_create_table(&mut self) -> io::Result<Statement> {
+    fn parse_create_table(&mut self) -> Result<Statement> {
         self.expect(Token::Create)?;
         self.expect(Token::Table)?;
 
@@ -140,36 +624,39 @@ impl Parser {
         let mut columns = Vec::new();
         loop {
             // column name
-            let token = self
-                .consume()
-                .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Unexpected end of input"))?;
-            let col_name = if let Token::Identifier(name) = token {
-                name
-            } else {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    "Expected column name".to_string(),
-                ));
-            };
+            if self.peek().is_none() {
+                return Err(HozonError::Parse("Unexpected end of input".to_string()));
+            }
+            let col_name = self
+                .consume_name()
+                .ok_or_else(|| HozonError::Parse("Expected column name".to_string()))?;
 
-            // column data type
-            let token = self
-                .consume()
-                .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Unexpected end of input"))?;
-            let data_type = match token {
-                Token::Integer => DataType::Integer,
-                Token::Text => DataType::Text,
-                Token::Boolean => DataType::Boolean,
-                Token::Null => DataType::Null,
-                _ => {
-                    return Err(Error::new(
-                        ErrorKind::InvalidData,
-                        "Expected data type".to_string(),
-                    ));
+            let data_type = self.parse_data_type()?;
+
+            let mut column = Column::new(&col_name, data_type);
+
+            // column constraints
+            loop {
+                match self.peek() {
+                    Some(Token::Primary) => {
+                        self.advance();
+                        self.expect(Token::Key)?;
+                        column = column.primary_key();
+                    }
+                    Some(Token::Not) => {
+                        self.advance();
+                        self.expect(Token::Null)?;
+                        column = column.not_null();
+                    }
+                    Some(Token::Autoincrement) => {
+                        self.advance();
+                        column = column.auto_increment();
+                    }
+                    _ => break,
                 }
-            };
+            }
 
-            columns.push(Column::new(&col_name, data_type));
+            columns.push(column);
 
             match self.peek() {
                 Some(&Token::Comma) => {
@@ -181,9 +668,8 @@ impl Parser {
                     break;
                 }
                 _ => {
-                    return Err(Error::new(
-                        ErrorKind::InvalidData,
-                        "Expected ',' or ')' after column definition",
+                    return Err(HozonError::Parse(
+                        "Expected ',' or ')' after column definition".to_string(),
                     ));
                 }
             }
@@ -197,28 +683,75 @@ impl Parser {
         })
     }
 
-    pub fn parse_insert(&mut self) -> io::Result<Statement> {
+    pub fn parse_insert(&mut self) -> Result<Statement> {
         self.expect(Token::Insert)?;
+        let or_action = match self.peek() {
+            Some(Token::Or) => {
+                self.advance();
+                match self.consume() {
+                    Some(Token::Replace) => Some(ConflictAction::Replace),
+                    Some(Token::Ignore) => Some(ConflictAction::Nothing),
+                    _ => {
+                        return Err(HozonError::Parse(
+                            "Expected REPLACE or IGNORE after INSERT OR".to_string(),
+                        ));
+                    }
+                }
+            }
+            _ => None,
+        };
         self.expect(Token::Into)?;
 
         // extract table name
         let table_name = self.get_table_name()?;
+
+        // optional column list
+        let columns = match self.peek() {
+            Some(Token::LeftParen) => Some(self.parse_column_list()?),
+            _ => None,
+        };
+
         self.expect(Token::Values)?;
-        self.expect(Token::LeftParen)?;
+        let mut values = vec![self.parse_value_row()?];
+        while let Some(Token::Comma) = self.peek() {
+            self.advance();
+            values.push(self.parse_value_row()?);
+        }
+
+        let on_conflict = match (or_action, self.peek()) {
+            (None, Some(Token::On)) => Some(self.parse_on_conflict()?),
+            (Some(_), Some(Token::On)) => {
+                return Err(HozonError::Parse(
+                    "INSERT OR ... can't also have an ON CONFLICT clause".to_string(),
+                ));
+            }
+            (action, _) => action.map(|action| OnConflict {
+                target: Vec::new(),
+                action,
+            }),
+        };
+
+        let returning = self.parse_returning()?;
+        self.expect(Token::Semicolon)?;
 
-        // extract values
+        Ok(Statement::Insert {
+            table_name,
+            columns,
+            values,
+            on_conflict,
+            returning,
+        })
+    }
+
+    /// One row of INSERT values: (value, ...)
+    fn parse_value_row(&mut self) -> Result<Vec<Expr>> {
+        self.expect(Token::LeftParen)?;
         let mut values = Vec::new();
         loop {
-            match self.consume() {
-                Some(Token::NumberLiteral(num)) => values.push(Value::Integer(num)),
-                Some(Token::StringLiteral(s)) => values.push(Value::Text(s)),
-                Some(Token::BoolLiteral(bool)) => values.push(Value::Boolean(bool)),
-                Some(Token::Null) => values.push(Value::Null),
-                _ => {
-                    return Err(Error::new(
-                        ErrorKind::InvalidData,
-                        "Expected value literals",
-                    ));
+            match self.parse_value()? {
+                Some(value) => values.push(value),
+                None => {
+                    return Err(HozonError::Parse("Expected value literals".to_string()));
                 }
             }
 
@@ -232,90 +765,631 @@ impl Parser {
                     break;
                 }
                 _ => {
-                    return Err(Error::new(
-                        ErrorKind::InvalidData,
-                        "Expected ',' or ')' after value",
+                    return Err(HozonError::Parse(
+                        "Expected ',' or ')' after value".to_string(),
                     ));
                 }
             }
         }
+        Ok(values)
+    }
 
+    fn parse_update(&mut self) -> Result<Statement> {
+        self.expect(Token::Update)?;
+        let table_name = self.get_table_name()?;
+        self.expect(Token::Set)?;
+        let assignments = self.parse_assignments()?;
+        let where_clause = self.parse_where()?;
+        let returning = self.parse_returning()?;
         self.expect(Token::Semicolon)?;
 
-        Ok(Statement::Insert { table_name, values })
+        Ok(Statement::Update {
+            table_name,
+            assignments,
+            where_clause,
+            returning,
+        })
     }
 
-    fn parse_select(&mut self) -> io::Result<Statement> {
-        self.expect(Token::Select)?;
+    fn parse_delete(&mut self) -> Result<Statement> {
+        self.expect(Token::Delete)?;
+        self.expect(Token::From)?;
+        let table_name = self.get_table_name()?;
+        let where_clause = self.parse_where()?;
+        let returning = self.parse_returning()?;
+        self.expect(Token::Semicolon)?;
 
-        // Check if it's * or column list
-        let columns = match self.peek() {
-            Some(Token::Asterisk) => {
+        Ok(Statement::Delete {
+            table_name,
+            where_clause,
+            returning,
+        })
+    }
+
+    /// `[WHERE expr]`
+    fn parse_where(&mut self) -> Result<Option<Expr>> {
+        match self.peek() {
+            Some(Token::Where) => {
                 self.advance();
-                SelectColumns::All
+                Ok(Some(self.parse_expr()?))
             }
-            Some(Token::Identifier(_)) => {
-                // Parse column list: id, name, etc.
-                let mut col_names = Vec::new();
+            _ => Ok(None),
+        }
+    }
 
-                loop {
-                    // Get column name
-                    match self.consume() {
-                        Some(Token::Identifier(name)) => col_names.push(name),
-                        _ => {
-                            return Err(Error::new(ErrorKind::InvalidData, "Expected column name"));
-                        }
-                    }
+    /// `[RETURNING * | col, ...]`
+    fn parse_returning(&mut self) -> Result<Option<SelectColumns>> {
+        match self.peek() {
+            Some(Token::Returning) => {
+                self.advance();
+                Ok(Some(self.parse_columns("RETURNING", Token::Semicolon)?))
+            }
+            _ => Ok(None),
+        }
+    }
 
-                    // Check for comma (more columns) or FROM (done)
-                    match self.peek() {
-                        Some(Token::Comma) => {
-                            self.advance();
-                            continue;
-                        }
-                        Some(Token::From) => break,
-                        _ => {
-                            return Err(Error::new(
-                                ErrorKind::InvalidData,
-                                "Expected ',' or 'FROM'",
-                            ));
-                        }
-                    }
+    /// `col = expr, ...` after SET
+    fn parse_assignments(&mut self) -> Result<Vec<Assignment>> {
+        let mut assignments = Vec::new();
+        loop {
+            let column = match self.consume_name() {
+                Some(name) => name,
+                None => {
+                    return Err(HozonError::Parse("Expected column name in SET".to_string()));
                 }
+            };
+            self.expect(Token::Equals)?;
+            let value = self.parse_expr()?;
+            assignments.push(Assignment { column, value });
+            if self.peek() != Some(&Token::Comma) {
+                return Ok(assignments);
+            }
+            self.advance();
+        }
+    }
+
+    /// `ON CONFLICT [(col, ...)] DO NOTHING | DO UPDATE SET col = expr, ...`
+    fn parse_on_conflict(&mut self) -> Result<OnConflict> {
+        self.expect(Token::On)?;
+        self.expect(Token::Conflict)?;
+
+        let target = match self.peek() {
+            Some(Token::LeftParen) => self.parse_column_list()?,
+            _ => Vec::new(),
+        };
 
-                SelectColumns::Specific(col_names)
+        self.expect(Token::Do)?;
+        let action = match self.consume() {
+            // NOTHING isn't reserved, so it stays usable as a column name
+            Some(Token::Identifier(word)) if word.eq_ignore_ascii_case("nothing") => {
+                ConflictAction::Nothing
+            }
+            Some(Token::Update) => {
+                self.expect(Token::Set)?;
+                ConflictAction::Update(self.parse_assignments()?)
             }
             _ => {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    "Expected '*' or column names after SELECT",
+                return Err(HozonError::Parse(
+                    "Expected NOTHING or UPDATE after ON CONFLICT DO".to_string(),
                 ));
             }
         };
 
-        self.expect(Token::From)?;
-        let table_name = self.get_table_name()?;
-        self.expect(Token::Semicolon)?;
-
-        Ok(Statement::Select {
-            table_name,
-            columns,
-            where_clause: None,
-        })
+        Ok(OnConflict { target, action })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::sql::tokenizer::tokenize;
+    fn parse_pragma(&mut self) -> Result<Statement> {
+        self.expect(Token::Pragma)?;
 
-    #[test]
-    fn test_parse_create_table() {
-        let sql = "CREATE TABLE users (id INTEGER, name TEXT);";
-        let tokens = tokenize(sql).unwrap();
-        let mut parser = Parser::new(tokens);
-        let statement = parser.parse().unwrap();
+        let name = match self.consume_name() {
+            Some(name) => name,
+            None => {
+                return Err(HozonError::Parse(
+                    "Expected pragma name after PRAGMA".to_string(),
+                ));
+            }
+        };
+
+        let value = if let Some(Token::Equals) = self.peek() {
+            self.advance();
+            match self.consume() {
+                Some(Token::Identifier(v)) | Some(Token::StringLiteral(v)) => Some(v),
+                Some(Token::NumberLiteral(n)) => Some(n.to_string()),
+                Some(Token::Minus) => match self.consume() {
+                    Some(Token::NumberLiteral(n)) => Some(format!("-{}", n)),
+                    _ => {
+                        return Err(HozonError::Parse("Expected a number after '-'".to_string()));
+                    }
+                },
+                Some(Token::BoolLiteral(b)) => Some(b.to_string()),
+                // ON is a keyword, but also the usual way to turn a pragma on
+                Some(Token::On) => Some("ON".to_string()),
+                _ => {
+                    return Err(HozonError::Parse(
+                        "Expected pragma value after '='".to_string(),
+                    ));
+                }
+            }
+        } else {
+            None
+        };
+
+        self.expect(Token::Semicolon)?;
+
+        Ok(Statement::Pragma { name, value })
+    }
+
+    /// A column type, as in CREATE TABLE or CAST
+    fn parse_data_type(&mut self) -> Result<DataType> {
+        let token = self
+            .consume()
+            .ok_or_else(|| HozonError::Parse("Unexpected end of input".to_string()))?;
+        match token {
+            Token::Integer => Ok(DataType::Integer),
+            Token::Text => Ok(DataType::Text),
+            Token::Boolean => Ok(DataType::Boolean),
+            Token::Float => Ok(DataType::Float),
+            Token::BigInt => Ok(DataType::BigInt),
+            Token::Blob => Ok(DataType::Blob),
+            _ => Err(HozonError::Parse("Expected data type".to_string())),
+        }
+    }
+
+    fn parse_select(&mut self) -> Result<Statement> {
+        let select = self.parse_query()?;
+        self.expect(Token::Semicolon)?;
+        Ok(select)
+    }
+
+    /// A SELECT without its closing semicolon, as nested in a subquery
+    /// A column list ending before `end`: `*`, `id, name`, expressions, or
+    /// a mix such as `rowid, *`
+    fn parse_columns(&mut self, clause: &str, end: Token) -> Result<SelectColumns> {
+        if self
+            .peek()
+            .is_none_or(|t| *t == end || *t == Token::Semicolon)
+        {
+            return Err(HozonError::Parse(format!(
+                "Expected '*' or column names after {}",
+                clause
+            )));
+        }
+        let mut items = Vec::new();
+        loop {
+            items.push(self.parse_select_item()?);
+
+            // Check for comma (more columns) or the end
+            match self.peek() {
+                Some(Token::Comma) => {
+                    self.advance();
+                    continue;
+                }
+                Some(token) if *token == end => break,
+                _ => {
+                    return Err(HozonError::Parse(format!("Expected ',' or '{}'", end)));
+                }
+            }
+        }
+        Ok(if items == [SelectItem::Wildcard(None)] {
+            SelectColumns::All
+        } else {
+            SelectColumns::Specific(items)
+        })
+    }
+
+    /// `*`, `table.*`, or an expression with an optional `AS name`
+    fn parse_select_item(&mut self) -> Result<SelectItem> {
+        match (self.peek(), self.tokens.get(self.position + 1)) {
+            (Some(Token::Asterisk), _) => {
+                self.advance();
+                return Ok(SelectItem::Wildcard(None));
+            }
+            (Some(Token::Identifier(table)), Some(Token::Dot))
+                if self.tokens.get(self.position + 2) == Some(&Token::Asterisk) =>
+            {
+                let table = table.clone();
+                self.position += 3;
+                return Ok(SelectItem::Wildcard(Some(table)));
+            }
+            _ => {}
+        }
+        let start = self.position;
+        let item = match self.parse_expr()? {
+            Expr::Column(name) => SelectItem::column(&name),
+            expr => SelectItem::Expr {
+                expr,
+                name: tokenizer::to_sql(&self.tokens[start..self.position]),
+            },
+        };
+        if self.peek() != Some(&Token::As) {
+            return Ok(item);
+        }
+        self.advance();
+        let name = self.get_name("column")?;
+        Ok(match item {
+            SelectItem::Expr { expr, .. } => SelectItem::Expr { expr, name },
+            wildcard => wildcard,
+        })
+    }
+
+    fn parse_query(&mut self) -> Result<Statement> {
+        self.expect(Token::Select)?;
+        let distinct = self.peek() == Some(&Token::Distinct);
+        if distinct {
+            self.advance();
+        }
+
+        let columns = self.parse_columns("SELECT", Token::From)?;
+        self.expect(Token::From)?;
+        let table_name = self.get_table_name()?;
+        let mut joins = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Token::Join) => self.advance(),
+                Some(Token::Inner) => {
+                    self.advance();
+                    self.expect(Token::Join)?;
+                }
+                _ => break,
+            }
+            let table_name = self.get_table_name()?;
+            self.expect(Token::On)?;
+            joins.push(Join {
+                table_name,
+                on: self.parse_expr()?,
+            });
+        }
+        let where_clause = self.parse_where()?;
+        let order_by = match self.peek() {
+            Some(Token::Order) => self.parse_order_by()?,
+            _ => Vec::new(),
+        };
+
+        Ok(Statement::Select {
+            table_name,
+            columns,
+            where_clause,
+            order_by,
+            distinct,
+            joins,
+        })
+    }
+
+    /// `name`, or `name.column` if a dot follows
+    fn qualified(&mut self, name: String) -> Result<String> {
+        if self.peek() != Some(&Token::Dot) {
+            return Ok(name);
+        }
+        self.advance();
+        match self.consume_name() {
+            Some(column) => Ok(format!("{}.{}", name, column)),
+            _ => Err(HozonError::Parse(format!(
+                "Expected a column name after '{}.'",
+                name
+            ))),
+        }
+    }
+
+    /// ORDER BY key [ASC|DESC], ...
+    fn parse_order_by(&mut self) -> Result<Vec<OrderBy>> {
+        self.expect(Token::Order)?;
+        self.expect(Token::By)?;
+
+        let mut keys = Vec::new();
+        loop {
+            let expr = self.parse_expr()?;
+            let descending = match self.peek() {
+                Some(Token::Asc) => {
+                    self.advance();
+                    false
+                }
+                Some(Token::Desc) => {
+                    self.advance();
+                    true
+                }
+                _ => false,
+            };
+            keys.push(OrderBy { expr, descending });
+
+            match self.peek() {
+                Some(Token::Comma) => self.advance(),
+                _ => return Ok(keys),
+            }
+        }
+    }
+
+    /// An expression; OR binds loosest, then AND, NOT, and comparisons
+    fn parse_expr(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.advance();
+            expr = Expr::BinaryOp {
+                left: Box::new(expr),
+                op: BinaryOperator::Or,
+                right: Box::new(self.parse_and()?),
+            };
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.advance();
+            expr = Expr::BinaryOp {
+                left: Box::new(expr),
+                op: BinaryOperator::And,
+                right: Box::new(self.parse_not()?),
+            };
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr> {
+        if self.peek() == Some(&Token::Not) {
+            self.advance();
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_comparison()
+    }
+
+    /// A comparison, IN or IS [NOT] NULL test, or a lone operand
+    fn parse_comparison(&mut self) -> Result<Expr> {
+        let left = self.parse_additive()?;
+        let negated = self.peek() == Some(&Token::Not)
+            && self.tokens.get(self.position + 1) == Some(&Token::In);
+        let mut expr = if negated || self.peek() == Some(&Token::In) {
+            self.position += if negated { 2 } else { 1 };
+            self.parse_in(left, negated)?
+        } else {
+            let op = match self.peek() {
+                Some(Token::Equals) => Some(BinaryOperator::Equals),
+                Some(Token::NotEquals) => Some(BinaryOperator::NotEquals),
+                Some(Token::LessThan) => Some(BinaryOperator::LessThan),
+                Some(Token::GreaterThan) => Some(BinaryOperator::GreaterThan),
+                Some(Token::LessOrEqual) => Some(BinaryOperator::LessOrEqual),
+                Some(Token::GreaterOrEqual) => Some(BinaryOperator::GreaterOrEqual),
+                _ => None,
+            };
+            match op {
+                Some(op) => {
+                    self.advance();
+                    Expr::BinaryOp {
+                        left: Box::new(left),
+                        op,
+                        right: Box::new(self.parse_additive()?),
+                    }
+                }
+                None => left,
+            }
+        };
+
+        // IS [NOT] NULL applies to whatever came before, even a comparison
+        while self.peek() == Some(&Token::Is) {
+            self.advance();
+            let negated = self.peek() == Some(&Token::Not);
+            if negated {
+                self.advance();
+            }
+            self.expect(Token::Null)?;
+            expr = Expr::IsNull {
+                expr: Box::new(expr),
+                negated,
+            };
+        }
+        Ok(expr)
+    }
+
+    /// Operands joined by binary '-', which binds tighter than comparisons
+    fn parse_additive(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_operand()?;
+        while self.peek() == Some(&Token::Minus) {
+            self.advance();
+            expr = Expr::BinaryOp {
+                left: Box::new(expr),
+                op: BinaryOperator::Subtract,
+                right: Box::new(self.parse_operand()?),
+            };
+        }
+        Ok(expr)
+    }
+
+    /// The `(...)` after `expr [NOT] IN`
+    fn parse_in(&mut self, expr: Expr, negated: bool) -> Result<Expr> {
+        let expr = Box::new(expr);
+        self.expect(Token::LeftParen)?;
+        if self.peek() == Some(&Token::Select) {
+            let query = Box::new(self.parse_query()?);
+            self.expect(Token::RightParen)?;
+            return Ok(Expr::InSubquery {
+                expr,
+                query,
+                negated,
+            });
+        }
+
+        let mut list = Vec::new();
+        loop {
+            list.push(self.parse_expr()?);
+            match self.consume() {
+                Some(Token::Comma) => continue,
+                Some(Token::RightParen) => break,
+                _ => {
+                    return Err(HozonError::Parse(
+                        "Expected ',' or ')' in IN list".to_string(),
+                    ));
+                }
+            }
+        }
+        Ok(Expr::InList {
+            expr,
+            list,
+            negated,
+        })
+    }
+
+    /// A literal, column name, function call, CAST, subquery, negation or
+    /// parenthesized expression
+    fn parse_operand(&mut self) -> Result<Expr> {
+        if let Some(value) = self.parse_value()? {
+            return Ok(value);
+        }
+        let token = match self.consume() {
+            Some(token) if token.is_non_reserved() => self
+                .as_name(token, self.position - 1)
+                .map(Token::Identifier),
+            token => token,
+        };
+        match token {
+            Some(Token::Minus) => Ok(Expr::Negate(Box::new(self.parse_operand()?))),
+            Some(Token::LeftParen) if self.peek() == Some(&Token::Select) => {
+                let query = self.parse_query()?;
+                self.expect(Token::RightParen)?;
+                Ok(Expr::Subquery(Box::new(query)))
+            }
+            Some(Token::Exists) => {
+                self.expect(Token::LeftParen)?;
+                let query = self.parse_query()?;
+                self.expect(Token::RightParen)?;
+                Ok(Expr::Exists(Box::new(query)))
+            }
+            Some(Token::Cast) => {
+                self.expect(Token::LeftParen)?;
+                let expr = Box::new(self.parse_expr()?);
+                self.expect(Token::As)?;
+                let data_type = self.parse_data_type()?;
+                self.expect(Token::RightParen)?;
+                Ok(Expr::Cast { expr, data_type })
+            }
+            Some(Token::Identifier(name))
+                if name.eq_ignore_ascii_case("COUNT")
+                    && self.tokens[self.position..].starts_with(&[
+                        Token::LeftParen,
+                        Token::Asterisk,
+                        Token::RightParen,
+                    ]) =>
+            {
+                self.position += 3;
+                Ok(Expr::CountAll)
+            }
+            Some(Token::Identifier(name)) if self.peek() == Some(&Token::LeftParen) => {
+                self.advance();
+                Ok(Expr::Function {
+                    name,
+                    args: self.parse_args()?,
+                })
+            }
+            Some(Token::Identifier(name)) => Ok(Expr::Column(self.qualified(name)?)),
+            Some(Token::LeftParen) => {
+                let expr = self.parse_expr()?;
+                self.expect(Token::RightParen)?;
+                Ok(expr)
+            }
+            Some(token) => Err(HozonError::Parse(format!(
+                "Expected an expression, found '{}'",
+                token
+            ))),
+            None => Err(HozonError::Parse("Unexpected end of input".to_string())),
+        }
+    }
+
+    /// A literal or parameter, or None with nothing consumed
+    fn parse_value(&mut self) -> Result<Option<Expr>> {
+        if let Some(&Token::Parameter(n)) = self.peek() {
+            self.advance();
+            // A bare ? takes the number after the highest so far
+            let n = n.unwrap_or(self.parameters + 1);
+            self.parameters = self.parameters.max(n);
+            return Ok(Some(Expr::Parameter(n)));
+        }
+        Ok(self.parse_literal()?.map(Expr::Literal))
+    }
+
+    /// A literal, taking a '-' before a number as its sign. None, with
+    /// nothing consumed, if the next tokens aren't one.
+    fn parse_literal(&mut self) -> Result<Option<Value>> {
+        let negative = self.peek() == Some(&Token::Minus);
+        let Some(token) = self.tokens.get(self.position + usize::from(negative)) else {
+            return Ok(None);
+        };
+        let value = match (token, negative) {
+            (Token::NumberLiteral(n), _) => integer(*n, negative)?,
+            (Token::FloatLiteral(n), true) => Value::Float(-n),
+            (token, false) => match literal(token) {
+                Some(value) => value,
+                None => return Ok(None),
+            },
+            (_, true) => return Ok(None),
+        };
+        self.position += 1 + usize::from(negative);
+        Ok(Some(value))
+    }
+
+    /// A function's arguments after its `(`, through the `)`
+    fn parse_args(&mut self) -> Result<Vec<Expr>> {
+        let mut args = Vec::new();
+        if self.peek() == Some(&Token::RightParen) {
+            self.advance();
+            return Ok(args);
+        }
+        loop {
+            args.push(self.parse_expr()?);
+            match self.consume() {
+                Some(Token::Comma) => continue,
+                Some(Token::RightParen) => return Ok(args),
+                _ => {
+                    return Err(HozonError::Parse(
+                        "Expected ',' or ')' after a function argument".to_string(),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// The value a literal token other than a number stands for
+fn literal(token: &Token) -> Option<Value> {
+    match token {
+        Token::FloatLiteral(num) => Some(Value::Float(*num)),
+        Token::StringLiteral(s) => Some(Value::Text(s.clone())),
+        Token::BoolLiteral(bool) => Some(Value::Boolean(*bool)),
+        Token::BlobLiteral(bytes) => Some(Value::Blob(bytes.clone())),
+        Token::Null => Some(Value::Null),
+        _ => None,
+    }
+}
+
+/// The integer `magnitude` with its sign: INTEGER if it fits, BIGINT
+/// otherwise
+fn integer(magnitude: u64, negative: bool) -> Result<Value> {
+    let num = match negative {
+        true => 0i64.checked_sub_unsigned(magnitude),
+        false => i64::try_from(magnitude).ok(),
+    }
+    .ok_or_else(|| {
+        HozonError::Parse(format!(
+            "Integer literal {}{} is out of range",
+            if negative { "-" } else { "" },
+            magnitude
+        ))
+    })?;
+    Ok(match i32::try_from(num) {
+        Ok(num) => Value::Integer(num),
+        Err(_) => Value::BigInt(num),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::tokenizer::tokenize;
+
+    #[test]
+    fn test_parse_create_table() {
+        let sql = "CREATE TABLE users (id INTEGER, name TEXT);";
+        let tokens = tokenize(sql).unwrap();
+        let mut parser = Parser::new(tokens);
+        let statement = parser.parse().unwrap();
 
         match statement {
             Statement::CreateTable { name, columns } => {
@@ -328,6 +1402,52 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_primary_key() {
+        let sql = "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);";
+        let mut parser = Parser::new(tokenize(sql).unwrap());
+
+        match parser.parse().unwrap() {
+            Statement::CreateTable { columns, .. } => {
+                assert!(columns[0].is_primary_key());
+                assert!(!columns[1].is_primary_key());
+            }
+            _ => panic!("Expected CreateTable statement"),
+        }
+
+        let sql = "CREATE TABLE users (id INTEGER PRIMARY);";
+        assert!(Parser::new(tokenize(sql).unwrap()).parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_not_null() {
+        let sql = "CREATE TABLE users (id INTEGER, name TEXT NOT NULL);";
+        let mut parser = Parser::new(tokenize(sql).unwrap());
+
+        match parser.parse().unwrap() {
+            Statement::CreateTable { columns, .. } => {
+                assert!(columns[0].is_nullable());
+                assert!(!columns[1].is_nullable());
+            }
+            _ => panic!("Expected CreateTable statement"),
+        }
+
+        let sql = "CREATE TABLE users (id INTEGER PRIMARY KEY AUTOINCREMENT);";
+        match Parser::new(tokenize(sql).unwrap()).parse().unwrap() {
+            Statement::CreateTable { columns, .. } => {
+                assert!(columns[0].is_auto_increment());
+            }
+            _ => panic!("Expected CreateTable statement"),
+        }
+
+        let sql = "CREATE TABLE users (name TEXT NOT);";
+        assert!(Parser::new(tokenize(sql).unwrap()).parse().is_err());
+
+        // NULL is a value, not a column type
+        let sql = "CREATE TABLE users (nothing NULL);";
+        assert!(Parser::new(tokenize(sql).unwrap()).parse().is_err());
+    }
+
     #[test]
     fn test_parse_create_table_single_column() {
         let sql = "CREATE TABLE products (name TEXT);";
@@ -344,6 +1464,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_keywords_as_names() {
+        let sql = "CREATE TABLE Set (Key TEXT, to INTEGER);";
+        match Parser::from_sql(sql).unwrap().parse().unwrap() {
+            Statement::CreateTable { name, columns } => {
+                assert_eq!(name, "Set");
+                assert_eq!(columns[0].name(), "Key");
+                assert_eq!(columns[1].name(), "to");
+            }
+            _ => panic!("Expected CreateTable statement"),
+        }
+
+        // Reserved words still aren't names
+        let sql = "CREATE TABLE t (select TEXT);";
+        assert!(Parser::from_sql(sql).unwrap().parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_create_view() {
+        let sql = "CREATE VIEW names AS SELECT name FROM users;";
+        match Parser::new(tokenize(sql).unwrap()).parse().unwrap() {
+            Statement::CreateView { name, query } => {
+                assert_eq!(name, "names");
+                assert_eq!(query, "SELECT name FROM users;");
+            }
+            _ => panic!("Expected CreateView statement"),
+        }
+
+        let sql = "create view names as select name as n from users order by 1 asc;";
+        match Parser::new(tokenize(sql).unwrap()).parse().unwrap() {
+            Statement::CreateView { query, .. } => {
+                assert_eq!(query, "SELECT name AS n FROM users ORDER BY 1;");
+            }
+            _ => panic!("Expected CreateView statement"),
+        }
+
+        let sql = "CREATE VIEW names AS INSERT INTO users VALUES (1);";
+        assert!(Parser::new(tokenize(sql).unwrap()).parse().is_err());
+    }
+
     #[test]
     fn test_parse_insert() {
         let sql = "INSERT INTO users VALUES (1, 'Alice', true);";
@@ -352,12 +1512,338 @@ mod tests {
         let statement = parser.parse().unwrap();
 
         match statement {
-            Statement::Insert { table_name, values } => {
+            Statement::Insert {
+                table_name,
+                columns,
+                values,
+                on_conflict,
+                returning,
+            } => {
                 assert_eq!(table_name, "users");
-                assert_eq!(values.len(), 3);
+                assert_eq!(columns, None);
+                assert_eq!(values.len(), 1);
+                assert_eq!(values[0].len(), 3);
+                assert_eq!(on_conflict, None);
+                assert_eq!(returning, None);
             }
             _ => panic!("Expected Insert statement"),
         }
+
+        let sql = "INSERT INTO users VALUES (1, 'Alice'), (2, 'Bob');";
+        match Parser::new(tokenize(sql).unwrap()).parse().unwrap() {
+            Statement::Insert { values, .. } => assert_eq!(
+                values,
+                vec![
+                    vec![
+                        Expr::Literal(Value::Integer(1)),
+                        Expr::Literal(Value::Text("Alice".to_string()))
+                    ],
+                    vec![
+                        Expr::Literal(Value::Integer(2)),
+                        Expr::Literal(Value::Text("Bob".to_string()))
+                    ],
+                ]
+            ),
+            _ => panic!("Expected Insert statement"),
+        }
+
+        let sql = "INSERT INTO users VALUES (1, 'Alice'),;";
+        assert!(Parser::new(tokenize(sql).unwrap()).parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_insert_on_conflict() {
+        let on_conflict = |sql: &str| -> Result<Option<OnConflict>> {
+            match Parser::new(tokenize(sql).unwrap()).parse()? {
+                Statement::Insert { on_conflict, .. } => Ok(on_conflict),
+                _ => panic!("Expected Insert statement"),
+            }
+        };
+        let action = |sql: &str| on_conflict(sql).unwrap().unwrap().action;
+
+        assert_eq!(
+            action("INSERT OR REPLACE INTO t VALUES (1);"),
+            ConflictAction::Replace
+        );
+        assert_eq!(
+            action("INSERT OR IGNORE INTO t VALUES (1);"),
+            ConflictAction::Nothing
+        );
+        assert_eq!(
+            on_conflict("INSERT INTO t VALUES (1) ON CONFLICT (id) DO NOTHING;").unwrap(),
+            Some(OnConflict {
+                target: vec!["id".to_string()],
+                action: ConflictAction::Nothing,
+            })
+        );
+        assert_eq!(
+            action("INSERT INTO t VALUES (1, 2) ON CONFLICT DO UPDATE SET n = excluded.n, m = 3;"),
+            ConflictAction::Update(vec![
+                Assignment {
+                    column: "n".to_string(),
+                    value: Expr::Column("excluded.n".to_string()),
+                },
+                Assignment {
+                    column: "m".to_string(),
+                    value: Expr::Literal(Value::Integer(3)),
+                },
+            ])
+        );
+
+        for sql in [
+            "INSERT OR UPDATE INTO t VALUES (1);",
+            "INSERT INTO t VALUES (1) ON CONFLICT DO;",
+            "INSERT INTO t VALUES (1) ON CONFLICT DO UPDATE n = 1;",
+            "INSERT OR IGNORE INTO t VALUES (1) ON CONFLICT DO NOTHING;",
+        ] {
+            assert!(on_conflict(sql).is_err(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_parse_update_and_delete() {
+        let parse = |sql: &str| Parser::new(tokenize(sql).unwrap()).parse();
+        let id_is = |n| {
+            Some(Expr::BinaryOp {
+                left: Box::new(Expr::Column("id".to_string())),
+                op: BinaryOperator::Equals,
+                right: Box::new(Expr::Literal(Value::Integer(n))),
+            })
+        };
+
+        assert_eq!(
+            parse("UPDATE users SET name = 'bob', active = active WHERE id = 1;").unwrap(),
+            Statement::Update {
+                table_name: "users".to_string(),
+                assignments: vec![
+                    Assignment {
+                        column: "name".to_string(),
+                        value: Expr::Literal(Value::Text("bob".to_string())),
+                    },
+                    Assignment {
+                        column: "active".to_string(),
+                        value: Expr::Column("active".to_string()),
+                    },
+                ],
+                where_clause: id_is(1),
+                returning: None,
+            }
+        );
+        assert_eq!(
+            parse("DELETE FROM users WHERE id = 2 RETURNING *;").unwrap(),
+            Statement::Delete {
+                table_name: "users".to_string(),
+                where_clause: id_is(2),
+                returning: Some(SelectColumns::All),
+            }
+        );
+        assert_eq!(
+            parse("DELETE FROM users;").unwrap(),
+            Statement::Delete {
+                table_name: "users".to_string(),
+                where_clause: None,
+                returning: None,
+            }
+        );
+        match parse("INSERT INTO users VALUES (1) ON CONFLICT DO NOTHING RETURNING rowid, id;")
+            .unwrap()
+        {
+            Statement::Insert { returning, .. } => assert_eq!(
+                returning,
+                Some(SelectColumns::Specific(vec![
+                    SelectItem::column("rowid"),
+                    SelectItem::column("id")
+                ]))
+            ),
+            _ => panic!("Expected Insert statement"),
+        }
+
+        for sql in [
+            "UPDATE users WHERE id = 1;",
+            "UPDATE users SET WHERE id = 1;",
+            "DELETE users;",
+            "DELETE FROM users RETURNING;",
+            "DELETE FROM users RETURNING id name;",
+        ] {
+            assert!(parse(sql).is_err(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_parse_insert_with_columns() {
+        let sql = "INSERT INTO users (name, active) VALUES ('Alice', true);";
+        let mut parser = Parser::new(tokenize(sql).unwrap());
+
+        match parser.parse().unwrap() {
+            Statement::Insert {
+                columns, values, ..
+            } => {
+                let names = vec!["name".to_string(), "active".to_string()];
+                assert_eq!(columns, Some(names));
+                assert_eq!(values[0].len(), 2);
+            }
+            _ => panic!("Expected Insert statement"),
+        }
+
+        let sql = "INSERT INTO prices (amount) VALUES (9.99);";
+        match Parser::new(tokenize(sql).unwrap()).parse().unwrap() {
+            Statement::Insert { values, .. } => {
+                assert_eq!(values, vec![vec![Expr::Literal(Value::Float(9.99))]])
+            }
+            _ => panic!("Expected Insert statement"),
+        }
+
+        let sql = "INSERT INTO events VALUES (2147483647, 2147483648);";
+        match Parser::new(tokenize(sql).unwrap()).parse().unwrap() {
+            Statement::Insert { values, .. } => assert_eq!(
+                values,
+                vec![vec![
+                    Expr::Literal(Value::Integer(i32::MAX)),
+                    Expr::Literal(Value::BigInt(2_147_483_648))
+                ]]
+            ),
+            _ => panic!("Expected Insert statement"),
+        }
+
+        let sql = "INSERT INTO users (name VALUES ('Alice');";
+        assert!(Parser::new(tokenize(sql).unwrap()).parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_pragma() {
+        let tokens = tokenize("PRAGMA synchronous = NORMAL;").unwrap();
+        match Parser::new(tokens).parse().unwrap() {
+            Statement::Pragma { name, value } => {
+                assert_eq!(name, "synchronous");
+                assert_eq!(value, Some("NORMAL".to_string()));
+            }
+            _ => panic!("Expected Pragma statement"),
+        }
+
+        let tokens = tokenize("PRAGMA strict = ON;").unwrap();
+        match Parser::new(tokens).parse().unwrap() {
+            Statement::Pragma { value, .. } => assert_eq!(value.as_deref(), Some("ON")),
+            _ => panic!("Expected Pragma statement"),
+        }
+
+        let tokens = tokenize("PRAGMA synchronous;").unwrap();
+        match Parser::new(tokens).parse().unwrap() {
+            Statement::Pragma { value, .. } => assert_eq!(value, None),
+            _ => panic!("Expected Pragma statement"),
+        }
+    }
+
+    #[test]
+    fn test_parse_checkpoint() {
+        let tokens = tokenize("CHECKPOINT;").unwrap();
+        assert!(matches!(
+            Parser::new(tokens).parse().unwrap(),
+            Statement::Checkpoint
+        ));
+
+        let tokens = tokenize("CHECKPOINT").unwrap();
+        assert!(Parser::new(tokens).parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_analyze() {
+        let parse = |sql: &str| Parser::new(tokenize(sql).unwrap()).parse();
+        assert_eq!(
+            parse("ANALYZE;").unwrap(),
+            Statement::Analyze { table_name: None }
+        );
+        assert_eq!(
+            parse("ANALYZE users;").unwrap(),
+            Statement::Analyze {
+                table_name: Some("users".to_string())
+            }
+        );
+        for sql in ["ANALYZE", "ANALYZE users", "ANALYZE a b;", "ANALYZE 1;"] {
+            assert!(parse(sql).is_err(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_parse_savepoints() {
+        let parse = |sql: &str| Parser::new(tokenize(sql).unwrap()).parse();
+        assert_eq!(
+            parse("SAVEPOINT a;").unwrap(),
+            Statement::Savepoint {
+                name: "a".to_string()
+            }
+        );
+        for sql in ["RELEASE a;", "RELEASE SAVEPOINT a;"] {
+            assert_eq!(
+                parse(sql).unwrap(),
+                Statement::Release {
+                    name: "a".to_string()
+                }
+            );
+        }
+        for sql in ["ROLLBACK TO a;", "ROLLBACK TO SAVEPOINT a;"] {
+            assert_eq!(
+                parse(sql).unwrap(),
+                Statement::RollbackTo {
+                    name: "a".to_string()
+                }
+            );
+        }
+        for sql in ["SAVEPOINT;", "ROLLBACK a;", "RELEASE a b;", "SAVEPOINT 1;"] {
+            assert!(parse(sql).is_err(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_parse_indexes() {
+        let parse = |sql: &str| Parser::new(tokenize(sql).unwrap()).parse();
+        assert_eq!(
+            parse("CREATE UNIQUE INDEX by_name ON users (last, first);").unwrap(),
+            Statement::CreateIndex {
+                name: "by_name".to_string(),
+                table_name: "users".to_string(),
+                columns: vec!["last".to_string(), "first".to_string()],
+                unique: true,
+            }
+        );
+        assert!(matches!(
+            parse("CREATE INDEX i ON t (a);").unwrap(),
+            Statement::CreateIndex { unique: false, .. }
+        ));
+        assert_eq!(
+            parse("DROP INDEX by_name;").unwrap(),
+            Statement::DropIndex {
+                name: "by_name".to_string()
+            }
+        );
+        for sql in [
+            "CREATE INDEX ON t (a);",
+            "CREATE INDEX i t (a);",
+            "CREATE INDEX i ON t ();",
+            "CREATE UNIQUE i ON t (a);",
+            "DROP i;",
+            "DROP INDEX;",
+        ] {
+            assert!(parse(sql).is_err(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_parse_explain() {
+        let tokens = tokenize("EXPLAIN SELECT * FROM users;").unwrap();
+        match Parser::new(tokens).parse().unwrap() {
+            Statement::Explain(statement) => {
+                assert!(matches!(*statement, Statement::Select { .. }))
+            }
+            other => panic!("Expected Explain statement, got {:?}", other),
+        }
+
+        for sql in ["EXPLAIN;", "EXPLAIN EXPLAIN SELECT * FROM users;"] {
+            assert!(
+                Parser::new(tokenize(sql).unwrap()).parse().is_err(),
+                "{}",
+                sql
+            );
+        }
     }
 
     #[test]
@@ -372,6 +1858,7 @@ mod tests {
                 table_name,
                 columns,
                 where_clause: None,
+                ..
             } => {
                 assert_eq!(table_name, "users");
                 assert_eq!(columns, SelectColumns::All);
@@ -392,13 +1879,14 @@ mod tests {
                 table_name,
                 columns,
                 where_clause: None,
+                ..
             } => {
                 assert_eq!(table_name, "users");
                 match columns {
                     SelectColumns::Specific(cols) => {
                         assert_eq!(cols.len(), 2);
-                        assert_eq!(cols[0], "id");
-                        assert_eq!(cols[1], "name");
+                        assert_eq!(cols[0], SelectItem::column("id"));
+                        assert_eq!(cols[1], SelectItem::column("name"));
                     }
                     _ => panic!("Expected specific columns"),
                 }
@@ -406,4 +1894,509 @@ mod tests {
             _ => panic!("Expected Select statement"),
         }
     }
+
+    #[test]
+    fn test_parse_function_calls_and_casts() {
+        let tokens = tokenize("SELECT UPPER(name), t.* FROM t WHERE COALESCE(a, 1) = 1;").unwrap();
+        match Parser::new(tokens).parse().unwrap() {
+            Statement::Select {
+                columns,
+                where_clause,
+                ..
+            } => {
+                assert_eq!(
+                    columns,
+                    SelectColumns::Specific(vec![
+                        SelectItem::Expr {
+                            expr: Expr::Function {
+                                name: "UPPER".to_string(),
+                                args: vec![Expr::Column("name".to_string())],
+                            },
+                            name: "UPPER(name)".to_string(),
+                        },
+                        SelectItem::Wildcard(Some("t".to_string())),
+                    ])
+                );
+                assert_eq!(
+                    where_clause,
+                    Some(Expr::BinaryOp {
+                        left: Box::new(Expr::Function {
+                            name: "COALESCE".to_string(),
+                            args: vec![
+                                Expr::Column("a".to_string()),
+                                Expr::Literal(Value::Integer(1)),
+                            ],
+                        }),
+                        op: BinaryOperator::Equals,
+                        right: Box::new(Expr::Literal(Value::Integer(1))),
+                    })
+                );
+            }
+            _ => panic!("Expected Select statement"),
+        }
+
+        let tokens = tokenize("SELECT CAST(id AS TEXT) FROM t;").unwrap();
+        match Parser::new(tokens).parse().unwrap() {
+            Statement::Select { columns, .. } => assert_eq!(
+                columns,
+                SelectColumns::Specific(vec![SelectItem::Expr {
+                    expr: Expr::Cast {
+                        expr: Box::new(Expr::Column("id".to_string())),
+                        data_type: DataType::Text,
+                    },
+                    name: "CAST(id AS TEXT)".to_string(),
+                }])
+            ),
+            _ => panic!("Expected Select statement"),
+        }
+        let tokens = tokenize("SELECT CAST(id AS DATE) FROM t;").unwrap();
+        assert!(Parser::new(tokens).parse().is_err());
+
+        // COUNT(*) is its own expression; COUNT with arguments is a call
+        let tokens = tokenize("SELECT count(*), COUNT(a) FROM t;").unwrap();
+        match Parser::new(tokens).parse().unwrap() {
+            Statement::Select { columns, .. } => assert_eq!(
+                columns,
+                SelectColumns::Specific(vec![
+                    SelectItem::Expr {
+                        expr: Expr::CountAll,
+                        name: "count(*)".to_string(),
+                    },
+                    SelectItem::Expr {
+                        expr: Expr::Function {
+                            name: "COUNT".to_string(),
+                            args: vec![Expr::Column("a".to_string())],
+                        },
+                        name: "COUNT(a)".to_string(),
+                    },
+                ])
+            ),
+            _ => panic!("Expected Select statement"),
+        }
+
+        let tokens = tokenize("SELECT UPPER(name FROM t;").unwrap();
+        let err = Parser::new(tokens).parse().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Expected ',' or ')' after a function argument"
+        );
+    }
+
+    #[test]
+    fn test_parse_select_rowid_and_star() {
+        let tokens = tokenize("SELECT rowid, * FROM users;").unwrap();
+        match Parser::new(tokens).parse().unwrap() {
+            Statement::Select { columns, .. } => assert_eq!(
+                columns,
+                SelectColumns::Specific(vec![
+                    SelectItem::column("rowid"),
+                    SelectItem::Wildcard(None)
+                ])
+            ),
+            _ => panic!("Expected Select statement"),
+        }
+    }
+
+    #[test]
+    fn test_parse_select_where() {
+        let tokens =
+            tokenize("SELECT * FROM users WHERE id = 1 AND NOT (name = 'x' OR age < 2);").unwrap();
+        let column = |name: &str| Box::new(Expr::Column(name.to_string()));
+        let literal = |value: Value| Box::new(Expr::Literal(value));
+        let or = Expr::BinaryOp {
+            left: Box::new(Expr::BinaryOp {
+                left: column("name"),
+                op: BinaryOperator::Equals,
+                right: literal(Value::Text("x".to_string())),
+            }),
+            op: BinaryOperator::Or,
+            right: Box::new(Expr::BinaryOp {
+                left: column("age"),
+                op: BinaryOperator::LessThan,
+                right: literal(Value::Integer(2)),
+            }),
+        };
+        let expected = Expr::BinaryOp {
+            left: Box::new(Expr::BinaryOp {
+                left: column("id"),
+                op: BinaryOperator::Equals,
+                right: literal(Value::Integer(1)),
+            }),
+            op: BinaryOperator::And,
+            right: Box::new(Expr::Not(Box::new(or))),
+        };
+
+        match Parser::new(tokens).parse().unwrap() {
+            Statement::Select { where_clause, .. } => assert_eq!(where_clause, Some(expected)),
+            _ => panic!("Expected Select statement"),
+        }
+
+        for sql in [
+            "SELECT * FROM users WHERE;",
+            "SELECT * FROM users WHERE id = ;",
+            "SELECT * FROM users WHERE (id = 1;",
+        ] {
+            assert!(
+                Parser::new(tokenize(sql).unwrap()).parse().is_err(),
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_order_by() {
+        let tokens =
+            tokenize("SELECT * FROM users WHERE id > 1 ORDER BY age DESC, name ASC, 1;").unwrap();
+        match Parser::new(tokens).parse().unwrap() {
+            Statement::Select { order_by, .. } => assert_eq!(
+                order_by,
+                vec![
+                    OrderBy {
+                        expr: Expr::Column("age".to_string()),
+                        descending: true,
+                    },
+                    OrderBy {
+                        expr: Expr::Column("name".to_string()),
+                        descending: false,
+                    },
+                    OrderBy {
+                        expr: Expr::Literal(Value::Integer(1)),
+                        descending: false,
+                    },
+                ]
+            ),
+            _ => panic!("Expected Select statement"),
+        }
+
+        for sql in [
+            "SELECT * FROM users ORDER age;",
+            "SELECT * FROM users ORDER BY;",
+        ] {
+            assert!(
+                Parser::new(tokenize(sql).unwrap()).parse().is_err(),
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_select_distinct() {
+        let parse = |sql: &str| Parser::new(tokenize(sql).unwrap()).parse().unwrap();
+        match parse("SELECT DISTINCT * FROM users;") {
+            Statement::Select {
+                columns, distinct, ..
+            } => {
+                assert_eq!(columns, SelectColumns::All);
+                assert!(distinct);
+            }
+            _ => panic!("Expected Select statement"),
+        }
+        match parse("SELECT name FROM users;") {
+            Statement::Select { distinct, .. } => assert!(!distinct),
+            _ => panic!("Expected Select statement"),
+        }
+    }
+
+    #[test]
+    fn test_parse_join() {
+        let sql = "SELECT users.name, orders.* FROM users JOIN orders ON users.id = orders.user_id \
+                   INNER JOIN items ON items.order_id = orders.id;";
+        match Parser::new(tokenize(sql).unwrap()).parse().unwrap() {
+            Statement::Select {
+                table_name,
+                columns,
+                joins,
+                ..
+            } => {
+                assert_eq!(table_name, "users");
+                assert_eq!(
+                    columns,
+                    SelectColumns::Specific(vec![
+                        SelectItem::column("users.name"),
+                        SelectItem::Wildcard(Some("orders".to_string()))
+                    ])
+                );
+                assert_eq!(joins.len(), 2);
+                assert_eq!(joins[0].table_name, "orders");
+                assert_eq!(
+                    joins[0].on,
+                    Expr::BinaryOp {
+                        left: Box::new(Expr::Column("users.id".to_string())),
+                        op: BinaryOperator::Equals,
+                        right: Box::new(Expr::Column("orders.user_id".to_string())),
+                    }
+                );
+                assert_eq!(joins[1].table_name, "items");
+            }
+            _ => panic!("Expected Select statement"),
+        }
+
+        for sql in [
+            "SELECT * FROM users JOIN orders;",
+            "SELECT * FROM users INNER orders ON id = id;",
+            "SELECT users. FROM users;",
+        ] {
+            assert!(
+                Parser::new(tokenize(sql).unwrap()).parse().is_err(),
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_in() {
+        let where_clause = |sql: &str| match Parser::new(tokenize(sql).unwrap()).parse().unwrap() {
+            Statement::Select { where_clause, .. } => where_clause.unwrap(),
+            _ => panic!("Expected Select statement"),
+        };
+
+        assert_eq!(
+            where_clause("SELECT * FROM t WHERE a NOT IN (1, b);"),
+            Expr::InList {
+                expr: Box::new(Expr::Column("a".to_string())),
+                list: vec![
+                    Expr::Literal(Value::Integer(1)),
+                    Expr::Column("b".to_string())
+                ],
+                negated: true,
+            }
+        );
+        match where_clause("SELECT * FROM t WHERE a IN (SELECT id FROM u) AND b = 1;") {
+            Expr::BinaryOp { left, .. } => match *left {
+                Expr::InSubquery { query, negated, .. } => {
+                    assert!(!negated);
+                    assert!(
+                        matches!(*query, Statement::Select { ref table_name, .. } if table_name == "u")
+                    );
+                }
+                other => panic!("Expected IN subquery, got {:?}", other),
+            },
+            other => panic!("Expected AND, got {:?}", other),
+        }
+
+        for sql in [
+            "SELECT * FROM t WHERE a IN ();",
+            "SELECT * FROM t WHERE a IN (1;",
+            "SELECT * FROM t WHERE a IN (SELECT id FROM u;);",
+        ] {
+            assert!(
+                Parser::new(tokenize(sql).unwrap()).parse().is_err(),
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_scalar_and_exists_subqueries() {
+        let sql = "SELECT (SELECT MAX FROM u) FROM t WHERE NOT EXISTS (SELECT * FROM u WHERE u.id = t.id);";
+        match Parser::new(tokenize(sql).unwrap()).parse().unwrap() {
+            Statement::Select {
+                columns: SelectColumns::Specific(columns),
+                where_clause: Some(Expr::Not(exists)),
+                ..
+            } => {
+                assert!(matches!(
+                    &columns[..],
+                    [SelectItem::Expr { expr: Expr::Subquery(_), name }]
+                        if name == "(SELECT MAX FROM u)"
+                ));
+                match *exists {
+                    Expr::Exists(query) => assert!(matches!(
+                        *query,
+                        Statement::Select {
+                            where_clause: Some(_),
+                            ..
+                        }
+                    )),
+                    other => panic!("Expected EXISTS, got {:?}", other),
+                }
+            }
+            other => panic!("Expected Select statement, got {:?}", other),
+        }
+
+        for sql in [
+            "SELECT * FROM t WHERE EXISTS u;",
+            "SELECT * FROM t WHERE EXISTS (1);",
+            "SELECT * FROM t WHERE a = (SELECT id FROM u;",
+        ] {
+            assert!(
+                Parser::new(tokenize(sql).unwrap()).parse().is_err(),
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_unary_and_binary_minus() {
+        let where_clause = |condition: &str| {
+            let sql = format!("SELECT * FROM t WHERE {};", condition);
+            match Parser::new(tokenize(&sql).unwrap()).parse().unwrap() {
+                Statement::Select { where_clause, .. } => where_clause.unwrap(),
+                other => panic!("Expected Select statement, got {:?}", other),
+            }
+        };
+        let literal = |value: Value| Box::new(Expr::Literal(value));
+        let subtract = |left, right| Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Subtract,
+            right,
+        };
+
+        // A '-' after an operand subtracts; anywhere else it's a sign
+        assert_eq!(
+            where_clause("a = 5-3"),
+            Expr::BinaryOp {
+                left: Box::new(Expr::Column("a".to_string())),
+                op: BinaryOperator::Equals,
+                right: Box::new(subtract(
+                    literal(Value::Integer(5)),
+                    literal(Value::Integer(3))
+                )),
+            }
+        );
+        assert_eq!(
+            where_clause("1- -2 - -a"),
+            subtract(
+                Box::new(subtract(
+                    literal(Value::Integer(1)),
+                    literal(Value::Integer(-2))
+                )),
+                Box::new(Expr::Negate(Box::new(Expr::Column("a".to_string())))),
+            )
+        );
+        assert_eq!(
+            where_clause("-9223372036854775808"),
+            Expr::Literal(Value::BigInt(i64::MIN))
+        );
+        assert_eq!(where_clause("-2.5"), Expr::Literal(Value::Float(-2.5)));
+
+        let sql = "INSERT INTO t VALUES (-1, - 2.5, -2147483649);";
+        match Parser::new(tokenize(sql).unwrap()).parse().unwrap() {
+            Statement::Insert { values, .. } => assert_eq!(
+                values[0],
+                [
+                    Value::Integer(-1),
+                    Value::Float(-2.5),
+                    Value::BigInt(-2_147_483_649)
+                ]
+                .map(Expr::Literal)
+            ),
+            other => panic!("Expected Insert statement, got {:?}", other),
+        }
+
+        for sql in [
+            "SELECT * FROM t WHERE a = 9223372036854775808;",
+            "INSERT INTO t VALUES (-'x');",
+            "INSERT INTO t VALUES (1 - 2);",
+            "SELECT * FROM t WHERE a -;",
+        ] {
+            assert!(
+                Parser::new(tokenize(sql).unwrap()).parse().is_err(),
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_attach() {
+        let parse = |sql: &str| Parser::from_sql(sql).unwrap().parse();
+        for sql in [
+            "ATTACH 'other.hdb' AS other;",
+            "attach database 'other.hdb' as other;",
+        ] {
+            assert_eq!(
+                parse(sql).unwrap(),
+                Statement::Attach {
+                    path: "other.hdb".to_string(),
+                    alias: "other".to_string(),
+                }
+            );
+        }
+        assert_eq!(
+            parse("DETACH DATABASE other;").unwrap(),
+            Statement::Detach {
+                alias: "other".to_string()
+            }
+        );
+        assert!(parse("ATTACH other.hdb AS other;").is_err());
+
+        let Statement::Insert { table_name, .. } =
+            parse("INSERT INTO other.t VALUES (1);").unwrap()
+        else {
+            panic!("not an INSERT");
+        };
+        assert_eq!(table_name, "other.t");
+    }
+
+    #[test]
+    fn test_parse_all() {
+        let sql = "CREATE TABLE t (a INTEGER);\n INSERT INTO t VALUES (1);; SELECT * FROM t;";
+        let statements = Parser::new(tokenize(sql).unwrap()).parse_all().unwrap();
+        assert_eq!(statements.len(), 3);
+        assert!(matches!(statements[0], Statement::CreateTable { .. }));
+        assert!(matches!(statements[1], Statement::Insert { .. }));
+        assert!(matches!(statements[2], Statement::Select { .. }));
+
+        assert!(
+            Parser::new(tokenize(" ; ").unwrap())
+                .parse_all()
+                .unwrap()
+                .is_empty()
+        );
+        let lines: Vec<usize> = Parser::from_sql(sql)
+            .unwrap()
+            .parse_all_with_lines()
+            .unwrap()
+            .into_iter()
+            .map(|(line, _)| line)
+            .collect();
+        assert_eq!(lines, [1, 2, 2]);
+        // Each statement needs its semicolon, the last one too
+        for sql in [
+            "SELECT * FROM t SELECT * FROM u;",
+            "SELECT * FROM t; SELECT * FROM u",
+        ] {
+            assert!(
+                Parser::new(tokenize(sql).unwrap()).parse_all().is_err(),
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn test_errors_say_where() {
+        let error = |sql: &str| {
+            Parser::from_sql(sql)
+                .unwrap()
+                .parse_all()
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("INSERT INTO t VALUES (1 2);"),
+            "Expected ',' or ')' after value at line 1, column 25\n  INSERT INTO t VALUES (1 2);\n                          ^"
+        );
+        assert_eq!(
+            error("SELECT * FROM t;\nSELECT * FROM t WHERE;"),
+            "Expected an expression, found ';' at line 2, column 22\n  SELECT * FROM t WHERE;\n                       ^"
+        );
+        // Past the last token is the end of the input
+        assert_eq!(
+            error("CREATE TABLE t (a INTEGER)"),
+            "Expected Semicolon, found Eof at line 1, column 27\n  CREATE TABLE t (a INTEGER)\n                            ^"
+        );
+
+        // Without the SQL there's nowhere to point
+        let err = Parser::new(tokenize("SELECT * FROM t WHERE;").unwrap())
+            .parse()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Expected an expression, found ';'");
+    }
 }
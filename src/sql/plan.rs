@@ -0,0 +1,262 @@
+//! Query plans. Before a SELECT reads a row, the executor settles how it
+//! will run: where each table's rows come from, how each join matches
+//! them, and what is done with the rows after. A `QueryPlan` holds those
+//! choices, as `optimizer` makes them; running the query follows it, and
+//! EXPLAIN lists it.
+
+use crate::catalog::row::Value;
+use crate::sql::eval::Bound;
+use crate::sql::formatter;
+use crate::sql::join::JoinMethod;
+use crate::sql::parser::{BinaryOperator, Expr, OrderBy, Statement};
+use crate::sql::select::SelectPlan;
+use std::ops;
+
+/// Where a table's rows come from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access {
+    /// Decoded from the table's pages
+    Table,
+    /// Decoded from the table's pages, but only the rows whose key in the
+    /// index falls between the bounds, which compare with its first column
+    Index {
+        name: String,
+        /// The index's first column
+        column: String,
+        lower: ops::Bound<Value>,
+        upper: ops::Bound<Value>,
+    },
+    /// The result of the view's query, whose plan's steps these are
+    View(Vec<String>),
+    /// Built from the catalog
+    System,
+    /// Not read at all: a COUNT(*) of every row is the row count the
+    /// catalog keeps
+    RowCount,
+}
+
+/// Reading every row of one table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scan {
+    pub table: String,
+    pub access: Access,
+}
+
+impl Scan {
+    fn describe(&self) -> String {
+        match &self.access {
+            Access::Table => format!("TABLE {}", self.table),
+            Access::Index {
+                name,
+                column,
+                lower,
+                upper,
+            } => format!(
+                "TABLE {} USING INDEX {} ({})",
+                self.table,
+                name,
+                describe_range(column, lower, upper)
+            ),
+            Access::View(_) => format!("VIEW {}", self.table),
+            Access::System => format!("SYSTEM TABLE {}", self.table),
+            Access::RowCount => format!("TABLE {} USING ROW COUNT", self.table),
+        }
+    }
+}
+
+/// A condition, bound to the rows it tests, and as written
+#[derive(Debug, Clone)]
+pub struct Predicate<'a> {
+    pub bound: Bound<'a>,
+    pub expr: Expr,
+}
+
+impl<'a> Predicate<'a> {
+    /// `predicates` ANDed together, or None if there are none
+    pub fn all(predicates: Vec<Predicate<'a>>) -> Option<Predicate<'a>> {
+        predicates.into_iter().reduce(|left, right| Predicate {
+            bound: Bound::BinaryOp {
+                left: Box::new(left.bound),
+                op: BinaryOperator::And,
+                right: Box::new(right.bound),
+            },
+            expr: Expr::BinaryOp {
+                left: Box::new(left.expr),
+                op: BinaryOperator::And,
+                right: Box::new(right.expr),
+            },
+        })
+    }
+}
+
+/// Reading one table of a query: the scan, and what was pushed down to it
+pub struct Source<'a> {
+    pub scan: Scan,
+    /// How many columns its rows hold
+    pub width: usize,
+    /// The conditions on its columns alone, bound to its rows
+    pub filter: Option<Predicate<'a>>,
+    /// Which of its columns the query reads, if it's known; the others
+    /// are left NULL rather than decoded
+    pub read: Option<Vec<bool>>,
+}
+
+/// Joining a table to the rows so far
+pub struct JoinStep<'a> {
+    pub source: Source<'a>,
+    /// ON, bound to the columns of the rows so far followed by the table's
+    pub on: Bound<'a>,
+    pub method: JoinMethod,
+    /// How many columns the rows so far have
+    pub left_width: usize,
+    /// ON as written, less what was pushed down
+    pub condition: Expr,
+}
+
+/// How a SELECT runs: read its table, join the others in order, then
+/// filter, deduplicate, sort and project what's left with `select`
+pub struct QueryPlan<'a> {
+    pub source: Source<'a>,
+    pub joins: Vec<JoinStep<'a>>,
+    pub select: SelectPlan<'a>,
+    /// What's left of WHERE once conditions on one table are pushed down
+    pub filter: Option<Predicate<'a>>,
+    /// ORDER BY as written
+    pub order_by: Vec<OrderBy>,
+}
+
+impl QueryPlan<'_> {
+    /// The steps in the order they run, one line each; a view's own steps
+    /// follow its scan, indented
+    pub fn steps(&self) -> Vec<String> {
+        let mut steps = Vec::new();
+        push_scan(&mut steps, "SCAN", &self.source, String::new());
+        for join in &self.joins {
+            let method = match join.method {
+                JoinMethod::NestedLoop => "NESTED LOOP JOIN",
+                JoinMethod::Hash { .. } => "HASH JOIN",
+            };
+            let on = format!(" ON {}", describe(&join.condition));
+            push_scan(&mut steps, method, &join.source, on);
+        }
+        if let Some(filter) = &self.filter {
+            steps.push(format!("FILTER {}", describe(&filter.expr)));
+        }
+        if self.select.is_distinct() {
+            steps.push("DISTINCT".to_string());
+        }
+        if !self.order_by.is_empty() {
+            let keys: Vec<String> = self
+                .order_by
+                .iter()
+                .map(|key| match key.descending {
+                    true => format!("{} DESC", describe(&key.expr)),
+                    false => describe(&key.expr),
+                })
+                .collect();
+            steps.push(format!("SORT BY {}", keys.join(", ")));
+        }
+        steps.push(format!("RESULT {}", self.select.names().join(", ")));
+        steps
+    }
+}
+
+/// `what` the source - SEARCH rather than SCAN through an index - and what
+/// was pushed down to it, then `rest`, and a view's steps under it
+fn push_scan(steps: &mut Vec<String>, what: &str, source: &Source, rest: String) {
+    let what = match (&source.scan.access, what) {
+        (Access::Index { .. }, "SCAN") => "SEARCH",
+        (Access::RowCount, "SCAN") => "COUNT",
+        _ => what,
+    };
+    let filter = match &source.filter {
+        Some(filter) => format!(" WHERE {}", describe(&filter.expr)),
+        None => String::new(),
+    };
+    steps.push(format!(
+        "{} {}{}{}",
+        what,
+        source.scan.describe(),
+        filter,
+        rest
+    ));
+    if let Access::View(view_steps) = &source.scan.access {
+        steps.extend(view_steps.iter().map(|step| format!("  {}", step)));
+    }
+}
+
+/// An index's bounds as conditions on `column`
+fn describe_range(column: &str, lower: &ops::Bound<Value>, upper: &ops::Bound<Value>) -> String {
+    let value = |value: &Value| formatter::expr(&Expr::Literal(value.clone()));
+    if let (ops::Bound::Included(a), ops::Bound::Included(b)) = (lower, upper)
+        && a == b
+    {
+        return format!("{} = {}", column, value(a));
+    }
+    let mut conditions = Vec::new();
+    match lower {
+        ops::Bound::Included(v) => conditions.push(format!("{} >= {}", column, value(v))),
+        ops::Bound::Excluded(v) => conditions.push(format!("{} > {}", column, value(v))),
+        ops::Bound::Unbounded => {}
+    }
+    match upper {
+        ops::Bound::Included(v) => conditions.push(format!("{} <= {}", column, value(v))),
+        ops::Bound::Excluded(v) => conditions.push(format!("{} < {}", column, value(v))),
+        ops::Bound::Unbounded => {}
+    }
+    conditions.join(" AND ")
+}
+
+/// `expr` as SQL, close to how it was written. Subqueries show only their
+/// table.
+pub fn describe(expr: &Expr) -> String {
+    formatter::expr_with(expr, &subquery)
+}
+
+fn subquery(query: &Statement) -> String {
+    match query {
+        Statement::Select { table_name, .. } => format!("(SELECT ... FROM {})", table_name),
+        _ => "(...)".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::Parser;
+    use crate::sql::tokenizer::tokenize;
+
+    /// The WHERE of `SELECT * FROM t WHERE <condition>`, described
+    fn described(condition: &str) -> String {
+        let sql = format!("SELECT * FROM t WHERE {};", condition);
+        match Parser::new(tokenize(&sql).unwrap()).parse().unwrap() {
+            Statement::Select {
+                where_clause: Some(expr),
+                ..
+            } => describe(&expr),
+            other => panic!("Expected a SELECT with WHERE, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_describe() {
+        for condition in [
+            "a = 1 AND b != 'x'",
+            "a = 1 OR b = 2 AND c = 3",
+            "(a = 1 OR b = 2) AND c = 3",
+            "NOT (a = 1 AND b = 2)",
+            "NOT a = 1",
+            "a - (b - c) > -1",
+            "t.a IS NOT NULL",
+            "a NOT IN (1, 2.5, NULL)",
+            "ABS(a - b) <= $1",
+            "CAST(a AS TEXT) = 'x'",
+        ] {
+            assert_eq!(described(condition), condition);
+        }
+        assert_eq!(
+            described("a IN (SELECT b FROM u WHERE c = 1)"),
+            "a IN (SELECT ... FROM u)"
+        );
+    }
+}
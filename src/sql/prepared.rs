@@ -0,0 +1,236 @@
+//! Prepared statements: SQL parsed once, with `?` and `$N` parameters that
+//! take new values each time it runs. Binding puts the values into a copy
+//! of the parsed statement as literals, so they're never parsed as SQL.
+
+use crate::catalog::row::Value;
+use crate::error::{HozonError, Result};
+use crate::sql::parser::{
+    Assignment, ConflictAction, Expr, OrderBy, Parser, SelectColumns, SelectItem, Statement,
+};
+use crate::sql::tokenizer::Token;
+
+/// A parsed statement waiting for its parameters' values
+#[derive(Debug, Clone)]
+pub struct PreparedStatement {
+    statement: Statement,
+    parameters: usize,
+}
+
+impl PreparedStatement {
+    /// Parse `sql`, which must be one statement
+    pub fn new(sql: &str) -> Result<Self> {
+        let mut parser = Parser::from_sql(sql)?;
+        let statement = parser.parse()?;
+        if parser.peek() != Some(&Token::Eof) {
+            return Err(HozonError::InvalidInput(
+                "Only one statement can be prepared at a time".to_string(),
+            ));
+        }
+        Ok(PreparedStatement {
+            statement,
+            parameters: parser.parameter_count(),
+        })
+    }
+
+    /// How many values `bind` takes: the highest parameter number
+    pub fn parameter_count(&self) -> usize {
+        self.parameters
+    }
+
+    /// The statement with `values[n - 1]` in place of parameter n
+    pub fn bind(&self, values: &[Value]) -> Result<Statement> {
+        if values.len() != self.parameters {
+            return Err(HozonError::InvalidInput(format!(
+                "Expected {} parameter values, got {}",
+                self.parameters,
+                values.len()
+            )));
+        }
+        let mut statement = self.statement.clone();
+        bind_statement(&mut statement, values);
+        Ok(statement)
+    }
+}
+
+fn bind_statement(statement: &mut Statement, values: &[Value]) {
+    match statement {
+        Statement::Insert {
+            values: rows,
+            on_conflict,
+            returning,
+            ..
+        } => {
+            rows.iter_mut()
+                .flatten()
+                .for_each(|expr| bind_expr(expr, values));
+            if let Some(on_conflict) = on_conflict
+                && let ConflictAction::Update(assignments) = &mut on_conflict.action
+            {
+                bind_assignments(assignments, values);
+            }
+            bind_columns(returning.as_mut(), values);
+        }
+        Statement::Update {
+            assignments,
+            where_clause,
+            returning,
+            ..
+        } => {
+            bind_assignments(assignments, values);
+            where_clause
+                .iter_mut()
+                .for_each(|expr| bind_expr(expr, values));
+            bind_columns(returning.as_mut(), values);
+        }
+        Statement::Delete {
+            where_clause,
+            returning,
+            ..
+        } => {
+            where_clause
+                .iter_mut()
+                .for_each(|expr| bind_expr(expr, values));
+            bind_columns(returning.as_mut(), values);
+        }
+        Statement::Select {
+            columns,
+            where_clause,
+            order_by,
+            joins,
+            ..
+        } => {
+            bind_columns(Some(columns), values);
+            where_clause
+                .iter_mut()
+                .for_each(|expr| bind_expr(expr, values));
+            for OrderBy { expr, .. } in order_by {
+                bind_expr(expr, values);
+            }
+            for join in joins {
+                bind_expr(&mut join.on, values);
+            }
+        }
+        Statement::Explain(statement) => bind_statement(statement, values),
+        // The parser rejects parameters in views, and these have no others
+        Statement::CreateTable { .. }
+        | Statement::CreateIndex { .. }
+        | Statement::DropIndex { .. }
+        | Statement::CreateView { .. }
+        | Statement::Pragma { .. }
+        | Statement::Checkpoint
+        | Statement::Attach { .. }
+        | Statement::Detach { .. }
+        | Statement::Analyze { .. }
+        | Statement::Savepoint { .. }
+        | Statement::Release { .. }
+        | Statement::RollbackTo { .. } => {}
+    }
+}
+
+fn bind_assignments(assignments: &mut [Assignment], values: &[Value]) {
+    for assignment in assignments {
+        bind_expr(&mut assignment.value, values);
+    }
+}
+
+fn bind_columns(columns: Option<&mut SelectColumns>, values: &[Value]) {
+    if let Some(SelectColumns::Specific(items)) = columns {
+        for item in items {
+            if let SelectItem::Expr { expr, .. } = item {
+                bind_expr(expr, values);
+            }
+        }
+    }
+}
+
+fn bind_expr(expr: &mut Expr, values: &[Value]) {
+    match expr {
+        Expr::Parameter(n) => *expr = Expr::Literal(values[*n - 1].clone()),
+        Expr::Literal(_) | Expr::Column(_) | Expr::CountAll => {}
+        Expr::Not(inner) | Expr::Negate(inner) => bind_expr(inner, values),
+        Expr::IsNull { expr, .. } | Expr::Cast { expr, .. } => bind_expr(expr, values),
+        Expr::InList { expr, list, .. } => {
+            bind_expr(expr, values);
+            list.iter_mut().for_each(|item| bind_expr(item, values));
+        }
+        Expr::InSubquery { expr, query, .. } => {
+            bind_expr(expr, values);
+            bind_statement(query, values);
+        }
+        Expr::BinaryOp { left, right, .. } => {
+            bind_expr(left, values);
+            bind_expr(right, values);
+        }
+        Expr::Function { args, .. } => args.iter_mut().for_each(|arg| bind_expr(arg, values)),
+        Expr::Subquery(query) | Expr::Exists(query) => bind_statement(query, values),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parameter_numbering() {
+        // A bare ? takes the number after the highest so far
+        let prepared =
+            PreparedStatement::new("SELECT * FROM t WHERE a = ? AND b = $3 OR c = ?;").unwrap();
+        assert_eq!(prepared.parameter_count(), 4);
+        let prepared = PreparedStatement::new("SELECT * FROM t WHERE a = $2 OR b = $1;").unwrap();
+        assert_eq!(prepared.parameter_count(), 2);
+        let prepared = PreparedStatement::new("SELECT * FROM t;").unwrap();
+        assert_eq!(prepared.parameter_count(), 0);
+    }
+
+    #[test]
+    fn test_bind() {
+        let prepared = PreparedStatement::new(
+            "UPDATE t SET a = $2 WHERE b IN (SELECT b FROM u WHERE c = $1) RETURNING a - ?;",
+        )
+        .unwrap();
+        let text = |s: &str| Value::Text(s.to_string());
+        let bound = prepared
+            .bind(&[text("x"), Value::Integer(7), Value::Null])
+            .unwrap();
+        let Statement::Update {
+            assignments,
+            where_clause: Some(Expr::InSubquery { query, .. }),
+            returning: Some(SelectColumns::Specific(returning)),
+            ..
+        } = bound
+        else {
+            panic!("Expected an UPDATE with an IN subquery, got {:?}", bound);
+        };
+        assert_eq!(assignments[0].value, Expr::Literal(Value::Integer(7)));
+        assert!(matches!(
+            *query,
+            Statement::Select {
+                where_clause: Some(Expr::BinaryOp { ref right, .. }),
+                ..
+            } if **right == Expr::Literal(text("x"))
+        ));
+        assert!(matches!(
+            &returning[0],
+            SelectItem::Expr { expr: Expr::BinaryOp { right, .. }, .. }
+                if **right == Expr::Literal(Value::Null)
+        ));
+
+        assert!(prepared.bind(&[text("x")]).is_err());
+        assert!(
+            prepared
+                .bind(&[text("x"), text("y"), text("z"), text("w")])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_prepare_rejects() {
+        for sql in [
+            "SELECT * FROM t; SELECT * FROM u;",
+            "CREATE VIEW v AS SELECT * FROM t WHERE a = ?;",
+            "SELECT * FROM t WHERE a = $0;",
+        ] {
+            assert!(PreparedStatement::new(sql).is_err(), "{}", sql);
+        }
+    }
+}
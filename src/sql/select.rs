@@ -0,0 +1,531 @@
+//! The row-at-a-time part of SELECT. A `Scope` says which columns the rows
+//! being queried hold and what they're called; a `SelectPlan` binds the
+//! result columns and ORDER BY to it once, then takes the rows that passed
+//! WHERE one by one and finishes with the sorted, deduplicated result - or,
+//! for `SELECT COUNT(*)`, with how many there were.
+
+use crate::catalog::ordering;
+use crate::catalog::row::{Row, Value};
+use crate::catalog::schema::IdentifierCase;
+use crate::error::{HozonError, Result};
+use crate::sql::eval::{self, Binder, Bound, Correlated, Subquery};
+use crate::sql::executor::{ExecutionResult, Executor};
+use crate::sql::parser::{Expr, OrderBy, SelectColumns, SelectItem, Statement};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+/// The columns of the rows a query works on, in order
+#[derive(Debug, Clone)]
+pub struct Scope {
+    columns: Vec<ScopeColumn>,
+}
+
+#[derive(Debug, Clone)]
+struct ScopeColumn {
+    /// The table or view the column comes from
+    table: String,
+    name: String,
+    /// rowid or an `excluded` column: `*` leaves it out, and a bare name
+    /// only finds it when no other column matches
+    hidden: bool,
+}
+
+impl Scope {
+    /// The columns of `table`, followed by its rowid if it has one
+    pub fn table(table: &str, names: Vec<String>, has_rowid: bool) -> Self {
+        let column = |name, hidden| ScopeColumn {
+            table: table.to_string(),
+            name,
+            hidden,
+        };
+        let mut columns: Vec<ScopeColumn> = names.into_iter().map(|n| column(n, false)).collect();
+        if has_rowid {
+            columns.push(column("rowid".to_string(), true));
+        }
+        Scope { columns }
+    }
+
+    /// The same columns, all hidden
+    pub fn hidden(mut self) -> Scope {
+        for column in &mut self.columns {
+            column.hidden = true;
+        }
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    pub fn has_table(&self, table: &str, case: IdentifierCase) -> bool {
+        self.columns.iter().any(|c| case.matches(&c.table, table))
+    }
+
+    /// The columns of `self`, then those of `other`, as a join's rows hold them
+    pub fn join(mut self, other: Scope) -> Scope {
+        self.columns.extend(other.columns);
+        self
+    }
+
+    /// The position of the column `name` (`column` or `table.column`)
+    /// refers to. A real column named rowid wins over the rowid.
+    pub fn resolve(&self, name: &str, case: IdentifierCase) -> Result<usize> {
+        self.find(name, case)?.ok_or_else(|| self.missing(name))
+    }
+
+    /// `resolve`, but None if no column matches
+    fn find(&self, name: &str, case: IdentifierCase) -> Result<Option<usize>> {
+        let (table, column) = split(name);
+        let find = |hidden: bool| -> Vec<usize> {
+            self.columns
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| {
+                    c.hidden == hidden
+                        && table.is_none_or(|t| case.matches(&c.table, t))
+                        && match hidden {
+                            false => case.matches(&c.name, column),
+                            true => c.name.eq_ignore_ascii_case(column),
+                        }
+                })
+                .map(|(i, _)| i)
+                .collect()
+        };
+
+        let mut found = find(false);
+        if found.is_empty() {
+            found = find(true);
+        }
+        match found[..] {
+            [position] => Ok(Some(position)),
+            [] => Ok(None),
+            _ => Err(HozonError::ColumnNotFound(format!(
+                "Column '{}' is ambiguous; qualify it with its table",
+                name
+            ))),
+        }
+    }
+
+    fn missing(&self, name: &str) -> HozonError {
+        let (table, column) = split(name);
+        HozonError::ColumnNotFound(format!(
+            "Column '{}' does not exist in {}",
+            column,
+            self.describe(table)
+        ))
+    }
+
+    /// What `*` (or `table.*`) expands to: names and positions
+    fn star(&self, table: Option<&str>, case: IdentifierCase) -> Result<Vec<(String, usize)>> {
+        if let Some(table) = table
+            && !self.has_table(table, case)
+        {
+            return Err(HozonError::InvalidStatement(format!(
+                "Table '{}' is not part of the query",
+                table
+            )));
+        }
+        Ok(self
+            .columns
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| !c.hidden && table.is_none_or(|t| case.matches(&c.table, t)))
+            .map(|(i, c)| (c.name.clone(), i))
+            .collect())
+    }
+
+    /// "table 'users'", or "tables 'users', 'orders'" for a join
+    fn describe(&self, table: Option<&str>) -> String {
+        let mut tables: Vec<&str> = match table {
+            Some(table) => vec![table],
+            None => self.columns.iter().map(|c| c.table.as_str()).collect(),
+        };
+        tables.dedup();
+        match tables[..] {
+            [table] => format!("table '{}'", table),
+            _ => format!("tables '{}'", tables.join("', '")),
+        }
+    }
+}
+
+/// `table.column` split, or `column` with no table
+fn split(name: &str) -> (Option<&str>, &str) {
+    match name.split_once('.') {
+        Some((table, column)) => (Some(table), column),
+        None => (None, name),
+    }
+}
+
+/// Where a query's expressions are bound: its scope, how names match, the
+/// executor its subqueries run on, and the row of the query it's a
+/// subquery of
+pub struct Context<'a> {
+    pub scope: &'a Scope,
+    pub case: IdentifierCase,
+    pub executor: &'a Executor,
+    pub outer: Option<&'a Outer<'a>>,
+}
+
+/// The row of an enclosing query, as a subquery sees it. Names the
+/// subquery's own columns don't match are looked up here, then further
+/// out.
+pub struct Outer<'a> {
+    scope: &'a Scope,
+    case: IdentifierCase,
+    /// The row's values; None while the subquery is first bound, when they
+    /// read as NULL
+    values: Option<&'a [Value]>,
+    /// Positions the subquery has referred to
+    used: RefCell<Vec<usize>>,
+    parent: Option<&'a Outer<'a>>,
+}
+
+impl Outer<'_> {
+    fn value(&self, name: &str) -> Result<Option<Value>> {
+        match self.scope.find(name, self.case)? {
+            Some(position) => {
+                self.used.borrow_mut().push(position);
+                Ok(Some(
+                    self.values
+                        .map_or(Value::Null, |values| values[position].clone()),
+                ))
+            }
+            None => match self.parent {
+                Some(parent) => parent.value(name),
+                None => Ok(None),
+            },
+        }
+    }
+}
+
+impl<'a> Binder<'a> for Context<'a> {
+    fn column(&self, name: &str) -> Result<Bound<'a>> {
+        if let Some(position) = self.scope.find(name, self.case)? {
+            return Ok(Bound::Column(position));
+        }
+        match self.outer {
+            Some(outer) => outer.value(name)?.map(Bound::Literal),
+            None => None,
+        }
+        .ok_or_else(|| self.scope.missing(name))
+    }
+
+    /// Runs the subquery once to see whether it refers to this query's row;
+    /// if not, that run's rows are its result
+    fn subquery(&self, query: &Statement) -> Result<(usize, Subquery<'a>)> {
+        let outer = Outer {
+            scope: self.scope,
+            case: self.case,
+            values: None,
+            used: RefCell::default(),
+            parent: self.outer,
+        };
+        let (columns, rows) = rows_of(self.executor.query_within(query.clone(), Some(&outer))?);
+        let mut positions = outer.used.into_inner();
+        if positions.is_empty() {
+            return Ok((columns.len(), Subquery::Rows(rows)));
+        }
+        positions.sort_unstable();
+        positions.dedup();
+        let correlated = CorrelatedQuery {
+            query: query.clone(),
+            scope: self.scope.clone(),
+            case: self.case,
+            executor: self.executor,
+            parent: self.outer,
+            positions,
+            cache: RefCell::default(),
+        };
+        Ok((columns.len(), Subquery::Correlated(Rc::new(correlated))))
+    }
+}
+
+fn rows_of(result: ExecutionResult) -> (Vec<String>, Vec<Row>) {
+    match result {
+        ExecutionResult::Rows { columns, rows } => (columns, rows),
+        _ => unreachable!("SELECT returns rows"),
+    }
+}
+
+/// A subquery that refers to the enclosing query's row
+struct CorrelatedQuery<'a> {
+    query: Statement,
+    /// The enclosing query's
+    scope: Scope,
+    case: IdentifierCase,
+    executor: &'a Executor,
+    parent: Option<&'a Outer<'a>>,
+    /// The enclosing row's columns it refers to
+    positions: Vec<usize>,
+    /// Rows by the encoded values of those columns, so rows that agree on
+    /// them share a run
+    cache: RefCell<HashMap<Vec<u8>, Vec<Row>>>,
+}
+
+impl fmt::Debug for CorrelatedQuery<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CorrelatedQuery")
+            .field("query", &self.query)
+            .field("positions", &self.positions)
+            .finish()
+    }
+}
+
+impl Correlated for CorrelatedQuery<'_> {
+    fn rows(&self, column: &dyn Fn(usize) -> Value) -> Result<Vec<Row>> {
+        let mut values = vec![Value::Null; self.scope.len()];
+        for &position in &self.positions {
+            values[position] = column(position);
+        }
+        let key = Row::new(self.positions.iter().map(|&p| values[p].clone()).collect()).to_bytes();
+        if let Some(rows) = self.cache.borrow().get(&key) {
+            return Ok(rows.clone());
+        }
+
+        let outer = Outer {
+            scope: &self.scope,
+            case: self.case,
+            values: Some(&values),
+            used: RefCell::default(),
+            parent: self.parent,
+        };
+        let (_, rows) = rows_of(
+            self.executor
+                .query_within(self.query.clone(), Some(&outer))?,
+        );
+        self.cache.borrow_mut().insert(key, rows.clone());
+        Ok(rows)
+    }
+}
+
+/// A SELECT's clauses bound to a scope
+pub struct SelectPlan<'a> {
+    names: Vec<String>,
+    /// The result columns, over the scope
+    projected: Vec<Bound<'a>>,
+    /// ORDER BY keys, each with whether it sorts descending
+    sort: Vec<(Bound<'a>, bool)>,
+    distinct: bool,
+    /// `SELECT COUNT(*)`: the result is one row, the number of rows accepted
+    count: bool,
+}
+
+impl<'a> SelectPlan<'a> {
+    pub fn new(
+        context: &Context<'a>,
+        select_columns: SelectColumns,
+        order_by: Vec<OrderBy>,
+        distinct: bool,
+    ) -> Result<Self> {
+        let items = match select_columns {
+            SelectColumns::All => vec![SelectItem::Wildcard(None)],
+            SelectColumns::Specific(items) => items,
+        };
+        // Its one row needs no sorting, nor deduplicating
+        if let [
+            SelectItem::Expr {
+                expr: Expr::CountAll,
+                name,
+            },
+        ] = &items[..]
+        {
+            return Ok(SelectPlan {
+                names: vec![name.clone()],
+                projected: Vec::new(),
+                sort: Vec::new(),
+                distinct: false,
+                count: true,
+            });
+        }
+        let mut names = Vec::new();
+        let mut projected = Vec::new();
+        for item in items {
+            match item {
+                SelectItem::Wildcard(table) => {
+                    for (name, position) in context.scope.star(table.as_deref(), context.case)? {
+                        names.push(name);
+                        projected.push(Bound::Column(position));
+                    }
+                }
+                SelectItem::Expr { expr, name } => {
+                    projected.push(eval::bind(&expr, context)?);
+                    names.push(name);
+                }
+            }
+        }
+
+        let mut sort = Vec::new();
+        for key in order_by {
+            let (bound, selected) = match key.expr {
+                // A bare integer names a result column by position
+                Expr::Literal(Value::Integer(position)) => {
+                    let index = usize::try_from(position)
+                        .ok()
+                        .filter(|p| (1..=projected.len()).contains(p))
+                        .ok_or_else(|| {
+                            HozonError::InvalidStatement(format!(
+                                "ORDER BY position {} is out of range; the result has {} columns",
+                                position,
+                                projected.len()
+                            ))
+                        })?;
+                    (projected[index - 1].clone(), true)
+                }
+                expr => {
+                    let bound = eval::bind(&expr, context)?;
+                    let selected = matches!(bound, Bound::Column(c)
+                        if projected.iter().any(|p| matches!(p, Bound::Column(s) if *s == c)));
+                    (bound, selected)
+                }
+            };
+            // With DISTINCT a row stands for all its duplicates, so it can
+            // only be sorted by values they share
+            if distinct && !selected {
+                return Err(HozonError::InvalidStatement(
+                    "With SELECT DISTINCT, ORDER BY keys must be selected columns".to_string(),
+                ));
+            }
+            sort.push((bound, key.descending));
+        }
+
+        Ok(SelectPlan {
+            names,
+            projected,
+            sort,
+            distinct,
+            count: false,
+        })
+    }
+
+    /// The result's column names
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn is_distinct(&self) -> bool {
+        self.distinct
+    }
+
+    pub fn is_count(&self) -> bool {
+        self.count
+    }
+
+    /// The positions of the columns the result and sort keys read, or
+    /// None if that isn't known (see `Bound::columns`)
+    pub fn columns(&self) -> Option<Vec<usize>> {
+        let mut columns = Vec::new();
+        for bound in self
+            .projected
+            .iter()
+            .chain(self.sort.iter().map(|(key, _)| key))
+        {
+            columns.extend(bound.columns()?);
+        }
+        Some(columns)
+    }
+
+    /// Add the row whose values `value` returns to `rows`
+    pub fn accept(
+        &self,
+        value: &impl Fn(usize) -> Value,
+        rows: &mut Vec<(Vec<Value>, Row)>,
+    ) -> Result<()> {
+        if self.count {
+            // One row, holding the count so far
+            if rows.is_empty() {
+                rows.push((Vec::new(), Row::new(vec![Value::BigInt(0)])));
+            }
+            let (_, row) = &mut rows[0];
+            *row = Row::new(vec![Value::BigInt(count(row) + 1)]);
+            return Ok(());
+        }
+        let key = self
+            .sort
+            .iter()
+            .map(|(key, _)| key.eval(value))
+            .collect::<Result<Vec<Value>>>()?;
+        let row = Row::new(
+            self.projected
+                .iter()
+                .map(|column| column.eval(value))
+                .collect::<Result<_>>()?,
+        );
+        rows.push((key, row));
+        Ok(())
+    }
+
+    /// The result of a COUNT(*) that found `rows` rows without visiting them
+    pub fn counted(self, rows: usize) -> ExecutionResult {
+        debug_assert!(self.count, "only COUNT(*) counts");
+        ExecutionResult::Rows {
+            columns: self.names,
+            rows: vec![Row::new(vec![Value::BigInt(rows as i64)])],
+        }
+    }
+
+    /// The result of the rows `accept` kept
+    pub fn finish(self, mut rows: Vec<(Vec<Value>, Row)>) -> ExecutionResult {
+        if self.count && rows.is_empty() {
+            return self.counted(0);
+        }
+        if self.distinct {
+            rows = without_duplicates(rows);
+        }
+        ExecutionResult::Rows {
+            columns: self.names,
+            rows: sorted(rows, &self.sort),
+        }
+    }
+}
+
+/// The count in a COUNT(*) result row
+fn count(row: &Row) -> i64 {
+    match row.values()[..] {
+        [Value::BigInt(n)] => n,
+        _ => unreachable!("a COUNT(*) row holds its count"),
+    }
+}
+
+/// A materialized row's value at `position`
+pub fn row_value(row: &Row, position: usize) -> Value {
+    row.get_value(position).cloned().unwrap_or(Value::Null)
+}
+
+/// The first of each set of equal result rows, in scan order. Rows are
+/// keyed by their encoding, so NULLs count as equal here.
+fn without_duplicates(rows: Vec<(Vec<Value>, Row)>) -> Vec<(Vec<Value>, Row)> {
+    let mut seen = HashSet::new();
+    rows.into_iter()
+        .filter(|(_, row)| seen.insert(row.to_bytes()))
+        .collect()
+}
+
+/// `rows` in ORDER BY order, each paired with its values for the keys in
+/// `sort`. The sort is stable, so ties keep scan order.
+fn sorted(mut rows: Vec<(Vec<Value>, Row)>, sort: &[(Bound<'_>, bool)]) -> Vec<Row> {
+    if !sort.is_empty() {
+        rows.sort_by(|(a, _), (b, _)| {
+            a.iter()
+                .zip(b)
+                .zip(sort)
+                .map(|((a, b), (_, descending))| {
+                    let ordering = ordering::total_cmp(a, b);
+                    if *descending {
+                        ordering.reverse()
+                    } else {
+                        ordering
+                    }
+                })
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+    }
+    rows.into_iter().map(|(_, row)| row).collect()
+}
@@ -1,4 +1,6 @@
-use std::io::{self, Error, ErrorKind};
+use crate::catalog::format::blob_literal;
+use crate::error::{HozonError, Result};
+use std::fmt;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
@@ -11,26 +13,75 @@ pub enum Token {
     Insert,
     Into,
     Values,
+    Pragma,
+    Checkpoint,
+    Analyze,
+    Primary,
+    Key,
+    Not,
+    Autoincrement,
+    View,
+    As,
+    Order,
+    By,
+    Asc,
+    Desc,
+    Distinct,
+    Join,
+    Inner,
+    On,
+    In,
+    Is,
+    Replace,
+    Ignore,
+    Conflict,
+    Do,
+    Update,
+    Set,
+    Delete,
+    Returning,
+    Cast,
+    Exists,
+    Explain,
+    Savepoint,
+    Release,
+    Rollback,
+    To,
+    Index,
+    Unique,
+    Drop,
+    Attach,
+    Detach,
+    Database,
 
     // Data types
     Integer,
     Text,
     Boolean,
     Null,
+    Float,
+    BigInt,
+    Blob,
 
     // Identifiers and literals
     Identifier(String),    // table names, column names
-    NumberLiteral(i32),    // integer values
+    NumberLiteral(u64),    // integer values, unsigned
+    FloatLiteral(f64),     // decimal values
     StringLiteral(String), // string values
     BoolLiteral(bool),     // true/false
+    BlobLiteral(Vec<u8>),  // X'DEADBEEF'
+    /// `?`, or `$N` for the Nth value bound to a prepared statement
+    Parameter(Option<usize>),
 
     // Symbols
     Comma,      // ,
     Semicolon,  // ;
     Asterisk,   // *
+    Dot,        // .
     LeftParen,  // (
     RightParen, // )
     Equals,     // =
+    Minus,      // -, unary or binary
 
     // Comparison
     LessThan,       // <
@@ -47,158 +98,592 @@ pub enum Token {
     Eof, // End of input
 }
 
-pub fn tokenize(str: &str) -> io::Result<Vec<Token>> {
+impl Token {
+    /// Whether this is a keyword that can still name a table or column, as
+    /// it never starts an expression or stands where a name could
+    pub fn is_non_reserved(&self) -> bool {
+        matches!(
+            self,
+            Token::Key
+                | Token::View
+                | Token::Replace
+                | Token::Ignore
+                | Token::Conflict
+                | Token::Do
+                | Token::Set
+                | Token::To
+                | Token::Index
+                | Token::Database
+                | Token::Autoincrement
+                | Token::Asc
+                | Token::Desc
+                | Token::Pragma
+                | Token::Checkpoint
+                | Token::Analyze
+                | Token::Savepoint
+                | Token::Release
+                | Token::Attach
+                | Token::Detach
+                | Token::Explain
+        )
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Token::Select => "SELECT",
+            Token::From => "FROM",
+            Token::Where => "WHERE",
+            Token::Create => "CREATE",
+            Token::Table => "TABLE",
+            Token::Insert => "INSERT",
+            Token::Into => "INTO",
+            Token::Values => "VALUES",
+            Token::Pragma => "PRAGMA",
+            Token::Checkpoint => "CHECKPOINT",
+            Token::Analyze => "ANALYZE",
+            Token::Primary => "PRIMARY",
+            Token::Key => "KEY",
+            Token::Not => "NOT",
+            Token::Autoincrement => "AUTOINCREMENT",
+            Token::View => "VIEW",
+            Token::As => "AS",
+            Token::Order => "ORDER",
+            Token::By => "BY",
+            Token::Asc => "ASC",
+            Token::Desc => "DESC",
+            Token::Distinct => "DISTINCT",
+            Token::Join => "JOIN",
+            Token::Inner => "INNER",
+            Token::On => "ON",
+            Token::In => "IN",
+            Token::Is => "IS",
+            Token::Replace => "REPLACE",
+            Token::Ignore => "IGNORE",
+            Token::Conflict => "CONFLICT",
+            Token::Do => "DO",
+            Token::Update => "UPDATE",
+            Token::Set => "SET",
+            Token::Delete => "DELETE",
+            Token::Returning => "RETURNING",
+            Token::Cast => "CAST",
+            Token::Exists => "EXISTS",
+            Token::Explain => "EXPLAIN",
+            Token::Savepoint => "SAVEPOINT",
+            Token::Release => "RELEASE",
+            Token::Rollback => "ROLLBACK",
+            Token::To => "TO",
+            Token::Index => "INDEX",
+            Token::Unique => "UNIQUE",
+            Token::Drop => "DROP",
+            Token::Attach => "ATTACH",
+            Token::Detach => "DETACH",
+            Token::Database => "DATABASE",
+            Token::Integer => "INTEGER",
+            Token::Text => "TEXT",
+            Token::Boolean => "BOOLEAN",
+            Token::Null => "NULL",
+            Token::Float => "FLOAT",
+            Token::BigInt => "BIGINT",
+            Token::Blob => "BLOB",
+            Token::Identifier(name) if is_bare(name) => name,
+            Token::Identifier(name) => return write!(f, "\"{}\"", name.replace('"', "\"\"")),
+            Token::NumberLiteral(n) => return write!(f, "{}", n),
+            // Debug always keeps a '.' or exponent, so it reads back as a float
+            Token::FloatLiteral(n) => return write!(f, "{:?}", n),
+            Token::StringLiteral(s) => return write!(f, "'{}'", s.replace('\'', "''")),
+            Token::BoolLiteral(true) => "TRUE",
+            Token::BoolLiteral(false) => "FALSE",
+            Token::BlobLiteral(bytes) => return f.write_str(&blob_literal(bytes)),
+            Token::Parameter(None) => "?",
+            Token::Parameter(Some(n)) => return write!(f, "${}", n),
+            Token::Comma => ",",
+            Token::Semicolon => ";",
+            Token::Asterisk => "*",
+            Token::Dot => ".",
+            Token::LeftParen => "(",
+            Token::RightParen => ")",
+            Token::Equals => "=",
+            Token::Minus => "-",
+            Token::LessThan => "<",
+            Token::GreaterThan => ">",
+            Token::GreaterOrEqual => ">=",
+            Token::LessOrEqual => "<=",
+            Token::NotEquals => "!=",
+            Token::And => "AND",
+            Token::Or => "OR",
+            Token::Eof => "",
+        };
+        f.write_str(text)
+    }
+}
+
+/// Render tokens back into SQL text that tokenizes to the same tokens
+pub fn to_sql(tokens: &[Token]) -> String {
+    let mut sql = String::new();
+    let mut previous = None;
+    // Whether `previous` is a '-' that negates what follows
+    let mut negation = false;
+
+    for token in tokens.iter().filter(|t| **t != Token::Eof) {
+        let tight = matches!(
+            token,
+            Token::Comma | Token::Semicolon | Token::RightParen | Token::Dot
+        ) || matches!(previous, Some(&Token::LeftParen | &Token::Dot))
+            || negation
+            // A function call or CAST
+            || (*token == Token::LeftParen
+                && matches!(previous, Some(Token::Identifier(_) | Token::Cast)));
+        if previous.is_some() && !tight {
+            sql.push(' ');
+        }
+        sql.push_str(&token.to_string());
+        negation = *token == Token::Minus && !previous.is_some_and(ends_operand);
+        previous = Some(token);
+    }
+
+    sql
+}
+
+/// Whether `token` can end an operand, so a '-' after it subtracts
+fn ends_operand(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Identifier(_)
+            | Token::NumberLiteral(_)
+            | Token::FloatLiteral(_)
+            | Token::StringLiteral(_)
+            | Token::BoolLiteral(_)
+            | Token::BlobLiteral(_)
+            | Token::Null
+            | Token::Parameter(_)
+            | Token::RightParen
+    )
+}
+
+/// Whether `name` reads back as the identifier without quotes
+fn is_bare(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+        && keyword(name).is_none()
+}
+
+/// The token a bare word stands for if it's a keyword or boolean literal
+fn keyword(word: &str) -> Option<Token> {
+    Some(match word.to_uppercase().as_str() {
+        "SELECT" => Token::Select,
+        "FROM" => Token::From,
+        "WHERE" => Token::Where,
+        "CREATE" => Token::Create,
+        "TABLE" => Token::Table,
+        "INSERT" => Token::Insert,
+        "INTO" => Token::Into,
+        "VALUES" => Token::Values,
+        "PRAGMA" => Token::Pragma,
+        "CHECKPOINT" => Token::Checkpoint,
+        "ANALYZE" => Token::Analyze,
+        "PRIMARY" => Token::Primary,
+        "KEY" => Token::Key,
+        "NOT" => Token::Not,
+        "AUTOINCREMENT" => Token::Autoincrement,
+        "VIEW" => Token::View,
+        "AS" => Token::As,
+        "ORDER" => Token::Order,
+        "BY" => Token::By,
+        "ASC" => Token::Asc,
+        "DESC" => Token::Desc,
+        "DISTINCT" => Token::Distinct,
+        "JOIN" => Token::Join,
+        "INNER" => Token::Inner,
+        "ON" => Token::On,
+        "IN" => Token::In,
+        "IS" => Token::Is,
+        "REPLACE" => Token::Replace,
+        "IGNORE" => Token::Ignore,
+        "CONFLICT" => Token::Conflict,
+        "DO" => Token::Do,
+        "UPDATE" => Token::Update,
+        "SET" => Token::Set,
+        "DELETE" => Token::Delete,
+        "RETURNING" => Token::Returning,
+        "CAST" => Token::Cast,
+        "EXISTS" => Token::Exists,
+        "EXPLAIN" => Token::Explain,
+        "SAVEPOINT" => Token::Savepoint,
+        "RELEASE" => Token::Release,
+        "ROLLBACK" => Token::Rollback,
+        "TO" => Token::To,
+        "INDEX" => Token::Index,
+        "UNIQUE" => Token::Unique,
+        "DROP" => Token::Drop,
+        "ATTACH" => Token::Attach,
+        "DETACH" => Token::Detach,
+        "DATABASE" => Token::Database,
+        "INTEGER" => Token::Integer,
+        "TEXT" => Token::Text,
+        "BOOLEAN" => Token::Boolean,
+        "NULL" => Token::Null,
+        "FLOAT" | "REAL" => Token::Float,
+        "BIGINT" => Token::BigInt,
+        "BLOB" => Token::Blob,
+        "TRUE" => Token::BoolLiteral(true),
+        "FALSE" => Token::BoolLiteral(false),
+        "AND" => Token::And,
+        "OR" => Token::Or,
+        _ => return None,
+    })
+}
+
+pub fn tokenize(str: &str) -> Result<Vec<Token>> {
+    tokenize_with_offsets(str).map(|(tokens, _)| tokens)
+}
+
+/// `tokenize`, with the byte offset each token starts at in `str`. An
+/// error is located at the start of the token it's about.
+pub fn tokenize_with_offsets(str: &str) -> Result<(Vec<Token>, Vec<usize>)> {
     let mut tokens = Vec::new();
-    let mut chars = str.chars().peekable();
+    let mut offsets = Vec::new();
+    let mut chars = Chars::new(str);
 
-    while let Some(&ch) = chars.peek() {
-        match ch {
-            ' ' | '\n' | '\t' | '\r' => {
-                chars.next(); // skip whitespace
-            }
-            ',' => {
-                tokens.push(Token::Comma);
-                chars.next();
-            }
-            ';' => {
-                tokens.push(Token::Semicolon);
-                chars.next();
-            }
-            '*' => {
-                tokens.push(Token::Asterisk);
-                chars.next();
-            }
-            '(' => {
-                tokens.push(Token::LeftParen);
-                chars.next();
-            }
-            ')' => {
-                tokens.push(Token::RightParen);
+    while chars.peek().is_some() {
+        let offset = chars.offset();
+        next_token(&mut chars, &mut tokens).map_err(|e| located(e, str, offset))?;
+        offsets.resize(tokens.len(), offset);
+    }
+
+    tokens.push(Token::Eof);
+    offsets.push(str.len());
+    Ok((tokens, offsets))
+}
+
+/// Whether `sql` is whole statements: its last token is a ';', not one in a
+/// string, quoted name or comment still open. Anything else that doesn't
+/// scan is passed over, for the parser to report once the SQL runs.
+pub fn ends_statement(sql: &str) -> bool {
+    let mut tokens = Vec::new();
+    let mut chars = Chars::new(sql);
+    while chars.peek().is_some() {
+        let offset = chars.offset();
+        match next_token(&mut chars, &mut tokens) {
+            // Unterminated, so the rest of `sql` is in it
+            Err(_) if chars.peek().is_none() => return false,
+            Err(_) if chars.offset() == offset => {
                 chars.next();
             }
-            '=' => {
-                tokens.push(Token::Equals);
-                chars.next();
+            _ => {}
+        }
+    }
+    tokens.last() == Some(&Token::Semicolon)
+}
+
+/// Scan the token, if any, that starts at the next character
+fn next_token(chars: &mut Chars, tokens: &mut Vec<Token>) -> Result<()> {
+    let Some(&ch) = chars.peek() else {
+        return Ok(());
+    };
+    match ch {
+        ' ' | '\n' | '\t' | '\r' => {
+            chars.next(); // skip whitespace
+        }
+        // -- to the end of the line
+        '-' if chars.starts_with("--") => while chars.next().is_some_and(|c| c != '\n') {},
+        '/' if chars.starts_with("/*") => {
+            chars.next();
+            chars.next();
+            while !chars.starts_with("*/") {
+                if chars.next().is_none() {
+                    return Err(HozonError::Parse("Unterminated comment".to_string()));
+                }
             }
-            '<' => {
-                chars.next(); // consume < 
-                if let Some(&c) = chars.peek() {
-                    if c == '=' {
-                        chars.next(); // consume the equals sign
-                        tokens.push(Token::LessOrEqual);
-                    } else {
-                        tokens.push(Token::LessThan);
-                    }
+            chars.next();
+            chars.next();
+        }
+        ',' => {
+            tokens.push(Token::Comma);
+            chars.next();
+        }
+        ';' => {
+            tokens.push(Token::Semicolon);
+            chars.next();
+        }
+        '*' => {
+            tokens.push(Token::Asterisk);
+            chars.next();
+        }
+        '.' => {
+            tokens.push(Token::Dot);
+            chars.next();
+        }
+        '(' => {
+            tokens.push(Token::LeftParen);
+            chars.next();
+        }
+        ')' => {
+            tokens.push(Token::RightParen);
+            chars.next();
+        }
+        '=' => {
+            tokens.push(Token::Equals);
+            chars.next();
+        }
+        '<' => {
+            chars.next(); // consume < 
+            if let Some(&c) = chars.peek() {
+                if c == '=' {
+                    chars.next(); // consume the equals sign
+                    tokens.push(Token::LessOrEqual);
                 } else {
                     tokens.push(Token::LessThan);
                 }
+            } else {
+                tokens.push(Token::LessThan);
             }
-            '>' => {
-                chars.next();
-                if let Some(&c) = chars.peek() {
-                    if c == '=' {
-                        chars.next(); // consume the equals sign
-                        tokens.push(Token::GreaterOrEqual);
-                    } else {
-                        tokens.push(Token::GreaterThan);
-                    }
+        }
+        '>' => {
+            chars.next();
+            if let Some(&c) = chars.peek() {
+                if c == '=' {
+                    chars.next(); // consume the equals sign
+                    tokens.push(Token::GreaterOrEqual);
                 } else {
                     tokens.push(Token::GreaterThan);
                 }
+            } else {
+                tokens.push(Token::GreaterThan);
             }
-            '!' => {
-                chars.next();
-                if let Some(&c) = chars.peek() {
-                    if c == '=' {
-                        chars.next(); // consume the equals
-                        tokens.push(Token::NotEquals);
+        }
+        '!' => {
+            chars.next();
+            if let Some(&'=') = chars.peek() {
+                chars.next(); // consume the equals
+                tokens.push(Token::NotEquals);
+            }
+        }
+        '\'' => {
+            chars.next(); // consume opening quote
+            let mut literal = String::new();
+
+            loop {
+                match chars.next() {
+                    // A doubled quote is one quote in the string
+                    Some('\'') if chars.peek() == Some(&'\'') => {
+                        chars.next();
+                        literal.push('\'');
+                    }
+                    Some('\'') => break, // closing quote
+                    Some(c) => literal.push(c),
+                    None => {
+                        return Err(HozonError::Parse("Unterminated string literal".to_string()));
                     }
                 }
             }
-            '\'' => {
-                chars.next(); // consume opening quote
-                let mut literal = String::new();
-
-                loop {
-                    match chars.next() {
-                        Some('\'') => break, // closing quote
-                        Some(c) => literal.push(c),
-                        None => {
-                            return Err(Error::new(
-                                ErrorKind::InvalidData,
-                                "Unterminated string literal",
-                            ));
-                        }
+
+            tokens.push(Token::StringLiteral(literal));
+        }
+        // "name" or `name`: any text, even a keyword, with the quote
+        // doubled to include it
+        '"' | '`' => {
+            chars.next(); // consume opening quote
+            let mut name = String::new();
+
+            loop {
+                match chars.next() {
+                    Some(c) if c == ch && chars.peek() == Some(&ch) => {
+                        chars.next();
+                        name.push(c);
+                    }
+                    Some(c) if c == ch => break, // closing quote
+                    Some(c) => name.push(c),
+                    None => {
+                        return Err(HozonError::Parse(
+                            "Unterminated quoted identifier".to_string(),
+                        ));
                     }
                 }
+            }
 
-                tokens.push(Token::StringLiteral(literal));
+            // Qualified names are joined with '.', so one inside a name
+            // would split it
+            if name.is_empty() || name.contains('.') {
+                return Err(HozonError::Parse(format!(
+                    "Invalid quoted identifier: {}{}{}",
+                    ch, name, ch
+                )));
             }
-            '0'..='9' | '-' => {
-                let mut num_string = String::new();
-                while let Some(&c) = chars.peek() {
-                    if c.is_ascii_digit() || c == '-' {
-                        num_string.push(c);
-                        chars.next();
-                    } else {
-                        break;
-                    }
+            tokens.push(Token::Identifier(name));
+        }
+        '-' => {
+            tokens.push(Token::Minus);
+            chars.next();
+        }
+        '?' => {
+            tokens.push(Token::Parameter(None));
+            chars.next();
+        }
+        '$' => {
+            chars.next(); // consume $
+            let mut digits = String::new();
+            while let Some(&c) = chars.peek()
+                && c.is_ascii_digit()
+            {
+                digits.push(c);
+                chars.next();
+            }
+            match digits.parse::<usize>() {
+                Ok(n) if n >= 1 => tokens.push(Token::Parameter(Some(n))),
+                _ => {
+                    return Err(HozonError::Parse(format!("Invalid parameter: ${}", digits)));
                 }
+            }
+        }
+        '0'..='9' => {
+            let mut num_string = String::new();
+            let mut is_float = false;
+            while let Some(&c) = chars.peek() {
+                let exponent_sign = matches!(c, '+' | '-') && num_string.ends_with(['e', 'E']);
+                if c.is_ascii_digit() || exponent_sign {
+                    num_string.push(c);
+                    chars.next();
+                } else if c == '.' || c == 'e' || c == 'E' {
+                    is_float = true;
+                    num_string.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
 
-                let value = num_string.parse::<i32>().map_err(|e| {
-                    Error::new(
-                        ErrorKind::InvalidData,
-                        format!("Invalid integer literal: {}", e),
-                    )
-                })?;
+            if is_float {
+                let value = num_string
+                    .parse::<f64>()
+                    .map_err(|e| HozonError::Parse(format!("Invalid decimal literal: {}", e)))?;
+                tokens.push(Token::FloatLiteral(value));
+            } else {
+                // Whether it fits a column type depends on any '-'
+                // before it, which the parser sees
+                let value = num_string
+                    .parse::<u64>()
+                    .map_err(|e| HozonError::Parse(format!("Invalid integer literal: {}", e)))?;
                 tokens.push(Token::NumberLiteral(value));
             }
-            'a'..='z' | 'A'..='Z' | '_' => {
-                let mut word = String::new();
-                while let Some(&c) = chars.peek() {
-                    if c.is_alphanumeric() || c == '_' {
-                        word.push(c);
-                        chars.next();
-                    } else {
-                        break;
+        }
+        'x' | 'X' if chars.clone().nth(1) == Some('\'') => {
+            chars.next(); // consume X
+            chars.next(); // consume opening quote
+            let mut hex = String::new();
+
+            loop {
+                match chars.next() {
+                    Some('\'') => break,
+                    Some(c) => hex.push(c),
+                    None => {
+                        return Err(HozonError::Parse("Unterminated blob literal".to_string()));
                     }
                 }
+            }
 
-                let word_upper = word.to_uppercase();
-
-                let token = match word_upper.as_str() {
-                    "SELECT" => Token::Select,
-                    "FROM" => Token::From,
-                    "WHERE" => Token::Where,
-                    "CREATE" => Token::Create,
-                    "TABLE" => Token::Table,
-                    "INSERT" => Token::Insert,
-                    "INTO" => Token::Into,
-                    "VALUES" => Token::Values,
-                    "INTEGER" => Token::Integer,
-                    "TEXT" => Token::Text,
-                    "BOOLEAN" => Token::Boolean,
-                    "NULL" => Token::Null,
-                    "TRUE" => Token::BoolLiteral(true),
-                    "FALSE" => Token::BoolLiteral(false),
-                    "AND" => Token::And,
-                    "OR" => Token::Or,
-                    _ => Token::Identifier(word),
-                };
-
-                tokens.push(token);
+            if !hex.len().is_multiple_of(2) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(HozonError::Parse(format!(
+                    "Invalid blob literal: X'{}'",
+                    hex
+                )));
             }
-            _ => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    format!("Unexpected character: {}", ch),
-                ));
+            let bytes = (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                .collect();
+            tokens.push(Token::BlobLiteral(bytes));
+        }
+        'a'..='z' | 'A'..='Z' | '_' => {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' {
+                    word.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
             }
+
+            let token = keyword(&word).unwrap_or(Token::Identifier(word));
+            tokens.push(token);
+        }
+        _ => {
+            return Err(HozonError::Parse(format!("Unexpected character: {}", ch)));
         }
     }
+    Ok(())
+}
 
-    tokens.push(Token::Eof);
-    Ok(tokens)
+/// The characters of SQL text, tracking how far in they are
+#[derive(Clone)]
+struct Chars<'a> {
+    rest: &'a str,
+    next: Option<char>,
+    len: usize,
+}
+
+impl<'a> Chars<'a> {
+    fn new(str: &'a str) -> Self {
+        Chars {
+            rest: str,
+            next: str.chars().next(),
+            len: str.len(),
+        }
+    }
+
+    fn peek(&self) -> Option<&char> {
+        self.next.as_ref()
+    }
+
+    /// Whether the characters from the next one on start with `prefix`
+    fn starts_with(&self, prefix: &str) -> bool {
+        self.rest.starts_with(prefix)
+    }
+
+    /// The byte offset of the next character
+    fn offset(&self) -> usize {
+        self.len - self.rest.len()
+    }
+}
+
+impl Iterator for Chars<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.next?;
+        self.rest = &self.rest[c.len_utf8()..];
+        self.next = self.rest.chars().next();
+        Some(c)
+    }
+}
+
+/// `error` with where in `source` it happened: the line and column of
+/// byte `offset`, and that line with a caret under it
+pub fn located(error: HozonError, source: &str, offset: usize) -> HozonError {
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[offset..]
+        .find('\n')
+        .map_or(source.len(), |i| offset + i);
+    let line = source[..offset].matches('\n').count() + 1;
+    let before = &source[line_start..offset];
+    // Tabs stay tabs so the caret lines up however they're shown
+    let indent: String = before
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    HozonError::Parse(format!(
+        "{} at line {}, column {}\n  {}\n  {}^",
+        error,
+        line,
+        before.chars().count() + 1,
+        source[line_start..line_end].trim_end_matches('\r'),
+        indent
+    ))
 }
 
 #[cfg(test)]
@@ -277,7 +762,83 @@ mod tests {
         let sql = "VALUES (-42);";
         let tokens = tokenize(sql).unwrap();
 
-        assert_eq!(tokens[2], Token::NumberLiteral(-42));
+        assert_eq!(tokens[2], Token::Minus);
+        assert_eq!(tokens[3], Token::NumberLiteral(42));
+
+        // The tokenizer only sees magnitudes; range is the parser's to check
+        let tokens = tokenize("1700000000000 -9223372036854775808").unwrap();
+        assert_eq!(tokens[0], Token::NumberLiteral(1_700_000_000_000));
+        assert_eq!(tokens[1], Token::Minus);
+        assert_eq!(tokens[2], Token::NumberLiteral(1 << 63));
+        assert!(tokenize("18446744073709551616").is_err());
+    }
+
+    #[test]
+    fn test_minus_between_operands() {
+        let minus = |sql: &str| tokenize(sql).unwrap();
+        assert_eq!(
+            minus("5-3"),
+            [
+                Token::NumberLiteral(5),
+                Token::Minus,
+                Token::NumberLiteral(3),
+                Token::Eof
+            ]
+        );
+        assert_eq!(
+            minus("1- -2"),
+            [
+                Token::NumberLiteral(1),
+                Token::Minus,
+                Token::Minus,
+                Token::NumberLiteral(2),
+                Token::Eof
+            ]
+        );
+        assert_eq!(
+            minus("a-b"),
+            [
+                Token::Identifier("a".to_string()),
+                Token::Minus,
+                Token::Identifier("b".to_string()),
+                Token::Eof
+            ]
+        );
+
+        // Binary minus is spaced, a negation isn't
+        assert_eq!(to_sql(&minus("5-3")), "5 - 3");
+        // Written out spaced, the two don't start a comment
+        assert_eq!(to_sql(&minus("1- -2")), "1 - -2");
+        assert_eq!(to_sql(&minus("(-a)-(-b)")), "(-a) - (-b)");
+        assert_eq!(to_sql(&minus("x = - 1")), "x = -1");
+    }
+
+    #[test]
+    fn test_comments() {
+        let sql = "SELECT a -- the first\n, '--not' /* two\n lines */ FROM t--;\n;";
+        assert_eq!(
+            tokenize(sql).unwrap(),
+            [
+                Token::Select,
+                Token::Identifier("a".to_string()),
+                Token::Comma,
+                Token::StringLiteral("--not".to_string()),
+                Token::From,
+                Token::Identifier("t".to_string()),
+                Token::Semicolon,
+                Token::Eof
+            ]
+        );
+        assert_eq!(
+            tokenize("1--2").unwrap(),
+            [Token::NumberLiteral(1), Token::Eof]
+        );
+
+        let err = tokenize("SELECT 1; /* never closed *").unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Unterminated comment at line 1, column 11")
+        );
     }
 
     #[test]
@@ -288,6 +849,22 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_ends_statement() {
+        assert!(ends_statement("SELECT 1;"));
+        assert!(ends_statement("SELECT 1; SELECT 2;\n"));
+        assert!(ends_statement("SELECT 1; -- one;"));
+        assert!(ends_statement("SELECT 'a;\nb';"));
+        assert!(ends_statement("SELECT + 1;"));
+        assert!(!ends_statement("SELECT 1"));
+        assert!(!ends_statement("SELECT 1; SELECT 2"));
+        assert!(!ends_statement("SELECT 'a;\n"));
+        assert!(!ends_statement("SELECT \"a;\n"));
+        assert!(!ends_statement("SELECT 1 /* a;\n"));
+        assert!(!ends_statement("SELECT 1; /* a;"));
+        assert!(!ends_statement(""));
+    }
+
     #[test]
     fn test_boolean_literals() {
         let sql = "VALUES (true, false);";
@@ -310,4 +887,106 @@ mod tests {
         assert_eq!(tokens[5], Token::And);
         assert_eq!(tokens[6], Token::Or);
     }
+
+    #[test]
+    fn test_to_sql_round_trips() {
+        let sql = "SELECT users.id, UPPER(name) FROM users WHERE name = 'Al' AND (n >= -3);";
+        let tokens = tokenize(sql).unwrap();
+        assert_eq!(to_sql(&tokens), sql);
+        assert_eq!(tokenize(&to_sql(&tokens)).unwrap(), tokens);
+    }
+
+    #[test]
+    fn test_tokenize_escaped_quotes() {
+        let tokens = tokenize("'it''s' ''''").unwrap();
+        assert_eq!(tokens[0], Token::StringLiteral("it's".to_string()));
+        assert_eq!(tokens[1], Token::StringLiteral("'".to_string()));
+        assert_eq!(to_sql(&tokens), "'it''s' ''''");
+        assert!(tokenize("'it''s").is_err());
+    }
+
+    #[test]
+    fn test_tokenize_float_literals() {
+        let tokens = tokenize("1.5 -0.25 2e3 1.5E-2 4e+1 7").unwrap();
+        assert_eq!(tokens[0], Token::FloatLiteral(1.5));
+        assert_eq!(tokens[1], Token::Minus);
+        assert_eq!(tokens[2], Token::FloatLiteral(0.25));
+        assert_eq!(tokens[3], Token::FloatLiteral(2000.0));
+        assert_eq!(tokens[4], Token::FloatLiteral(0.015));
+        assert_eq!(tokens[5], Token::FloatLiteral(40.0));
+        assert_eq!(tokens[6], Token::NumberLiteral(7));
+
+        assert_eq!(tokenize(&to_sql(&tokens)).unwrap(), tokens);
+        assert!(tokenize("1.2.3").is_err());
+    }
+
+    #[test]
+    fn test_tokenize_blob_literals() {
+        let tokens = tokenize("X'DEADbeef' x'' xy").unwrap();
+        assert_eq!(tokens[0], Token::BlobLiteral(vec![0xDE, 0xAD, 0xBE, 0xEF]));
+        assert_eq!(tokens[1], Token::BlobLiteral(Vec::new()));
+        assert_eq!(tokens[2], Token::Identifier("xy".to_string()));
+        assert_eq!(to_sql(&tokens[..1]), "X'DEADBEEF'");
+
+        assert!(tokenize("X'ABC'").is_err());
+        assert!(tokenize("X'GG'").is_err());
+        assert!(tokenize("X'AB").is_err());
+    }
+
+    #[test]
+    fn test_tokenize_quoted_identifiers() {
+        let tokens =
+            tokenize(r#"SELECT "user table"."select", `Mixed Case`, "say ""hi""" FROM t;"#)
+                .unwrap();
+        assert_eq!(tokens[1], Token::Identifier("user table".to_string()));
+        assert_eq!(tokens[2], Token::Dot);
+        assert_eq!(tokens[3], Token::Identifier("select".to_string()));
+        assert_eq!(tokens[5], Token::Identifier("Mixed Case".to_string()));
+        assert_eq!(tokens[7], Token::Identifier("say \"hi\"".to_string()));
+
+        // Quoted only where a bare word wouldn't read back the same
+        assert_eq!(
+            to_sql(&tokens),
+            r#"SELECT "user table"."select", "Mixed Case", "say ""hi""" FROM t;"#
+        );
+        assert_eq!(tokenize(&to_sql(&tokens)).unwrap(), tokens);
+        assert_eq!(to_sql(&tokenize("\"plain\"").unwrap()), "plain");
+
+        assert!(tokenize("\"unterminated").is_err());
+        assert!(tokenize("\"\"").is_err());
+        assert!(tokenize("\"a.b\"").is_err());
+    }
+
+    #[test]
+    fn test_tokenize_parameters() {
+        let tokens = tokenize("a = ? AND b IN ($2, $10)").unwrap();
+        assert_eq!(tokens[2], Token::Parameter(None));
+        assert_eq!(tokens[7], Token::Parameter(Some(2)));
+        assert_eq!(tokens[9], Token::Parameter(Some(10)));
+        assert_eq!(to_sql(&tokens), "a = ? AND b IN ($2, $10)");
+        assert_eq!(to_sql(&tokenize("$1-?").unwrap()), "$1 - ?");
+
+        assert!(tokenize("$").is_err());
+        assert!(tokenize("$0").is_err());
+        assert!(tokenize("$x").is_err());
+    }
+
+    #[test]
+    fn test_errors_say_where() {
+        let (tokens, offsets) = tokenize_with_offsets("SELECT 'é',\n  x ;").unwrap();
+        assert_eq!(tokens.len(), offsets.len());
+        assert_eq!(offsets, [0, 7, 11, 15, 17, 18]);
+
+        let err = tokenize("SELECT a,\n\tb # c;").unwrap_err();
+        assert!(matches!(err, HozonError::Parse(_)), "{:?}", err);
+        assert_eq!(
+            err.to_string(),
+            "Unexpected character: # at line 2, column 4\n  \tb # c;\n  \t  ^"
+        );
+        let err = tokenize("VALUES ('abc").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unterminated string literal at line 1, column 9\n  VALUES ('abc\n          ^"
+        );
+    }
 }
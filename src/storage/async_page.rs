@@ -0,0 +1,137 @@
+//! Async access to a PageManager for use inside tokio services.
+//!
+//! Page I/O and fsyncs still happen on blocking file calls, but they run on
+//! tokio's blocking thread pool so they never stall the async workers.
+
+use crate::error::Result;
+use crate::storage::page::{PAGE_SIZE, PageId, PageManager};
+use std::io;
+use std::sync::{Arc, RwLock};
+
+/// Run `f` on the blocking pool and wait for it without blocking the runtime
+pub(crate) async fn blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// A PageManager shared between tasks. Reads may run concurrently; anything
+/// that changes storage waits for exclusive access.
+#[derive(Debug, Clone)]
+pub struct AsyncPageManager {
+    inner: Arc<RwLock<PageManager>>,
+}
+
+impl AsyncPageManager {
+    pub async fn open(path: &str) -> Result<Self> {
+        let path = path.to_string();
+        let page_manager = blocking(move || PageManager::new(&path)).await?;
+        Ok(Self::from_page_manager(page_manager))
+    }
+
+    pub fn from_page_manager(page_manager: PageManager) -> Self {
+        AsyncPageManager {
+            inner: Arc::new(RwLock::new(page_manager)),
+        }
+    }
+
+    /// Run `f` with shared access on the blocking pool
+    pub async fn with<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&PageManager) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let inner = self.inner.clone();
+        blocking(move || f(&inner.read().unwrap())).await
+    }
+
+    /// Run `f` with exclusive access on the blocking pool
+    pub async fn with_mut<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut PageManager) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let inner = self.inner.clone();
+        blocking(move || f(&mut inner.write().unwrap())).await
+    }
+
+    pub async fn read_page(&self, page_id: PageId) -> Result<[u8; PAGE_SIZE]> {
+        self.with(move |pm| pm.read_page(page_id)).await
+    }
+
+    pub async fn write_page(&self, page_id: PageId, data: Vec<u8>) -> Result<()> {
+        self.with_mut(move |pm| pm.write_page(page_id, &data)).await
+    }
+
+    pub async fn write_at(&self, page_id: PageId, offset: usize, data: Vec<u8>) -> Result<()> {
+        self.with_mut(move |pm| pm.write_at(page_id, offset, &data))
+            .await
+    }
+
+    pub async fn allocate_page(&self) -> Result<PageId> {
+        self.with_mut(|pm| pm.allocate_page()).await
+    }
+
+    pub async fn commit(&self) -> Result<()> {
+        self.with_mut(|pm| pm.commit()).await
+    }
+
+    pub async fn sync(&self) -> Result<()> {
+        self.with_mut(|pm| pm.sync()).await
+    }
+
+    pub async fn checkpoint(&self) -> Result<()> {
+        self.with_mut(|pm| pm.checkpoint()).await
+    }
+
+    pub fn num_pages(&self) -> u32 {
+        self.inner.read().unwrap().num_pages()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::page::PAGE_DATA_START;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_async_read_write() {
+        runtime().block_on(async {
+            let pm = AsyncPageManager::from_page_manager(PageManager::new_in_memory().unwrap());
+            pm.allocate_page().await.unwrap();
+            let page_id = pm.allocate_page().await.unwrap();
+
+            pm.write_at(page_id, PAGE_DATA_START, b"async".to_vec())
+                .await
+                .unwrap();
+            pm.sync().await.unwrap();
+
+            let page = pm.read_page(page_id).await.unwrap();
+            assert_eq!(&page[PAGE_DATA_START..PAGE_DATA_START + 5], b"async");
+            assert_eq!(pm.num_pages(), 3);
+        });
+    }
+
+    #[test]
+    fn test_async_open_file() {
+        let _ = std::fs::remove_file("test_async_open.db");
+
+        runtime().block_on(async {
+            let pm = AsyncPageManager::open("test_async_open.db").await.unwrap();
+            assert_eq!(pm.num_pages(), 1);
+            assert!(pm.read_page(5).await.is_err());
+        });
+
+        let _ = std::fs::remove_file("test_async_open.db");
+    }
+}
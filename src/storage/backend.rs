@@ -0,0 +1,395 @@
+use crate::error::{HozonError, Result};
+use crate::storage::doublewrite::DoubleWriteBuffer;
+use crate::storage::page::{PAGE_SIZE, PageId};
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Raw page storage underneath a PageManager.
+///
+/// Backends only move whole pages around; the header, metadata and ownership
+/// records are handled by PageManager, so the catalog and executor never need
+/// to know which backend they're running on.
+///
+/// Reads take `&self` so PageManager can serve several reader threads at once
+/// under a shared lock; anything that changes storage takes `&mut self`.
+pub trait StorageBackend: Debug + Send + Sync {
+    /// Read page `page_id` into `buf`
+    fn read_page(&self, page_id: PageId, buf: &mut [u8; PAGE_SIZE]) -> Result<()>;
+
+    /// Write `buf` as page `page_id`. Durability is only promised after `sync`
+    fn write_page(&mut self, page_id: PageId, buf: &[u8; PAGE_SIZE]) -> Result<()>;
+
+    /// Overwrite `data.len()` bytes of page `page_id` starting at `offset`.
+    ///
+    /// The default reads, patches and rewrites the whole page; backends that
+    /// can write a byte range in place should override it.
+    fn write_at(&mut self, page_id: PageId, offset: usize, data: &[u8]) -> Result<()> {
+        let mut buf = [0u8; PAGE_SIZE];
+        self.read_page(page_id, &mut buf)?;
+        buf[offset..offset + data.len()].copy_from_slice(data);
+        self.write_page(page_id, &buf)
+    }
+
+    /// Grow (or shrink) storage to exactly `num_pages` pages
+    fn allocate(&mut self, num_pages: u32) -> Result<()>;
+
+    /// Flush everything written so far to durable storage
+    fn sync(&mut self) -> Result<()>;
+
+    /// Number of pages currently stored (0 for a brand new database)
+    fn num_pages(&self) -> Result<u32>;
+
+    /// Whether data outlives the process (and so needs an owner record)
+    fn is_persistent(&self) -> bool {
+        true
+    }
+}
+
+/// Pages stored in a single database file, locked for exclusive use.
+///
+/// Writes go through a double-write buffer (see `storage::doublewrite`) and
+/// reach the file in batches when synced, so a crash can't leave a torn page.
+#[derive(Debug)]
+pub struct FileBackend {
+    file: File,
+    double_write: DoubleWriteBuffer,
+}
+
+impl FileBackend {
+    /// Open or create `path` and take an exclusive lock on it
+    pub fn open(path: &str) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        // try to acquire lock before touching the header, so two processes
+        // racing to create the same file can't both initialize it
+        acquire_lock(&file, Path::new(path), false)?;
+
+        // Finish whatever batch a crash interrupted before anyone reads a page
+        let mut file = file;
+        let mut double_write = DoubleWriteBuffer::open(path)?;
+        double_write.recover(&mut file)?;
+
+        Ok(FileBackend { file, double_write })
+    }
+}
+
+/// Take an advisory lock (flock / LockFileEx) on the database file: an
+/// exclusive one to write it, or one `shared` with other readers.
+///
+/// The lock belongs to the open file handle, so the OS releases it when the
+/// backend is dropped or the process dies - nothing is left behind on disk.
+fn acquire_lock(file: &File, path: &Path, shared: bool) -> Result<()> {
+    let locked = match shared {
+        true => file.try_lock_shared(),
+        false => file.try_lock(),
+    };
+    match locked {
+        Ok(()) => Ok(()),
+        Err(TryLockError::WouldBlock) => {
+            // Another handle holds the lock - database is already open
+            let mut header = [0u8; PAGE_SIZE];
+            let mut reader = file;
+            let holder = match reader.read_exact(&mut header) {
+                Ok(()) => match crate::storage::page::read_owner(&header) {
+                    Some(owner) => format!(" by PID {} (since {})", owner.pid, owner.since),
+                    None => String::new(),
+                },
+                Err(_) => String::new(),
+            };
+            Err(HozonError::Locked(format!(
+                "Database is already in use{}: {}",
+                holder,
+                path.display()
+            )))
+        }
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+impl StorageBackend for FileBackend {
+    fn read_page(&self, page_id: PageId, buf: &mut [u8; PAGE_SIZE]) -> Result<()> {
+        // Positional read: no shared cursor, so readers don't need exclusive access
+        read_exact_at(&self.file, buf, (page_id as u64) * (PAGE_SIZE as u64))?;
+        self.double_write.overlay(page_id, buf);
+        Ok(())
+    }
+
+    fn write_page(&mut self, page_id: PageId, buf: &[u8; PAGE_SIZE]) -> Result<()> {
+        self.write_at(page_id, 0, buf)
+    }
+
+    fn write_at(&mut self, page_id: PageId, offset: usize, data: &[u8]) -> Result<()> {
+        self.double_write.push(page_id, offset, data);
+        if self.double_write.is_full() {
+            self.double_write.flush(&mut self.file)?;
+        }
+        Ok(())
+    }
+
+    fn allocate(&mut self, num_pages: u32) -> Result<()> {
+        self.file.set_len((num_pages as u64) * (PAGE_SIZE as u64))?;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        // flush syncs the file, but a bare allocate still needs one
+        if self.double_write.is_empty() {
+            self.file.sync_all()?;
+            return Ok(());
+        }
+        Ok(self.double_write.flush(&mut self.file)?)
+    }
+
+    fn num_pages(&self) -> Result<u32> {
+        Ok((self.file.metadata()?.len() / PAGE_SIZE as u64) as u32)
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            n => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Elsewhere (WASI) reads seek the shared handle, which is only safe
+// because those targets run one thread
+#[cfg(not(any(unix, windows)))]
+fn read_exact_at(mut file: &File, buf: &mut [u8], offset: u64) -> Result<()> {
+    use std::io::{Seek, SeekFrom};
+
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)?;
+    Ok(())
+}
+
+impl Drop for FileBackend {
+    fn drop(&mut self) {
+        // Keep the scratch file if anything is left that recovery might need
+        if self.double_write.flush(&mut self.file).is_ok() {
+            let _ = self.double_write.remove();
+        }
+    }
+}
+
+/// A database file opened only to be read. Other readers can have it open
+/// too, but not a FileBackend, so nothing changes it underneath them.
+#[derive(Debug)]
+pub struct ReadOnlyFileBackend {
+    file: File,
+}
+
+impl ReadOnlyFileBackend {
+    /// Open `path`, which must already exist, and take a shared lock on it
+    pub fn open(path: &str) -> Result<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(HozonError::NotFound(format!(
+                    "Database file '{}' does not exist",
+                    path
+                )));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        acquire_lock(&file, Path::new(path), true)?;
+
+        // Only a writer can replay what a crash left in the double-write buffer
+        if fs::metadata(format!("{}-dwb", path)).is_ok_and(|meta| meta.len() > 0) {
+            return Err(HozonError::ReadOnly(format!(
+                "'{}' wasn't closed cleanly; open it for writing once to recover it",
+                path
+            )));
+        }
+        Ok(ReadOnlyFileBackend { file })
+    }
+}
+
+/// What a write to a read-only database fails with
+pub(crate) fn read_only_error() -> HozonError {
+    HozonError::ReadOnly("The database was opened read-only".to_string())
+}
+
+impl StorageBackend for ReadOnlyFileBackend {
+    fn read_page(&self, page_id: PageId, buf: &mut [u8; PAGE_SIZE]) -> Result<()> {
+        read_exact_at(&self.file, buf, (page_id as u64) * (PAGE_SIZE as u64))
+    }
+
+    fn write_page(&mut self, _page_id: PageId, _buf: &[u8; PAGE_SIZE]) -> Result<()> {
+        Err(read_only_error())
+    }
+
+    fn write_at(&mut self, _page_id: PageId, _offset: usize, _data: &[u8]) -> Result<()> {
+        Err(read_only_error())
+    }
+
+    fn allocate(&mut self, _num_pages: u32) -> Result<()> {
+        Err(read_only_error())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        // Nothing was written
+        Ok(())
+    }
+
+    fn num_pages(&self) -> Result<u32> {
+        Ok((self.file.metadata()?.len() / PAGE_SIZE as u64) as u32)
+    }
+}
+
+/// Pages kept in a Vec - no file, no lock, gone when dropped
+#[derive(Debug, Default)]
+pub struct MemBackend {
+    pages: Vec<[u8; PAGE_SIZE]>,
+}
+
+impl MemBackend {
+    pub fn new() -> Self {
+        MemBackend { pages: Vec::new() }
+    }
+
+    /// Pages holding a copy of `bytes`, a whole database file's, as one
+    /// fetched or loaded where there's no file system
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if !bytes.len().is_multiple_of(PAGE_SIZE) {
+            return Err(HozonError::InvalidInput(format!(
+                "A database image is a whole number of {}-byte pages, not {} bytes",
+                PAGE_SIZE,
+                bytes.len()
+            )));
+        }
+        Ok(MemBackend {
+            pages: bytes
+                .chunks_exact(PAGE_SIZE)
+                .map(|page| page.try_into().unwrap())
+                .collect(),
+        })
+    }
+}
+
+impl StorageBackend for MemBackend {
+    fn read_page(&self, page_id: PageId, buf: &mut [u8; PAGE_SIZE]) -> Result<()> {
+        let page = self.pages.get(page_id as usize).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "Page beyond end of storage")
+        })?;
+        buf.copy_from_slice(page);
+        Ok(())
+    }
+
+    fn write_page(&mut self, page_id: PageId, buf: &[u8; PAGE_SIZE]) -> Result<()> {
+        if self.pages.len() <= page_id as usize {
+            self.pages.resize(page_id as usize + 1, [0u8; PAGE_SIZE]);
+        }
+        self.pages[page_id as usize].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn write_at(&mut self, page_id: PageId, offset: usize, data: &[u8]) -> Result<()> {
+        let page = self.pages.get_mut(page_id as usize).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "Page beyond end of storage")
+        })?;
+        page[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn allocate(&mut self, num_pages: u32) -> Result<()> {
+        self.pages.resize(num_pages as usize, [0u8; PAGE_SIZE]);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn num_pages(&self) -> Result<u32> {
+        Ok(self.pages.len() as u32)
+    }
+
+    fn is_persistent(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn roundtrip(backend: &mut dyn StorageBackend) {
+        assert_eq!(backend.num_pages().unwrap(), 0);
+
+        backend.allocate(2).unwrap();
+        assert_eq!(backend.num_pages().unwrap(), 2);
+
+        let mut page = [0u8; PAGE_SIZE];
+        page[0..5].copy_from_slice(b"hello");
+        backend.write_page(1, &page).unwrap();
+        backend.sync().unwrap();
+
+        let mut buf = [0u8; PAGE_SIZE];
+        backend.read_page(1, &mut buf).unwrap();
+        assert_eq!(&buf[0..5], b"hello");
+
+        backend.write_at(1, 2, b"LL").unwrap();
+        backend.read_page(1, &mut buf).unwrap();
+        assert_eq!(&buf[0..5], b"heLLo");
+
+        backend.read_page(0, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_file_backend_roundtrip() {
+        let _ = fs::remove_file("test_backend_file.db");
+
+        let mut backend = FileBackend::open("test_backend_file.db").unwrap();
+        roundtrip(&mut backend);
+        assert!(backend.is_persistent());
+
+        drop(backend);
+        let _ = fs::remove_file("test_backend_file.db");
+    }
+
+    #[test]
+    fn test_mem_backend_roundtrip() {
+        let mut backend = MemBackend::new();
+        roundtrip(&mut backend);
+        assert!(!backend.is_persistent());
+    }
+
+    #[test]
+    fn test_file_backend_is_exclusive() {
+        let _ = fs::remove_file("test_backend_lock.db");
+
+        let first = FileBackend::open("test_backend_lock.db").unwrap();
+        let second = FileBackend::open("test_backend_lock.db");
+        assert!(matches!(second, Err(HozonError::Locked(_))));
+
+        drop(first);
+        assert!(FileBackend::open("test_backend_lock.db").is_ok());
+
+        let _ = fs::remove_file("test_backend_lock.db");
+    }
+}
@@ -0,0 +1,568 @@
+//! B-trees holding index entries.
+//!
+//! An entry is a key, one row's values of the indexed columns, and that
+//! row's rowid. Entries are ordered by key under `ordering::total_cmp`, then
+//! by rowid, so equal keys are kept apart and each entry can be found again
+//! to delete it.
+//!
+//! Leaf pages hold entries in order. Interior pages hold children and the
+//! separators between them: `children[i]` has the entries before
+//! `separators[i]` and none before `separators[i - 1]`. The root stays on
+//! the page the tree was created on, so the catalog never has to change
+//! it; when the root splits, its contents move to a new page under it.
+//! Deleting never merges pages, so a tree only shrinks when dropped.
+//!
+//! Page layouts after the page metadata, whose row count is the number of
+//! entries or separators:
+//!
+//! - leaf: each entry as the rowid (i64), then the key as a self-describing
+//!   row (`Row::to_bytes`)
+//! - interior: the first child (u32), then each separator as an entry
+//!   followed by the child after it (u32)
+
+use crate::catalog::ordering::{self, SortKey};
+use crate::catalog::row::{Row, Value};
+use crate::error::{HozonError, Result};
+use crate::storage::page::{
+    OFFSET_PAGE_TYPE, PAGE_DATA_START, PAGE_SIZE, PageId, PageManager, PageMetadata, PageType,
+};
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+/// One row in an index: its key and rowid
+pub type Entry = (SortKey, i64);
+
+/// Bytes a node can take up on its page
+const CAPACITY: usize = PAGE_SIZE - PAGE_DATA_START;
+
+/// The largest encoded entry. A full node plus one such entry splits into
+/// two halves that each fit on a page.
+const MAX_ENTRY: usize = CAPACITY / 4;
+
+enum Node {
+    Leaf(Vec<Entry>),
+    Interior {
+        separators: Vec<Entry>,
+        children: Vec<PageId>,
+    },
+}
+
+impl Node {
+    fn read(page_manager: &PageManager, page_id: PageId) -> Result<Node> {
+        let page = page_manager.read_page(page_id)?;
+        let metadata = PageManager::read_metadata_from_buffer(&page);
+        let truncated = || HozonError::Corruption(format!("Index page {} is truncated", page_id));
+        let data = page
+            .get(PAGE_DATA_START..metadata.last_offset)
+            .ok_or_else(truncated)?;
+        let mut offset = 0;
+        let child = |offset: &mut usize| -> Result<PageId> {
+            let bytes = data.get(*offset..*offset + 4).ok_or_else(truncated)?;
+            *offset += 4;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+
+        match PageManager::page_type_from_buffer(&page) {
+            Some(PageType::Index) => {
+                let mut entries = Vec::with_capacity(metadata.num_rows);
+                for _ in 0..metadata.num_rows {
+                    entries.push(read_entry(data, &mut offset).ok_or_else(truncated)??);
+                }
+                Ok(Node::Leaf(entries))
+            }
+            Some(PageType::IndexInterior) => {
+                let mut separators = Vec::with_capacity(metadata.num_rows);
+                let mut children = vec![child(&mut offset)?];
+                for _ in 0..metadata.num_rows {
+                    separators.push(read_entry(data, &mut offset).ok_or_else(truncated)??);
+                    children.push(child(&mut offset)?);
+                }
+                Ok(Node::Interior {
+                    separators,
+                    children,
+                })
+            }
+            _ => Err(HozonError::Corruption(format!(
+                "Page {} is not an index page",
+                page_id
+            ))),
+        }
+    }
+
+    /// The bytes after the page metadata
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            Node::Leaf(entries) => {
+                for entry in entries {
+                    bytes.extend_from_slice(&encode_entry(entry));
+                }
+            }
+            Node::Interior {
+                separators,
+                children,
+            } => {
+                bytes.extend_from_slice(&children[0].to_le_bytes());
+                for (separator, child) in separators.iter().zip(&children[1..]) {
+                    bytes.extend_from_slice(&encode_entry(separator));
+                    bytes.extend_from_slice(&child.to_le_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    fn page_type(&self) -> PageType {
+        match self {
+            Node::Leaf(_) => PageType::Index,
+            Node::Interior { .. } => PageType::IndexInterior,
+        }
+    }
+
+    fn write(&self, page_manager: &mut PageManager, page_id: PageId) -> Result<()> {
+        let bytes = self.encode();
+        let mut page = [0u8; PAGE_SIZE];
+        page[PAGE_DATA_START..PAGE_DATA_START + bytes.len()].copy_from_slice(&bytes);
+        let num_rows = match self {
+            Node::Leaf(entries) => entries.len(),
+            Node::Interior { separators, .. } => separators.len(),
+        };
+        let metadata = PageMetadata {
+            is_full: false,
+            last_offset: PAGE_DATA_START + bytes.len(),
+            num_rows,
+            free_end: PAGE_SIZE,
+        };
+        PageManager::update_metadata_in_buffer(&mut page, &metadata);
+        page[OFFSET_PAGE_TYPE] = self.page_type() as u8;
+        page_manager.write_page(page_id, &page)
+    }
+
+    /// Split a node too big for its page near the middle byte: the lower
+    /// half, the separator to put above the two, and the upper half
+    fn split(self) -> (Node, Entry, Node) {
+        match self {
+            Node::Leaf(mut entries) => {
+                let mid = middle(&entries).max(1);
+                let upper = entries.split_off(mid);
+                let separator = upper[0].clone();
+                (Node::Leaf(entries), separator, Node::Leaf(upper))
+            }
+            Node::Interior {
+                mut separators,
+                mut children,
+            } => {
+                // The middle separator moves up rather than to either half
+                let mid = middle(&separators);
+                let upper_separators = separators.split_off(mid + 1);
+                let separator = separators.pop().expect("split_off left the middle");
+                let upper_children = children.split_off(mid + 1);
+                (
+                    Node::Interior {
+                        separators,
+                        children,
+                    },
+                    separator,
+                    Node::Interior {
+                        separators: upper_separators,
+                        children: upper_children,
+                    },
+                )
+            }
+        }
+    }
+}
+
+/// The index of the first of `entries` past half their encoded bytes
+fn middle(entries: &[Entry]) -> usize {
+    let sizes: Vec<usize> = entries.iter().map(|e| encode_entry(e).len()).collect();
+    let half = sizes.iter().sum::<usize>() / 2;
+    let mut total = 0;
+    sizes
+        .iter()
+        .position(|size| {
+            total += size;
+            total > half
+        })
+        .unwrap_or(0)
+        .min(entries.len() - 1)
+}
+
+fn encode_entry((key, rowid): &Entry) -> Vec<u8> {
+    let mut bytes = rowid.to_le_bytes().to_vec();
+    bytes.extend_from_slice(&Row::new(key.0.clone()).to_bytes());
+    bytes
+}
+
+/// The entry at `offset` in `data`, moving past it; None if `data` ends
+/// before its rowid
+fn read_entry(data: &[u8], offset: &mut usize) -> Option<Result<Entry>> {
+    let rowid = data.get(*offset..*offset + 8)?;
+    let rowid = i64::from_le_bytes(rowid.try_into().unwrap());
+    Some(
+        Row::from_bytes(&data[*offset + 8..]).map(|(key, consumed)| {
+            *offset += 8 + consumed;
+            (SortKey(key.values().clone()), rowid)
+        }),
+    )
+}
+
+/// Fail if `key` is too large for an index entry, so a caller can check
+/// before it writes anything else
+pub fn check_key(key: &[Value]) -> Result<()> {
+    let len = encode_entry(&(SortKey(key.to_vec()), 0)).len();
+    if len > MAX_ENTRY {
+        return Err(HozonError::InvalidInput(format!(
+            "Index key is too large: {} bytes, the limit is {}",
+            len, MAX_ENTRY
+        )));
+    }
+    Ok(())
+}
+
+/// `key` against `bound`, looking only at the columns `bound` has, so a
+/// bound on the first columns of an index matches every key that starts
+/// with them
+fn compare_prefix(key: &SortKey, bound: &[Value]) -> Ordering {
+    key.0
+        .iter()
+        .zip(bound)
+        .map(|(a, b)| ordering::total_cmp(a, b))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+fn below(lower: Bound<&[Value]>, key: &SortKey) -> bool {
+    match lower {
+        Bound::Unbounded => false,
+        Bound::Included(bound) => compare_prefix(key, bound).is_lt(),
+        Bound::Excluded(bound) => compare_prefix(key, bound).is_le(),
+    }
+}
+
+fn above(upper: Bound<&[Value]>, key: &SortKey) -> bool {
+    match upper {
+        Bound::Unbounded => false,
+        Bound::Included(bound) => compare_prefix(key, bound).is_gt(),
+        Bound::Excluded(bound) => compare_prefix(key, bound).is_ge(),
+    }
+}
+
+/// A B-tree rooted at a fixed page
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BTree {
+    root: PageId,
+}
+
+impl BTree {
+    /// A new, empty tree on a page of its own
+    pub fn create(page_manager: &mut PageManager) -> Result<Self> {
+        let root = page_manager.allocate_page_of_type(PageType::Index)?;
+        Ok(BTree { root })
+    }
+
+    /// The tree whose root is `root`
+    pub fn open(root: PageId) -> Self {
+        BTree { root }
+    }
+
+    pub fn root(&self) -> PageId {
+        self.root
+    }
+
+    pub fn insert(
+        &self,
+        page_manager: &mut PageManager,
+        key: Vec<Value>,
+        rowid: i64,
+    ) -> Result<()> {
+        check_key(&key)?;
+        let entry = (SortKey(key), rowid);
+
+        let Some((separator, upper)) = Self::insert_into(page_manager, self.root, entry)? else {
+            return Ok(());
+        };
+        // The root keeps its page: the lower half moves out from under it
+        let lower = Node::read(page_manager, self.root)?;
+        let lower_page = page_manager.allocate_page_of_type(lower.page_type())?;
+        lower.write(page_manager, lower_page)?;
+        Node::Interior {
+            separators: vec![separator],
+            children: vec![lower_page, upper],
+        }
+        .write(page_manager, self.root)
+    }
+
+    /// Add `entry` under `page_id`. If the page splits, the separator and
+    /// the new page holding the upper half.
+    fn insert_into(
+        page_manager: &mut PageManager,
+        page_id: PageId,
+        entry: Entry,
+    ) -> Result<Option<(Entry, PageId)>> {
+        let node = match Node::read(page_manager, page_id)? {
+            Node::Leaf(mut entries) => {
+                let position = entries.partition_point(|e| *e < entry);
+                entries.insert(position, entry);
+                Node::Leaf(entries)
+            }
+            Node::Interior {
+                mut separators,
+                mut children,
+            } => {
+                let i = separators.partition_point(|s| *s <= entry);
+                match Self::insert_into(page_manager, children[i], entry)? {
+                    Some((separator, page)) => {
+                        separators.insert(i, separator);
+                        children.insert(i + 1, page);
+                        Node::Interior {
+                            separators,
+                            children,
+                        }
+                    }
+                    None => return Ok(None),
+                }
+            }
+        };
+
+        if node.encode().len() <= CAPACITY {
+            node.write(page_manager, page_id)?;
+            return Ok(None);
+        }
+        let (lower, separator, upper) = node.split();
+        let upper_page = page_manager.allocate_page_of_type(upper.page_type())?;
+        upper.write(page_manager, upper_page)?;
+        lower.write(page_manager, page_id)?;
+        Ok(Some((separator, upper_page)))
+    }
+
+    /// Remove the entry of `key` and `rowid`; false if there is none
+    pub fn delete(
+        &self,
+        page_manager: &mut PageManager,
+        key: Vec<Value>,
+        rowid: i64,
+    ) -> Result<bool> {
+        let entry = (SortKey(key), rowid);
+        let mut page_id = self.root;
+        loop {
+            match Node::read(page_manager, page_id)? {
+                Node::Leaf(mut entries) => {
+                    let Ok(position) = entries.binary_search(&entry) else {
+                        return Ok(false);
+                    };
+                    entries.remove(position);
+                    Node::Leaf(entries).write(page_manager, page_id)?;
+                    return Ok(true);
+                }
+                Node::Interior {
+                    separators,
+                    children,
+                } => page_id = children[separators.partition_point(|s| *s <= entry)],
+            }
+        }
+    }
+
+    /// Rowids of the entries whose key starts with `key`, in order
+    pub fn get(&self, page_manager: &PageManager, key: &[Value]) -> Result<Vec<i64>> {
+        let entries = self.range(page_manager, Bound::Included(key), Bound::Included(key))?;
+        Ok(entries.into_iter().map(|(_, rowid)| rowid).collect())
+    }
+
+    /// The entries between `lower` and `upper`, in order. A bound with
+    /// fewer values than the keys compares with their first columns.
+    pub fn range(
+        &self,
+        page_manager: &PageManager,
+        lower: Bound<&[Value]>,
+        upper: Bound<&[Value]>,
+    ) -> Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        Self::collect(page_manager, self.root, lower, upper, &mut entries)?;
+        Ok(entries)
+    }
+
+    /// Every entry, in order
+    pub fn entries(&self, page_manager: &PageManager) -> Result<Vec<Entry>> {
+        self.range(page_manager, Bound::Unbounded, Bound::Unbounded)
+    }
+
+    /// Add the entries under `page_id` between the bounds to `out`; false
+    /// once one past `upper` is seen, as everything after it is too
+    fn collect(
+        page_manager: &PageManager,
+        page_id: PageId,
+        lower: Bound<&[Value]>,
+        upper: Bound<&[Value]>,
+        out: &mut Vec<Entry>,
+    ) -> Result<bool> {
+        match Node::read(page_manager, page_id)? {
+            Node::Leaf(entries) => {
+                for entry in entries {
+                    if above(upper, &entry.0) {
+                        return Ok(false);
+                    }
+                    if !below(lower, &entry.0) {
+                        out.push(entry);
+                    }
+                }
+            }
+            Node::Interior {
+                separators,
+                children,
+            } => {
+                for (i, child) in children.into_iter().enumerate() {
+                    // Everything in the child comes before its separator
+                    if separators.get(i).is_some_and(|s| below(lower, &s.0)) {
+                        continue;
+                    }
+                    if !Self::collect(page_manager, child, lower, upper, out)? {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(n: i32) -> Vec<Value> {
+        // Long enough that a few hundred entries need several levels
+        vec![Value::Integer(n), Value::Text(format!("{:0>40}", n))]
+    }
+
+    fn rowids(entries: Vec<Entry>) -> Vec<i64> {
+        entries.into_iter().map(|(_, rowid)| rowid).collect()
+    }
+
+    #[test]
+    fn test_insert_splits_and_keeps_order() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.allocate_page().unwrap();
+        let tree = BTree::create(&mut pm).unwrap();
+
+        // Out of order, so splits happen all over the tree
+        let numbers: Vec<i32> = (0..600).map(|i| (i * 7919) % 600).collect();
+        for &n in &numbers {
+            tree.insert(&mut pm, key(n), n as i64).unwrap();
+        }
+        assert_eq!(pm.page_type(tree.root()).unwrap(), PageType::IndexInterior);
+        assert!(pm.num_pages() > 10);
+
+        let entries = tree.entries(&pm).unwrap();
+        assert_eq!(rowids(entries), (0..600).collect::<Vec<i64>>());
+        assert_eq!(tree.get(&pm, &key(123)).unwrap(), [123]);
+        assert!(tree.get(&pm, &key(600)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_equal_keys_and_prefixes() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.allocate_page().unwrap();
+        let tree = BTree::create(&mut pm).unwrap();
+        for rowid in (0..300).rev() {
+            tree.insert(&mut pm, key(rowid as i32 % 3), rowid).unwrap();
+        }
+
+        let ones = tree.get(&pm, &key(1)).unwrap();
+        assert_eq!(ones, (1..300).step_by(3).collect::<Vec<i64>>());
+        // The first column alone matches the same entries; 1.0 equals 1
+        assert_eq!(tree.get(&pm, &[Value::Float(1.0)]).unwrap(), ones);
+
+        assert!(tree.delete(&mut pm, key(1), 4).unwrap());
+        assert!(!tree.delete(&mut pm, key(1), 4).unwrap());
+        assert!(!tree.delete(&mut pm, key(2), 4).unwrap());
+        assert_eq!(tree.get(&pm, &key(1)).unwrap().len(), 99);
+    }
+
+    #[test]
+    fn test_delete() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.allocate_page().unwrap();
+        let tree = BTree::create(&mut pm).unwrap();
+        // Twelve rowids under each key, spread over several levels
+        for rowid in 0..600 {
+            tree.insert(&mut pm, key(rowid as i32 % 50), rowid).unwrap();
+        }
+        assert_eq!(pm.page_type(tree.root()).unwrap(), PageType::IndexInterior);
+
+        // Only the entry with both the key and the rowid goes
+        assert!(tree.delete(&mut pm, key(7), 57).unwrap());
+        assert!(!tree.delete(&mut pm, key(7), 57).unwrap());
+        assert!(!tree.delete(&mut pm, key(8), 7).unwrap());
+        assert!(!tree.delete(&mut pm, key(50), 50).unwrap());
+        let sevens = tree.get(&pm, &key(7)).unwrap();
+        assert_eq!(sevens.len(), 11);
+        assert!(!sevens.contains(&57));
+        assert_eq!(tree.get(&pm, &key(8)).unwrap().len(), 12);
+
+        // Every rowid of a key, emptying its run across leaves
+        for rowid in (20..600).step_by(50) {
+            assert!(tree.delete(&mut pm, key(20), rowid).unwrap());
+        }
+        assert!(tree.get(&pm, &key(20)).unwrap().is_empty());
+        let lower = [Value::Integer(19)];
+        let upper = [Value::Integer(21)];
+        let between = rowids(
+            tree.range(
+                &pm,
+                Bound::Included(&lower[..]),
+                Bound::Included(&upper[..]),
+            )
+            .unwrap(),
+        );
+        assert_eq!(between.len(), 24);
+        assert!(between.iter().all(|rowid| rowid % 50 != 20));
+        assert_eq!(tree.entries(&pm).unwrap().len(), 600 - 13);
+    }
+
+    #[test]
+    fn test_range() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.allocate_page().unwrap();
+        let tree = BTree::create(&mut pm).unwrap();
+        for n in 0..400 {
+            tree.insert(&mut pm, key(n), n as i64).unwrap();
+        }
+        for n in (0..400).step_by(2) {
+            assert!(tree.delete(&mut pm, key(n), n as i64).unwrap());
+        }
+
+        // Bounds on the first column alone
+        let range = |lower: Bound<i32>, upper: Bound<i32>| {
+            let lower = lower.map(|n| vec![Value::Integer(n)]);
+            let upper = upper.map(|n| vec![Value::Integer(n)]);
+            let (lower, upper) = (
+                lower.as_ref().map(|v| &v[..]),
+                upper.as_ref().map(|v| &v[..]),
+            );
+            rowids(tree.range(&pm, lower, upper).unwrap())
+        };
+        assert_eq!(
+            range(Bound::Included(100), Bound::Included(110)),
+            [101, 103, 105, 107, 109]
+        );
+        assert_eq!(
+            range(Bound::Excluded(101), Bound::Excluded(107)),
+            [103, 105]
+        );
+        assert_eq!(range(Bound::Unbounded, Bound::Excluded(4)), [1, 3]);
+        assert_eq!(range(Bound::Included(396), Bound::Unbounded), [397, 399]);
+        assert!(range(Bound::Included(110), Bound::Included(100)).is_empty());
+    }
+
+    #[test]
+    fn test_oversized_key_rejected() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.allocate_page().unwrap();
+        let tree = BTree::create(&mut pm).unwrap();
+        let big = vec![Value::Text("x".repeat(MAX_ENTRY))];
+        let err = tree.insert(&mut pm, big, 1).unwrap_err();
+        assert!(matches!(err, HozonError::InvalidInput(_)), "{:?}", err);
+        assert!(tree.entries(&pm).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,60 @@
+//! Background thread that checkpoints the database on a timer.
+//!
+//! A checkpoint syncs the storage: for a file database that flushes the writes
+//! waiting in the double-write buffer into the database file and truncates the
+//! `-dwb` scratch file. It bounds how much work a crash can lose with
+//! `synchronous = OFF`, and keeps the buffer short under `NORMAL`.
+
+use crate::storage::backend::StorageBackend;
+use crate::storage::stats::IoCounters;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+pub(crate) type SharedStorage = Arc<RwLock<Box<dyn StorageBackend>>>;
+
+#[derive(Debug)]
+pub(crate) struct Checkpointer {
+    interval: Duration,
+    // dropping the sender wakes the thread up and tells it to stop
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Checkpointer {
+    pub fn start(storage: SharedStorage, stats: Arc<IoCounters>, interval: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+
+        let handle = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                // A failed checkpoint is retried on the next tick; the
+                // foreground commit reports I/O errors to the caller
+                if let Ok(mut storage) = storage.write()
+                    && storage.sync().is_ok()
+                {
+                    stats.synced();
+                }
+            }
+        });
+
+        Checkpointer {
+            interval,
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+impl Drop for Checkpointer {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
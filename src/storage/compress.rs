@@ -0,0 +1,163 @@
+//! Small LZ77-style codec used for page compression.
+//!
+//! The output is a sequence of tokens:
+//! - `0x00..=0x7F`: literal run, followed by (n + 1) raw bytes
+//! - `0x80..=0xFF`: match of (n & 0x7F) + 4 bytes, followed by a u16 LE
+//!   back-reference distance (1..=65535)
+
+use crate::error::{HozonError, Result};
+
+const MIN_MATCH: usize = 4;
+const MAX_MATCH: usize = 0x7F + MIN_MATCH;
+const MAX_LITERALS: usize = 0x80;
+const MAX_DISTANCE: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 12;
+
+fn hash(bytes: &[u8]) -> usize {
+    let v = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn flush_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LITERALS) {
+        out.push((chunk.len() - 1) as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2);
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let mut literal_start = 0;
+    let mut pos = 0;
+
+    while pos + MIN_MATCH <= input.len() {
+        let h = hash(&input[pos..]);
+        let candidate = table[h];
+        table[h] = pos;
+
+        if candidate != usize::MAX
+            && pos - candidate <= MAX_DISTANCE
+            && input[candidate..candidate + MIN_MATCH] == input[pos..pos + MIN_MATCH]
+        {
+            let mut len = MIN_MATCH;
+            while pos + len < input.len()
+                && len < MAX_MATCH
+                && input[candidate + len] == input[pos + len]
+            {
+                len += 1;
+            }
+
+            flush_literals(&mut out, &input[literal_start..pos]);
+            out.push(0x80 | (len - MIN_MATCH) as u8);
+            out.extend_from_slice(&((pos - candidate) as u16).to_le_bytes());
+
+            pos += len;
+            literal_start = pos;
+        } else {
+            pos += 1;
+        }
+    }
+
+    flush_literals(&mut out, &input[literal_start..]);
+    out
+}
+
+/// Decompress `input`, which must expand to exactly `expected_len` bytes
+pub fn decompress(input: &[u8], expected_len: usize) -> Result<Vec<u8>> {
+    let corrupt = |msg: &str| HozonError::Corruption(format!("Corrupt page: {}", msg));
+
+    let mut out = Vec::with_capacity(expected_len);
+    let mut pos = 0;
+
+    while pos < input.len() {
+        let token = input[pos];
+        pos += 1;
+
+        if token & 0x80 == 0 {
+            let len = token as usize + 1;
+            if pos + len > input.len() {
+                return Err(corrupt("literal run past end of input"));
+            }
+            out.extend_from_slice(&input[pos..pos + len]);
+            pos += len;
+        } else {
+            if pos + 2 > input.len() {
+                return Err(corrupt("truncated match"));
+            }
+            let len = (token & 0x7F) as usize + MIN_MATCH;
+            let distance = u16::from_le_bytes([input[pos], input[pos + 1]]) as usize;
+            pos += 2;
+
+            if distance == 0 || distance > out.len() {
+                return Err(corrupt("match distance out of range"));
+            }
+            // Overlapping copies are allowed (distance < len repeats a pattern)
+            let start = out.len() - distance;
+            for i in 0..len {
+                out.push(out[start + i]);
+            }
+        }
+
+        if out.len() > expected_len {
+            return Err(corrupt("output larger than expected"));
+        }
+    }
+
+    if out.len() != expected_len {
+        return Err(corrupt("output shorter than expected"));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_text() {
+        let input = "Alice,Bob,Charlie,".repeat(100);
+        let compressed = compress(input.as_bytes());
+        assert!(compressed.len() < input.len() / 4);
+
+        let output = decompress(&compressed, input.len()).unwrap();
+        assert_eq!(output, input.as_bytes());
+    }
+
+    #[test]
+    fn test_roundtrip_zeros() {
+        let input = [0u8; 4096];
+        let compressed = compress(&input);
+        assert!(compressed.len() < 128);
+        assert_eq!(decompress(&compressed, input.len()).unwrap(), input);
+    }
+
+    #[test]
+    fn test_roundtrip_incompressible() {
+        // Simple LCG noise - no repeats for the matcher to find
+        let mut state = 12345u32;
+        let input: Vec<u8> = (0..1000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 24) as u8
+            })
+            .collect();
+        let compressed = compress(&input);
+        assert_eq!(decompress(&compressed, input.len()).unwrap(), input);
+    }
+
+    #[test]
+    fn test_roundtrip_short_inputs() {
+        for input in [&b""[..], b"a", b"abc", b"abcd", b"aaaaaaaa"] {
+            let compressed = compress(input);
+            assert_eq!(decompress(&compressed, input.len()).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn test_decompress_rejects_garbage() {
+        assert!(decompress(&[0x80, 0x05, 0x00], 10).is_err()); // match before any output
+        assert!(decompress(&[0x05, b'a'], 6).is_err()); // truncated literal run
+        assert!(decompress(&compress(b"hello"), 6).is_err()); // wrong length
+    }
+}
@@ -0,0 +1,615 @@
+//! Append-only row storage, the alternative to rewriting rows inside pages.
+//!
+//! Rows are appended to numbered segment files in a directory next to the
+//! database. Once the active segment passes the segment size a new one is
+//! started, and once there are more than `max_segments` the live records are
+//! copied into a single fresh segment and the old ones deleted.
+//!
+//! Segment layout: magic (u32), then the id below which every segment is
+//! obsolete (u64), then records of
+//!   payload length (u32), CRC-32 of payload (u32),
+//!   payload: kind (u8), table id (u32), then for a row its bytes, and for
+//!   a replacement the slot it replaces (u32) followed by the new bytes.
+//! A row with no bytes is a deleted row, kept so later slots stay put.
+//! A torn record at the end of the newest segment is cut off on open.
+
+use crate::catalog::row::Row;
+use crate::catalog::schema::Column;
+use crate::error::{HozonError, Result};
+use crate::storage::doublewrite::crc32;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Bytes written to a segment before the next one is started
+pub const DEFAULT_SEGMENT_SIZE: u64 = 1024 * 1024;
+/// Segments kept before they are compacted into one
+pub const DEFAULT_MAX_SEGMENTS: usize = 8;
+
+const SEGMENT_MAGIC: u32 = 0x474C_5A48; // "HZLG" little-endian
+const SEGMENT_HEADER_SIZE: u64 = 12;
+const RECORD_HEADER_SIZE: usize = 8;
+
+const KIND_ROW: u8 = 1;
+// Every earlier row of the table is gone
+const KIND_DROP: u8 = 2;
+// New contents for one of the table's rows
+const KIND_REPLACE: u8 = 3;
+
+/// Where a row's bytes sit
+#[derive(Debug, Clone, Copy)]
+struct RecordLoc {
+    segment: u64,
+    offset: u64,
+    len: u32,
+}
+
+#[derive(Debug)]
+pub struct LogStore {
+    dir: PathBuf,
+    // ids in order; the last one is being appended to
+    segments: Vec<u64>,
+    active: File,
+    active_len: u64,
+    // rows of each table by slot, in insertion order; empty if deleted
+    index: HashMap<u32, Vec<RecordLoc>>,
+    segment_size: u64,
+    max_segments: usize,
+    unsynced: bool,
+}
+
+/// Segment directory for the database at `path`
+pub fn log_dir(path: &str) -> PathBuf {
+    PathBuf::from(format!("{}-log", path))
+}
+
+fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:08}.seg", id))
+}
+
+fn corrupt(id: u64, msg: &str) -> HozonError {
+    HozonError::Corruption(format!("Corrupt log segment {}: {}", id, msg))
+}
+
+/// A whole record: header, then kind, table and `body` as the payload
+fn encode_record(kind: u8, table: u32, body: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(5 + body.len());
+    payload.push(kind);
+    payload.extend_from_slice(&table.to_le_bytes());
+    payload.extend_from_slice(body);
+
+    let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&crc32(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+    record
+}
+
+fn create_segment(path: &Path, replaces_below: u64) -> Result<File> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+    file.write_all(&SEGMENT_MAGIC.to_le_bytes())?;
+    file.write_all(&replaces_below.to_le_bytes())?;
+    Ok(file)
+}
+
+impl LogStore {
+    /// Open the segments in `dir`, creating the directory if needed
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            match path.extension().and_then(|e| e.to_str()) {
+                // A compaction that never finished
+                Some("tmp") => fs::remove_file(&path)?,
+                Some("seg") => {
+                    if let Some(id) = path
+                        .file_stem()
+                        .and_then(|s| s.to_str())
+                        .and_then(|s| s.parse::<u64>().ok())
+                    {
+                        segments.push(id);
+                    }
+                }
+                _ => {}
+            }
+        }
+        segments.sort_unstable();
+
+        // A finished compaction replaces everything numbered below it; those
+        // files are only still here if we crashed before deleting them
+        let mut replaces_below = 0;
+        for &id in &segments {
+            let mut header = [0u8; SEGMENT_HEADER_SIZE as usize];
+            File::open(segment_path(&dir, id))?
+                .read_exact(&mut header)
+                .map_err(|_| corrupt(id, "missing header"))?;
+            if u32::from_le_bytes(header[0..4].try_into().unwrap()) != SEGMENT_MAGIC {
+                return Err(corrupt(id, "bad magic"));
+            }
+            replaces_below =
+                replaces_below.max(u64::from_le_bytes(header[4..12].try_into().unwrap()));
+        }
+        for &id in segments.iter().filter(|&&id| id < replaces_below) {
+            fs::remove_file(segment_path(&dir, id))?;
+        }
+        segments.retain(|&id| id >= replaces_below);
+
+        if segments.is_empty() {
+            create_segment(&segment_path(&dir, 1), 0)?.sync_all()?;
+            segments.push(1);
+        }
+
+        let mut index = HashMap::new();
+        let last = *segments.last().unwrap();
+        let mut active_len = 0;
+        for &id in &segments {
+            let valid_len = Self::replay(&dir, id, &mut index)?;
+            if id == last {
+                active_len = valid_len;
+            }
+        }
+
+        let active = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(segment_path(&dir, last))?;
+        // Drop a torn tail so new records follow the last good one
+        active.set_len(active_len)?;
+
+        Ok(LogStore {
+            dir,
+            segments,
+            active,
+            active_len,
+            index,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            max_segments: DEFAULT_MAX_SEGMENTS,
+            unsynced: false,
+        })
+    }
+
+    /// Index the records of segment `id`, returning where the valid part ends
+    fn replay(dir: &Path, id: u64, index: &mut HashMap<u32, Vec<RecordLoc>>) -> Result<u64> {
+        let data = fs::read(segment_path(dir, id))?;
+        let mut pos = SEGMENT_HEADER_SIZE as usize;
+
+        while pos + RECORD_HEADER_SIZE <= data.len() {
+            let len = u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+            let crc = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap());
+            let start = pos + RECORD_HEADER_SIZE;
+            if len < 5 || start + len > data.len() || crc32(&data[start..start + len]) != crc {
+                break;
+            }
+
+            let payload = &data[start..start + len];
+            let table = u32::from_le_bytes(payload[1..5].try_into().unwrap());
+            match payload[0] {
+                KIND_ROW => index.entry(table).or_default().push(RecordLoc {
+                    segment: id,
+                    offset: (start + 5) as u64,
+                    len: (len - 5) as u32,
+                }),
+                KIND_DROP => {
+                    index.remove(&table);
+                }
+                KIND_REPLACE => {
+                    let slot = payload
+                        .get(5..9)
+                        .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize);
+                    let loc = slot
+                        .and_then(|slot| index.get_mut(&table)?.get_mut(slot))
+                        .ok_or_else(|| corrupt(id, "replacement for a missing row"))?;
+                    *loc = RecordLoc {
+                        segment: id,
+                        offset: (start + 9) as u64,
+                        len: (len - 9) as u32,
+                    };
+                }
+                kind => return Err(corrupt(id, &format!("unknown record kind {}", kind))),
+            }
+            pos = start + len;
+        }
+
+        Ok(pos as u64)
+    }
+
+    pub fn set_segment_size(&mut self, bytes: u64) {
+        self.segment_size = bytes;
+    }
+
+    pub fn set_max_segments(&mut self, segments: usize) {
+        self.max_segments = segments.max(1);
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Append an encoded row (see `Row::encode`) to `table`
+    pub fn append(&mut self, table: u32, row_bytes: &[u8]) -> Result<()> {
+        let offset = self.write_record(KIND_ROW, table, row_bytes)?;
+        self.index.entry(table).or_default().push(RecordLoc {
+            segment: *self.segments.last().unwrap(),
+            offset: offset + 5,
+            len: row_bytes.len() as u32,
+        });
+        self.maybe_roll()
+    }
+
+    /// Give the row of `table` in `slot` (from 0, oldest first) new encoded
+    /// contents, keeping its place in scan order
+    pub fn replace(&mut self, table: u32, slot: usize, row_bytes: &[u8]) -> Result<()> {
+        self.rewrite(table, slot, row_bytes)
+    }
+
+    /// Delete the row of `table` in `slot`; the slot stays taken
+    pub fn delete(&mut self, table: u32, slot: usize) -> Result<()> {
+        self.rewrite(table, slot, &[])
+    }
+
+    fn rewrite(&mut self, table: u32, slot: usize, row_bytes: &[u8]) -> Result<()> {
+        if self
+            .index
+            .get(&table)
+            .and_then(|locs| locs.get(slot))
+            .is_none_or(|loc| loc.len == 0)
+        {
+            return Err(HozonError::NotFound(format!(
+                "Table {} has no row {} in the log",
+                table, slot
+            )));
+        }
+        let record = [&(slot as u32).to_le_bytes()[..], row_bytes].concat();
+        let offset = self.write_record(KIND_REPLACE, table, &record)?;
+        self.index.get_mut(&table).unwrap()[slot] = RecordLoc {
+            segment: *self.segments.last().unwrap(),
+            offset: offset + 9,
+            len: row_bytes.len() as u32,
+        };
+        self.maybe_roll()
+    }
+
+    /// Forget every row of `table`; compaction reclaims the space
+    pub fn drop_table(&mut self, table: u32) -> Result<()> {
+        self.write_record(KIND_DROP, table, &[])?;
+        self.index.remove(&table);
+        self.maybe_roll()
+    }
+
+    /// Live rows of `table`
+    pub fn row_count(&self, table: u32) -> usize {
+        self.index
+            .get(&table)
+            .map_or(0, |locs| locs.iter().filter(|loc| loc.len > 0).count())
+    }
+
+    /// Slots `table` has used, deleted rows included; the next row's slot
+    pub fn slot_count(&self, table: u32) -> usize {
+        self.index.get(&table).map_or(0, Vec::len)
+    }
+
+    /// All live rows of `table`, oldest first
+    pub fn scan(&self, table: u32, columns: &[Column]) -> Result<Vec<Row>> {
+        let mut rows = Vec::with_capacity(self.row_count(table));
+        self.scan_records(table, |_, row_bytes| {
+            rows.push(Row::decode(row_bytes, columns)?.0);
+            Ok(())
+        })?;
+        Ok(rows)
+    }
+
+    /// Visit the slot and encoded bytes of every live row of `table`,
+    /// oldest first
+    pub fn scan_records(
+        &self,
+        table: u32,
+        mut visit: impl FnMut(usize, &[u8]) -> Result<()>,
+    ) -> Result<()> {
+        let locs = match self.index.get(&table) {
+            Some(locs) => locs,
+            None => return Ok(()),
+        };
+
+        let mut open: Option<(u64, File)> = None;
+        for (slot, loc) in locs.iter().enumerate() {
+            if loc.len == 0 {
+                continue;
+            }
+            let file = match &mut open {
+                Some((id, file)) if *id == loc.segment => file,
+                _ => {
+                    let file = File::open(segment_path(&self.dir, loc.segment))?;
+                    &mut open.insert((loc.segment, file)).1
+                }
+            };
+            visit(slot, &Self::read_row(file, loc)?)?;
+        }
+        Ok(())
+    }
+
+    fn read_row(file: &mut File, loc: &RecordLoc) -> Result<Vec<u8>> {
+        let mut row_bytes = vec![0u8; loc.len as usize];
+        file.seek(SeekFrom::Start(loc.offset))?;
+        file.read_exact(&mut row_bytes)?;
+        Ok(row_bytes)
+    }
+
+    /// Append a record to the active segment, returning its payload offset
+    fn write_record(&mut self, kind: u8, table: u32, body: &[u8]) -> Result<u64> {
+        let record = encode_record(kind, table, body);
+
+        self.active.seek(SeekFrom::Start(self.active_len))?;
+        self.active.write_all(&record)?;
+        let offset = self.active_len + RECORD_HEADER_SIZE as u64;
+        self.active_len += record.len() as u64;
+        self.unsynced = true;
+        Ok(offset)
+    }
+
+    fn maybe_roll(&mut self) -> Result<()> {
+        if self.active_len < self.segment_size {
+            return Ok(());
+        }
+
+        // Sealed segments are never written again, so make them durable now
+        self.sync()?;
+        let id = self.segments.last().unwrap() + 1;
+        self.active = create_segment(&segment_path(&self.dir, id), 0)?;
+        self.active_len = SEGMENT_HEADER_SIZE;
+        self.segments.push(id);
+
+        if self.segments.len() > self.max_segments {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the live rows into one new segment and delete the rest
+    pub fn compact(&mut self) -> Result<()> {
+        let id = self.segments.last().unwrap() + 1;
+        let tmp = self.dir.join(format!("{:08}.seg.tmp", id));
+        let mut out = create_segment(&tmp, id)?;
+        let mut out_len = SEGMENT_HEADER_SIZE;
+        let mut index = HashMap::new();
+
+        let mut sources = HashMap::new();
+        for &segment in &self.segments {
+            sources.insert(segment, File::open(segment_path(&self.dir, segment))?);
+        }
+
+        // Replaced rows come out as plain rows in their slot, deleted ones
+        // as rows with no bytes
+        for (&table, locs) in &self.index {
+            let mut new_locs = Vec::with_capacity(locs.len());
+            for loc in locs {
+                let row_bytes = Self::read_row(sources.get_mut(&loc.segment).unwrap(), loc)?;
+                let record = encode_record(KIND_ROW, table, &row_bytes);
+                out.write_all(&record)?;
+                new_locs.push(RecordLoc {
+                    segment: id,
+                    offset: out_len + (RECORD_HEADER_SIZE + 5) as u64,
+                    len: loc.len,
+                });
+                out_len += record.len() as u64;
+            }
+            index.insert(table, new_locs);
+        }
+        drop(sources);
+
+        // Once the rename lands the new segment wins on open, even if the
+        // old segments are still there
+        out.sync_all()?;
+        fs::rename(&tmp, segment_path(&self.dir, id))?;
+        for &segment in &self.segments {
+            fs::remove_file(segment_path(&self.dir, segment))?;
+        }
+
+        self.active = out;
+        self.active_len = out_len;
+        self.segments = vec![id];
+        self.index = index;
+        self.unsynced = false;
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> Result<()> {
+        if self.unsynced {
+            self.active.sync_data()?;
+            self.unsynced = false;
+        }
+        Ok(())
+    }
+
+    /// Copy every segment into `dir`, replacing an earlier copy there
+    pub fn backup_to(&self, dir: impl AsRef<Path>) -> Result<()> {
+        let dir = dir.as_ref();
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+        fs::create_dir(dir)?;
+        for &segment in &self.segments {
+            fs::copy(segment_path(&self.dir, segment), segment_path(dir, segment))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::row::Value;
+    use crate::catalog::schema::DataType;
+
+    fn columns() -> Vec<Column> {
+        vec![
+            Column::new("id", DataType::Integer),
+            Column::new("name", DataType::Text),
+        ]
+    }
+
+    fn row(i: i32) -> Vec<u8> {
+        Row::new(vec![Value::Integer(i), Value::Text(format!("row {}", i))]).encode(&columns())
+    }
+
+    fn fresh(dir: &str) -> LogStore {
+        let _ = fs::remove_dir_all(dir);
+        LogStore::open(dir).unwrap()
+    }
+
+    fn ids(rows: &[Row]) -> Vec<Value> {
+        rows.iter().map(|r| r.values()[0].clone()).collect()
+    }
+
+    #[test]
+    fn test_append_scan_and_reopen() {
+        let mut log = fresh("test_log_reopen");
+        for i in 0..3 {
+            log.append(7, &row(i)).unwrap();
+        }
+        log.append(8, &row(100)).unwrap();
+        log.sync().unwrap();
+        drop(log);
+
+        let log = LogStore::open("test_log_reopen").unwrap();
+        assert_eq!(
+            ids(&log.scan(7, &columns()).unwrap()),
+            (0..3).map(Value::Integer).collect::<Vec<_>>()
+        );
+        assert_eq!(log.row_count(8), 1);
+        assert!(log.scan(9, &columns()).unwrap().is_empty());
+
+        let _ = fs::remove_dir_all("test_log_reopen");
+    }
+
+    #[test]
+    fn test_torn_tail_is_dropped() {
+        let mut log = fresh("test_log_torn");
+        log.append(1, &row(1)).unwrap();
+        log.append(1, &row(2)).unwrap();
+        drop(log);
+
+        // Chop the last record in half
+        let path = segment_path(Path::new("test_log_torn"), 1);
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let mut log = LogStore::open("test_log_torn").unwrap();
+        assert_eq!(
+            ids(&log.scan(1, &columns()).unwrap()),
+            vec![Value::Integer(1)]
+        );
+        // New records start where the good data ends
+        log.append(1, &row(3)).unwrap();
+        drop(log);
+        let log = LogStore::open("test_log_torn").unwrap();
+        assert_eq!(log.row_count(1), 2);
+
+        let _ = fs::remove_dir_all("test_log_torn");
+    }
+
+    #[test]
+    fn test_segments_roll_and_compact() {
+        let mut log = fresh("test_log_compact");
+        log.set_segment_size(128);
+        log.set_max_segments(3);
+
+        for i in 0..40 {
+            log.append(1, &row(i)).unwrap();
+            log.append(2, &row(i)).unwrap();
+        }
+        log.drop_table(2).unwrap();
+        assert!(log.segment_count() <= 3);
+
+        log.compact().unwrap();
+        assert_eq!(log.segment_count(), 1);
+        assert_eq!(fs::read_dir("test_log_compact").unwrap().count(), 1);
+        assert_eq!(
+            ids(&log.scan(1, &columns()).unwrap()),
+            (0..40).map(Value::Integer).collect::<Vec<_>>()
+        );
+        assert_eq!(log.row_count(2), 0);
+        drop(log);
+
+        let log = LogStore::open("test_log_compact").unwrap();
+        assert_eq!(log.row_count(1), 40);
+        assert_eq!(log.row_count(2), 0);
+
+        let _ = fs::remove_dir_all("test_log_compact");
+    }
+
+    #[test]
+    fn test_replace_and_delete_keep_slots() {
+        let mut log = fresh("test_log_replace");
+        for i in 0..4 {
+            log.append(1, &row(i)).unwrap();
+        }
+        log.replace(1, 1, &row(10)).unwrap();
+        log.delete(1, 2).unwrap();
+        assert!(log.replace(1, 4, &row(11)).is_err());
+        assert!(log.delete(1, 2).is_err());
+        let slots = |log: &LogStore| {
+            let mut slots = Vec::new();
+            log.scan_records(1, |slot, _| {
+                slots.push(slot);
+                Ok(())
+            })
+            .unwrap();
+            slots
+        };
+        let expected = vec![Value::Integer(0), Value::Integer(10), Value::Integer(3)];
+        assert_eq!(ids(&log.scan(1, &columns()).unwrap()), expected);
+        assert_eq!(slots(&log), [0, 1, 3]);
+        assert_eq!((log.row_count(1), log.slot_count(1)), (3, 4));
+        drop(log);
+
+        let mut log = LogStore::open("test_log_replace").unwrap();
+        assert_eq!(ids(&log.scan(1, &columns()).unwrap()), expected);
+        log.compact().unwrap();
+        drop(log);
+        let log = LogStore::open("test_log_replace").unwrap();
+        assert_eq!(ids(&log.scan(1, &columns()).unwrap()), expected);
+        assert_eq!(slots(&log), [0, 1, 3]);
+
+        let _ = fs::remove_dir_all("test_log_replace");
+    }
+
+    #[test]
+    fn test_obsolete_segments_removed_on_open() {
+        let mut log = fresh("test_log_obsolete");
+        log.append(1, &row(1)).unwrap();
+        log.compact().unwrap();
+        drop(log);
+
+        // As if the crash came after the rename but before the deletes
+        let stale = segment_path(Path::new("test_log_obsolete"), 1);
+        let mut file = create_segment(&stale, 0).unwrap();
+        let payload = [&[KIND_ROW][..], &1u32.to_le_bytes(), &row(99)].concat();
+        file.write_all(&(payload.len() as u32).to_le_bytes())
+            .unwrap();
+        file.write_all(&crc32(&payload).to_le_bytes()).unwrap();
+        file.write_all(&payload).unwrap();
+        drop(file);
+
+        let log = LogStore::open("test_log_obsolete").unwrap();
+        assert_eq!(
+            ids(&log.scan(1, &columns()).unwrap()),
+            vec![Value::Integer(1)]
+        );
+        assert!(!stale.exists());
+
+        let _ = fs::remove_dir_all("test_log_obsolete");
+    }
+}
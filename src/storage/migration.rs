@@ -0,0 +1,687 @@
+//! In-place upgrades for database files written with an older layout.
+//!
+//! Each migration lifts a file from `from` to `from + 1`. They run in order
+//! when a database is opened, and the header version is bumped and synced after
+//! each one, so an interrupted upgrade resumes from the last finished step.
+//! A migration that is cut short is run again, so it must be safe to repeat;
+//! migrations that rewrite pages one by one record how far they got with
+//! `PageManager::set_migration_progress`, which is reset with each version bump.
+
+use crate::error::{HozonError, Result};
+use crate::storage::log::{self, LogStore};
+use crate::storage::page::{
+    FIRST_DATA_PAGE, FORMAT_VERSION, OFFSET_PAGE_TYPE, PAGE_DATA_START, PAGE_SIZE, PageManager,
+    PageMetadata, PageType, StorageEngine, SyncMode,
+};
+
+pub struct Migration {
+    pub from: u32,
+    pub description: &'static str,
+    pub apply: fn(&mut PageManager) -> Result<()>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        description: "record the format version in the header",
+        // Layout is unchanged; bumping the version field is the whole upgrade
+        apply: |_| Ok(()),
+    },
+    Migration {
+        from: 1,
+        description: "add a page type byte to data page metadata",
+        apply: add_page_types,
+    },
+    Migration {
+        from: 2,
+        description: "widen data page metadata to u32 fields with a free-space pointer",
+        apply: widen_page_metadata,
+    },
+    Migration {
+        from: 3,
+        description: "add an AUTOINCREMENT sequence to each catalog entry",
+        apply: add_table_sequences,
+    },
+    Migration {
+        from: 4,
+        description: "add the last page and row count to each catalog entry",
+        apply: add_table_row_counts,
+    },
+    Migration {
+        from: 5,
+        description: "turn NULL-typed columns into nullable TEXT columns",
+        apply: retype_null_columns,
+    },
+    Migration {
+        from: 6,
+        description: "drop indexes, which had no entries or UNIQUE flag",
+        apply: drop_unfilled_indexes,
+    },
+];
+
+// Metadata layouts of older versions. Earlier migrations must keep using
+// these, not the current constants in `page`.
+const V1_DATA_START: usize = 5;
+const V2_LAST_OFFSET: usize = 1; // u16
+const V2_NUM_ROWS: usize = 3; // u16
+const V2_PAGE_TYPE: usize = 5;
+const V2_DATA_START: usize = 6;
+const V3_NUM_ROWS: usize = 8; // u32
+const CATALOG_PAGE: u32 = 1;
+// Bytes after the schema in a catalog entry
+const V3_ENTRY_TAIL: usize = 4; // first page
+const V4_ENTRY_TAIL: usize = 12; // first page, sequence
+const V5_ENTRY_TAIL: usize = 24; // first page, sequence, last page, row count
+// Column type bytes up to version 5: the type in the low bits, constraint
+// flags in the top three
+const V5_COLUMN_FLAGS: u8 = 0xE0;
+const V5_TYPE_TEXT: u8 = 1;
+const V5_TYPE_NULL: u8 = 3;
+
+fn read_u16(page: &[u8], offset: usize) -> usize {
+    u16::from_le_bytes([page[offset], page[offset + 1]]) as usize
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// Walk a serialized schema (name, column count, then each column's name
+/// and type byte), returning its length and the offset of each column's
+/// type byte. The layout hasn't changed since version 1.
+fn read_schema(bytes: &[u8]) -> Result<(usize, Vec<usize>)> {
+    let truncated = || HozonError::Corruption("Catalog entry is truncated".to_string());
+    let skip_name = |offset: usize| -> Result<usize> {
+        if bytes.len() < offset + 4 {
+            return Err(truncated());
+        }
+        let end = offset + 4 + read_u32(bytes, offset) as usize;
+        if bytes.len() < end {
+            return Err(truncated());
+        }
+        Ok(end)
+    };
+
+    let mut offset = skip_name(0)?;
+    if bytes.len() < offset + 4 {
+        return Err(truncated());
+    }
+    let num_columns = read_u32(bytes, offset);
+    offset += 4;
+
+    let mut type_offsets = Vec::new();
+    for _ in 0..num_columns {
+        offset = skip_name(offset)?;
+        if bytes.len() < offset + 1 {
+            return Err(truncated());
+        }
+        type_offsets.push(offset);
+        offset += 1;
+    }
+    Ok((offset, type_offsets))
+}
+
+/// Version 1 data pages have a 5 byte metadata block with rows right after
+/// it. Shift the rows up one byte to make room for the page type.
+fn add_page_types(page_manager: &mut PageManager) -> Result<()> {
+    rewrite_data_pages(page_manager, |page_id, page| {
+        let last_offset = read_u16(page, V2_LAST_OFFSET);
+        if !(V1_DATA_START..PAGE_SIZE).contains(&last_offset) {
+            return Err(HozonError::Corruption(format!(
+                "Page {} can't be upgraded: rows end at offset {}",
+                page_id, last_offset
+            )));
+        }
+
+        page.copy_within(V1_DATA_START..last_offset, V2_DATA_START);
+        page[V2_PAGE_TYPE] = PageType::TableData as u8;
+        let last_offset = (last_offset + V2_DATA_START - V1_DATA_START) as u16;
+        page[V2_LAST_OFFSET..V2_LAST_OFFSET + 2].copy_from_slice(&last_offset.to_le_bytes());
+        Ok(())
+    })
+}
+
+/// Version 2 metadata is 6 bytes with u16 offsets and counts. Move the page
+/// type next to the flags, widen the fields to u32, add the free-space end
+/// and shift everything after the block up to the new PAGE_DATA_START.
+fn widen_page_metadata(page_manager: &mut PageManager) -> Result<()> {
+    const SHIFT: usize = PAGE_DATA_START - V2_DATA_START;
+
+    rewrite_data_pages(page_manager, |page_id, page| {
+        // Only the tail the shift pushes off the page has to be empty
+        if page[PAGE_SIZE - SHIFT..].iter().any(|&b| b != 0) {
+            return Err(HozonError::Corruption(format!(
+                "Page {} can't be upgraded: less than {} bytes free at the end",
+                page_id, SHIFT
+            )));
+        }
+
+        let old = *page;
+        let metadata = PageMetadata {
+            is_full: old[0] != 0,
+            last_offset: read_u16(&old, V2_LAST_OFFSET) + SHIFT,
+            num_rows: read_u16(&old, V2_NUM_ROWS),
+            free_end: PAGE_SIZE,
+        };
+
+        page.fill(0);
+        page[PAGE_DATA_START..].copy_from_slice(&old[V2_DATA_START..PAGE_SIZE - SHIFT]);
+        // Carried over even if invalid, so read_page still reports the page
+        page[OFFSET_PAGE_TYPE] = old[V2_PAGE_TYPE];
+        PageManager::update_metadata_in_buffer(page, &metadata);
+        Ok(())
+    })
+}
+
+/// Version 3 catalog entries are a schema followed by the u32 first page.
+/// Append a zeroed i64 sequence to each one.
+fn add_table_sequences(page_manager: &mut PageManager) -> Result<()> {
+    rewrite_catalog_entries(page_manager, V3_ENTRY_TAIL, |_, tail| {
+        let mut tail = tail.to_vec();
+        tail.extend_from_slice(&0i64.to_le_bytes());
+        Ok(tail)
+    })
+}
+
+/// Version 4 entries end with the first page and sequence. Append the last
+/// page, which is the first page while tables are one page long, and the
+/// table's row count.
+fn add_table_row_counts(page_manager: &mut PageManager) -> Result<()> {
+    let log = match (page_manager.engine(), page_manager.path()) {
+        (StorageEngine::Log, Some(path)) => Some(LogStore::open(log::log_dir(path))?),
+        _ => None,
+    };
+
+    rewrite_catalog_entries(page_manager, V4_ENTRY_TAIL, |page_manager, tail| {
+        let first_page = read_u32(tail, 0);
+        let row_count = match &log {
+            Some(log) => log.row_count(first_page) as u64,
+            None => {
+                let page = page_manager.read_page_unchecked(first_page)?;
+                read_u32(&page, V3_NUM_ROWS) as u64
+            }
+        };
+
+        let mut tail = tail.to_vec();
+        tail.extend_from_slice(&first_page.to_le_bytes());
+        tail.extend_from_slice(&row_count.to_le_bytes());
+        Ok(tail)
+    })
+}
+
+/// Up to version 5 NULL was a column type, one that only ever held NULL.
+/// Such columns become nullable TEXT, which accepts every row they hold. The
+/// type bytes are patched in place, leaving the views and indexes after the
+/// entries untouched, and patching twice changes nothing.
+fn retype_null_columns(page_manager: &mut PageManager) -> Result<()> {
+    let mut catalog = page_manager.read_page_unchecked(CATALOG_PAGE)?;
+
+    let num_tables = read_u32(&catalog, 0);
+    let mut offset = 4;
+    for _ in 0..num_tables {
+        let (schema_len, type_offsets) = read_schema(&catalog[offset..])?;
+        for type_offset in type_offsets {
+            let type_byte = &mut catalog[offset + type_offset];
+            if *type_byte & !V5_COLUMN_FLAGS == V5_TYPE_NULL {
+                *type_byte = (*type_byte & V5_COLUMN_FLAGS) | V5_TYPE_TEXT;
+            }
+        }
+        offset += schema_len + V5_ENTRY_TAIL;
+        if offset > PAGE_SIZE {
+            return Err(HozonError::Corruption(
+                "Catalog entry runs past the end of the page".to_string(),
+            ));
+        }
+    }
+
+    page_manager.write_page(CATALOG_PAGE, &catalog)?;
+    page_manager.sync()
+}
+
+/// Up to version 6 an index was a catalog entry with an empty root page:
+/// nothing kept entries for it, and the entry had no UNIQUE flag. Such an
+/// index can't be trusted for lookups, so the indexes section is emptied;
+/// CREATE INDEX builds them again. Emptying it twice changes nothing.
+fn drop_unfilled_indexes(page_manager: &mut PageManager) -> Result<()> {
+    let mut catalog = page_manager.read_page_unchecked(CATALOG_PAGE)?;
+    if catalog.iter().all(|&b| b == 0) {
+        return Ok(()); // no tables yet
+    }
+    let past_end =
+        || HozonError::Corruption("Catalog entry runs past the end of the page".to_string());
+
+    let num_tables = read_u32(&catalog, 0);
+    let mut offset = 4;
+    for _ in 0..num_tables {
+        let (schema_len, _) = read_schema(&catalog[offset..])?;
+        offset += schema_len + V5_ENTRY_TAIL;
+        if offset > PAGE_SIZE {
+            return Err(past_end());
+        }
+    }
+
+    // Views, each a name and a query
+    if offset + 4 > PAGE_SIZE {
+        return Ok(()); // ends before the views, so has no indexes
+    }
+    let num_views = read_u32(&catalog, offset);
+    offset += 4;
+    for _ in 0..num_views * 2 {
+        if offset + 4 > PAGE_SIZE {
+            return Err(past_end());
+        }
+        offset += 4 + read_u32(&catalog, offset) as usize;
+    }
+    if offset + 4 > PAGE_SIZE {
+        return Ok(());
+    }
+
+    catalog[offset..].fill(0);
+    page_manager.write_page(CATALOG_PAGE, &catalog)?;
+    page_manager.sync()
+}
+
+/// Rebuild the catalog page, replacing the `tail_len` bytes after each
+/// entry's schema with what `extend` returns for them. The whole page is
+/// written at once and progress 1 marks it done, so a rerun leaves it alone.
+fn rewrite_catalog_entries(
+    page_manager: &mut PageManager,
+    tail_len: usize,
+    mut extend: impl FnMut(&PageManager, &[u8]) -> Result<Vec<u8>>,
+) -> Result<()> {
+    if page_manager.migration_progress()? != 0 {
+        return Ok(());
+    }
+
+    let old = page_manager.read_page_unchecked(CATALOG_PAGE)?;
+    if old.iter().all(|&b| b == 0) {
+        return Ok(()); // no tables yet
+    }
+
+    let num_tables = read_u32(&old, 0);
+    let mut catalog = num_tables.to_le_bytes().to_vec();
+    let mut offset = 4;
+    for _ in 0..num_tables {
+        let (schema_len, _) = read_schema(&old[offset..])?;
+        let tail_start = offset + schema_len;
+        let entry_end = tail_start + tail_len;
+        if entry_end > PAGE_SIZE {
+            return Err(HozonError::Corruption(
+                "Catalog entry runs past the end of the page".to_string(),
+            ));
+        }
+        catalog.extend_from_slice(&old[offset..tail_start]);
+        catalog.extend_from_slice(&extend(page_manager, &old[tail_start..entry_end])?);
+        offset = entry_end;
+    }
+
+    if catalog.len() > PAGE_SIZE {
+        return Err(HozonError::Corruption(
+            "Upgraded catalog no longer fits on one page".to_string(),
+        ));
+    }
+
+    page_manager.write_page(CATALOG_PAGE, &catalog)?;
+    page_manager.set_migration_progress(1)?;
+    page_manager.sync()
+}
+
+/// Apply `rewrite` to every data page, recording progress after each so an
+/// interrupted run picks up where it stopped. Rewriting a page twice would
+/// corrupt it.
+fn rewrite_data_pages(
+    page_manager: &mut PageManager,
+    rewrite: impl Fn(u32, &mut [u8; PAGE_SIZE]) -> Result<()>,
+) -> Result<()> {
+    let first = page_manager.migration_progress()?.max(FIRST_DATA_PAGE);
+
+    for page_id in first..page_manager.num_pages() {
+        let mut page = page_manager.read_page_unchecked(page_id)?;
+        rewrite(page_id, &mut page)?;
+
+        page_manager.write_page(page_id, &page)?;
+        page_manager.set_migration_progress(page_id + 1)?;
+        // One double-write batch, so the page and its marker land together
+        page_manager.sync()?;
+    }
+    Ok(())
+}
+
+/// Bring the database up to FORMAT_VERSION, returning how many steps ran
+pub fn migrate(page_manager: &mut PageManager) -> Result<usize> {
+    run_migrations(page_manager, MIGRATIONS, FORMAT_VERSION)
+}
+
+fn run_migrations(
+    page_manager: &mut PageManager,
+    migrations: &[Migration],
+    target: u32,
+) -> Result<usize> {
+    let mut version = page_manager.format_version()?;
+    let mut applied = 0;
+
+    while version < target {
+        let migration = migrations
+            .iter()
+            .find(|m| m.from == version)
+            .ok_or_else(|| {
+                HozonError::Unsupported(format!("No migration from format version {}", version))
+            })?;
+
+        // Migrations choose their own sync points; under FULL every write
+        // would be flushed on its own
+        let sync_mode = page_manager.sync_mode();
+        page_manager.set_sync_mode(SyncMode::Off)?;
+        let result = (migration.apply)(page_manager);
+        page_manager.set_sync_mode(sync_mode)?;
+
+        result.map_err(|e| {
+            e.context(format!(
+                "Migration from version {} ({}) failed",
+                version, migration.description
+            ))
+        })?;
+
+        version += 1;
+        page_manager.set_format_version(version)?;
+        page_manager.sync()?;
+        applied += 1;
+    }
+
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::schema::{DataType, Schema};
+
+    #[test]
+    fn test_new_database_needs_no_migration() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        assert_eq!(pm.format_version().unwrap(), FORMAT_VERSION);
+        assert_eq!(migrate(&mut pm).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_migrations_run_in_order() {
+        let migrations = [
+            Migration {
+                from: 1,
+                description: "second",
+                apply: |pm| pm.write_page(1, b"second"),
+            },
+            Migration {
+                from: 0,
+                description: "first",
+                apply: |pm| {
+                    pm.allocate_page()?;
+                    pm.write_page(1, b"first")
+                },
+            },
+        ];
+
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.set_format_version(0).unwrap();
+
+        assert_eq!(run_migrations(&mut pm, &migrations, 2).unwrap(), 2);
+        assert_eq!(pm.format_version().unwrap(), 2);
+        assert_eq!(&pm.read_page(1).unwrap()[..6], b"second");
+    }
+
+    #[test]
+    fn test_missing_migration_is_an_error() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.set_format_version(0).unwrap();
+
+        let err = run_migrations(&mut pm, &[], 1).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("No migration from format version 0")
+        );
+    }
+
+    #[test]
+    fn test_failed_migration_keeps_version() {
+        let migrations = [
+            Migration {
+                from: 0,
+                description: "ok",
+                apply: |_| Ok(()),
+            },
+            Migration {
+                from: 1,
+                description: "broken",
+                apply: |_| Err(HozonError::Corruption("boom".to_string())),
+            },
+        ];
+
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.set_format_version(0).unwrap();
+
+        let err = run_migrations(&mut pm, &migrations, 2).unwrap_err();
+        assert!(err.to_string().contains("broken"));
+        // The failure keeps its variant under the added context
+        assert!(matches!(err, HozonError::Corruption(_)), "{:?}", err);
+        // The step that succeeded is kept, the failed one runs again next time
+        assert_eq!(pm.format_version().unwrap(), 1);
+    }
+
+    /// Write `rows` into `page_id` the way a version 1 database laid it out
+    fn write_v1_page(pm: &mut PageManager, page_id: u32, rows: &[u8]) {
+        let mut page = [0u8; PAGE_SIZE];
+        page[1..3].copy_from_slice(&(5 + rows.len() as u16).to_le_bytes());
+        page[3..5].copy_from_slice(&1u16.to_le_bytes());
+        page[5..5 + rows.len()].copy_from_slice(rows);
+        pm.write_page(page_id, &page).unwrap();
+    }
+
+    #[test]
+    fn test_add_page_types_moves_rows() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.allocate_page().unwrap();
+        let page_id = pm.allocate_page().unwrap();
+        write_v1_page(&mut pm, page_id, b"\x01\x07\x00\x00\x00\x00");
+        pm.set_format_version(1).unwrap();
+        assert!(pm.read_page(page_id).is_err()); // untagged
+
+        // Through version 2 and on to the current layout
+        assert_eq!(migrate(&mut pm).unwrap(), (FORMAT_VERSION - 1) as usize);
+
+        let page = pm.read_page(page_id).unwrap();
+        assert_eq!(pm.page_type(page_id).unwrap(), PageType::TableData);
+        assert_eq!(
+            &page[PAGE_DATA_START..PAGE_DATA_START + 6],
+            b"\x01\x07\x00\x00\x00\x00"
+        );
+        let metadata = PageManager::read_metadata_from_buffer(&page);
+        assert_eq!(metadata.last_offset, PAGE_DATA_START + 6);
+        assert_eq!(metadata.num_rows, 1);
+    }
+
+    #[test]
+    fn test_add_page_types_resumes() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.allocate_page().unwrap();
+        pm.allocate_page().unwrap();
+        pm.allocate_page().unwrap();
+        // Page 2 was already moved before the interruption, page 3 wasn't
+        let mut done = [0u8; PAGE_SIZE];
+        done[V2_LAST_OFFSET] = V2_DATA_START as u8;
+        done[V2_PAGE_TYPE] = PageType::TableData as u8;
+        done[V2_DATA_START] = 9;
+        pm.write_page(2, &done).unwrap();
+        write_v1_page(&mut pm, 3, b"x");
+        pm.set_format_version(1).unwrap();
+        pm.set_migration_progress(3).unwrap();
+
+        run_migrations(&mut pm, MIGRATIONS, 2).unwrap();
+
+        assert_eq!(pm.read_page_unchecked(2).unwrap(), done);
+        assert_eq!(pm.read_page_unchecked(3).unwrap()[V2_DATA_START], b'x');
+        assert_eq!(pm.migration_progress().unwrap(), 0);
+    }
+
+    /// Write one row into `page_id` the way a version 2 database laid it out
+    fn write_v2_page(pm: &mut PageManager, page_id: u32, row: &[u8]) -> [u8; PAGE_SIZE] {
+        let mut page = [0u8; PAGE_SIZE];
+        page[V2_LAST_OFFSET..V2_LAST_OFFSET + 2]
+            .copy_from_slice(&((V2_DATA_START + row.len()) as u16).to_le_bytes());
+        page[V2_NUM_ROWS..V2_NUM_ROWS + 2].copy_from_slice(&1u16.to_le_bytes());
+        page[V2_PAGE_TYPE] = PageType::TableData as u8;
+        page[V2_DATA_START..V2_DATA_START + row.len()].copy_from_slice(row);
+        pm.write_page(page_id, &page).unwrap();
+        page
+    }
+
+    #[test]
+    fn test_widen_page_metadata() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.allocate_page().unwrap();
+        let page_id = pm.allocate_page().unwrap();
+        write_v2_page(&mut pm, page_id, b"\x01\x07\x00\x00\x00\x00");
+        pm.set_format_version(2).unwrap();
+
+        assert_eq!(migrate(&mut pm).unwrap(), (FORMAT_VERSION - 2) as usize);
+
+        let page = pm.read_page(page_id).unwrap();
+        assert_eq!(pm.page_type(page_id).unwrap(), PageType::TableData);
+        assert_eq!(
+            &page[PAGE_DATA_START..PAGE_DATA_START + 6],
+            b"\x01\x07\x00\x00\x00\x00"
+        );
+        let metadata = PageManager::read_metadata_from_buffer(&page);
+        assert_eq!(metadata.last_offset, PAGE_DATA_START + 6);
+        assert_eq!(metadata.num_rows, 1);
+        assert_eq!(metadata.free_end, PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_add_table_sequences() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.allocate_page().unwrap();
+        let schema = Schema::new("t", vec![]).unwrap().to_bytes();
+        let mut catalog = 2u32.to_le_bytes().to_vec();
+        for first_page in [2u32, 3] {
+            catalog.extend_from_slice(&schema);
+            catalog.extend_from_slice(&first_page.to_le_bytes());
+        }
+        pm.write_page(CATALOG_PAGE, &catalog).unwrap();
+        pm.set_format_version(3).unwrap();
+
+        run_migrations(&mut pm, MIGRATIONS, 4).unwrap();
+
+        let page = pm.read_page(CATALOG_PAGE).unwrap();
+        let second = 4 + schema.len() + 12;
+        assert_eq!(&page[second - 8..second], &[0; 8]);
+        assert_eq!(&page[second..second + schema.len()], &schema[..]);
+        assert_eq!(&page[second + schema.len()..][..4], &3u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_add_table_row_counts() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.allocate_page().unwrap();
+        let page_id = pm.allocate_page().unwrap();
+        let mut page = [0u8; PAGE_SIZE];
+        page[OFFSET_PAGE_TYPE] = PageType::TableData as u8;
+        page[V3_NUM_ROWS..V3_NUM_ROWS + 4].copy_from_slice(&7u32.to_le_bytes());
+        pm.write_page(page_id, &page).unwrap();
+
+        let schema = Schema::new("t", vec![]).unwrap().to_bytes();
+        let mut catalog = 1u32.to_le_bytes().to_vec();
+        catalog.extend_from_slice(&schema);
+        catalog.extend_from_slice(&page_id.to_le_bytes());
+        catalog.extend_from_slice(&5i64.to_le_bytes());
+        pm.write_page(CATALOG_PAGE, &catalog).unwrap();
+        pm.set_format_version(4).unwrap();
+
+        run_migrations(&mut pm, MIGRATIONS, 5).unwrap();
+
+        let page = pm.read_page(CATALOG_PAGE).unwrap();
+        let tail = &page[4 + schema.len()..][..24];
+        assert_eq!(&tail[..12], &catalog[4 + schema.len()..]);
+        assert_eq!(&tail[12..16], &page_id.to_le_bytes());
+        assert_eq!(&tail[16..], &7u64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_retype_null_columns() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.allocate_page().unwrap();
+
+        // A NULL column "a" and a NOT NULL INTEGER column "b"
+        let mut catalog = 1u32.to_le_bytes().to_vec();
+        catalog.extend_from_slice(&1u32.to_le_bytes());
+        catalog.push(b't');
+        catalog.extend_from_slice(&2u32.to_le_bytes());
+        for (name, type_byte) in [(b'a', V5_TYPE_NULL), (b'b', 0x40)] {
+            catalog.extend_from_slice(&1u32.to_le_bytes());
+            catalog.push(name);
+            catalog.push(type_byte);
+        }
+        let schema_end = catalog.len();
+        catalog.extend_from_slice(&[7; V5_ENTRY_TAIL]);
+        // The views and indexes sections follow the entries
+        catalog.extend_from_slice(&[9; 8]);
+        pm.write_page(CATALOG_PAGE, &catalog).unwrap();
+        pm.set_format_version(5).unwrap();
+
+        run_migrations(&mut pm, MIGRATIONS, 6).unwrap();
+
+        let page = pm.read_page(CATALOG_PAGE).unwrap();
+        let (schema, _) = Schema::from_bytes(&page[4..]).unwrap();
+        let columns = schema.columns();
+        assert!(matches!(columns[0].data_type(), DataType::Text));
+        assert!(columns[0].is_nullable());
+        assert!(matches!(columns[1].data_type(), DataType::Integer));
+        assert!(!columns[1].is_nullable());
+        assert_eq!(&page[schema_end..catalog.len()], &catalog[schema_end..]);
+    }
+
+    #[test]
+    fn test_drop_unfilled_indexes() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.allocate_page().unwrap();
+
+        let schema = Schema::new("t", vec![]).unwrap().to_bytes();
+        let mut catalog = 1u32.to_le_bytes().to_vec();
+        catalog.extend_from_slice(&schema);
+        catalog.extend_from_slice(&[7; V5_ENTRY_TAIL]);
+        // One view, then one index in the old layout
+        catalog.extend_from_slice(&1u32.to_le_bytes());
+        for text in ["v", "SELECT * FROM t"] {
+            catalog.extend_from_slice(&(text.len() as u32).to_le_bytes());
+            catalog.extend_from_slice(text.as_bytes());
+        }
+        let indexes = catalog.len();
+        catalog.extend_from_slice(&1u32.to_le_bytes());
+        catalog.extend_from_slice(&[9; 20]);
+        pm.write_page(CATALOG_PAGE, &catalog).unwrap();
+        pm.set_format_version(6).unwrap();
+
+        run_migrations(&mut pm, MIGRATIONS, 7).unwrap();
+
+        let page = pm.read_page(CATALOG_PAGE).unwrap();
+        assert_eq!(&page[..indexes], &catalog[..indexes]);
+        assert!(page[indexes..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_widen_rejects_page_without_room() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.allocate_page().unwrap();
+        let page_id = pm.allocate_page().unwrap();
+        write_v2_page(&mut pm, page_id, &[1; PAGE_SIZE - V2_DATA_START]);
+        pm.set_format_version(2).unwrap();
+
+        let err = migrate(&mut pm).unwrap_err();
+        assert!(err.to_string().contains("Page 2 can't be upgraded"));
+        assert_eq!(pm.format_version().unwrap(), 2);
+    }
+}
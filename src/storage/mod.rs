@@ -1 +1,16 @@
+#[cfg(feature = "async")]
+pub mod async_page;
+pub mod backend;
+pub mod backup;
+pub mod btree;
+pub mod checkpoint;
+pub mod compress;
+#[cfg(feature = "encryption")]
+pub mod crypt;
+pub mod doublewrite;
+pub mod log;
+pub mod migration;
 pub mod page;
+pub mod spill;
+pub mod stats;
+pub mod undo;
@@ -1,24 +1,209 @@
-use std::fs::{File, OpenOptions};
-use std::io::{self, Read, Seek, SeekFrom, Write};
-use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use crate::error::{HozonError, Result};
+use crate::storage::backend::{self, FileBackend, MemBackend, ReadOnlyFileBackend, StorageBackend};
+use crate::storage::backup::{BackupManifest, BackupStats, ChangeTracker};
+use crate::storage::checkpoint::{Checkpointer, SharedStorage};
+use crate::storage::compress;
+#[cfg(feature = "encryption")]
+use crate::storage::crypt::{self, PageCipher};
+use crate::storage::stats::{IoCounters, IoStats};
+use crate::storage::undo::UndoLog;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 pub const PAGE_SIZE: usize = 4096;
 pub type PageId = u32;
 
-pub const PAGE_METADATA_SIZE: usize = 5;
+// Header (page 0) offsets
+const HEADER_OWNER: usize = 8; // owner pid (u32) + lock timestamp (u64)
+const HEADER_FLAGS: usize = 20; // u32 feature flags, fixed at creation
+#[cfg(feature = "encryption")]
+const HEADER_SALT: usize = 24; // 16 byte key derivation salt
+#[cfg(feature = "encryption")]
+const HEADER_VERIFIER: usize = 40; // 32 byte hash proving the key is right
+#[cfg(feature = "encryption")]
+const HEADER_KDF_ITERATIONS: usize = 72; // u32
+const HEADER_FORMAT_VERSION: usize = 76; // u32, see storage::migration
+const HEADER_MIGRATION_PROGRESS: usize = 80; // u32, 0 unless a migration was interrupted
+// 84..256 reserved for future header fields, always written as zero
+
+/// On-disk layout version written by this build.
+///
+/// Files created before the version field existed read as 0. Bump this together
+/// with a new entry in `storage::migration` whenever the layout changes.
+pub const FORMAT_VERSION: u32 = 7;
+
+// Header feature flags
+const FLAG_COMPRESSED: u32 = 1;
+const FLAG_ENCRYPTED: u32 = 2;
+const FLAG_LOG_ENGINE: u32 = 4;
+
+/// First page with a metadata block (page 0 = header, page 1 = catalog)
+pub const FIRST_DATA_PAGE: PageId = 2;
+
+// Set in the first metadata byte of a stored data page whose payload is compressed
+const PAGE_FLAG_COMPRESSED: u8 = 0x80;
+// Leading bytes of a compressed page stored as is; the rest is compressed.
+// Independent of the metadata layout, and fixed so older files still decode.
+const COMPRESS_CLEAR: usize = 5;
+
+// Data page metadata block (all integers little endian):
+//   0      u8   flags: 1 = full (PAGE_FLAG_COMPRESSED is only ever set on disk)
+//   1      u8   page type, see PageType
+//   2..4        reserved, zero
+//   4..8   u32  last_offset: end of the row data, where free space starts
+//   8..12  u32  num_rows
+//   12..16 u32  free_end: end of the free space
+pub const PAGE_METADATA_SIZE: usize = 16;
 pub const PAGE_DATA_START: usize = PAGE_METADATA_SIZE;
 
 // Metadata offsets
 const OFFSET_IS_FULL: usize = 0;
-const OFFSET_LAST_OFFSET: usize = 1;
-const OFFSET_NUM_ROWS: usize = 3;
+pub(crate) const OFFSET_PAGE_TYPE: usize = 1;
+const OFFSET_LAST_OFFSET: usize = 4;
+const OFFSET_NUM_ROWS: usize = 8;
+const OFFSET_FREE_END: usize = 12;
+
+/// Pages the storage grows by when it runs out of preallocated space
+pub const DEFAULT_EXTENT_PAGES: u32 = 64;
+
+/// Path that selects a database kept entirely in memory
+pub const MEMORY_PATH: &str = ":memory:";
 
 #[derive(Debug)]
 pub struct PageManager {
-    file: Mutex<File>,
-    lock_path: PathBuf,
+    // shared with the checkpoint thread, when there is one
+    storage: SharedStorage,
     num_pages: u32,
+    // pages the storage already has room for (>= num_pages)
+    capacity: u32,
+    extent_pages: u32,
+    // num_pages changed but the header hasn't been written yet
+    header_dirty: bool,
+    sync_mode: SyncMode,
+    // writes not yet fsynced (only possible below FULL)
+    unsynced: bool,
+    compressed: bool,
+    engine: StorageEngine,
+    // database file, when there is one
+    path: Option<String>,
+    // opened with `new_read_only`; every write is refused
+    read_only: bool,
+    // which pages changed since an incremental backup
+    changes: ChangeTracker,
+    checkpointer: Option<Checkpointer>,
+    stats: Arc<IoCounters>,
+    // pages to write back for the open savepoints
+    undo: UndoLog,
+    // every page but the header is encrypted when set
+    #[cfg(feature = "encryption")]
+    cipher: Option<PageCipher>,
+}
+
+/// Choices made when opening or creating a database
+#[derive(Debug, Default)]
+struct OpenSettings {
+    force: bool,
+    // only honoured when creating; existing files keep what their header says
+    compress: bool,
+    // passphrase for encrypted databases; creating with one turns encryption on
+    #[cfg(feature = "encryption")]
+    key: Option<String>,
+    // only honoured when creating, like `compress`
+    engine: StorageEngine,
+    // open an existing file without writing anything, not even the owner
+    read_only: bool,
+}
+
+/// Where table rows are kept. The catalog always lives in pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StorageEngine {
+    /// Rows written in place into table data pages
+    #[default]
+    Pages,
+    /// Rows appended to segment files next to the database, see `storage::log`
+    Log,
+}
+
+/// How hard PageManager works to get writes onto durable storage
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SyncMode {
+    /// Never fsync - leave it to the OS. Fast, but a crash can lose data
+    Off,
+    /// fsync once per commit boundary (end of statement) instead of per write
+    Normal,
+    /// fsync after every page write
+    #[default]
+    Full,
+}
+
+impl SyncMode {
+    /// Parse a PRAGMA-style setting: OFF/NORMAL/FULL or 0/1/2
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_uppercase().as_str() {
+            "OFF" | "0" => Some(SyncMode::Off),
+            "NORMAL" | "1" => Some(SyncMode::Normal),
+            "FULL" | "2" => Some(SyncMode::Full),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncMode::Off => "OFF",
+            SyncMode::Normal => "NORMAL",
+            SyncMode::Full => "FULL",
+        }
+    }
+}
+
+/// What a page holds. Pages 0 and 1 are always the header and the catalog;
+/// every other page records its type in its metadata block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PageType {
+    Header = 1,
+    Catalog = 2,
+    TableData = 3,
+    /// A leaf of an index B-tree
+    Index = 4,
+    Overflow = 5,
+    Free = 6,
+    /// An interior node of an index B-tree
+    IndexInterior = 7,
+}
+
+impl PageType {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            1 => Some(PageType::Header),
+            2 => Some(PageType::Catalog),
+            3 => Some(PageType::TableData),
+            4 => Some(PageType::Index),
+            5 => Some(PageType::Overflow),
+            6 => Some(PageType::Free),
+            7 => Some(PageType::IndexInterior),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PageType::Header => "header",
+            PageType::Catalog => "catalog",
+            PageType::TableData => "table",
+            PageType::Index => "index",
+            PageType::Overflow => "overflow",
+            PageType::Free => "free",
+            PageType::IndexInterior => "interior",
+        }
+    }
+}
+
+/// Process recorded in the header as the current owner of the database
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockOwner {
+    pub pid: u32,
+    /// Seconds since the unix epoch when the lock was taken
+    pub since: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -26,116 +211,502 @@ pub struct PageMetadata {
     pub is_full: bool,
     pub last_offset: usize,
     pub num_rows: usize,
+    /// Rows may grow up to here. The page end until something is stored
+    /// at the tail of the page.
+    pub free_end: usize,
+}
+
+impl PageMetadata {
+    /// Bytes left between the rows and the end of the free space
+    pub fn free_space(&self) -> usize {
+        self.free_end.saturating_sub(self.last_offset)
+    }
 }
 
 impl PageManager {
-    pub fn new(path: &str) -> io::Result<Self> {
-        let lock_path = PathBuf::from(format!("{}.lock", path));
+    /// Open or create a database file, or an in-memory database for ":memory:"
+    pub fn new(path: &str) -> Result<Self> {
+        Self::open(path, OpenSettings::default())
+    }
+
+    /// Open the database even if the header names another live process as owner.
+    ///
+    /// Only needed on filesystems where advisory locks aren't enforced (e.g. some
+    /// network mounts); it can't break a lock the OS still reports as held.
+    pub fn new_with_force(path: &str) -> Result<Self> {
+        Self::open(
+            path,
+            OpenSettings {
+                force: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Open a database, creating it with page compression if it doesn't exist.
+    ///
+    /// Compression is recorded in the header at creation time, so an existing
+    /// database is opened the way it was created.
+    pub fn new_compressed(path: &str) -> Result<Self> {
+        Self::open(
+            path,
+            OpenSettings {
+                compress: true,
+                ..Default::default()
+            },
+        )
+    }
 
-        // try to acquire lock
-        Self::acquire_lock(Path::new(&lock_path))?;
+    /// Open a database, creating it with the log-structured row engine if it
+    /// doesn't exist. Suits write-heavy workloads; rows live in `<path>-log/`.
+    pub fn new_log_structured(path: &str) -> Result<Self> {
+        Self::open(
+            path,
+            OpenSettings {
+                engine: StorageEngine::Log,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Open an encrypted database, creating it encrypted with `key` if it doesn't exist.
+    ///
+    /// Every page except the header is encrypted, and a wrong key is rejected
+    /// when opening instead of returning garbage pages.
+    #[cfg(feature = "encryption")]
+    pub fn new_with_key(path: &str, key: &str) -> Result<Self> {
+        Self::open(
+            path,
+            OpenSettings {
+                key: Some(key.to_string()),
+                ..Default::default()
+            },
+        )
+    }
 
-        if Path::new(path).exists() {
-            let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    /// Open an existing database file without ever writing to it.
+    ///
+    /// Other read-only handles can have the file open at the same time, but
+    /// a writer can't, and this can't open a file a writer has open.
+    pub fn new_read_only(path: &str) -> Result<Self> {
+        Self::open(
+            path,
+            OpenSettings {
+                read_only: true,
+                ..Default::default()
+            },
+        )
+    }
 
-            // Go to start of file
-            file.seek(SeekFrom::Start(0))?;
+    /// Create a fresh database that lives only in memory
+    pub fn new_in_memory() -> Result<Self> {
+        Self::with_backend(MemBackend::new())
+    }
 
-            // Read magic number
-            let mut magic_bytes = [0u8; 4];
-            file.read_exact(&mut magic_bytes)?;
-            let magic_number = u32::from_le_bytes(magic_bytes);
+    /// Run the page manager on top of any storage backend
+    pub fn with_backend(backend: impl StorageBackend + 'static) -> Result<Self> {
+        Self::from_backend(Box::new(backend), OpenSettings::default())
+    }
 
-            if magic_number != 0x484F5A4E {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "Invalid magic number",
+    fn open(path: &str, settings: OpenSettings) -> Result<Self> {
+        if settings.read_only {
+            if path == MEMORY_PATH {
+                return Err(HozonError::InvalidInput(
+                    "An in-memory database can't be opened read-only".to_string(),
                 ));
             }
+            let backend = ReadOnlyFileBackend::open(path)?;
+            let mut page_manager = Self::from_backend(Box::new(backend), settings)?;
+            page_manager.path = Some(path.to_string());
+            return Ok(page_manager);
+        }
+        if path == MEMORY_PATH {
+            if settings.engine == StorageEngine::Log {
+                return Err(HozonError::InvalidInput(
+                    "The log-structured engine needs a database file".to_string(),
+                ));
+            }
+            return Self::from_backend(Box::new(MemBackend::new()), settings);
+        }
 
-            // Read number of pages
-            let mut num_pages_bytes = [0u8; 4];
-            file.read_exact(&mut num_pages_bytes)?;
-            let num_pages = u32::from_le_bytes(num_pages_bytes);
-
-            Ok(PageManager {
-                file: Mutex::new(file),
-                num_pages: num_pages,
-                lock_path,
-            })
-        } else {
-            let mut file = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open(path)?;
-
-            let mut headers = [0u8; PAGE_SIZE];
-            headers[0..4].copy_from_slice(&0x484F5A4E_u32.to_le_bytes());
-            headers[4..8].copy_from_slice(&1u32.to_le_bytes());
-            file.write_all(&headers)?;
-
-            Ok(PageManager {
-                file: Mutex::new(file),
-                num_pages: 1,
-                lock_path,
-            })
-        }
-    }
-
-    /// Try to acquire the lock file
-    fn acquire_lock(lock_path: &Path) -> io::Result<()> {
-        // Try to create the lock file exclusively
-        // This will fail if the file already exists
-        match OpenOptions::new()
-            .write(true)
-            .create_new(true) // Fails if file exists!
-            .open(lock_path)
+        let mut page_manager = Self::from_backend(Box::new(FileBackend::open(path)?), settings)?;
+        page_manager.path = Some(path.to_string());
+        Ok(page_manager)
+    }
+
+    fn from_backend(mut storage: Box<dyn StorageBackend>, settings: OpenSettings) -> Result<Self> {
+        if storage.num_pages()? == 0 {
+            return Self::init(storage, settings);
+        }
+
+        let mut header = [0u8; PAGE_SIZE];
+        storage.read_page(0, &mut header)?;
+
+        // Check magic number
+        let magic_number = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        if magic_number != 0x484F5A4E {
+            return Err(HozonError::Corruption("Invalid magic number".to_string()));
+        }
+
+        // Read number of pages
+        let num_pages = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let flags = read_u32(&header, HEADER_FLAGS);
+
+        // Refuse before touching anything: an older build can't know what changed
+        let version = read_u32(&header, HEADER_FORMAT_VERSION);
+        if version > FORMAT_VERSION {
+            return Err(HozonError::Unsupported(format!(
+                "Database format version {} is newer than this build supports (version {})",
+                version, FORMAT_VERSION
+            )));
+        }
+
+        #[cfg(feature = "encryption")]
+        let cipher = Self::open_cipher(&header, flags, settings.key.as_deref())?;
+        #[cfg(not(feature = "encryption"))]
+        if flags & FLAG_ENCRYPTED != 0 {
+            return Err(HozonError::Unsupported(
+                "Database is encrypted; rebuild with the `encryption` feature to open it"
+                    .to_string(),
+            ));
+        }
+
+        if storage.is_persistent() && !settings.read_only {
+            Self::claim_ownership(&mut header, settings.force)?;
+            storage.write_page(0, &header)?;
+            storage.sync()?;
+        }
+
+        let capacity = storage.num_pages()?.max(num_pages);
+
+        Ok(PageManager {
+            storage: Arc::new(RwLock::new(storage)),
+            num_pages,
+            capacity,
+            extent_pages: DEFAULT_EXTENT_PAGES,
+            header_dirty: false,
+            sync_mode: SyncMode::default(),
+            unsynced: false,
+            changes: ChangeTracker::new(),
+            checkpointer: None,
+            stats: Arc::default(),
+            undo: UndoLog::default(),
+            compressed: flags & FLAG_COMPRESSED != 0,
+            engine: if flags & FLAG_LOG_ENGINE != 0 {
+                StorageEngine::Log
+            } else {
+                StorageEngine::Pages
+            },
+            path: None,
+            read_only: settings.read_only,
+            #[cfg(feature = "encryption")]
+            cipher,
+        })
+    }
+
+    /// Check `key` against an existing header and derive the page cipher
+    #[cfg(feature = "encryption")]
+    fn open_cipher(
+        header: &[u8; PAGE_SIZE],
+        flags: u32,
+        key: Option<&str>,
+    ) -> Result<Option<PageCipher>> {
+        let key = match (flags & FLAG_ENCRYPTED != 0, key) {
+            (false, None) => return Ok(None),
+            (false, Some(_)) => {
+                return Err(HozonError::InvalidInput(
+                    "Database is not encrypted".to_string(),
+                ));
+            }
+            (true, None) => {
+                return Err(HozonError::Encryption(
+                    "Database is encrypted; a key is required".to_string(),
+                ));
+            }
+            (true, Some(key)) => key,
+        };
+
+        let mut salt = [0u8; crypt::SALT_SIZE];
+        salt.copy_from_slice(&header[HEADER_SALT..HEADER_SALT + crypt::SALT_SIZE]);
+        let iterations = read_u32(header, HEADER_KDF_ITERATIONS);
+
+        let cipher = PageCipher::derive(key, &salt, iterations);
+        if cipher.verifier()[..] != header[HEADER_VERIFIER..HEADER_VERIFIER + crypt::VERIFIER_SIZE]
         {
-            Ok(_) => {
-                // Successfully created lock file
-                // We can close it immediately - its existence is the lock
-                Ok(())
+            return Err(HozonError::Encryption("Invalid encryption key".to_string()));
+        }
+        Ok(Some(cipher))
+    }
+
+    /// Write the header page of a brand new database
+    fn init(mut storage: Box<dyn StorageBackend>, settings: OpenSettings) -> Result<Self> {
+        let mut flags = 0;
+        if settings.compress {
+            flags |= FLAG_COMPRESSED;
+        }
+        if settings.engine == StorageEngine::Log {
+            // Segment files aren't encrypted, so rows would be stored in the clear
+            #[cfg(feature = "encryption")]
+            if settings.key.is_some() {
+                return Err(HozonError::InvalidInput(
+                    "The log-structured engine doesn't support encryption".to_string(),
+                ));
             }
-            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
-                // Lock file exists - database is already open
-                Err(io::Error::new(
-                    io::ErrorKind::WouldBlock,
-                    format!(
-                        "Database is already in use. Lock file exists: {}",
-                        lock_path.display()
-                    ),
-                ))
+            flags |= FLAG_LOG_ENGINE;
+        }
+
+        let mut headers = [0u8; PAGE_SIZE];
+        headers[0..4].copy_from_slice(&0x484F5A4E_u32.to_le_bytes());
+        headers[4..8].copy_from_slice(&1u32.to_le_bytes());
+        headers[HEADER_FORMAT_VERSION..HEADER_FORMAT_VERSION + 4]
+            .copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+        #[cfg(feature = "encryption")]
+        let cipher = match settings.key.as_deref() {
+            Some(key) => {
+                let salt = PageCipher::new_salt()?;
+                let cipher = PageCipher::derive(key, &salt, crypt::KDF_ITERATIONS);
+                flags |= FLAG_ENCRYPTED;
+                headers[HEADER_SALT..HEADER_SALT + crypt::SALT_SIZE].copy_from_slice(&salt);
+                headers[HEADER_VERIFIER..HEADER_VERIFIER + crypt::VERIFIER_SIZE]
+                    .copy_from_slice(cipher.verifier());
+                headers[HEADER_KDF_ITERATIONS..HEADER_KDF_ITERATIONS + 4]
+                    .copy_from_slice(&crypt::KDF_ITERATIONS.to_le_bytes());
+                Some(cipher)
             }
-            Err(e) => Err(e), // Some other error
+            None => None,
+        };
+        headers[HEADER_FLAGS..HEADER_FLAGS + 4].copy_from_slice(&flags.to_le_bytes());
+        if storage.is_persistent() {
+            write_owner(&mut headers, &Self::current_owner());
         }
+        storage.allocate(1)?;
+        storage.write_page(0, &headers)?;
+        storage.sync()?;
+
+        Ok(PageManager {
+            storage: Arc::new(RwLock::new(storage)),
+            num_pages: 1,
+            capacity: 1,
+            extent_pages: DEFAULT_EXTENT_PAGES,
+            header_dirty: false,
+            sync_mode: SyncMode::default(),
+            unsynced: false,
+            changes: ChangeTracker::new(),
+            checkpointer: None,
+            stats: Arc::default(),
+            undo: UndoLog::default(),
+            compressed: settings.compress,
+            engine: settings.engine,
+            path: None,
+            read_only: settings.read_only,
+            #[cfg(feature = "encryption")]
+            cipher,
+        })
     }
 
-    fn release_lock(lock_path: &Path) -> io::Result<()> {
-        if lock_path.exists() {
-            std::fs::remove_file(lock_path)?;
+    /// Record this process as owner, taking over from a stale owner if needed.
+    ///
+    /// Holding the OS lock means no other handle has the file open, so a recorded
+    /// owner that is dead (or is us, from a handle that was leaked) is stale.
+    fn claim_ownership(header: &mut [u8; PAGE_SIZE], force: bool) -> Result<()> {
+        if let Some(owner) = read_owner(header) {
+            let stale = owner.pid == Self::current_owner().pid || !Self::process_alive(owner.pid);
+            if !stale && !force {
+                return Err(HozonError::Locked(format!(
+                    "Database is owned by running PID {} (since {}); open it with force to take over",
+                    owner.pid, owner.since
+                )));
+            }
         }
+
+        write_owner(header, &Self::current_owner());
         Ok(())
     }
 
+    #[cfg(not(all(target_family = "wasm", target_os = "unknown")))]
+    fn current_owner() -> LockOwner {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        LockOwner {
+            pid: std::process::id(),
+            since,
+        }
+    }
+
+    // Bare wasm has no process ids and no clock; the page running it is
+    // the only process there is
+    #[cfg(all(target_family = "wasm", target_os = "unknown"))]
+    fn current_owner() -> LockOwner {
+        LockOwner { pid: 0, since: 0 }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn process_alive(pid: u32) -> bool {
+        std::path::Path::new(&format!("/proc/{}", pid)).exists()
+    }
+
+    // Without a portable liveness check, go by the OS lock we already hold:
+    // assuming the owner runs would lock a crashed session's file out for good
+    #[cfg(not(target_os = "linux"))]
+    fn process_alive(_pid: u32) -> bool {
+        false
+    }
+
+    /// Owner recorded in the database header, if the last session didn't close cleanly
+    pub fn lock_owner(&self) -> Result<Option<LockOwner>> {
+        Ok(read_owner(&self.read_page(0)?))
+    }
+
+    /// Layout version recorded in the header
+    pub fn format_version(&self) -> Result<u32> {
+        Ok(read_u32(&self.read_page(0)?, HEADER_FORMAT_VERSION))
+    }
+
+    /// Record a new layout version and clear migration progress; only
+    /// migrations should call this
+    pub(crate) fn set_format_version(&mut self, version: u32) -> Result<()> {
+        let mut header = self.read_page(0)?;
+        header[HEADER_FORMAT_VERSION..HEADER_FORMAT_VERSION + 4]
+            .copy_from_slice(&version.to_le_bytes());
+        header[HEADER_MIGRATION_PROGRESS..HEADER_MIGRATION_PROGRESS + 4]
+            .copy_from_slice(&0u32.to_le_bytes());
+        self.write_page(0, &header)
+    }
+
+    /// Where the running migration got to, as recorded by the migration itself
+    pub(crate) fn migration_progress(&self) -> Result<u32> {
+        Ok(read_u32(&self.read_page(0)?, HEADER_MIGRATION_PROGRESS))
+    }
+
+    pub(crate) fn set_migration_progress(&mut self, progress: u32) -> Result<()> {
+        self.write_at(0, HEADER_MIGRATION_PROGRESS, &progress.to_le_bytes())
+    }
+
+    /// Copy every page into a new database file at `path`, returning the page count.
+    ///
+    /// The storage lock is held for the whole copy and writes need `&mut self`,
+    /// so the copy is a consistent snapshot while the database stays open. Pages
+    /// are copied as stored, so a backup keeps the source's compression and key.
+    pub fn backup_to(&self, path: &str) -> Result<u32> {
+        let mut target = FileBackend::open(path)?;
+        self.copy_pages(&mut target, |_| true)?;
+        Ok(self.num_pages)
+    }
+
+    /// Update a backup made by an earlier `backup_incremental` to `path`,
+    /// copying only the pages written since.
+    ///
+    /// Falls back to a full copy when there's no manifest or it comes from
+    /// another session. The manifest is saved next to the backup after the copy
+    /// is synced, so an interrupted backup is simply redone from the old one.
+    pub fn backup_incremental(&self, path: &str) -> Result<BackupStats> {
+        let mut target = FileBackend::open(path)?;
+
+        let since = match BackupManifest::load(path)? {
+            Some(manifest)
+                if manifest.session == self.changes.session()
+                    && manifest.num_pages <= self.num_pages
+                    && target.num_pages()? == manifest.num_pages =>
+            {
+                Some(manifest)
+            }
+            _ => None,
+        };
+
+        let pages_copied = self.copy_pages(&mut target, |page_id| match &since {
+            // the header is always copied: it's rewritten at open and on allocation
+            Some(manifest) => {
+                page_id == 0
+                    || page_id >= manifest.num_pages
+                    || self.changes.changed_since(page_id, manifest.generation)
+            }
+            None => true,
+        })?;
+
+        BackupManifest {
+            session: self.changes.session(),
+            generation: self.changes.generation(),
+            num_pages: self.num_pages,
+        }
+        .save(path)?;
+
+        Ok(BackupStats {
+            pages_copied,
+            total_pages: self.num_pages,
+        })
+    }
+
+    /// Copy the pages picked by `include` into `target` as stored, under the storage lock
+    fn copy_pages(
+        &self,
+        target: &mut FileBackend,
+        include: impl Fn(PageId) -> bool,
+    ) -> Result<u32> {
+        let storage = self.storage.read().unwrap();
+
+        target.allocate(self.num_pages)?;
+        let mut buf = [0u8; PAGE_SIZE];
+        let mut copied = 0;
+        for page_id in (0..self.num_pages).filter(|&id| include(id)) {
+            storage.read_page(page_id, &mut buf)?;
+            self.stats.page_read();
+            if page_id == 0 {
+                // The copy isn't open anywhere yet
+                write_owner(&mut buf, &LockOwner { pid: 0, since: 0 });
+                buf[4..8].copy_from_slice(&self.num_pages.to_le_bytes());
+            }
+            target.write_page(page_id, &buf)?;
+            copied += 1;
+        }
+        target.sync()?;
+
+        Ok(copied)
+    }
+
+    /// Whether this database lives only in memory
+    pub fn is_in_memory(&self) -> bool {
+        !self.storage.read().unwrap().is_persistent()
+    }
+
     /// Allocate a new page and return its ID
     ///
     /// Note: Page 0 is reserved for database header and created in new().
     /// This method allocates pages starting from page 1 with initialized metadata.
-    pub fn allocate_page(&mut self) -> io::Result<PageId> {
-        let page_id: PageId = self.num_pages;
-        self.num_pages += 1;
+    pub fn allocate_page(&mut self) -> Result<PageId> {
+        self.allocate_page_of_type(PageType::TableData)
+    }
+
+    /// Allocate a new page tagged with `page_type` (ignored for the catalog page)
+    pub fn allocate_page_of_type(&mut self, page_type: PageType) -> Result<PageId> {
+        self.check_writable()?;
+        if matches!(page_type, PageType::Header | PageType::Catalog) && self.num_pages >= 2 {
+            return Err(HozonError::InvalidInput(format!(
+                "Only page {} can be a {} page",
+                page_type as u8 - 1,
+                page_type.as_str()
+            )));
+        }
 
-        let new_size = (self.num_pages as u64) * (PAGE_SIZE as u64);
-        let num_pages_bytes = self.num_pages.to_le_bytes();
+        let page_id: PageId = self.num_pages;
 
-        // Extend db file size and set new number of pages
-        {
-            let mut file = self.file.lock().unwrap();
-            file.set_len(new_size)?;
-            file.seek(SeekFrom::Start(4))?;
-            file.write_all(&num_pages_bytes)?;
-        };
+        // Grow a whole extent at a time; the new page count reaches the header
+        // with the next sync, together with whatever refers to the new page
+        if page_id >= self.capacity {
+            let capacity = self.capacity.saturating_add(self.extent_pages);
+            self.storage.write().unwrap().allocate(capacity)?;
+            self.capacity = capacity;
+        }
+        self.num_pages += 1;
+        self.header_dirty = true;
+        if self.checkpointer.is_some() {
+            // A checkpoint can land at any moment and mustn't capture pages
+            // that the header doesn't count yet
+            self.flush_header()?;
+        }
 
         let mut page_data = [0u8; PAGE_SIZE];
 
@@ -143,6 +714,7 @@ impl PageManager {
         if self.num_pages > 2 {
             // Create page buffer with metadata
             Self::init_page_metadata_buffer(&mut page_data);
+            page_data[OFFSET_PAGE_TYPE] = page_type as u8;
         }
 
         // Write initialized page
@@ -151,169 +723,593 @@ impl PageManager {
         Ok(page_id)
     }
 
-    /// Write data to a specific page
-    pub fn write_page(&mut self, page_id: PageId, data: &[u8]) -> io::Result<()> {
-        // Check page ID validity
-        if page_id >= self.num_pages {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                format!("Invalid page ID: {} (max: {})", page_id, self.num_pages - 1),
+    /// Pages the storage grows by when it's full
+    pub fn extent_size(&self) -> u32 {
+        self.extent_pages
+    }
+
+    pub fn set_extent_size(&mut self, pages: u32) -> Result<()> {
+        if pages == 0 {
+            return Err(HozonError::InvalidInput(
+                "Extent size must be at least one page".to_string(),
             ));
         }
+        self.extent_pages = pages;
+        Ok(())
+    }
+
+    /// Write the page count to the header if allocations changed it
+    fn flush_header(&mut self) -> Result<()> {
+        if !self.header_dirty {
+            return Ok(());
+        }
+
+        let mut storage = self.storage.write().unwrap();
+        let mut header = [0u8; PAGE_SIZE];
+        storage.read_page(0, &mut header)?;
+        header[4..8].copy_from_slice(&self.num_pages.to_le_bytes());
+        storage.write_page(0, &header)?;
+        self.stats.page_read();
+        self.stats.page_written(PAGE_SIZE);
+
+        self.changes.mark(0);
+        self.header_dirty = false;
+        self.unsynced = true;
+        Ok(())
+    }
+
+    /// Write data to a specific page
+    pub fn write_page(&mut self, page_id: PageId, data: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        self.check_page_id(page_id)?;
 
         // Check that data is not longer than PAGE_SIZE
         if data.len() > PAGE_SIZE {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                format!("Data length {} exceeds PAGE_SIZE {}", data.len(), PAGE_SIZE),
-            ));
+            return Err(HozonError::InvalidInput(format!(
+                "Data length {} exceeds PAGE_SIZE {}",
+                data.len(),
+                PAGE_SIZE
+            )));
         }
 
-        let offset = (page_id as u64) * (PAGE_SIZE as u64);
         let mut buffer = [0u8; PAGE_SIZE];
         buffer[0..data.len()].copy_from_slice(data);
 
+        if page_id == 0 {
+            // Whatever the caller read, the header carries the live page count
+            buffer[4..8].copy_from_slice(&self.num_pages.to_le_bytes());
+            self.header_dirty = false;
+        }
+
+        if self.compressed && page_id >= FIRST_DATA_PAGE {
+            buffer = Self::compress_page(&buffer)?;
+        }
+
+        // Encrypt last, so compression still sees the plaintext
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher
+            && page_id > 0
+        {
+            cipher.encrypt_page(page_id, &mut buffer);
+        }
+
+        if self.sync_mode == SyncMode::Full {
+            self.flush_header()?;
+        }
+
+        self.save_undo(page_id)?;
+        self.changes.mark(page_id);
         {
-            let mut file = self.file.lock().unwrap();
-            file.seek(SeekFrom::Start(offset))?;
-            file.write_all(&buffer)?;
-            file.sync_all()?;
+            let mut storage = self.storage.write().unwrap();
+            storage.write_page(page_id, &buffer)?;
+            self.stats.page_written(PAGE_SIZE);
+            if self.sync_mode == SyncMode::Full {
+                storage.sync()?;
+                self.stats.synced();
+            } else {
+                self.unsynced = true;
+            }
         };
 
         Ok(())
     }
 
-    /// Read data from a specific page
-    pub fn read_page(&self, page_id: PageId) -> io::Result<[u8; PAGE_SIZE]> {
-        // Check page ID validity
-        if page_id >= self.num_pages {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                format!("Invalid page ID: {} (max: {})", page_id, self.num_pages - 1),
-            ));
+    /// Overwrite part of a page, writing only `data` instead of the whole page.
+    ///
+    /// Compressed or encrypted pages have to be re-encoded as a whole, so for
+    /// those this falls back to a read-modify-write of the full page.
+    pub fn write_at(&mut self, page_id: PageId, offset: usize, data: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        self.check_page_id(page_id)?;
+
+        if offset + data.len() > PAGE_SIZE {
+            return Err(HozonError::InvalidInput(format!(
+                "Write of {} bytes at offset {} crosses the end of the page",
+                data.len(),
+                offset
+            )));
         }
 
-        let offset = (page_id as u64) * (PAGE_SIZE as u64);
-        let mut buf = [0u8; PAGE_SIZE];
+        if self.encodes_page(page_id) {
+            let mut page = self.read_page(page_id)?;
+            page[offset..offset + data.len()].copy_from_slice(data);
+            return self.write_page(page_id, &page);
+        }
+
+        if self.sync_mode == SyncMode::Full {
+            self.flush_header()?;
+        }
+
+        self.save_undo(page_id)?;
+        self.changes.mark(page_id);
         {
-            let mut file = self.file.lock().unwrap();
-            file.seek(SeekFrom::Start(offset as u64))?;
-            file.read_exact(&mut buf)?;
+            let mut storage = self.storage.write().unwrap();
+            storage.write_at(page_id, offset, data)?;
+            self.stats.page_written(data.len());
+            if self.sync_mode == SyncMode::Full {
+                storage.sync()?;
+                self.stats.synced();
+            } else {
+                self.unsynced = true;
+            }
         };
 
-        Ok(buf)
-    }
-
-    /// Get total number of pages
-    pub fn num_pages(&self) -> u32 {
-        self.num_pages
+        Ok(())
     }
 
-    fn init_page_metadata_buffer(page_data: &mut [u8; PAGE_SIZE]) {
-        page_data[OFFSET_IS_FULL] = 0;
-        page_data[OFFSET_LAST_OFFSET..OFFSET_LAST_OFFSET + 2]
-            .copy_from_slice(&(PAGE_DATA_START as u16).to_le_bytes());
-        page_data[OFFSET_NUM_ROWS..OFFSET_NUM_ROWS + 2].copy_from_slice(&0u16.to_le_bytes());
+    /// Whether the stored form of a page differs from its contents
+    fn encodes_page(&self, page_id: PageId) -> bool {
+        (self.compressed && page_id >= FIRST_DATA_PAGE) || (self.is_encrypted() && page_id > 0)
     }
 
-    /// Read metadata from a page
-    pub fn read_page_metadata(&self, page_id: PageId) -> io::Result<PageMetadata> {
-        let page_data = self.read_page(page_id)?;
-        Ok(Self::read_metadata_from_buffer(&page_data))
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(backend::read_only_error());
+        }
+        Ok(())
     }
 
-    /// Update metadata for a page
-    pub fn update_page_metadata(
-        &mut self,
-        page_id: PageId,
-        metadata: &PageMetadata,
-    ) -> io::Result<()> {
-        let mut page_data = self.read_page(page_id)?;
-        Self::update_metadata_in_buffer(&mut page_data, metadata);
-        self.write_page(page_id, &page_data)?;
+    fn check_page_id(&self, page_id: PageId) -> Result<()> {
+        if page_id >= self.num_pages {
+            return Err(HozonError::InvalidInput(format!(
+                "Invalid page ID: {} (max: {})",
+                page_id,
+                self.num_pages - 1
+            )));
+        }
         Ok(())
     }
 
-    pub fn read_metadata_from_buffer(page_data: &[u8; PAGE_SIZE]) -> PageMetadata {
-        let is_full = page_data[OFFSET_IS_FULL] != 0;
-
-        let last_offset = u16::from_le_bytes([
-            page_data[OFFSET_LAST_OFFSET],
-            page_data[OFFSET_LAST_OFFSET + 1],
-        ]) as usize;
+    pub fn sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
 
-        let num_rows =
-            u16::from_le_bytes([page_data[OFFSET_NUM_ROWS], page_data[OFFSET_NUM_ROWS + 1]])
-                as usize;
+    pub fn set_sync_mode(&mut self, mode: SyncMode) -> Result<()> {
+        // Don't carry unsynced writes into a stricter mode
+        if mode != SyncMode::Off {
+            self.sync()?;
+        }
+        self.sync_mode = mode;
+        Ok(())
+    }
 
-        PageMetadata {
-            is_full,
-            last_offset,
-            num_rows,
+    /// Commit boundary: make pending writes durable unless sync mode is OFF
+    pub fn commit(&mut self) -> Result<()> {
+        match self.sync_mode {
+            SyncMode::Off => Ok(()),
+            SyncMode::Normal | SyncMode::Full => self.sync(),
         }
     }
 
-    pub fn update_metadata_in_buffer(page_data: &mut [u8; PAGE_SIZE], metadata: &PageMetadata) {
-        page_data[OFFSET_IS_FULL] = if metadata.is_full { 1 } else { 0 };
-        page_data[OFFSET_LAST_OFFSET..OFFSET_LAST_OFFSET + 2]
-            .copy_from_slice(&(metadata.last_offset as u16).to_le_bytes());
-        page_data[OFFSET_NUM_ROWS..OFFSET_NUM_ROWS + 2]
-            .copy_from_slice(&(metadata.num_rows as u16).to_le_bytes());
+    /// Open a savepoint: rolling back to it undoes every write made since
+    pub fn savepoint(&mut self, name: &str) {
+        self.undo.begin(name, self.num_pages);
     }
-}
 
-impl Drop for PageManager {
-    fn drop(&mut self) {
-        // This runs automatically when PageManager is destroyed
-        // Clean up the lock file
-        let _ = Self::release_lock(&self.lock_path);
+    pub fn in_savepoint(&self) -> bool {
+        self.undo.is_active()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
+    /// Close the savepoint `name`, and those opened after it, keeping
+    /// their writes
+    pub fn release(&mut self, name: &str) -> Result<()> {
+        self.undo.release(name)
+    }
 
-    #[test]
-    fn test_page_manager_new() {
-        let _ = fs::remove_file("test.db");
-        let _ = fs::remove_file("test.db.lock");
+    /// Put every page back as it was when the savepoint `name` was opened,
+    /// which stays open
+    pub fn rollback_to(&mut self, name: &str) -> Result<()> {
+        let (num_pages, images) = self.undo.rollback_to(name)?;
+        self.restore(num_pages, images)
+    }
 
-        let pm = PageManager::new("test.db");
-        assert!(pm.is_ok());
-        assert_eq!(pm.unwrap().num_pages(), 1);
+    fn restore(
+        &mut self,
+        num_pages: u32,
+        images: Vec<(PageId, Box<[u8; PAGE_SIZE]>)>,
+    ) -> Result<()> {
+        {
+            let mut storage = self.storage.write().unwrap();
+            for (page_id, image) in images {
+                storage.write_page(page_id, &image)?;
+                self.stats.page_written(PAGE_SIZE);
+                self.changes.mark(page_id);
+            }
+        }
+        self.num_pages = num_pages;
+        self.header_dirty = true;
+        self.unsynced = true;
+        self.flush_header()
+    }
 
-        let pm2 = PageManager::new("test.db");
-        assert!(pm2.is_ok());
-        assert_eq!(pm2.unwrap().num_pages(), 1);
+    /// Save the stored bytes of `page_id` before its first write since the
+    /// newest savepoint
+    fn save_undo(&mut self, page_id: PageId) -> Result<()> {
+        if self.undo.needs_image(page_id) {
+            let mut image = [0u8; PAGE_SIZE];
+            self.storage
+                .read()
+                .unwrap()
+                .read_page(page_id, &mut image)?;
+            self.stats.page_read();
+            self.undo.save_image(page_id, image);
+        }
+        Ok(())
+    }
 
-        let _ = fs::remove_file("test.db");
-        let _ = fs::remove_file("test.db.lock");
+    /// Flush all pending writes to the database file now, whatever the sync mode
+    pub fn checkpoint(&mut self) -> Result<()> {
+        self.flush_header()?;
+        self.storage.write().unwrap().sync()?;
+        self.stats.synced();
+        self.unsynced = false;
+        Ok(())
     }
 
-    #[test]
-    fn test_allocate_page() {
-        let _ = fs::remove_file("test_alloc.db");
-        let _ = fs::remove_file("test_alloc.db.lock");
+    /// I/O done since the database was opened or the stats were reset
+    pub fn stats(&self) -> IoStats {
+        self.stats.snapshot()
+    }
 
-        let mut pm = PageManager::new("test_alloc.db").unwrap();
-        assert_eq!(pm.num_pages(), 1);
+    pub fn reset_stats(&self) {
+        self.stats.reset();
+    }
 
-        let page_id = pm.allocate_page().unwrap();
-        assert_eq!(page_id, 1);
-        assert_eq!(pm.num_pages(), 2);
+    /// Checkpoint in the background every `interval`; None stops the thread
+    pub fn set_checkpoint_interval(&mut self, interval: Option<Duration>) -> Result<()> {
+        // Stop (and join) the old thread before anything else
+        self.checkpointer = None;
 
-        let page_id = pm.allocate_page().unwrap();
-        assert_eq!(page_id, 2);
-        assert_eq!(pm.num_pages(), 3);
+        if let Some(interval) = interval {
+            if interval.is_zero() {
+                return Err(HozonError::InvalidInput(
+                    "Checkpoint interval must be greater than zero".to_string(),
+                ));
+            }
+            self.flush_header()?;
+            self.checkpointer = Some(Checkpointer::start(
+                self.storage.clone(),
+                self.stats.clone(),
+                interval,
+            ));
+        }
+        Ok(())
+    }
 
-        drop(pm);
-        let pm = PageManager::new("test_alloc.db").unwrap();
-        assert_eq!(pm.num_pages(), 3);
+    pub fn checkpoint_interval(&self) -> Option<Duration> {
+        self.checkpointer.as_ref().map(|c| c.interval())
+    }
 
-        let _ = fs::remove_file("test_alloc.db");
-        let _ = fs::remove_file("test_alloc.db.lock");
+    /// Force any unsynced writes to durable storage, whatever the sync mode
+    pub fn sync(&mut self) -> Result<()> {
+        self.flush_header()?;
+        if self.unsynced {
+            self.storage.write().unwrap().sync()?;
+            self.stats.synced();
+            self.unsynced = false;
+        }
+        Ok(())
+    }
+
+    /// Read data from a specific page
+    pub fn read_page(&self, page_id: PageId) -> Result<[u8; PAGE_SIZE]> {
+        let buf = self.read_page_unchecked(page_id)?;
+
+        if page_id >= FIRST_DATA_PAGE {
+            Self::check_page_type(page_id, &buf)?;
+        }
+        Ok(buf)
+    }
+
+    /// Read a page without validating its type (for migrations and recovery tools)
+    pub(crate) fn read_page_unchecked(&self, page_id: PageId) -> Result<[u8; PAGE_SIZE]> {
+        self.check_page_id(page_id)?;
+
+        let mut buf = [0u8; PAGE_SIZE];
+        self.storage.read().unwrap().read_page(page_id, &mut buf)?;
+        self.stats.page_read();
+
+        if page_id == 0 {
+            buf[4..8].copy_from_slice(&self.num_pages.to_le_bytes());
+        }
+
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher
+            && page_id > 0
+        {
+            cipher.decrypt_page(page_id, &mut buf);
+        }
+
+        if self.compressed && page_id >= FIRST_DATA_PAGE {
+            buf = Self::decompress_page(&buf)?;
+        }
+
+        Ok(buf)
+    }
+
+    /// Type of page `page_id`
+    pub fn page_type(&self, page_id: PageId) -> Result<PageType> {
+        match page_id {
+            0 => Ok(PageType::Header),
+            1 => Ok(PageType::Catalog),
+            _ => Self::check_page_type(page_id, &self.read_page_unchecked(page_id)?),
+        }
+    }
+
+    /// Page type recorded in a data page's metadata, None if the byte is invalid
+    pub fn page_type_from_buffer(page_data: &[u8; PAGE_SIZE]) -> Option<PageType> {
+        match PageType::from_byte(page_data[OFFSET_PAGE_TYPE]) {
+            Some(PageType::Header | PageType::Catalog) | None => None,
+            page_type => page_type,
+        }
+    }
+
+    fn check_page_type(page_id: PageId, page_data: &[u8; PAGE_SIZE]) -> Result<PageType> {
+        match Self::page_type_from_buffer(page_data) {
+            Some(page_type) => Ok(page_type),
+            None => Err(HozonError::Corruption(format!(
+                "Corrupt page {}: invalid page type byte {}",
+                page_id, page_data[OFFSET_PAGE_TYPE]
+            ))),
+        }
+    }
+
+    /// Whether page payloads are stored compressed
+    pub fn engine(&self) -> StorageEngine {
+        self.engine
+    }
+
+    /// Path of the database file; None for in-memory and custom backends
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// Whether pages are stored encrypted
+    #[cfg(feature = "encryption")]
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    pub fn is_encrypted(&self) -> bool {
+        false
+    }
+
+    /// Compress the data area of a page, leaving its metadata readable.
+    ///
+    /// Pages that don't shrink enough to hold the length prefix are stored raw,
+    /// so every page always fits in its slot.
+    fn compress_page(page: &[u8; PAGE_SIZE]) -> Result<[u8; PAGE_SIZE]> {
+        if page[0] & PAGE_FLAG_COMPRESSED != 0 {
+            return Err(HozonError::InvalidInput(
+                "Page metadata uses the reserved compression bit".to_string(),
+            ));
+        }
+
+        let packed = compress::compress(&page[COMPRESS_CLEAR..]);
+        if COMPRESS_CLEAR + 2 + packed.len() > PAGE_SIZE {
+            return Ok(*page);
+        }
+
+        let mut stored = [0u8; PAGE_SIZE];
+        stored[..COMPRESS_CLEAR].copy_from_slice(&page[..COMPRESS_CLEAR]);
+        stored[0] |= PAGE_FLAG_COMPRESSED;
+        stored[COMPRESS_CLEAR..COMPRESS_CLEAR + 2]
+            .copy_from_slice(&(packed.len() as u16).to_le_bytes());
+        stored[COMPRESS_CLEAR + 2..COMPRESS_CLEAR + 2 + packed.len()].copy_from_slice(&packed);
+        Ok(stored)
+    }
+
+    fn decompress_page(stored: &[u8; PAGE_SIZE]) -> Result<[u8; PAGE_SIZE]> {
+        if stored[0] & PAGE_FLAG_COMPRESSED == 0 {
+            return Ok(*stored);
+        }
+
+        let len = u16::from_le_bytes([stored[COMPRESS_CLEAR], stored[COMPRESS_CLEAR + 1]]) as usize;
+        if COMPRESS_CLEAR + 2 + len > PAGE_SIZE {
+            return Err(HozonError::Corruption(
+                "Corrupt page: compressed length out of range".to_string(),
+            ));
+        }
+        let data = compress::decompress(
+            &stored[COMPRESS_CLEAR + 2..COMPRESS_CLEAR + 2 + len],
+            PAGE_SIZE - COMPRESS_CLEAR,
+        )?;
+
+        let mut page = [0u8; PAGE_SIZE];
+        page[..COMPRESS_CLEAR].copy_from_slice(&stored[..COMPRESS_CLEAR]);
+        page[0] &= !PAGE_FLAG_COMPRESSED;
+        page[COMPRESS_CLEAR..].copy_from_slice(&data);
+        Ok(page)
+    }
+
+    /// Get total number of pages
+    pub fn num_pages(&self) -> u32 {
+        self.num_pages
+    }
+
+    fn init_page_metadata_buffer(page_data: &mut [u8; PAGE_SIZE]) {
+        page_data[..PAGE_METADATA_SIZE].fill(0);
+        page_data[OFFSET_PAGE_TYPE] = PageType::TableData as u8;
+        write_u32(page_data, OFFSET_LAST_OFFSET, PAGE_DATA_START as u32);
+        write_u32(page_data, OFFSET_FREE_END, PAGE_SIZE as u32);
+    }
+
+    /// Read metadata from a page
+    pub fn read_page_metadata(&self, page_id: PageId) -> Result<PageMetadata> {
+        let page_data = self.read_page(page_id)?;
+        Ok(Self::read_metadata_from_buffer(&page_data))
+    }
+
+    /// Update metadata for a page
+    pub fn update_page_metadata(&mut self, page_id: PageId, metadata: &PageMetadata) -> Result<()> {
+        // Only the metadata block is rewritten, the rows are left alone
+        let mut page_data = self.read_page(page_id)?;
+        Self::update_metadata_in_buffer(&mut page_data, metadata);
+        self.write_at(page_id, 0, &page_data[..PAGE_METADATA_SIZE])
+    }
+
+    pub fn read_metadata_from_buffer(page_data: &[u8; PAGE_SIZE]) -> PageMetadata {
+        PageMetadata {
+            is_full: page_data[OFFSET_IS_FULL] != 0,
+            last_offset: read_u32(page_data, OFFSET_LAST_OFFSET) as usize,
+            num_rows: read_u32(page_data, OFFSET_NUM_ROWS) as usize,
+            free_end: read_u32(page_data, OFFSET_FREE_END) as usize,
+        }
+    }
+
+    pub fn update_metadata_in_buffer(page_data: &mut [u8; PAGE_SIZE], metadata: &PageMetadata) {
+        page_data[OFFSET_IS_FULL] = if metadata.is_full { 1 } else { 0 };
+        write_u32(page_data, OFFSET_LAST_OFFSET, metadata.last_offset as u32);
+        write_u32(page_data, OFFSET_NUM_ROWS, metadata.num_rows as u32);
+        write_u32(page_data, OFFSET_FREE_END, metadata.free_end as u32);
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Owner record stored in a header page, None after a clean shutdown
+pub(crate) fn read_owner(header: &[u8; PAGE_SIZE]) -> Option<LockOwner> {
+    let bytes = &header[HEADER_OWNER..HEADER_OWNER + 12];
+
+    let pid = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    if pid == 0 {
+        return None; // clean shutdown
+    }
+    let mut since_bytes = [0u8; 8];
+    since_bytes.copy_from_slice(&bytes[4..12]);
+
+    Some(LockOwner {
+        pid,
+        since: u64::from_le_bytes(since_bytes),
+    })
+}
+
+fn write_owner(header: &mut [u8; PAGE_SIZE], owner: &LockOwner) {
+    header[HEADER_OWNER..HEADER_OWNER + 4].copy_from_slice(&owner.pid.to_le_bytes());
+    header[HEADER_OWNER + 4..HEADER_OWNER + 12].copy_from_slice(&owner.since.to_le_bytes());
+}
+
+impl Drop for PageManager {
+    fn drop(&mut self) {
+        // The checkpoint thread shares the storage; stop it first
+        self.checkpointer = None;
+
+        // Savepoints still open are abandoned
+        if let Some((num_pages, images)) = self.undo.rollback_all() {
+            let _ = self.restore(num_pages, images);
+        }
+
+        // A reader never took ownership
+        if self.read_only {
+            return;
+        }
+
+        // Clear the owner record so the next open sees a clean shutdown.
+        // The OS lock itself goes away with the file handle.
+        let num_pages = self.num_pages;
+        if let Ok(mut storage) = self.storage.write() {
+            if !storage.is_persistent() {
+                return;
+            }
+            let mut header = [0u8; PAGE_SIZE];
+            if storage.read_page(0, &mut header).is_ok() {
+                write_owner(&mut header, &LockOwner { pid: 0, since: 0 });
+                header[4..8].copy_from_slice(&num_pages.to_le_bytes());
+                let _ = storage.write_page(0, &header);
+                // Give back the unused end of the last extent
+                let _ = storage.allocate(num_pages);
+                let _ = storage.sync();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::path::Path;
+
+    #[test]
+    fn test_page_manager_new() {
+        let _ = fs::remove_file("test.db");
+        let _ = fs::remove_file("test.db.lock");
+
+        let pm = PageManager::new("test.db");
+        assert!(pm.is_ok());
+        assert_eq!(pm.unwrap().num_pages(), 1);
+
+        let pm2 = PageManager::new("test.db");
+        assert!(pm2.is_ok());
+        assert_eq!(pm2.unwrap().num_pages(), 1);
+
+        let _ = fs::remove_file("test.db");
+        let _ = fs::remove_file("test.db.lock");
+    }
+
+    #[test]
+    fn test_allocate_page() {
+        let _ = fs::remove_file("test_alloc.db");
+        let _ = fs::remove_file("test_alloc.db.lock");
+
+        let mut pm = PageManager::new("test_alloc.db").unwrap();
+        assert_eq!(pm.num_pages(), 1);
+
+        let page_id = pm.allocate_page().unwrap();
+        assert_eq!(page_id, 1);
+        assert_eq!(pm.num_pages(), 2);
+
+        let page_id = pm.allocate_page().unwrap();
+        assert_eq!(page_id, 2);
+        assert_eq!(pm.num_pages(), 3);
+
+        drop(pm);
+        let pm = PageManager::new("test_alloc.db").unwrap();
+        assert_eq!(pm.num_pages(), 3);
+
+        let _ = fs::remove_file("test_alloc.db");
+        let _ = fs::remove_file("test_alloc.db.lock");
     }
 
     #[test]
@@ -327,7 +1323,7 @@ mod tests {
         // Second connection should fail
         let pm2 = PageManager::new("test_lock.db");
         assert!(pm2.is_err());
-        assert_eq!(pm2.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert!(matches!(pm2, Err(HozonError::Locked(_))));
 
         // After dropping pm1, lock should be released
         drop(_pm1);
@@ -340,6 +1336,736 @@ mod tests {
         let _ = fs::remove_file("test_lock.db.lock");
     }
 
+    #[test]
+    fn test_leftover_lock_file_does_not_block() {
+        let _ = fs::remove_file("test_stale_marker.db");
+
+        // A marker left behind by an old version (or a crash) must not matter
+        fs::write("test_stale_marker.db.lock", b"").unwrap();
+
+        let pm = PageManager::new("test_stale_marker.db");
+        assert!(pm.is_ok());
+        drop(pm);
+
+        fs::remove_file("test_stale_marker.db.lock").unwrap();
+
+        // Opening never creates a marker file
+        let _pm = PageManager::new("test_stale_marker.db").unwrap();
+        assert!(!Path::new("test_stale_marker.db.lock").exists());
+
+        let _ = fs::remove_file("test_stale_marker.db");
+    }
+
+    #[test]
+    fn test_read_only() {
+        let path = "test_read_only.db";
+        let _ = fs::remove_file(path);
+        let err = PageManager::new_read_only(path).unwrap_err();
+        assert!(matches!(err, HozonError::NotFound(_)), "{:?}", err);
+
+        let mut pm = PageManager::new(path).unwrap();
+        let page_id = pm.allocate_page().unwrap();
+        pm.write_at(page_id, 100, b"kept").unwrap();
+        // A writer keeps readers out
+        let err = PageManager::new_read_only(path).unwrap_err();
+        assert!(matches!(err, HozonError::Locked(_)), "{:?}", err);
+        drop(pm);
+        let before = fs::read(path).unwrap();
+
+        let mut pm = PageManager::new_read_only(path).unwrap();
+        assert!(pm.is_read_only());
+        // Readers share the file, but keep a writer out
+        let other = PageManager::new_read_only(path).unwrap();
+        assert!(matches!(PageManager::new(path), Err(HozonError::Locked(_))));
+        assert_eq!(&pm.read_page(page_id).unwrap()[100..104], b"kept");
+        assert!(pm.lock_owner().unwrap().is_none());
+
+        let err = pm.write_at(page_id, 100, b"lost").unwrap_err();
+        assert!(matches!(err, HozonError::ReadOnly(_)), "{:?}", err);
+        assert!(pm.allocate_page().is_err());
+        assert_eq!(pm.num_pages(), page_id + 1);
+        pm.commit().unwrap();
+        drop(pm);
+        drop(other);
+        assert_eq!(fs::read(path).unwrap(), before);
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_owner_recorded_and_cleared() {
+        let _ = fs::remove_file("test_owner.db");
+
+        let pm = PageManager::new("test_owner.db").unwrap();
+        let owner = pm.lock_owner().unwrap().unwrap();
+        assert_eq!(owner.pid, std::process::id());
+        assert!(owner.since > 0);
+        drop(pm);
+
+        // Clean shutdown leaves no owner behind
+        let mut file = File::open("test_owner.db").unwrap();
+        let mut header = [0u8; PAGE_SIZE];
+        file.read_exact(&mut header).unwrap();
+        assert!(
+            header[HEADER_OWNER..HEADER_OWNER + 12]
+                .iter()
+                .all(|&b| b == 0)
+        );
+
+        let _ = fs::remove_file("test_owner.db");
+    }
+
+    fn write_fake_owner(path: &str, pid: u32) {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        let mut header = [0u8; PAGE_SIZE];
+        file.read_exact(&mut header).unwrap();
+        write_owner(&mut header, &LockOwner { pid, since: 1 });
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(&header).unwrap();
+    }
+
+    #[test]
+    fn test_incremental_backup_copies_changed_pages() {
+        let _ = fs::remove_file("test_incr_dst.db");
+        let _ = fs::remove_file("test_incr_dst.db.manifest");
+
+        let mut pm = PageManager::new_in_memory().unwrap();
+        for _ in 0..4 {
+            pm.allocate_page().unwrap();
+        }
+
+        // First run has no manifest, so everything is copied
+        let stats = pm.backup_incremental("test_incr_dst.db").unwrap();
+        assert_eq!(stats.pages_copied, 5);
+        assert_eq!(stats.total_pages, 5);
+
+        // Nothing changed: only the header
+        let stats = pm.backup_incremental("test_incr_dst.db").unwrap();
+        assert_eq!(stats.pages_copied, 1);
+
+        // Header, the rewritten page and the new one
+        pm.write_page(2, b"changed").unwrap();
+        pm.allocate_page().unwrap();
+        let stats = pm.backup_incremental("test_incr_dst.db").unwrap();
+        assert_eq!(stats.pages_copied, 3);
+        assert_eq!(stats.total_pages, 6);
+
+        // A different session can't trust the manifest
+        let mut other = PageManager::new_in_memory().unwrap();
+        for _ in 0..5 {
+            other.allocate_page().unwrap();
+        }
+        let stats = other.backup_incremental("test_incr_dst.db").unwrap();
+        assert_eq!(stats.pages_copied, 6);
+
+        let _ = fs::remove_file("test_incr_dst.db");
+        let _ = fs::remove_file("test_incr_dst.db.manifest");
+    }
+
+    #[test]
+    fn test_incremental_backup_matches_source() {
+        let _ = fs::remove_file("test_incr_copy.db");
+        let _ = fs::remove_file("test_incr_copy.db.manifest");
+
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.allocate_page().unwrap();
+        let page_id = pm.allocate_page().unwrap();
+        pm.backup_incremental("test_incr_copy.db").unwrap();
+
+        pm.write_at(page_id, PAGE_DATA_START, b"second").unwrap();
+        pm.backup_incremental("test_incr_copy.db").unwrap();
+
+        let copy = PageManager::new("test_incr_copy.db").unwrap();
+        assert_eq!(copy.num_pages(), 3);
+        let page = copy.read_page(page_id).unwrap();
+        assert_eq!(&page[PAGE_DATA_START..PAGE_DATA_START + 6], b"second");
+        drop(copy);
+
+        let _ = fs::remove_file("test_incr_copy.db");
+        let _ = fs::remove_file("test_incr_copy.db.manifest");
+    }
+
+    #[test]
+    fn test_page_types() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.allocate_page().unwrap();
+        let table = pm.allocate_page().unwrap();
+        let index = pm.allocate_page_of_type(PageType::Index).unwrap();
+
+        assert_eq!(pm.page_type(0).unwrap(), PageType::Header);
+        assert_eq!(pm.page_type(1).unwrap(), PageType::Catalog);
+        assert_eq!(pm.page_type(table).unwrap(), PageType::TableData);
+        assert_eq!(pm.page_type(index).unwrap(), PageType::Index);
+        assert!(pm.allocate_page_of_type(PageType::Catalog).is_err());
+
+        // A data page whose type byte is garbage is reported as corrupt
+        let mut page = pm.read_page(table).unwrap();
+        page[OFFSET_PAGE_TYPE] = 0xEE;
+        pm.write_page(table, &page).unwrap();
+        let err = pm.read_page(table).unwrap_err();
+        assert!(matches!(err, HozonError::Corruption(_)), "{:?}", err);
+        assert!(err.to_string().contains("invalid page type"));
+    }
+
+    #[test]
+    fn test_backup_while_open() {
+        let _ = fs::remove_file("test_backup_src.db");
+        let _ = fs::remove_file("test_backup_dst.db");
+
+        let mut pm = PageManager::new("test_backup_src.db").unwrap();
+        let page_id = pm.allocate_page().unwrap();
+        pm.write_page(page_id, b"snapshot").unwrap();
+
+        assert_eq!(pm.backup_to("test_backup_dst.db").unwrap(), 2);
+        // Can't back up over a database that is open
+        assert!(pm.backup_to("test_backup_src.db").is_err());
+
+        // Source keeps working, later writes don't reach the backup
+        pm.write_page(page_id, b"changed").unwrap();
+
+        let copy = PageManager::new("test_backup_dst.db").unwrap();
+        assert_eq!(copy.num_pages(), 2);
+        assert_eq!(&copy.read_page(page_id).unwrap()[..8], b"snapshot");
+        assert_eq!(&pm.read_page(page_id).unwrap()[..7], b"changed");
+
+        drop(copy);
+        drop(pm);
+        let _ = fs::remove_file("test_backup_src.db");
+        let _ = fs::remove_file("test_backup_dst.db");
+    }
+
+    #[test]
+    fn test_newer_format_version_rejected() {
+        let _ = fs::remove_file("test_newer_version.db");
+
+        let mut pm = PageManager::new("test_newer_version.db").unwrap();
+        assert_eq!(pm.format_version().unwrap(), FORMAT_VERSION);
+        pm.set_format_version(FORMAT_VERSION + 1).unwrap();
+        drop(pm);
+
+        let err = PageManager::new("test_newer_version.db").unwrap_err();
+        assert!(matches!(err, HozonError::Unsupported(_)), "{:?}", err);
+        assert!(err.to_string().contains("newer than this build supports"));
+
+        let _ = fs::remove_file("test_newer_version.db");
+    }
+
+    #[test]
+    fn test_stale_owner_is_reclaimed() {
+        let _ = fs::remove_file("test_stale_owner.db");
+        drop(PageManager::new("test_stale_owner.db").unwrap());
+
+        // Simulate a crashed session: owner recorded but the process is gone
+        write_fake_owner("test_stale_owner.db", u32::MAX - 1);
+
+        let pm = PageManager::new("test_stale_owner.db").unwrap();
+        assert_eq!(pm.lock_owner().unwrap().unwrap().pid, std::process::id());
+
+        drop(pm);
+        let _ = fs::remove_file("test_stale_owner.db");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_live_owner_requires_force() {
+        let _ = fs::remove_file("test_live_owner.db");
+        drop(PageManager::new("test_live_owner.db").unwrap());
+
+        // PID 1 is always running, but doesn't hold our OS lock
+        write_fake_owner("test_live_owner.db", 1);
+
+        let err = PageManager::new("test_live_owner.db").unwrap_err();
+        assert!(matches!(err, HozonError::Locked(_)), "{:?}", err);
+        assert!(err.to_string().contains("PID 1 "), "{}", err);
+        // Refused the same way through the library, which can force it too
+        let err = crate::Database::open("test_live_owner.db").err().unwrap();
+        assert!(matches!(err, HozonError::Locked(_)), "{:?}", err);
+        drop(crate::Database::open_with_force("test_live_owner.db").unwrap());
+        write_fake_owner("test_live_owner.db", 1);
+
+        let pm = PageManager::new_with_force("test_live_owner.db").unwrap();
+        assert_eq!(pm.lock_owner().unwrap().unwrap().pid, std::process::id());
+
+        drop(pm);
+        let _ = fs::remove_file("test_live_owner.db");
+    }
+
+    #[test]
+    fn test_in_memory_database() {
+        let mut pm = PageManager::new(MEMORY_PATH).unwrap();
+        assert!(pm.is_in_memory());
+        assert_eq!(pm.num_pages(), 1);
+
+        let page_id = pm.allocate_page().unwrap();
+        pm.write_page(page_id, b"in memory").unwrap();
+        assert_eq!(&pm.read_page(page_id).unwrap()[0..9], b"in memory");
+
+        // Nothing touches the filesystem, and there is no lock to conflict on
+        assert!(!Path::new(MEMORY_PATH).exists());
+        let other = PageManager::new(MEMORY_PATH).unwrap();
+        assert_eq!(other.num_pages(), 1);
+        assert!(pm.lock_owner().unwrap().is_none());
+    }
+
+    /// Backend that wraps MemBackend and counts writes, to prove any
+    /// StorageBackend can sit under a PageManager
+    #[derive(Debug, Default)]
+    struct CountingBackend {
+        inner: MemBackend,
+        writes: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        syncs: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        bytes: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        allocations: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        header_writes: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl StorageBackend for CountingBackend {
+        fn read_page(&self, page_id: PageId, buf: &mut [u8; PAGE_SIZE]) -> Result<()> {
+            self.inner.read_page(page_id, buf)
+        }
+
+        fn write_page(&mut self, page_id: PageId, buf: &[u8; PAGE_SIZE]) -> Result<()> {
+            self.writes
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.bytes
+                .fetch_add(PAGE_SIZE, std::sync::atomic::Ordering::SeqCst);
+            if page_id == 0 {
+                self.header_writes
+                    .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            }
+            self.inner.write_page(page_id, buf)
+        }
+
+        fn write_at(&mut self, page_id: PageId, offset: usize, data: &[u8]) -> Result<()> {
+            self.writes
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.bytes
+                .fetch_add(data.len(), std::sync::atomic::Ordering::SeqCst);
+            self.inner.write_at(page_id, offset, data)
+        }
+
+        fn allocate(&mut self, num_pages: u32) -> Result<()> {
+            self.allocations
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.inner.allocate(num_pages)
+        }
+
+        fn sync(&mut self) -> Result<()> {
+            self.syncs.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.inner.sync()
+        }
+
+        fn num_pages(&self) -> Result<u32> {
+            self.inner.num_pages()
+        }
+
+        fn is_persistent(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_custom_backend() {
+        let backend = CountingBackend::default();
+        let writes = backend.writes.clone();
+
+        let mut pm = PageManager::with_backend(backend).unwrap();
+        let page_id = pm.allocate_page().unwrap();
+        pm.write_page(page_id, b"custom").unwrap();
+
+        assert_eq!(&pm.read_page(page_id).unwrap()[0..6], b"custom");
+        assert!(writes.load(std::sync::atomic::Ordering::SeqCst) >= 3);
+    }
+
+    #[test]
+    fn test_sync_modes() {
+        use std::sync::atomic::Ordering;
+
+        let backend = CountingBackend::default();
+        let syncs = backend.syncs.clone();
+        let mut pm = PageManager::with_backend(backend).unwrap();
+        assert_eq!(pm.sync_mode(), SyncMode::Full);
+        let page_id = pm.allocate_page().unwrap();
+
+        // FULL: every write is synced
+        let before = syncs.load(Ordering::SeqCst);
+        pm.write_page(page_id, b"a").unwrap();
+        pm.write_page(page_id, b"b").unwrap();
+        assert_eq!(syncs.load(Ordering::SeqCst), before + 2);
+
+        // NORMAL: one sync at the commit boundary
+        pm.set_sync_mode(SyncMode::Normal).unwrap();
+        let before = syncs.load(Ordering::SeqCst);
+        pm.write_page(page_id, b"c").unwrap();
+        pm.write_page(page_id, b"d").unwrap();
+        assert_eq!(syncs.load(Ordering::SeqCst), before);
+        pm.commit().unwrap();
+        assert_eq!(syncs.load(Ordering::SeqCst), before + 1);
+
+        // OFF: not even at commit
+        pm.set_sync_mode(SyncMode::Off).unwrap();
+        let before = syncs.load(Ordering::SeqCst);
+        pm.write_page(page_id, b"e").unwrap();
+        pm.commit().unwrap();
+        assert_eq!(syncs.load(Ordering::SeqCst), before);
+
+        // ...but an explicit sync still flushes
+        pm.sync().unwrap();
+        assert_eq!(syncs.load(Ordering::SeqCst), before + 1);
+        assert_eq!(&pm.read_page(page_id).unwrap()[0..1], b"e");
+    }
+
+    #[test]
+    fn test_write_at_writes_only_the_range() {
+        use std::sync::atomic::Ordering;
+
+        let backend = CountingBackend::default();
+        let bytes = backend.bytes.clone();
+        let mut pm = PageManager::with_backend(backend).unwrap();
+        pm.allocate_page().unwrap();
+        let page_id = pm.allocate_page().unwrap();
+
+        let before = bytes.load(Ordering::SeqCst);
+        pm.write_at(page_id, 100, b"row").unwrap();
+        let metadata = PageMetadata {
+            is_full: false,
+            last_offset: 103,
+            num_rows: 1,
+            free_end: PAGE_SIZE,
+        };
+        pm.update_page_metadata(page_id, &metadata).unwrap();
+        assert_eq!(
+            bytes.load(Ordering::SeqCst) - before,
+            3 + PAGE_METADATA_SIZE
+        );
+
+        let page = pm.read_page(page_id).unwrap();
+        assert_eq!(&page[100..103], b"row");
+        assert_eq!(PageManager::read_metadata_from_buffer(&page).num_rows, 1);
+
+        assert!(pm.write_at(page_id, PAGE_SIZE - 1, b"ab").is_err());
+        assert!(pm.write_at(99, 0, b"ab").is_err());
+    }
+
+    #[test]
+    fn test_write_at_compressed_page() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.compressed = true;
+        pm.allocate_page().unwrap();
+        let page_id = pm.allocate_page().unwrap();
+
+        pm.write_at(page_id, PAGE_DATA_START, b"payload").unwrap();
+        let page = pm.read_page(page_id).unwrap();
+        assert_eq!(&page[PAGE_DATA_START..PAGE_DATA_START + 7], b"payload");
+        assert_eq!(
+            PageManager::read_metadata_from_buffer(&page).last_offset,
+            PAGE_DATA_START
+        );
+    }
+
+    #[test]
+    fn test_allocation_grows_in_extents() {
+        use std::sync::atomic::Ordering;
+
+        let backend = CountingBackend::default();
+        let allocations = backend.allocations.clone();
+        let header_writes = backend.header_writes.clone();
+        let mut pm = PageManager::with_backend(backend).unwrap();
+        pm.set_sync_mode(SyncMode::Normal).unwrap();
+        pm.set_extent_size(8).unwrap();
+
+        let allocations_before = allocations.load(Ordering::SeqCst);
+        let headers_before = header_writes.load(Ordering::SeqCst);
+        for _ in 0..10 {
+            pm.allocate_page().unwrap();
+        }
+        assert_eq!(pm.num_pages(), 11);
+        // 1 + 8 + 8 pages of room: two extents for ten pages
+        assert_eq!(allocations.load(Ordering::SeqCst) - allocations_before, 2);
+        assert_eq!(header_writes.load(Ordering::SeqCst), headers_before);
+
+        // The header is written once, at the commit
+        pm.commit().unwrap();
+        assert_eq!(header_writes.load(Ordering::SeqCst) - headers_before, 1);
+        assert_eq!(read_u32(&pm.read_page(0).unwrap(), 4), 11);
+
+        assert!(pm.set_extent_size(0).is_err());
+    }
+
+    #[test]
+    fn test_preallocated_space_trimmed_on_close() {
+        let _ = fs::remove_file("test_extent.db");
+
+        {
+            let mut pm = PageManager::new("test_extent.db").unwrap();
+            pm.set_sync_mode(SyncMode::Off).unwrap();
+            for _ in 0..3 {
+                pm.allocate_page().unwrap();
+            }
+            let len = fs::metadata("test_extent.db").unwrap().len();
+            assert_eq!(len, (1 + DEFAULT_EXTENT_PAGES as u64) * PAGE_SIZE as u64);
+        }
+
+        assert_eq!(
+            fs::metadata("test_extent.db").unwrap().len(),
+            4 * PAGE_SIZE as u64
+        );
+        let pm = PageManager::new("test_extent.db").unwrap();
+        assert_eq!(pm.num_pages(), 4);
+        assert_eq!(pm.page_type(3).unwrap(), PageType::TableData);
+        drop(pm);
+
+        let _ = fs::remove_file("test_extent.db");
+    }
+
+    #[test]
+    fn test_background_checkpoint() {
+        use std::sync::atomic::Ordering;
+
+        let backend = CountingBackend::default();
+        let syncs = backend.syncs.clone();
+        let mut pm = PageManager::with_backend(backend).unwrap();
+        pm.set_sync_mode(SyncMode::Off).unwrap();
+        assert_eq!(pm.checkpoint_interval(), None);
+
+        pm.set_checkpoint_interval(Some(Duration::from_millis(5)))
+            .unwrap();
+        let page_id = pm.allocate_page().unwrap();
+        pm.write_page(page_id, b"background").unwrap();
+
+        let before = syncs.load(Ordering::SeqCst);
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while syncs.load(Ordering::SeqCst) == before && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(syncs.load(Ordering::SeqCst) > before);
+
+        // Stopping joins the thread, so no more syncs after this
+        pm.set_checkpoint_interval(None).unwrap();
+        let stopped = syncs.load(Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(syncs.load(Ordering::SeqCst), stopped);
+
+        assert!(pm.set_checkpoint_interval(Some(Duration::ZERO)).is_err());
+    }
+
+    #[test]
+    fn test_manual_checkpoint_syncs_in_off_mode() {
+        let _ = fs::remove_file("test_checkpoint.db");
+
+        let mut pm = PageManager::new("test_checkpoint.db").unwrap();
+        pm.set_sync_mode(SyncMode::Off).unwrap();
+        pm.allocate_page().unwrap();
+        pm.commit().unwrap(); // OFF: nothing reaches the file
+        assert!(fs::metadata("test_checkpoint.db-dwb").is_ok());
+
+        pm.checkpoint().unwrap();
+        assert_eq!(read_physical_page("test_checkpoint.db", 0)[4], 2);
+        assert_eq!(fs::metadata("test_checkpoint.db-dwb").unwrap().len(), 0);
+
+        drop(pm);
+        let _ = fs::remove_file("test_checkpoint.db");
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<PageManager>();
+
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.allocate_page().unwrap();
+        let page_id = pm.allocate_page().unwrap();
+        pm.write_at(page_id, PAGE_DATA_START, b"shared").unwrap();
+
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let page = pm.read_page(page_id).unwrap();
+                    assert_eq!(&page[PAGE_DATA_START..PAGE_DATA_START + 6], b"shared");
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_io_stats() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.set_sync_mode(SyncMode::Full).unwrap();
+        pm.allocate_page().unwrap();
+        let page_id = pm.allocate_page().unwrap();
+        pm.reset_stats();
+
+        pm.write_at(page_id, PAGE_DATA_START, b"abc").unwrap();
+        pm.read_page(page_id).unwrap();
+        pm.sync().unwrap();
+
+        let stats = pm.stats();
+        assert_eq!(stats.pages_read, 1);
+        assert_eq!(stats.pages_written, 1);
+        assert_eq!(stats.bytes_written, 3);
+        assert_eq!(stats.syncs, 2); // FULL write + explicit sync
+
+        pm.reset_stats();
+        assert_eq!(pm.stats(), IoStats::default());
+    }
+
+    #[test]
+    fn test_sync_mode_parse() {
+        assert_eq!(SyncMode::parse("off"), Some(SyncMode::Off));
+        assert_eq!(SyncMode::parse("1"), Some(SyncMode::Normal));
+        assert_eq!(SyncMode::parse("FULL"), Some(SyncMode::Full));
+        assert_eq!(SyncMode::parse("sometimes"), None);
+    }
+
+    fn read_physical_page(path: &str, page_id: PageId) -> [u8; PAGE_SIZE] {
+        let mut file = File::open(path).unwrap();
+        let mut buf = [0u8; PAGE_SIZE];
+        file.seek(SeekFrom::Start(page_id as u64 * PAGE_SIZE as u64))
+            .unwrap();
+        file.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_compressed_pages_roundtrip() {
+        let _ = fs::remove_file("test_compressed.db");
+
+        let text = "HozonDB stores text, lots of repeated text. ".repeat(80);
+        let mut page = [0u8; PAGE_SIZE];
+        PageManager::init_page_metadata_buffer(&mut page);
+        page[PAGE_DATA_START..PAGE_DATA_START + text.len()].copy_from_slice(text.as_bytes());
+
+        {
+            let mut pm = PageManager::new_compressed("test_compressed.db").unwrap();
+            assert!(pm.is_compressed());
+            pm.allocate_page().unwrap(); // catalog
+            let page_id = pm.allocate_page().unwrap();
+            pm.write_page(page_id, &page).unwrap();
+            assert_eq!(pm.read_page(page_id).unwrap(), page);
+        }
+
+        // On disk the payload is much smaller than the text, metadata stays readable
+        let stored = read_physical_page("test_compressed.db", 2);
+        assert_ne!(stored[0] & PAGE_FLAG_COMPRESSED, 0);
+        let packed_len = u16::from_le_bytes([stored[COMPRESS_CLEAR], stored[COMPRESS_CLEAR + 1]]);
+        assert!((packed_len as usize) < text.len() / 4);
+
+        // The header remembers compression, so a plain open still decompresses
+        let pm = PageManager::new("test_compressed.db").unwrap();
+        assert!(pm.is_compressed());
+        assert_eq!(pm.read_page(2).unwrap(), page);
+        assert_eq!(
+            pm.read_page_metadata(2).unwrap().last_offset,
+            PAGE_DATA_START
+        );
+
+        drop(pm);
+        let _ = fs::remove_file("test_compressed.db");
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_pages_roundtrip() {
+        let _ = fs::remove_file("test_encrypted.db");
+
+        let secret = b"top secret row data";
+        {
+            let mut pm = PageManager::new_with_key("test_encrypted.db", "hunter2").unwrap();
+            assert!(pm.is_encrypted());
+            let page_id = pm.allocate_page().unwrap();
+            pm.write_page(page_id, secret).unwrap();
+            assert_eq!(&pm.read_page(page_id).unwrap()[..secret.len()], secret);
+        }
+
+        // Nothing readable on disk, and the header stays in the clear
+        let stored = read_physical_page("test_encrypted.db", 1);
+        assert!(!stored.windows(secret.len()).any(|w| w == secret));
+        let header = read_physical_page("test_encrypted.db", 0);
+        assert_eq!(read_u32(&header, 0), 0x484F5A4E);
+
+        let pm = PageManager::new_with_key("test_encrypted.db", "hunter2").unwrap();
+        assert_eq!(&pm.read_page(1).unwrap()[..secret.len()], secret);
+        drop(pm);
+
+        let _ = fs::remove_file("test_encrypted.db");
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_requires_correct_key() {
+        let _ = fs::remove_file("test_encrypted_key.db");
+
+        drop(PageManager::new_with_key("test_encrypted_key.db", "hunter2").unwrap());
+
+        let wrong = PageManager::new_with_key("test_encrypted_key.db", "hunter3").unwrap_err();
+        assert!(matches!(wrong, HozonError::Encryption(_)), "{:?}", wrong);
+        assert!(wrong.to_string().contains("Invalid encryption key"));
+
+        let missing = PageManager::new("test_encrypted_key.db").unwrap_err();
+        assert!(missing.to_string().contains("a key is required"));
+
+        let _ = fs::remove_file("test_encrypted_key.db");
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_and_compressed() {
+        let mut pm = PageManager::new_in_memory().unwrap();
+        pm.compressed = true;
+        pm.cipher = Some(PageCipher::derive("key", &[1u8; crypt::SALT_SIZE], 1000));
+
+        pm.allocate_page().unwrap();
+        let page_id = pm.allocate_page().unwrap();
+        let mut page = [0u8; PAGE_SIZE];
+        PageManager::init_page_metadata_buffer(&mut page);
+        page[PAGE_DATA_START..PAGE_DATA_START + 8].copy_from_slice(b"abcdabcd");
+        pm.write_page(page_id, &page).unwrap();
+        assert_eq!(pm.read_page(page_id).unwrap(), page);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_key_rejected_for_plain_database() {
+        let _ = fs::remove_file("test_plain_key.db");
+
+        drop(PageManager::new("test_plain_key.db").unwrap());
+        assert!(PageManager::new_with_key("test_plain_key.db", "hunter2").is_err());
+
+        let _ = fs::remove_file("test_plain_key.db");
+    }
+
+    #[test]
+    fn test_incompressible_page_stored_raw() {
+        let mut pm = PageManager::new(MEMORY_PATH).unwrap();
+        pm.compressed = true;
+        pm.allocate_page().unwrap();
+        let page_id = pm.allocate_page().unwrap();
+
+        let mut state = 7u32;
+        let mut page = [0u8; PAGE_SIZE];
+        PageManager::init_page_metadata_buffer(&mut page);
+        for b in page[PAGE_DATA_START..].iter_mut() {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            *b = (state >> 24) as u8;
+        }
+
+        pm.write_page(page_id, &page).unwrap();
+        assert_eq!(pm.read_page(page_id).unwrap(), page);
+
+        let stored = PageManager::compress_page(&page).unwrap();
+        assert_eq!(stored[0] & PAGE_FLAG_COMPRESSED, 0);
+    }
+
+    #[test]
+    fn test_uncompressed_by_default() {
+        let pm = PageManager::new(MEMORY_PATH).unwrap();
+        assert!(!pm.is_compressed());
+    }
+
     #[test]
     fn test_write_and_read_page() {
         let _ = fs::remove_file("test_rw.db");
@@ -437,7 +2163,7 @@ mod tests {
         let metadata = pm.read_page_metadata(page_id_2).unwrap();
 
         // Check initial values
-        assert_eq!(metadata.is_full, false);
+        assert!(!metadata.is_full);
         assert_eq!(metadata.last_offset, PAGE_DATA_START);
         assert_eq!(metadata.num_rows, 0);
 
@@ -458,13 +2184,14 @@ mod tests {
             is_full: true,
             last_offset: 100,
             num_rows: 5,
+            free_end: PAGE_SIZE,
         };
         pm.update_page_metadata(page_id, &new_metadata).unwrap();
 
         // Read it back
         let read_metadata = pm.read_page_metadata(page_id).unwrap();
 
-        assert_eq!(read_metadata.is_full, true);
+        assert!(read_metadata.is_full);
         assert_eq!(read_metadata.last_offset, 100);
         assert_eq!(read_metadata.num_rows, 5);
 
@@ -486,6 +2213,7 @@ mod tests {
                 is_full: false,
                 last_offset: 250,
                 num_rows: 10,
+                free_end: PAGE_SIZE,
             };
             pm.update_page_metadata(page_id, &metadata).unwrap();
         } // pm dropped, file closed
@@ -496,7 +2224,7 @@ mod tests {
             let metadata = pm.read_page_metadata(1).unwrap();
 
             // Metadata should persist
-            assert_eq!(metadata.is_full, false);
+            assert!(!metadata.is_full);
             assert_eq!(metadata.last_offset, 250);
             assert_eq!(metadata.num_rows, 10);
         }
@@ -521,6 +2249,7 @@ mod tests {
             is_full: true,
             last_offset: 100,
             num_rows: 3,
+            free_end: PAGE_SIZE,
         };
         pm.update_page_metadata(page1, &meta1).unwrap();
 
@@ -529,6 +2258,7 @@ mod tests {
             is_full: false,
             last_offset: 200,
             num_rows: 7,
+            free_end: PAGE_SIZE,
         };
         pm.update_page_metadata(page2, &meta2).unwrap();
 
@@ -564,6 +2294,7 @@ mod tests {
             is_full: false,
             last_offset: PAGE_DATA_START + test_data.len(),
             num_rows: 1,
+            free_end: PAGE_SIZE,
         };
         pm.update_page_metadata(page_id, &metadata).unwrap();
 
@@ -0,0 +1,227 @@
+//! Temporary storage for operators whose intermediate rows outgrow memory.
+//!
+//! A RowBuffer keeps rows in memory until they pass its byte budget, then
+//! moves them to a SpillFile: an anonymous temp file of length-prefixed
+//! rows that is deleted when dropped.
+
+use crate::catalog::row::Row;
+use crate::error::{HozonError, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Bytes of serialized rows an operator may hold before spilling
+pub const DEFAULT_SPILL_BUDGET: usize = 16 * 1024 * 1024;
+
+static NEXT_SPILL_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Rows appended to a temp file and read back in the same order
+#[derive(Debug)]
+pub struct SpillFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    rows: usize,
+}
+
+impl SpillFile {
+    pub fn new() -> Result<Self> {
+        let id = NEXT_SPILL_ID.fetch_add(1, Ordering::Relaxed);
+        let path =
+            std::env::temp_dir().join(format!("hozondb-spill-{}-{}", std::process::id(), id));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+
+        Ok(SpillFile {
+            path,
+            writer: BufWriter::new(file),
+            rows: 0,
+        })
+    }
+
+    pub fn push(&mut self, row: &Row) -> Result<()> {
+        let bytes = row.to_bytes();
+        self.writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&bytes)?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows == 0
+    }
+
+    /// Read every row back from the start; the file is removed once the
+    /// iterator is dropped
+    pub fn into_rows(mut self) -> Result<SpillRows> {
+        self.writer.flush()?;
+        let mut file = self.writer.get_ref().try_clone()?;
+        file.seek(SeekFrom::Start(0))?;
+
+        Ok(SpillRows {
+            reader: BufReader::new(file),
+            remaining: self.rows,
+            _spill: self,
+        })
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+pub struct SpillRows {
+    reader: BufReader<File>,
+    remaining: usize,
+    _spill: SpillFile,
+}
+
+impl SpillRows {
+    fn read_row(&mut self) -> Result<Row> {
+        let mut len = [0u8; 4];
+        self.reader.read_exact(&mut len)?;
+        let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+        self.reader.read_exact(&mut bytes)?;
+
+        let (row, consumed) = Row::from_bytes(&bytes)?;
+        if consumed != bytes.len() {
+            return Err(HozonError::Corruption("Corrupt spill record".to_string()));
+        }
+        Ok(row)
+    }
+}
+
+impl Iterator for SpillRows {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        Some(self.read_row())
+    }
+}
+
+/// Rows collected by an operator, moved to disk once they pass `budget`
+/// bytes. Iteration returns them in insertion order.
+#[derive(Debug)]
+pub struct RowBuffer {
+    budget: usize,
+    rows: Vec<Row>,
+    bytes: usize,
+    spill: Option<SpillFile>,
+}
+
+impl RowBuffer {
+    pub fn new(budget: usize) -> Self {
+        RowBuffer {
+            budget,
+            rows: Vec::new(),
+            bytes: 0,
+            spill: None,
+        }
+    }
+
+    pub fn push(&mut self, row: Row) -> Result<()> {
+        self.bytes += row.to_bytes().len();
+        self.rows.push(row);
+
+        if self.bytes > self.budget {
+            let spill = match &mut self.spill {
+                Some(spill) => spill,
+                None => self.spill.insert(SpillFile::new()?),
+            };
+            for row in self.rows.drain(..) {
+                spill.push(&row)?;
+            }
+            self.bytes = 0;
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len() + self.spill.as_ref().map_or(0, SpillFile::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_spilled(&self) -> bool {
+        self.spill.is_some()
+    }
+
+    pub fn into_rows(self) -> Result<Box<dyn Iterator<Item = Result<Row>>>> {
+        let in_memory = self.rows.into_iter().map(Ok);
+        match self.spill {
+            Some(spill) => Ok(Box::new(spill.into_rows()?.chain(in_memory))),
+            None => Ok(Box::new(in_memory)),
+        }
+    }
+}
+
+impl Default for RowBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_SPILL_BUDGET)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::row::Value;
+
+    fn row(i: i32) -> Row {
+        Row::new(vec![Value::Integer(i), Value::Text(format!("row {}", i))])
+    }
+
+    #[test]
+    fn test_spill_file_roundtrip() {
+        let mut spill = SpillFile::new().unwrap();
+        let path = spill.path.clone();
+        for i in 0..100 {
+            spill.push(&row(i)).unwrap();
+        }
+        assert_eq!(spill.len(), 100);
+
+        let rows: Vec<Row> = spill.into_rows().unwrap().map(Result::unwrap).collect();
+        assert_eq!(rows.len(), 100);
+        assert_eq!(rows[42].values(), row(42).values());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_row_buffer_stays_in_memory_under_budget() {
+        let mut buffer = RowBuffer::default();
+        buffer.push(row(1)).unwrap();
+        assert!(!buffer.is_spilled());
+        assert_eq!(buffer.into_rows().unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_row_buffer_spills_and_keeps_order() {
+        let mut buffer = RowBuffer::new(64);
+        for i in 0..50 {
+            buffer.push(row(i)).unwrap();
+        }
+        assert!(buffer.is_spilled());
+        assert_eq!(buffer.len(), 50);
+
+        let values: Vec<Value> = buffer
+            .into_rows()
+            .unwrap()
+            .map(|r| r.unwrap().values()[0].clone())
+            .collect();
+        assert_eq!(values, (0..50).map(Value::Integer).collect::<Vec<_>>());
+    }
+}
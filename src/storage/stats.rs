@@ -0,0 +1,77 @@
+//! I/O counters kept by PageManager, for diagnosing performance.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Live counters, shared with the checkpoint thread
+#[derive(Debug, Default)]
+pub(crate) struct IoCounters {
+    pages_read: AtomicU64,
+    pages_written: AtomicU64,
+    bytes_written: AtomicU64,
+    syncs: AtomicU64,
+}
+
+impl IoCounters {
+    pub fn page_read(&self) {
+        self.pages_read.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A write to one page, `bytes` long (less than a page for partial writes)
+    pub fn page_written(&self, bytes: usize) {
+        self.pages_written.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn synced(&self) {
+        self.syncs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> IoStats {
+        IoStats {
+            pages_read: self.pages_read.load(Ordering::Relaxed),
+            pages_written: self.pages_written.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            syncs: self.syncs.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.pages_read.store(0, Ordering::Relaxed);
+        self.pages_written.store(0, Ordering::Relaxed);
+        self.bytes_written.store(0, Ordering::Relaxed);
+        self.syncs.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Storage I/O since the database was opened (or the stats were last reset)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IoStats {
+    pub pages_read: u64,
+    /// Page writes, including partial ones from `write_at`
+    pub pages_written: u64,
+    pub bytes_written: u64,
+    pub syncs: u64,
+}
+
+impl IoStats {
+    /// The I/O done between `earlier` and these stats
+    pub fn since(&self, earlier: &IoStats) -> IoStats {
+        IoStats {
+            pages_read: self.pages_read.saturating_sub(earlier.pages_read),
+            pages_written: self.pages_written.saturating_sub(earlier.pages_written),
+            bytes_written: self.bytes_written.saturating_sub(earlier.bytes_written),
+            syncs: self.syncs.saturating_sub(earlier.syncs),
+        }
+    }
+}
+
+impl fmt::Display for IoStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "pages read:    {}", self.pages_read)?;
+        writeln!(f, "pages written: {}", self.pages_written)?;
+        writeln!(f, "bytes written: {}", self.bytes_written)?;
+        write!(f, "syncs:         {}", self.syncs)
+    }
+}
@@ -0,0 +1,153 @@
+//! Savepoints and the undo log behind them.
+//!
+//! While a savepoint is open, the first write to a page since the newest
+//! savepoint saves the page's stored bytes first. Rolling back to a
+//! savepoint writes those bytes back and forgets the pages allocated since
+//! it was set; releasing one hands its saved pages to the savepoint around
+//! it, which still needs them.
+
+use crate::error::{HozonError, Result};
+use crate::storage::page::{PAGE_SIZE, PageId};
+use std::collections::BTreeMap;
+
+type Image = Box<[u8; PAGE_SIZE]>;
+
+#[derive(Debug)]
+struct Savepoint {
+    name: String,
+    /// The page count when it was set; later pages are simply dropped
+    num_pages: u32,
+    /// Stored bytes of each page from before its first write since
+    images: BTreeMap<PageId, Image>,
+}
+
+/// The open savepoints, oldest first
+#[derive(Debug, Default)]
+pub(crate) struct UndoLog {
+    savepoints: Vec<Savepoint>,
+}
+
+impl UndoLog {
+    pub fn is_active(&self) -> bool {
+        !self.savepoints.is_empty()
+    }
+
+    pub fn begin(&mut self, name: &str, num_pages: u32) {
+        self.savepoints.push(Savepoint {
+            name: name.to_string(),
+            num_pages,
+            images: BTreeMap::new(),
+        });
+    }
+
+    /// Whether a write to `page_id` has to save the page first
+    pub fn needs_image(&self, page_id: PageId) -> bool {
+        self.savepoints
+            .last()
+            .is_some_and(|s| page_id < s.num_pages && !s.images.contains_key(&page_id))
+    }
+
+    pub fn save_image(&mut self, page_id: PageId, image: [u8; PAGE_SIZE]) {
+        if let Some(savepoint) = self.savepoints.last_mut() {
+            savepoint.images.insert(page_id, Box::new(image));
+        }
+    }
+
+    /// Close the newest savepoint called `name` and every one set after it
+    pub fn release(&mut self, name: &str) -> Result<()> {
+        let position = self.find(name)?;
+        for released in self.savepoints.split_off(position) {
+            if let Some(outer) = self.savepoints.last_mut() {
+                for (page_id, image) in released.images {
+                    if page_id < outer.num_pages {
+                        outer.images.entry(page_id).or_insert(image);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Undo back to the newest savepoint called `name`, which stays open:
+    /// the page count it was set at and the pages to write back
+    pub fn rollback_to(&mut self, name: &str) -> Result<(u32, Vec<(PageId, Image)>)> {
+        let position = self.find(name)?;
+        let mut images = BTreeMap::new();
+        // Newest first, so each page ends up as the oldest image of it
+        for savepoint in self.savepoints.drain(position + 1..).rev() {
+            images.extend(savepoint.images);
+        }
+        let target = &mut self.savepoints[position];
+        images.extend(std::mem::take(&mut target.images));
+        let num_pages = target.num_pages;
+        Ok((
+            num_pages,
+            images.into_iter().filter(|(p, _)| *p < num_pages).collect(),
+        ))
+    }
+
+    /// Undo everything and close every savepoint
+    pub fn rollback_all(&mut self) -> Option<(u32, Vec<(PageId, Image)>)> {
+        let name = self.savepoints.first()?.name.clone();
+        let undone = self.rollback_to(&name).ok();
+        self.savepoints.clear();
+        undone
+    }
+
+    fn find(&self, name: &str) -> Result<usize> {
+        self.savepoints
+            .iter()
+            .rposition(|s| s.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| HozonError::NotFound(format!("No savepoint named '{}'", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(byte: u8) -> [u8; PAGE_SIZE] {
+        [byte; PAGE_SIZE]
+    }
+
+    #[test]
+    fn test_rollback_keeps_oldest_image() {
+        let mut undo = UndoLog::default();
+        undo.begin("a", 3);
+        assert!(undo.needs_image(2));
+        undo.save_image(2, page(1));
+        assert!(!undo.needs_image(2));
+        // Pages allocated since aren't saved
+        assert!(!undo.needs_image(3));
+
+        undo.begin("b", 4);
+        undo.save_image(2, page(2));
+        undo.save_image(3, page(3));
+
+        let (num_pages, images) = undo.rollback_to("A").unwrap();
+        assert_eq!(num_pages, 3);
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].0, 2);
+        assert_eq!(images[0].1[0], 1);
+        // "a" is still open, "b" is gone
+        assert!(undo.is_active());
+        assert!(undo.rollback_to("b").is_err());
+    }
+
+    #[test]
+    fn test_release_hands_images_outward() {
+        let mut undo = UndoLog::default();
+        undo.begin("a", 3);
+        undo.begin("b", 4);
+        undo.save_image(2, page(2));
+        undo.save_image(3, page(3));
+        undo.release("b").unwrap();
+
+        let (_, images) = undo.rollback_to("a").unwrap();
+        assert_eq!(images.iter().map(|(p, _)| *p).collect::<Vec<_>>(), [2]);
+
+        undo.release("a").unwrap();
+        assert!(!undo.is_active());
+        assert!(undo.release("a").is_err());
+    }
+}
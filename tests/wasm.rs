@@ -0,0 +1,34 @@
+//! The library running on bare wasm, where there's no clock, process or
+//! file system. Run under node with wasm-bindgen's test runner:
+//! `cargo test --target wasm32-unknown-unknown --test wasm`
+#![cfg(all(target_family = "wasm", target_os = "unknown"))]
+
+use hozondb::Database;
+use hozondb::storage::backend::MemBackend;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+#[wasm_bindgen_test]
+fn test_in_memory_database() {
+    let db = Database::open_in_memory().unwrap();
+    let conn = db.connect();
+    conn.execute(
+        "CREATE TABLE users (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT);
+         INSERT INTO users (name) VALUES ('alice'), ('bob');",
+    )
+    .unwrap();
+    assert_eq!(conn.last_insert_id(), Some(2));
+    let names: Vec<(String,)> = conn
+        .query_as("SELECT name FROM users ORDER BY id DESC;")
+        .unwrap();
+    assert_eq!(names, [("bob".to_string(),), ("alice".to_string(),)]);
+}
+
+#[wasm_bindgen_test]
+fn test_with_backend() {
+    let db = Database::with_backend(MemBackend::new()).unwrap();
+    db.connect()
+        .execute("CREATE TABLE t (n INTEGER); INSERT INTO t VALUES (7);")
+        .unwrap();
+    let rows = db.connect().query("SELECT n FROM t;").unwrap();
+    assert_eq!(rows.len(), 1);
+}